@@ -0,0 +1,72 @@
+#ifndef MLDEC_H
+#define MLDEC_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// A parsed metalib, opaque to C.
+typedef struct MldecMetalib MldecMetalib;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Parses the metalib at the start of the `len` bytes at `buf` and stores a handle to it in
+// `*out_handle`. Returns 0 on success, or -1 with `*out_handle` set to NULL.
+//
+// # Safety
+//
+// `buf` must point to `len` readable bytes and `out_handle` must be valid for writes.
+int mldec_parse(const uint8_t *buf, size_t len, MldecMetalib **out_handle);
+
+// Exports the metalib as TDR XML. Returns NULL on failure.
+//
+// # Safety
+//
+// `handle` must be NULL or a handle from [`mldec_parse`] that hasn't been freed.
+char *mldec_export_xml(const MldecMetalib *handle);
+
+// The number of structs and unions in the metalib, or 0 if `handle` is NULL.
+//
+// # Safety
+//
+// `handle` must be NULL or a handle from [`mldec_parse`] that hasn't been freed.
+size_t mldec_meta_count(const MldecMetalib *handle);
+
+// The name of the `idx`th struct or union, in file order. Returns NULL if `idx` is out of
+// range.
+//
+// # Safety
+//
+// `handle` must be NULL or a handle from [`mldec_parse`] that hasn't been freed.
+char *mldec_get_meta_name(const MldecMetalib *handle, size_t idx);
+
+// Frees a handle from [`mldec_parse`]. NULL is ignored.
+//
+// # Safety
+//
+// `handle` must be NULL or a handle from [`mldec_parse`] that hasn't been freed.
+void mldec_free(MldecMetalib *handle);
+
+// Frees a string returned by this library. NULL is ignored.
+//
+// # Safety
+//
+// `s` must be NULL or a string returned by this library that hasn't been freed.
+void mldec_free_string(char *s);
+
+// The error from the last call on this thread that failed, or NULL if none has. The
+// string belongs to the library and stays valid until the next failing call on the
+// same thread; don't free it.
+const char *mldec_last_error(void);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif // MLDEC_H
@@ -0,0 +1,477 @@
+//! Consistency probe for the pointer width claimed by `MetalibHeader.platform_arch`.
+//!
+//! Some metalibs carry a header copied from another build, so the claimed arch can't
+//! always be trusted. The probe decodes the first macro and the first meta under each
+//! candidate layout and scores how sane the results look, so the parser can go with
+//! whichever layout the data actually supports.
+
+use anyhow::{anyhow, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use encoding::all::GBK;
+use encoding::{DecoderTrap, Encoding};
+use int_enum::IntEnum;
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::metalib::{
+    MetaPrimativeType, MetalibHeader, RecordSizes, TDRMetaFlags, INVALID_METALIB_VALUE,
+    RECORD_SIZES_32, RECORD_SIZES_64,
+};
+
+/// `platform_arch` value written by 32-bit builds (the `20` in `D6 02 0B 00 20`).
+pub const PLATFORM_ARCH_32: u32 = 0x20;
+
+/// `platform_arch` value written by 64-bit builds.
+pub const PLATFORM_ARCH_64: u32 = 0x40;
+
+/// Longest string the probe will follow before deciding a pointer is bogus.
+const MAX_PROBE_STRING_SIZE: usize = 1024;
+
+/// Upper bound on a believable `TDRMeta.entries_num`.
+const MAX_PLAUSIBLE_ENTRIES: i32 = 0x10000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MetalibArch {
+    Bits32,
+    Bits64,
+}
+
+impl MetalibArch {
+    /// Returns the arch for a known `platform_arch` header value.
+    pub fn from_platform_arch(platform_arch: u32) -> Option<MetalibArch> {
+        match platform_arch {
+            PLATFORM_ARCH_32 => Some(MetalibArch::Bits32),
+            PLATFORM_ARCH_64 => Some(MetalibArch::Bits64),
+            _ => None,
+        }
+    }
+
+    /// The `platform_arch` header value for this arch.
+    pub fn platform_arch(self) -> u32 {
+        match self {
+            MetalibArch::Bits32 => PLATFORM_ARCH_32,
+            MetalibArch::Bits64 => PLATFORM_ARCH_64,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<MetalibArch> {
+        match name {
+            "32" | "x86" => Ok(MetalibArch::Bits32),
+            "64" | "x64" => Ok(MetalibArch::Bits64),
+            _ => Err(anyhow!("Unknown arch: {name} (expected 32 or 64)")),
+        }
+    }
+
+    /// Sizes of the records in this layout.
+    pub fn record_sizes(self) -> &'static RecordSizes {
+        match self {
+            MetalibArch::Bits32 => &RECORD_SIZES_32,
+            MetalibArch::Bits64 => &RECORD_SIZES_64,
+        }
+    }
+
+    fn layout(self) -> &'static ProbeLayout {
+        match self {
+            MetalibArch::Bits32 => &LAYOUT_32,
+            MetalibArch::Bits64 => &LAYOUT_64,
+        }
+    }
+}
+
+impl fmt::Display for MetalibArch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetalibArch::Bits32 => write!(f, "32-bit"),
+            MetalibArch::Bits64 => write!(f, "64-bit"),
+        }
+    }
+}
+
+/// Offsets (in bytes) of the fields the probe looks at for one candidate layout.
+struct ProbeLayout {
+    pointer_size: usize,
+    macro_size: usize,
+    macro_name: usize,
+    macro_value: usize,
+    macro_desc: usize,
+    meta_flags: usize,
+    meta_type: usize,
+    meta_entries_num: usize,
+    meta_name: usize,
+}
+
+/// The layout `read_metalib` parses.
+const LAYOUT_32: ProbeLayout = ProbeLayout {
+    pointer_size: 4,
+    macro_size: 0x10,
+    macro_name: 0x0,
+    macro_value: 0x4,
+    macro_desc: 0x8,
+    meta_flags: 0x0,
+    meta_type: 0x10,
+    meta_entries_num: 0x2C,
+    meta_name: 0x84,
+};
+
+/// The 64-bit layout `read_metalib` parses: the same structs with every pointer field
+/// widened to 8 bytes and naturally aligned.
+const LAYOUT_64: ProbeLayout = ProbeLayout {
+    pointer_size: 8,
+    macro_size: 0x20,
+    macro_name: 0x0,
+    macro_value: 0x8,
+    macro_desc: 0x10,
+    meta_flags: 0x0,
+    meta_type: 0x10,
+    meta_entries_num: 0x2C,
+    meta_name: 0x98,
+};
+
+/// Score and reasoning for a single candidate layout.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ArchCandidate {
+    pub arch: MetalibArch,
+    pub score: i32,
+    pub notes: Vec<String>,
+}
+
+/// Result of checking the header's arch claim against the body tables.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ArchProbe {
+    /// Arch claimed by `platform_arch`, if it's a known value.
+    pub claimed: Option<MetalibArch>,
+
+    /// Arch with the strictly highest score, if any.
+    pub detected: Option<MetalibArch>,
+
+    pub candidates: Vec<ArchCandidate>,
+}
+
+impl ArchProbe {
+    /// Returns true if the data clearly points at a different arch than the header claims.
+    pub fn is_mismatch(&self) -> bool {
+        matches!((self.claimed, self.detected), (Some(claimed), Some(detected)) if claimed != detected)
+    }
+
+    /// Picks the arch to parse with: the override if given, otherwise the evidence,
+    /// otherwise the header claim, otherwise 32-bit.
+    pub fn resolve(&self, force_arch: Option<MetalibArch>) -> MetalibArch {
+        force_arch
+            .or(self.detected)
+            .or(self.claimed)
+            .unwrap_or(MetalibArch::Bits32)
+    }
+}
+
+fn read_pointer(body: &[u8], offset: usize, layout: &ProbeLayout) -> Option<i64> {
+    let bytes = body.get(offset..offset + layout.pointer_size)?;
+    Some(match layout.pointer_size {
+        8 => LittleEndian::read_i64(bytes),
+        _ => LittleEndian::read_i32(bytes) as i64,
+    })
+}
+
+fn read_i32(body: &[u8], offset: usize) -> Option<i32> {
+    body.get(offset..offset + 4).map(LittleEndian::read_i32)
+}
+
+/// Scores the string behind an offset pointer. Names are expected to look like identifiers,
+/// descriptions only need to be valid, printable GBK.
+fn score_string(
+    body: &[u8],
+    offset: usize,
+    layout: &ProbeLayout,
+    is_name: bool,
+    label: &str,
+    notes: &mut Vec<String>,
+) -> i32 {
+    let ptr = match read_pointer(body, offset, layout) {
+        Some(ptr) => ptr,
+        None => {
+            notes.push(format!("{label}: pointer is past the end of the body"));
+            return -2;
+        }
+    };
+
+    if ptr == INVALID_METALIB_VALUE as i64 {
+        return if is_name {
+            notes.push(format!("{label}: missing"));
+            -1
+        } else {
+            0
+        };
+    }
+
+    let data = match usize::try_from(ptr).ok().and_then(|ptr| body.get(ptr..)) {
+        Some(data) => data,
+        None => {
+            notes.push(format!("{label}: pointer {ptr:#X} is outside the body"));
+            return -2;
+        }
+    };
+
+    let len = match data
+        .iter()
+        .take(MAX_PROBE_STRING_SIZE)
+        .position(|&c| c == b'\0')
+    {
+        Some(len) => len,
+        None => {
+            notes.push(format!("{label}: no terminator near {ptr:#X}"));
+            return -2;
+        }
+    };
+
+    let s = match GBK.decode(&data[..len], DecoderTrap::Strict) {
+        Ok(s) => s,
+        Err(_) => {
+            notes.push(format!("{label}: invalid GBK at {ptr:#X}"));
+            return -1;
+        }
+    };
+
+    if is_name {
+        let is_ident = !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if is_ident {
+            notes.push(format!("{label}: `{s}`"));
+            2
+        } else {
+            notes.push(format!("{label}: {s:?} is not an identifier"));
+            -1
+        }
+    } else if s.chars().any(|c| c.is_control()) {
+        notes.push(format!("{label}: contains control characters"));
+        -1
+    } else {
+        1
+    }
+}
+
+fn probe_candidate(header: &MetalibHeader, body: &[u8], arch: MetalibArch) -> ArchCandidate {
+    let layout = arch.layout();
+    let mut notes = Vec::new();
+    let mut score = 0;
+
+    // Counts: the macro table has to fit before the next table starts.
+    if header.cur_macro_num > 0 {
+        let macro_table_end =
+            header.ptr_macro as usize + header.cur_macro_num as usize * layout.macro_size;
+        if macro_table_end > body.len() {
+            notes.push("macro table runs past the end of the body".to_string());
+            score -= 2;
+        } else if header.ptr_id as usize >= header.ptr_macro as usize
+            && macro_table_end > header.ptr_id as usize
+        {
+            notes.push("macro table overlaps the id table".to_string());
+            score -= 2;
+        } else {
+            score += 1;
+        }
+
+        let base = header.ptr_macro as usize;
+        score += score_string(
+            body,
+            base + layout.macro_name,
+            layout,
+            true,
+            "first macro name",
+            &mut notes,
+        );
+        score += score_string(
+            body,
+            base + layout.macro_desc,
+            layout,
+            false,
+            "first macro desc",
+            &mut notes,
+        );
+        if read_i32(body, base + layout.macro_value).is_none() {
+            notes.push("first macro value is past the end of the body".to_string());
+            score -= 2;
+        }
+    }
+
+    if header.cur_meta_num > 0 {
+        let base = header.ptr_meta as usize;
+        let flags = read_i32(body, base + layout.meta_flags).map(|bits| bits as u32);
+        if flags.and_then(TDRMetaFlags::from_bits).is_none() {
+            notes.push("first meta has unknown flags".to_string());
+            score -= 1;
+        }
+
+        let type_ = read_i32(body, base + layout.meta_type)
+            .and_then(|value| MetaPrimativeType::from_int(value).ok());
+        if !matches!(
+            type_,
+            Some(MetaPrimativeType::STRUCT | MetaPrimativeType::UNION)
+        ) {
+            notes.push("first meta is neither a struct nor a union".to_string());
+            score -= 1;
+        }
+
+        let entries_num = read_i32(body, base + layout.meta_entries_num).unwrap_or(-1);
+        if !(0..=MAX_PLAUSIBLE_ENTRIES).contains(&entries_num) {
+            notes.push(format!("first meta has {entries_num} entries"));
+            score -= 1;
+        }
+
+        score += score_string(
+            body,
+            base + layout.meta_name,
+            layout,
+            true,
+            "first meta name",
+            &mut notes,
+        );
+    }
+
+    ArchCandidate { arch, score, notes }
+}
+
+/// Scores every candidate layout against the (post-header) body of a metalib.
+pub fn probe_arch(header: &MetalibHeader, body: &[u8]) -> ArchProbe {
+    let candidates: Vec<ArchCandidate> = [MetalibArch::Bits32, MetalibArch::Bits64]
+        .into_iter()
+        .map(|arch| probe_candidate(header, body, arch))
+        .collect();
+
+    let best = candidates.iter().map(|c| c.score).max().unwrap_or(0);
+    let mut winners = candidates.iter().filter(|c| c.score == best);
+    let detected = match (winners.next(), winners.next()) {
+        (Some(winner), None) => Some(winner.arch),
+        _ => None,
+    };
+
+    ArchProbe {
+        claimed: MetalibArch::from_platform_arch(header.platform_arch),
+        detected,
+        candidates,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metalib::Metalib;
+    use crate::options::ParseOptions;
+    use crate::test_support::{compile_bytes, parse_with, FULL_XML, TYPES_XML};
+    use crate::xml::export_metalib_xml;
+
+    /// `platform_arch` sits right after the magic and build.
+    const PLATFORM_ARCH_OFFSET: usize = 4;
+
+    fn claim(bytes: &mut [u8], arch: MetalibArch) {
+        bytes[PLATFORM_ARCH_OFFSET..PLATFORM_ARCH_OFFSET + 4]
+            .copy_from_slice(&arch.platform_arch().to_le_bytes());
+    }
+
+    #[test]
+    fn honest_headers_agree_with_the_data() {
+        for arch in [MetalibArch::Bits32, MetalibArch::Bits64] {
+            let bytes = compile_bytes(FULL_XML, arch);
+            let metalib = parse_with(&bytes, &ParseOptions::default()).unwrap();
+            assert_eq!(metalib.arch, arch);
+            assert_eq!(metalib.arch_probe.claimed, Some(arch));
+            assert_eq!(metalib.arch_probe.detected, Some(arch));
+            assert!(!metalib.arch_probe.is_mismatch());
+        }
+    }
+
+    /// What a metalib describes, without the file offsets and host sizes that depend on
+    /// the pointer width.
+    fn logical(metalib: &Metalib) -> Vec<String> {
+        let mut lines = vec![format!(
+            "{} v{}",
+            metalib.header.name, metalib.header.version
+        )];
+        for macro_ in metalib.macros.iter() {
+            lines.push(format!("macro {} = {}", macro_.name, macro_.value));
+        }
+        for meta in metalib.metas.iter() {
+            lines.push(format!(
+                "{:?} {} v{}..{} net {}",
+                meta.type_, meta.name, meta.base_version, meta.cur_version, meta.n_unit_size
+            ));
+            for entry in meta.entries.iter() {
+                lines.push(format!(
+                    "  {} {:?} x{} v{} net {}+{} default {:?} attr {:?}",
+                    entry.name,
+                    entry.type_,
+                    entry.count,
+                    entry.version,
+                    entry.n_off,
+                    entry.n_unit_size,
+                    entry.default_value_string,
+                    entry.custom_attr
+                ));
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn both_widths_are_equivalent() {
+        for xml in [FULL_XML, TYPES_XML] {
+            let [narrow, wide] = [MetalibArch::Bits32, MetalibArch::Bits64].map(|arch| {
+                let bytes = compile_bytes(xml, arch);
+                parse_with(&bytes, &ParseOptions::default()).unwrap()
+            });
+            assert_eq!(narrow.arch, MetalibArch::Bits32);
+            assert_eq!(wide.arch, MetalibArch::Bits64);
+            assert!(wide.header.size > narrow.header.size);
+
+            assert_eq!(logical(&wide), logical(&narrow));
+            assert_eq!(
+                export_metalib_xml(&wide).unwrap(),
+                export_metalib_xml(&narrow).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn mismatched_claim_parses_with_the_detected_layout() {
+        for (data, claimed) in [
+            (MetalibArch::Bits32, MetalibArch::Bits64),
+            (MetalibArch::Bits64, MetalibArch::Bits32),
+        ] {
+            let mut bytes = compile_bytes(FULL_XML, data);
+            claim(&mut bytes, claimed);
+
+            let metalib = parse_with(&bytes, &ParseOptions::default()).unwrap();
+            assert!(metalib.arch_probe.is_mismatch());
+            assert_eq!(metalib.arch_probe.claimed, Some(claimed));
+            assert_eq!(metalib.arch, data);
+            assert_eq!(export_metalib_xml(&metalib).unwrap(), FULL_XML);
+        }
+    }
+
+    #[test]
+    fn force_arch_overrides_the_evidence() {
+        let mut bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        claim(&mut bytes, MetalibArch::Bits64);
+        let options = ParseOptions::default().force_arch(MetalibArch::Bits64);
+        // The 32-bit tables read as 64-bit pointers run off the end of the body.
+        let err = parse_with(&bytes, &options).unwrap_err();
+        assert!(err.to_string().contains("out of range"), "{err:#}");
+    }
+
+    #[test]
+    fn resolve_prefers_override_then_evidence_then_claim() {
+        let probe = |claimed, detected| ArchProbe {
+            claimed,
+            detected,
+            candidates: Vec::new(),
+        };
+        let (b32, b64) = (MetalibArch::Bits32, MetalibArch::Bits64);
+        assert_eq!(probe(Some(b32), Some(b32)).resolve(Some(b64)), b64);
+        assert_eq!(probe(Some(b32), Some(b64)).resolve(None), b64);
+        assert_eq!(probe(Some(b64), None).resolve(None), b64);
+        assert_eq!(probe(None, None).resolve(None), b32);
+        assert!(!probe(None, Some(b64)).is_mismatch());
+    }
+}
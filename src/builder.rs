@@ -0,0 +1,1284 @@
+//! Assembling a [`Metalib`] in memory, without a compiled file to parse.
+//!
+//! The builder lays the tables out the way the 32-bit compiler does: macros, then the id,
+//! name and map tables, then the metas with their entries, then the macrogroups, then the
+//! string buffer. Every `_offset` and pointer field is filled in from that layout, so the
+//! offset lookups, the exporters and [`Metalib::validate`] all work on the result.
+//!
+//! ```
+//! use mldec_rs::builder::{EntryBuilder, MetaBuilder, MetalibBuilder};
+//!
+//! let metalib = MetalibBuilder::new("demo")
+//!     .macro_("MAX_NAME", 32)
+//!     .meta(
+//!         MetaBuilder::struct_("Player")
+//!             .entry(EntryBuilder::new("level", "uint16").default("1"))
+//!             .entry(EntryBuilder::new("name", "string").count("MAX_NAME")),
+//!     )
+//!     .build()?;
+//! assert_eq!(metalib.get_meta_by_name("Player")?.h_unit_size, 34);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use anyhow::{anyhow, Context, Result};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::OnceLock;
+
+use crate::arch::{ArchProbe, MetalibArch, PLATFORM_ARCH_32};
+use crate::metalib::{
+    encode_default_value, read_default_value, MetaPrimativeType, Metalib, MetalibHeader,
+    TDRDBKeyInfo, TDRIdEntry, TDRMacro, TDRMacroGroup, TDRMapEntry, TDRMeta, TDRMetaEntry,
+    TDRMetaEntryDBFlags, TDRMetaEntryFlags, TDRMetaFlags, TDRNameEntry, TDRRedirector, TDRSelector,
+    TDRSizeInfo, TDRSortKeyInfo, INVALID_METALIB_VALUE, MAX_SPLIT_TABLE_RULE, METALIB_BUILD,
+    METALIB_HEADER_SIZE, METALIB_MAGIC, RECORD_SIZES_32, TDR_MACRO_GROUP_SIZE, TDR_MACRO_SIZE,
+    TDR_PRIMATIVE_TYPE_INFO, TDR_TABLE_ENTRY_SIZE,
+};
+use crate::options::ParseOptions;
+use crate::writer::{BodyLayout, StringBuffer};
+
+/// Host size of a pointer member in the 32-bit layout.
+const POINTER_SIZE: i32 = 4;
+
+/// A number given either literally or as the name of a macro, like the `count`, `version`
+/// and `id` attributes in the XML.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Value {
+    Literal(i32),
+    Macro(String),
+}
+
+impl Value {
+    /// Parses an XML attribute value: a number, or else a macro name.
+    pub fn parse(value: &str) -> Value {
+        match value.parse() {
+            Ok(value) => Value::Literal(value),
+            Err(_) => Value::Macro(value.to_string()),
+        }
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Value {
+        Value::Literal(value)
+    }
+}
+
+/// A macro name.
+impl From<&str> for Value {
+    fn from(name: &str) -> Value {
+        Value::Macro(name.to_string())
+    }
+}
+
+/// Builds a [`Metalib`]. Names are resolved and offsets laid out in [`MetalibBuilder::build`].
+#[derive(Clone, Debug)]
+pub struct MetalibBuilder {
+    name: String,
+    version: u32,
+    id: i32,
+    tag_set_version: u32,
+    macros: Vec<(String, i32, String)>,
+    macrogroups: Vec<(String, Vec<String>, String)>,
+    metas: Vec<MetaBuilder>,
+}
+
+impl MetalibBuilder {
+    pub fn new(name: &str) -> MetalibBuilder {
+        MetalibBuilder {
+            name: name.to_string(),
+            version: 1,
+            id: INVALID_METALIB_VALUE,
+            tag_set_version: 1,
+            macros: Vec::new(),
+            macrogroups: Vec::new(),
+            metas: Vec::new(),
+        }
+    }
+
+    pub fn version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn id(mut self, id: i32) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Sets the `tagsetversion` of the `metalib` tag.
+    pub fn tag_set_version(mut self, tag_set_version: u32) -> Self {
+        self.tag_set_version = tag_set_version;
+        self
+    }
+
+    /// Adds a macro. Macros keep the order they're added in.
+    pub fn macro_(self, name: &str, value: i32) -> Self {
+        self.macro_with_desc(name, value, "")
+    }
+
+    pub fn macro_with_desc(mut self, name: &str, value: i32, desc: &str) -> Self {
+        self.macros
+            .push((name.to_string(), value, desc.to_string()));
+        self
+    }
+
+    /// Adds a macrogroup of already added macros, by name.
+    pub fn macrogroup(self, name: &str, members: &[&str]) -> Self {
+        self.macrogroup_with_desc(name, members, "")
+    }
+
+    pub fn macrogroup_with_desc(mut self, name: &str, members: &[&str], desc: &str) -> Self {
+        let members = members.iter().map(|member| member.to_string()).collect();
+        self.macrogroups
+            .push((name.to_string(), members, desc.to_string()));
+        self
+    }
+
+    /// Adds a struct or union. Entries can refer to metas added before or after it.
+    pub fn meta(mut self, meta: MetaBuilder) -> Self {
+        self.metas.push(meta);
+        self
+    }
+
+    /// Resolves every name and lays out the tables.
+    pub fn build(self) -> Result<Metalib> {
+        build(self)
+    }
+}
+
+/// Builds one [`TDRMeta`] of a [`MetalibBuilder`].
+#[derive(Clone, Debug)]
+pub struct MetaBuilder {
+    name: String,
+    type_: MetaPrimativeType,
+    version: Value,
+    id: Option<Value>,
+    desc: String,
+    cname: String,
+    flags: TDRMetaFlags,
+    size: Option<Value>,
+    align: i32,
+    version_indicator: Option<String>,
+    sizeinfo: Option<String>,
+    sort_key: Option<String>,
+    primary_key: Vec<String>,
+    split_table_factor: Option<Value>,
+    split_table_key: Option<String>,
+    split_table_rule: i16,
+    depend_on_table: Option<String>,
+    entries: Vec<EntryBuilder>,
+}
+
+impl MetaBuilder {
+    fn new(name: &str, type_: MetaPrimativeType) -> MetaBuilder {
+        MetaBuilder {
+            name: name.to_string(),
+            type_,
+            version: Value::Literal(1),
+            id: None,
+            desc: String::new(),
+            cname: String::new(),
+            flags: TDRMetaFlags::FIXED_SIZE,
+            size: None,
+            align: 1,
+            version_indicator: None,
+            sizeinfo: None,
+            sort_key: None,
+            primary_key: Vec::new(),
+            split_table_factor: None,
+            split_table_key: None,
+            split_table_rule: 0,
+            depend_on_table: None,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn struct_(name: &str) -> MetaBuilder {
+        MetaBuilder::new(name, MetaPrimativeType::STRUCT)
+    }
+
+    pub fn union(name: &str) -> MetaBuilder {
+        MetaBuilder::new(name, MetaPrimativeType::UNION)
+    }
+
+    /// Sets the base version, which is also the default version of its entries.
+    pub fn version(mut self, version: impl Into<Value>) -> Self {
+        self.version = version.into();
+        self
+    }
+
+    pub fn id(mut self, id: impl Into<Value>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn desc(mut self, desc: &str) -> Self {
+        self.desc = desc.to_string();
+        self
+    }
+
+    pub fn cname(mut self, cname: &str) -> Self {
+        self.cname = cname.to_string();
+        self
+    }
+
+    /// Sets extra meta flags. `FIXED_SIZE` is set by default and `HAS_ID` follows `id`.
+    pub fn flags(mut self, flags: TDRMetaFlags) -> Self {
+        self.flags |= flags;
+        self
+    }
+
+    /// Sets the host size, which must fit the entries.
+    pub fn size(mut self, size: impl Into<Value>) -> Self {
+        self.size = Some(size.into());
+        self
+    }
+
+    /// Sets the largest alignment of the entries in host memory. Defaults to 1 (packed).
+    pub fn align(mut self, align: i32) -> Self {
+        self.align = align;
+        self
+    }
+
+    /// Sets the dotted path of the entry holding the version of each message.
+    pub fn version_indicator(mut self, path: &str) -> Self {
+        self.version_indicator = Some(path.to_string());
+        self
+    }
+
+    /// Sets where the serialized size is stored: a primitive type for a length prefix, or
+    /// the dotted path of an entry.
+    pub fn sizeinfo(mut self, sizeinfo: &str) -> Self {
+        self.sizeinfo = Some(sizeinfo.to_string());
+        self
+    }
+
+    pub fn sort_key(mut self, path: &str) -> Self {
+        self.sort_key = Some(path.to_string());
+        self
+    }
+
+    /// Sets the dotted paths of the entries making up the primary key, in order.
+    pub fn primary_key<'a>(mut self, paths: impl IntoIterator<Item = &'a str>) -> Self {
+        self.primary_key = paths.into_iter().map(str::to_string).collect();
+        self
+    }
+
+    /// Sets the number of tables the struct's rows are split across.
+    pub fn split_table_factor(mut self, factor: impl Into<Value>) -> Self {
+        self.split_table_factor = Some(factor.into());
+        self
+    }
+
+    /// Sets the dotted path of the entry that picks which split table a row goes in.
+    pub fn split_table_key(mut self, path: &str) -> Self {
+        self.split_table_key = Some(path.to_string());
+        self
+    }
+
+    /// Sets how rows are split across tables, up to [`MAX_SPLIT_TABLE_RULE`]. Defaults to 0
+    /// (not split).
+    pub fn split_table_rule(mut self, rule: i16) -> Self {
+        self.split_table_rule = rule;
+        self
+    }
+
+    /// Sets the struct whose table this one's depends on.
+    pub fn depend_on_table(mut self, name: &str) -> Self {
+        self.depend_on_table = Some(name.to_string());
+        self
+    }
+
+    pub fn entry(mut self, entry: EntryBuilder) -> Self {
+        self.entries.push(entry);
+        self
+    }
+}
+
+/// Builds one [`TDRMetaEntry`] of a [`MetaBuilder`].
+#[derive(Clone, Debug)]
+pub struct EntryBuilder {
+    name: String,
+    type_name: String,
+    count: Value,
+    version: Option<Value>,
+    id: Option<Value>,
+    size: Option<Value>,
+    default: Option<String>,
+    desc: String,
+    cname: String,
+    custom_attr: String,
+    flag: TDRMetaEntryFlags,
+    db_flag: TDRMetaEntryDBFlags,
+    macrogroup: Option<String>,
+    refer: Option<String>,
+    select: Option<String>,
+    sizeinfo: Option<String>,
+    order: u8,
+    io: i32,
+    id_range: Option<(Value, Value)>,
+}
+
+impl EntryBuilder {
+    /// Starts an entry of the given type: a primitive as written in the XML `type`
+    /// attribute (`int`, `string`, ...) or the name of a meta. Like the XML, a `*` or `@`
+    /// prefix makes it a pointer or reference.
+    pub fn new(name: &str, type_name: &str) -> EntryBuilder {
+        let (flag, type_name) = if let Some(rest) = type_name.strip_prefix('*') {
+            (TDRMetaEntryFlags::POINT_TYPE, rest)
+        } else if let Some(rest) = type_name.strip_prefix('@') {
+            (TDRMetaEntryFlags::REFER_TYPE, rest)
+        } else {
+            (TDRMetaEntryFlags::empty(), type_name)
+        };
+        EntryBuilder {
+            name: name.to_string(),
+            type_name: type_name.to_string(),
+            count: Value::Literal(1),
+            version: None,
+            id: None,
+            size: None,
+            default: None,
+            desc: String::new(),
+            cname: String::new(),
+            custom_attr: String::new(),
+            flag,
+            db_flag: TDRMetaEntryDBFlags::empty(),
+            macrogroup: None,
+            refer: None,
+            select: None,
+            sizeinfo: None,
+            order: 0,
+            io: 0,
+            id_range: None,
+        }
+    }
+
+    pub fn count(mut self, count: impl Into<Value>) -> Self {
+        self.count = count.into();
+        self
+    }
+
+    /// Sets the version the entry was added in. Defaults to the meta's base version.
+    pub fn version(mut self, version: impl Into<Value>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Sets the id, which selects this entry of a union.
+    pub fn id(mut self, id: impl Into<Value>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets the host size of one element, in units of the type (bytes for a string).
+    pub fn size(mut self, size: impl Into<Value>) -> Self {
+        self.size = Some(size.into());
+        self
+    }
+
+    /// Sets the default value, written as it would be in the XML.
+    pub fn default(mut self, value: &str) -> Self {
+        self.default = Some(value.to_string());
+        self
+    }
+
+    pub fn desc(mut self, desc: &str) -> Self {
+        self.desc = desc.to_string();
+        self
+    }
+
+    pub fn cname(mut self, cname: &str) -> Self {
+        self.cname = cname.to_string();
+        self
+    }
+
+    /// Sets the `customattr` string, which TDR stores but doesn't interpret.
+    pub fn custom_attr(mut self, custom_attr: &str) -> Self {
+        self.custom_attr = custom_attr.to_string();
+        self
+    }
+
+    pub fn flags(mut self, flag: TDRMetaEntryFlags) -> Self {
+        self.flag |= flag;
+        self
+    }
+
+    pub fn db_flags(mut self, db_flag: TDRMetaEntryDBFlags) -> Self {
+        self.db_flag |= db_flag;
+        self
+    }
+
+    /// Binds the entry to a macrogroup, by name.
+    pub fn macrogroup(mut self, name: &str) -> Self {
+        self.macrogroup = Some(name.to_string());
+        self
+    }
+
+    /// Sets the dotted path of the entry holding the number of elements in use.
+    pub fn refer(mut self, path: &str) -> Self {
+        self.refer = Some(path.to_string());
+        self
+    }
+
+    /// Sets the dotted path of the entry that selects the member of a union entry.
+    pub fn select(mut self, path: &str) -> Self {
+        self.select = Some(path.to_string());
+        self
+    }
+
+    /// Sets where the serialized size is stored: a primitive type for a length prefix, or
+    /// the dotted path of an entry.
+    pub fn sizeinfo(mut self, sizeinfo: &str) -> Self {
+        self.sizeinfo = Some(sizeinfo.to_string());
+        self
+    }
+
+    /// Sets the `sortMethod` of an array: 1 for ascending, 2 for descending.
+    pub fn order(mut self, order: u8) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Sets the `io` restriction: 1 for noinput, 2 for nooutput, 3 for noio.
+    pub fn io(mut self, io: i32) -> Self {
+        self.io = io;
+        self
+    }
+
+    /// Sets the `minid`/`maxid` range of the ids this entry accepts.
+    pub fn id_range(mut self, min: impl Into<Value>, max: impl Into<Value>) -> Self {
+        self.id_range = Some((min.into(), max.into()));
+        self
+    }
+}
+
+/// Lays out the tables of `builder` and fills in every record.
+fn build(builder: MetalibBuilder) -> Result<Metalib> {
+    let layout = BodyLayout::new(
+        &RECORD_SIZES_32,
+        builder.macros.len(),
+        builder.metas.iter().map(|meta| meta.entries.len()),
+        builder
+            .macrogroups
+            .iter()
+            .map(|(_, members, _)| members.len()),
+        builder.metas.iter().map(|meta| meta.primary_key.len()),
+    );
+    let ctx = Resolver::new(&builder)?;
+    // Strings go in in the order `write_metalib` adds them, so writing a built metalib
+    // puts them at the same offsets.
+    let mut strings = StringBuffer::new(layout.ptr_str_buf);
+
+    let mut macros = Vec::new();
+    for (idx, (name, value, desc)) in builder.macros.iter().enumerate() {
+        strings.add_string(name)?;
+        strings.add_string(desc)?;
+        macros.push(TDRMacro {
+            _offset: (idx as u32 * TDR_MACRO_SIZE) as u64,
+            name: name.clone(),
+            value: *value,
+            desc: desc.clone(),
+            unk: 0,
+        });
+    }
+
+    let mut ids = Vec::new();
+    let mut names = Vec::new();
+    for (idx, meta) in builder.metas.iter().enumerate() {
+        let row = idx as u32 * TDR_TABLE_ENTRY_SIZE;
+        ids.push(TDRIdEntry {
+            _offset: (layout.ptr_id + row) as u64,
+            id: INVALID_METALIB_VALUE,
+            idx: idx as i32,
+        });
+        names.push(TDRNameEntry {
+            _offset: (layout.ptr_name + row) as u64,
+            ptr: strings.add_string(&meta.name)?,
+            idx: idx as i32,
+            name: meta.name.clone(),
+        });
+    }
+
+    let mut metas = Vec::new();
+    for (idx, meta) in builder.metas.iter().enumerate() {
+        metas.push(
+            build_meta(&ctx, &layout, &mut strings, idx, meta)
+                .with_context(|| format!("Failed to build meta `{}`", meta.name))?,
+        );
+    }
+    for (idx, meta) in builder.metas.iter().enumerate() {
+        resolve_paths(&mut metas, idx, meta)
+            .with_context(|| format!("Failed to build meta `{}`", meta.name))?;
+    }
+
+    let mut meta_map = Vec::new();
+    for (idx, meta) in metas.iter().enumerate() {
+        meta_map.push(TDRMapEntry {
+            _offset: (layout.ptr_map + idx as u32 * TDR_TABLE_ENTRY_SIZE) as u64,
+            ptr: meta._offset as i32,
+            size: meta.mem_size,
+        });
+    }
+
+    let mut macrogroups = Vec::new();
+    for (idx, (name, members, desc)) in builder.macrogroups.iter().enumerate() {
+        let mut name_idx_map = Vec::new();
+        for member in members.iter() {
+            name_idx_map.push(
+                ctx.macro_idx(member)
+                    .with_context(|| format!("Failed to build macrogroup `{name}`"))?,
+            );
+        }
+        let mut value_idx_map = name_idx_map.clone();
+        name_idx_map.sort_by_key(|&idx| &macros[idx as usize].name);
+        value_idx_map.sort_by_key(|&idx| macros[idx as usize].value);
+        strings.add_string(desc)?;
+        let count = members.len() as i32;
+        macrogroups.push(TDRMacroGroup {
+            _offset: layout.group_offsets[idx] as u64,
+            cur_macro_count: count,
+            max_macro_count: count,
+            desc: desc.clone(),
+            _ptr_name_idx_map: TDR_MACRO_GROUP_SIZE as i32,
+            _ptr_value_idx_map: TDR_MACRO_GROUP_SIZE as i32 + 4 * count,
+            name: name.clone(),
+            name_idx_map,
+            value_idx_map,
+        });
+    }
+
+    let ptr_free_str_buf = layout.ptr_str_buf + strings.data.len() as u32;
+    let header = MetalibHeader {
+        magic: METALIB_MAGIC,
+        build: METALIB_BUILD,
+        platform_arch: PLATFORM_ARCH_32,
+        size: METALIB_HEADER_SIZE + ptr_free_str_buf,
+        field_c: 0,
+        field_10: 0,
+        field_14: 0,
+        field_18: 0,
+        id: builder.id,
+        xml_tag_set_ver: builder.tag_set_version,
+        field_24: 0,
+        max_meta_num: metas.len() as i32,
+        cur_meta_num: metas.len() as i32,
+        max_macro_num: macros.len() as i32,
+        cur_macro_num: macros.len() as i32,
+        max_macros_group_num: macrogroups.len() as i32,
+        cur_macros_group_num: macrogroups.len() as i32,
+        field_40: 0,
+        field_44: 0,
+        version: builder.version,
+        ptr_macro: 0,
+        ptr_id: layout.ptr_id,
+        ptr_name: layout.ptr_name,
+        ptr_map: layout.ptr_map,
+        ptr_meta: layout.ptr_meta,
+        ptr_last_meta: layout
+            .meta_offsets
+            .last()
+            .copied()
+            .unwrap_or(layout.ptr_meta),
+        free_str_buf_size: 0,
+        ptr_str_buf: layout.ptr_str_buf,
+        ptr_free_str_buf,
+        ptr_macro_group_map: layout.ptr_macros_group,
+        ptr_macros_group: layout.ptr_macros_group,
+        field_78: 0,
+        field_7c: 0,
+        field_80: 0,
+        field_84: 0,
+        field_88: 0,
+        field_8c: 0,
+        field_90: 0,
+        name: builder.name.clone(),
+    };
+
+    Ok(Metalib {
+        _offset: 0,
+        header,
+        macros,
+        ids,
+        names,
+        meta_map,
+        metas,
+        macrogroups,
+        arch: MetalibArch::Bits32,
+        arch_probe: ArchProbe {
+            claimed: Some(MetalibArch::Bits32),
+            detected: None,
+            candidates: Vec::new(),
+        },
+        raw_strings: Vec::new(),
+        index: OnceLock::new(),
+    })
+}
+
+fn build_meta(
+    ctx: &Resolver,
+    layout: &BodyLayout,
+    strings: &mut StringBuffer,
+    idx: usize,
+    meta: &MetaBuilder,
+) -> Result<TDRMeta> {
+    let offset = layout.meta_offsets[idx];
+    let (version, idx_version) = ctx.value(&meta.version)?;
+    let (id, idx_id) = match &meta.id {
+        Some(id) => ctx.value(id)?,
+        None => (INVALID_METALIB_VALUE, INVALID_METALIB_VALUE),
+    };
+    let (custom_h_unit_size, idx_custom_h_unit_size) = match &meta.size {
+        Some(size) => ctx.value(size)?,
+        None => (0, INVALID_METALIB_VALUE),
+    };
+    let (split_table_factor, idx_split_table_factor) = match &meta.split_table_factor {
+        Some(factor) => ctx.value(factor)?,
+        None => (0, INVALID_METALIB_VALUE),
+    };
+    if meta.split_table_factor.is_some() && split_table_factor <= 0 {
+        return Err(anyhow!(
+            "Meta `{}` has a splittablefactor of {split_table_factor}, it must be positive",
+            meta.name
+        ));
+    }
+    if !(0..=MAX_SPLIT_TABLE_RULE).contains(&meta.split_table_rule) {
+        return Err(anyhow!(
+            "Meta `{}` has splittablerule {}, expected 0 to {MAX_SPLIT_TABLE_RULE}",
+            meta.name,
+            meta.split_table_rule
+        ));
+    }
+    let ptr_dependon_struct = match &meta.depend_on_table {
+        Some(name) => {
+            let other = *ctx
+                .metas
+                .get(name.as_str())
+                .ok_or_else(|| anyhow!("Unknown dependontable `{name}`"))?;
+            if ctx.builder.metas[other].type_ != MetaPrimativeType::STRUCT {
+                return Err(anyhow!("dependontable `{name}` isn't a struct"));
+            }
+            layout.meta_offsets[other] as i32
+        }
+        None => INVALID_METALIB_VALUE,
+    };
+    let mut flags = meta.flags;
+    flags.set(TDRMetaFlags::HAS_ID, meta.id.is_some());
+    let auto_increment = meta
+        .entries
+        .iter()
+        .filter(|entry| entry.db_flag.contains(TDRMetaEntryDBFlags::AUTO_INCREMENT))
+        .count();
+    if auto_increment > 1 {
+        return Err(anyhow!(
+            "Meta `{}` has {auto_increment} autoincrement entries, at most one is allowed",
+            meta.name
+        ));
+    }
+    flags.set(TDRMetaFlags::HAS_AUTOINCREMENT_ENTRY, auto_increment > 0);
+    strings.add_string(&meta.name)?;
+    strings.add_string(&meta.desc)?;
+    strings.add_string(&meta.cname)?;
+
+    let meta_layout = ctx.meta_layout(idx, &mut Vec::new())?;
+    let mut entries = Vec::new();
+    for (i, (entry, entry_layout)) in meta
+        .entries
+        .iter()
+        .zip(meta_layout.entries.iter())
+        .enumerate()
+    {
+        entries.push(
+            build_entry(
+                ctx,
+                strings,
+                layout,
+                entry,
+                entry_layout,
+                layout.entry_offset(idx, i),
+                version,
+            )
+            .with_context(|| format!("Failed to build entry `{}`", entry.name))?,
+        );
+    }
+
+    // The newest version any entry was added in.
+    let cur_version = entries
+        .iter()
+        .map(|entry| entry.version)
+        .fold(version, i32::max);
+    let at = |field: u32| (offset + field) as u64;
+    Ok(TDRMeta {
+        _offset: offset as u64,
+        flags,
+        id,
+        base_version: version,
+        cur_version,
+        type_: meta.type_,
+        raw_type: meta.type_ as i32,
+        mem_size: meta_layout.h_size,
+        n_unit_size: meta_layout.n_size,
+        h_unit_size: meta_layout.h_size,
+        custom_h_unit_size,
+        idx_custom_h_unit_size,
+        uncertain_max_sub_id: INVALID_METALIB_VALUE,
+        entries_num: entries.len() as i32,
+        unk_table_count: 0,
+        unk_table_ptr: 0,
+        unk_table_unk: 0,
+        ptr_meta: offset as i32,
+        idx: idx as i32,
+        idx_id,
+        idx_type: primative_type_idx(meta.type_),
+        idx_version,
+        custom_align: meta.align,
+        valid_align: meta_layout.align,
+        uncertain_version_indicator_min_ver: 0,
+        size_type: TDRSizeInfo {
+            _offset: at(0x5C),
+            n_off: INVALID_METALIB_VALUE,
+            h_off: INVALID_METALIB_VALUE,
+            unit_size: 0,
+            idx_size_type: INVALID_METALIB_VALUE,
+        },
+        version_indicator: TDRRedirector {
+            _offset: at(0x6C),
+            n_off: INVALID_METALIB_VALUE,
+            h_off: INVALID_METALIB_VALUE,
+            unit_size: 0,
+        },
+        sort_key: TDRSortKeyInfo {
+            _offset: at(0x78),
+            idx_sort_entry: INVALID_METALIB_VALUE,
+            sort_key_offset: INVALID_METALIB_VALUE,
+            ptr_sort_key_meta: INVALID_METALIB_VALUE,
+        },
+        name: meta.name.clone(),
+        desc: meta.desc.clone(),
+        chinese_name: meta.cname.clone(),
+        split_table_factor,
+        split_table_rule_id: meta.split_table_rule,
+        primary_key_member_num: i16::try_from(meta.primary_key.len())
+            .map_err(|_| anyhow!("{} primary key members is too many", meta.primary_key.len()))?,
+        idx_split_table_factor,
+        split_table_key: TDRDBKeyInfo {
+            _offset: at(0x9C),
+            h_off: INVALID_METALIB_VALUE,
+            ptr_entry: INVALID_METALIB_VALUE,
+        },
+        ptr_primary_key_base: layout.key_offsets[idx],
+        ptr_dependon_struct,
+        field_ac: 0,
+        field_b0: 0,
+        field_b4: 0,
+        entries,
+        primary_key: Vec::new(),
+    })
+}
+
+fn build_entry(
+    ctx: &Resolver,
+    strings: &mut StringBuffer,
+    layout: &BodyLayout,
+    entry: &EntryBuilder,
+    entry_layout: &EntryLayout,
+    offset: u32,
+    meta_version: i32,
+) -> Result<TDRMetaEntry> {
+    let (type_, idx_type, ptr_meta) = match ctx.entry_type(entry)? {
+        EntryType::Meta(meta) => {
+            let type_ = ctx.builder.metas[meta].type_;
+            (
+                type_,
+                primative_type_idx(type_),
+                layout.meta_offsets[meta] as i32,
+            )
+        }
+        EntryType::Primative(idx) => (
+            TDR_PRIMATIVE_TYPE_INFO[idx].primative_type,
+            idx as i32,
+            INVALID_METALIB_VALUE,
+        ),
+    };
+    if entry.db_flag.contains(TDRMetaEntryDBFlags::EXTEND_TO_TABLE)
+        && type_ != MetaPrimativeType::STRUCT
+    {
+        return Err(anyhow!(
+            "Entry `{}` is extendtotable but isn't a struct",
+            entry.name
+        ));
+    }
+    let (count, idx_count) = ctx.value(&entry.count)?;
+    let (version, idx_version) = match &entry.version {
+        Some(version) => ctx.value(version)?,
+        None => (meta_version, INVALID_METALIB_VALUE),
+    };
+    let (id, idx_id) = match &entry.id {
+        Some(id) => ctx.value(id)?,
+        None => (INVALID_METALIB_VALUE, INVALID_METALIB_VALUE),
+    };
+    let idx_custom_h_unit_size = match &entry.size {
+        Some(size) => ctx.value(size)?.1,
+        None => INVALID_METALIB_VALUE,
+    };
+    let mut flag = entry.flag;
+    let (min_id, min_id_idx, max_id, max_id_idx) = match &entry.id_range {
+        Some((min, max)) => {
+            flag |= TDRMetaEntryFlags::HAS_MAXMIN_ID;
+            let (min_id, min_id_idx) = ctx.value(min)?;
+            let (max_id, max_id_idx) = ctx.value(max)?;
+            (min_id, min_id_idx, max_id, max_id_idx)
+        }
+        None => (0, INVALID_METALIB_VALUE, 0, INVALID_METALIB_VALUE),
+    };
+    let ptr_macros_group = match &entry.macrogroup {
+        Some(name) => {
+            let idx = ctx
+                .groups
+                .get(name.as_str())
+                .ok_or_else(|| anyhow!("Unknown macrogroup `{name}`"))?;
+            layout.group_offsets[*idx] as i32
+        }
+        None => INVALID_METALIB_VALUE,
+    };
+
+    strings.add_string(&entry.name)?;
+    strings.add_string(&entry.desc)?;
+    strings.add_string(&entry.cname)?;
+    let (ptr_default_val, default_val_len, default_value_string) = match &entry.default {
+        Some(value) => {
+            let bytes = encode_default_value(idx_type, value)?;
+            let len = bytes.len() as i32;
+            // Stored the way the reader will see it, e.g. `1.0` for a float `1`.
+            let string = read_default_value(
+                &mut Cursor::new(&bytes),
+                idx_type,
+                len,
+                count,
+                &ParseOptions::default(),
+            )?;
+            (strings.add(bytes), len, string)
+        }
+        None => (INVALID_METALIB_VALUE, 0, String::new()),
+    };
+    let ptr_custom_attr = strings.add_string(&entry.custom_attr)?;
+
+    let at = |field: u32| (offset + field) as u64;
+    Ok(TDRMetaEntry {
+        _offset: offset as u64,
+        id,
+        version,
+        type_,
+        raw_type: type_ as i32,
+        name: entry.name.clone(),
+        h_real_size: entry_layout.h_unit * count,
+        n_real_size: entry_layout.n_unit * count,
+        h_unit_size: entry_layout.h_unit,
+        n_unit_size: entry_layout.n_unit,
+        custom_h_unit_size: entry_layout.custom_size,
+        count,
+        n_off: entry_layout.n_off,
+        h_off: entry_layout.h_off,
+        idx_id,
+        idx_version,
+        idx_count,
+        idx_type,
+        idx_custom_h_unit_size,
+        flag,
+        db_flag: entry.db_flag,
+        order: entry.order,
+        size_info: TDRSizeInfo {
+            _offset: at(0x48),
+            n_off: INVALID_METALIB_VALUE,
+            h_off: INVALID_METALIB_VALUE,
+            unit_size: 0,
+            idx_size_type: INVALID_METALIB_VALUE,
+        },
+        referer: TDRSelector {
+            _offset: at(0x58),
+            unit_size: 0,
+            h_off: INVALID_METALIB_VALUE,
+            ptr_entry: INVALID_METALIB_VALUE,
+        },
+        selector: TDRSelector {
+            _offset: at(0x64),
+            unit_size: 0,
+            h_off: INVALID_METALIB_VALUE,
+            ptr_entry: INVALID_METALIB_VALUE,
+        },
+        io: entry.io,
+        idx_io: INVALID_METALIB_VALUE,
+        ptr_meta,
+        max_id,
+        min_id,
+        max_id_idx,
+        min_id_idx,
+        default_val_len,
+        desc: entry.desc.clone(),
+        chinese_name: entry.cname.clone(),
+        ptr_default_val,
+        ptr_macros_group,
+        ptr_custom_attr,
+        off_to_meta: entry_layout.h_off,
+        field_a8: 0,
+        field_ac: 0,
+        field_b0: 0,
+        default_value_string,
+        custom_attr: entry.custom_attr.clone(),
+    })
+}
+
+/// An entry reached by a dotted path, with its offsets from the start of the root meta.
+struct PathTarget {
+    h_off: i32,
+    n_off: i32,
+    h_unit_size: i32,
+    /// Index of the top-level entry the path starts with.
+    first_entry: usize,
+    /// Offsets of the entry and the meta it's in.
+    entry_offset: u64,
+    meta_offset: u64,
+}
+
+/// Follows a dotted path like `header.len` from the meta at `idx` of the built metas.
+fn find_path(metas: &[TDRMeta], idx: usize, path: &str) -> Result<PathTarget> {
+    let mut meta = &metas[idx];
+    let (mut h_off, mut n_off) = (0, 0);
+    let mut first_entry = None;
+    let mut parts = path.split('.').peekable();
+    while let Some(part) = parts.next() {
+        let (entry_idx, entry) = meta
+            .entries
+            .iter()
+            .enumerate()
+            .find(|(_, entry)| entry.name == part)
+            .ok_or_else(|| anyhow!("`{path}`: `{}` has no entry `{part}`", meta.name))?;
+        first_entry.get_or_insert(entry_idx);
+        h_off += entry.h_off;
+        n_off += entry.n_off;
+        if parts.peek().is_none() {
+            return Ok(PathTarget {
+                h_off,
+                n_off,
+                h_unit_size: entry.h_unit_size,
+                first_entry: first_entry.unwrap_or_default(),
+                entry_offset: entry._offset,
+                meta_offset: meta._offset,
+            });
+        }
+        meta = metas
+            .iter()
+            .find(|meta| meta._offset as i32 == entry.ptr_meta)
+            .ok_or_else(|| anyhow!("`{path}`: `{part}` isn't a struct"))?;
+    }
+    Err(anyhow!("Empty path"))
+}
+
+/// Builds a size info from a primitive type name or a dotted path.
+fn sizeinfo(metas: &[TDRMeta], idx: usize, spec: &str) -> Result<TDRSizeInfo> {
+    if let Ok(type_idx) = primative_idx(spec) {
+        return Ok(TDRSizeInfo {
+            _offset: 0,
+            n_off: INVALID_METALIB_VALUE,
+            h_off: INVALID_METALIB_VALUE,
+            unit_size: TDR_PRIMATIVE_TYPE_INFO[type_idx].size,
+            idx_size_type: type_idx as i32,
+        });
+    }
+    let target = find_path(metas, idx, spec)?;
+    Ok(TDRSizeInfo {
+        _offset: 0,
+        n_off: target.n_off,
+        h_off: target.h_off,
+        unit_size: target.h_unit_size,
+        idx_size_type: INVALID_METALIB_VALUE,
+    })
+}
+
+/// Fills in the fields that name other entries by path, once every meta is laid out.
+fn resolve_paths(metas: &mut [TDRMeta], idx: usize, meta: &MetaBuilder) -> Result<()> {
+    let selector = |metas: &[TDRMeta], path: &str| -> Result<(i32, i32, i32)> {
+        let target = find_path(metas, idx, path)?;
+        Ok((target.h_unit_size, target.h_off, target.entry_offset as i32))
+    };
+
+    if let Some(path) = &meta.version_indicator {
+        let target = find_path(metas, idx, path)?;
+        let indicator = &mut metas[idx].version_indicator;
+        indicator.n_off = target.n_off;
+        indicator.h_off = target.h_off;
+        indicator.unit_size = target.h_unit_size;
+    }
+    if let Some(spec) = &meta.sizeinfo {
+        let size_info = sizeinfo(metas, idx, spec)?;
+        let size_type = &mut metas[idx].size_type;
+        size_type.n_off = size_info.n_off;
+        size_type.h_off = size_info.h_off;
+        size_type.unit_size = size_info.unit_size;
+        size_type.idx_size_type = size_info.idx_size_type;
+    }
+    if let Some(path) = &meta.sort_key {
+        let target = find_path(metas, idx, path)?;
+        let sort_key = &mut metas[idx].sort_key;
+        sort_key.idx_sort_entry = target.first_entry as i32;
+        sort_key.sort_key_offset = target.n_off;
+        sort_key.ptr_sort_key_meta = target.meta_offset as i32;
+    }
+    if let Some(path) = &meta.split_table_key {
+        let target = find_path(metas, idx, path)?;
+        let split_table_key = &mut metas[idx].split_table_key;
+        split_table_key.h_off = target.h_off;
+        split_table_key.ptr_entry = target.entry_offset as i32;
+    }
+    let key_size = RECORD_SIZES_32.db_key_info;
+    for (i, path) in meta.primary_key.iter().enumerate() {
+        let target = find_path(metas, idx, path)
+            .with_context(|| format!("Failed to resolve primary key member `{path}`"))?;
+        let base = metas[idx].ptr_primary_key_base as u32;
+        metas[idx].primary_key.push(TDRDBKeyInfo {
+            _offset: (base + i as u32 * key_size) as u64,
+            h_off: target.h_off,
+            ptr_entry: target.entry_offset as i32,
+        });
+    }
+
+    for (i, entry) in meta.entries.iter().enumerate() {
+        let context = || format!("Failed to build entry `{}`", entry.name);
+        if let Some(path) = &entry.refer {
+            let (unit_size, h_off, ptr_entry) = selector(metas, path).with_context(context)?;
+            let referer = &mut metas[idx].entries[i].referer;
+            referer.unit_size = unit_size;
+            referer.h_off = h_off;
+            referer.ptr_entry = ptr_entry;
+        }
+        if let Some(path) = &entry.select {
+            let (unit_size, h_off, ptr_entry) = selector(metas, path).with_context(context)?;
+            let selector = &mut metas[idx].entries[i].selector;
+            selector.unit_size = unit_size;
+            selector.h_off = h_off;
+            selector.ptr_entry = ptr_entry;
+        }
+        if let Some(spec) = &entry.sizeinfo {
+            let size_info = sizeinfo(metas, idx, spec).with_context(context)?;
+            let entry_size_info = &mut metas[idx].entries[i].size_info;
+            entry_size_info.n_off = size_info.n_off;
+            entry_size_info.h_off = size_info.h_off;
+            entry_size_info.unit_size = size_info.unit_size;
+            entry_size_info.idx_size_type = size_info.idx_size_type;
+        }
+    }
+    Ok(())
+}
+
+/// What an entry's type names.
+enum EntryType {
+    /// Index in [`TDR_PRIMATIVE_TYPE_INFO`].
+    Primative(usize),
+    /// Index of a meta in the builder.
+    Meta(usize),
+}
+
+/// Where one entry goes within its meta.
+#[derive(Clone, Debug)]
+struct EntryLayout {
+    h_off: i32,
+    n_off: i32,
+    h_unit: i32,
+    n_unit: i32,
+    /// `custom_h_unit_size`, in bytes.
+    custom_size: i32,
+}
+
+/// Sizes and entry offsets of one meta.
+#[derive(Clone, Debug)]
+struct MetaLayout {
+    h_size: i32,
+    n_size: i32,
+    /// Alignment of the meta in host memory (`valid_align`).
+    align: i32,
+    entries: Vec<EntryLayout>,
+}
+
+/// Rounds `offset` up to a multiple of `align`.
+fn align_up(offset: i32, align: i32) -> i32 {
+    (offset + align - 1) / align * align
+}
+
+/// Looks names up in a [`MetalibBuilder`] and lays out its metas.
+struct Resolver<'a> {
+    builder: &'a MetalibBuilder,
+    macros: HashMap<&'a str, usize>,
+    metas: HashMap<&'a str, usize>,
+    groups: HashMap<&'a str, usize>,
+    /// Layouts of the metas laid out so far.
+    layouts: RefCell<HashMap<usize, MetaLayout>>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(builder: &'a MetalibBuilder) -> Result<Resolver<'a>> {
+        fn index<'a>(
+            what: &str,
+            names: impl Iterator<Item = &'a str>,
+        ) -> Result<HashMap<&'a str, usize>> {
+            let mut map = HashMap::new();
+            for (idx, name) in names.enumerate() {
+                if map.insert(name, idx).is_some() {
+                    return Err(anyhow!("Duplicate {what} `{name}`"));
+                }
+            }
+            Ok(map)
+        }
+
+        Ok(Resolver {
+            builder,
+            macros: index("macro", builder.macros.iter().map(|m| m.0.as_str()))?,
+            metas: index("meta", builder.metas.iter().map(|m| m.name.as_str()))?,
+            groups: index(
+                "macrogroup",
+                builder.macrogroups.iter().map(|g| g.0.as_str()),
+            )?,
+            layouts: RefCell::default(),
+        })
+    }
+
+    fn macro_idx(&self, name: &str) -> Result<i32> {
+        self.macros
+            .get(name)
+            .map(|&idx| idx as i32)
+            .ok_or_else(|| anyhow!("Unknown macro `{name}`"))
+    }
+
+    /// Returns the value and, for a macro, its index.
+    fn value(&self, value: &Value) -> Result<(i32, i32)> {
+        match value {
+            Value::Literal(value) => Ok((*value, INVALID_METALIB_VALUE)),
+            Value::Macro(name) => {
+                let idx = self.macro_idx(name)?;
+                Ok((self.builder.macros[idx as usize].1, idx))
+            }
+        }
+    }
+
+    fn entry_type(&self, entry: &EntryBuilder) -> Result<EntryType> {
+        match self.metas.get(entry.type_name.as_str()) {
+            Some(&meta) => Ok(EntryType::Meta(meta)),
+            None => Ok(EntryType::Primative(primative_idx(&entry.type_name)?)),
+        }
+    }
+
+    /// Host size, network size, host alignment and custom size of one element of `entry`.
+    fn unit(&self, entry: &EntryBuilder, seen: &mut Vec<usize>) -> Result<(i32, i32, i32, i32)> {
+        let custom_size = match &entry.size {
+            Some(size) => {
+                let size = self.value(size)?.0;
+                match self.entry_type(entry)? {
+                    EntryType::Primative(idx) if TDR_PRIMATIVE_TYPE_INFO[idx].size > 0 => {
+                        size * TDR_PRIMATIVE_TYPE_INFO[idx].size
+                    }
+                    _ => size,
+                }
+            }
+            None => 0,
+        };
+
+        let address = TDRMetaEntryFlags::POINT_TYPE | TDRMetaEntryFlags::REFER_TYPE;
+        let (h_unit, n_unit, align) = if entry.flag.intersects(address) {
+            // Pointers and references hold an address, which isn't sent over the network.
+            (POINTER_SIZE, 0, POINTER_SIZE)
+        } else {
+            match self.entry_type(entry)? {
+                EntryType::Meta(meta) => {
+                    let layout = self.meta_layout(meta, seen)?;
+                    (layout.h_size, layout.n_size, layout.align)
+                }
+                EntryType::Primative(idx) => {
+                    let size = TDR_PRIMATIVE_TYPE_INFO[idx].size;
+                    (size, size, size.max(1))
+                }
+            }
+        };
+        if custom_size > 0 {
+            return Ok((custom_size, custom_size, align, custom_size));
+        }
+        Ok((h_unit, n_unit, align, 0))
+    }
+
+    /// Lays out the meta at `idx`. `seen` holds the metas being laid out, to catch a struct
+    /// that contains itself.
+    fn meta_layout(&self, idx: usize, seen: &mut Vec<usize>) -> Result<MetaLayout> {
+        if let Some(layout) = self.layouts.borrow().get(&idx) {
+            return Ok(layout.clone());
+        }
+        let meta = &self.builder.metas[idx];
+        if seen.contains(&idx) {
+            return Err(anyhow!("Meta `{}` contains itself", meta.name));
+        }
+        seen.push(idx);
+
+        let pack = meta.align.max(1);
+        let union = meta.type_ == MetaPrimativeType::UNION;
+        let (mut h_size, mut n_size, mut max_align) = (0, 0, 1);
+        let mut entries = Vec::new();
+        for entry in meta.entries.iter() {
+            let (h_unit, n_unit, align, custom_size) = self
+                .unit(entry, seen)
+                .with_context(|| format!("Failed to lay out entry `{}`", entry.name))?;
+            let count = self.value(&entry.count)?.0;
+            let align = align.min(pack);
+            max_align = max_align.max(align);
+            let (h_off, n_off) = match union {
+                true => (0, 0),
+                false => (align_up(h_size, align), n_size),
+            };
+            entries.push(EntryLayout {
+                h_off,
+                n_off,
+                h_unit,
+                n_unit,
+                custom_size,
+            });
+            h_size = h_size.max(h_off + h_unit * count);
+            n_size = n_size.max(n_off + n_unit * count);
+        }
+        h_size = align_up(h_size, max_align);
+
+        if let Some(size) = &meta.size {
+            let size = self.value(size)?.0;
+            if size < h_size {
+                return Err(anyhow!(
+                    "Meta `{}` has size {size}, but its entries need {h_size} bytes",
+                    meta.name
+                ));
+            }
+            h_size = size;
+        }
+
+        seen.pop();
+        let layout = MetaLayout {
+            h_size,
+            n_size,
+            align: max_align,
+            entries,
+        };
+        self.layouts.borrow_mut().insert(idx, layout.clone());
+        Ok(layout)
+    }
+}
+
+/// Index in [`TDR_PRIMATIVE_TYPE_INFO`] of a primitive type name.
+fn primative_idx(type_name: &str) -> Result<usize> {
+    TDR_PRIMATIVE_TYPE_INFO
+        .iter()
+        .position(|info| info.xml_name == type_name)
+        .ok_or_else(|| anyhow!("Unknown type `{type_name}`, expected a primitive or a meta name"))
+}
+
+/// Index in [`TDR_PRIMATIVE_TYPE_INFO`] of the first row with the given type.
+fn primative_type_idx(type_: MetaPrimativeType) -> i32 {
+    TDR_PRIMATIVE_TYPE_INFO
+        .iter()
+        .position(|info| info.primative_type == type_)
+        .map_or(INVALID_METALIB_VALUE, |idx| idx as i32)
+}
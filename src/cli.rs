@@ -0,0 +1,639 @@
+//! Command line definition.
+//!
+//! The original `mldec <file> <offset>` form is still accepted: if the first argument isn't
+//! a subcommand, `dump` is assumed (see [`parse_args`]).
+
+use clap::{ArgAction, Args, Parser, Subcommand};
+use log::LevelFilter;
+use std::ffi::OsString;
+
+use mldec_rs::arch::MetalibArch;
+use mldec_rs::error::ErrorFormat;
+use mldec_rs::find::{parse_db_flag, parse_entry_type};
+use mldec_rs::list::ListSort;
+use mldec_rs::metalib::{MetaPrimativeType, TDRMetaEntryDBFlags};
+use mldec_rs::options::{ExportOptions, ParseOptions, StringEncoding, XmlEncoding};
+
+use crate::{parse_indent, OffsetSpec, OutputFormat};
+
+#[derive(Parser)]
+#[command(name = "mldec", version, about = "Decompiles TDR metalibs")]
+pub struct Cli {
+    #[command(flatten)]
+    pub parse: ParseArgs,
+
+    /// Log more detail to stderr (-v for each table read, -vv for each meta)
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Don't show progress for scans and directory dumps
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// How to report a failure on stderr: text, or json with an error code and offset
+    #[arg(long, global = true, default_value = "text", value_parser = ErrorFormat::from_name)]
+    pub errors: ErrorFormat,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+impl Cli {
+    pub fn log_level(&self) -> LevelFilter {
+        match self.verbose {
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Dump a metalib (or a directory of them) as XML or another format
+    Dump(DumpArgs),
+
+    /// Find every metalib embedded in a file and dump each one
+    Scan(ScanArgs),
+
+    /// Copy the raw bytes of a metalib out of the file it's embedded in
+    Extract(ExtractArgs),
+
+    /// Summarize a metalib's header without parsing its tables
+    Info(InfoArgs),
+
+    /// List the structs and unions (and optionally macros) in a metalib
+    List(ListArgs),
+
+    /// Parse a metalib and check that its tables are consistent
+    Validate(ValidateArgs),
+
+    /// Print a struct or union as a tree of its entries, offsets and sizes
+    Show(ShowArgs),
+
+    /// Print the host offset and size of a field path like `items[3].id`, or the field at
+    /// an offset
+    Offsetof(OffsetofArgs),
+
+    /// Decode a struct copied out of memory, or a packet, into JSON using a meta for its
+    /// layout
+    Decode(DecodeArgs),
+
+    /// Encode JSON (as printed by `decode`) into a struct's host layout
+    Encode(EncodeArgs),
+
+    /// Print the `meta.entry` paths of every entry with the given type and DB flags
+    Find(FindArgs),
+
+    /// Compile TDR XML into a metalib
+    Compile(CompileArgs),
+
+    /// List the metas, entries, macros and macrogroups that differ between two metalibs
+    Diff {
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+
+        /// Old metalib, as `<file>[@<offset>]`
+        old: String,
+
+        /// New metalib, as `<file>[@<offset>]`
+        new: String,
+    },
+
+    /// Compare two digests written with `--format digest`
+    DigestCompare {
+        /// Metalibs, as `<file>[@<offset>]`, whose meta names label the hashes in the
+        /// output. Digests only store the hashes.
+        #[arg(long = "names", value_name = "METALIB")]
+        names: Vec<String>,
+
+        old: String,
+        new: String,
+    },
+
+    /// Tally raw header/meta/entry field values across metalibs
+    SurveyFields {
+        /// Print JSON instead of CSV
+        #[arg(long)]
+        json: bool,
+
+        /// Metalibs to survey, as `<file>[@<offset>]`
+        #[arg(required = true)]
+        inputs: Vec<String>,
+    },
+
+    /// Build a static documentation site from a manifest of metalib versions
+    Site {
+        /// One `<file>[@<hex offset>] [label]` per line, oldest first
+        manifest: String,
+
+        /// Output directory
+        #[arg(short, long, default_value = "./output/site")]
+        output: String,
+    },
+}
+
+/// Options that affect how metalibs are parsed, shared by every subcommand.
+#[derive(Args)]
+pub struct ParseArgs {
+    /// Limit on the length of strings read from the metalib, in bytes
+    #[arg(long, global = true)]
+    pub max_string_size: Option<usize>,
+
+    /// Limit on the size a metalib header may claim, in bytes
+    #[arg(long, global = true)]
+    pub max_metalib_size: Option<u64>,
+
+    /// Encoding of the metalib's strings: gbk (the default), gb18030, big5 or utf-8
+    #[arg(long, global = true, value_parser = StringEncoding::from_name)]
+    pub string_encoding: Option<StringEncoding>,
+
+    /// Parse with this layout (32 or 64) instead of the one detected from the data
+    #[arg(long, global = true, value_parser = MetalibArch::from_name)]
+    pub force_arch: Option<MetalibArch>,
+
+    /// Replace unreadable strings, unknown types and bad macro indexes with placeholders
+    /// instead of failing
+    #[arg(long, global = true)]
+    pub lenient: bool,
+
+    /// Fail if `--lenient` had to work around anything
+    #[arg(long, global = true)]
+    pub fail_on_warnings: bool,
+}
+
+impl ParseArgs {
+    pub fn to_options(&self) -> ParseOptions {
+        let mut options = ParseOptions::default();
+        if let Some(max_string_size) = self.max_string_size {
+            options = options.max_string_size(max_string_size);
+        }
+        if let Some(max_metalib_size) = self.max_metalib_size {
+            options = options.max_metalib_size(max_metalib_size);
+        }
+        if let Some(arch) = self.force_arch {
+            options = options.force_arch(arch);
+        }
+        if let Some(string_encoding) = self.string_encoding {
+            options = options.string_encoding(string_encoding);
+        }
+        options
+            .strict(!self.lenient)
+            .fail_on_warnings(self.fail_on_warnings)
+    }
+}
+
+/// Output format and destination, shared by the subcommands that export metalibs.
+#[derive(Args)]
+pub struct OutputArgs {
+    /// xml, rust, csharp, python, proto, fbs, ksy, dot, csv, md, html, yaml, sql, digest, or json (with --macros-only)
+    #[arg(long, default_value = "xml", value_parser = OutputFormat::from_name)]
+    pub format: OutputFormat,
+
+    /// File or directory to write to (`-` for stdout)
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Write to stdout instead of a file
+    #[arg(long, conflicts_with = "output")]
+    pub stdout: bool,
+
+    /// XML indentation: `tabs` or `spaces:N`
+    #[arg(long, value_parser = parse_indent)]
+    pub indent: Option<String>,
+
+    /// Put each attribute of an `<entry>` tag on its own line once the tag is wider than
+    /// WIDTH characters
+    #[arg(long, value_name = "WIDTH", num_args = 0..=1, default_missing_value = "100")]
+    pub wrap_attrs: Option<usize>,
+
+    /// XML encoding: utf-8 or gbk
+    #[arg(long, default_value = "utf-8", value_parser = XmlEncoding::from_name)]
+    pub encoding: XmlEncoding,
+}
+
+impl OutputArgs {
+    pub fn to_options(&self) -> ExportOptions {
+        let mut options = ExportOptions::default().encoding(self.encoding);
+        if let Some(indent) = &self.indent {
+            options = options.indent(indent.clone());
+        }
+        if let Some(width) = self.wrap_attrs {
+            options = options.wrap_attrs(width);
+        }
+        options
+    }
+
+    /// Returns the output path, or `None` for stdout.
+    pub fn path(&self) -> Option<&str> {
+        match self.output.as_deref() {
+            _ if self.stdout => None,
+            Some("-") => None,
+            Some(path) => Some(path),
+            None => Some("./output/"),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct DumpArgs {
+    /// Metalib file (`-` for stdin), or a directory of them
+    pub file: String,
+
+    #[command(flatten)]
+    pub offset: OffsetArgs,
+
+    /// Only dump files matching this pattern (e.g. '*.bin') when given a directory
+    #[arg(long)]
+    pub glob: Option<String>,
+
+    /// Same as the `scan` subcommand
+    #[arg(long)]
+    pub all: bool,
+
+    /// Only dump macros and macrogroups, without reading the structs (XML or JSON)
+    #[arg(long, conflicts_with = "metas")]
+    pub macros_only: bool,
+
+    /// Only dump the struct/union with this name (repeatable, XML only)
+    #[arg(long = "meta", value_name = "NAME")]
+    pub metas: Vec<String>,
+
+    /// With --meta, also dump the structs and macrogroups the selected ones use
+    #[arg(long, requires = "metas")]
+    pub with_deps: bool,
+
+    /// Dump the metalib as it was at this version, leaving out newer structs and entries
+    /// and the entries that depend on them (XML only)
+    #[arg(long, value_name = "VERSION")]
+    pub cut_version: Option<i32>,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+impl DumpArgs {
+    pub fn offset(&self) -> OffsetSpec {
+        self.offset.spec()
+    }
+
+    pub fn export_options(&self) -> ExportOptions {
+        let options = self
+            .output
+            .to_options()
+            .metas(self.metas.clone(), self.with_deps);
+        match self.cut_version {
+            Some(version) => options.cut_version(version),
+            None => options,
+        }
+    }
+}
+
+/// Where a metalib starts within its file, given positionally or with `--offset`.
+#[derive(Args)]
+pub struct OffsetArgs {
+    /// Where the metalib starts: decimal, 0x-prefixed hex, or -N from the end of the file
+    #[arg(
+        value_name = "OFFSET",
+        allow_hyphen_values = true,
+        value_parser = OffsetSpec::parse,
+        conflicts_with = "offset"
+    )]
+    pub position: Option<OffsetSpec>,
+
+    /// Same as the positional offset
+    #[arg(long, allow_hyphen_values = true, value_parser = OffsetSpec::parse)]
+    pub offset: Option<OffsetSpec>,
+}
+
+impl OffsetArgs {
+    pub fn spec(&self) -> OffsetSpec {
+        self.position
+            .or(self.offset)
+            .unwrap_or(OffsetSpec::Start(0))
+    }
+}
+
+#[derive(Args)]
+pub struct ExtractArgs {
+    /// File containing the metalib (`-` for stdin)
+    pub file: String,
+
+    #[command(flatten)]
+    pub offset: OffsetArgs,
+
+    /// File or directory to write to
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Extract every metalib in the file, named after each one, into the output directory
+    #[arg(long, conflicts_with_all = ["position", "offset"])]
+    pub all: bool,
+}
+
+#[derive(Args)]
+pub struct ShowArgs {
+    /// Metalib file (`-` for stdin)
+    pub file: String,
+
+    /// Where the metalib starts: decimal, 0x-prefixed hex, or -N from the end of the file
+    #[arg(allow_hyphen_values = true, value_parser = OffsetSpec::parse)]
+    pub offset: OffsetSpec,
+
+    /// Name of the struct or union
+    pub meta: String,
+
+    /// How many levels of nested structs to expand
+    #[arg(long, default_value_t = 1)]
+    pub depth: usize,
+}
+
+#[derive(Args)]
+pub struct OffsetofArgs {
+    /// Metalib file (`-` for stdin)
+    pub file: String,
+
+    /// Where the metalib starts: decimal, 0x-prefixed hex, or -N from the end of the file
+    #[arg(allow_hyphen_values = true, value_parser = OffsetSpec::parse)]
+    pub offset: OffsetSpec,
+
+    /// Name of the struct or union
+    pub meta: String,
+
+    /// Dotted field path (`a.b[2].c`), or a decimal/0x-prefixed host offset to look up
+    pub path: String,
+}
+
+#[derive(Args)]
+pub struct DecodeArgs {
+    /// Metalib file (`-` for stdin)
+    pub file: String,
+
+    /// Where the metalib starts: decimal, 0x-prefixed hex, or -N from the end of the file
+    #[arg(allow_hyphen_values = true, value_parser = OffsetSpec::parse)]
+    pub offset: OffsetSpec,
+
+    /// Name of the struct or union
+    pub meta: String,
+
+    /// File holding the struct's bytes (`-` for stdin)
+    pub data: String,
+
+    /// The data is in the packed, big endian network layout rather than the host layout
+    #[arg(long)]
+    pub net: bool,
+
+    /// Leave out entries added after this version. With --net, a `versionindicator`
+    /// field in the packet can cut it further
+    #[arg(long)]
+    pub cut_version: Option<i32>,
+}
+
+#[derive(Args)]
+pub struct EncodeArgs {
+    /// Metalib file (`-` for stdin)
+    pub file: String,
+
+    /// Where the metalib starts: decimal, 0x-prefixed hex, or -N from the end of the file
+    #[arg(allow_hyphen_values = true, value_parser = OffsetSpec::parse)]
+    pub offset: OffsetSpec,
+
+    /// Name of the struct or union
+    pub meta: String,
+
+    /// JSON file with the field values (`-` for stdin)
+    pub json: String,
+
+    /// Leave entries added after this version zeroed
+    #[arg(long)]
+    pub cut_version: Option<i32>,
+
+    /// File or directory to write to
+    #[arg(short, long, default_value = "./output/")]
+    pub output: String,
+}
+
+#[derive(Args)]
+pub struct CompileArgs {
+    /// XML file (`-` for stdin)
+    pub file: String,
+
+    /// Layout to write the tables in
+    #[arg(long, default_value = "32", value_name = "32|64", value_parser = MetalibArch::from_name)]
+    pub arch: MetalibArch,
+
+    /// File or directory to write to
+    #[arg(short, long, default_value = "./output/")]
+    pub output: String,
+}
+
+#[derive(Args)]
+pub struct InfoArgs {
+    /// Metalib file (`-` for stdin)
+    pub file: String,
+
+    #[command(flatten)]
+    pub offset: OffsetArgs,
+
+    /// Print JSON instead of text
+    #[arg(long)]
+    pub json: bool,
+
+    /// Also read the body and show the layout probe
+    #[arg(long)]
+    pub full: bool,
+}
+
+impl InfoArgs {
+    pub fn offset(&self) -> OffsetSpec {
+        self.offset.spec()
+    }
+}
+
+#[derive(Args)]
+pub struct ScanArgs {
+    /// File to scan (`-` for stdin)
+    pub file: String,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Args)]
+pub struct ValidateArgs {
+    /// Metalib file (`-` for stdin)
+    pub file: String,
+
+    #[command(flatten)]
+    pub offset: OffsetArgs,
+}
+
+impl ValidateArgs {
+    pub fn offset(&self) -> OffsetSpec {
+        self.offset.spec()
+    }
+}
+
+#[derive(Args)]
+pub struct ListArgs {
+    /// Metalib file (`-` for stdin)
+    pub file: String,
+
+    #[command(flatten)]
+    pub offset: OffsetArgs,
+
+    /// Also list macros
+    #[arg(long)]
+    pub macros: bool,
+
+    /// file, name, id or size (largest first)
+    #[arg(long, default_value = "file", value_parser = ListSort::from_name)]
+    pub sort: ListSort,
+
+    /// Print tab separated values instead of aligned columns
+    #[arg(long)]
+    pub tsv: bool,
+}
+
+impl ListArgs {
+    pub fn offset(&self) -> OffsetSpec {
+        self.offset.spec()
+    }
+}
+
+#[derive(Args)]
+pub struct FindArgs {
+    /// Metalib file (`-` for stdin)
+    pub file: String,
+
+    #[command(flatten)]
+    pub offset: OffsetArgs,
+
+    /// Only entries of this type, as written in the XML (e.g. `string`, `uint16`)
+    #[arg(long = "type", value_parser = parse_entry_type)]
+    pub type_: Option<MetaPrimativeType>,
+
+    /// Only entries with this DB flag: unique, notnull, extendtotable, primarykey or
+    /// autoincrement (repeatable, all must be set)
+    #[arg(long = "flag", value_parser = parse_db_flag)]
+    pub flags: Vec<TDRMetaEntryDBFlags>,
+}
+
+impl FindArgs {
+    pub fn offset(&self) -> OffsetSpec {
+        self.offset.spec()
+    }
+}
+
+const SUBCOMMANDS: &[&str] = &[
+    "dump",
+    "scan",
+    "extract",
+    "info",
+    "list",
+    "validate",
+    "find",
+    "show",
+    "offsetof",
+    "decode",
+    "encode",
+    "compile",
+    "diff",
+    "digest-compare",
+    "survey-fields",
+    "site",
+    "help",
+];
+
+/// Parses the command line, treating anything that doesn't name a subcommand as `dump`.
+pub fn parse_args() -> Cli {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let is_new_style = args.iter().skip(1).any(|arg| {
+        let arg = arg.to_string_lossy();
+        SUBCOMMANDS.contains(&arg.as_ref())
+            || ["-h", "--help", "-V", "--version"].contains(&arg.as_ref())
+    });
+    if !is_new_style && args.len() > 1 {
+        args.insert(1, OsString::from("dump"));
+    }
+    Cli::parse_from(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cli(args: &[&str]) -> Cli {
+        Cli::try_parse_from([&["mldec"], args].concat()).unwrap()
+    }
+
+    #[test]
+    fn no_flags_are_the_library_defaults() {
+        let cli = cli(&["dump", "a.bin"]);
+        assert_eq!(
+            format!("{:?}", cli.parse.to_options()),
+            format!("{:?}", ParseOptions::default())
+        );
+        let Command::Dump(dump) = cli.command else {
+            panic!("expected dump");
+        };
+        assert_eq!(
+            format!("{:?}", dump.export_options()),
+            format!("{:?}", ExportOptions::default())
+        );
+    }
+
+    #[test]
+    fn flags_map_onto_the_options() {
+        let cli = cli(&[
+            "dump",
+            "a.bin",
+            "--lenient",
+            "--fail-on-warnings",
+            "--max-string-size",
+            "16",
+            "--max-metalib-size",
+            "1024",
+            "--string-encoding",
+            "big5",
+            "--force-arch",
+            "64",
+            "--indent",
+            "spaces:2",
+            "--wrap-attrs",
+            "--encoding",
+            "gbk",
+            "--meta",
+            "Packet",
+            "--with-deps",
+            "--cut-version",
+            "2",
+        ]);
+        let expected = ParseOptions::default()
+            .strict(false)
+            .fail_on_warnings(true)
+            .max_string_size(16)
+            .max_metalib_size(1024)
+            .string_encoding(StringEncoding::Big5)
+            .force_arch(MetalibArch::Bits64);
+        assert_eq!(
+            format!("{:?}", cli.parse.to_options()),
+            format!("{expected:?}")
+        );
+
+        let Command::Dump(dump) = cli.command else {
+            panic!("expected dump");
+        };
+        let expected = ExportOptions::default()
+            .indent("  ")
+            .wrap_attrs(100)
+            .encoding(XmlEncoding::Gbk)
+            .metas(vec!["Packet".to_string()], true)
+            .cut_version(2);
+        assert_eq!(
+            format!("{:?}", dump.export_options()),
+            format!("{expected:?}")
+        );
+    }
+}
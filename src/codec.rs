@@ -0,0 +1,542 @@
+//! Converting data described by a meta, e.g. a struct copied out of process memory or a
+//! captured packet, to and from a tree of named, typed [`Value`]s.
+//!
+//! [`decode_host`] reads a struct as laid out in memory, [`decode_net`] as packed on the
+//! wire, and [`encode_host`] turns a value back into memory layout. [`hton`] and [`ntoh`]
+//! convert between the two layouts directly. Strings are GBK like the metalib's own.
+//! Dates, times and IPs are shown as text (`2024-01-31`, `12:30:05`, `2024-01-31 12:30:05`,
+//! `10.0.0.1`).
+
+use anyhow::{anyhow, Result};
+use byteorder::ByteOrder;
+use encoding::all::GBK;
+use encoding::{DecoderTrap, EncoderTrap, Encoding};
+use std::fmt::Write as _;
+use std::net::Ipv4Addr;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::export::json_string;
+use crate::metalib::{
+    MetaPrimativeType, TDRMeta, TDRMetaEntry, TDRMetaEntryFlags, INVALID_METALIB_VALUE,
+};
+
+mod convert;
+mod host;
+mod net;
+
+/// Deepest the codecs nest structs before giving up on a meta that (through corruption)
+/// contains itself.
+const MAX_NESTING_DEPTH: usize = 64;
+
+/// Fails if `prefix`, the path of a field of type `meta` followed by a dot, is nested deeper
+/// than [`MAX_NESTING_DEPTH`].
+fn check_depth(meta: &TDRMeta, prefix: &str) -> Result<()> {
+    if prefix.matches('.').count() > MAX_NESTING_DEPTH {
+        return Err(anyhow!(
+            "Exceeded max nesting depth in meta `{}`, which may contain itself",
+            meta.name
+        ));
+    }
+    Ok(())
+}
+
+pub use convert::{hton, ntoh};
+pub use host::{decode_host, encode_host};
+pub use net::decode_net;
+
+/// A decoded field.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Value {
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(String),
+    /// Elements of an entry with a `count`.
+    Array(Vec<Value>),
+    /// Entries of a struct, in order. A union holds just the member in use, or every
+    /// member when that isn't known.
+    Struct(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The value at a dotted path like `head.items[2].id` inside a struct.
+    pub fn get(&self, path: &str) -> Option<&Value> {
+        match self {
+            Value::Struct(fields) => find_field(fields, path),
+            _ => None,
+        }
+    }
+
+    /// The value as an integer, if it is one.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::Int(value) => Some(value),
+            Value::UInt(value) => i64::try_from(value).ok(),
+            _ => None,
+        }
+    }
+
+    /// Parses JSON in the shape [`Value::to_json`] writes. Objects become structs, whole
+    /// numbers integers and `null` a NaN float.
+    pub fn from_json(json: &str) -> Result<Value> {
+        let json: serde_json::Value =
+            serde_json::from_str(json).map_err(|err| anyhow!("Invalid JSON: {err}"))?;
+        Ok(Value::from(json))
+    }
+
+    /// Formats the value as indented JSON. Structs become objects (in entry order) and
+    /// non-finite floats `null`.
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write_json(&mut out, 0);
+        out.push('\n');
+        out
+    }
+
+    fn write_json(&self, out: &mut String, level: usize) {
+        let indent = "  ".repeat(level + 1);
+        let close = "  ".repeat(level);
+        match self {
+            Value::Int(value) => write!(out, "{value}").unwrap(),
+            Value::UInt(value) => write!(out, "{value}").unwrap(),
+            Value::Float(value) if value.is_finite() => write!(out, "{value:?}").unwrap(),
+            Value::Float(_) => out.push_str("null"),
+            Value::String(value) => out.push_str(&json_string(value)),
+            Value::Array(elements) if elements.is_empty() => out.push_str("[]"),
+            Value::Array(elements) => {
+                out.push_str("[\n");
+                for (i, element) in elements.iter().enumerate() {
+                    out.push_str(&indent);
+                    element.write_json(out, level + 1);
+                    out.push_str(if i + 1 < elements.len() { ",\n" } else { "\n" });
+                }
+                write!(out, "{close}]").unwrap();
+            }
+            Value::Struct(fields) if fields.is_empty() => out.push_str("{}"),
+            Value::Struct(fields) => {
+                out.push_str("{\n");
+                for (i, (name, field)) in fields.iter().enumerate() {
+                    write!(out, "{indent}{}: ", json_string(name)).unwrap();
+                    field.write_json(out, level + 1);
+                    out.push_str(if i + 1 < fields.len() { ",\n" } else { "\n" });
+                }
+                write!(out, "{close}}}").unwrap();
+            }
+        }
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(json: serde_json::Value) -> Value {
+        match json {
+            serde_json::Value::Null => Value::Float(f64::NAN),
+            serde_json::Value::Bool(value) => Value::UInt(value as u64),
+            serde_json::Value::Number(number) => match (number.as_i64(), number.as_u64()) {
+                (Some(value), _) => Value::Int(value),
+                (None, Some(value)) => Value::UInt(value),
+                _ => Value::Float(number.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(value) => Value::String(value),
+            serde_json::Value::Array(elements) => {
+                Value::Array(elements.into_iter().map(Value::from).collect())
+            }
+            serde_json::Value::Object(fields) => Value::Struct(
+                fields
+                    .into_iter()
+                    .map(|(name, field)| (name, Value::from(field)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// What kind of value this is, for error messages.
+fn describe(value: &Value) -> &'static str {
+    match value {
+        Value::Int(_) | Value::UInt(_) => "an integer",
+        Value::Float(_) => "a float",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Struct(_) => "a struct",
+    }
+}
+
+/// Looks up a dotted path in the fields of a struct, which may still be being decoded.
+fn find_field<'a>(fields: &'a [(String, Value)], path: &str) -> Option<&'a Value> {
+    let (component, rest) = match path.split_once('.') {
+        Some((component, rest)) => (component, Some(rest)),
+        None => (path, None),
+    };
+    let (name, index) = match component.split_once('[') {
+        Some((name, index)) => (name, Some(index.strip_suffix(']')?.parse::<usize>().ok()?)),
+        None => (component, None),
+    };
+    let mut value = fields
+        .iter()
+        .find(|(field, _)| field == name)
+        .map(|(_, value)| value)?;
+    if let Some(index) = index {
+        let Value::Array(elements) = value else {
+            return None;
+        };
+        value = elements.get(index)?;
+    }
+    match rest {
+        Some(rest) => value.get(rest),
+        None => Some(value),
+    }
+}
+
+/// Byte range `start..start + len` of `data`, or an error naming the field at `path`.
+fn slice<'a>(data: &'a [u8], start: usize, len: usize, path: &str) -> Result<&'a [u8]> {
+    start
+        .checked_add(len)
+        .and_then(|end| data.get(start..end))
+        .ok_or_else(|| {
+            anyhow!(
+                "`{path}` needs bytes {start}..{} but the buffer is {} bytes",
+                start.saturating_add(len),
+                data.len()
+            )
+        })
+}
+
+/// Where a field `h_off` bytes into a meta starting at `base` begins. Offsets come from the
+/// file, so a negative one, or one past the end of memory, is an error.
+fn field_offset(base: usize, h_off: i32, path: &str) -> Result<usize> {
+    usize::try_from(h_off)
+        .ok()
+        .and_then(|h_off| base.checked_add(h_off))
+        .ok_or_else(|| anyhow!("`{path}` is at offset {h_off} from {base}, which is out of range"))
+}
+
+/// Where element `i` of an array of `unit` byte elements starting at `start` begins.
+fn element_offset(start: usize, i: usize, unit: usize, path: &str) -> Result<usize> {
+    i.checked_mul(unit)
+        .and_then(|offset| start.checked_add(offset))
+        .ok_or_else(|| anyhow!("`{path}` is past the end of memory"))
+}
+
+/// A size read from the file, which can't be negative.
+fn file_size(size: i32, what: &str, path: &str) -> Result<usize> {
+    usize::try_from(size).map_err(|_| anyhow!("`{path}` has a negative {what} of {size}"))
+}
+
+/// How many elements `entry` holds, checking the `count` and element size the file gives
+/// it: a negative count is an error, and so is more than one element of zero bytes, which
+/// would all sit at the same offset.
+fn entry_count(entry: &TDRMetaEntry, unit: usize, path: &str) -> Result<usize> {
+    let count = file_size(entry.count, "count", path)?;
+    if count > 1 && unit == 0 {
+        return Err(anyhow!("`{path}` has {count} elements of 0 bytes"));
+    }
+    Ok(count)
+}
+
+/// The fewest bytes [`read_primitive`] and [`write_primitive`] need for a `type_`
+/// element. Strings can be any length.
+fn primitive_size(type_: MetaPrimativeType) -> usize {
+    match type_ {
+        MetaPrimativeType::CHAR | MetaPrimativeType::UCHAR | MetaPrimativeType::BYTE => 1,
+        MetaPrimativeType::SHORT | MetaPrimativeType::USHORT | MetaPrimativeType::WCHAR => 2,
+        MetaPrimativeType::INT
+        | MetaPrimativeType::UINT
+        | MetaPrimativeType::LONG
+        | MetaPrimativeType::ULONG
+        | MetaPrimativeType::MONEY
+        | MetaPrimativeType::FLOAT
+        | MetaPrimativeType::DATE
+        | MetaPrimativeType::TIME
+        | MetaPrimativeType::IP => 4,
+        MetaPrimativeType::LONGLONG
+        | MetaPrimativeType::ULONGLONG
+        | MetaPrimativeType::DOUBLE
+        | MetaPrimativeType::DATETIME => 8,
+        _ => 0,
+    }
+}
+
+/// Errors if `len` bytes are too few for one `type_` element.
+fn check_primitive_size(type_: MetaPrimativeType, len: usize) -> Result<()> {
+    let size = primitive_size(type_);
+    if len < size {
+        return Err(anyhow!("{type_:?} needs {size} bytes but only has {len}"));
+    }
+    Ok(())
+}
+
+/// Reads a little or big endian integer of 1, 2, 4 or 8 bytes, e.g. a `refer` or
+/// `select` field whose size is only known at runtime.
+fn read_int<B: ByteOrder>(data: &[u8]) -> Result<i64> {
+    match data.len() {
+        1 => Ok(data[0] as i8 as i64),
+        2 => Ok(B::read_i16(data) as i64),
+        4 => Ok(B::read_i32(data) as i64),
+        8 => Ok(B::read_i64(data)),
+        len => Err(anyhow!("Can't read a {len} byte integer")),
+    }
+}
+
+/// Decodes one element of a primitive entry from exactly its bytes.
+fn read_primitive<B: ByteOrder>(type_: MetaPrimativeType, data: &[u8]) -> Result<Value> {
+    check_primitive_size(type_, data.len())?;
+    let value = match type_ {
+        MetaPrimativeType::CHAR => Value::Int(data[0] as i8 as i64),
+        MetaPrimativeType::UCHAR | MetaPrimativeType::BYTE => Value::UInt(data[0] as u64),
+        MetaPrimativeType::SHORT => Value::Int(B::read_i16(data) as i64),
+        MetaPrimativeType::USHORT | MetaPrimativeType::WCHAR => {
+            Value::UInt(B::read_u16(data) as u64)
+        }
+        MetaPrimativeType::INT | MetaPrimativeType::LONG | MetaPrimativeType::MONEY => {
+            Value::Int(B::read_i32(data) as i64)
+        }
+        MetaPrimativeType::UINT | MetaPrimativeType::ULONG => Value::UInt(B::read_u32(data) as u64),
+        MetaPrimativeType::LONGLONG => Value::Int(B::read_i64(data)),
+        MetaPrimativeType::ULONGLONG => Value::UInt(B::read_u64(data)),
+        // Going through the shortest f32 text keeps `0.1` as 0.1 rather than 0.100000001...
+        MetaPrimativeType::FLOAT => {
+            Value::Float(B::read_f32(data).to_string().parse().unwrap_or(f64::NAN))
+        }
+        MetaPrimativeType::DOUBLE => Value::Float(B::read_f64(data)),
+        MetaPrimativeType::DATE => Value::String(format_date::<B>(data)),
+        MetaPrimativeType::TIME => Value::String(format_time::<B>(data)),
+        MetaPrimativeType::DATETIME => Value::String(format!(
+            "{} {}",
+            format_date::<B>(&data[..4]),
+            format_time::<B>(&data[4..8])
+        )),
+        MetaPrimativeType::IP => Value::String(format_ip(data)),
+        MetaPrimativeType::STRING => {
+            let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+            let text = GBK
+                .decode(&data[..end], DecoderTrap::Replace)
+                .map_err(|err| anyhow!("Failed to decode string: {err}"))?;
+            Value::String(text)
+        }
+        MetaPrimativeType::WSTRING => {
+            let units: Vec<u16> = data
+                .chunks_exact(2)
+                .map(B::read_u16)
+                .take_while(|&unit| unit != 0)
+                .collect();
+            Value::String(String::from_utf16_lossy(&units))
+        }
+        type_ => return Err(anyhow!("Can't decode a value of type {type_:?}")),
+    };
+    Ok(value)
+}
+
+/// Encodes `value` into exactly the bytes of one element of a primitive entry, the reverse
+/// of [`read_primitive`]. Strings must leave room for their NUL.
+fn write_primitive<B: ByteOrder>(
+    type_: MetaPrimativeType,
+    value: &Value,
+    out: &mut [u8],
+) -> Result<()> {
+    check_primitive_size(type_, out.len())?;
+    let expected = |what: &str| anyhow!("Expected {what}, got {}", describe(value));
+    let int = || -> Result<i128> {
+        match *value {
+            Value::Int(value) => Ok(value as i128),
+            Value::UInt(value) => Ok(value as i128),
+            _ => Err(expected("an integer")),
+        }
+    };
+    let ranged = |min: i128, max: i128| -> Result<i128> {
+        let int = int()?;
+        match (min..=max).contains(&int) {
+            true => Ok(int),
+            false => Err(anyhow!("{int} is out of range for {type_:?}")),
+        }
+    };
+    let text = || match value {
+        Value::String(text) => Ok(text.as_str()),
+        _ => Err(expected("a string")),
+    };
+
+    match type_ {
+        MetaPrimativeType::CHAR => out[0] = ranged(i8::MIN as i128, i8::MAX as i128)? as u8,
+        MetaPrimativeType::UCHAR | MetaPrimativeType::BYTE => {
+            out[0] = ranged(0, u8::MAX as i128)? as u8
+        }
+        MetaPrimativeType::SHORT => {
+            B::write_i16(out, ranged(i16::MIN as i128, i16::MAX as i128)? as i16)
+        }
+        MetaPrimativeType::USHORT | MetaPrimativeType::WCHAR => {
+            B::write_u16(out, ranged(0, u16::MAX as i128)? as u16)
+        }
+        MetaPrimativeType::INT | MetaPrimativeType::LONG | MetaPrimativeType::MONEY => {
+            B::write_i32(out, ranged(i32::MIN as i128, i32::MAX as i128)? as i32)
+        }
+        MetaPrimativeType::UINT | MetaPrimativeType::ULONG => {
+            B::write_u32(out, ranged(0, u32::MAX as i128)? as u32)
+        }
+        MetaPrimativeType::LONGLONG => {
+            B::write_i64(out, ranged(i64::MIN as i128, i64::MAX as i128)? as i64)
+        }
+        MetaPrimativeType::ULONGLONG => B::write_u64(out, ranged(0, u64::MAX as i128)? as u64),
+        MetaPrimativeType::FLOAT | MetaPrimativeType::DOUBLE => {
+            let float = match *value {
+                Value::Float(value) => value,
+                Value::Int(value) => value as f64,
+                Value::UInt(value) => value as f64,
+                _ => return Err(expected("a number")),
+            };
+            match type_ {
+                MetaPrimativeType::FLOAT => B::write_f32(out, float as f32),
+                _ => B::write_f64(out, float),
+            }
+        }
+        MetaPrimativeType::DATE => parse_date::<B>(text()?, out)?,
+        MetaPrimativeType::TIME => parse_time::<B>(text()?, out)?,
+        MetaPrimativeType::DATETIME => {
+            let text = text()?;
+            let (date, time) = text
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("Expected `YYYY-MM-DD HH:MM:SS`, got `{text}`"))?;
+            parse_date::<B>(date, &mut out[..4])?;
+            parse_time::<B>(time, &mut out[4..8])?;
+        }
+        MetaPrimativeType::IP => {
+            let text = text()?;
+            let ip: Ipv4Addr = text
+                .parse()
+                .map_err(|_| anyhow!("Expected an IPv4 address, got `{text}`"))?;
+            out[..4].copy_from_slice(&ip.octets());
+        }
+        MetaPrimativeType::STRING => {
+            let text = text()?;
+            let bytes = GBK
+                .encode(text, EncoderTrap::Strict)
+                .map_err(|_| anyhow!("`{text}` can't be encoded as GBK"))?;
+            if bytes.len() >= out.len() {
+                return Err(anyhow!(
+                    "`{text}` is {} bytes, but the buffer only fits {} and a NUL",
+                    bytes.len(),
+                    out.len().saturating_sub(1)
+                ));
+            }
+            out.fill(0);
+            out[..bytes.len()].copy_from_slice(&bytes);
+        }
+        MetaPrimativeType::WSTRING => {
+            let text = text()?;
+            let units: Vec<u16> = text.encode_utf16().collect();
+            if units.len() >= out.len() / 2 {
+                return Err(anyhow!(
+                    "`{text}` is {} UTF-16 units, but the buffer only fits {} and a NUL",
+                    units.len(),
+                    (out.len() / 2).saturating_sub(1)
+                ));
+            }
+            out.fill(0);
+            for (unit, chunk) in units.iter().zip(out.chunks_exact_mut(2)) {
+                B::write_u16(chunk, *unit);
+            }
+        }
+        type_ => return Err(anyhow!("Can't encode a value of type {type_:?}")),
+    }
+    Ok(())
+}
+
+/// Splits `a-b-c` (or `a:b:c`) into a 16-bit number and two bytes.
+fn parse_triple(text: &str, separator: char) -> Option<(i16, u8, u8)> {
+    let mut parts = text.split(separator);
+    let triple = (
+        parts.next()?.parse().ok()?,
+        parts.next()?.parse().ok()?,
+        parts.next()?.parse().ok()?,
+    );
+    parts.next().is_none().then_some(triple)
+}
+
+/// The reverse of [`format_date`].
+pub(crate) fn parse_date<B: ByteOrder>(text: &str, out: &mut [u8]) -> Result<()> {
+    let (year, month, day) = parse_triple(text, '-')
+        .ok_or_else(|| anyhow!("Expected a `YYYY-MM-DD` date, got `{text}`"))?;
+    B::write_i16(out, year);
+    out[2] = month;
+    out[3] = day;
+    Ok(())
+}
+
+/// The reverse of [`format_time`].
+pub(crate) fn parse_time<B: ByteOrder>(text: &str, out: &mut [u8]) -> Result<()> {
+    let (hour, minute, second) = parse_triple(text, ':')
+        .ok_or_else(|| anyhow!("Expected an `HH:MM:SS` time, got `{text}`"))?;
+    B::write_i16(out, hour);
+    out[2] = minute;
+    out[3] = second;
+    Ok(())
+}
+
+/// `tdr_date_t`: 16-bit year, then month and day bytes.
+pub(crate) fn format_date<B: ByteOrder>(data: &[u8]) -> String {
+    format!("{:04}-{:02}-{:02}", B::read_i16(data), data[2], data[3])
+}
+
+/// `tdr_time_t`: 16-bit hour, then minute and second bytes.
+pub(crate) fn format_time<B: ByteOrder>(data: &[u8]) -> String {
+    format!("{:02}:{:02}:{:02}", B::read_i16(data), data[2], data[3])
+}
+
+/// Formats a `tdr_ip_t` as a dotted quad. It's an `in_addr`, so the first byte is the
+/// first octet whatever the byte order of the data around it.
+pub(crate) fn format_ip(data: &[u8]) -> String {
+    Ipv4Addr::new(data[0], data[1], data[2], data[3]).to_string()
+}
+
+/// Whether `entry` existed at `cut_version`. Without a cut every entry does.
+fn in_version(entry: &TDRMetaEntry, cut_version: Option<i32>) -> bool {
+    cut_version.is_none_or(|cut| entry.version <= cut)
+}
+
+/// The cut for the rest of `meta` once its `versionindicator` field says the data is from
+/// `version`: the older of that and `cut_version`.
+fn indicated_cut(meta: &TDRMeta, cut_version: Option<i32>, version: i64) -> Result<Option<i32>> {
+    let version = i32::try_from(version)
+        .ok()
+        .filter(|&version| version >= meta.base_version)
+        .ok_or_else(|| {
+            anyhow!(
+                "`{}` says it's version {version}, but it was added in version {}",
+                meta.name,
+                meta.base_version
+            )
+        })?;
+    Ok(Some(cut_version.map_or(version, |cut| cut.min(version))))
+}
+
+/// Whether `entry` holds an address rather than the data itself.
+fn is_pointer(entry: &TDRMetaEntry) -> bool {
+    entry
+        .flag
+        .intersects(TDRMetaEntryFlags::POINT_TYPE | TDRMetaEntryFlags::REFER_TYPE)
+}
+
+fn is_string(entry: &TDRMetaEntry) -> bool {
+    matches!(
+        entry.type_,
+        MetaPrimativeType::STRING | MetaPrimativeType::WSTRING
+    )
+}
+
+/// Whether `entry` is a string without a `size`, so its `count` is its length.
+fn is_unsized_string(entry: &TDRMetaEntry) -> bool {
+    is_string(entry) && entry.custom_h_unit_size <= 0
+}
+
+/// The member of a union that `selector` picks: the one whose id range contains it, or
+/// whose id equals it.
+fn select_member(union: &TDRMeta, selector: i64) -> Option<&TDRMetaEntry> {
+    union.entries.iter().find(|member| {
+        if member.flag.contains(TDRMetaEntryFlags::HAS_MAXMIN_ID) {
+            (member.min_id as i64..=member.max_id as i64).contains(&selector)
+        } else {
+            member.id != INVALID_METALIB_VALUE && member.id as i64 == selector
+        }
+    })
+}
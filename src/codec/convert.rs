@@ -0,0 +1,840 @@
+//! Converting a buffer between the host and network layouts of the same meta, like TDR's
+//! `hton` and `ntoh`, without decoding the values.
+//!
+//! Each primitive element is copied between its `h_off` and its place in the packed
+//! stream with its bytes swapped. `refer`, `select` and `sizeinfo` fields are read from the
+//! host side, which `ntoh` fills in as it goes since they come before what they describe.
+
+use anyhow::{anyhow, Context, Result};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use super::{
+    check_depth, element_offset, entry_count, field_offset, file_size, in_version, indicated_cut,
+    is_pointer, is_string, is_unsized_string, read_int, select_member, slice,
+};
+use crate::metalib::{
+    MetaPrimativeType, Metalib, TDRMeta, TDRMetaEntry, TDRSizeInfo, INVALID_METALIB_VALUE,
+};
+
+/// Packs `host`, laid out in memory as `meta`, into the network layout in `net`. Returns
+/// how many bytes of `net` were written, which depends on the `refer` counts, selected
+/// union members and string lengths.
+///
+/// Entries newer than `cut_version` and pointers are left out. `sizeinfo` prefixes and
+/// fields are set to the packed length, and `versionindicator` fields to `cut_version`
+/// (or the meta's current version), so neither needs filling in in `host`. Unions without a `select` entry can't be packed, since which member is in
+/// use isn't known.
+pub fn hton(
+    metalib: &Metalib,
+    meta: &TDRMeta,
+    host: &[u8],
+    net: &mut [u8],
+    cut_version: Option<i32>,
+) -> Result<usize> {
+    let mut packer = Packer {
+        metalib,
+        host,
+        out: Vec::new(),
+        written: Vec::new(),
+        cut_version,
+    };
+    packer.pack_meta(meta, 0, "")?;
+
+    let len = packer.out.len();
+    let net_len = net.len();
+    net.get_mut(..len)
+        .ok_or_else(|| anyhow!("Packing needs {len} bytes but the buffer is {net_len}"))?
+        .copy_from_slice(&packer.out);
+    Ok(len)
+}
+
+/// Unpacks `net`, in the network layout of `meta`, into `host`. Returns how many bytes of
+/// `host` were written: `meta.h_unit_size`, with padding, pointers, unused array elements
+/// and entries newer than `cut_version` zeroed. A meta with a `versionindicator` cuts the
+/// rest of its entries at the version it gives, if that's older.
+pub fn ntoh(
+    metalib: &Metalib,
+    meta: &TDRMeta,
+    net: &[u8],
+    host: &mut [u8],
+    cut_version: Option<i32>,
+) -> Result<usize> {
+    let len = file_size(meta.h_unit_size, "size", &meta.name)?;
+    let host_len = host.len();
+    let host = host.get_mut(..len).ok_or_else(|| {
+        anyhow!(
+            "`{}` needs {len} bytes but the buffer is {host_len}",
+            meta.name
+        )
+    })?;
+    host.fill(0);
+
+    let mut unpacker = Unpacker {
+        metalib,
+        net,
+        pos: 0,
+        host,
+        cut_version,
+    };
+    unpacker.unpack_meta(meta, 0, "")?;
+    Ok(len)
+}
+
+/// Swaps the bytes of one primitive element between little and big endian. Strings and
+/// IPs are the same in both.
+fn swap_primitive(type_: MetaPrimativeType, src: &[u8], dst: &mut [u8]) {
+    dst.copy_from_slice(src);
+    match type_ {
+        MetaPrimativeType::STRING | MetaPrimativeType::IP => {}
+        MetaPrimativeType::DATE | MetaPrimativeType::TIME => dst[..2].reverse(),
+        MetaPrimativeType::DATETIME => {
+            dst[..2].reverse();
+            dst[4..6].reverse();
+        }
+        MetaPrimativeType::WSTRING => dst.chunks_exact_mut(2).for_each(<[u8]>::reverse),
+        _ => dst.reverse(),
+    }
+}
+
+/// Length of a sized string in `data`: up to and including its NUL.
+fn string_len(type_: MetaPrimativeType, data: &[u8]) -> usize {
+    let unit = match type_ {
+        MetaPrimativeType::WSTRING => 2,
+        _ => 1,
+    };
+    data.chunks(unit)
+        .position(|chunk| chunk.iter().all(|&b| b == 0))
+        .map_or(data.len(), |i| (i + 1) * unit)
+}
+
+/// The size of a `sizeinfo` or `versionindicator` integer, which has to be one
+/// [`read_int`] can read.
+fn int_size(unit_size: i32, what: &str, path: &str) -> Result<usize> {
+    match unit_size {
+        1 | 2 | 4 | 8 => Ok(unit_size as usize),
+        _ => Err(anyhow!(
+            "`{path}` has a {unit_size} byte `{what}`, but it must be 1, 2, 4 or 8"
+        )),
+    }
+}
+
+/// The entry a `sizeinfo` gets its length from: a prefix of `size` bytes, or the field of
+/// `size` bytes at a host offset.
+enum Length {
+    Prefix(usize),
+    Field { h_off: i32, size: usize },
+}
+
+fn length_of(size_info: &TDRSizeInfo, path: &str) -> Result<Option<Length>> {
+    let length = if size_info.unit_size <= 0 {
+        None
+    } else if size_info.idx_size_type != INVALID_METALIB_VALUE {
+        Some(Length::Prefix(int_size(
+            size_info.unit_size,
+            "sizeinfo",
+            path,
+        )?))
+    } else if size_info.h_off != INVALID_METALIB_VALUE {
+        Some(Length::Field {
+            h_off: size_info.h_off,
+            size: int_size(size_info.unit_size, "sizeinfo", path)?,
+        })
+    } else {
+        None
+    };
+    Ok(length)
+}
+
+/// Whether `entry` is in the network layout at `cut_version`.
+fn is_packed(entry: &TDRMetaEntry, cut_version: Option<i32>) -> bool {
+    in_version(entry, cut_version) && !is_pointer(entry) && !entry.is_zero_size()
+}
+
+fn nested_meta<'a>(
+    metalib: &'a Metalib,
+    entry: &TDRMetaEntry,
+    path: &str,
+) -> Result<Option<&'a TDRMeta>> {
+    match entry.ptr_meta {
+        INVALID_METALIB_VALUE => Ok(None),
+        ptr_meta => metalib
+            .get_meta_by_offset(ptr_meta)
+            .map(Some)
+            .with_context(|| format!("`{path}` has no meta")),
+    }
+}
+
+/// Reads a `refer` count from the host buffer and checks it against the entry's `count`,
+/// for elements of `unit` bytes.
+fn refer_count(
+    host: &[u8],
+    entry: &TDRMetaEntry,
+    base: usize,
+    unit: usize,
+    path: &str,
+) -> Result<usize> {
+    let count = entry_count(entry, unit, path)?;
+    if entry.referer.h_off == INVALID_METALIB_VALUE {
+        return Ok(count);
+    }
+    let refer = slice(
+        host,
+        field_offset(base, entry.referer.h_off, path)?,
+        file_size(entry.referer.unit_size, "refer size", path)?,
+        path,
+    )?;
+    let refer = read_int::<LittleEndian>(refer)?;
+    if refer < 0 || refer as u64 > count as u64 {
+        return Err(anyhow!(
+            "`{path}` refers to {refer} elements but holds at most {}",
+            entry.count
+        ));
+    }
+    Ok(refer as usize)
+}
+
+fn no_selector(path: &str) -> anyhow::Error {
+    anyhow!("`{path}` is a union without a `select` entry, so the member in use isn't known")
+}
+
+/// The member of the union in `entry` that its `select` field picks.
+fn selected<'a>(
+    host: &[u8],
+    entry: &TDRMetaEntry,
+    union: &'a TDRMeta,
+    base: usize,
+    path: &str,
+) -> Result<Option<&'a TDRMetaEntry>> {
+    if entry.selector.h_off == INVALID_METALIB_VALUE {
+        return Err(no_selector(path));
+    }
+    let selector = slice(
+        host,
+        field_offset(base, entry.selector.h_off, path)?,
+        file_size(entry.selector.unit_size, "select size", path)?,
+        path,
+    )?;
+    Ok(select_member(union, read_int::<LittleEndian>(selector)?))
+}
+
+struct Packer<'a> {
+    metalib: &'a Metalib,
+    host: &'a [u8],
+    out: Vec<u8>,
+    /// Host offset and output position of each primitive written, for `sizeinfo` fields.
+    written: Vec<(usize, usize)>,
+    cut_version: Option<i32>,
+}
+
+impl Packer<'_> {
+    /// Where the primitive at host offset `host` was packed.
+    fn position_of(&self, host: usize) -> Option<usize> {
+        self.written
+            .iter()
+            .find(|(written, _)| *written == host)
+            .map(|(_, at)| *at)
+    }
+
+    /// Runs `pack`, then stores the length it wrote as `size_info` says: in a prefix
+    /// written before it, or in a field written earlier (relative to `base`).
+    fn sized(
+        &mut self,
+        size_info: &TDRSizeInfo,
+        base: usize,
+        path: &str,
+        pack: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        let length = length_of(size_info, path)?;
+        let prefix = self.out.len();
+        if let Some(Length::Prefix(size)) = length {
+            self.out.resize(prefix + size, 0);
+        }
+        let start = self.out.len();
+        pack(self)?;
+        let len = (self.out.len() - start) as u64;
+
+        let (at, size) = match length {
+            None => return Ok(()),
+            Some(Length::Prefix(size)) => (prefix, size),
+            Some(Length::Field { h_off, size }) => {
+                let at = self
+                    .position_of(field_offset(base, h_off, path)?)
+                    .ok_or_else(|| {
+                        anyhow!("`{path}` has a `sizeinfo` field that isn't packed before it")
+                    })?;
+                (at, size)
+            }
+        };
+        if size < 8 && len >> (size * 8) != 0 {
+            return Err(anyhow!(
+                "`{path}` is {len} bytes, too long for its {size} byte `sizeinfo`"
+            ));
+        }
+        let out = self.out.get_mut(at..at + size).ok_or_else(|| {
+            anyhow!("`{path}` has a {size} byte `sizeinfo` field that was packed smaller")
+        })?;
+        BigEndian::write_uint(out, len, size);
+        Ok(())
+    }
+
+    /// Packs the entries of `meta`, whose host layout starts at `base`. `prefix` is the
+    /// path of the meta followed by a dot, or empty for the root.
+    fn pack_meta(&mut self, meta: &TDRMeta, base: usize, prefix: &str) -> Result<()> {
+        check_depth(meta, prefix)?;
+        let path = prefix.strip_suffix('.').unwrap_or(&meta.name);
+        if meta.type_ == MetaPrimativeType::UNION {
+            return Err(no_selector(path));
+        }
+        self.sized(&meta.size_type, base, path, |packer| {
+            for entry in meta.entries.iter() {
+                if !is_packed(entry, packer.cut_version) {
+                    continue;
+                }
+                let path = format!("{prefix}{}", entry.name);
+                packer.sized(&entry.size_info, base, &path, |packer| {
+                    packer.pack_entry(entry, base, &path)
+                })?;
+            }
+
+            // Tell the reader which version the entries were cut at.
+            let indicator = &meta.version_indicator;
+            if indicator.h_off != INVALID_METALIB_VALUE {
+                let version = packer.cut_version.unwrap_or(meta.cur_version);
+                let h_off = field_offset(base, indicator.h_off, path)?;
+                if let Some(at) = packer.position_of(h_off) {
+                    let size = int_size(indicator.unit_size, "versionindicator", path)?;
+                    let out = packer.out.get_mut(at..at + size).ok_or_else(|| {
+                        anyhow!("`{path}` has a {size} byte `versionindicator` packed smaller")
+                    })?;
+                    BigEndian::write_int(out, version as i64, size);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn pack_entry(&mut self, entry: &TDRMetaEntry, base: usize, path: &str) -> Result<()> {
+        let start = field_offset(base, entry.h_off, path)?;
+        if is_unsized_string(entry) {
+            let size = file_size(entry.h_real_size, "size", path)?;
+            return self.pack_primitive(entry, start, size, path);
+        }
+        let unit = file_size(entry.h_unit_size, "element size", path)?;
+        let count = refer_count(self.host, entry, base, unit, path)?;
+        for i in 0..count {
+            let path = match entry.count {
+                1 => path.to_string(),
+                _ => format!("{path}[{i}]"),
+            };
+            let start = element_offset(start, i, unit, &path)?;
+            self.pack_element(entry, base, start, &path)?;
+        }
+        Ok(())
+    }
+
+    fn pack_element(
+        &mut self,
+        entry: &TDRMetaEntry,
+        base: usize,
+        start: usize,
+        path: &str,
+    ) -> Result<()> {
+        let Some(nested) = nested_meta(self.metalib, entry, path)? else {
+            let size = file_size(entry.h_unit_size, "element size", path)?;
+            return self.pack_primitive(entry, start, size, path);
+        };
+        if nested.type_ != MetaPrimativeType::UNION {
+            return self.pack_meta(nested, start, &format!("{path}."));
+        }
+        let Some(member) = selected(self.host, entry, nested, base, path)? else {
+            return Ok(());
+        };
+        if !is_packed(member, self.cut_version) {
+            return Ok(());
+        }
+        let path = format!("{path}.{}", member.name);
+        self.sized(&member.size_info, start, &path, |packer| {
+            packer.pack_entry(member, start, &path)
+        })
+    }
+
+    fn pack_primitive(
+        &mut self,
+        entry: &TDRMetaEntry,
+        start: usize,
+        size: usize,
+        path: &str,
+    ) -> Result<()> {
+        let mut src = slice(self.host, start, size, path)?;
+        // Only the used part of a string with a length is sent.
+        if is_string(entry) && entry.size_info.unit_size > 0 {
+            src = &src[..string_len(entry.type_, src)];
+        }
+        self.written.push((start, self.out.len()));
+        let at = self.out.len();
+        self.out.resize(at + src.len(), 0);
+        swap_primitive(entry.type_, src, &mut self.out[at..]);
+        Ok(())
+    }
+}
+
+struct Unpacker<'a> {
+    metalib: &'a Metalib,
+    net: &'a [u8],
+    pos: usize,
+    host: &'a mut [u8],
+    cut_version: Option<i32>,
+}
+
+impl Unpacker<'_> {
+    /// The next `len` bytes of the network buffer.
+    fn take(&mut self, len: usize, path: &str) -> Result<&[u8]> {
+        let bytes = slice(self.net, self.pos, len, path)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Runs `unpack` on just the bytes `size_info` gives the length of, if it has one, and
+    /// skips any it leaves (fields from a newer version).
+    fn sized(
+        &mut self,
+        size_info: &TDRSizeInfo,
+        base: usize,
+        path: &str,
+        unpack: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        let len = match length_of(size_info, path)? {
+            None => return unpack(self),
+            Some(Length::Prefix(size)) => read_int::<BigEndian>(self.take(size, path)?)?,
+            Some(Length::Field { h_off, size }) => read_int::<LittleEndian>(slice(
+                self.host,
+                field_offset(base, h_off, path)?,
+                size,
+                path,
+            )?)?,
+        };
+        let len = usize::try_from(len).map_err(|_| anyhow!("`{path}` has a length of {len}"))?;
+        slice(self.net, self.pos, len, path)?;
+        let end = self.pos + len;
+
+        let net = self.net;
+        self.net = &net[..end];
+        let result = unpack(self);
+        self.net = net;
+        result?;
+        self.pos = end;
+        Ok(())
+    }
+
+    /// Unpacks the entries of `meta`, whose host layout starts at `base`. `prefix` is the
+    /// path of the meta followed by a dot, or empty for the root.
+    fn unpack_meta(&mut self, meta: &TDRMeta, base: usize, prefix: &str) -> Result<()> {
+        check_depth(meta, prefix)?;
+        let path = prefix.strip_suffix('.').unwrap_or(&meta.name);
+        if meta.type_ == MetaPrimativeType::UNION {
+            return Err(no_selector(path));
+        }
+        let unpack_entries = |unpacker: &mut Self| {
+            let outer_cut = unpacker.cut_version;
+            let mut indicator = Some(&meta.version_indicator)
+                .filter(|indicator| indicator.h_off != INVALID_METALIB_VALUE);
+            for entry in meta.entries.iter() {
+                if !is_packed(entry, unpacker.cut_version) {
+                    continue;
+                }
+                let path = format!("{prefix}{}", entry.name);
+                unpacker.sized(&entry.size_info, base, &path, |unpacker| {
+                    unpacker.unpack_entry(entry, base, &path)
+                })?;
+
+                // The rest of the meta is cut at the version the indicator gives, once
+                // it's been unpacked.
+                let Some(version_indicator) = indicator else {
+                    continue;
+                };
+                let h_off = entry.h_off as i64;
+                let end = h_off + entry.h_real_size as i64;
+                if (h_off..end).contains(&(version_indicator.h_off as i64)) {
+                    let version = read_int::<LittleEndian>(slice(
+                        unpacker.host,
+                        field_offset(base, version_indicator.h_off, &path)?,
+                        int_size(version_indicator.unit_size, "versionindicator", &path)?,
+                        &path,
+                    )?)?;
+                    unpacker.cut_version = indicated_cut(meta, unpacker.cut_version, version)?;
+                    indicator = None;
+                }
+            }
+            unpacker.cut_version = outer_cut;
+            Ok(())
+        };
+        // A `sizeinfo` field inside the meta can't be read before the meta is.
+        match length_of(&meta.size_type, path)? {
+            Some(Length::Prefix(_)) => self.sized(&meta.size_type, base, path, unpack_entries),
+            _ => unpack_entries(self),
+        }
+    }
+
+    fn unpack_entry(&mut self, entry: &TDRMetaEntry, base: usize, path: &str) -> Result<()> {
+        let start = field_offset(base, entry.h_off, path)?;
+        if is_unsized_string(entry) {
+            let size = file_size(entry.h_real_size, "size", path)?;
+            return self.unpack_primitive(entry, start, size, path);
+        }
+        let unit = file_size(entry.h_unit_size, "element size", path)?;
+        let count = refer_count(self.host, entry, base, unit, path)?;
+        for i in 0..count {
+            let path = match entry.count {
+                1 => path.to_string(),
+                _ => format!("{path}[{i}]"),
+            };
+            let start = element_offset(start, i, unit, &path)?;
+            self.unpack_element(entry, base, start, &path)?;
+        }
+        Ok(())
+    }
+
+    fn unpack_element(
+        &mut self,
+        entry: &TDRMetaEntry,
+        base: usize,
+        start: usize,
+        path: &str,
+    ) -> Result<()> {
+        let Some(nested) = nested_meta(self.metalib, entry, path)? else {
+            let size = file_size(entry.h_unit_size, "element size", path)?;
+            return self.unpack_primitive(entry, start, size, path);
+        };
+        if nested.type_ != MetaPrimativeType::UNION {
+            return self.unpack_meta(nested, start, &format!("{path}."));
+        }
+        let Some(member) = selected(self.host, entry, nested, base, path)? else {
+            return Ok(());
+        };
+        if !is_packed(member, self.cut_version) {
+            return Ok(());
+        }
+        let path = format!("{path}.{}", member.name);
+        self.sized(&member.size_info, start, &path, |unpacker| {
+            unpacker.unpack_entry(member, start, &path)
+        })
+    }
+
+    fn unpack_primitive(
+        &mut self,
+        entry: &TDRMetaEntry,
+        start: usize,
+        size: usize,
+        path: &str,
+    ) -> Result<()> {
+        // A string with a length takes the rest of the bytes `sized` gave it.
+        let len = match is_string(entry) && entry.size_info.unit_size > 0 {
+            true => self.net.len() - self.pos,
+            false => size,
+        };
+        if len > size {
+            return Err(anyhow!("`{path}` is {len} bytes but only {size} fit"));
+        }
+        let src = slice(self.net, self.pos, len, path)?;
+        let host_len = self.host.len();
+        let dst = start
+            .checked_add(len)
+            .and_then(|end| self.host.get_mut(start..end))
+            .ok_or_else(|| anyhow!("`{path}` runs past the end of the {host_len} byte struct"))?;
+        swap_primitive(entry.type_, src, dst);
+        self.pos += len;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{EntryBuilder, MetaBuilder, MetalibBuilder};
+    use crate::codec::{decode_host, decode_net, encode_host, Value};
+
+    /// `Record` is aligned to 4, so its host layout has padding that the net one doesn't.
+    fn metalib() -> Metalib {
+        MetalibBuilder::new("convert")
+            .macro_("MAX_POINTS", 4)
+            .meta(
+                MetaBuilder::struct_("Point")
+                    .entry(EntryBuilder::new("x", "int16"))
+                    .entry(EntryBuilder::new("y", "int16")),
+            )
+            .meta(
+                MetaBuilder::union("Shape")
+                    .entry(EntryBuilder::new("radius", "float").id(1))
+                    .entry(EntryBuilder::new("side", "uint16").id(2))
+                    .entry(EntryBuilder::new("label", "string").size(6).id(3)),
+            )
+            .meta(
+                MetaBuilder::struct_("Record")
+                    .align(4)
+                    .entry(EntryBuilder::new("a", "int8"))
+                    .entry(EntryBuilder::new("b", "int32"))
+                    .entry(EntryBuilder::new("c", "uint64"))
+                    .entry(EntryBuilder::new("d", "double"))
+                    .entry(EntryBuilder::new("name", "string").size(10))
+                    .entry(EntryBuilder::new("pos", "Point"))
+                    .entry(EntryBuilder::new("num", "uint8"))
+                    .entry(
+                        EntryBuilder::new("points", "Point")
+                            .count("MAX_POINTS")
+                            .refer("num"),
+                    )
+                    .entry(EntryBuilder::new("kind", "int16"))
+                    .entry(EntryBuilder::new("shape", "Shape").select("kind"))
+                    .entry(EntryBuilder::new("addr", "ip"))
+                    .entry(EntryBuilder::new("day", "date")),
+            )
+            .build()
+            .unwrap()
+    }
+
+    /// `Msg` has a `versionindicator` and a string with a `sizeinfo` field.
+    fn sized_metalib() -> Metalib {
+        MetalibBuilder::new("sized")
+            .meta(
+                MetaBuilder::struct_("Msg")
+                    .version(1)
+                    .version_indicator("ver")
+                    .entry(EntryBuilder::new("ver", "uint16"))
+                    .entry(EntryBuilder::new("len", "uint16"))
+                    .entry(EntryBuilder::new("text", "string").size(8).sizeinfo("len")),
+            )
+            .build()
+            .unwrap()
+    }
+
+    /// xorshift64, so the test needs no dependencies and always sees the same values.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        fn text(&mut self, max_len: u64) -> Value {
+            let len = self.below(max_len + 1);
+            let text = (0..len)
+                .map(|_| (b'a' + self.below(26) as u8) as char)
+                .collect();
+            Value::String(text)
+        }
+
+        fn point(&mut self) -> Value {
+            Value::Struct(vec![
+                ("x".to_string(), Value::Int(self.next() as i16 as i64)),
+                ("y".to_string(), Value::Int(self.next() as i16 as i64)),
+            ])
+        }
+
+        fn record(&mut self) -> Value {
+            let num = self.below(5);
+            let kind = 1 + self.below(4) as i64;
+            let shape = match kind {
+                1 => vec![(
+                    "radius".to_string(),
+                    Value::Float(self.below(1000) as f64 / 8.0),
+                )],
+                2 => vec![("side".to_string(), Value::UInt(self.next() as u16 as u64))],
+                3 => vec![("label".to_string(), self.text(5))],
+                // No member has this id.
+                _ => vec![],
+            };
+            let day = format!(
+                "{}-{:02}-{:02}",
+                1970 + self.below(100),
+                1 + self.below(12),
+                1 + self.below(28)
+            );
+            let fields = vec![
+                ("a", Value::Int(self.next() as i8 as i64)),
+                ("b", Value::Int(self.next() as i32 as i64)),
+                ("c", Value::UInt(self.next())),
+                ("d", Value::Float(self.next() as i32 as f64 / 3.0)),
+                ("name", self.text(9)),
+                ("pos", self.point()),
+                ("num", Value::UInt(num)),
+                (
+                    "points",
+                    Value::Array((0..num).map(|_| self.point()).collect()),
+                ),
+                ("kind", Value::Int(kind)),
+                ("shape", Value::Struct(shape)),
+                (
+                    "addr",
+                    Value::String(std::net::Ipv4Addr::from(self.next() as u32).to_string()),
+                ),
+                ("day", Value::String(day)),
+            ];
+            let fields = fields
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect();
+            Value::Struct(fields)
+        }
+    }
+
+    #[test]
+    fn ntoh_reverses_hton() {
+        let metalib = metalib();
+        let record = metalib.get_meta_by_name("Record").unwrap();
+        let size = record.h_unit_size as usize;
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        for _ in 0..500 {
+            let value = rng.record();
+            let host = encode_host(&metalib, record, &value, None).unwrap();
+
+            let mut net = vec![0; record.n_unit_size as usize];
+            let net_len = hton(&metalib, record, &host, &mut net, None).unwrap();
+            assert!(net_len <= net.len());
+            let net = &net[..net_len];
+            assert_eq!(decode_net(&metalib, record, net, None).unwrap(), value);
+
+            let mut back = vec![0xCC; size + 4];
+            let host_len = ntoh(&metalib, record, net, &mut back, None).unwrap();
+            assert_eq!(host_len, size);
+            assert_eq!(back[..size], host[..], "{value:?}");
+            assert_eq!(decode_host(&metalib, record, &back, None).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn net_layout_is_packed_big_endian() {
+        let metalib = metalib();
+        let point = metalib.get_meta_by_name("Point").unwrap();
+        let host = [0x01, 0x02, 0x03, 0x04];
+        let mut net = [0; 4];
+        assert_eq!(hton(&metalib, point, &host, &mut net, None).unwrap(), 4);
+        assert_eq!(net, [0x02, 0x01, 0x04, 0x03]);
+
+        let record = metalib.get_meta_by_name("Record").unwrap();
+        let value = Rng(1).record();
+        let host = encode_host(&metalib, record, &value, None).unwrap();
+        let mut small = [0; 8];
+        let err = hton(&metalib, record, &host, &mut small, None).unwrap_err();
+        assert!(err.to_string().starts_with("Packing needs "), "{err}");
+        let err = ntoh(&metalib, record, &net, &mut small, None).unwrap_err();
+        assert!(err.to_string().starts_with("`Record` needs "), "{err}");
+    }
+
+    /// The `hton` and `ntoh` errors for `meta_name` once `change` corrupts it, converting
+    /// the buffers `value` packs to in the uncorrupted metalib.
+    fn corrupt(
+        metalib: fn() -> Metalib,
+        meta_name: &str,
+        value: &Value,
+        change: impl FnOnce(&mut TDRMeta),
+    ) -> (Option<String>, Option<String>) {
+        let good = metalib();
+        let meta = good.get_meta_by_name(meta_name).unwrap();
+        let host = encode_host(&good, meta, value, None).unwrap();
+        let mut net = vec![0; meta.n_unit_size as usize];
+        let net_len = hton(&good, meta, &host, &mut net, None).unwrap();
+
+        let mut metalib = metalib();
+        change(
+            metalib
+                .metas
+                .iter_mut()
+                .find(|m| m.name == meta_name)
+                .unwrap(),
+        );
+        let meta = metalib.get_meta_by_name(meta_name).unwrap();
+        let mut out = vec![0; 1024];
+        let packed = hton(&metalib, meta, &host, &mut out, None).err();
+        let unpacked = ntoh(&metalib, meta, &net[..net_len], &mut out, None).err();
+        let error = |err: anyhow::Error| format!("{err:#}");
+        (packed.map(error), unpacked.map(error))
+    }
+
+    #[test]
+    fn corrupt_offsets_and_counts_are_errors() {
+        let value = Rng(7).record();
+        let entry = |name: &'static str, change: fn(&mut TDRMetaEntry)| {
+            corrupt(metalib, "Record", &value, |meta| {
+                change(meta.entries.iter_mut().find(|e| e.name == name).unwrap())
+            })
+        };
+        let both = |error: &str| (Some(error.to_string()), Some(error.to_string()));
+
+        assert_eq!(
+            entry("b", |entry| entry.h_off = -4),
+            both("`b` is at offset -4 from 0, which is out of range")
+        );
+        assert_eq!(
+            entry("points", |entry| entry.count = -1),
+            both("`points` has a negative count of -1")
+        );
+        assert_eq!(
+            entry("points", |entry| entry.h_unit_size = 0),
+            both("`points` has 4 elements of 0 bytes")
+        );
+        assert_eq!(
+            entry("points", |entry| entry.referer.h_off = i32::MIN),
+            both("`points` is at offset -2147483648 from 0, which is out of range")
+        );
+        assert_eq!(
+            entry("shape", |entry| entry.selector.unit_size = -2),
+            both("`shape` has a negative select size of -2")
+        );
+        // Only `ntoh` writes a whole struct.
+        assert_eq!(
+            corrupt(metalib, "Record", &value, |meta| meta.h_unit_size = -1),
+            (None, Some("`Record` has a negative size of -1".to_string()))
+        );
+    }
+
+    #[test]
+    fn corrupt_unit_sizes_are_errors() {
+        let value = Value::Struct(vec![
+            ("ver".to_string(), Value::UInt(1)),
+            ("len".to_string(), Value::UInt(0)),
+            ("text".to_string(), Value::String("hi".to_string())),
+        ]);
+        let text_size = |change: fn(&mut TDRSizeInfo)| {
+            corrupt(sized_metalib, "Msg", &value, |meta| {
+                change(&mut meta.entries[2].size_info)
+            })
+        };
+        let both = |error: &str| (Some(error.to_string()), Some(error.to_string()));
+
+        for size in [0, 3, 16] {
+            let errors = corrupt(sized_metalib, "Msg", &value, |meta| {
+                meta.version_indicator.unit_size = size
+            });
+            let error = |path| {
+                format!(
+                    "`{path}` has a {size} byte `versionindicator`, but it must be 1, 2, 4 or 8"
+                )
+            };
+            // `ntoh` finds it reading the entry that holds it.
+            assert_eq!(errors, (Some(error("Msg")), Some(error("ver"))));
+        }
+        assert_eq!(
+            text_size(|size_info| size_info.unit_size = 3),
+            both("`text` has a 3 byte `sizeinfo`, but it must be 1, 2, 4 or 8")
+        );
+        // A prefix rather than a field.
+        assert_eq!(
+            text_size(|size_info| {
+                size_info.idx_size_type = 0;
+                size_info.unit_size = 1000;
+            }),
+            both("`text` has a 1000 byte `sizeinfo`, but it must be 1, 2, 4 or 8")
+        );
+        assert_eq!(
+            text_size(|size_info| size_info.h_off = -2),
+            both("`text` is at offset -2 from 0, which is out of range")
+        );
+    }
+}
@@ -0,0 +1,507 @@
+//! The host (in-memory) layout, where each entry sits at its `h_off`.
+
+use anyhow::{anyhow, Context, Result};
+use byteorder::LittleEndian;
+
+use super::{
+    check_depth, describe, element_offset, entry_count, field_offset, file_size, in_version,
+    is_pointer, is_unsized_string, read_int, read_primitive, select_member, slice, write_primitive,
+    Value,
+};
+use crate::metalib::{
+    encode_default_value, MetaPrimativeType, Metalib, TDRMeta, TDRMetaEntry, INVALID_METALIB_VALUE,
+};
+
+/// Decodes `data`, laid out in memory as `meta` (little endian, 32-bit), into a
+/// [`Value::Struct`].
+///
+/// Arrays with a `refer` entry only decode the elements in use, and unions with a `select`
+/// entry only the member it picks. Entries newer than `cut_version` are left out. Reading
+/// past the end of `data` fails with the path of the field being read.
+pub fn decode_host(
+    metalib: &Metalib,
+    meta: &TDRMeta,
+    data: &[u8],
+    cut_version: Option<i32>,
+) -> Result<Value> {
+    let decoder = Decoder {
+        metalib,
+        data,
+        cut_version,
+    };
+    decoder.decode_meta(meta, 0, "")
+}
+
+struct Decoder<'a> {
+    metalib: &'a Metalib,
+    data: &'a [u8],
+    cut_version: Option<i32>,
+}
+
+impl Decoder<'_> {
+    fn decode_meta(&self, meta: &TDRMeta, base: usize, prefix: &str) -> Result<Value> {
+        check_depth(meta, prefix)?;
+        let mut fields = Vec::new();
+        for entry in meta.entries.iter() {
+            if !in_version(entry, self.cut_version) {
+                continue;
+            }
+            let path = format!("{prefix}{}", entry.name);
+            let value = self.decode_entry(entry, base, &path)?;
+            fields.push((entry.name.clone(), value));
+        }
+        Ok(Value::Struct(fields))
+    }
+
+    /// Decodes every element of `entry`, in the meta starting at `base`.
+    fn decode_entry(&self, entry: &TDRMetaEntry, base: usize, path: &str) -> Result<Value> {
+        // Without a `size`, a string's `count` is its length rather than a number of strings.
+        if is_unsized_string(entry) {
+            let bytes = slice(
+                self.data,
+                field_offset(base, entry.h_off, path)?,
+                file_size(entry.h_real_size, "size", path)?,
+                path,
+            )?;
+            return read_primitive::<LittleEndian>(entry.type_, bytes);
+        }
+
+        let start = field_offset(base, entry.h_off, path)?;
+        let unit = file_size(entry.h_unit_size, "element size", path)?;
+        let max_count = entry_count(entry, unit, path)?;
+        let count = match entry.referer.h_off {
+            INVALID_METALIB_VALUE => max_count,
+            h_off => {
+                let refer = slice(
+                    self.data,
+                    field_offset(base, h_off, path)?,
+                    file_size(entry.referer.unit_size, "refer size", path)?,
+                    path,
+                )?;
+                let refer = read_int::<LittleEndian>(refer)?;
+                if refer < 0 || refer as u64 > max_count as u64 {
+                    return Err(anyhow!(
+                        "`{path}` refers to {refer} elements but holds at most {}",
+                        entry.count
+                    ));
+                }
+                refer as usize
+            }
+        };
+
+        if entry.count == 1 && entry.referer.h_off == INVALID_METALIB_VALUE {
+            return self.decode_element(entry, base, start, path);
+        }
+        let mut elements = Vec::new();
+        for i in 0..count {
+            let path = format!("{path}[{i}]");
+            let start = element_offset(start, i, unit, &path)?;
+            elements.push(self.decode_element(entry, base, start, &path)?);
+        }
+        Ok(Value::Array(elements))
+    }
+
+    /// Decodes one element of `entry` starting at `start`. `base` is where the entry's meta
+    /// starts, for finding its `select` entry.
+    fn decode_element(
+        &self,
+        entry: &TDRMetaEntry,
+        base: usize,
+        start: usize,
+        path: &str,
+    ) -> Result<Value> {
+        let size = file_size(entry.h_unit_size, "element size", path)?;
+        let bytes = slice(self.data, start, size, path)?;
+        if is_pointer(entry) {
+            return read_int::<LittleEndian>(bytes)
+                .map(|address| Value::UInt(address as u32 as u64));
+        }
+
+        let nested = match entry.ptr_meta {
+            INVALID_METALIB_VALUE => None,
+            ptr_meta => Some(
+                self.metalib
+                    .get_meta_by_offset(ptr_meta)
+                    .with_context(|| format!("`{path}` has no meta"))?,
+            ),
+        };
+        let Some(nested) = nested else {
+            return read_primitive::<LittleEndian>(entry.type_, bytes)
+                .with_context(|| format!("Failed to decode `{path}`"));
+        };
+        if nested.type_ != MetaPrimativeType::UNION || entry.selector.h_off == INVALID_METALIB_VALUE
+        {
+            return self.decode_meta(nested, start, &format!("{path}."));
+        }
+
+        let selector = slice(
+            self.data,
+            field_offset(base, entry.selector.h_off, path)?,
+            file_size(entry.selector.unit_size, "select size", path)?,
+            path,
+        )?;
+        let selector = read_int::<LittleEndian>(selector)?;
+        let member = match select_member(nested, selector) {
+            Some(member) if in_version(member, self.cut_version) => member,
+            _ => return Ok(Value::Struct(Vec::new())),
+        };
+        let member_path = format!("{path}.{}", member.name);
+        let value = self.decode_entry(member, start, &member_path)?;
+        Ok(Value::Struct(vec![(member.name.clone(), value)]))
+    }
+}
+
+/// Lays `value`, a [`Value::Struct`] of `meta`'s entries, out in memory: the reverse of
+/// [`decode_host`]. The buffer is `meta.h_unit_size` bytes with zeroed padding.
+///
+/// Entries (and array elements) left out of `value` get their `default`, or zeros.
+/// Entries newer than `cut_version` are left zeroed, and giving them is an error, as are
+/// strings that don't fit their buffer with a NUL, out of range integers and names that
+/// aren't entries of the meta. `refer` and `select` fields are written as given, not
+/// worked out from the arrays and union members present.
+pub fn encode_host(
+    metalib: &Metalib,
+    meta: &TDRMeta,
+    value: &Value,
+    cut_version: Option<i32>,
+) -> Result<Vec<u8>> {
+    let mut data = vec![0; file_size(meta.h_unit_size, "size", &meta.name)?];
+    let encoder = Encoder {
+        metalib,
+        cut_version,
+    };
+    encoder.encode_meta(meta, Some(value), &mut data, 0, "")?;
+    Ok(data)
+}
+
+struct Encoder<'a> {
+    metalib: &'a Metalib,
+    cut_version: Option<i32>,
+}
+
+impl Encoder<'_> {
+    fn encode_meta(
+        &self,
+        meta: &TDRMeta,
+        value: Option<&Value>,
+        data: &mut [u8],
+        base: usize,
+        prefix: &str,
+    ) -> Result<()> {
+        check_depth(meta, prefix)?;
+        let fields = match value {
+            Some(Value::Struct(fields)) => fields.as_slice(),
+            Some(value) => {
+                let path = prefix.strip_suffix('.').unwrap_or(&meta.name);
+                return Err(anyhow!(
+                    "`{path}` should be a struct, not {}",
+                    describe(value)
+                ));
+            }
+            None => &[],
+        };
+        if let Some((name, _)) = fields
+            .iter()
+            .find(|(name, _)| !meta.entries.iter().any(|entry| &entry.name == name))
+        {
+            return Err(anyhow!("`{}` has no entry `{name}`", meta.name));
+        }
+
+        for entry in meta.entries.iter() {
+            let value = fields
+                .iter()
+                .find(|(name, _)| *name == entry.name)
+                .map(|(_, value)| value);
+            let path = format!("{prefix}{}", entry.name);
+            if !in_version(entry, self.cut_version) {
+                if value.is_some() {
+                    return Err(anyhow!(
+                        "`{path}` was added in version {}, after the cut",
+                        entry.version
+                    ));
+                }
+                continue;
+            }
+            // A union's members overlap, so only the ones given are written.
+            if meta.type_ == MetaPrimativeType::UNION && value.is_none() {
+                continue;
+            }
+            self.encode_entry(entry, value, data, base, &path)?;
+        }
+        Ok(())
+    }
+
+    fn encode_entry(
+        &self,
+        entry: &TDRMetaEntry,
+        value: Option<&Value>,
+        data: &mut [u8],
+        base: usize,
+        path: &str,
+    ) -> Result<()> {
+        let start = field_offset(base, entry.h_off, path)?;
+        if is_unsized_string(entry) {
+            let size = file_size(entry.h_real_size, "size", path)?;
+            return self.encode_element(entry, value, data, start, size, path);
+        }
+        let unit = file_size(entry.h_unit_size, "element size", path)?;
+        if entry.count == 1 && entry.referer.h_off == INVALID_METALIB_VALUE {
+            return self.encode_element(entry, value, data, start, unit, path);
+        }
+        let count = entry_count(entry, unit, path)?;
+
+        let elements = match value {
+            Some(Value::Array(elements)) => elements.as_slice(),
+            Some(value) => {
+                return Err(anyhow!(
+                    "`{path}` should be an array, not {}",
+                    describe(value)
+                ));
+            }
+            None => &[],
+        };
+        if elements.len() > count {
+            return Err(anyhow!(
+                "`{path}` has {} elements but holds at most {}",
+                elements.len(),
+                entry.count
+            ));
+        }
+        for i in 0..count {
+            let path = format!("{path}[{i}]");
+            let start = element_offset(start, i, unit, &path)?;
+            self.encode_element(entry, elements.get(i), data, start, unit, &path)?;
+        }
+        Ok(())
+    }
+
+    /// Writes one element of `entry`, `size` bytes at `start`.
+    fn encode_element(
+        &self,
+        entry: &TDRMetaEntry,
+        value: Option<&Value>,
+        data: &mut [u8],
+        start: usize,
+        size: usize,
+        path: &str,
+    ) -> Result<()> {
+        let len = data.len();
+        let out = start
+            .checked_add(size)
+            .and_then(|end| data.get_mut(start..end))
+            .ok_or_else(|| anyhow!("`{path}` runs past the end of the {len} byte struct"))?;
+        if is_pointer(entry) {
+            let address = match value {
+                Some(value) => value.as_i64().ok_or_else(|| {
+                    anyhow!("`{path}` should be an address, not {}", describe(value))
+                })?,
+                None => 0,
+            };
+            return write_primitive::<LittleEndian>(
+                MetaPrimativeType::UINT,
+                &Value::Int(address),
+                out,
+            )
+            .with_context(|| format!("Failed to encode `{path}`"));
+        }
+
+        if entry.ptr_meta != INVALID_METALIB_VALUE {
+            let nested = self
+                .metalib
+                .get_meta_by_offset(entry.ptr_meta)
+                .with_context(|| format!("`{path}` has no meta"))?;
+            return self.encode_meta(nested, value, data, start, &format!("{path}."));
+        }
+
+        match value {
+            Some(value) => write_primitive::<LittleEndian>(entry.type_, value, out),
+            None if entry.default_value_string.is_empty() => Ok(()),
+            None => encode_default_value(entry.idx_type, &entry.default_value_string).and_then(
+                |default| {
+                    out.get_mut(..default.len())
+                        .ok_or_else(|| anyhow!("The default doesn't fit in {size} bytes"))?
+                        .copy_from_slice(&default);
+                    Ok(())
+                },
+            ),
+        }
+        .with_context(|| format!("Failed to encode `{path}`"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{EntryBuilder, MetaBuilder, MetalibBuilder};
+
+    /// `Player` is packed: id 0..4, name 4..12, pos 12..16, num 16, tags 17..23, then a
+    /// `Shape` union selected by `kind` at 23 and the union at 24..28.
+    fn metalib() -> Metalib {
+        MetalibBuilder::new("decode")
+            .meta(
+                MetaBuilder::struct_("Point")
+                    .entry(EntryBuilder::new("x", "int16"))
+                    .entry(EntryBuilder::new("y", "int16")),
+            )
+            .meta(
+                MetaBuilder::union("Shape")
+                    .entry(EntryBuilder::new("radius", "float").id(1))
+                    .entry(EntryBuilder::new("side", "uint16").id(2)),
+            )
+            .meta(
+                MetaBuilder::struct_("Player")
+                    .entry(EntryBuilder::new("id", "int"))
+                    .entry(EntryBuilder::new("name", "string").size(8))
+                    .entry(EntryBuilder::new("pos", "Point"))
+                    .entry(EntryBuilder::new("num", "uint8"))
+                    .entry(EntryBuilder::new("tags", "uint16").count(3).refer("num"))
+                    .entry(EntryBuilder::new("kind", "uint8"))
+                    .entry(EntryBuilder::new("shape", "Shape").select("kind")),
+            )
+            .build()
+            .unwrap()
+    }
+
+    fn player_bytes() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend((-7i32).to_le_bytes());
+        data.extend(b"bob\0junk");
+        data.extend(3i16.to_le_bytes());
+        data.extend((-4i16).to_le_bytes());
+        data.push(2);
+        data.extend([10, 0, 20, 0, 0xFF, 0xFF]);
+        data.push(2);
+        data.extend([5, 0, 0xAA, 0xAA]);
+        data
+    }
+
+    #[test]
+    fn decode_built_meta() {
+        let metalib = metalib();
+        let player = metalib.get_meta_by_name("Player").unwrap();
+        assert_eq!(player.h_unit_size, 28);
+
+        let value = decode_host(&metalib, player, &player_bytes(), None).unwrap();
+        let field = |name: &str, value| (name.to_string(), value);
+        assert_eq!(
+            value,
+            Value::Struct(vec![
+                field("id", Value::Int(-7)),
+                // Stops at the NUL, not the end of the buffer.
+                field("name", Value::String("bob".to_string())),
+                field(
+                    "pos",
+                    Value::Struct(vec![field("x", Value::Int(3)), field("y", Value::Int(-4))])
+                ),
+                field("num", Value::UInt(2)),
+                // Only the `num` elements in use.
+                field("tags", Value::Array(vec![Value::UInt(10), Value::UInt(20)])),
+                field("kind", Value::UInt(2)),
+                field("shape", Value::Struct(vec![field("side", Value::UInt(5))])),
+            ])
+        );
+        assert_eq!(value.get("pos.y"), Some(&Value::Int(-4)));
+    }
+
+    #[test]
+    fn decode_errors_name_the_field() {
+        let metalib = metalib();
+        let player = metalib.get_meta_by_name("Player").unwrap();
+        let decode = |data: &[u8]| {
+            decode_host(&metalib, player, data, None)
+                .unwrap_err()
+                .to_string()
+        };
+
+        let data = player_bytes();
+        assert_eq!(
+            decode(&data[..14]),
+            "`pos` needs bytes 12..16 but the buffer is 14 bytes"
+        );
+        assert_eq!(
+            decode(&data[..20]),
+            "`tags[1]` needs bytes 19..21 but the buffer is 20 bytes"
+        );
+
+        let mut data = player_bytes();
+        data[16] = 4;
+        assert_eq!(
+            decode(&data),
+            "`tags` refers to 4 elements but holds at most 3"
+        );
+    }
+
+    #[test]
+    fn corrupt_layouts_are_errors() {
+        let value = decode_host(
+            &metalib(),
+            metalib().get_meta_by_name("Player").unwrap(),
+            &player_bytes(),
+            None,
+        )
+        .unwrap();
+        let corrupt = |name: &str, change: fn(&mut TDRMetaEntry)| {
+            let mut metalib = metalib();
+            let player = metalib
+                .metas
+                .iter_mut()
+                .find(|m| m.name == "Player")
+                .unwrap();
+            change(player.entries.iter_mut().find(|e| e.name == name).unwrap());
+            let player = metalib.get_meta_by_name("Player").unwrap();
+            let decoded = decode_host(&metalib, player, &player_bytes(), None).unwrap_err();
+            let encoded = encode_host(&metalib, player, &value, None).err();
+            (
+                format!("{decoded:#}"),
+                encoded.map(|err| format!("{err:#}")),
+            )
+        };
+        let both = |error: &str| (error.to_string(), Some(error.to_string()));
+
+        assert_eq!(
+            corrupt("id", |entry| entry.h_off = -4),
+            both("`id` is at offset -4 from 0, which is out of range")
+        );
+        // An `int` in two bytes.
+        assert_eq!(
+            corrupt("id", |entry| entry.h_unit_size = 2),
+            (
+                "Failed to decode `id`: INT needs 4 bytes but only has 2".to_string(),
+                Some("Failed to encode `id`: INT needs 4 bytes but only has 2".to_string())
+            )
+        );
+        assert_eq!(
+            corrupt("tags", |entry| entry.count = -1),
+            both("`tags` has a negative count of -1")
+        );
+        assert_eq!(
+            corrupt("tags", |entry| entry.h_unit_size = 0),
+            both("`tags` has 3 elements of 0 bytes")
+        );
+        assert_eq!(
+            corrupt("tags", |entry| entry.h_unit_size = -2),
+            both("`tags` has a negative element size of -2")
+        );
+        // `refer` and `select` fields are only read when decoding.
+        assert_eq!(
+            corrupt("tags", |entry| entry.referer.h_off = -8),
+            (
+                "`tags` is at offset -8 from 0, which is out of range".to_string(),
+                None
+            )
+        );
+        assert_eq!(
+            corrupt("shape", |entry| entry.selector.unit_size = 3),
+            ("Can't read a 3 byte integer".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn encode_reverses_decode() {
+        let metalib = metalib();
+        let player = metalib.get_meta_by_name("Player").unwrap();
+        let value = decode_host(&metalib, player, &player_bytes(), None).unwrap();
+        let data = encode_host(&metalib, player, &value, None).unwrap();
+        assert_eq!(decode_host(&metalib, player, &data, None).unwrap(), value);
+    }
+}
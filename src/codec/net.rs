@@ -0,0 +1,247 @@
+//! Decoding the network layout (what TDR's `unpack` reads): entries back to back in big
+//! endian, with only the parts in use present.
+//!
+//! Unlike the host layout, positions depend on the data: an array with a `refer` entry
+//! only holds that many elements, a union with a `select` entry only the member it picks,
+//! and a `sizeinfo` prefix or field gives the length of a string or struct. So the buffer
+//! is read front to back, and `refer`, `select` and `sizeinfo` fields are looked up in the
+//! values already decoded.
+
+use anyhow::{anyhow, Context, Result};
+use byteorder::BigEndian;
+
+use super::{
+    check_depth, find_field, in_version, indicated_cut, is_pointer, is_string, is_unsized_string,
+    read_int, read_primitive, select_member, slice, Value,
+};
+use crate::metalib::{
+    MetaPrimativeType, Metalib, SizeSource, TDRMeta, TDRMetaEntry, INVALID_METALIB_VALUE,
+};
+
+/// Decodes `data`, packed in the network layout of `meta`, into a [`Value::Struct`].
+///
+/// Entries added after `cut_version` aren't on the wire and are left out, as are pointers.
+/// A meta with a `versionindicator` cuts the rest of its entries at the version it gives,
+/// if that's older. Bytes after the end of a struct with a `sizeinfo` prefix (fields from
+/// a newer version) are skipped.
+pub fn decode_net(
+    metalib: &Metalib,
+    meta: &TDRMeta,
+    data: &[u8],
+    cut_version: Option<i32>,
+) -> Result<Value> {
+    let mut reader = NetReader {
+        metalib,
+        data,
+        pos: 0,
+        cut_version,
+    };
+    reader.read_meta(meta, "")
+}
+
+struct NetReader<'a> {
+    metalib: &'a Metalib,
+    data: &'a [u8],
+    pos: usize,
+    cut_version: Option<i32>,
+}
+
+impl<'a> NetReader<'a> {
+    /// The next `len` bytes.
+    fn take(&mut self, len: usize, path: &str) -> Result<&'a [u8]> {
+        let bytes = slice(self.data, self.pos, len, path)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Reads a length prefix of `size` bytes.
+    fn read_length(&mut self, size: i32, path: &str) -> Result<usize> {
+        let bytes = self.take(size as usize, path)?;
+        let length = read_int::<BigEndian>(bytes)?;
+        usize::try_from(length).map_err(|_| anyhow!("`{path}` has a length of {length}"))
+    }
+
+    /// A reader over just the next `len` bytes, for data with a `sizeinfo`.
+    fn sub_reader(&mut self, len: usize, path: &str) -> Result<NetReader<'a>> {
+        Ok(NetReader {
+            metalib: self.metalib,
+            data: self.take(len, path)?,
+            pos: 0,
+            cut_version: self.cut_version,
+        })
+    }
+
+    fn read_meta(&mut self, meta: &TDRMeta, prefix: &str) -> Result<Value> {
+        check_depth(meta, prefix)?;
+        let path = prefix.strip_suffix('.').unwrap_or(&meta.name);
+        if let Some(SizeSource::TypePrefix(info)) = meta.size_type.resolve(self.metalib, meta)? {
+            let len = self.read_length(info.size, path)?;
+            return self.sub_reader(len, path)?.read_members(meta, prefix);
+        }
+        self.read_members(meta, prefix)
+    }
+
+    fn read_members(&mut self, meta: &TDRMeta, prefix: &str) -> Result<Value> {
+        let start = self.pos;
+        let mut end = start;
+        let mut fields = Vec::new();
+        let outer_cut = self.cut_version;
+        let mut indicator = match meta.version_indicator.n_off {
+            INVALID_METALIB_VALUE => None,
+            n_off => Some(
+                self.metalib
+                    .resolve_field_path_by_net_offset(meta, n_off)?
+                    .path,
+            ),
+        };
+        for entry in meta.entries.iter() {
+            if !in_version(entry, self.cut_version) || is_pointer(entry) || entry.is_zero_size() {
+                continue;
+            }
+            // Without a selector every member of a union is read from the same bytes.
+            if meta.type_ == MetaPrimativeType::UNION {
+                self.pos = start;
+            }
+            let path = format!("{prefix}{}", entry.name);
+            let value = self.read_entry(meta, entry, &fields, &path)?;
+            fields.push((entry.name.clone(), value));
+            end = end.max(self.pos);
+
+            let version = indicator
+                .as_deref()
+                .and_then(|path| find_field(&fields, path))
+                .and_then(Value::as_i64);
+            if let Some(version) = version {
+                self.cut_version = indicated_cut(meta, self.cut_version, version)?;
+                indicator = None;
+            }
+        }
+        self.cut_version = outer_cut;
+        self.pos = end;
+        Ok(Value::Struct(fields))
+    }
+
+    /// Reads every element of `entry`. `fields` are the entries of `meta` read so far.
+    fn read_entry(
+        &mut self,
+        meta: &TDRMeta,
+        entry: &TDRMetaEntry,
+        fields: &[(String, Value)],
+        path: &str,
+    ) -> Result<Value> {
+        let len = match entry.size_info.resolve(self.metalib, meta)? {
+            Some(SizeSource::TypePrefix(info)) => Some(self.read_length(info.size, path)?),
+            Some(SizeSource::Field(field)) => {
+                let len = sibling(fields, &field.path, path)?;
+                Some(usize::try_from(len).map_err(|_| anyhow!("`{path}` has a length of {len}"))?)
+            }
+            None => None,
+        };
+        match len {
+            Some(len) => self
+                .sub_reader(len, path)?
+                .read_elements(meta, entry, fields, path, true),
+            None => self.read_elements(meta, entry, fields, path, false),
+        }
+    }
+
+    /// Reads the elements of `entry`. With `sized`, the reader holds exactly the entry.
+    fn read_elements(
+        &mut self,
+        meta: &TDRMeta,
+        entry: &TDRMetaEntry,
+        fields: &[(String, Value)],
+        path: &str,
+        sized: bool,
+    ) -> Result<Value> {
+        if is_unsized_string(entry) {
+            let len = match sized {
+                true => self.data.len() - self.pos,
+                false => entry.n_real_size as usize,
+            };
+            let bytes = self.take(len, path)?;
+            return read_primitive::<BigEndian>(entry.type_, bytes);
+        }
+
+        let count = match entry.referer.h_off {
+            INVALID_METALIB_VALUE => None,
+            h_off => {
+                let target = self
+                    .metalib
+                    .resolve_field_path_by_host_offset(meta, h_off)?;
+                let count = sibling(fields, &target.path, path)?;
+                if count < 0 || count > entry.count as i64 {
+                    return Err(anyhow!(
+                        "`{path}` refers to {count} elements but holds at most {}",
+                        entry.count
+                    ));
+                }
+                Some(count as usize)
+            }
+        };
+        let Some(count) = count.or((entry.count != 1).then_some(entry.count as usize)) else {
+            return self.read_element(meta, entry, fields, path, sized);
+        };
+
+        let mut elements = Vec::new();
+        for i in 0..count {
+            let path = format!("{path}[{i}]");
+            elements.push(self.read_element(meta, entry, fields, &path, false)?);
+        }
+        Ok(Value::Array(elements))
+    }
+
+    /// Reads one element of `entry`. With `sized`, a string takes the rest of the reader.
+    fn read_element(
+        &mut self,
+        meta: &TDRMeta,
+        entry: &TDRMetaEntry,
+        fields: &[(String, Value)],
+        path: &str,
+        sized: bool,
+    ) -> Result<Value> {
+        let nested = match entry.ptr_meta {
+            INVALID_METALIB_VALUE => None,
+            ptr_meta => Some(
+                self.metalib
+                    .get_meta_by_offset(ptr_meta)
+                    .with_context(|| format!("`{path}` has no meta"))?,
+            ),
+        };
+        let Some(nested) = nested else {
+            let len = match sized && is_string(entry) {
+                true => self.data.len() - self.pos,
+                false => entry.n_unit_size as usize,
+            };
+            let bytes = self.take(len, path)?;
+            return read_primitive::<BigEndian>(entry.type_, bytes)
+                .with_context(|| format!("Failed to decode `{path}`"));
+        };
+        if nested.type_ != MetaPrimativeType::UNION || entry.selector.h_off == INVALID_METALIB_VALUE
+        {
+            return self.read_meta(nested, &format!("{path}."));
+        }
+
+        let target = self
+            .metalib
+            .resolve_field_path_by_host_offset(meta, entry.selector.h_off)?;
+        let selector = sibling(fields, &target.path, path)?;
+        let member = match select_member(nested, selector) {
+            Some(member) if in_version(member, self.cut_version) => member,
+            _ => return Ok(Value::Struct(Vec::new())),
+        };
+        let member_path = format!("{path}.{}", member.name);
+        let value = self.read_entry(nested, member, &[], &member_path)?;
+        Ok(Value::Struct(vec![(member.name.clone(), value)]))
+    }
+}
+
+/// The integer value of the field at `target`, read earlier in the same meta, that
+/// `path` takes its count, length or selector from.
+fn sibling(fields: &[(String, Value)], target: &str, path: &str) -> Result<i64> {
+    find_field(fields, target)
+        .and_then(Value::as_i64)
+        .ok_or_else(|| {
+            anyhow!("`{path}` depends on `{target}`, which isn't an integer read before it")
+        })
+}
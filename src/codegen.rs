@@ -0,0 +1,132 @@
+use anyhow::{Context, Result};
+
+use crate::metalib::{
+    self, MetaPrimativeType, Metalib, TDRMeta, TDRMetaEntry, TDRMetaFlags, INVALID_METALIB_VALUE,
+};
+
+mod csharp;
+mod fbs;
+mod ksy;
+mod proto;
+mod python;
+mod rust;
+mod sql;
+
+pub use csharp::emit_csharp;
+pub use fbs::emit_fbs;
+pub use ksy::emit_ksy;
+pub use proto::emit_proto;
+pub use python::emit_python;
+pub use rust::emit_rust;
+pub use sql::emit_sql;
+
+/// A single output file produced by a backend that emits more than one file.
+pub struct GeneratedFile {
+    pub file_name: String,
+    pub contents: String,
+}
+
+/// Returns the name of the meta referenced by a STRUCT/UNION entry.
+fn referenced_meta_name<'a>(metalib: &'a Metalib, entry: &TDRMetaEntry) -> Result<&'a str> {
+    let meta = metalib
+        .get_meta_by_offset(entry.ptr_meta)
+        .with_context(|| format!("Failed to get meta referenced by entry `{}`", entry.name))?;
+    Ok(&meta.name)
+}
+
+/// Returns the member name of an entry of `meta`. With `uniqueentryname` it's prefixed with
+/// the meta's name (`Meta_entry`), the way tdr names the members in its C headers.
+fn member_name(meta: &TDRMeta, entry: &TDRMetaEntry) -> String {
+    if meta
+        .flags
+        .contains(TDRMetaFlags::NEED_PREFIX_FOR_UNIQUENAME)
+    {
+        format!("{}_{}", meta.name, entry.name)
+    } else {
+        entry.name.clone()
+    }
+}
+
+/// What a backend writes in a comment in place of a zero-size entry. Zero-size entries have
+/// no storage, so they're only recorded as a comment.
+fn zero_size_note(entry: &TDRMetaEntry) -> String {
+    format!("{}: zero-size {:?} entry", entry.name, entry.type_)
+}
+
+/// Returns the array length expression of an entry, preferring the macro name if one is bound.
+fn entry_count_expr(metalib: &Metalib, entry: &TDRMetaEntry) -> Result<String> {
+    if entry.idx_count != INVALID_METALIB_VALUE {
+        let count_macro = metalib
+            .macros
+            .get(entry.idx_count as usize)
+            .context("Failed to get macro by meta_entry.idx_count")?;
+        Ok(count_macro.name.clone())
+    } else {
+        Ok(entry.count.to_string())
+    }
+}
+
+/// Returns true for the entry types that are emitted as strings rather than arrays.
+fn is_string_type(type_: MetaPrimativeType) -> bool {
+    type_ == MetaPrimativeType::STRING || type_ == MetaPrimativeType::WSTRING
+}
+
+/// Returns true if the entry refers to another meta.
+fn is_meta_type(type_: MetaPrimativeType) -> bool {
+    type_ == MetaPrimativeType::STRUCT || type_ == MetaPrimativeType::UNION
+}
+
+/// Replaces any character that isn't valid in an identifier with an underscore.
+fn sanitize_ident(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if out.is_empty() || out.starts_with(|c: char| c.is_ascii_digit()) {
+        out.insert(0, '_');
+    }
+    out
+}
+
+/// Collects the macro indices referenced by the `count` of any entry, in macro table order.
+fn count_macro_indices(metalib: &Metalib) -> Vec<usize> {
+    let mut indices: Vec<usize> = metalib
+        .metas
+        .iter()
+        .flat_map(|meta| meta.entries.iter())
+        .filter(|entry| entry.idx_count != INVALID_METALIB_VALUE)
+        .map(|entry| entry.idx_count as usize)
+        .filter(|&idx| idx < metalib.macros.len())
+        .collect();
+    indices.sort_unstable();
+    indices.dedup();
+    indices
+}
+
+/// Returns true if the meta is a union.
+pub(crate) fn is_union(meta: &metalib::TDRMeta) -> bool {
+    meta.type_ == MetaPrimativeType::UNION
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{parse, DB_XML};
+
+    #[test]
+    fn unique_entry_names_are_prefixed() {
+        let metalib = parse(DB_XML);
+        let rust = emit_rust(&metalib).unwrap();
+        assert!(rust.contains("    pub Stats_level: i32,\n    pub Stats_exp: u64,\n"), "{rust}");
+        assert!(rust.contains("    pub id: u32,\n"), "{rust}");
+        let python = emit_python(&metalib).unwrap();
+        assert!(python.contains("    Stats_level: int = 0\n"), "{python}");
+        assert!(python.contains("    stats: Stats = "), "{python}");
+    }
+}
@@ -0,0 +1,401 @@
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+
+use super::{
+    is_meta_type, is_string_type, is_union, member_name, sanitize_ident, zero_size_note,
+    GeneratedFile,
+};
+use crate::metalib::{MetaPrimativeType, Metalib, TDRMeta, TDRMetaEntry};
+
+const CSHARP_KEYWORDS: &[&str] = &[
+    "abstract",
+    "as",
+    "base",
+    "bool",
+    "break",
+    "byte",
+    "case",
+    "catch",
+    "char",
+    "checked",
+    "class",
+    "const",
+    "continue",
+    "decimal",
+    "default",
+    "delegate",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "event",
+    "explicit",
+    "extern",
+    "false",
+    "finally",
+    "fixed",
+    "float",
+    "for",
+    "foreach",
+    "goto",
+    "if",
+    "implicit",
+    "in",
+    "int",
+    "interface",
+    "internal",
+    "is",
+    "lock",
+    "long",
+    "namespace",
+    "new",
+    "null",
+    "object",
+    "operator",
+    "out",
+    "override",
+    "params",
+    "private",
+    "protected",
+    "public",
+    "readonly",
+    "ref",
+    "return",
+    "sbyte",
+    "sealed",
+    "short",
+    "sizeof",
+    "stackalloc",
+    "static",
+    "string",
+    "struct",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "uint",
+    "ulong",
+    "unchecked",
+    "unsafe",
+    "ushort",
+    "using",
+    "virtual",
+    "void",
+    "volatile",
+    "while",
+];
+
+fn csharp_ident(name: &str) -> String {
+    let ident = sanitize_ident(name);
+    if CSHARP_KEYWORDS.contains(&ident.as_str()) {
+        format!("@{ident}")
+    } else {
+        ident
+    }
+}
+
+/// Class names for every meta, by `_offset`. Metas whose names sanitize to the same
+/// identifier, ignoring case so their `.cs` files don't clash either, get a `_2`, `_3`, ...
+/// suffix in table order.
+fn class_names(metalib: &Metalib) -> HashMap<u64, String> {
+    let mut used = HashSet::new();
+    let mut names = HashMap::new();
+    for meta in metalib.metas.iter() {
+        let base = csharp_ident(&meta.name);
+        let mut name = base.clone();
+        let mut n = 2;
+        while !used.insert(name.trim_start_matches('@').to_lowercase()) {
+            name = format!("{base}_{n}");
+            n += 1;
+        }
+        names.insert(meta._offset, name);
+    }
+    names
+}
+
+/// The class name of the meta a STRUCT/UNION entry refers to.
+fn referenced_class_name<'a>(
+    metalib: &Metalib,
+    classes: &'a HashMap<u64, String>,
+    entry: &TDRMetaEntry,
+) -> Result<&'a str> {
+    let meta = metalib
+        .get_meta_by_offset(entry.ptr_meta)
+        .with_context(|| format!("Failed to get meta referenced by entry `{}`", entry.name))?;
+    Ok(&classes[&meta._offset])
+}
+
+fn csharp_primitive_type(type_: MetaPrimativeType) -> &'static str {
+    match type_ {
+        MetaPrimativeType::CHAR => "sbyte",
+        MetaPrimativeType::UCHAR | MetaPrimativeType::BYTE => "byte",
+        MetaPrimativeType::SHORT => "short",
+        MetaPrimativeType::USHORT => "ushort",
+        MetaPrimativeType::INT | MetaPrimativeType::LONG => "int",
+        MetaPrimativeType::UINT | MetaPrimativeType::ULONG => "uint",
+        MetaPrimativeType::LONGLONG => "long",
+        MetaPrimativeType::ULONGLONG => "ulong",
+        // 4 byte TDR-specific types, kept as their raw representation.
+        MetaPrimativeType::DATE
+        | MetaPrimativeType::TIME
+        | MetaPrimativeType::MONEY
+        | MetaPrimativeType::IP => "uint",
+        MetaPrimativeType::DATETIME => "ulong",
+        MetaPrimativeType::FLOAT => "float",
+        MetaPrimativeType::DOUBLE => "double",
+        MetaPrimativeType::WCHAR => "char",
+        MetaPrimativeType::STRING | MetaPrimativeType::WSTRING => "string",
+        MetaPrimativeType::VOID | MetaPrimativeType::UNKNOWN => "byte",
+        MetaPrimativeType::UNION | MetaPrimativeType::STRUCT => unreachable!(),
+    }
+}
+
+fn xml_doc_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn write_xml_doc(out: &mut String, indent: &str, desc: &str, chinese_name: &str) -> Result<()> {
+    if !desc.is_empty() {
+        writeln!(out, "{indent}/// <summary>")?;
+        for line in desc.lines() {
+            writeln!(out, "{indent}/// {}", xml_doc_escape(line))?;
+        }
+        writeln!(out, "{indent}/// </summary>")?;
+    }
+    if !chinese_name.is_empty() {
+        writeln!(
+            out,
+            "{indent}/// <remarks>{}</remarks>",
+            xml_doc_escape(chinese_name)
+        )?;
+    }
+    Ok(())
+}
+
+/// Describes every selector used to pick a member of the given union: `selected by
+/// `Parent.field``, or why a selector couldn't be resolved.
+fn union_selectors(metalib: &Metalib, union_meta: &TDRMeta) -> Vec<String> {
+    let mut selectors = Vec::new();
+    for meta in metalib.metas.iter() {
+        for entry in meta.entries.iter() {
+            if entry.type_ != MetaPrimativeType::UNION
+                || entry.ptr_meta as u64 != union_meta._offset
+            {
+                continue;
+            }
+
+            match entry.selector.resolve(metalib, meta) {
+                Ok(Some(select_field)) => {
+                    selectors.push(format!("selected by `{}.{}`", meta.name, select_field.path))
+                }
+                Ok(None) => {}
+                Err(err) => selectors.push(format!(
+                    "selected by an unresolved selector of `{}.{}`: {err:#}",
+                    meta.name, entry.name
+                )),
+            }
+        }
+    }
+    selectors
+}
+
+fn emit_csharp_entry(
+    out: &mut String,
+    metalib: &Metalib,
+    classes: &HashMap<u64, String>,
+    meta: &TDRMeta,
+    entry: &TDRMetaEntry,
+) -> Result<()> {
+    if entry.is_zero_size() {
+        writeln!(
+            out,
+            "        // {} at host offset {}",
+            zero_size_note(entry),
+            entry.h_off
+        )?;
+        return Ok(());
+    }
+
+    write_xml_doc(out, "        ", &entry.desc, &entry.chinese_name)?;
+    writeln!(out, "        // [FieldOffset({})]", entry.h_off)?;
+
+    let type_name = if is_meta_type(entry.type_) {
+        referenced_class_name(metalib, classes, entry)?.to_string()
+    } else {
+        csharp_primitive_type(entry.type_).to_string()
+    };
+
+    let field_name = csharp_ident(&member_name(meta, entry));
+    if is_string_type(entry.type_) {
+        // SizeConst counts characters; `count` is 1 for a `size`d string, so it comes from
+        // the buffer size instead.
+        let chars = match entry.type_ {
+            MetaPrimativeType::WSTRING => entry.h_unit_size / 2,
+            _ => entry.h_unit_size,
+        };
+        writeln!(
+            out,
+            "        [MarshalAs(UnmanagedType.ByValTStr, SizeConst = {chars})]"
+        )?;
+        writeln!(out, "        public {type_name} {field_name};")?;
+    } else if entry.count > 1 {
+        writeln!(
+            out,
+            "        [MarshalAs(UnmanagedType.ByValArray, SizeConst = {})]",
+            entry.count
+        )?;
+        writeln!(out, "        public {type_name}[] {field_name};")?;
+    } else {
+        writeln!(out, "        public {type_name} {field_name};")?;
+    }
+
+    Ok(())
+}
+
+fn emit_csharp_meta(
+    metalib: &Metalib,
+    classes: &HashMap<u64, String>,
+    meta: &TDRMeta,
+) -> Result<String> {
+    let mut out = String::new();
+    writeln!(
+        &mut out,
+        "// Generated by mldec from metalib `{}` (version {}).",
+        metalib.header.name, metalib.header.version
+    )?;
+    writeln!(&mut out, "using System.Runtime.InteropServices;")?;
+    writeln!(&mut out)?;
+    writeln!(&mut out, "namespace {}", csharp_namespace(metalib))?;
+    writeln!(&mut out, "{{")?;
+
+    write_xml_doc(&mut out, "    ", &meta.desc, &meta.chinese_name)?;
+    if is_union(meta) {
+        for selector in union_selectors(metalib, meta) {
+            writeln!(&mut out, "    // Union member {selector}.")?;
+        }
+    }
+    writeln!(
+        &mut out,
+        "    [StructLayout(LayoutKind.Sequential, Pack = 1)]"
+    )?;
+    writeln!(&mut out, "    public class {}", classes[&meta._offset])?;
+    writeln!(&mut out, "    {{")?;
+    for (i, entry) in meta.entries.iter().enumerate() {
+        if i > 0 {
+            writeln!(&mut out)?;
+        }
+        emit_csharp_entry(&mut out, metalib, classes, meta, entry)?;
+    }
+    writeln!(&mut out, "    }}")?;
+    writeln!(&mut out, "}}")?;
+
+    Ok(out)
+}
+
+fn csharp_namespace(metalib: &Metalib) -> String {
+    if metalib.header.name.is_empty() {
+        "Metalib".to_string()
+    } else {
+        csharp_ident(&metalib.header.name)
+    }
+}
+
+/// Generates one C# class per meta in the metalib, returned as separate `.cs` files named
+/// after the class.
+///
+/// Field offsets from the host layout are recorded as comments, since unions are
+/// emitted as plain classes containing all of their members.
+pub fn emit_csharp(metalib: &Metalib) -> Result<Vec<GeneratedFile>> {
+    let classes = class_names(metalib);
+    let mut files = Vec::new();
+    for meta in metalib.metas.iter() {
+        let class = &classes[&meta._offset];
+        files.push(GeneratedFile {
+            file_name: format!("{}.cs", class.trim_start_matches('@')),
+            contents: emit_csharp_meta(metalib, &classes, meta)?,
+        });
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{compile, TYPES_XML};
+
+    #[test]
+    fn string_sizes_are_in_characters() {
+        let files = emit_csharp(&compile(TYPES_XML)).unwrap();
+        assert_eq!(files[0].file_name, "All.cs");
+        let source = &files[0].contents;
+        assert!(source.contains("ByValTStr, SizeConst = 8)]\n        public string str;"));
+        assert!(source.contains("ByValTStr, SizeConst = 8)]\n        public string w;"));
+    }
+
+    #[test]
+    fn clashing_names_get_unique_classes_and_files() {
+        let xml = r#"<metalib tagsetversion="1" name="clash" version="1">
+            <struct name="a-b" version="1">
+                <entry name="x" type="int"/>
+            </struct>
+            <struct name="A_B" version="1">
+                <entry name="y" type="int"/>
+            </struct>
+            <struct name="Holder" version="1">
+                <entry name="first" type="a-b"/>
+                <entry name="second" type="A_B"/>
+            </struct>
+        </metalib>"#;
+        let files = emit_csharp(&compile(xml)).unwrap();
+        let names: Vec<&str> = files.iter().map(|file| file.file_name.as_str()).collect();
+        assert_eq!(names, ["a_b.cs", "A_B_2.cs", "Holder.cs"]);
+        assert!(files[1].contents.contains("    public class A_B_2\n"));
+        let holder = &files[2].contents;
+        assert!(holder.contains("        public a_b first;\n"), "{holder}");
+        assert!(
+            holder.contains("        public A_B_2 second;\n"),
+            "{holder}"
+        );
+    }
+
+    #[test]
+    fn unresolved_selectors_are_comments() {
+        let xml = r#"<metalib tagsetversion="1" name="sel" version="1">
+            <union name="Shape" version="1">
+                <entry name="radius" type="float" id="1"/>
+                <entry name="side" type="int" id="2"/>
+            </union>
+            <struct name="Holder" version="1">
+                <entry name="kind" type="int"/>
+                <entry name="shape" type="Shape" select="kind"/>
+            </struct>
+        </metalib>"#;
+        let mut metalib = compile(xml);
+        let shape = &emit_csharp(&metalib).unwrap()[0].contents;
+        assert!(
+            shape.contains("    // Union member selected by `Holder.kind`.\n"),
+            "{shape}"
+        );
+
+        let holder = metalib
+            .metas
+            .iter_mut()
+            .find(|m| m.name == "Holder")
+            .unwrap();
+        // Neither its offset nor its entry pointer leads to a field.
+        holder.entries[1].selector.h_off = 99;
+        holder.entries[1].selector.ptr_entry = 1;
+        let shape = &emit_csharp(&metalib).unwrap()[0].contents;
+        assert!(
+            shape.contains(
+                "    // Union member selected by an unresolved selector of `Holder.shape`: "
+            ),
+            "{shape}"
+        );
+    }
+}
@@ -0,0 +1,470 @@
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+
+use super::{
+    entry_count_expr, is_meta_type, is_string_type, is_union, member_name, sanitize_ident,
+};
+use crate::metalib::{
+    MetaPrimativeType, Metalib, TDRMacroGroup, TDRMeta, TDRMetaEntry, TDRMetaFlags,
+    INVALID_METALIB_VALUE,
+};
+
+/// Words the FlatBuffers schema parser treats specially, including every built-in type name.
+const FBS_KEYWORDS: &[&str] = &[
+    "attribute",
+    "bool",
+    "byte",
+    "double",
+    "enum",
+    "false",
+    "file_extension",
+    "file_identifier",
+    "float",
+    "float32",
+    "float64",
+    "include",
+    "inf",
+    "infinity",
+    "int",
+    "int16",
+    "int32",
+    "int64",
+    "int8",
+    "long",
+    "namespace",
+    "nan",
+    "native_include",
+    "root_type",
+    "rpc_service",
+    "short",
+    "string",
+    "struct",
+    "table",
+    "true",
+    "ubyte",
+    "uint",
+    "uint16",
+    "uint32",
+    "uint64",
+    "uint8",
+    "ulong",
+    "union",
+    "ushort",
+];
+
+/// Sanitizes a name into a FlatBuffers identifier that doesn't clash with a keyword or with
+/// any name already in `used`.
+fn fbs_ident(name: &str, used: &mut HashSet<String>) -> String {
+    let mut base = sanitize_ident(name);
+    if FBS_KEYWORDS.contains(&base.as_str()) {
+        base.push('_');
+    }
+
+    let mut ident = base.clone();
+    let mut n = 2;
+    while !used.insert(ident.clone()) {
+        ident = format!("{base}_{n}");
+        n += 1;
+    }
+    ident
+}
+
+fn fbs_primitive_type(type_: MetaPrimativeType) -> &'static str {
+    match type_ {
+        MetaPrimativeType::CHAR => "byte",
+        MetaPrimativeType::UCHAR | MetaPrimativeType::BYTE => "ubyte",
+        MetaPrimativeType::SHORT => "short",
+        MetaPrimativeType::USHORT | MetaPrimativeType::WCHAR => "ushort",
+        MetaPrimativeType::INT | MetaPrimativeType::LONG => "int",
+        MetaPrimativeType::UINT | MetaPrimativeType::ULONG => "uint",
+        MetaPrimativeType::LONGLONG => "long",
+        MetaPrimativeType::ULONGLONG => "ulong",
+        // 4 byte TDR-specific types, kept as their raw representation.
+        MetaPrimativeType::DATE
+        | MetaPrimativeType::TIME
+        | MetaPrimativeType::MONEY
+        | MetaPrimativeType::IP => "uint",
+        MetaPrimativeType::DATETIME => "ulong",
+        MetaPrimativeType::FLOAT => "float",
+        MetaPrimativeType::DOUBLE => "double",
+        MetaPrimativeType::STRING | MetaPrimativeType::WSTRING => "string",
+        MetaPrimativeType::VOID | MetaPrimativeType::UNKNOWN => "ubyte",
+        MetaPrimativeType::UNION | MetaPrimativeType::STRUCT => unreachable!(),
+    }
+}
+
+/// Returns true if the entry holds a variable or fixed number of elements.
+fn is_array(entry: &TDRMetaEntry) -> bool {
+    if is_string_type(entry.type_) {
+        return false;
+    }
+    entry.count > 1 || entry.count == 0 || entry.referer.h_off != INVALID_METALIB_VALUE
+}
+
+fn write_comment(out: &mut String, indent: &str, text: &str) -> Result<()> {
+    for line in text.lines() {
+        writeln!(out, "{indent}// {line}")?;
+    }
+    Ok(())
+}
+
+/// Schema-wide names, assigned up front so references and declarations always agree.
+struct FbsNames {
+    metas: HashMap<u64, String>,
+    macrogroups: HashMap<u64, String>,
+
+    /// Offsets of the metas emitted as FlatBuffers `struct`s rather than `table`s.
+    structs: HashSet<u64>,
+
+    /// Wrapper tables for union members that aren't tables, keyed by member entry offset.
+    union_wrappers: HashMap<u64, String>,
+}
+
+impl FbsNames {
+    fn new(metalib: &Metalib) -> FbsNames {
+        let mut used = HashSet::new();
+        let metas = metalib
+            .metas
+            .iter()
+            .map(|meta| (meta._offset, fbs_ident(&meta.name, &mut used)))
+            .collect();
+        let macrogroups = metalib
+            .macrogroups
+            .iter()
+            .map(|group| (group._offset, fbs_ident(&group.name, &mut used)))
+            .collect();
+        let structs = fixed_size_structs(metalib);
+
+        let mut union_wrappers = HashMap::new();
+        for meta in metalib.metas.iter().filter(|meta| is_union(meta)) {
+            for entry in meta.entries.iter().filter(|e| !e.is_zero_size()) {
+                let is_table = entry.type_ == MetaPrimativeType::STRUCT
+                    && !is_array(entry)
+                    && !metalib
+                        .get_meta_by_offset(entry.ptr_meta)
+                        .map(|m| structs.contains(&m._offset))
+                        .unwrap_or(true);
+                if !is_table {
+                    union_wrappers.insert(
+                        entry._offset,
+                        fbs_ident(&format!("{}_{}", meta.name, entry.name), &mut used),
+                    );
+                }
+            }
+        }
+
+        FbsNames {
+            metas,
+            macrogroups,
+            structs,
+            union_wrappers,
+        }
+    }
+}
+
+/// Finds the `FIXED_SIZE` metas that FlatBuffers can represent as a `struct`: ones made only
+/// of single scalars and other such structs.
+fn fixed_size_structs(metalib: &Metalib) -> HashSet<u64> {
+    let mut structs: HashSet<u64> = metalib
+        .metas
+        .iter()
+        .filter(|meta| !is_union(meta) && meta.flags.contains(TDRMetaFlags::FIXED_SIZE))
+        .filter(|meta| meta.entries.iter().any(|e| !e.is_zero_size()))
+        .map(|meta| meta._offset)
+        .collect();
+
+    // Drop candidates until every remaining struct only nests other remaining structs.
+    loop {
+        let rejected: Vec<u64> = metalib
+            .metas
+            .iter()
+            .filter(|meta| structs.contains(&meta._offset))
+            .filter(|meta| {
+                meta.entries.iter().any(|entry| {
+                    if is_array(entry)
+                        || is_string_type(entry.type_)
+                        || entry.type_ == MetaPrimativeType::UNION
+                    {
+                        return true;
+                    }
+                    entry.type_ == MetaPrimativeType::STRUCT
+                        && !metalib
+                            .get_meta_by_offset(entry.ptr_meta)
+                            .map(|m| structs.contains(&m._offset))
+                            .unwrap_or(false)
+                })
+            })
+            .map(|meta| meta._offset)
+            .collect();
+        if rejected.is_empty() {
+            return structs;
+        }
+        for offset in rejected {
+            structs.remove(&offset);
+        }
+    }
+}
+
+/// Returns the FlatBuffers type of a single element of an entry.
+fn fbs_element_type(metalib: &Metalib, names: &FbsNames, entry: &TDRMetaEntry) -> Result<String> {
+    if is_meta_type(entry.type_) {
+        let meta = metalib.get_meta_by_offset(entry.ptr_meta)?;
+        Ok(names.metas[&meta._offset].clone())
+    } else {
+        Ok(fbs_primitive_type(entry.type_).to_string())
+    }
+}
+
+/// Returns the `= value` suffix for a scalar field with a parseable default.
+fn fbs_default(entry: &TDRMetaEntry) -> Option<String> {
+    if entry.ptr_default_val == INVALID_METALIB_VALUE
+        || is_meta_type(entry.type_)
+        || is_string_type(entry.type_)
+        || is_array(entry)
+    {
+        return None;
+    }
+    let value = entry.default_value_string.trim();
+    if value.parse::<i128>().is_ok() || value.parse::<f64>().is_ok_and(f64::is_finite) {
+        Some(format!(" = {value}"))
+    } else {
+        None
+    }
+}
+
+fn emit_fbs_fields(
+    out: &mut String,
+    metalib: &Metalib,
+    names: &FbsNames,
+    meta: &TDRMeta,
+    is_struct: bool,
+) -> Result<()> {
+    let mut used = HashSet::new();
+    for entry in meta.entries.iter() {
+        if entry.is_zero_size() {
+            writeln!(
+                out,
+                "  // {}: zero-size {:?} entry",
+                entry.name, entry.type_
+            )?;
+            continue;
+        }
+        if !entry.desc.is_empty() {
+            write_comment(out, "  ", &entry.desc)?;
+        }
+
+        let element_type = fbs_element_type(metalib, names, entry)?;
+        let field_type = if is_array(entry) {
+            format!("[{element_type}]")
+        } else {
+            element_type
+        };
+        // Struct fields can't have defaults.
+        let default = if is_struct {
+            String::new()
+        } else {
+            fbs_default(entry).unwrap_or_default()
+        };
+        write!(
+            out,
+            "  {}: {field_type}{default};",
+            fbs_ident(&member_name(meta, entry), &mut used)
+        )?;
+        if entry.referer.h_off != INVALID_METALIB_VALUE || entry.count == 0 {
+            writeln!(out, " // max count: {}", entry_count_expr(metalib, entry)?)?;
+        } else if is_array(entry) {
+            writeln!(out, " // count: {}", entry_count_expr(metalib, entry)?)?;
+        } else {
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+fn emit_fbs_union(
+    out: &mut String,
+    metalib: &Metalib,
+    names: &FbsNames,
+    meta: &TDRMeta,
+) -> Result<()> {
+    let mut used = HashSet::new();
+    let mut members = Vec::new();
+    for entry in meta.entries.iter().filter(|e| !e.is_zero_size()) {
+        let member_type = match names.union_wrappers.get(&entry._offset) {
+            Some(wrapper) => wrapper.clone(),
+            None => fbs_element_type(metalib, names, entry)?,
+        };
+        members.push(format!(
+            "{}: {member_type}",
+            fbs_ident(&member_name(meta, entry), &mut used)
+        ));
+    }
+
+    // Unions can only hold tables, so anything else gets a single-field wrapper table.
+    for entry in meta.entries.iter() {
+        if let Some(wrapper) = names.union_wrappers.get(&entry._offset) {
+            let element_type = fbs_element_type(metalib, names, entry)?;
+            let field_type = if is_array(entry) {
+                format!("[{element_type}]")
+            } else {
+                element_type
+            };
+            writeln!(out, "table {wrapper} {{")?;
+            writeln!(out, "  value: {field_type};")?;
+            writeln!(out, "}}")?;
+            writeln!(out)?;
+        }
+    }
+
+    if !meta.desc.is_empty() {
+        write_comment(out, "", &meta.desc)?;
+    }
+    writeln!(out, "union {} {{", names.metas[&meta._offset])?;
+    for (i, member) in members.iter().enumerate() {
+        let separator = if i + 1 < members.len() { "," } else { "" };
+        writeln!(out, "  {member}{separator}")?;
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+fn emit_fbs_meta(
+    out: &mut String,
+    metalib: &Metalib,
+    names: &FbsNames,
+    meta: &TDRMeta,
+) -> Result<()> {
+    if is_union(meta) {
+        return emit_fbs_union(out, metalib, names, meta);
+    }
+
+    if !meta.desc.is_empty() {
+        write_comment(out, "", &meta.desc)?;
+    }
+    let is_struct = names.structs.contains(&meta._offset);
+    let keyword = if is_struct { "struct" } else { "table" };
+    writeln!(out, "{keyword} {} {{", names.metas[&meta._offset])?;
+    emit_fbs_fields(out, metalib, names, meta, is_struct)?;
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+fn emit_fbs_enum(
+    out: &mut String,
+    metalib: &Metalib,
+    names: &FbsNames,
+    group: &TDRMacroGroup,
+) -> Result<()> {
+    let mut macros: Vec<_> = metalib.macrogroup_macros(group).collect();
+    // FlatBuffers wants enum values in ascending order with no repeats.
+    macros.sort_by_key(|tdr_macro| tdr_macro.value);
+
+    if !group.desc.is_empty() {
+        write_comment(out, "", &group.desc)?;
+    }
+    if macros.is_empty() {
+        writeln!(out, "// macrogroup {} has no macros", group.name)?;
+        return Ok(());
+    }
+    writeln!(out, "enum {} : int {{", names.macrogroups[&group._offset])?;
+    let mut used = HashSet::new();
+    let mut last_value = None;
+    let mut lines = Vec::new();
+    for tdr_macro in macros {
+        if last_value == Some(tdr_macro.value) {
+            lines.push(format!(
+                "  // {} = {} (duplicate value)",
+                tdr_macro.name, tdr_macro.value
+            ));
+            continue;
+        }
+        last_value = Some(tdr_macro.value);
+        lines.push(format!(
+            "  {} = {}",
+            fbs_ident(&tdr_macro.name, &mut used),
+            tdr_macro.value
+        ));
+    }
+
+    // Only value lines take a separator; comments can sit anywhere.
+    let last_value_line = lines.iter().rposition(|line| !line.starts_with("  //"));
+    for (i, line) in lines.iter().enumerate() {
+        let is_value = !line.starts_with("  //");
+        let separator = if is_value && Some(i) != last_value_line {
+            ","
+        } else {
+            ""
+        };
+        writeln!(out, "{line}{separator}")?;
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+/// Generates a FlatBuffers schema for the whole metalib.
+///
+/// Fixed-size metas made only of scalars become `struct`s, everything else a `table`.
+/// Unions use aliased members so the same type can appear more than once, and members
+/// that aren't tables are wrapped in a single-field table.
+pub fn emit_fbs(metalib: &Metalib) -> Result<String> {
+    let names = FbsNames::new(metalib);
+
+    let mut out = String::new();
+    writeln!(
+        &mut out,
+        "// Generated by mldec from metalib `{}` (version {}).",
+        metalib.header.name, metalib.header.version
+    )?;
+    if !metalib.header.name.is_empty() {
+        writeln!(&mut out)?;
+        writeln!(
+            &mut out,
+            "namespace {};",
+            fbs_ident(&metalib.header.name, &mut HashSet::new())
+        )?;
+    }
+
+    for group in metalib.macrogroups.iter() {
+        writeln!(&mut out)?;
+        emit_fbs_enum(&mut out, metalib, &names, group)?;
+    }
+
+    for meta in metalib.metas.iter() {
+        writeln!(&mut out)?;
+        emit_fbs_meta(&mut out, metalib, &names, meta)?;
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{compile, FULL_XML};
+
+    #[test]
+    fn matches_golden_file() {
+        let expected = include_str!("../../tests/fixtures/full.fbs");
+        assert_eq!(emit_fbs(&compile(FULL_XML)).unwrap(), expected);
+    }
+
+    #[test]
+    fn keywords_and_collisions_are_renamed() {
+        let xml = r#"<metalib tagsetversion="1" name="kw" version="1">
+            <struct name="table" version="1">
+                <entry name="union" type="int"/>
+                <entry name="union_" type="int"/>
+            </struct>
+            <struct name="table_" version="1"><entry name="x" type="int"/></struct>
+        </metalib>"#;
+        let source = emit_fbs(&compile(xml)).unwrap();
+        assert!(source.contains("struct table_ {\n  union_: int;\n  union__2: int;\n}"));
+        assert!(
+            source.contains("struct table__2 {\n  x: int;\n}"),
+            "{source}"
+        );
+        // The same input always gets the same names.
+        assert_eq!(source, emit_fbs(&compile(xml)).unwrap());
+    }
+}
@@ -0,0 +1,283 @@
+use anyhow::Result;
+use std::fmt::Write as _;
+
+use super::{is_meta_type, is_string_type, is_union, referenced_meta_name};
+use crate::metalib::{
+    MetaPrimativeType, Metalib, SizeSource, TDRMeta, TDRMetaEntry, TDRMetaFlags,
+    INVALID_METALIB_VALUE,
+};
+
+/// Converts a TDR name to a Kaitai identifier (`[a-z][a-z0-9_]*`).
+fn ksy_ident(name: &str) -> String {
+    let mut out = String::new();
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            if prev_lower {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+            prev_lower = false;
+        } else if c.is_ascii_alphanumeric() {
+            out.push(c);
+            prev_lower = true;
+        } else {
+            out.push('_');
+            prev_lower = false;
+        }
+    }
+    if !out.starts_with(|c: char| c.is_ascii_lowercase()) {
+        out.insert_str(0, "f_");
+    }
+    out
+}
+
+/// Converts a dotted entry path (as returned by the offset resolvers) to a Kaitai expression.
+fn ksy_path(path: &str) -> String {
+    path.split('.').map(ksy_ident).collect::<Vec<_>>().join(".")
+}
+
+fn ksy_primitive_type(type_: MetaPrimativeType) -> &'static str {
+    match type_ {
+        MetaPrimativeType::CHAR => "s1",
+        MetaPrimativeType::UCHAR | MetaPrimativeType::BYTE => "u1",
+        MetaPrimativeType::SHORT => "s2",
+        MetaPrimativeType::USHORT | MetaPrimativeType::WCHAR => "u2",
+        MetaPrimativeType::INT | MetaPrimativeType::LONG => "s4",
+        MetaPrimativeType::UINT | MetaPrimativeType::ULONG => "u4",
+        MetaPrimativeType::LONGLONG => "s8",
+        MetaPrimativeType::ULONGLONG => "u8",
+        // 4 byte TDR-specific types, kept as their raw representation.
+        MetaPrimativeType::DATE
+        | MetaPrimativeType::TIME
+        | MetaPrimativeType::MONEY
+        | MetaPrimativeType::IP => "u4",
+        MetaPrimativeType::DATETIME => "u8",
+        MetaPrimativeType::FLOAT => "f4",
+        MetaPrimativeType::DOUBLE => "f8",
+        MetaPrimativeType::STRING | MetaPrimativeType::WSTRING => "strz",
+        MetaPrimativeType::VOID | MetaPrimativeType::UNKNOWN => "u1",
+        MetaPrimativeType::UNION | MetaPrimativeType::STRUCT => unreachable!(),
+    }
+}
+
+/// Unsigned Kaitai type for a length prefix of the given width.
+fn ksy_prefix_type(unit_size: i32) -> &'static str {
+    match unit_size {
+        1 => "u1",
+        2 => "u2",
+        8 => "u8",
+        _ => "u4",
+    }
+}
+
+/// Returns the Kaitai type of a single element of a non-union entry.
+fn ksy_element_type(metalib: &Metalib, entry: &TDRMetaEntry) -> Result<String> {
+    if is_meta_type(entry.type_) {
+        Ok(ksy_ident(referenced_meta_name(metalib, entry)?))
+    } else {
+        Ok(ksy_primitive_type(entry.type_).to_string())
+    }
+}
+
+/// Writes the `type:` of a union entry as a `switch-on` over its selector.
+fn write_union_switch(
+    out: &mut String,
+    metalib: &Metalib,
+    meta: &TDRMeta,
+    entry: &TDRMetaEntry,
+) -> Result<bool> {
+    let selector = match entry.selector.resolve(metalib, meta)? {
+        Some(selector) => selector.path,
+        None => return Ok(false),
+    };
+    let union_meta = metalib.get_meta_by_offset(entry.ptr_meta)?;
+
+    writeln!(out, "        type:")?;
+    writeln!(out, "          switch-on: {}", ksy_path(&selector))?;
+    writeln!(out, "          cases:")?;
+    let mut has_default = false;
+    for member in union_meta.entries.iter().filter(|e| !e.is_zero_size()) {
+        let member_type = ksy_element_type(metalib, member)?;
+        if member.id != INVALID_METALIB_VALUE {
+            writeln!(out, "            {}: {member_type}", member.id)?;
+        } else if !has_default {
+            // Members without an id are picked when nothing else matches.
+            writeln!(out, "            _: {member_type}")?;
+            has_default = true;
+        }
+    }
+    Ok(true)
+}
+
+fn emit_ksy_entry(
+    out: &mut String,
+    metalib: &Metalib,
+    meta: &TDRMeta,
+    entry: &TDRMetaEntry,
+) -> Result<()> {
+    let id = ksy_ident(&entry.name);
+
+    // A `sizeinfo` with its own type is a length prefix written right before the data.
+    let mut size_expr = match entry.size_info.resolve(metalib, meta)? {
+        Some(SizeSource::TypePrefix(_)) => {
+            writeln!(out, "      - id: {id}_size")?;
+            writeln!(
+                out,
+                "        type: {}",
+                ksy_prefix_type(entry.size_info.unit_size)
+            )?;
+            Some(format!("{id}_size"))
+        }
+        Some(SizeSource::Field(field)) => Some(ksy_path(&field.path)),
+        None => None,
+    };
+
+    writeln!(out, "      - id: {id}")?;
+    if !entry.desc.is_empty() {
+        writeln!(out, "        doc: {:?}", entry.desc)?;
+    }
+
+    if entry.type_ == MetaPrimativeType::UNION {
+        if !write_union_switch(out, metalib, meta, entry)? && size_expr.is_none() {
+            // No selector to switch on, so the best we can do is skip over it.
+            size_expr = Some(entry.n_unit_size.to_string());
+        }
+    } else {
+        writeln!(out, "        type: {}", ksy_element_type(metalib, entry)?)?;
+    }
+
+    // `count` is the length in characters; the wire holds `n_unit_size` bytes.
+    if is_string_type(entry.type_) && size_expr.is_none() {
+        size_expr = Some(entry.n_unit_size.to_string());
+    }
+    if let Some(size_expr) = size_expr {
+        writeln!(out, "        size: {size_expr}")?;
+    }
+
+    if is_string_type(entry.type_) {
+        return Ok(());
+    }
+    if let Some(count_field) = entry.referer.resolve(metalib, meta)? {
+        writeln!(out, "        repeat: expr")?;
+        writeln!(out, "        repeat-expr: {}", ksy_path(&count_field.path))?;
+    } else if entry.count > 1 {
+        writeln!(out, "        repeat: expr")?;
+        writeln!(out, "        repeat-expr: {}", entry.count)?;
+    }
+
+    Ok(())
+}
+
+fn emit_ksy_meta(out: &mut String, metalib: &Metalib, meta: &TDRMeta) -> Result<()> {
+    writeln!(out, "  {}:", ksy_ident(&meta.name))?;
+    if !meta.desc.is_empty() {
+        writeln!(out, "    doc: {:?}", meta.desc)?;
+    }
+    writeln!(out, "    seq:")?;
+
+    // Gaps between entries are only meaningful when every entry has a fixed net size.
+    let is_fixed = !meta.flags.contains(TDRMetaFlags::VARIABLE);
+    let mut net_end: i64 = 0;
+    let mut has_entries = false;
+    for entry in meta.entries.iter() {
+        if entry.is_zero_size() {
+            writeln!(
+                out,
+                "      # {}: zero-size {:?} entry",
+                entry.name, entry.type_
+            )?;
+            continue;
+        }
+        // Offsets come straight from the file, so overlapping or out of order entries get no
+        // padding, and the end is worked out in i64 so it can't overflow.
+        let n_off = i64::from(entry.n_off);
+        if is_fixed && n_off > net_end {
+            writeln!(out, "      - id: padding_{net_end:x}")?;
+            writeln!(out, "        size: {}", n_off - net_end)?;
+        }
+        emit_ksy_entry(out, metalib, meta, entry)?;
+        let size = entry.n_real_size.max(entry.n_unit_size);
+        net_end = net_end.max(n_off + i64::from(size));
+        has_entries = true;
+    }
+
+    if !has_entries {
+        writeln!(out, "      []")?;
+    }
+
+    Ok(())
+}
+
+/// Generates a Kaitai Struct definition describing the network layout of every struct.
+///
+/// Unions aren't emitted as types of their own: each use site switches on its selector
+/// field and picks the member type directly.
+pub fn emit_ksy(metalib: &Metalib) -> Result<String> {
+    let mut out = String::new();
+    writeln!(
+        &mut out,
+        "# Generated by mldec from metalib `{}` (version {}).",
+        metalib.header.name, metalib.header.version
+    )?;
+    writeln!(&mut out, "meta:")?;
+    let id = if metalib.header.name.is_empty() {
+        "metalib".to_string()
+    } else {
+        ksy_ident(&metalib.header.name)
+    };
+    writeln!(&mut out, "  id: {id}")?;
+    // TDR encodes everything on the wire in network byte order.
+    writeln!(&mut out, "  endian: be")?;
+    writeln!(&mut out, "  encoding: GBK")?;
+    let structs: Vec<&TDRMeta> = metalib.metas.iter().filter(|meta| !is_union(meta)).collect();
+    if structs.is_empty() {
+        // A bare `types:` would be null, which ksc rejects.
+        writeln!(&mut out, "types: {{}}")?;
+        return Ok(out);
+    }
+    writeln!(&mut out, "types:")?;
+
+    for meta in structs {
+        emit_ksy_meta(&mut out, metalib, meta)?;
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{compile, FULL_XML};
+
+    #[test]
+    fn matches_golden_file() {
+        let expected = include_str!("../../tests/fixtures/full.ksy");
+        assert_eq!(emit_ksy(&compile(FULL_XML)).unwrap(), expected);
+    }
+
+    #[test]
+    fn gaps_get_padding_and_bad_offsets_dont_panic() {
+        let xml = r#"<metalib tagsetversion="1" name="gaps" version="1">
+            <struct name="S" version="1">
+                <entry name="a" type="int"/>
+                <entry name="b" type="int"/>
+                <entry name="c" type="int"/>
+            </struct>
+        </metalib>"#;
+        let mut metalib = compile(xml);
+        let entries = &mut metalib.metas[0].entries;
+        entries[1].n_off = 12;
+        let source = emit_ksy(&metalib).unwrap();
+        assert!(source.contains("      - id: padding_4\n        size: 8\n"));
+
+        // Negative, overflowing and overlapping offsets from a corrupted file.
+        let entries = &mut metalib.metas[0].entries;
+        entries[0].n_off = -8;
+        entries[1].n_off = i32::MAX - 1;
+        entries[2].n_off = 2;
+        let source = emit_ksy(&metalib).unwrap();
+        assert_eq!(source.matches("padding").count(), 1, "{source}");
+        assert!(source.contains("      - id: padding_0\n        size: 2147483646\n"));
+    }
+}
@@ -0,0 +1,387 @@
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+
+use super::{
+    is_meta_type, is_string_type, is_union, member_name, referenced_meta_name, sanitize_ident,
+    zero_size_note,
+};
+use crate::metalib::{MetaPrimativeType, Metalib, TDRMeta, TDRMetaEntry, INVALID_METALIB_VALUE};
+
+/// Largest field number protobuf allows.
+const MAX_FIELD_NUMBER: i32 = 536_870_911;
+
+/// Field numbers reserved for the protobuf implementation.
+const RESERVED_FIELD_NUMBERS: std::ops::RangeInclusive<i32> = 19000..=19999;
+
+fn proto_primitive_type(type_: MetaPrimativeType) -> &'static str {
+    match type_ {
+        MetaPrimativeType::CHAR
+        | MetaPrimativeType::SHORT
+        | MetaPrimativeType::INT
+        | MetaPrimativeType::LONG => "int32",
+        MetaPrimativeType::UCHAR
+        | MetaPrimativeType::BYTE
+        | MetaPrimativeType::USHORT
+        | MetaPrimativeType::UINT
+        | MetaPrimativeType::ULONG
+        | MetaPrimativeType::WCHAR => "uint32",
+        MetaPrimativeType::LONGLONG => "int64",
+        MetaPrimativeType::ULONGLONG => "uint64",
+        // 4 byte TDR-specific types, kept as their raw representation.
+        MetaPrimativeType::DATE
+        | MetaPrimativeType::TIME
+        | MetaPrimativeType::MONEY
+        | MetaPrimativeType::IP => "uint32",
+        MetaPrimativeType::DATETIME => "uint64",
+        MetaPrimativeType::FLOAT => "float",
+        MetaPrimativeType::DOUBLE => "double",
+        MetaPrimativeType::STRING | MetaPrimativeType::WSTRING => "string",
+        MetaPrimativeType::VOID | MetaPrimativeType::UNKNOWN => "bytes",
+        MetaPrimativeType::UNION | MetaPrimativeType::STRUCT => unreachable!(),
+    }
+}
+
+fn is_valid_field_number(number: i32) -> bool {
+    number > 0 && number <= MAX_FIELD_NUMBER && !RESERVED_FIELD_NUMBERS.contains(&number)
+}
+
+/// Assigns a field number to each entry: the entry `id` when it's valid and unused,
+/// otherwise its 1-based position (bumped past any number that's already taken).
+fn assign_field_numbers(entries: &[&TDRMetaEntry]) -> Vec<i32> {
+    let mut used = HashSet::new();
+    let mut numbers = vec![0; entries.len()];
+
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.id != INVALID_METALIB_VALUE
+            && is_valid_field_number(entry.id)
+            && used.insert(entry.id)
+        {
+            numbers[i] = entry.id;
+        }
+    }
+
+    for (i, number) in numbers.iter_mut().enumerate() {
+        if *number != 0 {
+            continue;
+        }
+        let mut candidate = i as i32 + 1;
+        while !is_valid_field_number(candidate) || used.contains(&candidate) {
+            candidate += 1;
+        }
+        used.insert(candidate);
+        *number = candidate;
+    }
+
+    numbers
+}
+
+/// Returns true if the entry holds a variable or fixed number of elements.
+fn is_repeated(entry: &TDRMetaEntry) -> bool {
+    if is_string_type(entry.type_) {
+        return false;
+    }
+    entry.count > 1 || entry.count == 0 || entry.referer.h_off != INVALID_METALIB_VALUE
+}
+
+fn proto_field_type(metalib: &Metalib, entry: &TDRMetaEntry) -> Result<(String, bool)> {
+    let repeated = is_repeated(entry);
+    if is_meta_type(entry.type_) {
+        return Ok((
+            sanitize_ident(referenced_meta_name(metalib, entry)?),
+            repeated,
+        ));
+    }
+
+    // Byte arrays are far more compact as `bytes` than as `repeated uint32`.
+    if repeated
+        && (entry.type_ == MetaPrimativeType::UCHAR || entry.type_ == MetaPrimativeType::BYTE)
+    {
+        return Ok(("bytes".to_string(), false));
+    }
+
+    Ok((proto_primitive_type(entry.type_).to_string(), repeated))
+}
+
+fn write_comment(out: &mut String, indent: &str, text: &str) -> Result<()> {
+    for line in text.lines() {
+        writeln!(out, "{indent}// {line}")?;
+    }
+    Ok(())
+}
+
+fn emit_proto_meta(out: &mut String, metalib: &Metalib, meta: &TDRMeta) -> Result<()> {
+    if !meta.desc.is_empty() {
+        write_comment(out, "", &meta.desc)?;
+    }
+    writeln!(out, "message {} {{", sanitize_ident(&meta.name))?;
+
+    let entries: Vec<&TDRMetaEntry> = meta.entries.iter().filter(|e| !e.is_zero_size()).collect();
+    for entry in meta.entries.iter().filter(|e| e.is_zero_size()) {
+        writeln!(out, "  // {}", zero_size_note(entry))?;
+    }
+
+    let numbers = assign_field_numbers(&entries);
+    let indent = if is_union(meta) {
+        writeln!(out, "  oneof value {{")?;
+        "    "
+    } else {
+        "  "
+    };
+
+    for (entry, number) in entries.iter().zip(numbers) {
+        if !entry.desc.is_empty() {
+            write_comment(out, indent, &entry.desc)?;
+        }
+        let (type_name, repeated) = proto_field_type(metalib, entry)?;
+        // `oneof` members can't be repeated, so arrays inside unions are emitted as singular.
+        let label = if repeated && !is_union(meta) {
+            "repeated "
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "{indent}{label}{type_name} {} = {number};",
+            sanitize_ident(&member_name(meta, entry))
+        )?;
+    }
+
+    if is_union(meta) {
+        writeln!(out, "  }}")?;
+    }
+    writeln!(out, "}}")?;
+
+    Ok(())
+}
+
+/// Generates a proto3 schema with one message per meta.
+///
+/// Unions become messages wrapping a single `oneof`, and field numbers come from the
+/// entry `id` where possible so they stay stable across builds.
+pub fn emit_proto(metalib: &Metalib) -> Result<String> {
+    let mut out = String::new();
+    writeln!(
+        &mut out,
+        "// Generated by mldec from metalib `{}` (version {}).",
+        metalib.header.name, metalib.header.version
+    )?;
+    writeln!(&mut out, "syntax = \"proto3\";")?;
+    if !metalib.header.name.is_empty() {
+        writeln!(&mut out)?;
+        writeln!(
+            &mut out,
+            "package {};",
+            sanitize_ident(&metalib.header.name)
+        )?;
+    }
+
+    for meta in metalib.metas.iter() {
+        writeln!(&mut out)?;
+        emit_proto_meta(&mut out, metalib, meta)?;
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::test_support::{compile, FULL_XML, TYPES_XML};
+
+    const SCALARS: &[&str] = &[
+        "double", "float", "int32", "int64", "uint32", "uint64", "sint32", "sint64", "fixed32",
+        "fixed64", "sfixed32", "sfixed64", "bool", "string", "bytes",
+    ];
+
+    fn tokenize(source: &str) -> Vec<String> {
+        let mut tokens = Vec::new();
+        for line in source.lines() {
+            let line = line.split("//").next().unwrap();
+            let mut chars = line.chars().peekable();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    chars.next();
+                } else if "{}=;".contains(c) {
+                    tokens.push(c.to_string());
+                    chars.next();
+                } else if c == '"' {
+                    let mut token = String::from(chars.next().unwrap());
+                    for c in chars.by_ref() {
+                        token.push(c);
+                        if c == '"' {
+                            break;
+                        }
+                    }
+                    assert!(
+                        token.len() > 1 && token.ends_with('"'),
+                        "unterminated {token}"
+                    );
+                    tokens.push(token);
+                } else {
+                    let mut token = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() || "{}=;\"".contains(c) {
+                            break;
+                        }
+                        token.push(c);
+                        chars.next();
+                    }
+                    tokens.push(token);
+                }
+            }
+        }
+        tokens
+    }
+
+    fn is_ident(token: &str) -> bool {
+        let mut chars = token.chars();
+        chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    struct Tokens {
+        tokens: Vec<String>,
+        pos: usize,
+    }
+
+    impl Tokens {
+        fn next(&mut self) -> String {
+            self.pos += 1;
+            self.tokens
+                .get(self.pos - 1)
+                .cloned()
+                .expect("unexpected end of file")
+        }
+
+        fn expect(&mut self, want: &str) {
+            assert_eq!(self.next(), want, "expected `{want}`");
+        }
+
+        fn at_end(&self) -> bool {
+            self.pos == self.tokens.len()
+        }
+    }
+
+    /// A recursive descent check of the proto3 subset `emit_proto` writes: field names and
+    /// numbers must be unique and valid within each message, and every field type must be a
+    /// scalar or a message defined in the file.
+    fn check_proto(source: &str) {
+        let mut tokens = Tokens {
+            tokens: tokenize(source),
+            pos: 0,
+        };
+        tokens.expect("syntax");
+        tokens.expect("=");
+        tokens.expect("\"proto3\"");
+        tokens.expect(";");
+
+        let mut messages = HashSet::new();
+        let mut field_types = Vec::new();
+        let mut token = tokens.next();
+        if token == "package" {
+            let package = tokens.next();
+            assert!(package.split('.').all(is_ident), "bad package {package}");
+            tokens.expect(";");
+            token = tokens.next();
+        }
+        loop {
+            assert_eq!(token, "message");
+            let name = tokens.next();
+            assert!(is_ident(&name), "bad message name {name}");
+            assert!(messages.insert(name.clone()), "duplicate message {name}");
+            tokens.expect("{");
+
+            let mut names = HashSet::new();
+            let mut numbers = HashMap::new();
+            let mut depth = 1;
+            while depth > 0 {
+                let mut token = tokens.next();
+                match token.as_str() {
+                    "}" => {
+                        depth -= 1;
+                        continue;
+                    }
+                    "oneof" => {
+                        assert_eq!(depth, 1, "nested oneof in {name}");
+                        assert!(is_ident(&tokens.next()));
+                        tokens.expect("{");
+                        depth += 1;
+                        continue;
+                    }
+                    "repeated" => {
+                        assert_eq!(depth, 1, "repeated oneof member in {name}");
+                        token = tokens.next();
+                    }
+                    _ => {}
+                }
+                let field = tokens.next();
+                assert!(is_ident(&field), "bad field name {field} in {name}");
+                assert!(
+                    names.insert(field.clone()),
+                    "duplicate field {name}.{field}"
+                );
+                tokens.expect("=");
+                let number: i32 = tokens.next().parse().expect("field number");
+                assert!(is_valid_field_number(number), "{name}.{field} = {number}");
+                assert!(
+                    numbers.insert(number, field.clone()).is_none(),
+                    "duplicate number {number} in {name}"
+                );
+                tokens.expect(";");
+                field_types.push(token);
+            }
+
+            if tokens.at_end() {
+                break;
+            }
+            token = tokens.next();
+        }
+
+        for type_name in field_types {
+            assert!(
+                SCALARS.contains(&type_name.as_str()) || messages.contains(&type_name),
+                "unknown type {type_name}"
+            );
+        }
+    }
+
+    #[test]
+    fn generated_proto_is_valid() {
+        check_proto(&emit_proto(&compile(FULL_XML)).unwrap());
+        check_proto(&emit_proto(&compile(TYPES_XML)).unwrap());
+    }
+
+    #[test]
+    fn union_ids_become_field_numbers() {
+        let xml = r#"<metalib tagsetversion="1" name="ids" version="1">
+            <union name="U" version="1">
+                <entry name="a" type="int" id="7"/>
+                <entry name="b" type="int" id="7"/>
+                <entry name="c" type="int" id="19500"/>
+                <entry name="d" type="int"/>
+            </union>
+        </metalib>"#;
+        let source = emit_proto(&compile(xml)).unwrap();
+        check_proto(&source);
+        // The duplicate and reserved ids fall back to their position.
+        for field in [
+            "int32 a = 7;",
+            "int32 b = 2;",
+            "int32 c = 3;",
+            "int32 d = 4;",
+        ] {
+            assert!(source.contains(field), "{field} missing from\n{source}");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate number 1 in M")]
+    fn checker_rejects_duplicate_numbers() {
+        check_proto("syntax = \"proto3\";\nmessage M {\n  int32 a = 1;\n  int32 b = 1;\n}\n");
+    }
+}
@@ -0,0 +1,398 @@
+use anyhow::Result;
+use byteorder::LittleEndian;
+use std::fmt::Write as _;
+
+use super::{
+    is_meta_type, is_string_type, is_union, member_name, referenced_meta_name, sanitize_ident,
+};
+use crate::codec::{format_date, format_ip, format_time};
+use crate::metalib::{
+    self, MetaPrimativeType, Metalib, TDRMeta, TDRMetaEntry, INVALID_METALIB_VALUE,
+};
+
+const PYTHON_KEYWORDS: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+    "with", "yield",
+];
+
+fn python_ident(name: &str) -> String {
+    let ident = sanitize_ident(name);
+    if PYTHON_KEYWORDS.contains(&ident.as_str()) {
+        format!("{ident}_")
+    } else {
+        ident
+    }
+}
+
+fn python_primitive_type(type_: MetaPrimativeType) -> &'static str {
+    match type_ {
+        MetaPrimativeType::FLOAT | MetaPrimativeType::DOUBLE => "float",
+        MetaPrimativeType::VOID | MetaPrimativeType::UNKNOWN => "bytes",
+        MetaPrimativeType::UNION | MetaPrimativeType::STRUCT => unreachable!(),
+        _ if is_text_type(type_) => "str",
+        _ => "int",
+    }
+}
+
+/// Types whose values are text, both in the XML and when the data is decoded.
+fn is_text_type(type_: MetaPrimativeType) -> bool {
+    matches!(
+        type_,
+        MetaPrimativeType::STRING
+            | MetaPrimativeType::WSTRING
+            | MetaPrimativeType::WCHAR
+            | MetaPrimativeType::IP
+            | MetaPrimativeType::DATE
+            | MetaPrimativeType::TIME
+            | MetaPrimativeType::DATETIME
+    )
+}
+
+/// Formats a Python string literal.
+fn python_str_literal(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(&mut out, "\\x{:02x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The value of a zeroed element, as the data decoder formats it.
+fn python_zero(type_: MetaPrimativeType) -> String {
+    match type_ {
+        MetaPrimativeType::FLOAT | MetaPrimativeType::DOUBLE => "0.0".to_string(),
+        MetaPrimativeType::VOID | MetaPrimativeType::UNKNOWN => "b\"\"".to_string(),
+        MetaPrimativeType::IP => python_str_literal(&format_ip(&[0; 4])),
+        MetaPrimativeType::DATE => python_str_literal(&format_date::<LittleEndian>(&[0; 4])),
+        MetaPrimativeType::TIME => python_str_literal(&format_time::<LittleEndian>(&[0; 4])),
+        MetaPrimativeType::DATETIME => python_str_literal(&format!(
+            "{} {}",
+            format_date::<LittleEndian>(&[0; 4]),
+            format_time::<LittleEndian>(&[0; 4])
+        )),
+        _ if is_text_type(type_) => python_str_literal(""),
+        _ => "0".to_string(),
+    }
+}
+
+/// Returns the Python expression for one element of the entry, `value` as the XML has it.
+fn python_value(type_: MetaPrimativeType, value: &str) -> String {
+    match type_ {
+        MetaPrimativeType::VOID | MetaPrimativeType::UNKNOWN => python_zero(type_),
+        _ if is_text_type(type_) => python_str_literal(value),
+        _ => value.to_string(),
+    }
+}
+
+/// Returns the Python expression for a single element of the entry.
+fn python_scalar_default(entry: &TDRMetaEntry) -> String {
+    if entry.ptr_default_val == INVALID_METALIB_VALUE
+        || (entry.default_value_string.is_empty() && !is_text_type(entry.type_))
+    {
+        return python_zero(entry.type_);
+    }
+    python_value(entry.type_, &entry.default_value_string)
+}
+
+/// Returns the Python expression for the whole of an array entry.
+///
+/// The XML default of an array is one element repeated, the elements separated by spaces,
+/// or for `char` arrays the text itself. Whatever the default leaves out is zeroed.
+fn python_list_default(entry: &TDRMetaEntry) -> String {
+    let count = entry.count.max(0) as usize;
+    let value = &entry.default_value_string;
+    let has_default = entry.ptr_default_val != INVALID_METALIB_VALUE && !value.is_empty();
+    let is_char_text = metalib::primitive_type_info(entry.idx_type)
+        .is_some_and(|type_info| type_info.xml_name == "char")
+        && value.parse::<i8>().is_err();
+
+    let mut elements: Vec<String> = if !has_default {
+        Vec::new()
+    } else if is_char_text {
+        value.bytes().map(|byte| (byte as i8).to_string()).collect()
+    } else if entry.type_ == MetaPrimativeType::DATETIME {
+        // Each datetime is two words.
+        let words: Vec<&str> = value.split_whitespace().collect();
+        words
+            .chunks(2)
+            .map(|pair| python_value(entry.type_, &pair.join(" ")))
+            .collect()
+    } else {
+        value
+            .split_whitespace()
+            .map(|element| python_value(entry.type_, element))
+            .collect()
+    };
+
+    if elements.len() == 1 && !is_char_text {
+        return format!("[{}] * {count}", elements[0]);
+    }
+    elements.truncate(count);
+    let padding = count - elements.len();
+    match (elements.is_empty(), padding) {
+        (true, _) => format!("[{}] * {count}", python_zero(entry.type_)),
+        (false, 0) => format!("[{}]", elements.join(", ")),
+        (false, _) => format!(
+            "[{}] + [{}] * {padding}",
+            elements.join(", "),
+            python_zero(entry.type_)
+        ),
+    }
+}
+
+fn emit_python_field(
+    out: &mut String,
+    metalib: &Metalib,
+    meta: &TDRMeta,
+    entry: &TDRMetaEntry,
+) -> Result<()> {
+    let field_name = python_ident(&member_name(meta, entry));
+    let element_type = if is_meta_type(entry.type_) {
+        python_ident(referenced_meta_name(metalib, entry)?)
+    } else {
+        python_primitive_type(entry.type_).to_string()
+    };
+    let is_list = entry.count > 1 && !is_string_type(entry.type_);
+
+    let (annotation, default) = if is_union(meta) {
+        // Only one member of a union is ever set.
+        let annotation = if is_list {
+            format!("Optional[List[{element_type}]]")
+        } else {
+            format!("Optional[{element_type}]")
+        };
+        (annotation, "None".to_string())
+    } else if is_meta_type(entry.type_) {
+        // Nested dataclasses are looked up lazily since they may be defined further down.
+        let default = if is_list {
+            format!(
+                "field(default_factory=lambda: [{element_type}() for _ in range({})])",
+                entry.count
+            )
+        } else {
+            format!("field(default_factory=lambda: {element_type}())")
+        };
+        let annotation = if is_list {
+            format!("List[{element_type}]")
+        } else {
+            element_type
+        };
+        (annotation, default)
+    } else if is_list {
+        let default = format!(
+            "field(default_factory=lambda: {})",
+            python_list_default(entry)
+        );
+        (format!("List[{element_type}]"), default)
+    } else {
+        (element_type, python_scalar_default(entry))
+    };
+
+    let mut line = format!("    {field_name}: {annotation} = {default}");
+    if !entry.desc.is_empty() {
+        write!(&mut line, "  # {}", entry.desc.replace('\n', " "))?;
+    }
+    writeln!(out, "{line}")?;
+
+    Ok(())
+}
+
+fn emit_python_meta(out: &mut String, metalib: &Metalib, meta: &TDRMeta) -> Result<()> {
+    writeln!(out, "@dataclass")?;
+    writeln!(out, "class {}:", python_ident(&meta.name))?;
+    if !meta.desc.is_empty() {
+        writeln!(out, "    {}", python_str_literal(&meta.desc))?;
+    }
+
+    let mut has_fields = false;
+    for entry in meta.entries.iter() {
+        if entry.is_zero_size() {
+            writeln!(
+                out,
+                "    # {}: zero-size {:?} entry",
+                entry.name, entry.type_
+            )?;
+            continue;
+        }
+        emit_python_field(out, metalib, meta, entry)?;
+        has_fields = true;
+    }
+
+    if !has_fields && meta.desc.is_empty() {
+        writeln!(out, "    pass")?;
+    }
+
+    Ok(())
+}
+
+/// Generates a Python module with one dataclass per meta.
+///
+/// Macros outside of any group become module-level constants, and each macrogroup
+/// becomes an `IntEnum`.
+pub fn emit_python(metalib: &Metalib) -> Result<String> {
+    let mut out = String::new();
+    writeln!(
+        &mut out,
+        "# Generated by mldec from metalib `{}` (version {}).",
+        metalib.header.name, metalib.header.version
+    )?;
+    writeln!(&mut out, "from __future__ import annotations")?;
+    writeln!(&mut out)?;
+    writeln!(&mut out, "from dataclasses import dataclass, field")?;
+    writeln!(&mut out, "from enum import IntEnum")?;
+    writeln!(&mut out, "from typing import List, Optional")?;
+
+    let mut wrote_macro = false;
+    for tdr_macro in metalib.macros.iter() {
+        if metalib.is_macro_in_group(tdr_macro)? {
+            continue;
+        }
+        if !wrote_macro {
+            writeln!(&mut out)?;
+            wrote_macro = true;
+        }
+        writeln!(
+            &mut out,
+            "{} = {}",
+            python_ident(&tdr_macro.name),
+            tdr_macro.value
+        )?;
+    }
+
+    for macrogroup in metalib.macrogroups.iter() {
+        writeln!(&mut out)?;
+        writeln!(&mut out)?;
+        writeln!(
+            &mut out,
+            "class {}(IntEnum):",
+            python_ident(&macrogroup.name)
+        )?;
+        if !macrogroup.desc.is_empty() {
+            writeln!(&mut out, "    {}", python_str_literal(&macrogroup.desc))?;
+        }
+        for tdr_macro in metalib.macrogroup_macros(macrogroup) {
+            writeln!(
+                &mut out,
+                "    {} = {}",
+                python_ident(&tdr_macro.name),
+                tdr_macro.value
+            )?;
+        }
+        if macrogroup.value_idx_map.is_empty() && macrogroup.desc.is_empty() {
+            writeln!(&mut out, "    pass")?;
+        }
+    }
+
+    for meta in metalib.metas.iter() {
+        writeln!(&mut out)?;
+        writeln!(&mut out)?;
+        emit_python_meta(&mut out, metalib, meta)?;
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+    use crate::test_support::{compile, temp_path, FULL_XML, TYPES_XML};
+
+    /// Checks `source` with Python's own parser, if there's a `python3` to run.
+    fn assert_compiles(name: &str, source: &str) {
+        let path = temp_path(name);
+        std::fs::write(&path, source).unwrap();
+        let output = Command::new("python3")
+            .args(["-m", "py_compile"])
+            .arg(&path)
+            .output();
+        std::fs::remove_file(&path).unwrap();
+        match output {
+            Ok(output) => assert!(
+                output.status.success(),
+                "{}\n{source}",
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            Err(err) => eprintln!("Skipping the py_compile check, python3 isn't runnable: {err}"),
+        }
+    }
+
+    #[test]
+    fn generated_python_compiles() {
+        let source = emit_python(&compile(TYPES_XML)).unwrap();
+        assert_compiles("types.py", &source);
+    }
+
+    #[test]
+    fn structs_unions_and_macrogroups() {
+        let xml = FULL_XML.replace(
+            "</metalib>",
+            r#"<struct name="class" version="1"><entry name="def" type="int"/></struct>
+            </metalib>"#,
+        );
+        let source = emit_python(&compile(&xml)).unwrap();
+        assert!(source.contains("class Kind(IntEnum):"));
+        assert!(source.contains("MAX_ITEMS = 8"));
+        assert!(source.contains("head: Header = field(default_factory=lambda: Header())"));
+        assert_compiles("full.py", &source);
+    }
+
+    #[test]
+    fn defaults_are_python_literals() {
+        let source = emit_python(&compile(FULL_XML)).unwrap();
+        assert!(
+            source.contains("    addr: str = \"127.0.0.1\"\n"),
+            "{source}"
+        );
+        assert!(source.contains("    gold: float = 2.25\n"), "{source}");
+        assert!(
+            source.contains("    flags: List[int] = field(default_factory=lambda: [7] * 4)\n"),
+            "{source}"
+        );
+        assert!(
+            source.contains(
+                "    tag: List[int] = field(default_factory=lambda: [97, 98, 99] + [0] * 5)\n"
+            ),
+            "{source}"
+        );
+        assert_compiles("full_defaults.py", &source);
+
+        let xml = r#"<metalib tagsetversion="1" name="lists" version="1">
+            <struct name="Lists" version="1">
+                <entry name="ints" type="int" count="3" default="1 2 3"/>
+                <entry name="short" type="int" count="4" default="1 2"/>
+                <entry name="ips" type="ip" count="2" default="10.0.0.1 10.0.0.2"/>
+                <entry name="dates" type="date" default="2024-02-29"/>
+                <entry name="stamps" type="datetime" count="2"
+                    default="1999-12-31 23:59:59 2000-01-01 00:00:00"/>
+                <entry name="name" type="string" size="16" default="a &quot;b&quot;"/>
+                <entry name="when" type="time"/>
+            </struct>
+        </metalib>"#;
+        let source = emit_python(&compile(xml)).unwrap();
+        for line in [
+            "    ints: List[int] = field(default_factory=lambda: [1, 2, 3])",
+            "    short: List[int] = field(default_factory=lambda: [1, 2] + [0] * 2)",
+            r#"    ips: List[str] = field(default_factory=lambda: ["10.0.0.1", "10.0.0.2"])"#,
+            r#"    dates: str = "2024-02-29""#,
+            r#"    stamps: List[str] = field(default_factory=lambda: ["1999-12-31 23:59:59", "2000-01-01 00:00:00"])"#,
+            r#"    name: str = "a \"b\"""#,
+            r#"    when: str = "00:00:00""#,
+        ] {
+            assert!(source.contains(&format!("{line}\n")), "{line}\n{source}");
+        }
+        assert_compiles("lists.py", &source);
+    }
+}
@@ -0,0 +1,246 @@
+use anyhow::Result;
+use std::fmt::Write as _;
+
+use super::{
+    count_macro_indices, entry_count_expr, is_meta_type, is_string_type, is_union, member_name,
+    referenced_meta_name, sanitize_ident, zero_size_note,
+};
+use crate::metalib::{MetaPrimativeType, Metalib, TDRMeta, TDRMetaEntry};
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "box", "break", "const", "continue", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "macro", "match", "mod", "move",
+    "mut", "priv", "pub", "ref", "return", "static", "struct", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield", "abstract",
+    "become", "do", "final", "override", "gen",
+];
+
+/// These can't be used as raw identifiers.
+const RUST_RESERVED: &[&str] = &["self", "Self", "super", "crate", "_"];
+
+fn rust_ident(name: &str) -> String {
+    let ident = sanitize_ident(name);
+    if RUST_RESERVED.contains(&ident.as_str()) {
+        format!("{ident}_")
+    } else if RUST_KEYWORDS.contains(&ident.as_str()) {
+        format!("r#{ident}")
+    } else {
+        ident
+    }
+}
+
+fn rust_primitive_type(type_: MetaPrimativeType) -> &'static str {
+    match type_ {
+        MetaPrimativeType::CHAR => "i8",
+        MetaPrimativeType::UCHAR | MetaPrimativeType::BYTE => "u8",
+        MetaPrimativeType::SHORT => "i16",
+        MetaPrimativeType::USHORT | MetaPrimativeType::WCHAR => "u16",
+        MetaPrimativeType::INT | MetaPrimativeType::LONG => "i32",
+        MetaPrimativeType::UINT | MetaPrimativeType::ULONG => "u32",
+        MetaPrimativeType::LONGLONG => "i64",
+        MetaPrimativeType::ULONGLONG => "u64",
+        // 4 byte TDR-specific types, kept as their raw representation.
+        MetaPrimativeType::DATE
+        | MetaPrimativeType::TIME
+        | MetaPrimativeType::MONEY
+        | MetaPrimativeType::IP => "u32",
+        MetaPrimativeType::DATETIME => "u64",
+        MetaPrimativeType::FLOAT => "f32",
+        MetaPrimativeType::DOUBLE => "f64",
+        MetaPrimativeType::STRING => "String",
+        MetaPrimativeType::WSTRING => "Vec<u16>",
+        MetaPrimativeType::VOID | MetaPrimativeType::UNKNOWN => "()",
+        MetaPrimativeType::UNION | MetaPrimativeType::STRUCT => unreachable!(),
+    }
+}
+
+fn rust_entry_type(metalib: &Metalib, entry: &TDRMetaEntry) -> Result<String> {
+    let type_name = if is_meta_type(entry.type_) {
+        rust_ident(referenced_meta_name(metalib, entry)?)
+    } else {
+        rust_primitive_type(entry.type_).to_string()
+    };
+
+    // Strings carry their buffer size in `count`, so they aren't emitted as arrays.
+    if entry.count > 1 && !is_string_type(entry.type_) {
+        let count = entry_count_expr(metalib, entry)?;
+        Ok(format!("[{type_name}; {}]", rust_count_expr(&count)))
+    } else {
+        Ok(type_name)
+    }
+}
+
+/// Macro names are emitted as constants, literal counts stay as-is.
+fn rust_count_expr(count: &str) -> String {
+    if count.parse::<i64>().is_ok() {
+        count.to_string()
+    } else {
+        rust_ident(count)
+    }
+}
+
+fn write_doc_comment(out: &mut String, indent: &str, text: &str) -> Result<()> {
+    for line in text.lines() {
+        writeln!(out, "{indent}/// {line}")?;
+    }
+    Ok(())
+}
+
+fn write_zero_size_comment(out: &mut String, entry: &TDRMetaEntry) -> Result<()> {
+    writeln!(
+        out,
+        "    // {} at net offset {}",
+        zero_size_note(entry),
+        entry.n_off
+    )?;
+    Ok(())
+}
+
+fn emit_rust_meta(out: &mut String, metalib: &Metalib, meta: &TDRMeta) -> Result<()> {
+    if !meta.desc.is_empty() {
+        write_doc_comment(out, "", &meta.desc)?;
+    }
+
+    let type_name = rust_ident(&meta.name);
+    if is_union(meta) {
+        writeln!(out, "#[derive(Debug, Clone)]")?;
+        writeln!(out, "pub enum {type_name} {{")?;
+        for entry in meta.entries.iter() {
+            if entry.is_zero_size() {
+                write_zero_size_comment(out, entry)?;
+                continue;
+            }
+            if !entry.desc.is_empty() {
+                write_doc_comment(out, "    ", &entry.desc)?;
+            }
+            writeln!(
+                out,
+                "    {}({}),",
+                rust_ident(&member_name(meta, entry)),
+                rust_entry_type(metalib, entry)?
+            )?;
+        }
+    } else {
+        writeln!(out, "#[derive(Debug, Clone)]")?;
+        writeln!(out, "pub struct {type_name} {{")?;
+        for entry in meta.entries.iter() {
+            if entry.is_zero_size() {
+                write_zero_size_comment(out, entry)?;
+                continue;
+            }
+            if !entry.desc.is_empty() {
+                write_doc_comment(out, "    ", &entry.desc)?;
+            }
+            writeln!(
+                out,
+                "    pub {}: {},",
+                rust_ident(&member_name(meta, entry)),
+                rust_entry_type(metalib, entry)?
+            )?;
+        }
+    }
+    writeln!(out, "}}")?;
+
+    Ok(())
+}
+
+/// Generates Rust type definitions for every meta in the metalib.
+///
+/// Structs become `struct`s, unions become `enum`s with one variant per member,
+/// and macros used as array counts become `pub const` items.
+pub fn emit_rust(metalib: &Metalib) -> Result<String> {
+    let mut out = String::new();
+    writeln!(
+        &mut out,
+        "// Generated by mldec from metalib `{}` (version {}).",
+        metalib.header.name, metalib.header.version
+    )?;
+    writeln!(
+        &mut out,
+        "#![allow(non_camel_case_types, non_snake_case, non_upper_case_globals, dead_code)]"
+    )?;
+
+    let count_macros = count_macro_indices(metalib);
+    if !count_macros.is_empty() {
+        writeln!(&mut out)?;
+    }
+    for idx in count_macros {
+        let tdr_macro = &metalib.macros[idx];
+        if !tdr_macro.desc.is_empty() {
+            write_doc_comment(&mut out, "", &tdr_macro.desc)?;
+        }
+        // A negative count can't size an array (such entries aren't emitted as one), but
+        // the macro is still a valid constant.
+        let type_name = if tdr_macro.value < 0 { "i64" } else { "usize" };
+        writeln!(
+            &mut out,
+            "pub const {}: {type_name} = {};",
+            rust_ident(&tdr_macro.name),
+            tdr_macro.value
+        )?;
+    }
+
+    for meta in metalib.metas.iter() {
+        writeln!(&mut out)?;
+        emit_rust_meta(&mut out, metalib, meta)?;
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{compile, FULL_XML};
+
+    fn parse_rust(source: &str) -> syn::File {
+        syn::parse_file(source).unwrap_or_else(|err| panic!("{err}:\n{source}"))
+    }
+
+    #[test]
+    fn generated_rust_parses() {
+        let source = emit_rust(&compile(FULL_XML)).unwrap();
+        let file = parse_rust(&source);
+        let names: Vec<String> = file
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                syn::Item::Struct(item) => Some(item.ident.to_string()),
+                syn::Item::Enum(item) => Some(item.ident.to_string()),
+                syn::Item::Const(item) => Some(item.ident.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            names,
+            ["MAX_ITEMS", "Header", "Body", "Packet", "Sorted", "Account"]
+        );
+    }
+
+    #[test]
+    fn negative_count_macro_is_signed() {
+        let mut metalib = compile(FULL_XML);
+        let idx = metalib
+            .macros
+            .iter()
+            .position(|m| m.name == "MAX_ITEMS")
+            .unwrap();
+        metalib.macros[idx].value = -1;
+
+        let source = emit_rust(&metalib).unwrap();
+        assert!(source.contains("pub const MAX_ITEMS: i64 = -1;"));
+        parse_rust(&source);
+    }
+
+    #[test]
+    fn keyword_and_reserved_names_parse() {
+        let xml = r#"<metalib tagsetversion="1" name="kw" version="1">
+            <struct name="Self" version="1">
+                <entry name="type" type="int"/>
+                <entry name="self" type="int"/>
+                <entry name="9lives" type="int"/>
+            </struct>
+        </metalib>"#;
+        parse_rust(&emit_rust(&compile(xml)).unwrap());
+    }
+}
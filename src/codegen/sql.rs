@@ -0,0 +1,264 @@
+use anyhow::{anyhow, Result};
+use std::fmt::Write as _;
+
+use super::{is_union, sanitize_ident};
+use crate::metalib::{MetaPrimativeType, Metalib, TDRMeta, TDRMetaEntry, TDRMetaEntryDBFlags};
+
+/// Limit for flattening nested structs into columns.
+const MAX_NESTING_DEPTH: usize = 64;
+
+/// Longest string still stored as VARCHAR instead of TEXT.
+const MAX_VARCHAR_SIZE: i32 = 65535;
+
+struct SqlColumn {
+    name: String,
+    sql_type: String,
+    db_flag: TDRMetaEntryDBFlags,
+    desc: String,
+}
+
+fn sql_primitive_type(entry: &TDRMetaEntry) -> String {
+    match entry.type_ {
+        MetaPrimativeType::CHAR => "TINYINT".to_string(),
+        MetaPrimativeType::UCHAR | MetaPrimativeType::BYTE => "TINYINT UNSIGNED".to_string(),
+        MetaPrimativeType::SHORT => "SMALLINT".to_string(),
+        MetaPrimativeType::USHORT | MetaPrimativeType::WCHAR => "SMALLINT UNSIGNED".to_string(),
+        MetaPrimativeType::INT | MetaPrimativeType::LONG | MetaPrimativeType::MONEY => {
+            "INT".to_string()
+        }
+        MetaPrimativeType::UINT | MetaPrimativeType::ULONG | MetaPrimativeType::IP => {
+            "INT UNSIGNED".to_string()
+        }
+        MetaPrimativeType::LONGLONG => "BIGINT".to_string(),
+        MetaPrimativeType::ULONGLONG => "BIGINT UNSIGNED".to_string(),
+        MetaPrimativeType::FLOAT => "FLOAT".to_string(),
+        MetaPrimativeType::DOUBLE => "DOUBLE".to_string(),
+        MetaPrimativeType::DATE => "DATE".to_string(),
+        MetaPrimativeType::TIME => "TIME".to_string(),
+        MetaPrimativeType::DATETIME => "DATETIME".to_string(),
+        // `count` is the length in characters (1 for a `size`d string); the column holds
+        // the whole buffer, `h_unit_size` bytes.
+        MetaPrimativeType::STRING => {
+            if entry.h_unit_size > 0 && entry.h_unit_size <= MAX_VARCHAR_SIZE {
+                format!("VARCHAR({})", entry.h_unit_size)
+            } else {
+                "TEXT".to_string()
+            }
+        }
+        MetaPrimativeType::WSTRING => {
+            if entry.h_unit_size > 0 && entry.h_unit_size <= MAX_VARCHAR_SIZE {
+                format!("VARBINARY({})", entry.h_unit_size)
+            } else {
+                "BLOB".to_string()
+            }
+        }
+        MetaPrimativeType::VOID | MetaPrimativeType::UNKNOWN => "BLOB".to_string(),
+        MetaPrimativeType::UNION | MetaPrimativeType::STRUCT => unreachable!(),
+    }
+}
+
+/// Collects the columns of a meta, flattening single nested structs into
+/// `parent_child` columns the same way the tdr tooling does.
+fn collect_columns(
+    metalib: &Metalib,
+    meta: &TDRMeta,
+    prefix: &str,
+    depth: usize,
+    columns: &mut Vec<SqlColumn>,
+) -> Result<()> {
+    if depth > MAX_NESTING_DEPTH {
+        return Err(anyhow!(
+            "Exceeded max nesting depth while flattening meta `{}`",
+            meta.name
+        ));
+    }
+
+    for entry in meta.entries.iter() {
+        if entry.is_zero_size() {
+            continue;
+        }
+
+        let name = format!("{prefix}{}", entry.name);
+        if entry.type_ == MetaPrimativeType::STRUCT && entry.count <= 1 {
+            let referenced_meta = metalib.get_meta_by_offset(entry.ptr_meta)?;
+            collect_columns(
+                metalib,
+                referenced_meta,
+                &format!("{name}_"),
+                depth + 1,
+                columns,
+            )?;
+            continue;
+        }
+
+        // Unions and arrays don't map onto a single column, so they're stored as raw bytes.
+        let is_blob = entry.type_ == MetaPrimativeType::UNION
+            || entry.type_ == MetaPrimativeType::STRUCT
+            || (entry.count > 1
+                && entry.type_ != MetaPrimativeType::STRING
+                && entry.type_ != MetaPrimativeType::WSTRING);
+        let sql_type = if is_blob {
+            "BLOB".to_string()
+        } else {
+            sql_primitive_type(entry)
+        };
+
+        columns.push(SqlColumn {
+            name,
+            sql_type,
+            db_flag: entry.db_flag,
+            desc: entry.desc.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+fn sql_string_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "''"))
+}
+
+fn emit_sql_table(out: &mut String, metalib: &Metalib, meta: &TDRMeta) -> Result<()> {
+    let mut columns = Vec::new();
+    collect_columns(metalib, meta, "", 0, &mut columns)?;
+
+    // MySQL has no split tables, so the rule is only noted for whoever creates them.
+    if meta.split_table_rule_id != 0 {
+        write!(out, "-- Split table rule {}", meta.split_table_rule_id)?;
+        if meta.split_table_factor > 0 {
+            write!(out, ", {} tables", meta.split_table_factor)?;
+        }
+        if let Some(key) = meta.split_table_key.resolve(metalib, meta)? {
+            write!(out, ", by `{}`", key.path)?;
+        }
+        writeln!(out, ".")?;
+    }
+    writeln!(out, "CREATE TABLE `{}` (", sanitize_ident(&meta.name))?;
+    let mut lines = Vec::new();
+    for column in columns.iter() {
+        let mut line = format!("  `{}` {}", sanitize_ident(&column.name), column.sql_type);
+        if column.db_flag.contains(TDRMetaEntryDBFlags::NOT_NULL) {
+            line.push_str(" NOT NULL");
+        }
+        if column.db_flag.contains(TDRMetaEntryDBFlags::AUTO_INCREMENT) {
+            line.push_str(" AUTO_INCREMENT");
+        }
+        if column.db_flag.contains(TDRMetaEntryDBFlags::UNIQUE) {
+            line.push_str(" UNIQUE");
+        }
+        if !column.desc.is_empty() {
+            write!(&mut line, " COMMENT {}", sql_string_literal(&column.desc))?;
+        }
+        lines.push(line);
+    }
+
+    let mut key_columns = Vec::new();
+    for key in meta.primary_key.iter() {
+        // Columns of nested structs are flattened with `_`, paths join them with `.`.
+        if let Some(key) = key.resolve(metalib, meta)? {
+            key_columns.push(format!("`{}`", sanitize_ident(&key.path.replace('.', "_"))));
+        }
+    }
+    // Without a key table, fall back on the entries flagged as part of the key.
+    if key_columns.is_empty() && meta.primary_key_member_num > 0 {
+        key_columns = columns
+            .iter()
+            .filter(|column| column.db_flag.contains(TDRMetaEntryDBFlags::PRIMARY_KEY))
+            .map(|column| format!("`{}`", sanitize_ident(&column.name)))
+            .collect();
+    }
+    if !key_columns.is_empty() {
+        lines.push(format!("  PRIMARY KEY ({})", key_columns.join(", ")));
+    }
+
+    writeln!(out, "{}", lines.join(",\n"))?;
+    write!(out, ")")?;
+    if !meta.desc.is_empty() {
+        write!(out, " COMMENT={}", sql_string_literal(&meta.desc))?;
+    }
+    writeln!(out, ";")?;
+
+    Ok(())
+}
+
+/// Generates MySQL `CREATE TABLE` statements for every struct meta.
+///
+/// Column constraints come from each entry's `db_flag`, and nested structs are flattened
+/// into underscore-joined columns.
+pub fn emit_sql(metalib: &Metalib) -> Result<String> {
+    let mut out = String::new();
+    writeln!(
+        &mut out,
+        "-- Generated by mldec from metalib `{}` (version {}).",
+        metalib.header.name, metalib.header.version
+    )?;
+
+    for meta in metalib.metas.iter().filter(|meta| !is_union(meta)) {
+        writeln!(&mut out)?;
+        emit_sql_table(&mut out, metalib, meta)?;
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{parse, DB_XML, FULL_XML, TYPES_XML};
+
+    fn table<'a>(sql: &'a str, name: &str) -> &'a str {
+        let start = sql.find(&format!("CREATE TABLE `{name}`")).unwrap();
+        let end = start + sql[start..].find(";\n").unwrap();
+        &sql[start..end]
+    }
+
+    #[test]
+    fn db_flags_become_constraints() {
+        let xml = r#"<metalib tagsetversion="1" name="db" version="1">
+            <struct name="User" version="1" primarykey="id" desc="it's">
+                <entry name="id" type="uint" autoincrement="true" notnull="true"/>
+                <entry name="mail" type="string" size="64" unique="true" desc="a 'mail'"/>
+                <entry name="plain" type="int"/>
+            </struct>
+        </metalib>"#;
+        let sql = emit_sql(&parse(xml)).unwrap();
+        assert_eq!(
+            table(&sql, "User"),
+            "CREATE TABLE `User` (
+  `id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+  `mail` VARCHAR(64) UNIQUE COMMENT 'a ''mail''',
+  `plain` INT,
+  PRIMARY KEY (`id`)
+) COMMENT='it''s'"
+        );
+    }
+
+    #[test]
+    fn keys_split_tables_and_flattening() {
+        let sql = emit_sql(&parse(FULL_XML)).unwrap();
+        let account = table(&sql, "Account");
+        assert!(account.contains("  `uin` BIGINT UNSIGNED NOT NULL,\n"));
+        assert!(account.contains("  PRIMARY KEY (`uin`, `zone`)\n"));
+        assert!(account.contains("  `lastpacket_name` VARCHAR(16) NOT NULL UNIQUE,\n"));
+        assert!(sql.contains("-- Split table rule 1, 4 tables, by `uin`.\nCREATE TABLE `Account`"));
+        // Unions aren't tables.
+        assert!(!sql.contains("CREATE TABLE `Body`"));
+    }
+
+    #[test]
+    fn composite_primary_key() {
+        let sql = emit_sql(&parse(DB_XML)).unwrap();
+        let friend = table(&sql, "Friend");
+        assert!(friend.contains("  PRIMARY KEY (`owner`, `friend`)\n"), "{friend}");
+        let split = "-- Split table rule 1, 16 tables, by `owner`.\nCREATE TABLE `Friend`";
+        assert!(sql.contains(split), "{sql}");
+    }
+
+    #[test]
+    fn string_widths_are_in_bytes() {
+        let sql = emit_sql(&parse(TYPES_XML)).unwrap();
+        // `size="8"`: eight bytes, and eight two-byte units.
+        assert!(sql.contains("  `str` VARCHAR(8),\n"), "{sql}");
+        assert!(sql.contains("  `w` VARBINARY(16),\n"), "{sql}");
+    }
+}
@@ -0,0 +1,344 @@
+//! Compiling TDR XML back into a [`Metalib`] (`compile` subcommand), the inverse of [`crate::xml`].
+//!
+//! Every tag and attribute the XML dumper writes is understood. Offsets and sizes are laid
+//! out by [`MetalibBuilder`], so XML dumped from a compiled metalib compiles back to one that
+//! dumps the same. Attributes the dumper can't write either are rejected rather than
+//! dropped.
+
+use anyhow::{anyhow, bail, Context, Result};
+use encoding::all::GBK;
+use encoding::{DecoderTrap, Encoding};
+use roxmltree::{Document, Node};
+
+use crate::builder::{EntryBuilder, MetaBuilder, MetalibBuilder, Value};
+use crate::find::parse_db_flag;
+use crate::metalib::{Metalib, TDRMetaFlags};
+
+/// Decodes an XML document, as GBK if its declaration says so and as UTF-8 otherwise.
+pub fn decode_xml(data: &[u8]) -> Result<String> {
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    let declaration = match data.starts_with(b"<?xml") {
+        true => {
+            let end = data.windows(2).position(|w| w == b"?>").unwrap_or(0);
+            String::from_utf8_lossy(&data[..end]).to_ascii_lowercase()
+        }
+        false => String::new(),
+    };
+    let is_gbk = ["gbk", "gb2312", "gb18030"]
+        .iter()
+        .any(|name| declaration.contains(&format!("encoding=\"{name}\"")));
+    if is_gbk {
+        GBK.decode(data, DecoderTrap::Strict)
+            .map_err(|err| anyhow!("Failed to decode the XML as GBK: {err}"))
+    } else {
+        String::from_utf8(data.to_vec()).context("Failed to decode the XML as UTF-8")
+    }
+}
+
+/// Compiles a TDR XML document into a metalib.
+pub fn compile_metalib_xml(xml: &str) -> Result<Metalib> {
+    let doc = Document::parse(xml).context("Failed to parse the XML")?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "metalib" {
+        bail!(
+            "Expected a <metalib> root tag, found <{}>",
+            root.tag_name().name()
+        );
+    }
+
+    let mut builder = MetalibBuilder::new(required(root, "name")?);
+    for attr in root.attributes() {
+        builder = match attr.name() {
+            "name" => builder,
+            "tagsetversion" => builder.tag_set_version(number(root, &attr)?),
+            "version" => builder.version(number(root, &attr)?),
+            "id" => builder.id(number(root, &attr)?),
+            _ => return Err(unsupported(root, attr.name())),
+        };
+    }
+
+    for node in root.children().filter(Node::is_element) {
+        builder = match node.tag_name().name() {
+            "macro" => {
+                let (name, value, desc) = compile_macro(node)?;
+                builder.macro_with_desc(name, value, desc)
+            }
+            "macrosgroup" => compile_macrogroup(builder, node)?,
+            "struct" => builder.meta(compile_meta(node, MetaBuilder::struct_)?),
+            "union" => builder.meta(compile_meta(node, MetaBuilder::union)?),
+            tag => bail!("Unknown tag <{tag}> at line {}", line(node)),
+        };
+    }
+    builder.build()
+}
+
+fn compile_macro<'a>(node: Node<'a, '_>) -> Result<(&'a str, i32, &'a str)> {
+    let mut value = None;
+    for attr in node.attributes() {
+        match attr.name() {
+            "name" | "desc" => {}
+            "value" => value = Some(number(node, &attr)?),
+            _ => return Err(unsupported(node, attr.name())),
+        }
+    }
+    let value = value.ok_or_else(|| missing(node, "value"))?;
+    Ok((
+        required(node, "name")?,
+        value,
+        node.attribute("desc").unwrap_or(""),
+    ))
+}
+
+/// Adds the macros of a `macrosgroup` tag, then the group itself.
+fn compile_macrogroup(mut builder: MetalibBuilder, node: Node) -> Result<MetalibBuilder> {
+    for attr in node.attributes() {
+        if !matches!(attr.name(), "name" | "desc") {
+            return Err(unsupported(node, attr.name()));
+        }
+    }
+
+    let mut members = Vec::new();
+    for child in node.children().filter(Node::is_element) {
+        if child.tag_name().name() != "macro" {
+            bail!(
+                "Unexpected <{}> in <macrosgroup> at line {}",
+                child.tag_name().name(),
+                line(child)
+            );
+        }
+        let (name, value, desc) = compile_macro(child)?;
+        builder = builder.macro_with_desc(name, value, desc);
+        members.push(name);
+    }
+    Ok(builder.macrogroup_with_desc(
+        required(node, "name")?,
+        &members,
+        node.attribute("desc").unwrap_or(""),
+    ))
+}
+
+fn compile_meta(node: Node, new: fn(&str) -> MetaBuilder) -> Result<MetaBuilder> {
+    let mut meta = new(required(node, "name")?);
+    for attr in node.attributes() {
+        let value = attr.value();
+        meta = match attr.name() {
+            "name" => meta,
+            "version" => meta.version(Value::parse(value)),
+            "id" => meta.id(Value::parse(value)),
+            "cname" => meta.cname(value),
+            "desc" => meta.desc(value),
+            "size" => meta.size(Value::parse(value)),
+            "align" => meta.align(number(node, &attr)?),
+            "versionindicator" => meta.version_indicator(value),
+            "sizeinfo" => meta.sizeinfo(value),
+            "sortkey" => meta.sort_key(value),
+            "primarykey" => meta.primary_key(value.split(',').map(str::trim)),
+            "splittablefactor" => meta.split_table_factor(Value::parse(value)),
+            "splittablekey" => meta.split_table_key(value),
+            "splittablerule" => meta.split_table_rule(number(node, &attr)?),
+            "dependontable" => meta.depend_on_table(value),
+            "uniqueentryname" => match value {
+                "true" => meta.flags(TDRMetaFlags::NEED_PREFIX_FOR_UNIQUENAME),
+                "false" => meta,
+                _ => bail!(
+                    "Expected true or false for `uniqueentryname`, found `{value}` at line {}",
+                    line(node)
+                ),
+            },
+            _ => return Err(unsupported(node, attr.name())),
+        };
+    }
+
+    for child in node.children().filter(Node::is_element) {
+        if child.tag_name().name() != "entry" {
+            bail!(
+                "Unexpected <{}> in <{}> at line {}",
+                child.tag_name().name(),
+                node.tag_name().name(),
+                line(child)
+            );
+        }
+        meta = meta.entry(compile_entry(child)?);
+    }
+    Ok(meta)
+}
+
+fn compile_entry(node: Node) -> Result<EntryBuilder> {
+    let mut entry = EntryBuilder::new(required(node, "name")?, required(node, "type")?);
+    let mut id_range = (None, None);
+    for attr in node.attributes() {
+        let value = attr.value();
+        entry = match attr.name() {
+            "name" | "type" => entry,
+            "count" => entry.count(Value::parse(value)),
+            "version" => entry.version(Value::parse(value)),
+            "id" => entry.id(Value::parse(value)),
+            "size" => entry.size(Value::parse(value)),
+            "cname" => entry.cname(value),
+            "desc" => entry.desc(value),
+            "customattr" => entry.custom_attr(value),
+            "default" => entry.default(value),
+            "refer" => entry.refer(value),
+            "select" => entry.select(value),
+            "sizeinfo" => entry.sizeinfo(value),
+            "bindmacrosgroup" => entry.macrogroup(value),
+            "sortMethod" => match value {
+                "asc" => entry.order(1),
+                "desc" => entry.order(2),
+                _ => bail!("Unknown sortMethod `{value}` at line {}", line(node)),
+            },
+            "io" => match value {
+                "noinput" => entry.io(1),
+                "nooutput" => entry.io(2),
+                "noio" => entry.io(3),
+                _ => bail!("Unknown io `{value}` at line {}", line(node)),
+            },
+            "minid" => {
+                id_range.0 = Some(Value::parse(value));
+                entry
+            }
+            "maxid" => {
+                id_range.1 = Some(Value::parse(value));
+                entry
+            }
+            "unique" | "notnull" | "extendtotable" | "autoincrement" => match value {
+                "true" => entry.db_flags(parse_db_flag(attr.name())?),
+                "false" => entry,
+                _ => bail!(
+                    "Expected true or false for `{}`, found `{value}` at line {}",
+                    attr.name(),
+                    line(node)
+                ),
+            },
+            _ => return Err(unsupported(node, attr.name())),
+        };
+    }
+
+    match id_range {
+        (Some(min), Some(max)) => Ok(entry.id_range(min, max)),
+        (None, None) => Ok(entry),
+        _ => bail!(
+            "minid and maxid must be given together at line {}",
+            line(node)
+        ),
+    }
+}
+
+/// Line of the tag, for error messages.
+fn line(node: Node) -> u32 {
+    node.document().text_pos_at(node.range().start).row
+}
+
+fn required<'a>(node: Node<'a, '_>, name: &str) -> Result<&'a str> {
+    node.attribute(name).ok_or_else(|| missing(node, name))
+}
+
+fn missing(node: Node, name: &str) -> anyhow::Error {
+    anyhow!(
+        "<{}> at line {} is missing the `{name}` attribute",
+        node.tag_name().name(),
+        line(node)
+    )
+}
+
+fn unsupported(node: Node, name: &str) -> anyhow::Error {
+    anyhow!(
+        "The `{name}` attribute of <{}> at line {} isn't supported",
+        node.tag_name().name(),
+        line(node)
+    )
+}
+
+/// Parses a numeric attribute, in decimal or `0x` hex.
+fn number<T: TryFrom<i64>>(node: Node, attr: &roxmltree::Attribute) -> Result<T> {
+    let value = attr.value().trim();
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    };
+    parsed.and_then(|n| T::try_from(n).ok()).ok_or_else(|| {
+        anyhow!(
+            "Invalid `{}` value `{value}` at line {}",
+            attr.name(),
+            line(node)
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding::EncoderTrap;
+
+    use super::*;
+    use crate::arch::MetalibArch;
+    use crate::options::ParseOptions;
+    use crate::test_support::{compile_bytes, parse_with, FULL_XML, TYPES_XML};
+    use crate::xml::export_metalib_xml;
+
+    const REFS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes" ?>
+<metalib tagsetversion="1" name="refs" version="1">
+	<struct name="Node" version="1">
+		<entry name="value" type="int" io="noinput"/>
+		<entry name="parent" type="@Node"/>
+		<entry name="children" type="*Node"/>
+		<entry name="weights" type="int16" count="4" sortMethod="desc" io="noio"/>
+	</struct>
+
+</metalib>
+"#;
+
+    #[test]
+    fn binary_xml_round_trip() {
+        for xml in [FULL_XML, TYPES_XML, REFS_XML] {
+            for arch in [MetalibArch::Bits32, MetalibArch::Bits64] {
+                let bytes = compile_bytes(xml, arch);
+                let metalib = parse_with(&bytes, &ParseOptions::default()).unwrap();
+                let dumped = export_metalib_xml(&metalib).unwrap();
+                let recompiled = compile_metalib_xml(&dumped).unwrap();
+                assert_eq!(export_metalib_xml(&recompiled).unwrap(), dumped);
+                assert_eq!(dumped, xml);
+            }
+        }
+    }
+
+    #[test]
+    fn gbk_documents() {
+        let gbk = GBK
+            .encode(&FULL_XML.replacen("UTF-8", "GBK", 1), EncoderTrap::Strict)
+            .unwrap();
+        let xml = decode_xml(&gbk).unwrap();
+        assert!(xml.contains("cname=\"包\""));
+        assert!(decode_xml(FULL_XML.as_bytes()).unwrap() == FULL_XML);
+        // Without a GBK declaration, GBK bytes aren't valid UTF-8.
+        assert!(decode_xml(&GBK.encode("<a>包</a>", EncoderTrap::Strict).unwrap()).is_err());
+    }
+
+    #[test]
+    fn compile_errors() {
+        let error = |body: &str| {
+            let xml =
+                format!(r#"<metalib tagsetversion="1" name="e" version="1">{body}</metalib>"#);
+            format!("{:#}", compile_metalib_xml(&xml).unwrap_err())
+        };
+        let err = error(r#"<struct name="S" version="1"><entry name="a" type="nope"/></struct>"#);
+        assert!(err.contains("nope"), "{err}");
+        let err = error(
+            r#"<struct name="S" version="1"><entry name="a" type="int" count="NO_MACRO"/></struct>"#,
+        );
+        assert!(err.contains("NO_MACRO"), "{err}");
+        let err = error(
+            r#"<struct name="S" version="1"><entry name="a" type="int" io="sometimes"/></struct>"#,
+        );
+        assert!(err.contains("Unknown io `sometimes` at line 1"), "{err}");
+        let err = error("<table/>");
+        assert!(err.contains("Unknown tag <table> at line 1"), "{err}");
+        let err = error(r#"<struct version="1"/>"#);
+        assert!(
+            err.contains("<struct> at line 1 is missing the `name` attribute"),
+            "{err}"
+        );
+    }
+}
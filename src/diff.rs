@@ -0,0 +1,592 @@
+//! Structural comparison of two metalibs, e.g. two builds of a game, by name rather than
+//! by offset.
+//!
+//! Unlike a digest comparison this says what changed: which entries were added, removed,
+//! retyped or moved, which macros changed value and which macrogroups gained or lost
+//! members. Entries are matched by name within their meta, so reordering alone isn't a change
+//! unless it moves offsets.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::export::json_string;
+use crate::metalib::{Metalib, TDRMacroGroup, TDRMeta, TDRMetaEntry, INVALID_METALIB_VALUE};
+
+/// The parts of an entry that are compared.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EntrySummary {
+    pub name: String,
+    /// Type as written in the XML, e.g. `uint16`, `Header` or `*Header`.
+    pub type_name: String,
+    pub count: i32,
+    pub n_off: i32,
+    pub h_off: i32,
+    pub version: i32,
+    pub default: Option<String>,
+}
+
+/// An entry present in both metas, with the names of the fields that differ.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EntryChange {
+    pub old: EntrySummary,
+    pub new: EntrySummary,
+    /// `type`, `count`, `n_off`, `h_off`, `version` and/or `default`.
+    pub fields: Vec<String>,
+}
+
+/// Entry changes of a meta present in both metalibs.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MetaDiff {
+    pub name: String,
+    pub added: Vec<EntrySummary>,
+    pub removed: Vec<EntrySummary>,
+    pub changed: Vec<EntryChange>,
+}
+
+/// A macro present in both metalibs with a different value.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MacroChange {
+    pub name: String,
+    pub old: i32,
+    pub new: i32,
+}
+
+/// Membership changes of a macrogroup present in both metalibs, by macro name.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MacrogroupDiff {
+    pub name: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// Result of [`compare`]. Everything is listed in the order of the metalib it comes from.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MetalibDiff {
+    pub added_metas: Vec<String>,
+    pub removed_metas: Vec<String>,
+    /// Metas in both metalibs whose entries differ.
+    pub changed_metas: Vec<MetaDiff>,
+    pub added_macros: Vec<String>,
+    pub removed_macros: Vec<String>,
+    pub changed_macros: Vec<MacroChange>,
+    pub added_macrogroups: Vec<String>,
+    pub removed_macrogroups: Vec<String>,
+    pub changed_macrogroups: Vec<MacrogroupDiff>,
+}
+
+/// Compares `old` against `new`, matching metas, macros and macrogroups by name.
+pub fn compare(old: &Metalib, new: &Metalib) -> Result<MetalibDiff> {
+    let mut diff = MetalibDiff::default();
+
+    let new_metas: HashMap<&str, &TDRMeta> = new
+        .metas
+        .iter()
+        .map(|meta| (meta.name.as_str(), meta))
+        .collect();
+    for old_meta in old.metas.iter() {
+        match new_metas.get(old_meta.name.as_str()) {
+            Some(new_meta) => {
+                let meta_diff = compare_meta(old, old_meta, new, new_meta)
+                    .with_context(|| format!("Failed to compare meta `{}`", old_meta.name))?;
+                if !meta_diff.is_empty() {
+                    diff.changed_metas.push(meta_diff);
+                }
+            }
+            None => diff.removed_metas.push(old_meta.name.clone()),
+        }
+    }
+    for new_meta in new.metas.iter() {
+        if !old.metas.iter().any(|meta| meta.name == new_meta.name) {
+            diff.added_metas.push(new_meta.name.clone());
+        }
+    }
+
+    let new_macros: HashMap<&str, i32> = new
+        .macros
+        .iter()
+        .map(|macro_| (macro_.name.as_str(), macro_.value))
+        .collect();
+    for old_macro in old.macros.iter() {
+        match new_macros.get(old_macro.name.as_str()) {
+            Some(&value) if value != old_macro.value => diff.changed_macros.push(MacroChange {
+                name: old_macro.name.clone(),
+                old: old_macro.value,
+                new: value,
+            }),
+            Some(_) => {}
+            None => diff.removed_macros.push(old_macro.name.clone()),
+        }
+    }
+    for new_macro in new.macros.iter() {
+        if !old
+            .macros
+            .iter()
+            .any(|macro_| macro_.name == new_macro.name)
+        {
+            diff.added_macros.push(new_macro.name.clone());
+        }
+    }
+
+    for old_group in old.macrogroups.iter() {
+        let Some(new_group) = new.macrogroups.iter().find(|g| g.name == old_group.name) else {
+            diff.removed_macrogroups.push(old_group.name.clone());
+            continue;
+        };
+        let old_members = macrogroup_members(old, old_group);
+        let new_members = macrogroup_members(new, new_group);
+        let group_diff = MacrogroupDiff {
+            name: old_group.name.clone(),
+            added: new_members
+                .iter()
+                .filter(|name| !old_members.contains(name))
+                .cloned()
+                .collect(),
+            removed: old_members
+                .iter()
+                .filter(|name| !new_members.contains(name))
+                .cloned()
+                .collect(),
+        };
+        if !group_diff.added.is_empty() || !group_diff.removed.is_empty() {
+            diff.changed_macrogroups.push(group_diff);
+        }
+    }
+    for new_group in new.macrogroups.iter() {
+        if !old.macrogroups.iter().any(|g| g.name == new_group.name) {
+            diff.added_macrogroups.push(new_group.name.clone());
+        }
+    }
+
+    Ok(diff)
+}
+
+fn compare_meta(
+    old: &Metalib,
+    old_meta: &TDRMeta,
+    new: &Metalib,
+    new_meta: &TDRMeta,
+) -> Result<MetaDiff> {
+    let mut diff = MetaDiff {
+        name: old_meta.name.clone(),
+        added: Vec::new(),
+        removed: Vec::new(),
+        changed: Vec::new(),
+    };
+
+    for old_entry in old_meta.entries.iter() {
+        let old_summary = EntrySummary::new(old, old_entry)?;
+        let Some(new_entry) = new_meta.entries.iter().find(|e| e.name == old_entry.name) else {
+            diff.removed.push(old_summary);
+            continue;
+        };
+        let new_summary = EntrySummary::new(new, new_entry)?;
+        let fields = old_summary.changed_fields(&new_summary);
+        if !fields.is_empty() {
+            diff.changed.push(EntryChange {
+                old: old_summary,
+                new: new_summary,
+                fields: fields.iter().map(|field| field.to_string()).collect(),
+            });
+        }
+    }
+    for new_entry in new_meta.entries.iter() {
+        if !old_meta.entries.iter().any(|e| e.name == new_entry.name) {
+            diff.added.push(EntrySummary::new(new, new_entry)?);
+        }
+    }
+
+    Ok(diff)
+}
+
+fn macrogroup_members(metalib: &Metalib, group: &TDRMacroGroup) -> Vec<String> {
+    metalib
+        .macrogroup_macros(group)
+        .map(|macro_| macro_.name.clone())
+        .collect()
+}
+
+impl EntrySummary {
+    fn new(metalib: &Metalib, entry: &TDRMetaEntry) -> Result<EntrySummary> {
+        Ok(EntrySummary {
+            name: entry.name.clone(),
+            type_name: metalib.entry_type_name(entry)?,
+            count: entry.count,
+            n_off: entry.n_off,
+            h_off: entry.h_off,
+            version: entry.version,
+            default: (entry.ptr_default_val != INVALID_METALIB_VALUE)
+                .then(|| entry.default_value_string.clone()),
+        })
+    }
+
+    fn changed_fields(&self, new: &EntrySummary) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        if self.type_name != new.type_name {
+            fields.push("type");
+        }
+        if self.count != new.count {
+            fields.push("count");
+        }
+        if self.n_off != new.n_off {
+            fields.push("n_off");
+        }
+        if self.h_off != new.h_off {
+            fields.push("h_off");
+        }
+        if self.version != new.version {
+            fields.push("version");
+        }
+        if self.default != new.default {
+            fields.push("default");
+        }
+        fields
+    }
+
+    /// Old and new value of a field named by [`EntryChange::fields`].
+    fn field(&self, field: &str) -> String {
+        match field {
+            "type" => self.type_name.clone(),
+            "count" => self.count.to_string(),
+            "n_off" => self.n_off.to_string(),
+            "h_off" => self.h_off.to_string(),
+            "version" => self.version.to_string(),
+            "default" => self.default.clone().unwrap_or_else(|| "(none)".to_string()),
+            _ => String::new(),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let default = match &self.default {
+            Some(default) => json_string(default),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{ \"name\": {}, \"type_name\": {}, \"count\": {}, \"n_off\": {}, \"h_off\": {}, \"version\": {}, \"default\": {default} }}",
+            json_string(&self.name),
+            json_string(&self.type_name),
+            self.count,
+            self.n_off,
+            self.h_off,
+            self.version
+        )
+    }
+}
+
+impl MetaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl MetalibDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_metas.is_empty()
+            && self.removed_metas.is_empty()
+            && self.changed_metas.is_empty()
+            && self.added_macros.is_empty()
+            && self.removed_macros.is_empty()
+            && self.changed_macros.is_empty()
+            && self.added_macrogroups.is_empty()
+            && self.removed_macrogroups.is_empty()
+            && self.changed_macrogroups.is_empty()
+    }
+
+    /// Formats the diff for people, one line per change: `+` added, `-` removed, `~` changed.
+    pub fn to_text(&self) -> Result<String> {
+        let mut out = String::new();
+        for name in self.removed_macros.iter() {
+            writeln!(&mut out, "- macro {name}")?;
+        }
+        for name in self.added_macros.iter() {
+            writeln!(&mut out, "+ macro {name}")?;
+        }
+        for change in self.changed_macros.iter() {
+            writeln!(
+                &mut out,
+                "~ macro {}: {} -> {}",
+                change.name, change.old, change.new
+            )?;
+        }
+
+        for name in self.removed_macrogroups.iter() {
+            writeln!(&mut out, "- macrosgroup {name}")?;
+        }
+        for name in self.added_macrogroups.iter() {
+            writeln!(&mut out, "+ macrosgroup {name}")?;
+        }
+        for group in self.changed_macrogroups.iter() {
+            writeln!(&mut out, "~ macrosgroup {}", group.name)?;
+            for name in group.removed.iter() {
+                writeln!(&mut out, "    - {name}")?;
+            }
+            for name in group.added.iter() {
+                writeln!(&mut out, "    + {name}")?;
+            }
+        }
+
+        for name in self.removed_metas.iter() {
+            writeln!(&mut out, "- meta {name}")?;
+        }
+        for name in self.added_metas.iter() {
+            writeln!(&mut out, "+ meta {name}")?;
+        }
+        for meta in self.changed_metas.iter() {
+            writeln!(&mut out, "~ meta {}", meta.name)?;
+            for entry in meta.removed.iter() {
+                writeln!(&mut out, "    - {} ({})", entry.name, entry.type_name)?;
+            }
+            for entry in meta.added.iter() {
+                writeln!(&mut out, "    + {} ({})", entry.name, entry.type_name)?;
+            }
+            for change in meta.changed.iter() {
+                let fields: Vec<String> = change
+                    .fields
+                    .iter()
+                    .map(|field| {
+                        format!(
+                            "{field} {} -> {}",
+                            change.old.field(field),
+                            change.new.field(field)
+                        )
+                    })
+                    .collect();
+                writeln!(&mut out, "    ~ {}: {}", change.old.name, fields.join(", "))?;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Formats the diff as JSON, with the same field names as the serde representation.
+    pub fn to_json(&self) -> Result<String> {
+        let names = |names: &[String]| {
+            let names: Vec<String> = names.iter().map(|name| json_string(name)).collect();
+            format!("[{}]", names.join(", "))
+        };
+        let entries = |entries: &[EntrySummary]| {
+            let entries: Vec<String> = entries.iter().map(EntrySummary::to_json).collect();
+            format!("[{}]", entries.join(", "))
+        };
+
+        let mut out = String::new();
+        writeln!(&mut out, "{{")?;
+        writeln!(&mut out, "  \"added_metas\": {},", names(&self.added_metas))?;
+        writeln!(
+            &mut out,
+            "  \"removed_metas\": {},",
+            names(&self.removed_metas)
+        )?;
+        writeln!(&mut out, "  \"changed_metas\": [")?;
+        for (i, meta) in self.changed_metas.iter().enumerate() {
+            let changed: Vec<String> = meta
+                .changed
+                .iter()
+                .map(|change| {
+                    format!(
+                        "{{ \"old\": {}, \"new\": {}, \"fields\": {} }}",
+                        change.old.to_json(),
+                        change.new.to_json(),
+                        names(&change.fields)
+                    )
+                })
+                .collect();
+            let comma = if i + 1 < self.changed_metas.len() {
+                ","
+            } else {
+                ""
+            };
+            writeln!(
+                &mut out,
+                "    {{ \"name\": {}, \"added\": {}, \"removed\": {}, \"changed\": [{}] }}{comma}",
+                json_string(&meta.name),
+                entries(&meta.added),
+                entries(&meta.removed),
+                changed.join(", ")
+            )?;
+        }
+        writeln!(&mut out, "  ],")?;
+        writeln!(
+            &mut out,
+            "  \"added_macros\": {},",
+            names(&self.added_macros)
+        )?;
+        writeln!(
+            &mut out,
+            "  \"removed_macros\": {},",
+            names(&self.removed_macros)
+        )?;
+        let changed_macros: Vec<String> = self
+            .changed_macros
+            .iter()
+            .map(|change| {
+                format!(
+                    "{{ \"name\": {}, \"old\": {}, \"new\": {} }}",
+                    json_string(&change.name),
+                    change.old,
+                    change.new
+                )
+            })
+            .collect();
+        writeln!(
+            &mut out,
+            "  \"changed_macros\": [{}],",
+            changed_macros.join(", ")
+        )?;
+        writeln!(
+            &mut out,
+            "  \"added_macrogroups\": {},",
+            names(&self.added_macrogroups)
+        )?;
+        writeln!(
+            &mut out,
+            "  \"removed_macrogroups\": {},",
+            names(&self.removed_macrogroups)
+        )?;
+        let changed_groups: Vec<String> = self
+            .changed_macrogroups
+            .iter()
+            .map(|group| {
+                format!(
+                    "{{ \"name\": {}, \"added\": {}, \"removed\": {} }}",
+                    json_string(&group.name),
+                    names(&group.added),
+                    names(&group.removed)
+                )
+            })
+            .collect();
+        writeln!(
+            &mut out,
+            "  \"changed_macrogroups\": [{}]",
+            changed_groups.join(", ")
+        )?;
+        writeln!(&mut out, "}}")?;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{EntryBuilder, MetaBuilder, MetalibBuilder};
+
+    fn old() -> Metalib {
+        MetalibBuilder::new("game")
+            .macro_("MAX_ITEMS", 8)
+            .macro_("GONE", 1)
+            .macrogroup("Kind", &["KIND_A", "KIND_B"])
+            .macro_("KIND_A", 1)
+            .macro_("KIND_B", 2)
+            .meta(
+                MetaBuilder::struct_("Player")
+                    .entry(EntryBuilder::new("id", "int"))
+                    .entry(EntryBuilder::new("level", "uint8").default("1"))
+                    .entry(EntryBuilder::new("items", "uint32").count("MAX_ITEMS"))
+                    .entry(EntryBuilder::new("old_field", "int")),
+            )
+            .meta(MetaBuilder::struct_("Removed").entry(EntryBuilder::new("a", "int")))
+            .meta(MetaBuilder::struct_("Same").entry(EntryBuilder::new("a", "int")))
+            .build()
+            .unwrap()
+    }
+
+    fn new() -> Metalib {
+        MetalibBuilder::new("game")
+            .macro_("MAX_ITEMS", 16)
+            .macro_("ADDED", 3)
+            .macrogroup("Kind", &["KIND_A", "KIND_C"])
+            .macro_("KIND_A", 1)
+            .macro_("KIND_C", 3)
+            .meta(MetaBuilder::struct_("Same").entry(EntryBuilder::new("a", "int")))
+            .meta(
+                // Reordered, which alone isn't a change for `id` and `items`.
+                MetaBuilder::struct_("Player")
+                    .entry(EntryBuilder::new("level", "uint16").default("2"))
+                    .entry(EntryBuilder::new("id", "int"))
+                    .entry(EntryBuilder::new("items", "uint32").count("MAX_ITEMS"))
+                    .entry(EntryBuilder::new("new_field", "string").size(4).version(2)),
+            )
+            .meta(MetaBuilder::struct_("Added").entry(EntryBuilder::new("a", "int")))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn known_differences() {
+        let diff = compare(&old(), &new()).unwrap();
+        assert_eq!(diff.added_metas, ["Added"]);
+        assert_eq!(diff.removed_metas, ["Removed"]);
+        assert_eq!(diff.added_macros, ["ADDED", "KIND_C"]);
+        assert_eq!(diff.removed_macros, ["GONE", "KIND_B"]);
+        assert_eq!(diff.changed_macros.len(), 1);
+        assert_eq!(
+            (
+                diff.changed_macros[0].name.as_str(),
+                diff.changed_macros[0].old,
+                diff.changed_macros[0].new
+            ),
+            ("MAX_ITEMS", 8, 16)
+        );
+        assert!(diff.added_macrogroups.is_empty() && diff.removed_macrogroups.is_empty());
+        assert_eq!(diff.changed_macrogroups[0].added, ["KIND_C"]);
+        assert_eq!(diff.changed_macrogroups[0].removed, ["KIND_B"]);
+
+        assert_eq!(diff.changed_metas.len(), 1);
+        let player = &diff.changed_metas[0];
+        assert_eq!(player.name, "Player");
+        assert_eq!(player.added[0].name, "new_field");
+        assert_eq!(player.removed[0].name, "old_field");
+        let changed: Vec<(&str, Vec<&str>)> = player
+            .changed
+            .iter()
+            .map(|c| {
+                (
+                    c.old.name.as_str(),
+                    c.fields.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            changed,
+            [
+                ("id", vec!["n_off", "h_off"]),
+                ("level", vec!["type", "n_off", "h_off", "default"]),
+                ("items", vec!["count", "n_off", "h_off"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn text_and_json_reports() {
+        let diff = compare(&old(), &new()).unwrap();
+        let text = diff.to_text().unwrap();
+        assert!(text.contains("~ macro MAX_ITEMS: 8 -> 16\n"), "{text}");
+        assert!(
+            text.contains("~ macrosgroup Kind\n    - KIND_B\n    + KIND_C\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains("- meta Removed\n+ meta Added\n~ meta Player\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains("    ~ level: type uint8 -> uint16,"),
+            "{text}"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&diff.to_json().unwrap()).unwrap();
+        assert_eq!(json["added_metas"], serde_json::json!(["Added"]));
+        assert_eq!(json["changed_metas"][0]["added"][0]["name"], "new_field");
+        assert_eq!(json["changed_macros"][0]["new"], 16);
+
+        assert!(compare(&old(), &old()).unwrap().is_empty());
+    }
+}
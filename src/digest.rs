@@ -0,0 +1,438 @@
+//! Compact binary "schema digest" of a metalib.
+//!
+//! A digest lets a runtime check that the metalib it loaded matches the one its tooling
+//! was generated from, without shipping the full XML.
+//!
+//! On-disk format (all integers little-endian):
+//!
+//! ```text
+//! Header (16 bytes):
+//!   0x00  [u8; 4]  magic, always "MLDG"
+//!   0x04  u16      format version (DIGEST_FORMAT_VERSION)
+//!   0x06  u16      reserved, always 0
+//!   0x08  u32      metalib version (MetalibHeader.version)
+//!   0x0C  u32      number of meta records that follow
+//!
+//! Meta record (32 bytes each, sorted by name hash):
+//!   0x00  u64      FNV-1a 64 hash of the meta name
+//!   0x08  u64      FNV-1a 64 hash of the meta layout (see `layout_hash`)
+//!   0x10  i32      meta version (TDRMeta.cur_version)
+//!   0x14  u32      minimum net size in bytes
+//!   0x18  u32      maximum net size in bytes
+//!   0x1C  u32      reserved, always 0
+//! ```
+//!
+//! Readers must reject unknown magic and any format version newer than they understand.
+
+use anyhow::{anyhow, Result};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use crate::metalib::{MetaPrimativeType, Metalib, TDRMeta, TDRMetaFlags, INVALID_METALIB_VALUE};
+
+pub const DIGEST_MAGIC: &[u8; 4] = b"MLDG";
+pub const DIGEST_FORMAT_VERSION: u16 = 1;
+
+/// Limit for following nested struct references when computing size bounds.
+const MAX_NESTING_DEPTH: usize = 64;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// FNV-1a 64, used instead of `std::hash` since it must be stable across builds.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Fnv1a {
+        Fnv1a(FNV_OFFSET_BASIS)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn write_i32(&mut self, value: i32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_str(&mut self, value: &str) {
+        // Length prefix keeps adjacent strings from running together.
+        self.write_i32(value.len() as i32);
+        self.write(value.as_bytes());
+    }
+}
+
+pub fn name_hash(name: &str) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write(name.as_bytes());
+    hasher.0
+}
+
+/// Hashes everything about a meta that affects its layout: each entry's name, type,
+/// count, version and host/net placement, plus the names of referenced metas.
+fn layout_hash(metalib: &Metalib, meta: &TDRMeta) -> Result<u64> {
+    let mut hasher = Fnv1a::new();
+    hasher.write_i32(meta.type_ as i32);
+    hasher.write_i32(meta.n_unit_size);
+    hasher.write_i32(meta.h_unit_size);
+    hasher.write_i32(meta.entries.len() as i32);
+    for entry in meta.entries.iter() {
+        hasher.write_str(&entry.name);
+        hasher.write_i32(entry.type_ as i32);
+        hasher.write_i32(entry.count);
+        hasher.write_i32(entry.version);
+        hasher.write_i32(entry.n_off);
+        hasher.write_i32(entry.n_unit_size);
+        hasher.write_i32(entry.h_off);
+        hasher.write_i32(entry.h_unit_size);
+        if entry.ptr_meta != INVALID_METALIB_VALUE {
+            hasher.write_str(&metalib.get_meta_by_offset(entry.ptr_meta)?.name);
+        }
+    }
+    Ok(hasher.0)
+}
+
+/// Returns the (min, max) number of bytes the meta can occupy on the wire. `bounds` holds
+/// the metas already sized, by `_offset`, so a meta nested in many others is sized once.
+fn net_size_bounds(
+    metalib: &Metalib,
+    meta: &TDRMeta,
+    depth: usize,
+    bounds: &mut HashMap<u64, (u32, u32)>,
+) -> Result<(u32, u32)> {
+    if let Some(&known) = bounds.get(&meta._offset) {
+        return Ok(known);
+    }
+    if depth > MAX_NESTING_DEPTH {
+        return Err(anyhow!(
+            "Exceeded max nesting depth while sizing meta `{}`",
+            meta.name
+        ));
+    }
+
+    let max = meta.n_unit_size.max(0) as u32;
+    if !meta.flags.contains(TDRMetaFlags::VARIABLE) {
+        return Ok((max, max));
+    }
+
+    let mut min: u32 = 0;
+    for entry in meta.entries.iter() {
+        if entry.is_zero_size() {
+            continue;
+        }
+
+        // Entries with a refer count or a sizeinfo prefix can shrink down to nothing.
+        let is_variable = entry.referer.h_off != INVALID_METALIB_VALUE
+            || entry.size_info.unit_size > 0
+            || entry.count == 0;
+        if is_variable {
+            min = min.saturating_add(entry.size_info.unit_size.max(0) as u32);
+            continue;
+        }
+
+        let element_min = if entry.type_ == MetaPrimativeType::STRUCT
+            || entry.type_ == MetaPrimativeType::UNION
+        {
+            let referenced_meta = metalib.get_meta_by_offset(entry.ptr_meta)?;
+            net_size_bounds(metalib, referenced_meta, depth + 1, bounds)?.0
+        } else {
+            entry.n_unit_size.max(0) as u32
+        };
+        min = min.saturating_add(element_min.saturating_mul(entry.count.max(1) as u32));
+    }
+
+    let known = (min.min(max), max);
+    bounds.insert(meta._offset, known);
+    Ok(known)
+}
+
+/// Digest of a single meta.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MetaDigest {
+    pub name_hash: u64,
+    pub layout_hash: u64,
+    pub version: i32,
+    pub min_net_size: u32,
+    pub max_net_size: u32,
+}
+
+/// Digest of a whole metalib.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Digest {
+    pub metalib_version: u32,
+    pub metas: Vec<MetaDigest>,
+}
+
+/// A meta present in both digests whose contents differ.
+#[derive(Debug)]
+pub struct MetaDigestChange {
+    pub old: MetaDigest,
+    pub new: MetaDigest,
+}
+
+/// Result of comparing two digests, keyed by meta name hash.
+#[derive(Debug, Default)]
+pub struct DigestDiff {
+    pub added: Vec<MetaDigest>,
+    pub removed: Vec<MetaDigest>,
+    pub changed: Vec<MetaDigestChange>,
+}
+
+impl DigestDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Digest {
+    pub fn from_metalib(metalib: &Metalib) -> Result<Digest> {
+        let mut metas = Vec::new();
+        let mut bounds = HashMap::new();
+        for meta in metalib.metas.iter() {
+            let (min_net_size, max_net_size) = net_size_bounds(metalib, meta, 0, &mut bounds)?;
+            metas.push(MetaDigest {
+                name_hash: name_hash(&meta.name),
+                layout_hash: layout_hash(metalib, meta)?,
+                version: meta.cur_version,
+                min_net_size,
+                max_net_size,
+            });
+        }
+        metas.sort_by_key(|m| m.name_hash);
+
+        Ok(Digest {
+            metalib_version: metalib.header.version,
+            metas,
+        })
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(DIGEST_MAGIC)?;
+        w.write_u16::<LittleEndian>(DIGEST_FORMAT_VERSION)?;
+        w.write_u16::<LittleEndian>(0)?;
+        w.write_u32::<LittleEndian>(self.metalib_version)?;
+        w.write_u32::<LittleEndian>(self.metas.len().try_into()?)?;
+        for meta in self.metas.iter() {
+            w.write_u64::<LittleEndian>(meta.name_hash)?;
+            w.write_u64::<LittleEndian>(meta.layout_hash)?;
+            w.write_i32::<LittleEndian>(meta.version)?;
+            w.write_u32::<LittleEndian>(meta.min_net_size)?;
+            w.write_u32::<LittleEndian>(meta.max_net_size)?;
+            w.write_u32::<LittleEndian>(0)?;
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        self.write(&mut out)?;
+        Ok(out)
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Digest> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != DIGEST_MAGIC {
+            return Err(anyhow!("Not a metalib digest (bad magic {magic:02X?})"));
+        }
+
+        let format_version = r.read_u16::<LittleEndian>()?;
+        if format_version > DIGEST_FORMAT_VERSION {
+            return Err(anyhow!(
+                "Unsupported digest format version {format_version} (max supported: {DIGEST_FORMAT_VERSION})"
+            ));
+        }
+        let _reserved = r.read_u16::<LittleEndian>()?;
+
+        let metalib_version = r.read_u32::<LittleEndian>()?;
+        let meta_count = r.read_u32::<LittleEndian>()?;
+        let mut metas = Vec::new();
+        for _ in 0..meta_count {
+            let meta = MetaDigest {
+                name_hash: r.read_u64::<LittleEndian>()?,
+                layout_hash: r.read_u64::<LittleEndian>()?,
+                version: r.read_i32::<LittleEndian>()?,
+                min_net_size: r.read_u32::<LittleEndian>()?,
+                max_net_size: r.read_u32::<LittleEndian>()?,
+            };
+            let _reserved = r.read_u32::<LittleEndian>()?;
+            metas.push(meta);
+        }
+
+        Ok(Digest {
+            metalib_version,
+            metas,
+        })
+    }
+
+    /// Compares this (old) digest against `other` (new).
+    pub fn diff(&self, other: &Digest) -> DigestDiff {
+        let mut diff = DigestDiff::default();
+
+        for old in self.metas.iter() {
+            match other.metas.iter().find(|m| m.name_hash == old.name_hash) {
+                Some(new) if new != old => diff.changed.push(MetaDigestChange {
+                    old: old.clone(),
+                    new: new.clone(),
+                }),
+                Some(_) => {}
+                None => diff.removed.push(old.clone()),
+            }
+        }
+
+        for new in other.metas.iter() {
+            if !self.metas.iter().any(|m| m.name_hash == new.name_hash) {
+                diff.added.push(new.clone());
+            }
+        }
+
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{compile, FULL_XML};
+
+    fn meta_digest<'a>(digest: &'a Digest, name: &str) -> Option<&'a MetaDigest> {
+        digest.metas.iter().find(|m| m.name_hash == name_hash(name))
+    }
+
+    #[test]
+    fn name_hash_is_fnv1a_64() {
+        assert_eq!(name_hash(""), 0xcbf29ce484222325);
+        assert_eq!(name_hash("a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let digest = Digest::from_metalib(&compile(FULL_XML)).unwrap();
+        let bytes = digest.to_bytes().unwrap();
+        assert_eq!(&bytes[..4], DIGEST_MAGIC);
+        assert_eq!(bytes.len(), 16 + 32 * digest.metas.len());
+
+        let read = Digest::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read, digest);
+        assert!(digest.diff(&read).is_empty());
+    }
+
+    #[test]
+    fn is_stable_across_compiles() {
+        let a = Digest::from_metalib(&compile(FULL_XML)).unwrap();
+        let b = Digest::from_metalib(&compile(FULL_XML)).unwrap();
+        assert_eq!(a.to_bytes().unwrap(), b.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn rejects_bad_magic_and_newer_versions() {
+        let mut bytes = Digest::from_metalib(&compile(FULL_XML))
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&(DIGEST_FORMAT_VERSION + 1).to_le_bytes());
+        let err = Digest::read(&mut newer.as_slice()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Unsupported digest format version"));
+
+        bytes[0] = b'X';
+        let err = Digest::read(&mut bytes.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("bad magic"));
+    }
+
+    #[test]
+    fn diff_reports_added_removed_and_changed() {
+        let old = Digest::from_metalib(&compile(FULL_XML)).unwrap();
+        let new_xml = FULL_XML
+            .replace(
+                r#"<entry name="len" type="uint16"/>"#,
+                r#"<entry name="len" type="uint32"/>"#,
+            )
+            .replace(r#"<struct name="Sorted""#, r#"<struct name="Ordered""#);
+        let new = Digest::from_metalib(&compile(&new_xml)).unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, [meta_digest(&new, "Ordered").unwrap().clone()]);
+        assert_eq!(diff.removed, [meta_digest(&old, "Sorted").unwrap().clone()]);
+
+        let mut changed: Vec<u64> = diff.changed.iter().map(|c| c.old.name_hash).collect();
+        changed.sort_unstable();
+        // Header changed directly, and every meta that embeds it changed with it.
+        let mut expected: Vec<u64> = ["Header", "Packet", "Account"]
+            .iter()
+            .map(|name| name_hash(name))
+            .collect();
+        expected.sort_unstable();
+        assert_eq!(changed, expected);
+    }
+
+    #[test]
+    fn zero_size_entries_take_no_space() {
+        let xml = r#"<metalib tagsetversion="1" name="zero" version="1">
+            <struct name="Node" version="1">
+                <entry name="a" type="int"/>
+                <entry name="link" type="*Node"/>
+                <entry name="b" type="int"/>
+            </struct>
+        </metalib>"#;
+        let digest = Digest::from_metalib(&compile(xml)).unwrap();
+        assert_eq!(digest.metas[0].min_net_size, 8);
+        assert_eq!(digest.metas[0].max_net_size, 8);
+    }
+
+    /// Marks every meta variable, so its minimum size is worked out from its entries.
+    fn all_variable(xml: &str) -> Metalib {
+        let mut metalib = compile(xml);
+        for meta in metalib.metas.iter_mut() {
+            meta.flags |= TDRMetaFlags::VARIABLE;
+        }
+        metalib
+    }
+
+    #[test]
+    fn shared_metas_are_sized_once() {
+        // Each level holds 8 of the one below, so sizing every path would take 8^9 calls.
+        let mut xml = String::from(r#"<metalib tagsetversion="1" name="fan" version="1">"#);
+        xml += r#"<struct name="L0" version="1"><entry name="x" type="int"/></struct>"#;
+        for level in 1..=9 {
+            xml += &format!(r#"<struct name="L{level}" version="1">"#);
+            for i in 0..8 {
+                xml += &format!(r#"<entry name="e{i}" type="L{}"/>"#, level - 1);
+            }
+            xml += "</struct>";
+        }
+        xml += "</metalib>";
+
+        let digest = Digest::from_metalib(&all_variable(&xml)).unwrap();
+        let top = digest
+            .metas
+            .iter()
+            .find(|m| m.name_hash == name_hash("L9"))
+            .unwrap();
+        assert_eq!(top.min_net_size, 4 << 27);
+        assert_eq!(top.max_net_size, 4 << 27);
+    }
+
+    #[test]
+    fn huge_counts_saturate() {
+        let mut metalib = all_variable(
+            r#"<metalib tagsetversion="1" name="huge" version="1">
+                <struct name="Big" version="1">
+                    <entry name="a" type="bigint" count="2"/>
+                </struct>
+            </metalib>"#,
+        );
+        metalib.metas[0].entries[0].count = i32::MAX;
+        let digest = Digest::from_metalib(&metalib).unwrap();
+        // The minimum is capped at the maximum rather than wrapping.
+        assert_eq!(digest.metas[0].min_net_size, 16);
+        assert_eq!(digest.metas[0].max_net_size, 16);
+    }
+}
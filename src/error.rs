@@ -0,0 +1,248 @@
+//! Categorised parse errors, so failures can be reported to scripts as JSON with a
+//! distinct exit code (`--errors json`).
+
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::io;
+
+use crate::export::json_string;
+use crate::reader_utils::StringReadError;
+use crate::report::ParseReport;
+
+/// What went wrong while parsing.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    BadMagic,
+    BadHeader,
+    Truncated,
+    StringOob,
+    UnknownType,
+    /// Warnings when asked to fail on them.
+    Warnings,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorKind::BadMagic => "bad_magic",
+            ErrorKind::BadHeader => "bad_header",
+            ErrorKind::Truncated => "truncated",
+            ErrorKind::StringOob => "string_oob",
+            ErrorKind::UnknownType => "unknown_type",
+            ErrorKind::Warnings => "warnings",
+            ErrorKind::Other => "other",
+        }
+    }
+
+    /// Process exit code for this kind of failure (2 is taken by usage errors).
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::BadMagic => 3,
+            ErrorKind::Truncated => 4,
+            ErrorKind::StringOob => 5,
+            ErrorKind::UnknownType => 6,
+            ErrorKind::BadHeader => 7,
+            ErrorKind::Warnings => 8,
+        }
+    }
+}
+
+/// A parse failure at a known position.
+///
+/// Readers attach it with the offset in whatever they're reading; `read_metalib_with`
+/// rebases offsets from the body buffer onto the file.
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: ErrorKind,
+    pub offset: u64,
+    pub message: String,
+}
+
+impl ParseError {
+    /// Builds an error of `kind` at `offset`.
+    pub fn at(kind: ErrorKind, offset: u64, message: impl Into<String>) -> anyhow::Error {
+        anyhow::Error::new(ParseError {
+            kind,
+            offset,
+            message: message.into(),
+        })
+    }
+
+    /// Builds an error of `kind` for the header `field` at `offset`, which holds `found`
+    /// where `expected` was required.
+    pub fn header_field(
+        kind: ErrorKind,
+        offset: u64,
+        field: &str,
+        expected: impl fmt::Display,
+        found: impl fmt::Display,
+    ) -> anyhow::Error {
+        ParseError::at(
+            kind,
+            offset,
+            format!("Header {field} is {found}, expected {expected}"),
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at {:#X})", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Returns the first [`ParseError`] in the error's chain.
+pub fn find_parse_error(err: &anyhow::Error) -> Option<&ParseError> {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<ParseError>())
+}
+
+/// Tags `err` as having happened at `offset`, unless it's already tagged. Running out of
+/// data counts as truncation.
+pub fn locate(err: anyhow::Error, offset: u64) -> anyhow::Error {
+    if find_parse_error(&err).is_some() {
+        return err;
+    }
+    let kind = if is_truncation(&err) {
+        ErrorKind::Truncated
+    } else {
+        ErrorKind::Other
+    };
+    ParseError::at(kind, offset, format!("{err:#}"))
+}
+
+/// Whether `err` comes from running out of data.
+pub fn is_truncation(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|io_err| io_err.kind() == io::ErrorKind::UnexpectedEof)
+            || matches!(
+                cause.downcast_ref::<StringReadError>(),
+                Some(StringReadError::Eof { .. })
+            )
+    })
+}
+
+/// Adds `base` to the offset of the error's [`ParseError`], if it has one.
+pub fn rebase(err: anyhow::Error, base: u64) -> anyhow::Error {
+    match err.downcast::<ParseError>() {
+        Ok(mut parse_error) => {
+            parse_error.offset += base;
+            anyhow::Error::new(parse_error)
+        }
+        Err(err) => err,
+    }
+}
+
+/// How a failure is reported on stderr.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorFormat {
+    Text,
+    Json,
+}
+
+impl ErrorFormat {
+    pub fn from_name(name: &str) -> Result<ErrorFormat> {
+        match name {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(anyhow!(
+                "Unknown error format `{name}`, expected text or json"
+            )),
+        }
+    }
+}
+
+/// Prints `err` to stderr in `format` and returns the exit code to use. Failing on
+/// warnings adds them to the JSON as `warnings`.
+pub fn report(err: &anyhow::Error, format: ErrorFormat) -> i32 {
+    let parse_error = find_parse_error(err);
+    let warnings = err
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<ParseReport>());
+    let kind = match warnings {
+        Some(_) => ErrorKind::Warnings,
+        None => parse_error.map_or(ErrorKind::Other, |e| e.kind),
+    };
+    match format {
+        ErrorFormat::Text => eprintln!("Error: {err:?}"),
+        ErrorFormat::Json => {
+            let offset = parse_error.map_or("null".to_string(), |e| e.offset.to_string());
+            let warnings = match warnings {
+                Some(report) => format!(", \"warnings\": {}", report.to_json()),
+                None => String::new(),
+            };
+            eprintln!(
+                "{{\"code\": {}, \"offset\": {offset}, \"message\": {}{warnings}}}",
+                json_string(kind.code()),
+                json_string(&format!("{err:#}"))
+            );
+        }
+    }
+    kind.exit_code()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::MetalibArch;
+    use crate::metalib::METALIB_HEADER_SIZE;
+    use crate::options::ParseOptions;
+    use crate::test_support::{compile_bytes, parse_with, FULL_XML};
+
+    fn parse_error(bytes: &[u8]) -> (ErrorKind, u64) {
+        let err = parse_with(bytes, &ParseOptions::default()).unwrap_err();
+        let parse_error = find_parse_error(&err).unwrap();
+        (parse_error.kind, parse_error.offset)
+    }
+
+    #[test]
+    fn truncated_fixtures() {
+        let bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+
+        // Inside the header, and inside the body.
+        assert_eq!(parse_error(&bytes[..0x80]), (ErrorKind::Truncated, 0x80));
+        let len = bytes.len() - 100;
+        assert_eq!(
+            parse_error(&bytes[..len]),
+            (ErrorKind::Truncated, len as u64)
+        );
+        assert!(len > METALIB_HEADER_SIZE as usize);
+        assert_eq!(ErrorKind::Truncated.code(), "truncated");
+        assert_eq!(ErrorKind::Truncated.exit_code(), 4);
+    }
+
+    #[test]
+    fn shifted_data_is_a_bad_magic() {
+        let bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        let mut shifted = b"XX".to_vec();
+        shifted.extend_from_slice(&bytes);
+        assert_eq!(parse_error(&shifted), (ErrorKind::BadMagic, 0));
+    }
+
+    #[test]
+    fn locate_and_rebase() {
+        let eof = anyhow::Error::new(io::Error::from(io::ErrorKind::UnexpectedEof));
+        let located = locate(eof, 0x10);
+        let parse_error = find_parse_error(&located).unwrap();
+        assert_eq!(
+            (parse_error.kind, parse_error.offset),
+            (ErrorKind::Truncated, 0x10)
+        );
+
+        // An error that's already placed keeps its offset and kind.
+        let located = locate(located, 0x20);
+        assert_eq!(find_parse_error(&located).unwrap().offset, 0x10);
+
+        let rebased = rebase(located, 0x114);
+        assert_eq!(find_parse_error(&rebased).unwrap().offset, 0x124);
+
+        let other = locate(anyhow!("something else"), 3);
+        assert_eq!(find_parse_error(&other).unwrap().kind, ErrorKind::Other);
+    }
+}
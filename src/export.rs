@@ -0,0 +1,65 @@
+//! Document and data formats (as opposed to the language backends in `codegen`).
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+
+mod csv;
+mod dot;
+pub(crate) mod html;
+mod json;
+mod markdown;
+mod yaml;
+
+pub use csv::emit_csv;
+pub use dot::emit_dot;
+pub use html::emit_html;
+pub use json::emit_macros_json;
+pub use markdown::emit_markdown;
+pub use yaml::emit_yaml;
+
+/// Quotes and escapes a string for JSON.
+pub(crate) fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            // Keeps the output safe to embed in a <script> tag.
+            '<' => out.push_str("\\u003c"),
+            c if c.is_control() => write!(&mut out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Turns a name into something safe for file names and anchors, deduplicating repeats.
+pub fn unique_slug(name: &str, used: &mut HashSet<String>) -> String {
+    let base: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let base = if base.is_empty() {
+        "_".to_string()
+    } else {
+        base
+    };
+
+    let mut slug = base.clone();
+    let mut n = 2;
+    while !used.insert(slug.to_lowercase()) {
+        slug = format!("{base}-{n}");
+        n += 1;
+    }
+    slug
+}
@@ -0,0 +1,116 @@
+use anyhow::Result;
+use std::fmt::Write as _;
+
+use crate::metalib::{
+    self, MetaPrimativeType, Metalib, TDRMetaEntry, TDRMetaEntryFlags, INVALID_METALIB_VALUE,
+};
+
+/// Column order is part of the format, so diffs between versions line up.
+const CSV_COLUMNS: &[&str] = &[
+    "meta",
+    "entry",
+    "type",
+    "referenced_meta",
+    "count",
+    "h_off",
+    "n_off",
+    "h_unit_size",
+    "n_unit_size",
+    "version",
+    "flags",
+    "db_flags",
+    "default",
+    "desc",
+];
+
+/// Quotes a CSV field if it contains a separator, quote or line break.
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Formats a bitflags value as `A|B`, or an empty string if no flags are set.
+fn flag_names(flags: impl std::fmt::Debug) -> String {
+    let names = format!("{flags:?}");
+    if names == "(empty)" {
+        String::new()
+    } else {
+        names.replace(" | ", "|")
+    }
+}
+
+/// Returns the XML-style type of an entry, including the `*`/`@` pointer prefixes. An
+/// unknown type is given as its raw number.
+fn entry_type_string(entry: &TDRMetaEntry) -> Result<String> {
+    let type_string = if entry.ptr_meta != INVALID_METALIB_VALUE {
+        if entry.type_ == MetaPrimativeType::UNION {
+            "union".to_string()
+        } else {
+            "struct".to_string()
+        }
+    } else if entry.type_ == MetaPrimativeType::UNKNOWN {
+        entry.raw_type.to_string()
+    } else if entry.idx_type != INVALID_METALIB_VALUE {
+        metalib::primitive_type_info(entry.idx_type)
+            .map_or_else(|| entry.idx_type.to_string(), |info| info.xml_name.to_string())
+    } else {
+        String::new()
+    };
+
+    let type_prefix = if entry.flag.contains(TDRMetaEntryFlags::POINT_TYPE) {
+        "*"
+    } else if entry.flag.contains(TDRMetaEntryFlags::REFER_TYPE) {
+        "@"
+    } else {
+        ""
+    };
+
+    Ok(format!("{type_prefix}{type_string}"))
+}
+
+/// Generates a flat CSV listing with one row per entry of every meta.
+///
+/// Nested struct/union entries aren't expanded; the `referenced_meta` column names the meta
+/// they point at instead.
+pub fn emit_csv(metalib: &Metalib) -> Result<String> {
+    let mut out = String::new();
+    writeln!(&mut out, "{}", CSV_COLUMNS.join(","))?;
+
+    for meta in metalib.metas.iter() {
+        for entry in meta.entries.iter() {
+            let referenced_meta = if entry.ptr_meta != INVALID_METALIB_VALUE {
+                metalib.get_meta_by_offset(entry.ptr_meta)?.name.as_str()
+            } else {
+                ""
+            };
+            let default = if entry.ptr_default_val != INVALID_METALIB_VALUE {
+                entry.default_value_string.as_str()
+            } else {
+                ""
+            };
+
+            let row = [
+                csv_escape(&meta.name),
+                csv_escape(&entry.name),
+                csv_escape(&entry_type_string(entry)?),
+                csv_escape(referenced_meta),
+                entry.count.to_string(),
+                entry.h_off.to_string(),
+                entry.n_off.to_string(),
+                entry.h_unit_size.to_string(),
+                entry.n_unit_size.to_string(),
+                entry.version.to_string(),
+                flag_names(entry.flag),
+                flag_names(entry.db_flag),
+                csv_escape(default),
+                csv_escape(&entry.desc),
+            ];
+            writeln!(&mut out, "{}", row.join(","))?;
+        }
+    }
+
+    Ok(out)
+}
@@ -0,0 +1,143 @@
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+
+use crate::codegen::is_union;
+use crate::metalib::{Metalib, TDRMetaEntry, INVALID_METALIB_VALUE};
+
+/// Quotes a string as a DOT ID.
+fn dot_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Gives every name a unique node ID, suffixing repeats with `#2`, `#3`, ...
+fn unique_node_id(name: &str, used: &mut HashSet<String>) -> String {
+    let mut id = name.to_string();
+    let mut n = 2;
+    while !used.insert(id.clone()) {
+        id = format!("{name}#{n}");
+        n += 1;
+    }
+    id
+}
+
+/// Returns the edge label for an entry: its name plus `[count]` or `[var]`.
+fn edge_label(entry: &TDRMetaEntry) -> String {
+    let is_variable = entry.referer.h_off != INVALID_METALIB_VALUE || entry.size_info.unit_size > 0;
+    if is_variable {
+        format!("{} [var]", entry.name)
+    } else if entry.count > 1 {
+        format!("{} [{}]", entry.name, entry.count)
+    } else {
+        entry.name.clone()
+    }
+}
+
+/// Generates a Graphviz digraph with a node per meta and an edge per nested struct/union entry.
+///
+/// Macrogroups bound to entries show up as dashed edges to note-shaped nodes, and a
+/// struct's `dependontable` as a dotted edge to the struct it depends on.
+pub fn emit_dot(metalib: &Metalib) -> Result<String> {
+    let mut out = String::new();
+    let graph_name = if metalib.header.name.is_empty() {
+        "metalib"
+    } else {
+        &metalib.header.name
+    };
+    writeln!(&mut out, "digraph {} {{", dot_quote(graph_name))?;
+    writeln!(&mut out, "    rankdir=LR;")?;
+    writeln!(&mut out, "    node [shape=box];")?;
+
+    // Node IDs are keyed by table offset since names aren't guaranteed to be unique.
+    let mut used = HashSet::new();
+    let mut meta_ids = HashMap::new();
+    for meta in metalib.metas.iter() {
+        let id = unique_node_id(&meta.name, &mut used);
+        if is_union(meta) {
+            writeln!(
+                &mut out,
+                "    {} [label={}, shape=ellipse, style=filled, fillcolor=lightyellow];",
+                dot_quote(&id),
+                dot_quote(&meta.name)
+            )?;
+        } else {
+            writeln!(
+                &mut out,
+                "    {} [label={}];",
+                dot_quote(&id),
+                dot_quote(&meta.name)
+            )?;
+        }
+        meta_ids.insert(meta._offset, id);
+    }
+
+    let mut macrogroup_ids = HashMap::new();
+    for macrogroup in metalib.macrogroups.iter() {
+        let id = unique_node_id(&format!("enum {}", macrogroup.name), &mut used);
+        writeln!(
+            &mut out,
+            "    {} [label={}, shape=note];",
+            dot_quote(&id),
+            dot_quote(&macrogroup.name)
+        )?;
+        macrogroup_ids.insert(macrogroup._offset, id);
+    }
+
+    for meta in metalib.metas.iter() {
+        let from = &meta_ids[&meta._offset];
+        if meta.ptr_dependon_struct != INVALID_METALIB_VALUE {
+            let dependon_struct = metalib.get_meta_by_offset(meta.ptr_dependon_struct)?;
+            writeln!(
+                &mut out,
+                "    {} -> {} [label=\"dependontable\", style=dotted];",
+                dot_quote(from),
+                dot_quote(&meta_ids[&dependon_struct._offset])
+            )?;
+        }
+        for entry in meta.entries.iter() {
+            if entry.ptr_meta != INVALID_METALIB_VALUE {
+                let referenced_meta = metalib.get_meta_by_offset(entry.ptr_meta)?;
+                writeln!(
+                    &mut out,
+                    "    {} -> {} [label={}];",
+                    dot_quote(from),
+                    dot_quote(&meta_ids[&referenced_meta._offset]),
+                    dot_quote(&edge_label(entry))
+                )?;
+            }
+
+            if entry.ptr_macros_group != INVALID_METALIB_VALUE {
+                let macrogroup = metalib.get_macrogroup_by_offset(entry.ptr_macros_group)?;
+                writeln!(
+                    &mut out,
+                    "    {} -> {} [label={}, style=dashed];",
+                    dot_quote(from),
+                    dot_quote(&macrogroup_ids[&macrogroup._offset]),
+                    dot_quote(&entry.name)
+                )?;
+            }
+        }
+    }
+
+    writeln!(&mut out, "}}")?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{parse, DB_XML};
+
+    #[test]
+    fn dependontable_edges() {
+        let dot = emit_dot(&parse(DB_XML)).unwrap();
+        assert!(
+            dot.contains("    \"Friend\" -> \"Player\" [label=\"dependontable\", style=dotted];\n"),
+            "{dot}"
+        );
+        assert!(
+            dot.contains("    \"Player\" -> \"Stats\" [label=\"stats\"];\n"),
+            "{dot}"
+        );
+    }
+}
@@ -0,0 +1,425 @@
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+
+use super::unique_slug;
+use crate::metalib::{
+    MetaPrimativeType, Metalib, SizeSource, TDRMeta, TDRMetaEntry, INVALID_METALIB_VALUE,
+};
+
+const PAGE_CSS: &str = "body{font-family:sans-serif;margin:0;display:flex}\
+nav{width:16em;padding:1em;background:#f4f4f4;height:100vh;overflow:auto;position:sticky;top:0;box-sizing:border-box}\
+nav ul{padding-left:1em}\
+main{padding:1em 2em;flex:1;min-width:0}\
+table{border-collapse:collapse;margin-bottom:2em}\
+td,th{border:1px solid #ccc;padding:2px 6px;text-align:left;vertical-align:top}\
+tr:target,h2:target{background:#ffc}\
+.desc{white-space:pre-wrap}";
+
+/// Hides sidebar links that don't contain the search text.
+const FILTER_SCRIPT: &str = "function filterNav(q){q=q.toLowerCase();\
+document.querySelectorAll('nav li').forEach(function(li){\
+li.style.display=li.textContent.toLowerCase().indexOf(q)>=0?'':'none';});}";
+
+pub(crate) fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Anchors for every macro table, meta, entry and macrogroup of one page, keyed by table offset.
+pub(crate) struct PageAnchors {
+    pub macros: String,
+    pub metas: HashMap<u64, String>,
+    pub entries: HashMap<u64, String>,
+    pub macrogroups: HashMap<u64, String>,
+}
+
+impl PageAnchors {
+    pub fn new(metalib: &Metalib) -> PageAnchors {
+        let mut used = HashSet::new();
+        let macros = unique_slug("macros", &mut used);
+        let metas = metalib
+            .metas
+            .iter()
+            .map(|meta| (meta._offset, unique_slug(&meta.name, &mut used)))
+            .collect();
+        let macrogroups = metalib
+            .macrogroups
+            .iter()
+            .map(|group| {
+                (
+                    group._offset,
+                    unique_slug(&format!("enum-{}", group.name), &mut used),
+                )
+            })
+            .collect();
+        let entries = metalib
+            .metas
+            .iter()
+            .flat_map(|meta| meta.entries.iter().map(move |entry| (meta, entry)))
+            .map(|(meta, entry)| {
+                (
+                    entry._offset,
+                    unique_slug(&format!("{}-{}", meta.name, entry.name), &mut used),
+                )
+            })
+            .collect();
+        PageAnchors {
+            macros,
+            metas,
+            entries,
+            macrogroups,
+        }
+    }
+}
+
+pub(crate) fn write_page_start(out: &mut String, title: &str) -> Result<()> {
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html><head><meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>{}</title>", html_escape(title))?;
+    writeln!(out, "<style>{PAGE_CSS}</style></head><body>")?;
+    Ok(())
+}
+
+/// Follows a dotted entry path (as returned by the offset resolvers) from `meta` to the entry
+/// it names.
+fn find_entry_by_path<'a>(
+    metalib: &'a Metalib,
+    meta: &'a TDRMeta,
+    path: &str,
+) -> Option<&'a TDRMetaEntry> {
+    let mut meta = meta;
+    let mut segments = path.split('.').peekable();
+    while let Some(segment) = segments.next() {
+        let entry = meta.entries.iter().find(|entry| entry.name == segment)?;
+        if segments.peek().is_none() {
+            return Some(entry);
+        }
+        meta = metalib.get_meta_by_offset(entry.ptr_meta).ok()?;
+    }
+    None
+}
+
+/// Links a resolved entry path to its row, falling back to plain text if it can't be followed.
+fn entry_path_link(metalib: &Metalib, anchors: &PageAnchors, meta: &TDRMeta, path: &str) -> String {
+    match find_entry_by_path(metalib, meta, path) {
+        Some(entry) => format!(
+            "<a href=\"#{}\">{}</a>",
+            anchors.entries[&entry._offset],
+            html_escape(path)
+        ),
+        None => html_escape(path),
+    }
+}
+
+/// Collects the `refer`/`select`/`sizeinfo`/`bindmacrosgroup` cross-references of an entry.
+fn entry_references(
+    metalib: &Metalib,
+    anchors: &PageAnchors,
+    meta: &TDRMeta,
+    entry: &TDRMetaEntry,
+) -> Result<Vec<String>> {
+    let mut refs = Vec::new();
+    if let Some(refer) = entry.referer.resolve(metalib, meta)? {
+        refs.push(format!(
+            "refer {}",
+            entry_path_link(metalib, anchors, meta, &refer.path)
+        ));
+    }
+    if entry.type_ == MetaPrimativeType::UNION {
+        if let Some(select) = entry.selector.resolve(metalib, meta)? {
+            refs.push(format!(
+                "select {}",
+                entry_path_link(metalib, anchors, meta, &select.path)
+            ));
+        }
+    }
+    match entry.size_info.resolve(metalib, meta)? {
+        Some(SizeSource::TypePrefix(type_info)) => {
+            refs.push(format!("sizeinfo {}", type_info.xml_name));
+        }
+        Some(SizeSource::Field(field)) => {
+            refs.push(format!(
+                "sizeinfo {}",
+                entry_path_link(metalib, anchors, meta, &field.path)
+            ));
+        }
+        None => {}
+    }
+    if entry.ptr_macros_group != INVALID_METALIB_VALUE {
+        let group = metalib.get_macrogroup_by_offset(entry.ptr_macros_group)?;
+        refs.push(format!(
+            "bindmacrosgroup <a href=\"#{}\">{}</a>",
+            anchors.macrogroups[&group._offset],
+            html_escape(&group.name)
+        ));
+    }
+    Ok(refs)
+}
+
+fn write_meta_section(
+    out: &mut String,
+    metalib: &Metalib,
+    anchors: &PageAnchors,
+    meta: &TDRMeta,
+) -> Result<()> {
+    let kind = if meta.type_ == MetaPrimativeType::UNION {
+        "union"
+    } else {
+        "struct"
+    };
+    writeln!(
+        out,
+        "<h2 id=\"{}\">{kind} {}</h2>",
+        anchors.metas[&meta._offset],
+        html_escape(&meta.name)
+    )?;
+    if !meta.desc.is_empty() {
+        writeln!(out, "<p class=\"desc\">{}</p>", html_escape(&meta.desc))?;
+    }
+
+    writeln!(
+        out,
+        "<table><tr><th>Name</th><th>Type</th><th>Count</th><th>Version</th>\
+<th>Host offset</th><th>Net offset</th><th>Default</th><th>References</th><th>Description</th></tr>"
+    )?;
+    for entry in meta.entries.iter() {
+        let type_cell = if entry.ptr_meta != INVALID_METALIB_VALUE {
+            let referenced_meta = metalib.get_meta_by_offset(entry.ptr_meta)?;
+            format!(
+                "<a href=\"#{}\">{}</a>",
+                anchors.metas[&referenced_meta._offset],
+                html_escape(&referenced_meta.name)
+            )
+        } else {
+            format!("{:?}", entry.type_).to_lowercase()
+        };
+        let count = if entry.idx_count != INVALID_METALIB_VALUE {
+            metalib
+                .macros
+                .get(entry.idx_count as usize)
+                .context("Failed to get macro by meta_entry.idx_count")?
+                .name
+                .clone()
+        } else {
+            entry.count.to_string()
+        };
+        let default = if entry.ptr_default_val != INVALID_METALIB_VALUE {
+            html_escape(&entry.default_value_string)
+        } else {
+            String::new()
+        };
+        writeln!(
+            out,
+            "<tr id=\"{}\"><td>{}</td><td>{type_cell}</td><td>{}</td><td>{}</td>\
+<td>{:#X}</td><td>{:#X}</td><td>{default}</td><td>{}</td><td class=\"desc\">{}</td></tr>",
+            anchors.entries[&entry._offset],
+            html_escape(&entry.name),
+            html_escape(&count),
+            entry.version,
+            entry.h_off,
+            entry.n_off,
+            entry_references(metalib, anchors, meta, entry)?.join("<br>"),
+            html_escape(&entry.desc)
+        )?;
+    }
+    writeln!(out, "</table>")?;
+    Ok(())
+}
+
+/// Renders a complete page for one metalib.
+///
+/// `label` is appended to the title and heading, and `back_link` adds a link above the
+/// sidebar; both are used by the multi-version site.
+pub(crate) fn render_metalib_page(
+    metalib: &Metalib,
+    anchors: &PageAnchors,
+    label: Option<&str>,
+    back_link: Option<(&str, &str)>,
+) -> Result<String> {
+    let mut out = String::new();
+    let title = match label {
+        Some(label) => format!("{} ({label})", metalib.header.name),
+        None => metalib.header.name.clone(),
+    };
+    write_page_start(&mut out, &title)?;
+
+    writeln!(&mut out, "<nav>")?;
+    if let Some((href, text)) = back_link {
+        writeln!(
+            &mut out,
+            "<p><a href=\"{}\">&larr; {}</a></p>",
+            html_escape(href),
+            html_escape(text)
+        )?;
+    }
+    writeln!(
+        &mut out,
+        "<input type=\"search\" placeholder=\"Filter\" oninput=\"filterNav(this.value)\">"
+    )?;
+    writeln!(&mut out, "<h3>Structs</h3><ul>")?;
+    for meta in metalib.metas.iter() {
+        writeln!(
+            &mut out,
+            "<li><a href=\"#{}\">{}</a></li>",
+            anchors.metas[&meta._offset],
+            html_escape(&meta.name)
+        )?;
+    }
+    writeln!(&mut out, "</ul><h3>Macrogroups</h3><ul>")?;
+    for group in metalib.macrogroups.iter() {
+        writeln!(
+            &mut out,
+            "<li><a href=\"#{}\">{}</a></li>",
+            anchors.macrogroups[&group._offset],
+            html_escape(&group.name)
+        )?;
+    }
+    writeln!(
+        &mut out,
+        "</ul><p><a href=\"#{}\">Macros</a></p></nav><main>",
+        anchors.macros
+    )?;
+
+    match label {
+        Some(label) => writeln!(
+            &mut out,
+            "<h1>{} <small>{}</small></h1>",
+            html_escape(&metalib.header.name),
+            html_escape(label)
+        )?,
+        None => writeln!(&mut out, "<h1>{}</h1>", html_escape(&metalib.header.name))?,
+    }
+    writeln!(
+        &mut out,
+        "<p>Version {}, tagsetversion {}, {} structs, {} macros, {} macrogroups.</p>",
+        metalib.header.version,
+        metalib.header.xml_tag_set_ver,
+        metalib.metas.len(),
+        metalib.macros.len(),
+        metalib.macrogroups.len()
+    )?;
+
+    for meta in metalib.metas.iter() {
+        write_meta_section(&mut out, metalib, anchors, meta)?;
+    }
+
+    for group in metalib.macrogroups.iter() {
+        writeln!(
+            &mut out,
+            "<h2 id=\"{}\">macrogroup {}</h2>",
+            anchors.macrogroups[&group._offset],
+            html_escape(&group.name)
+        )?;
+        if !group.desc.is_empty() {
+            writeln!(
+                &mut out,
+                "<p class=\"desc\">{}</p>",
+                html_escape(&group.desc)
+            )?;
+        }
+        writeln!(
+            &mut out,
+            "<table><tr><th>Name</th><th>Value</th><th>Description</th></tr>"
+        )?;
+        for tdr_macro in metalib.macrogroup_macros(group) {
+            writeln!(
+                &mut out,
+                "<tr><td>{}</td><td>{}</td><td class=\"desc\">{}</td></tr>",
+                html_escape(&tdr_macro.name),
+                tdr_macro.value,
+                html_escape(&tdr_macro.desc)
+            )?;
+        }
+        writeln!(&mut out, "</table>")?;
+    }
+
+    writeln!(&mut out, "<h2 id=\"{}\">Macros</h2>", anchors.macros)?;
+    writeln!(
+        &mut out,
+        "<table><tr><th>Name</th><th>Value</th><th>Description</th></tr>"
+    )?;
+    for tdr_macro in metalib.macros.iter() {
+        if metalib.is_macro_in_group(tdr_macro)? {
+            continue;
+        }
+        writeln!(
+            &mut out,
+            "<tr><td>{}</td><td>{}</td><td class=\"desc\">{}</td></tr>",
+            html_escape(&tdr_macro.name),
+            tdr_macro.value,
+            html_escape(&tdr_macro.desc)
+        )?;
+    }
+    writeln!(&mut out, "</table>")?;
+
+    writeln!(
+        &mut out,
+        "</main><script>{FILTER_SCRIPT}</script></body></html>"
+    )?;
+    Ok(out)
+}
+
+/// Generates a single self-contained HTML page documenting the metalib.
+///
+/// Struct-typed entries link to their meta, and `refer`/`select`/`sizeinfo` paths link to
+/// the entry they resolve to.
+pub fn emit_html(metalib: &Metalib) -> Result<String> {
+    render_metalib_page(metalib, &PageAnchors::new(metalib), None, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::test_support::{compile, FULL_XML};
+
+    /// Values of every `attr="..."` in the page.
+    fn attr_values<'a>(html: &'a str, attr: &str) -> Vec<&'a str> {
+        let needle = format!(" {attr}=\"");
+        html.match_indices(&needle)
+            .map(|(i, _)| {
+                let value = &html[i + needle.len()..];
+                &value[..value.find('"').unwrap()]
+            })
+            .collect()
+    }
+
+    fn check_anchors(html: &str) {
+        let ids = attr_values(html, "id");
+        let unique: HashSet<&str> = ids.iter().copied().collect();
+        assert_eq!(unique.len(), ids.len(), "duplicate ids in {ids:?}");
+
+        let links: Vec<&str> = attr_values(html, "href")
+            .into_iter()
+            .filter_map(|href| href.strip_prefix('#'))
+            .collect();
+        assert!(!links.is_empty());
+        for link in links {
+            assert!(unique.contains(link), "#{link} has no anchor");
+        }
+    }
+
+    #[test]
+    fn internal_links_have_anchors() {
+        check_anchors(&emit_html(&compile(FULL_XML)).unwrap());
+    }
+
+    #[test]
+    fn colliding_names_get_distinct_anchors() {
+        let xml = r#"<metalib tagsetversion="1" name="clash" version="1">
+            <macrosgroup name="macros"><macro name="M" value="1"/></macrosgroup>
+            <struct name="a.b" version="1"><entry name="x" type="int"/></struct>
+            <struct name="a_b" version="1"><entry name="x" type="int"/></struct>
+            <struct name="a b" version="1">
+                <entry name="x" type="a.b"/>
+                <entry name="y" type="a_b"/>
+                <entry name="k" type="int" bindmacrosgroup="macros"/>
+            </struct>
+        </metalib>"#;
+        check_anchors(&emit_html(&compile(xml)).unwrap());
+    }
+}
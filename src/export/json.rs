@@ -0,0 +1,55 @@
+use anyhow::Result;
+use std::fmt::Write as _;
+
+use super::json_string;
+use crate::metalib::Metalib;
+
+/// Writes the macro name -> value mapping, plus each macrogroup's members, as JSON.
+pub fn emit_macros_json(metalib: &Metalib) -> Result<String> {
+    let mut out = String::new();
+    writeln!(&mut out, "{{")?;
+    writeln!(
+        &mut out,
+        "  \"name\": {},",
+        json_string(&metalib.header.name)
+    )?;
+    writeln!(&mut out, "  \"version\": {},", metalib.header.version)?;
+
+    writeln!(&mut out, "  \"macros\": {{")?;
+    for (i, macro_) in metalib.macros.iter().enumerate() {
+        let separator = if i + 1 < metalib.macros.len() {
+            ","
+        } else {
+            ""
+        };
+        writeln!(
+            &mut out,
+            "    {}: {}{separator}",
+            json_string(&macro_.name),
+            macro_.value
+        )?;
+    }
+    writeln!(&mut out, "  }},")?;
+
+    writeln!(&mut out, "  \"macrogroups\": {{")?;
+    for (i, group) in metalib.macrogroups.iter().enumerate() {
+        let mut members = Vec::new();
+        for macro_ in metalib.macrogroup_macros(group) {
+            members.push(format!("{}: {}", json_string(&macro_.name), macro_.value));
+        }
+        let separator = if i + 1 < metalib.macrogroups.len() {
+            ","
+        } else {
+            ""
+        };
+        writeln!(
+            &mut out,
+            "    {}: {{ {} }}{separator}",
+            json_string(&group.name),
+            members.join(", ")
+        )?;
+    }
+    writeln!(&mut out, "  }}")?;
+    writeln!(&mut out, "}}")?;
+    Ok(out)
+}
@@ -0,0 +1,177 @@
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+
+use super::unique_slug;
+use crate::metalib::{MetaPrimativeType, Metalib, TDRMeta, INVALID_METALIB_VALUE};
+
+/// Escapes text for use inside a Markdown table cell.
+fn md_cell(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>")
+}
+
+fn write_meta_section(
+    out: &mut String,
+    metalib: &Metalib,
+    anchors: &HashMap<u64, String>,
+    meta: &TDRMeta,
+) -> Result<()> {
+    let kind = if meta.type_ == MetaPrimativeType::UNION {
+        "union"
+    } else {
+        "struct"
+    };
+    writeln!(out, "<a id=\"{}\"></a>", anchors[&meta._offset])?;
+    writeln!(out, "## {} ({kind})", meta.name)?;
+    writeln!(out)?;
+    if !meta.desc.is_empty() {
+        writeln!(out, "{}", meta.desc)?;
+        writeln!(out)?;
+    }
+
+    writeln!(
+        out,
+        "| Name | Type | Count | Version | Default | Description | Chinese name |"
+    )?;
+    writeln!(out, "|---|---|---|---|---|---|---|")?;
+    for entry in meta.entries.iter() {
+        let type_cell = if entry.ptr_meta != INVALID_METALIB_VALUE {
+            let referenced_meta = metalib.get_meta_by_offset(entry.ptr_meta)?;
+            format!(
+                "[{}](#{})",
+                md_cell(&referenced_meta.name),
+                anchors[&referenced_meta._offset]
+            )
+        } else {
+            format!("{:?}", entry.type_).to_lowercase()
+        };
+        let count = if entry.idx_count != INVALID_METALIB_VALUE {
+            metalib
+                .macros
+                .get(entry.idx_count as usize)
+                .context("Failed to get macro by meta_entry.idx_count")?
+                .name
+                .clone()
+        } else {
+            entry.count.to_string()
+        };
+        let default = if entry.ptr_default_val != INVALID_METALIB_VALUE {
+            md_cell(&entry.default_value_string)
+        } else {
+            String::new()
+        };
+        writeln!(
+            out,
+            "| {} | {type_cell} | {} | {} | {default} | {} | {} |",
+            md_cell(&entry.name),
+            md_cell(&count),
+            entry.version,
+            md_cell(&entry.desc),
+            md_cell(&entry.chinese_name)
+        )?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Generates a Markdown document with a section per meta and a table per macrogroup.
+///
+/// Struct-typed entries link to the section of the meta they reference.
+pub fn emit_markdown(metalib: &Metalib) -> Result<String> {
+    let mut out = String::new();
+    writeln!(&mut out, "---")?;
+    writeln!(&mut out, "name: {:?}", metalib.header.name)?;
+    writeln!(&mut out, "version: {}", metalib.header.version)?;
+    writeln!(
+        &mut out,
+        "tagsetversion: {}",
+        metalib.header.xml_tag_set_ver
+    )?;
+    writeln!(&mut out, "---")?;
+    writeln!(&mut out)?;
+    writeln!(&mut out, "# {}", metalib.header.name)?;
+    writeln!(&mut out)?;
+
+    let mut used = HashSet::new();
+    let anchors: HashMap<u64, String> = metalib
+        .metas
+        .iter()
+        .map(|meta| (meta._offset, unique_slug(&meta.name, &mut used)))
+        .collect();
+
+    let mut wrote_macro = false;
+    for tdr_macro in metalib.macros.iter() {
+        if metalib.is_macro_in_group(tdr_macro)? {
+            continue;
+        }
+        if !wrote_macro {
+            writeln!(&mut out, "## Macros")?;
+            writeln!(&mut out)?;
+            writeln!(&mut out, "| Name | Value | Description |")?;
+            writeln!(&mut out, "|---|---|---|")?;
+            wrote_macro = true;
+        }
+        writeln!(
+            &mut out,
+            "| {} | {} | {} |",
+            md_cell(&tdr_macro.name),
+            tdr_macro.value,
+            md_cell(&tdr_macro.desc)
+        )?;
+    }
+    if wrote_macro {
+        writeln!(&mut out)?;
+    }
+
+    for macrogroup in metalib.macrogroups.iter() {
+        writeln!(
+            &mut out,
+            "<a id=\"{}\"></a>",
+            unique_slug(&format!("enum-{}", macrogroup.name), &mut used)
+        )?;
+        writeln!(&mut out, "## {} (macrogroup)", macrogroup.name)?;
+        writeln!(&mut out)?;
+        if !macrogroup.desc.is_empty() {
+            writeln!(&mut out, "{}", macrogroup.desc)?;
+            writeln!(&mut out)?;
+        }
+        writeln!(&mut out, "| Name | Value | Description |")?;
+        writeln!(&mut out, "|---|---|---|")?;
+        for tdr_macro in metalib.macrogroup_macros(macrogroup) {
+            writeln!(
+                &mut out,
+                "| {} | {} | {} |",
+                md_cell(&tdr_macro.name),
+                tdr_macro.value,
+                md_cell(&tdr_macro.desc)
+            )?;
+        }
+        writeln!(&mut out)?;
+    }
+
+    for meta in metalib.metas.iter() {
+        write_meta_section(&mut out, metalib, &anchors, meta)?;
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{compile, FULL_XML};
+
+    #[test]
+    fn matches_snapshot() {
+        let expected = include_str!("../../tests/fixtures/full.md");
+        assert_eq!(emit_markdown(&compile(FULL_XML)).unwrap(), expected);
+    }
+
+    #[test]
+    fn cells_are_escaped() {
+        assert_eq!(md_cell("a|b\\c\r\nd\ne"), "a\\|b\\\\c<br>d<br>e");
+    }
+}
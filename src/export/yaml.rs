@@ -0,0 +1,372 @@
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+
+use crate::metalib::{
+    MetaPrimativeType, Metalib, SizeSource, TDRMacro, TDRMeta, TDRMetaEntry, TDRMetaEntryDBFlags,
+    TDRMetaEntryFlags, TDRMetaFlags, INVALID_METALIB_VALUE,
+};
+
+const META_FLAG_NAMES: &[(TDRMetaFlags, &str)] = &[
+    (TDRMetaFlags::FIXED_SIZE, "fixed_size"),
+    (TDRMetaFlags::HAS_ID, "has_id"),
+    (TDRMetaFlags::RESOVLED, "resolved"),
+    (TDRMetaFlags::VARIABLE, "variable"),
+    (TDRMetaFlags::STRICT_INPUT, "strict_input"),
+    (
+        TDRMetaFlags::HAS_AUTOINCREMENT_ENTRY,
+        "has_autoincrement_entry",
+    ),
+    (
+        TDRMetaFlags::NEED_PREFIX_FOR_UNIQUENAME,
+        "need_prefix_for_uniquename",
+    ),
+    (TDRMetaFlags::HAS_EXTEND_META, "has_extend_meta"),
+    (TDRMetaFlags::IS_EXTEND_META, "is_extend_meta"),
+    (TDRMetaFlags::UNKNOWN_FLAG_512, "unknown_flag_512"),
+];
+
+const ENTRY_FLAG_NAMES: &[(TDRMetaEntryFlags, &str)] = &[
+    (TDRMetaEntryFlags::RESOVLED, "resolved"),
+    (TDRMetaEntryFlags::POINT_TYPE, "point_type"),
+    (TDRMetaEntryFlags::REFER_TYPE, "refer_type"),
+    (TDRMetaEntryFlags::HAS_ID, "has_id"),
+    (TDRMetaEntryFlags::HAS_MAXMIN_ID, "has_maxmin_id"),
+    (TDRMetaEntryFlags::FIXED_SIZE, "fixed_size"),
+    (TDRMetaEntryFlags::REFER_COUNT, "refer_count"),
+];
+
+const ENTRY_DB_FLAG_NAMES: &[(TDRMetaEntryDBFlags, &str)] = &[
+    (TDRMetaEntryDBFlags::UNIQUE, "unique"),
+    (TDRMetaEntryDBFlags::NOT_NULL, "not_null"),
+    (TDRMetaEntryDBFlags::EXTEND_TO_TABLE, "extend_to_table"),
+    (TDRMetaEntryDBFlags::PRIMARY_KEY, "primary_key"),
+    (TDRMetaEntryDBFlags::AUTO_INCREMENT, "auto_increment"),
+];
+
+/// Quotes a string as a YAML double-quoted scalar.
+///
+/// Non-ASCII text is kept as-is (YAML is UTF-8), only control characters are escaped, so
+/// multi-line and CJK descriptions round-trip exactly.
+fn yaml_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04X}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Formats a list of the flag names set in `flags`, plus the raw value of any unknown bits.
+fn yaml_flags<T, F>(flags: T, names: &[(T, &str)], bits: F) -> String
+where
+    T: Copy,
+    F: Fn(T) -> u64,
+{
+    let mut known = 0;
+    let mut items: Vec<String> = Vec::new();
+    for &(flag, name) in names {
+        known |= bits(flag);
+        if bits(flags) & bits(flag) == bits(flag) {
+            items.push(name.to_string());
+        }
+    }
+    let unknown = bits(flags) & !known;
+    if unknown != 0 {
+        items.push(format!("{unknown:#X}"));
+    }
+    format!("[{}]", items.join(", "))
+}
+
+/// Returns the macro name for `idx` if it's set, otherwise the literal value.
+fn macro_or_value(metalib: &Metalib, idx: i32, value: i32) -> Result<String> {
+    if idx != INVALID_METALIB_VALUE {
+        let tdr_macro = metalib
+            .macros
+            .get(idx as usize)
+            .context("Failed to get macro by index")?;
+        Ok(yaml_str(&tdr_macro.name))
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+fn write_macro(out: &mut String, indent: &str, tdr_macro: &TDRMacro) -> Result<()> {
+    writeln!(out, "{indent}- name: {}", yaml_str(&tdr_macro.name))?;
+    writeln!(out, "{indent}  value: {}", tdr_macro.value)?;
+    if !tdr_macro.desc.is_empty() {
+        writeln!(out, "{indent}  desc: {}", yaml_str(&tdr_macro.desc))?;
+    }
+    Ok(())
+}
+
+fn write_entry(
+    out: &mut String,
+    metalib: &Metalib,
+    meta: &TDRMeta,
+    entry: &TDRMetaEntry,
+) -> Result<()> {
+    writeln!(out, "      - name: {}", yaml_str(&entry.name))?;
+    writeln!(
+        out,
+        "        type: {}",
+        yaml_str(&metalib.entry_type_name(entry)?)
+    )?;
+    writeln!(
+        out,
+        "        count: {}",
+        macro_or_value(metalib, entry.idx_count, entry.count)?
+    )?;
+    writeln!(
+        out,
+        "        version: {}",
+        macro_or_value(metalib, entry.idx_version, entry.version)?
+    )?;
+    if entry.idx_id != INVALID_METALIB_VALUE || entry.id != INVALID_METALIB_VALUE {
+        writeln!(
+            out,
+            "        id: {}",
+            macro_or_value(metalib, entry.idx_id, entry.id)?
+        )?;
+    }
+    if entry.flag.contains(TDRMetaEntryFlags::HAS_MAXMIN_ID) {
+        writeln!(
+            out,
+            "        minid: {}",
+            macro_or_value(metalib, entry.min_id_idx, entry.min_id)?
+        )?;
+        writeln!(
+            out,
+            "        maxid: {}",
+            macro_or_value(metalib, entry.max_id_idx, entry.max_id)?
+        )?;
+    }
+    writeln!(out, "        n_off: {}", entry.n_off)?;
+    writeln!(out, "        h_off: {}", entry.h_off)?;
+    writeln!(out, "        n_unit_size: {}", entry.n_unit_size)?;
+    writeln!(out, "        h_unit_size: {}", entry.h_unit_size)?;
+    if !entry.chinese_name.is_empty() {
+        writeln!(out, "        cname: {}", yaml_str(&entry.chinese_name))?;
+    }
+    if !entry.desc.is_empty() {
+        writeln!(out, "        desc: {}", yaml_str(&entry.desc))?;
+    }
+    if entry.ptr_default_val != INVALID_METALIB_VALUE {
+        writeln!(
+            out,
+            "        default: {}",
+            yaml_str(&entry.default_value_string)
+        )?;
+    }
+    writeln!(
+        out,
+        "        unique: {}",
+        entry.db_flag.contains(TDRMetaEntryDBFlags::UNIQUE)
+    )?;
+    writeln!(
+        out,
+        "        notnull: {}",
+        entry.db_flag.contains(TDRMetaEntryDBFlags::NOT_NULL)
+    )?;
+    writeln!(
+        out,
+        "        extendtotable: {}",
+        entry.db_flag.contains(TDRMetaEntryDBFlags::EXTEND_TO_TABLE)
+    )?;
+    if let Some(refer) = entry.referer.resolve(metalib, meta)? {
+        writeln!(out, "        refer: {}", yaml_str(&refer.path))?;
+    }
+    match entry.size_info.resolve(metalib, meta)? {
+        Some(SizeSource::TypePrefix(type_info)) => {
+            writeln!(out, "        sizeinfo: {}", yaml_str(type_info.xml_name))?;
+        }
+        Some(SizeSource::Field(field)) => {
+            writeln!(out, "        sizeinfo: {}", yaml_str(&field.path))?;
+        }
+        None => {}
+    }
+    if entry.type_ == MetaPrimativeType::UNION {
+        if let Some(select) = entry.selector.resolve(metalib, meta)? {
+            writeln!(out, "        select: {}", yaml_str(&select.path))?;
+        }
+    }
+    if entry.count > 1 {
+        match entry.order {
+            1 => writeln!(out, "        sortmethod: asc")?,
+            2 => writeln!(out, "        sortmethod: desc")?,
+            0 => {}
+            order => writeln!(out, "        sortmethod: {order}")?,
+        }
+    }
+    match entry.io {
+        0 => {}
+        1 => writeln!(out, "        io: noinput")?,
+        2 => writeln!(out, "        io: nooutput")?,
+        3 => writeln!(out, "        io: noio")?,
+        io => writeln!(out, "        io: {io}")?,
+    }
+    if entry.ptr_macros_group != INVALID_METALIB_VALUE {
+        let macrogroup = metalib.get_macrogroup_by_offset(entry.ptr_macros_group)?;
+        writeln!(
+            out,
+            "        bindmacrosgroup: {}",
+            yaml_str(&macrogroup.name)
+        )?;
+    }
+    writeln!(
+        out,
+        "        flags: {}",
+        yaml_flags(entry.flag, ENTRY_FLAG_NAMES, |f| f.bits() as u64)
+    )?;
+    writeln!(
+        out,
+        "        db_flags: {}",
+        yaml_flags(entry.db_flag, ENTRY_DB_FLAG_NAMES, |f| f.bits() as u64)
+    )?;
+    Ok(())
+}
+
+fn write_meta(out: &mut String, metalib: &Metalib, meta: &TDRMeta) -> Result<()> {
+    let kind = if meta.type_ == MetaPrimativeType::UNION {
+        "union"
+    } else {
+        "struct"
+    };
+    writeln!(out, "  - name: {}", yaml_str(&meta.name))?;
+    writeln!(out, "    kind: {kind}")?;
+    writeln!(
+        out,
+        "    version: {}",
+        macro_or_value(metalib, meta.idx_version, meta.base_version)?
+    )?;
+    if meta.flags.contains(TDRMetaFlags::HAS_ID) {
+        writeln!(
+            out,
+            "    id: {}",
+            macro_or_value(metalib, meta.idx_id, meta.id)?
+        )?;
+    }
+    if !meta.chinese_name.is_empty() {
+        writeln!(out, "    cname: {}", yaml_str(&meta.chinese_name))?;
+    }
+    if !meta.desc.is_empty() {
+        writeln!(out, "    desc: {}", yaml_str(&meta.desc))?;
+    }
+    if meta.type_ == MetaPrimativeType::STRUCT {
+        if meta.idx_custom_h_unit_size != INVALID_METALIB_VALUE || meta.custom_h_unit_size > 0 {
+            writeln!(
+                out,
+                "    size: {}",
+                macro_or_value(
+                    metalib,
+                    meta.idx_custom_h_unit_size,
+                    meta.custom_h_unit_size
+                )?
+            )?;
+        }
+        writeln!(out, "    align: {}", meta.custom_align)?;
+        if let Some(version_indicator) = meta.version_indicator.resolve(metalib, meta)? {
+            writeln!(
+                out,
+                "    versionindicator: {}",
+                yaml_str(&version_indicator.path)
+            )?;
+        }
+        match meta.size_type.resolve(metalib, meta)? {
+            Some(SizeSource::TypePrefix(type_info)) => {
+                writeln!(out, "    sizeinfo: {}", yaml_str(type_info.xml_name))?;
+            }
+            Some(SizeSource::Field(field)) => {
+                writeln!(out, "    sizeinfo: {}", yaml_str(&field.path))?;
+            }
+            None => {}
+        }
+    }
+    writeln!(
+        out,
+        "    flags: {}",
+        yaml_flags(meta.flags, META_FLAG_NAMES, |f| f.bits() as u64)
+    )?;
+
+    if meta.entries.is_empty() {
+        writeln!(out, "    entries: []")?;
+    } else {
+        writeln!(out, "    entries:")?;
+        for entry in meta.entries.iter() {
+            write_entry(out, metalib, meta, entry)?;
+        }
+    }
+    Ok(())
+}
+
+/// Generates a YAML document with the same logical model as the XML dump.
+///
+/// Numbers and booleans are written as YAML scalars rather than strings, macro references
+/// as the macro's name, and bit flags as lists of flag names.
+pub fn emit_yaml(metalib: &Metalib) -> Result<String> {
+    let mut out = String::new();
+    let header = &metalib.header;
+    writeln!(&mut out, "metalib:")?;
+    writeln!(&mut out, "  name: {}", yaml_str(&header.name))?;
+    writeln!(&mut out, "  tagsetversion: {}", header.xml_tag_set_ver)?;
+    writeln!(&mut out, "  version: {}", header.version)?;
+    if header.id != INVALID_METALIB_VALUE {
+        writeln!(&mut out, "  id: {}", header.id)?;
+    }
+
+    let mut free_macros = Vec::new();
+    for tdr_macro in metalib.macros.iter() {
+        if !metalib.is_macro_in_group(tdr_macro)? {
+            free_macros.push(tdr_macro);
+        }
+    }
+    if free_macros.is_empty() {
+        writeln!(&mut out, "macros: []")?;
+    } else {
+        writeln!(&mut out, "macros:")?;
+        for tdr_macro in free_macros {
+            write_macro(&mut out, "  ", tdr_macro)?;
+        }
+    }
+
+    if metalib.macrogroups.is_empty() {
+        writeln!(&mut out, "macrogroups: []")?;
+    } else {
+        writeln!(&mut out, "macrogroups:")?;
+    }
+    for macrogroup in metalib.macrogroups.iter() {
+        writeln!(&mut out, "  - name: {}", yaml_str(&macrogroup.name))?;
+        if !macrogroup.desc.is_empty() {
+            writeln!(&mut out, "    desc: {}", yaml_str(&macrogroup.desc))?;
+        }
+        if macrogroup.value_idx_map.is_empty() {
+            writeln!(&mut out, "    macros: []")?;
+            continue;
+        }
+        writeln!(&mut out, "    macros:")?;
+        for tdr_macro in metalib.macrogroup_macros(macrogroup) {
+            write_macro(&mut out, "      ", tdr_macro)?;
+        }
+    }
+
+    if metalib.metas.is_empty() {
+        writeln!(&mut out, "metas: []")?;
+    } else {
+        writeln!(&mut out, "metas:")?;
+    }
+    for meta in metalib.metas.iter() {
+        write_meta(&mut out, metalib, meta)?;
+    }
+
+    Ok(out)
+}
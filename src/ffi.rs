@@ -0,0 +1,167 @@
+//! A small C ABI over the parser, for calling it from C/C++ tools (built with the `ffi`
+//! feature). The header is `include/mldec.h`.
+//!
+//! A metalib is parsed into an opaque handle that must be released with [`mldec_free`].
+//! Strings returned to the caller are NUL-terminated UTF-8 owned by the caller, released
+//! with [`mldec_free_string`]. Functions that fail return NULL (or -1) and leave a
+//! description of the error for [`mldec_last_error`].
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use anyhow::{anyhow, Result};
+
+use crate::metalib::Metalib;
+use crate::xml::export_metalib_xml;
+
+/// A parsed metalib, opaque to C.
+pub struct MldecMetalib(Metalib);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: &anyhow::Error) {
+    // Messages can't hold a NUL, but strings from the metalib end up in them.
+    let message = format!("{error:#}").replace('\0', "\\0");
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, turning an error or a panic into `failed` and the last error.
+fn guard<T>(failed: T, f: impl FnOnce() -> Result<T>) -> T {
+    let result = catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(anyhow!("Panicked: {message}"))
+    });
+    result.unwrap_or_else(|error| {
+        set_last_error(&error);
+        failed
+    })
+}
+
+fn into_c_string(s: String) -> Result<*mut c_char> {
+    Ok(CString::new(s)?.into_raw())
+}
+
+/// The metalib behind `handle`, or an error if it's NULL.
+unsafe fn metalib<'a>(handle: *const MldecMetalib) -> Result<&'a Metalib> {
+    handle
+        .as_ref()
+        .map(|handle| &handle.0)
+        .ok_or_else(|| anyhow!("The handle is NULL"))
+}
+
+/// Parses the metalib at the start of the `len` bytes at `buf` and stores a handle to it in
+/// `*out_handle`. Returns 0 on success, or -1 with `*out_handle` set to NULL.
+///
+/// # Safety
+///
+/// `buf` must point to `len` readable bytes and `out_handle` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn mldec_parse(
+    buf: *const u8,
+    len: usize,
+    out_handle: *mut *mut MldecMetalib,
+) -> c_int {
+    if out_handle.is_null() {
+        set_last_error(&anyhow!("out_handle is NULL"));
+        return -1;
+    }
+    *out_handle = std::ptr::null_mut();
+    guard(-1, || {
+        if buf.is_null() {
+            return Err(anyhow!("The buffer is NULL"));
+        }
+        let data = std::slice::from_raw_parts(buf, len);
+        let metalib = Metalib::from_bytes(data)?;
+        *out_handle = Box::into_raw(Box::new(MldecMetalib(metalib)));
+        Ok(0)
+    })
+}
+
+/// Exports the metalib as TDR XML. Returns NULL on failure.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a handle from [`mldec_parse`] that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn mldec_export_xml(handle: *const MldecMetalib) -> *mut c_char {
+    guard(std::ptr::null_mut(), || {
+        into_c_string(export_metalib_xml(metalib(handle)?)?)
+    })
+}
+
+/// The number of structs and unions in the metalib, or 0 if `handle` is NULL.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a handle from [`mldec_parse`] that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn mldec_meta_count(handle: *const MldecMetalib) -> usize {
+    guard(0, || Ok(metalib(handle)?.metas.len()))
+}
+
+/// The name of the `idx`th struct or union, in file order. Returns NULL if `idx` is out of
+/// range.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a handle from [`mldec_parse`] that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn mldec_get_meta_name(
+    handle: *const MldecMetalib,
+    idx: usize,
+) -> *mut c_char {
+    guard(std::ptr::null_mut(), || {
+        let metalib = metalib(handle)?;
+        let meta = metalib.metas.get(idx).ok_or_else(|| {
+            anyhow!(
+                "Meta index {idx} is out of range ({} metas)",
+                metalib.metas.len()
+            )
+        })?;
+        into_c_string(meta.name.clone())
+    })
+}
+
+/// Frees a handle from [`mldec_parse`]. NULL is ignored.
+///
+/// # Safety
+///
+/// `handle` must be NULL or a handle from [`mldec_parse`] that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn mldec_free(handle: *mut MldecMetalib) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Frees a string returned by this library. NULL is ignored.
+///
+/// # Safety
+///
+/// `s` must be NULL or a string returned by this library that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn mldec_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// The error from the last call on this thread that failed, or NULL if none has. The
+/// string belongs to the library and stays valid until the next failing call on the
+/// same thread; don't free it.
+#[no_mangle]
+pub extern "C" fn mldec_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
@@ -0,0 +1,511 @@
+//! Enumerating every field of a meta as a dotted path, recursing into nested structs, and
+//! finding the field at a given offset.
+
+use anyhow::{anyhow, Result};
+
+use crate::metalib::{
+    MetaPrimativeType, Metalib, TDRMeta, TDRMetaEntry, TDRMetaEntryFlags, INVALID_METALIB_VALUE,
+};
+
+/// One field reached from the root meta, as returned by [`Metalib::iter_field_paths`].
+#[derive(Clone, Debug)]
+pub struct FieldPath<'a> {
+    /// Dotted path from the root meta, e.g. `header.sub.count` (or `items[2].id` when
+    /// expanding arrays).
+    pub path: String,
+    /// Meta the entry belongs to.
+    pub meta: &'a TDRMeta,
+    pub entry: &'a TDRMetaEntry,
+    /// Host offset from the start of the root meta.
+    pub abs_h_off: i64,
+    /// Network offset from the start of the root meta.
+    pub abs_n_off: i64,
+    /// 0 for the root meta's own entries, 1 for their members, and so on.
+    pub depth: usize,
+}
+
+/// A meta being walked, and how far through it we are.
+struct Frame<'a> {
+    meta: &'a TDRMeta,
+    prefix: String,
+    h_base: i64,
+    n_base: i64,
+    depth: usize,
+    entry: usize,
+    element: i32,
+}
+
+/// Iterator over the fields of a meta, depth first, each field before its members.
+///
+/// Struct-typed entries are followed through `ptr_meta`, except into a meta that's already
+/// being walked, so self-referential metas end rather than recursing forever.
+pub struct FieldPaths<'a> {
+    metalib: &'a Metalib,
+    stack: Vec<Frame<'a>>,
+    expand_arrays: bool,
+}
+
+impl<'a> FieldPaths<'a> {
+    /// Lists each element of an array entry separately (`items[0]`, `items[1]`, ...), with
+    /// its offsets advanced by the element size, instead of only the first.
+    pub fn expand_arrays(mut self, expand_arrays: bool) -> Self {
+        self.expand_arrays = expand_arrays;
+        self
+    }
+}
+
+impl<'a> Iterator for FieldPaths<'a> {
+    type Item = FieldPath<'a>;
+
+    fn next(&mut self) -> Option<FieldPath<'a>> {
+        loop {
+            let frame = self.stack.last_mut()?;
+            let Some(entry) = frame.meta.entries.get(frame.entry) else {
+                self.stack.pop();
+                continue;
+            };
+
+            let expand = self.expand_arrays && entry.count > 1;
+            let element = frame.element;
+            if expand && element + 1 < entry.count {
+                frame.element += 1;
+            } else {
+                frame.entry += 1;
+                frame.element = 0;
+            }
+
+            let path = if expand {
+                format!("{}{}[{element}]", frame.prefix, entry.name)
+            } else {
+                format!("{}{}", frame.prefix, entry.name)
+            };
+            let abs_h_off =
+                frame.h_base + entry.h_off as i64 + element as i64 * entry.h_unit_size as i64;
+            let abs_n_off =
+                frame.n_base + entry.n_off as i64 + element as i64 * entry.n_unit_size as i64;
+            let meta = frame.meta;
+            let depth = frame.depth;
+
+            if entry.ptr_meta != INVALID_METALIB_VALUE && !entry.is_zero_size() {
+                if let Ok(child) = self.metalib.get_meta_by_offset(entry.ptr_meta) {
+                    let walking = self
+                        .stack
+                        .iter()
+                        .any(|frame| frame.meta._offset == child._offset);
+                    if !walking {
+                        self.stack.push(Frame {
+                            meta: child,
+                            prefix: format!("{path}."),
+                            h_base: abs_h_off,
+                            n_base: abs_n_off,
+                            depth: depth + 1,
+                            entry: 0,
+                            element: 0,
+                        });
+                    }
+                }
+            }
+
+            return Some(FieldPath {
+                path,
+                meta,
+                entry,
+                abs_h_off,
+                abs_n_off,
+                depth,
+            });
+        }
+    }
+}
+
+/// The field found at an offset by [`Metalib::resolve_field_path_by_net_offset`] or
+/// [`Metalib::resolve_field_path_by_host_offset`].
+#[derive(Clone, Debug)]
+pub struct ResolvedField<'a> {
+    /// Dotted path from the meta the search started in, e.g. `header.len`.
+    pub path: String,
+    /// Meta the entry belongs to.
+    pub meta: &'a TDRMeta,
+    pub entry: &'a TDRMetaEntry,
+}
+
+/// Which layout an offset is in.
+#[derive(Clone, Copy)]
+enum Side {
+    Net,
+    Host,
+}
+
+impl Side {
+    /// Offset and size of one element of `entry`.
+    fn span(self, entry: &TDRMetaEntry) -> (i32, i32) {
+        match self {
+            Side::Net => (entry.n_off, entry.n_unit_size),
+            Side::Host => (entry.h_off, entry.h_unit_size),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Side::Net => "net",
+            Side::Host => "host",
+        }
+    }
+}
+
+/// Fails, naming the cycle, if `meta` is one of the metas in `walking` (the chain of structs
+/// a walk has descended through), since it would then contain itself.
+pub(crate) fn check_not_walking(walking: &[&TDRMeta], meta: &TDRMeta) -> Result<()> {
+    let Some(i) = walking.iter().position(|m| m._offset == meta._offset) else {
+        return Ok(());
+    };
+    let cycle: Vec<&str> = walking[i..]
+        .iter()
+        .chain([&meta])
+        .map(|m| m.name.as_str())
+        .collect();
+    Err(anyhow!(
+        "`{}` contains itself ({})",
+        meta.name,
+        cycle.join(" -> ")
+    ))
+}
+
+impl Metalib {
+    /// Finds the field starting at the network offset `offset` from the start of `meta`,
+    /// descending into nested structs. This is how `versionindicator`, `sizeinfo` and
+    /// `sortkey` refer to fields.
+    pub fn resolve_field_path_by_net_offset<'a>(
+        &'a self,
+        meta: &'a TDRMeta,
+        offset: i32,
+    ) -> Result<ResolvedField<'a>> {
+        self.resolve_field_path(meta, offset, Side::Net)
+    }
+
+    /// Finds the field starting at the host offset `offset` from the start of `meta`,
+    /// descending into nested structs. This is how `refer` and `select` refer to fields.
+    pub fn resolve_field_path_by_host_offset<'a>(
+        &'a self,
+        meta: &'a TDRMeta,
+        offset: i32,
+    ) -> Result<ResolvedField<'a>> {
+        self.resolve_field_path(meta, offset, Side::Host)
+    }
+
+    /// An entry covers `start..start + size` (of one element). Struct entries covering
+    /// `offset` are descended into; any other entry, pointers included, must start exactly
+    /// at `offset`. Entries
+    /// with no size on this side cover nothing, and only match an exact offset if no sized
+    /// entry covers it.
+    ///
+    /// A struct that (through corruption) contains itself is an error naming the cycle,
+    /// rather than being descended into forever.
+    fn resolve_field_path<'a>(
+        &'a self,
+        meta: &'a TDRMeta,
+        offset: i32,
+        side: Side,
+    ) -> Result<ResolvedField<'a>> {
+        let mut meta = meta;
+        let offset = offset as i64;
+        let mut base = 0i64;
+        let mut prefix = String::new();
+        let mut walking = vec![meta];
+        'walk: loop {
+            let mut zero_size_hit = None;
+            for entry in meta.entries.iter() {
+                // i64, so that corrupt offsets and sizes near i32::MAX can't overflow.
+                let (off, size) = side.span(entry);
+                let (start, size) = (base + off as i64, size as i64);
+                if size == 0 {
+                    if start == offset && zero_size_hit.is_none() {
+                        zero_size_hit = Some(entry);
+                    }
+                    continue;
+                }
+                if offset < start || offset >= start + size {
+                    continue;
+                }
+
+                // A pointer only holds an address, not the struct.
+                if entry.type_ == MetaPrimativeType::STRUCT
+                    && !entry.flag.contains(TDRMetaEntryFlags::POINT_TYPE)
+                {
+                    meta = self.get_meta_by_offset(entry.ptr_meta)?;
+                    check_not_walking(&walking, meta)?;
+                    walking.push(meta);
+                    base = start;
+                    prefix = format!("{prefix}{}.", entry.name);
+                    continue 'walk;
+                }
+                if start == offset {
+                    return Ok(ResolvedField {
+                        path: format!("{prefix}{}", entry.name),
+                        meta,
+                        entry,
+                    });
+                }
+            }
+
+            return match zero_size_hit {
+                Some(entry) => Ok(ResolvedField {
+                    path: format!("{prefix}{}", entry.name),
+                    meta,
+                    entry,
+                }),
+                None => Err(anyhow!(
+                    "No field of `{}` starts at {} offset {offset}",
+                    meta.name,
+                    side.name()
+                )),
+            };
+        }
+    }
+
+    /// Iterates over every field of `meta`, including the members of nested structs, with
+    /// dotted paths and offsets relative to the start of `meta`.
+    pub fn iter_field_paths<'a>(&'a self, meta: &'a TDRMeta) -> FieldPaths<'a> {
+        FieldPaths {
+            metalib: self,
+            stack: vec![Frame {
+                meta,
+                prefix: String::new(),
+                h_base: 0,
+                n_base: 0,
+                depth: 0,
+                entry: 0,
+                element: 0,
+            }],
+            expand_arrays: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{compile, parse, FULL_XML};
+
+    const POINTERS_XML: &str = r#"<metalib tagsetversion="1" name="zero" version="1">
+        <struct name="Node" version="1">
+            <entry name="a" type="int"/>
+            <entry name="link" type="*Node"/>
+            <entry name="b" type="int"/>
+            <entry name="tail" type="*Node"/>
+        </struct>
+    </metalib>"#;
+
+    #[test]
+    fn zero_size_entries_match_exact_offsets() {
+        let metalib = compile(POINTERS_XML);
+        let meta = &metalib.metas[0];
+        assert!(meta.entries[1].is_zero_size());
+        assert!(meta.entries[3].is_zero_size());
+
+        // `link` shares its offset with `b`, which has the storage and so wins.
+        let b = metalib.resolve_field_path_by_net_offset(meta, 4).unwrap();
+        assert_eq!(b.path, "b");
+        // Nothing sized starts at the end, so the trailing pointer matches there.
+        let tail = metalib.resolve_field_path_by_net_offset(meta, 8).unwrap();
+        assert_eq!(tail.path, "tail");
+        assert!(metalib.resolve_field_path_by_net_offset(meta, 9).is_err());
+    }
+
+    #[test]
+    fn zero_size_entries_are_not_descended_into() {
+        let metalib = compile(POINTERS_XML);
+        let paths: Vec<String> = metalib
+            .iter_field_paths(&metalib.metas[0])
+            .map(|field| field.path)
+            .collect();
+        assert_eq!(paths, ["a", "link", "b", "tail"]);
+    }
+
+    const NESTED_XML: &str = r#"<metalib tagsetversion="1" name="nested" version="1">
+        <struct name="Leaf" version="1">
+            <entry name="w" type="int"/>
+        </struct>
+        <struct name="Inner" version="1" align="4">
+            <entry name="y" type="uint16"/>
+            <entry name="z" type="Leaf"/>
+        </struct>
+        <struct name="Outer" version="1" align="4">
+            <entry name="x" type="uint8"/>
+            <entry name="inner" type="Inner" count="2"/>
+        </struct>
+    </metalib>"#;
+
+    fn fields(metalib: &crate::metalib::Metalib, name: &str, expand: bool) -> Vec<String> {
+        let meta = metalib.get_meta_by_name(name).unwrap();
+        metalib
+            .iter_field_paths(meta)
+            .expand_arrays(expand)
+            .map(|f| format!("{} h{} n{} d{}", f.path, f.abs_h_off, f.abs_n_off, f.depth))
+            .collect()
+    }
+
+    #[test]
+    fn nested_field_paths() {
+        let metalib = parse(NESTED_XML);
+        assert_eq!(
+            fields(&metalib, "Outer", false),
+            [
+                "x h0 n0 d0",
+                "inner h2 n1 d0",
+                "inner.y h2 n1 d1",
+                "inner.z h4 n3 d1",
+                "inner.z.w h4 n3 d2",
+            ]
+        );
+        assert_eq!(
+            fields(&metalib, "Outer", true),
+            [
+                "x h0 n0 d0",
+                "inner[0] h2 n1 d0",
+                "inner[0].y h2 n1 d1",
+                "inner[0].z h4 n3 d1",
+                "inner[0].z.w h4 n3 d2",
+                "inner[1] h8 n7 d0",
+                "inner[1].y h8 n7 d1",
+                "inner[1].z h10 n9 d1",
+                "inner[1].z.w h10 n9 d2",
+            ]
+        );
+
+        let metalib = parse(FULL_XML);
+        let items: Vec<String> = fields(&metalib, "Packet", true)
+            .into_iter()
+            .filter(|f| f.starts_with("items"))
+            .collect();
+        assert_eq!(items.len(), 8);
+        assert_eq!(items[3], "items[3] h36 n23 d0");
+    }
+
+    fn resolve(metalib: &crate::metalib::Metalib, offset: i32, host: bool) -> String {
+        let outer = metalib.get_meta_by_name("Outer").unwrap();
+        let found = match host {
+            true => metalib.resolve_field_path_by_host_offset(outer, offset),
+            false => metalib.resolve_field_path_by_net_offset(outer, offset),
+        };
+        match found {
+            Ok(field) => field.path,
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn resolve_nested_offsets() {
+        let metalib = parse(NESTED_XML);
+        assert_eq!(resolve(&metalib, 0, true), "x");
+        assert_eq!(resolve(&metalib, 0, false), "x");
+        assert_eq!(resolve(&metalib, 2, true), "inner.y");
+        assert_eq!(resolve(&metalib, 1, false), "inner.y");
+        assert_eq!(resolve(&metalib, 4, true), "inner.z.w");
+        assert_eq!(resolve(&metalib, 3, false), "inner.z.w");
+
+        // Padding, and the middle of a field, start no field.
+        assert_eq!(
+            resolve(&metalib, 1, true),
+            "No field of `Outer` starts at host offset 1"
+        );
+        assert_eq!(
+            resolve(&metalib, 3, true),
+            "No field of `Inner` starts at host offset 3"
+        );
+        assert_eq!(
+            resolve(&metalib, 100, false),
+            "No field of `Outer` starts at net offset 100"
+        );
+    }
+
+    #[test]
+    fn resolve_corrupt_offsets_without_overflow() {
+        let mut metalib = parse(NESTED_XML);
+        let idx = metalib
+            .metas
+            .iter()
+            .position(|m| m.name == "Outer")
+            .unwrap();
+        let inner = &mut metalib.metas[idx].entries[1];
+        inner.h_off = i32::MAX - 1;
+        inner.h_unit_size = i32::MAX;
+        metalib.reindex();
+
+        assert_eq!(
+            resolve(&metalib, i32::MAX, true),
+            "No field of `Inner` starts at host offset 2147483647"
+        );
+        assert_eq!(resolve(&metalib, i32::MAX - 1, true), "inner.y");
+        assert_eq!(
+            resolve(&metalib, i32::MIN, true),
+            "No field of `Outer` starts at host offset -2147483648"
+        );
+    }
+
+    #[test]
+    fn self_referential_meta_ends() {
+        let mut metalib = parse(NESTED_XML);
+        let inner = metalib.get_meta_by_name("Inner").unwrap()._offset as i32;
+        let idx = metalib
+            .metas
+            .iter()
+            .position(|m| m.name == "Inner")
+            .unwrap();
+        metalib.metas[idx].entries[1].ptr_meta = inner;
+        metalib.reindex();
+
+        assert_eq!(
+            fields(&metalib, "Outer", false),
+            [
+                "x h0 n0 d0",
+                "inner h2 n1 d0",
+                "inner.y h2 n1 d1",
+                "inner.z h4 n3 d1"
+            ]
+        );
+        assert_eq!(
+            fields(&metalib, "Inner", false),
+            ["y h0 n0 d0", "z h2 n2 d0"]
+        );
+
+        let outer = metalib.get_meta_by_name("Outer").unwrap();
+        let err = metalib
+            .resolve_field_path_by_host_offset(outer, 4)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "`Inner` contains itself (Inner -> Inner)");
+    }
+
+    #[test]
+    fn self_referential_meta_in_every_walker() {
+        let mut metalib = parse(NESTED_XML);
+        let inner = metalib.get_meta_by_name("Inner").unwrap()._offset as i32;
+        let idx = metalib
+            .metas
+            .iter()
+            .position(|m| m.name == "Inner")
+            .unwrap();
+        metalib.metas[idx].entries[1].ptr_meta = inner;
+        metalib.reindex();
+
+        // Each either copes or fails; none of them may recurse forever.
+        let _ = crate::xml::export_metalib_xml(&metalib);
+        let _ = crate::codegen::emit_rust(&metalib);
+        let _ = crate::codegen::emit_csharp(&metalib);
+        let _ = crate::codegen::emit_python(&metalib);
+        let _ = crate::codegen::emit_proto(&metalib);
+        let _ = crate::codegen::emit_fbs(&metalib);
+        let _ = crate::codegen::emit_ksy(&metalib);
+        let _ = crate::export::emit_dot(&metalib);
+        let _ = crate::export::emit_yaml(&metalib);
+        let _ = crate::export::emit_html(&metalib);
+        let _ = crate::export::emit_markdown(&metalib);
+        let _ = crate::export::emit_csv(&metalib);
+        let _ = metalib.validate();
+
+        let err = crate::codegen::emit_sql(&metalib).unwrap_err();
+        assert!(err.to_string().contains("Inner"), "{err:#}");
+        let outer = metalib.get_meta_by_name("Outer").unwrap();
+        let err = crate::codec::decode_host(&metalib, outer, &[0; 4096], None).unwrap_err();
+        assert!(format!("{err:#}").contains("Inner"), "{err:#}");
+    }
+}
@@ -0,0 +1,121 @@
+//! Searching the entries of every meta, for audits like "every string without a sizeinfo"
+//! (`find` subcommand).
+
+use anyhow::{anyhow, Result};
+
+use crate::metalib::{
+    MetaPrimativeType, Metalib, TDRMeta, TDRMetaEntry, TDRMetaEntryDBFlags,
+    TDR_PRIMATIVE_TYPE_INFO,
+};
+
+/// Parses a type as written in the XML `type` attribute, e.g. `string` or `uint16`.
+pub fn parse_entry_type(name: &str) -> Result<MetaPrimativeType> {
+    TDR_PRIMATIVE_TYPE_INFO
+        .iter()
+        .find(|info| info.xml_name.eq_ignore_ascii_case(name))
+        .map(|info| info.primative_type)
+        .ok_or_else(|| anyhow!("Unknown type `{name}`"))
+}
+
+/// Parses a DB flag by its XML attribute name: `unique`, `notnull`, `extendtotable`,
+/// `primarykey` or `autoincrement`.
+pub fn parse_db_flag(name: &str) -> Result<TDRMetaEntryDBFlags> {
+    match name.to_ascii_lowercase().as_str() {
+        "unique" => Ok(TDRMetaEntryDBFlags::UNIQUE),
+        "notnull" => Ok(TDRMetaEntryDBFlags::NOT_NULL),
+        "extendtotable" => Ok(TDRMetaEntryDBFlags::EXTEND_TO_TABLE),
+        "primarykey" => Ok(TDRMetaEntryDBFlags::PRIMARY_KEY),
+        "autoincrement" => Ok(TDRMetaEntryDBFlags::AUTO_INCREMENT),
+        _ => Err(anyhow!(
+            "Unknown flag `{name}`, expected unique, notnull, extendtotable, primarykey or autoincrement"
+        )),
+    }
+}
+
+impl Metalib {
+    /// Returns every entry matching `pred`, in metalib order.
+    pub fn find_entries(
+        &self,
+        pred: impl Fn(&TDRMeta, &TDRMetaEntry) -> bool,
+    ) -> Vec<(&TDRMeta, &TDRMetaEntry)> {
+        self.metas
+            .iter()
+            .flat_map(|meta| meta.entries.iter().map(move |entry| (meta, entry)))
+            .filter(|(meta, entry)| pred(meta, entry))
+            .collect()
+    }
+
+    /// Returns every entry of the given type.
+    pub fn find_entries_by_type(
+        &self,
+        type_: MetaPrimativeType,
+    ) -> Vec<(&TDRMeta, &TDRMetaEntry)> {
+        self.find_entries(|_, entry| entry.type_ == type_)
+    }
+
+    /// Returns every entry with all of the given DB flags set.
+    pub fn find_entries_with_db_flag(
+        &self,
+        flag: TDRMetaEntryDBFlags,
+    ) -> Vec<(&TDRMeta, &TDRMetaEntry)> {
+        self.find_entries(|_, entry| entry.db_flag.contains(flag))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{parse, FULL_XML, TYPES_XML};
+
+    fn paths(found: Vec<(&TDRMeta, &TDRMetaEntry)>) -> Vec<String> {
+        found
+            .into_iter()
+            .map(|(meta, entry)| format!("{}.{}", meta.name, entry.name))
+            .collect()
+    }
+
+    #[test]
+    fn find_by_type_and_flag() {
+        let metalib = parse(FULL_XML);
+        assert_eq!(
+            paths(metalib.find_entries_by_type(MetaPrimativeType::STRING)),
+            ["Body.b", "Packet.name"]
+        );
+        assert_eq!(
+            paths(metalib.find_entries_with_db_flag(TDRMetaEntryDBFlags::NOT_NULL)),
+            ["Packet.name", "Account.uin"]
+        );
+        assert_eq!(
+            paths(metalib.find_entries_with_db_flag(
+                TDRMetaEntryDBFlags::UNIQUE | TDRMetaEntryDBFlags::NOT_NULL
+            )),
+            ["Packet.name"]
+        );
+        assert!(metalib
+            .find_entries_with_db_flag(TDRMetaEntryDBFlags::AUTO_INCREMENT)
+            .is_empty());
+
+        // Every struct-typed entry (not the union), in metalib order.
+        let structs = metalib.find_entries(|_, entry| entry.type_ == MetaPrimativeType::STRUCT);
+        assert_eq!(
+            paths(structs),
+            ["Packet.head", "Packet.next", "Account.lastpacket"]
+        );
+        let in_account = metalib.find_entries(|meta, _| meta.name == "Account");
+        assert_eq!(in_account.len(), 8);
+    }
+
+    #[test]
+    fn type_and_flag_names() {
+        let metalib = parse(TYPES_XML);
+        for (meta, entry) in metalib.find_entries(|_, _| true) {
+            let name = metalib.entry_type_name(entry).unwrap();
+            assert_eq!(parse_entry_type(&name).unwrap(), entry.type_, "{}", meta.name);
+        }
+        assert_eq!(parse_entry_type("UInt16").unwrap(), MetaPrimativeType::USHORT);
+        assert!(parse_entry_type("Header").is_err());
+
+        assert_eq!(parse_db_flag("PrimaryKey").unwrap(), TDRMetaEntryDBFlags::PRIMARY_KEY);
+        assert!(parse_db_flag("indexed").is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Header-only summary of a metalib, for the `info` subcommand.
+//!
+//! Only the fixed-size header is required, so this still works when the body tables are
+//! corrupt. With `--full` the body is also read (as much of it as exists) and run through
+//! the layout probe.
+
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::arch::{probe_arch, ArchProbe, MetalibArch};
+use crate::metalib::{read_metalib_header, MetalibHeader};
+use crate::export::json_string;
+
+/// Header of a metalib, plus the layout probe when requested.
+pub struct MetalibInfo {
+    /// Offset of the header within the file.
+    pub offset: u64,
+    pub header: MetalibHeader,
+
+    /// Body bytes actually available, if the body was read.
+    pub body_read: Option<u64>,
+    pub probe: Option<ArchProbe>,
+}
+
+impl MetalibInfo {
+    /// Reads the header at `offset`, and with `full` the body and layout probe too.
+    pub fn read<R: Read + Seek>(file: &mut R, offset: u64, full: bool) -> Result<MetalibInfo> {
+        file.seek(SeekFrom::Start(offset))?;
+        let header = read_metalib_header(file)
+            .with_context(|| format!("Failed to read metalib header at {offset:#X}"))?;
+
+        let mut info = MetalibInfo {
+            offset,
+            header,
+            body_read: None,
+            probe: None,
+        };
+        if full {
+            file.seek(SeekFrom::Start(offset + info.header.header_size() as u64))?;
+            let mut body = Vec::new();
+            file.take(info.body_size())
+                .read_to_end(&mut body)
+                .context("Failed to read metalib body")?;
+            info.body_read = Some(body.len() as u64);
+            info.probe = Some(probe_arch(&info.header, &body));
+        }
+        Ok(info)
+    }
+
+    /// Size of the body as claimed by the header.
+    fn body_size(&self) -> u64 {
+        self.header.body_size() as u64
+    }
+
+    /// Bytes of the string table in use.
+    fn str_buf_used(&self) -> i64 {
+        self.header.ptr_free_str_buf as i64 - self.header.ptr_str_buf as i64
+    }
+
+    fn arch_name(&self) -> String {
+        MetalibArch::from_platform_arch(self.header.platform_arch)
+            .map_or("unknown".to_string(), |arch| arch.to_string())
+    }
+
+    /// Formats the summary for people.
+    pub fn to_text(&self) -> Result<String> {
+        let header = &self.header;
+        let mut out = String::new();
+        writeln!(&mut out, "Offset:            {:#X}", self.offset)?;
+        writeln!(&mut out, "Name:              {}", header.name)?;
+        writeln!(&mut out, "ID:                {}", header.id)?;
+        writeln!(&mut out, "Version:           {}", header.version)?;
+        writeln!(&mut out, "Tag set version:   {}", header.xml_tag_set_ver)?;
+        writeln!(
+            &mut out,
+            "Platform arch:     {:#X} ({})",
+            header.platform_arch,
+            self.arch_name()
+        )?;
+        writeln!(
+            &mut out,
+            "Build:             {} ({:#X} byte header)",
+            header.build,
+            header.header_size()
+        )?;
+        writeln!(&mut out, "Size:              {} bytes", header.size)?;
+        writeln!(
+            &mut out,
+            "Metas:             {} of {}",
+            header.cur_meta_num, header.max_meta_num
+        )?;
+        writeln!(
+            &mut out,
+            "Macros:            {} of {}",
+            header.cur_macro_num, header.max_macro_num
+        )?;
+        writeln!(
+            &mut out,
+            "Macro groups:      {} of {}",
+            header.cur_macros_group_num, header.max_macros_group_num
+        )?;
+        writeln!(
+            &mut out,
+            "String table:      {:#X}..{:#X} ({} bytes used), free_str_buf_size {}",
+            header.ptr_str_buf,
+            header.ptr_free_str_buf,
+            self.str_buf_used(),
+            header.free_str_buf_size
+        )?;
+
+        if let Some(body_read) = self.body_read {
+            if body_read < self.body_size() {
+                writeln!(
+                    &mut out,
+                    "Body:              truncated, {} of {} bytes present",
+                    body_read,
+                    self.body_size()
+                )?;
+            }
+        }
+        if let Some(probe) = &self.probe {
+            out.push_str(&format_arch_probe(probe)?);
+        }
+        Ok(out)
+    }
+
+    /// Formats the summary as a JSON object.
+    pub fn to_json(&self) -> Result<String> {
+        let header = &self.header;
+        let mut out = String::new();
+        writeln!(&mut out, "{{")?;
+        writeln!(&mut out, "  \"offset\": {},", self.offset)?;
+        writeln!(&mut out, "  \"name\": {},", json_string(&header.name))?;
+        writeln!(&mut out, "  \"id\": {},", header.id)?;
+        writeln!(&mut out, "  \"version\": {},", header.version)?;
+        writeln!(
+            &mut out,
+            "  \"xml_tag_set_ver\": {},",
+            header.xml_tag_set_ver
+        )?;
+        writeln!(&mut out, "  \"platform_arch\": {},", header.platform_arch)?;
+        writeln!(&mut out, "  \"arch\": {},", json_string(&self.arch_name()))?;
+        writeln!(&mut out, "  \"build\": {},", header.build)?;
+        writeln!(&mut out, "  \"size\": {},", header.size)?;
+        writeln!(
+            &mut out,
+            "  \"metas\": {{ \"cur\": {}, \"max\": {} }},",
+            header.cur_meta_num, header.max_meta_num
+        )?;
+        writeln!(
+            &mut out,
+            "  \"macros\": {{ \"cur\": {}, \"max\": {} }},",
+            header.cur_macro_num, header.max_macro_num
+        )?;
+        writeln!(
+            &mut out,
+            "  \"macro_groups\": {{ \"cur\": {}, \"max\": {} }},",
+            header.cur_macros_group_num, header.max_macros_group_num
+        )?;
+        write!(
+            &mut out,
+            "  \"string_table\": {{ \"used\": {}, \"free_str_buf_size\": {}, \"ptr_str_buf\": {}, \"ptr_free_str_buf\": {} }}",
+            self.str_buf_used(),
+            header.free_str_buf_size,
+            header.ptr_str_buf,
+            header.ptr_free_str_buf
+        )?;
+
+        if let Some(body_read) = self.body_read {
+            write!(&mut out, ",\n  \"body_read\": {body_read}")?;
+        }
+        if let Some(probe) = &self.probe {
+            write!(&mut out, ",\n  \"probe\": {}", arch_probe_json(probe))?;
+        }
+        writeln!(&mut out, "\n}}")?;
+        Ok(out)
+    }
+}
+
+fn arch_json(arch: Option<MetalibArch>) -> String {
+    arch.map_or("null".to_string(), |arch| json_string(&arch.to_string()))
+}
+
+fn arch_probe_json(probe: &ArchProbe) -> String {
+    let candidates: Vec<String> = probe
+        .candidates
+        .iter()
+        .map(|candidate| {
+            let notes: Vec<String> = candidate.notes.iter().map(|n| json_string(n)).collect();
+            format!(
+                "{{ \"arch\": {}, \"score\": {}, \"notes\": [{}] }}",
+                json_string(&candidate.arch.to_string()),
+                candidate.score,
+                notes.join(", ")
+            )
+        })
+        .collect();
+    format!(
+        "{{ \"claimed\": {}, \"detected\": {}, \"candidates\": [{}] }}",
+        arch_json(probe.claimed),
+        arch_json(probe.detected),
+        candidates.join(", ")
+    )
+}
+
+/// Formats the score and reasoning behind each candidate layout.
+pub fn format_arch_probe(probe: &ArchProbe) -> Result<String> {
+    let claimed = probe
+        .claimed
+        .map_or("unknown".to_string(), |arch| arch.to_string());
+    let detected = probe
+        .detected
+        .map_or("inconclusive".to_string(), |arch| arch.to_string());
+    let mut out = format!("Layout probe: header claims {claimed}, data suggests {detected}\n");
+    for candidate in probe.candidates.iter() {
+        writeln!(
+            &mut out,
+            "  {} (score {}):",
+            candidate.arch, candidate.score
+        )?;
+        for note in candidate.notes.iter() {
+            writeln!(&mut out, "    {note}")?;
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,127 @@
+//! Opening the input named on the command line, where `-` means stdin.
+//!
+//! The parser needs to seek, so stdin is read in full first: into memory, or into a
+//! temporary file once it grows past [`STDIN_MEMORY_LIMIT`].
+
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Input name that reads from stdin.
+pub const STDIN: &str = "-";
+
+/// Most stdin bytes held in memory before spilling to a temporary file.
+const STDIN_MEMORY_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// Returns true if `path` names stdin.
+pub fn is_stdin(path: &str) -> bool {
+    path == STDIN
+}
+
+/// Name used for output files derived from `path`: the file stem, or `stdin`.
+pub fn file_stem(path: &str) -> String {
+    if is_stdin(path) {
+        return "stdin".to_string();
+    }
+    Path::new(path)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Reads all of `path` (or stdin).
+pub fn read_all(path: &str) -> Result<Vec<u8>> {
+    if is_stdin(path) {
+        let mut data = Vec::new();
+        io::stdin()
+            .lock()
+            .read_to_end(&mut data)
+            .context("Failed to read stdin")?;
+        return Ok(data);
+    }
+    fs::read(path).with_context(|| format!("Failed to read {path}"))
+}
+
+/// A seekable input.
+pub enum Input {
+    File(BufReader<File>),
+    Memory(Cursor<Vec<u8>>),
+    /// Stdin spilled to disk, removed on drop.
+    Spilled(BufReader<File>, PathBuf),
+}
+
+impl Input {
+    /// Opens `path`, or buffers stdin for `-`.
+    pub fn open(path: &str) -> Result<Input> {
+        if is_stdin(path) {
+            return Input::buffer_stdin();
+        }
+        let file = File::open(path).with_context(|| format!("Failed to open {path}"))?;
+        Ok(Input::File(BufReader::new(file)))
+    }
+
+    fn buffer_stdin() -> Result<Input> {
+        let mut stdin = io::stdin().lock();
+        let mut data = Vec::new();
+        (&mut stdin)
+            .take(STDIN_MEMORY_LIMIT + 1)
+            .read_to_end(&mut data)
+            .context("Failed to read stdin")?;
+        if data.len() as u64 <= STDIN_MEMORY_LIMIT {
+            return Ok(Input::Memory(Cursor::new(data)));
+        }
+
+        let path = std::env::temp_dir().join(format!("mldec-stdin-{}", std::process::id()));
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        // Built before copying so the file is removed even if that fails.
+        let spill = |file| Input::Spilled(BufReader::new(file), path.clone());
+        let copied = file
+            .write_all(&data)
+            .and_then(|_| io::copy(&mut stdin, &mut file))
+            .and_then(|_| file.seek(SeekFrom::Start(0)));
+        let input = spill(file);
+        copied.context("Failed to buffer stdin")?;
+        Ok(input)
+    }
+
+    /// Total length of the input.
+    pub fn len(&mut self) -> Result<u64> {
+        let pos = self.stream_position()?;
+        let len = self.seek(SeekFrom::End(0))?;
+        self.seek(SeekFrom::Start(pos))?;
+        Ok(len)
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Input::File(file) | Input::Spilled(file, _) => file.read(buf),
+            Input::Memory(cursor) => cursor.read(buf),
+        }
+    }
+}
+
+impl Seek for Input {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Input::File(file) | Input::Spilled(file, _) => file.seek(pos),
+            Input::Memory(cursor) => cursor.seek(pos),
+        }
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        if let Input::Spilled(_, path) = self {
+            _ = fs::remove_file(path);
+        }
+    }
+}
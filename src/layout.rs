@@ -0,0 +1,232 @@
+//! Host memory layout of a meta: where a field like `player.items[3].id` lives, and which
+//! field is at a given offset (`offsetof` subcommand).
+//!
+//! Everything comes from the compiled offsets and sizes, which already include the padding
+//! from `align` and the element sizes from `size`, so array strides are `h_unit_size`.
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::fields::check_not_walking;
+use crate::metalib::{MetaPrimativeType, Metalib, TDRMeta, TDRMetaEntry, TDRMetaEntryFlags};
+
+/// A field found by [`resolve_path`] or [`field_at`].
+#[derive(Clone, Debug)]
+pub struct FieldLocation<'a> {
+    /// Dotted path with array indexes, e.g. `items[3].id`.
+    pub path: String,
+    /// Host offset from the start of the root meta.
+    pub offset: i64,
+    /// Host size: one element if indexed, otherwise the whole entry.
+    pub size: i64,
+    pub type_: MetaPrimativeType,
+    pub entry: &'a TDRMetaEntry,
+}
+
+/// Splits `name[3]` into the name and index.
+fn parse_component(component: &str) -> Result<(&str, Option<i32>)> {
+    let Some((name, rest)) = component.split_once('[') else {
+        return Ok((component, None));
+    };
+    let index = rest
+        .strip_suffix(']')
+        .and_then(|index| index.parse().ok())
+        .ok_or_else(|| anyhow!("Invalid array index in `{component}`"))?;
+    Ok((name, Some(index)))
+}
+
+/// The meta a struct or union entry holds, if it holds one in place (not via a pointer).
+fn nested_meta<'a>(metalib: &'a Metalib, entry: &TDRMetaEntry) -> Option<&'a TDRMeta> {
+    if entry.flag.contains(TDRMetaEntryFlags::POINT_TYPE) {
+        return None;
+    }
+    metalib.get_meta_by_offset(entry.ptr_meta).ok()
+}
+
+/// Finds the field at a dotted path like `a.b[2].c` from the start of `meta`. Unindexed
+/// arrays resolve to the whole array; union members all share the union's offset.
+pub fn resolve_path<'a>(
+    metalib: &'a Metalib,
+    meta: &'a TDRMeta,
+    path: &str,
+) -> Result<FieldLocation<'a>> {
+    let mut current = meta;
+    let mut offset = 0i64;
+    let mut components = path.split('.').peekable();
+    while let Some(component) = components.next() {
+        let (name, index) = parse_component(component)?;
+        let entry = current
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| anyhow!("`{}` has no entry `{name}`", current.name))?;
+
+        offset += entry.h_off as i64;
+        let mut size = entry.h_real_size as i64;
+        if let Some(index) = index {
+            if index < 0 || index >= entry.count {
+                return Err(anyhow!(
+                    "Index {index} is out of range for `{}.{name}` ({} elements)",
+                    current.name,
+                    entry.count
+                ));
+            }
+            offset += index as i64 * entry.h_unit_size as i64;
+            size = entry.h_unit_size as i64;
+        }
+
+        if components.peek().is_none() {
+            return Ok(FieldLocation {
+                path: path.to_string(),
+                offset,
+                size,
+                type_: entry.type_,
+                entry,
+            });
+        }
+        if entry.count > 1 && index.is_none() {
+            return Err(anyhow!("`{component}` is an array, give an index"));
+        }
+        current = nested_meta(metalib, entry)
+            .with_context(|| format!("`{component}` isn't a struct or union"))?;
+    }
+    Err(anyhow!("Empty path"))
+}
+
+/// Finds the innermost field containing host offset `offset` from the start of `meta`,
+/// with the array element it's in. The returned offset is where that field starts.
+/// Offsets in a nested union resolve to the union entry, since the member in use isn't
+/// known. A struct that contains itself is an error naming the cycle.
+pub fn field_at<'a>(
+    metalib: &'a Metalib,
+    meta: &'a TDRMeta,
+    offset: i64,
+) -> Result<FieldLocation<'a>> {
+    let mut current = meta;
+    let mut base = 0i64;
+    let mut prefix = String::new();
+    let mut walking = vec![meta];
+    'walk: loop {
+        for entry in current.entries.iter() {
+            let start = base + entry.h_off as i64;
+            let unit = entry.h_unit_size as i64;
+            if unit == 0 || offset < start || offset >= start + entry.h_real_size as i64 {
+                continue;
+            }
+
+            let index = (offset - start) / unit;
+            let element_start = start + index * unit;
+            let path = match entry.count > 1 {
+                true => format!("{prefix}{}[{index}]", entry.name),
+                false => format!("{prefix}{}", entry.name),
+            };
+            if let Some(child) = nested_meta(metalib, entry) {
+                if child.type_ == MetaPrimativeType::STRUCT {
+                    check_not_walking(&walking, child)?;
+                    walking.push(child);
+                    current = child;
+                    base = element_start;
+                    prefix = format!("{path}.");
+                    continue 'walk;
+                }
+            }
+            return Ok(FieldLocation {
+                path,
+                offset: element_start,
+                size: unit,
+                type_: entry.type_,
+                entry,
+            });
+        }
+        return Err(anyhow!(
+            "No field of `{}` contains host offset {offset}",
+            meta.name
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{parse, FULL_XML};
+
+    fn located(location: Result<FieldLocation>) -> String {
+        match location {
+            Ok(field) => format!(
+                "{} {}+{} {:?}",
+                field.path, field.offset, field.size, field.type_
+            ),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn arrays() {
+        let metalib = parse(FULL_XML);
+        let packet = metalib.get_meta_by_name("Packet").unwrap();
+        let path = |path| located(resolve_path(&metalib, packet, path));
+        let at = |offset| located(field_at(&metalib, packet, offset));
+
+        assert_eq!(path("items"), "items 24+32 UINT");
+        assert_eq!(path("items[0]"), "items[0] 24+4 UINT");
+        assert_eq!(path("items[7]"), "items[7] 52+4 UINT");
+        assert_eq!(
+            path("items[8]"),
+            "Index 8 is out of range for `Packet.items` (8 elements)"
+        );
+        assert_eq!(path("items[x]"), "Invalid array index in `items[x]`");
+        assert_eq!(path("items.id"), "`items` is an array, give an index");
+
+        // Offsets inside an element resolve to the element's start.
+        assert_eq!(at(24), "items[0] 24+4 UINT");
+        assert_eq!(at(39), "items[3] 36+4 UINT");
+        assert_eq!(at(55), "items[7] 52+4 UINT");
+    }
+
+    #[test]
+    fn nested_structs() {
+        let metalib = parse(FULL_XML);
+        let packet = metalib.get_meta_by_name("Packet").unwrap();
+        let path = |path| located(resolve_path(&metalib, packet, path));
+        let at = |offset| located(field_at(&metalib, packet, offset));
+
+        assert_eq!(path("head"), "head 0+16 STRUCT");
+        assert_eq!(path("head.len"), "head.len 0+2 USHORT");
+        assert_eq!(path("head.ver"), "head.ver 4+4 INT");
+        assert_eq!(path("kind"), "kind 16+4 INT");
+        assert_eq!(path("head.nope"), "`Header` has no entry `nope`");
+        assert_eq!(path("kind.x"), "`kind` isn't a struct or union");
+
+        assert_eq!(at(0), "head.len 0+2 USHORT");
+        assert_eq!(at(6), "head.ver 4+4 INT");
+        assert_eq!(at(16), "kind 16+4 INT");
+        // Header's padding after `ver` is inside `head` but in no field.
+        assert_eq!(at(12), "No field of `Packet` contains host offset 12");
+        assert_eq!(at(1000), "No field of `Packet` contains host offset 1000");
+
+        // A pointer holds an address on the host.
+        assert_eq!(path("next"), "next 108+4 STRUCT");
+        assert_eq!(at(111), "next 108+4 STRUCT");
+        assert_eq!(packet.h_unit_size, 112);
+    }
+
+    #[test]
+    fn unions() {
+        let metalib = parse(FULL_XML);
+        let packet = metalib.get_meta_by_name("Packet").unwrap();
+        let body = metalib.get_meta_by_name("Body").unwrap();
+        let path = |meta, path| located(resolve_path(&metalib, meta, path));
+
+        // Every member starts at the union's offset.
+        assert_eq!(path(packet, "body"), "body 56+32 UNION");
+        assert_eq!(path(packet, "body.a"), "body.a 56+4 INT");
+        assert_eq!(path(packet, "body.b"), "body.b 56+32 STRING");
+        assert_eq!(path(packet, "body.c"), "body.c 56+1 UCHAR");
+        assert_eq!(path(body, "c"), "c 0+1 UCHAR");
+
+        // Which member is in use isn't known, so offsets in a nested union stop there.
+        assert_eq!(located(field_at(&metalib, packet, 60)), "body 56+32 UNION");
+        // Looked up in the union itself, the first member containing the offset wins.
+        assert_eq!(located(field_at(&metalib, body, 0)), "a 0+4 INT");
+        assert_eq!(located(field_at(&metalib, body, 4)), "b 0+32 STRING");
+    }
+}
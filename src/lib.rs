@@ -0,0 +1,38 @@
+//! Reading compiled Tencent TDR metalibs and writing them back out.
+//!
+//! [`metalib::read_metalib_with`] parses a metalib from any seekable reader into a
+//! [`metalib::Metalib`], which [`xml`] turns back into TDR XML and [`codegen`] and [`export`]
+//! into other languages and documentation formats.
+
+pub mod arch;
+pub mod builder;
+pub mod codec;
+pub mod codegen;
+pub mod compile;
+pub mod diff;
+pub mod digest;
+pub mod error;
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fields;
+pub mod find;
+pub mod info;
+pub mod layout;
+pub mod list;
+pub mod metalib;
+pub mod options;
+pub mod reader_utils;
+pub mod report;
+pub mod scan;
+pub mod select;
+pub mod site;
+pub mod survey;
+#[cfg(test)]
+mod test_support;
+pub mod tree;
+pub mod validate;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod writer;
+pub mod xml;
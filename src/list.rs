@@ -0,0 +1,121 @@
+//! One-line-per-item listings of the metas and macros in a metalib, for the `list` subcommand.
+
+use anyhow::{anyhow, Result};
+use std::fmt::Write as _;
+
+use crate::metalib::{MetaPrimativeType, Metalib};
+
+/// Order of the rows printed by `list`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ListSort {
+    /// Order within the metalib.
+    File,
+    Name,
+    /// By id (macros by value).
+    Id,
+    /// Largest `mem_size` first (macros by value).
+    Size,
+}
+
+impl ListSort {
+    pub fn from_name(name: &str) -> Result<ListSort> {
+        match name.to_ascii_lowercase().as_str() {
+            "file" => Ok(ListSort::File),
+            "name" => Ok(ListSort::Name),
+            "id" => Ok(ListSort::Id),
+            "size" => Ok(ListSort::Size),
+            _ => Err(anyhow!(
+                "Unknown sort order `{name}`, expected file, name, id or size"
+            )),
+        }
+    }
+}
+
+/// Writes `rows` under `header`, either tab separated or padded into columns.
+pub(crate) fn write_table(out: &mut String, header: &[&str], rows: &[Vec<String>], tsv: bool) -> Result<()> {
+    if tsv {
+        writeln!(out, "{}", header.join("\t"))?;
+        for row in rows.iter() {
+            writeln!(out, "{}", row.join("\t"))?;
+        }
+        return Ok(());
+    }
+
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut write_row = |cells: Vec<&str>| -> Result<()> {
+        let mut line = String::new();
+        for (cell, width) in cells.iter().zip(widths.iter()) {
+            write!(&mut line, "{cell:<width$}  ")?;
+        }
+        writeln!(out, "{}", line.trim_end())?;
+        Ok(())
+    };
+    write_row(header.to_vec())?;
+    for row in rows.iter() {
+        write_row(row.iter().map(String::as_str).collect())?;
+    }
+    Ok(())
+}
+
+/// Lists every meta (and with `macros`, every macro) in the metalib.
+pub fn list_metalib(metalib: &Metalib, sort: ListSort, macros: bool, tsv: bool) -> Result<String> {
+    let mut metas: Vec<_> = metalib.metas.iter().collect();
+    match sort {
+        ListSort::File => {}
+        ListSort::Name => metas.sort_by(|a, b| a.name.cmp(&b.name)),
+        ListSort::Id => metas.sort_by_key(|meta| meta.id),
+        ListSort::Size => metas.sort_by_key(|meta| std::cmp::Reverse(meta.mem_size)),
+    }
+    let rows: Vec<Vec<String>> = metas
+        .iter()
+        .map(|meta| {
+            let kind = match meta.type_ {
+                MetaPrimativeType::UNION => "union",
+                _ => "struct",
+            };
+            vec![
+                kind.to_string(),
+                meta.name.clone(),
+                meta.id.to_string(),
+                meta.base_version.to_string(),
+                meta.entries_num.to_string(),
+                meta.mem_size.to_string(),
+            ]
+        })
+        .collect();
+
+    let mut out = String::new();
+    write_table(
+        &mut out,
+        &["KIND", "NAME", "ID", "VERSION", "ENTRIES", "SIZE"],
+        &rows,
+        tsv,
+    )?;
+
+    if macros {
+        let mut macros: Vec<_> = metalib.macros.iter().collect();
+        match sort {
+            ListSort::File => {}
+            ListSort::Name => macros.sort_by(|a, b| a.name.cmp(&b.name)),
+            ListSort::Id | ListSort::Size => macros.sort_by_key(|macro_| macro_.value),
+        }
+        let rows: Vec<Vec<String>> = macros
+            .iter()
+            .map(|macro_| {
+                vec![
+                    "macro".to_string(),
+                    macro_.name.clone(),
+                    macro_.value.to_string(),
+                ]
+            })
+            .collect();
+        writeln!(&mut out)?;
+        write_table(&mut out, &["KIND", "NAME", "VALUE"], &rows, tsv)?;
+    }
+    Ok(out)
+}
@@ -1,620 +1,1086 @@
-mod metalib;
-mod reader_utils;
+mod cli;
+mod input;
 
 use anyhow::{anyhow, Context, Result};
-use metalib::{
-    read_metalib, MetaPrimativeType, Metalib, TDRMetaEntryDBFlags, TDRMetaEntryFlags, TDRMetaFlags,
-    INVALID_METALIB_VALUE,
+use cli::Command;
+use input::Input;
+use log::{info, warn};
+use mldec_rs::digest::{name_hash, Digest};
+use mldec_rs::info::MetalibInfo;
+use mldec_rs::metalib::{read_metalib_with, read_metalib_with_report, Metalib};
+use mldec_rs::options::{ExportOptions, ParseOptions};
+use mldec_rs::report::ParseReport;
+use mldec_rs::{
+    codec, codegen, compile, diff, error, export, info, layout, list, metalib, scan, site, survey, validate, writer, xml,
 };
 
-// Needed to prevent namespace clash.
-use std::fmt::Write as _;
-use std::io::Write as _;
-
+use std::collections::HashMap;
 use std::io::{prelude::*, BufReader, SeekFrom};
-use std::path::Path;
-use std::{env, fs::File};
-
-fn walk_meta_for_net_offset_field_name(
-    metalib: &Metalib,
-    meta: &metalib::TDRMeta,
-    search_net_offset: i32,
-    current_base: i32,
-    current_path: String,
-) -> Result<String> {
-    for entry in meta.entries.iter() {
-        // Skip any that don't contain our search range
-        let entry_start = current_base + entry.n_off;
-        let entry_end = current_base + entry.n_off + entry.n_unit_size;
-        if entry_start > search_net_offset || entry_end <= search_net_offset {
-            continue;
-        }
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{fs, fs::File};
+
+/// Output formats selectable with `--format`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum OutputFormat {
+    Xml,
+    Rust,
+    CSharp,
+    Python,
+    Proto,
+    Fbs,
+    Ksy,
+    Dot,
+    Csv,
+    Markdown,
+    Html,
+    Yaml,
+    Sql,
+    Digest,
+    /// Macro values only, for `--macros-only`.
+    Json,
+}
 
-        if entry.type_ == metalib::MetaPrimativeType::STRUCT {
-            let referenced_meta = metalib.get_meta_by_offset(entry.ptr_meta)?;
-            return walk_meta_for_net_offset_field_name(
-                metalib,
-                referenced_meta,
-                search_net_offset,
-                entry_start,
-                format!("{}.", entry.name),
-            );
-        } else if entry_start == search_net_offset {
-            return Ok(format!("{}{}", current_path, entry.name));
+/// Exported data, either as a single document or as a set of files for one directory.
+enum ExportOutput {
+    Single(Vec<u8>),
+    Files(Vec<codegen::GeneratedFile>),
+}
+
+impl OutputFormat {
+    fn from_name(name: &str) -> Result<OutputFormat> {
+        match name {
+            "xml" => Ok(OutputFormat::Xml),
+            "rust" | "rs" => Ok(OutputFormat::Rust),
+            "csharp" | "cs" => Ok(OutputFormat::CSharp),
+            "python" | "py" => Ok(OutputFormat::Python),
+            "proto" | "protobuf" => Ok(OutputFormat::Proto),
+            "fbs" | "flatbuffers" => Ok(OutputFormat::Fbs),
+            "ksy" | "kaitai" => Ok(OutputFormat::Ksy),
+            "dot" => Ok(OutputFormat::Dot),
+            "csv" => Ok(OutputFormat::Csv),
+            "md" | "markdown" => Ok(OutputFormat::Markdown),
+            "html" => Ok(OutputFormat::Html),
+            "yaml" | "yml" => Ok(OutputFormat::Yaml),
+            "sql" => Ok(OutputFormat::Sql),
+            "digest" => Ok(OutputFormat::Digest),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(anyhow!("Unknown output format: {name}")),
         }
     }
 
-    Err(anyhow!("Failed to find meta field by net offset!"))
-}
-
-fn walk_meta_for_host_offset_field_name(
-    metalib: &Metalib,
-    meta: &metalib::TDRMeta,
-    search_host_offset: i32,
-    current_base: i32,
-    current_path: String,
-) -> Result<String> {
-    for entry in meta.entries.iter() {
-        // Skip any that don't contain our search range
-        let entry_start = current_base + entry.h_off;
-        let entry_end = current_base + entry.h_off + entry.h_unit_size;
-        if entry_start > search_host_offset || entry_end <= search_host_offset {
-            continue;
+    /// File extension used for output files of this format.
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Xml => "xml",
+            OutputFormat::Rust => "rs",
+            OutputFormat::CSharp => "cs",
+            OutputFormat::Python => "py",
+            OutputFormat::Proto => "proto",
+            OutputFormat::Fbs => "fbs",
+            OutputFormat::Ksy => "ksy",
+            OutputFormat::Dot => "dot",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Markdown => "md",
+            OutputFormat::Html => "html",
+            OutputFormat::Yaml => "yaml",
+            OutputFormat::Sql => "sql",
+            OutputFormat::Digest => "dig",
+            OutputFormat::Json => "json",
         }
+    }
 
-        if entry.type_ == metalib::MetaPrimativeType::STRUCT {
-            let referenced_meta = metalib.get_meta_by_offset(entry.ptr_meta)?;
-            return walk_meta_for_host_offset_field_name(
+    fn export(self, metalib: &Metalib, options: &ExportOptions) -> Result<ExportOutput> {
+        match self {
+            OutputFormat::Xml => Ok(ExportOutput::Single(xml::encode_xml(
                 metalib,
-                referenced_meta,
-                search_host_offset,
-                entry_start,
-                format!("{}.", entry.name),
-            );
-        } else if entry_start == search_host_offset {
-            return Ok(format!("{}{}", current_path, entry.name));
+                xml::export_metalib_xml_with(metalib, options)?,
+                options.encoding,
+            )?)),
+            OutputFormat::Rust => Ok(ExportOutput::Single(
+                codegen::emit_rust(metalib)?.into_bytes(),
+            )),
+            OutputFormat::CSharp => Ok(ExportOutput::Files(codegen::emit_csharp(metalib)?)),
+            OutputFormat::Python => Ok(ExportOutput::Single(
+                codegen::emit_python(metalib)?.into_bytes(),
+            )),
+            OutputFormat::Proto => Ok(ExportOutput::Single(
+                codegen::emit_proto(metalib)?.into_bytes(),
+            )),
+            OutputFormat::Fbs => Ok(ExportOutput::Single(
+                codegen::emit_fbs(metalib)?.into_bytes(),
+            )),
+            OutputFormat::Ksy => Ok(ExportOutput::Single(
+                codegen::emit_ksy(metalib)?.into_bytes(),
+            )),
+            OutputFormat::Dot => Ok(ExportOutput::Single(
+                export::emit_dot(metalib)?.into_bytes(),
+            )),
+            OutputFormat::Csv => Ok(ExportOutput::Single(
+                export::emit_csv(metalib)?.into_bytes(),
+            )),
+            OutputFormat::Markdown => Ok(ExportOutput::Single(
+                export::emit_markdown(metalib)?.into_bytes(),
+            )),
+            OutputFormat::Html => Ok(ExportOutput::Single(
+                export::emit_html(metalib)?.into_bytes(),
+            )),
+            OutputFormat::Yaml => Ok(ExportOutput::Single(
+                export::emit_yaml(metalib)?.into_bytes(),
+            )),
+            OutputFormat::Sql => Ok(ExportOutput::Single(
+                codegen::emit_sql(metalib)?.into_bytes(),
+            )),
+            OutputFormat::Digest => Ok(ExportOutput::Single(
+                Digest::from_metalib(metalib)?.to_bytes()?,
+            )),
+            OutputFormat::Json => Ok(ExportOutput::Single(
+                export::emit_macros_json(metalib)?.into_bytes(),
+            )),
         }
     }
-
-    Err(anyhow!("Failed to find meta field by host offset!"))
-}
-
-fn resolve_meta_entry_name_by_net_offset(
-    metalib: &Metalib,
-    meta: &metalib::TDRMeta,
-    search_net_offset: i32,
-) -> Result<String> {
-    walk_meta_for_net_offset_field_name(metalib, meta, search_net_offset, 0, "".to_string())
-}
-
-fn resolve_meta_entry_name_by_host_offset(
-    metalib: &Metalib,
-    meta: &metalib::TDRMeta,
-    search_host_offset: i32,
-) -> Result<String> {
-    walk_meta_for_host_offset_field_name(metalib, meta, search_host_offset, 0, "".to_string())
 }
 
-fn dump_tdr_macro_xml(tdr_macro: &metalib::TDRMacro) -> Result<String> {
-    let mut out = String::new();
-    write!(&mut out, "<macro")?;
-    write!(&mut out, " name=\"{}\"", tdr_macro.name)?;
-    write!(&mut out, " value=\"{}\"", tdr_macro.value)?;
-    if !tdr_macro.desc.is_empty() {
-        write!(&mut out, " desc=\"{}\"", tdr_macro.desc)?;
+/// Compares two digest files and reports every struct that doesn't match.
+fn digest_compare(
+    old_path: &str,
+    new_path: &str,
+    name_specs: &[String],
+    parse_options: &ParseOptions,
+) -> Result<()> {
+    let old = Digest::read(&mut BufReader::new(
+        File::open(old_path).with_context(|| format!("Failed to open {old_path}"))?,
+    ))?;
+    let new = Digest::read(&mut BufReader::new(
+        File::open(new_path).with_context(|| format!("Failed to open {new_path}"))?,
+    ))?;
+
+    if old.metalib_version != new.metalib_version {
+        println!(
+            "Metalib version: {} -> {}",
+            old.metalib_version, new.metalib_version
+        );
     }
-    write!(&mut out, " />")?;
-    Ok(out)
-}
-
-fn dump_tdr_macrogroup_xml(
-    metalib: &Metalib,
-    macrogroup: &metalib::TDRMacroGroup,
-) -> Result<String> {
-    let mut out = String::new();
 
-    // Open `macrosgroup` tag.
-    let mut macrogroup_tag = String::new();
-    write!(&mut macrogroup_tag, "\t<macrosgroup")?;
-    write!(&mut macrogroup_tag, " name=\"{}\"", macrogroup.name)?;
-    if !macrogroup.desc.is_empty() {
-        write!(&mut macrogroup_tag, " desc=\"{}\"", macrogroup.desc)?;
+    // Digests only hold name hashes, so names come from any metalibs given with `--names`.
+    let mut names = HashMap::new();
+    for spec in name_specs {
+        let metalib = read_metalib_spec(spec, parse_options)
+            .with_context(|| format!("Failed to read {spec}"))?;
+        for meta in metalib.metas.iter() {
+            names.insert(name_hash(&meta.name), meta.name.clone());
+        }
     }
-    write!(&mut macrogroup_tag, ">")?;
-    writeln!(&mut out, "{macrogroup_tag}")?;
+    let label = |hash: u64| match names.get(&hash) {
+        Some(name) => format!("{hash:016X} {name}"),
+        None => format!("{hash:016X}"),
+    };
 
-    // Write macro entries
-    for &tdr_macro_idx in macrogroup.value_idx_map.iter() {
-        assert!(tdr_macro_idx >= 0);
-        let tdr_macro = metalib.macros.get(tdr_macro_idx as usize).unwrap();
-        let macro_tag = dump_tdr_macro_xml(tdr_macro)?;
-        writeln!(&mut out, "\t\t{macro_tag}")?;
+    let diff = old.diff(&new);
+    let mut hashes = diff
+        .removed
+        .iter()
+        .chain(diff.added.iter())
+        .map(|meta| meta.name_hash)
+        .chain(diff.changed.iter().map(|change| change.old.name_hash));
+    if hashes.any(|hash| !names.contains_key(&hash)) {
+        println!("Digests don't hold meta names, so metas are shown by name hash where");
+        println!("--names <metalib> doesn't give one");
     }
-
-    // Close `macrosgroup` tag.
-    write!(&mut out, "\t</macrosgroup>")?;
-
-    Ok(out)
-}
-
-fn dump_tdr_meta_entry_xml(
-    metalib: &Metalib,
-    meta: &metalib::TDRMeta,
-    meta_entry: &metalib::TDRMetaEntry,
-) -> Result<String> {
-    // Open `entry` tag.
-    let mut out = String::new();
-    write!(&mut out, "<entry")?;
-    write!(&mut out, " name=\"{}\"", meta_entry.name)?;
-
-    // Write "type" attribute
-    let type_string: &str = {
-        if meta_entry.ptr_meta != INVALID_METALIB_VALUE {
-            let type_meta = metalib
-                .get_meta_by_offset(meta_entry.ptr_meta)
-                .context("Failed to get meta by ptr_meta")?;
-            &type_meta.name
-        } else if meta_entry.idx_type != INVALID_METALIB_VALUE {
-            let type_info = metalib::TDR_PRIMATIVE_TYPE_INFO
-                .get(meta_entry.idx_type as usize)
-                .context("Failed to get type info")?;
-
-            type_info.xml_name
-        } else {
-            ""
+    for meta in diff.removed.iter() {
+        println!("- removed  {}", label(meta.name_hash));
+    }
+    for meta in diff.added.iter() {
+        println!("+ added    {}", label(meta.name_hash));
+    }
+    for change in diff.changed.iter() {
+        let (old, new) = (&change.old, &change.new);
+        let mut reasons = Vec::new();
+        if old.layout_hash != new.layout_hash {
+            reasons.push("layout".to_string());
         }
-    };
-    let type_prefix = {
-        if meta_entry.flag.contains(TDRMetaEntryFlags::POINT_TYPE) {
-            "*"
-        } else if meta_entry.flag.contains(TDRMetaEntryFlags::REFER_TYPE) {
-            "@"
-        } else {
-            ""
+        if old.version != new.version {
+            reasons.push(format!("version {} -> {}", old.version, new.version));
         }
-    };
-    write!(&mut out, " type=\"{type_prefix}{type_string}\"")?;
-
-    // Write `count` attribute
-    if meta_entry.count > 1 {
-        if meta_entry.idx_count != INVALID_METALIB_VALUE {
-            let count_macro = metalib
-                .macros
-                .get(meta_entry.idx_count as usize)
-                .context("Failed to get macro by meta_entry.idx_count")?;
-            write!(&mut out, " count=\"{}\"", count_macro.name)?;
-        } else {
-            write!(&mut out, " count=\"{}\"", meta_entry.count)?;
+        if old.min_net_size != new.min_net_size || old.max_net_size != new.max_net_size {
+            reasons.push(format!(
+                "net size {}..{} -> {}..{}",
+                old.min_net_size, old.max_net_size, new.min_net_size, new.max_net_size
+            ));
         }
+        println!("~ changed  {} ({})", label(old.name_hash), reasons.join(", "));
     }
 
-    // Write `version` attribute
-    if meta_entry.version != meta.base_version{
-        if meta_entry.idx_version != INVALID_METALIB_VALUE {
-            let version_macro = metalib
-                .macros
-                .get(meta_entry.idx_version as usize)
-                .context("Failed to get macro by meta_entry.idx_version")?;
-            write!(&mut out, " version=\"{}\"", version_macro.name)?;
-        } else {
-            write!(&mut out, " version=\"{}\"", meta_entry.version)?;
-        }
-    }
-
-    // Write `id` attribute
-    if meta_entry.idx_id != INVALID_METALIB_VALUE {
-        let id_macro = metalib
-            .macros
-            .get(meta_entry.idx_id as usize)
-            .context("Failed to get macro by meta_entry.idx_id")?;
-        write!(&mut out, " id=\"{}\"", id_macro.name)?;
-    } else if meta_entry.id != INVALID_METALIB_VALUE {
-        write!(&mut out, " id=\"{}\"", meta_entry.id)?;
-    }
-
-    // Write `size` attribute
-    if meta_entry.idx_custom_h_unit_size != INVALID_METALIB_VALUE {
-        let id_macro = metalib
-            .macros
-            .get(meta_entry.idx_custom_h_unit_size as usize)
-            .context("Failed to get macro by meta_entry.idx_custom_h_unit_size")?;
-        write!(&mut out, " size=\"{}\"", id_macro.name)?;
-    } else if meta_entry.custom_h_unit_size > 0 {
-        let type_info = metalib::TDR_PRIMATIVE_TYPE_INFO
-            .get(meta_entry.idx_type as usize)
-            .context("Failed to get type info")?;
-        write!(
-            &mut out,
-            " size=\"{}\"",
-            meta_entry.custom_h_unit_size / type_info.size
-        )?;
+    if !diff.is_empty() {
+        anyhow::bail!(
+            "Digests differ: {} added, {} removed, {} changed",
+            diff.added.len(),
+            diff.removed.len(),
+            diff.changed.len()
+        );
     }
 
-    if !meta_entry.chinese_name.is_empty() {
-        write!(&mut out, " cname=\"{}\"", meta_entry.chinese_name)?;
-    }
+    println!("Digests match ({} structs)", old.metas.len());
+    Ok(())
+}
 
-    if !meta_entry.desc.is_empty() {
-        write!(&mut out, " desc=\"{}\"", meta_entry.desc)?;
+/// Parses an `--indent` value: `tabs` or `spaces:N`, optionally as `tabs:N` for several
+/// tabs per level. The older `tab` and bare `N` (spaces) forms are still accepted.
+fn parse_indent(value: &str) -> Result<String> {
+    let (unit, width) = match value.split_once(':') {
+        Some((unit, width)) => (unit, Some(width)),
+        None if value.chars().all(|c| c.is_ascii_digit()) => ("spaces", Some(value)),
+        None => (value, None),
+    };
+    let width: usize = match width {
+        Some(width) => width.parse().with_context(|| {
+            format!("Invalid --indent width in `{value}` (expected a number)")
+        })?,
+        None => 1,
+    };
+    match unit {
+        "tab" | "tabs" => Ok("\t".repeat(width)),
+        "space" | "spaces" => Ok(" ".repeat(width)),
+        _ => Err(anyhow!(
+            "Invalid --indent value: {value} (expected `tabs` or `spaces:N`)"
+        )),
     }
+}
 
-    if meta_entry.db_flag.contains(TDRMetaEntryDBFlags::UNIQUE) {
-        write!(&mut out, " unique=\"true\"")?;
-    }
+/// A metalib offset given on the command line.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum OffsetSpec {
+    /// Bytes from the start of the file.
+    Start(u64),
+    /// Bytes back from the end of the file (written with a leading `-`).
+    FromEnd(u64),
+}
 
-    if meta_entry.db_flag.contains(TDRMetaEntryDBFlags::NOT_NULL) {
-        write!(&mut out, " notnull=\"true\"")?;
-    }
+impl OffsetSpec {
+    /// Parses a decimal or `0x`-prefixed hex offset, optionally prefixed with `-` to count
+    /// back from the end of the file.
+    fn parse(value: &str) -> Result<OffsetSpec> {
+        let (from_end, digits) = match value.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, value),
+        };
+        let hex_digits = digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix("0X"));
+        let parsed = match hex_digits {
+            Some(hex_digits) => u64::from_str_radix(hex_digits, 16).ok(),
+            None => digits.parse::<u64>().ok(),
+        };
+        let offset = match parsed {
+            Some(offset) => offset,
+            None if hex_digits.is_none() && u64::from_str_radix(digits, 16).is_ok() => {
+                anyhow::bail!(
+                    "Invalid offset: {value} (hex offsets need a 0x prefix, e.g. 0x{digits})"
+                )
+            }
+            None => anyhow::bail!(
+                "Invalid offset: {value} (expected a decimal or 0x-prefixed hex number, \
+                 optionally prefixed with - to count from the end of the file)"
+            ),
+        };
 
-    // Write `refer` attribute
-    // TODO: Can we just do this by net offset?
-    if meta_entry.referer.h_off != INVALID_METALIB_VALUE {
-        write!(
-            &mut out,
-            " refer=\"{}\"",
-            resolve_meta_entry_name_by_host_offset(metalib, meta, meta_entry.referer.h_off)?
-        )?;
+        if from_end {
+            Ok(OffsetSpec::FromEnd(offset))
+        } else {
+            Ok(OffsetSpec::Start(offset))
+        }
     }
 
-    // Write `default` attribute
-    // TODO: Update default value reader to parse value instead of bytes if needed.
-    if meta_entry.ptr_default_val != INVALID_METALIB_VALUE {
-        write!(&mut out, " default=\"{}\"", meta_entry.default_value_string)?;
+    /// Returns the absolute offset within a file of `file_len` bytes.
+    fn resolve(self, file_len: u64) -> Result<u64> {
+        let offset = match self {
+            OffsetSpec::Start(offset) => offset,
+            OffsetSpec::FromEnd(distance) => match file_len.checked_sub(distance) {
+                Some(offset) => offset,
+                None => anyhow::bail!(
+                    "Offset -{distance:#X} is before the start of the file ({file_len:#X} bytes)"
+                ),
+            },
+        };
+        if offset >= file_len {
+            anyhow::bail!("Offset {offset:#X} is past the end of the file ({file_len:#X} bytes)");
+        }
+        Ok(offset)
     }
+}
 
-    // Write `sizeinfo` attribute
-    if meta_entry.size_info.unit_size > 0 {
-        if meta_entry.size_info.idx_size_type != INVALID_METALIB_VALUE {
-            let type_info = metalib::TDR_PRIMATIVE_TYPE_INFO
-                .get(meta_entry.size_info.idx_size_type as usize)
-                .context("Failed to get type info from meta_entry.size_info.idx_size_type")?;
+/// Reads the metalib named by a `<file>[@<offset>]` spec (offset 0 if omitted). The offset
+/// takes the same forms as everywhere else, so a bare number is decimal.
+fn read_metalib_spec(spec: &str, parse_options: &ParseOptions) -> Result<Metalib> {
+    let (path, offset_spec) = match spec.rsplit_once('@') {
+        Some((path, offset)) => (
+            path,
+            OffsetSpec::parse(offset).with_context(|| format!("Invalid offset in {spec}"))?,
+        ),
+        None => (spec, OffsetSpec::Start(0)),
+    };
 
-            if (type_info.primative_type != MetaPrimativeType::STRING
-                && type_info.primative_type != MetaPrimativeType::WSTRING)
-                || type_info.xml_name == "int"
-            {
-                write!(&mut out, " sizeinfo=\"{}\"", type_info.xml_name)?;
-            }
-        } else if meta_entry.size_info.n_off != INVALID_METALIB_VALUE {
-            write!(
-                &mut out,
-                " sizeinfo=\"{}\"",
-                resolve_meta_entry_name_by_net_offset(metalib, meta, meta_entry.size_info.n_off)?
-            )?;
-        }
-    }
-
-    // Write `sortMethod` attribute
-    if meta_entry.count > 1 && (meta_entry.order == 1 || meta_entry.order == 2) {
-        let sort_order = {
-            match meta_entry.order {
-                1 => "asc",
-                2 => "desc",
-                _ => unreachable!(),
-            }
-        };
-        write!(&mut out, " sortMethod=\"{sort_order}\"")?;
-    }
+    let file = File::open(path).with_context(|| format!("Failed to open {path}"))?;
+    let offset = offset_spec.resolve(file.metadata()?.len())?;
+    let mut file = BufReader::new(file);
+    file.seek(SeekFrom::Start(offset))?;
+    read_metalib_with(&mut file, parse_options)
+}
 
-    // Write `io` attribute
-    if meta_entry.io != 0 {
-        let io_type = {
-            match meta_entry.io {
-                1 => "noinput",
-                2 => "nooutput",
-                3 => "noio",
-                _ => unreachable!(),
-            }
-        };
+/// Prints what differs between two `<file>[@<offset>]` metalibs, as text or JSON.
+fn diff_metalibs(old_spec: &str, new_spec: &str, json: bool, parse_options: &ParseOptions) -> Result<()> {
+    let old = read_metalib_spec(old_spec, parse_options)
+        .with_context(|| format!("Failed to read {old_spec}"))?;
+    let new = read_metalib_spec(new_spec, parse_options)
+        .with_context(|| format!("Failed to read {new_spec}"))?;
+
+    let diff = diff::compare(&old, &new)?;
+    if json {
+        print!("{}", diff.to_json()?);
+    } else if diff.is_empty() {
+        println!("No differences");
+    } else {
+        print!("{}", diff.to_text()?);
+    }
+    Ok(())
+}
 
-        write!(&mut out, " io=\"{io_type}\"")?;
+/// Aggregates field statistics over every `<file>[@<offset>]` input and prints them
+/// as CSV (or JSON with `--json`).
+fn survey_fields(inputs: &[String], json: bool, parse_options: &ParseOptions) -> Result<()> {
+    let mut survey = survey::Survey::new();
+    for input in inputs {
+        match read_metalib_spec(input, parse_options) {
+            Ok(metalib) => survey.add(&metalib),
+            Err(err) => warn!("Skipping {input}: {err:#}"),
+        }
     }
 
-    // Write `select` attribute
-    if meta_entry.type_ == MetaPrimativeType::UNION
-        && meta_entry.selector.h_off != INVALID_METALIB_VALUE
-    {
-        let select_field =
-            resolve_meta_entry_name_by_host_offset(metalib, meta, meta_entry.selector.h_off)?;
-        write!(&mut out, " select=\"{select_field}\"")?;
+    if json {
+        print!("{}", survey.to_json()?);
+    } else {
+        survey.write_csv(&mut std::io::stdout().lock())?;
     }
 
-    if meta_entry.flag.contains(TDRMetaEntryFlags::HAS_MAXMIN_ID) {
-        // Write `minid` attribute
-        if meta_entry.min_id_idx != INVALID_METALIB_VALUE {
-            let min_id_macro = metalib
-                .macros
-                .get(meta_entry.min_id_idx as usize)
-                .context("Failed to get macro by meta_entry.min_id_idx")?;
-            write!(&mut out, " minid=\"{}\"", min_id_macro.name)?;
-        } else {
-            write!(&mut out, " minid=\"{}\"", meta_entry.min_id)?;
+    Ok(())
+}
+
+/// Builds a static site from a manifest listing one `<file>[@<hex offset>] [label]` per line,
+/// oldest version first. Relative paths are resolved against the manifest's directory.
+fn build_site(manifest_path: &str, out_dir: &str, parse_options: &ParseOptions) -> Result<()> {
+    let manifest = fs::read_to_string(manifest_path)
+        .with_context(|| format!("Failed to read manifest {manifest_path}"))?;
+    let base_dir = Path::new(manifest_path).parent().unwrap_or(Path::new(""));
+
+    let mut versions = Vec::new();
+    for line in manifest.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
         }
 
-        // Write `maxid` attribute
-        if meta_entry.max_id_idx != INVALID_METALIB_VALUE {
-            let max_id_macro = metalib
-                .macros
-                .get(meta_entry.max_id_idx as usize)
-                .context("Failed to get macro by meta_entry.max_id_idx")?;
-            write!(&mut out, " maxid=\"{}\"", max_id_macro.name)?;
+        let (spec, label) = match line.split_once(char::is_whitespace) {
+            Some((spec, label)) => (spec, label.trim().to_string()),
+            None => (line, String::new()),
+        };
+        let spec = base_dir.join(spec).to_string_lossy().to_string();
+        let metalib = read_metalib_spec(&spec, parse_options)
+            .with_context(|| format!("Failed to read metalib {spec}"))?;
+
+        let label = if label.is_empty() {
+            Path::new(spec.rsplit_once('@').map_or(spec.as_str(), |(path, _)| path))
+                .file_stem()
+                .map_or(spec.clone(), |stem| stem.to_string_lossy().to_string())
         } else {
-            write!(&mut out, " maxid=\"{}\"", meta_entry.max_id)?;
-        }
+            label
+        };
+        versions.push(site::SiteVersion { label, metalib });
     }
 
-    // Unused `extendtotable` attribute
-    if meta_entry
-        .db_flag
-        .contains(TDRMetaEntryDBFlags::EXTEND_TO_TABLE)
-    {
-        todo!()
-    }
+    site::build_site(&versions, Path::new(out_dir))?;
+    println!("Wrote {} versions to {out_dir}", versions.len());
+    Ok(())
+}
 
-    // Write `bindmacrosgroup` attribute
-    if meta_entry.ptr_macros_group != INVALID_METALIB_VALUE {
-        let macro_group = metalib.get_macrogroup_by_offset(meta_entry.ptr_macros_group)?;
-        write!(&mut out, " bindmacrosgroup=\"{}\"", macro_group.name)?;
-    }
+/// Returns true if an `-o` path names a directory: one that already exists, or one spelled
+/// with a trailing separator.
+fn is_directory_path(path: &str) -> bool {
+    path.ends_with('/') || path.ends_with(std::path::MAIN_SEPARATOR) || Path::new(path).is_dir()
+}
 
-    // Unused `autoincrement` attribute
-    if meta_entry
-        .db_flag
-        .contains(TDRMetaEntryDBFlags::AUTO_INCREMENT)
-    {
-        todo!()
+/// Writes `data` to `path`, creating any missing parent directories.
+fn write_file(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
     }
+    fs::write(path, data).with_context(|| format!("Failed to write {}", path.display()))
+}
 
-    // Unused `customattr` attribute
-    if meta_entry.ptr_custom_attr != INVALID_METALIB_VALUE {
-        todo!()
+/// Writes an export to `output_path`.
+///
+/// If `output_path` is a directory, single-file output goes to `<default_name>.<extension>`
+/// inside it and multi-file output to a `<default_name>` subdirectory. Otherwise it's used
+/// as the exact file (or, for multi-file output, directory) to write.
+fn write_output(
+    output: ExportOutput,
+    output_path: &str,
+    default_name: &str,
+    extension: &str,
+) -> Result<()> {
+    let is_dir = is_directory_path(output_path);
+    match output {
+        ExportOutput::Single(data) => {
+            let path = if is_dir {
+                Path::new(output_path).join(format!("{default_name}.{extension}"))
+            } else {
+                PathBuf::from(output_path)
+            };
+            write_file(&path, &data)?;
+        }
+        ExportOutput::Files(files) => {
+            // Multi-file formats get their own directory.
+            let output_dir = if is_dir {
+                Path::new(output_path).join(default_name)
+            } else {
+                PathBuf::from(output_path)
+            };
+            for generated in files {
+                write_file(
+                    &output_dir.join(&generated.file_name),
+                    generated.contents.as_bytes(),
+                )?;
+            }
+        }
     }
+    Ok(())
+}
 
-    // Close tag
-    write!(&mut out, "/>")?;
+/// Writes a single-file export to stdout, so it can be piped into other tools.
+fn write_stdout(output: ExportOutput) -> Result<()> {
+    match output {
+        ExportOutput::Single(data) => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&data)?;
+            stdout.flush()?;
+            Ok(())
+        }
+        ExportOutput::Files(_) => {
+            anyhow::bail!("This format writes multiple files and can't be written to stdout")
+        }
+    }
+}
 
-    Ok(out)
+/// Resolves `offset_spec` against the length of `input`.
+fn resolve_offset(input: &mut Input, offset_spec: OffsetSpec) -> Result<u64> {
+    offset_spec.resolve(input.len()?)
 }
 
-fn dump_tdr_meta_xml(metalib: &Metalib, meta: &metalib::TDRMeta) -> Result<String> {
-    let mut out = String::new();
+/// Reads the metalib at `offset` in `input`, warning if the header's arch is wrong.
+fn read_metalib_at(input: &mut Input, offset: u64, parse_options: &ParseOptions) -> Result<Metalib> {
+    Ok(read_metalib_with_report_at(input, offset, parse_options)?.0)
+}
 
-    let tag_name = match meta.type_ {
-        metalib::MetaPrimativeType::UNION => "union",
-        metalib::MetaPrimativeType::STRUCT => "struct",
-        _ => unreachable!(),
-    };
-    write!(&mut out, "\t<{tag_name}")?;
-    write!(&mut out, " name=\"{}\"", meta.name)?;
-
-    if meta.idx_version != INVALID_METALIB_VALUE {
-        let version_macro = metalib
-            .macros
-            .get(meta.idx_version as usize)
-            .context("Error getting macro by idx_version")?;
-        write!(&mut out, " version=\"{}\"", version_macro.name)?;
-    } else {
-        write!(&mut out, " version=\"{}\"", meta.base_version)?;
+/// Like [`read_metalib_at`], also returning the warnings.
+fn read_metalib_with_report_at(
+    input: &mut Input,
+    offset: u64,
+    parse_options: &ParseOptions,
+) -> Result<(Metalib, ParseReport)> {
+    _ = input.seek(SeekFrom::Start(offset));
+    let (metalib, report) = read_metalib_with_report(input, parse_options)?;
+    if metalib.arch_probe.is_mismatch() && parse_options.force_arch.is_none() {
+        warn!(
+            "Header platform_arch ({:#X}) doesn't match the data, parsing as {} instead\n{}",
+            metalib.header.platform_arch,
+            metalib.arch,
+            info::format_arch_probe(&metalib.arch_probe)?.trim_end()
+        );
     }
+    Ok((metalib, report))
+}
 
-    if meta.flags.contains(TDRMetaFlags::HAS_ID) {
-        if meta.idx_id != INVALID_METALIB_VALUE {
-            let id_macro = metalib
-                .macros
-                .get(meta.idx_id as usize)
-                .context("Error getting macro by idx_id")?;
-            write!(&mut out, " id=\"{}\"", id_macro.name)?;
-        } else {
-            write!(&mut out, " id=\"{}\"", meta.id)?;
+/// Reads the metalib at `offset_spec` in `input_filepath` and writes it out in `format`.
+///
+/// An `output_path` of `None` writes to stdout.
+fn dump_metalib(
+    input_filepath: &str,
+    offset_spec: OffsetSpec,
+    format: OutputFormat,
+    parse_options: &ParseOptions,
+    export_options: &ExportOptions,
+    output_path: Option<&str>,
+) -> Result<()> {
+    let mut input = Input::open(input_filepath)?;
+    let offset = resolve_offset(&mut input, offset_spec)?;
+
+    info!("Attempting to load TDR Metalib in file:{input_filepath}, offset:{offset:X}");
+    let (metalib, report) = read_metalib_with_report_at(&mut input, offset, parse_options)?;
+
+    let output = format.export(&metalib, export_options)?;
+
+    match output_path {
+        Some(output_path) => {
+            // Find input file name
+            let default_name = if input::is_stdin(input_filepath) {
+                input::file_stem(input_filepath)
+            } else {
+                format!("{}_{offset:X}", input::file_stem(input_filepath))
+            };
+            write_output(output, output_path, &default_name, format.extension())?;
         }
+        None => write_stdout(output)?,
     }
 
-    if !meta.chinese_name.is_empty() {
-        write!(&mut out, " cname=\"{}\"", meta.chinese_name)?;
+    if !report.is_empty() {
+        warn!(
+            "Dumped `{}` with {} warnings (see above)",
+            metalib.header.name,
+            report.warnings.len()
+        );
     }
+    Ok(())
+}
 
-    if !meta.desc.is_empty() {
-        write!(&mut out, " desc=\"{}\"", meta.desc)?;
+/// Matches a file name against a shell-style pattern supporting `*` and `?`.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // Classic wildcard matching: on a mismatch, retry from the last `*` one character later.
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = backtrack {
+            p = star_p + 1;
+            n = star_n + 1;
+            backtrack = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
     }
+    pattern[p..].iter().all(|&c| c == '*')
+}
 
-    // Fields diverge here depending on if this is a union or a struct tag.
-    if meta.type_ == metalib::MetaPrimativeType::STRUCT {
-        // Write `size` tag
-        if meta.idx_custom_h_unit_size != INVALID_METALIB_VALUE {
-            let custom_host_size_macro =
-                metalib
-                    .macros
-                    .get(meta.idx_custom_h_unit_size as usize)
-                    .context("Error getting macro by idx_custom_h_unit_size")?;
-            write!(&mut out, " size=\"{}\"", custom_host_size_macro.name)?;
-        } else if meta.custom_h_unit_size > 0 {
-            write!(&mut out, " size=\"{}\"", meta.custom_h_unit_size)?;
-        }
+/// Dumps every file in `dir` (optionally only those matching `glob`) into `output_dir`.
+///
+/// A file that fails doesn't stop the run; all failures are listed at the end and turned
+/// Seconds between progress lines.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Periodic progress lines on stderr for scans and batch runs. Only shown on a terminal,
+/// and only once a run has taken longer than [`PROGRESS_INTERVAL`].
+struct Progress {
+    enabled: bool,
+    last: Instant,
+}
 
-        // Write custom align tag
-        // (Always defaults to 1, tag skipped if default.)
-        if meta.custom_align != 1 {
-            write!(&mut out, " align=\"{}\"", meta.custom_align)?;
+impl Progress {
+    fn new(quiet: bool) -> Progress {
+        Progress {
+            enabled: !quiet && std::io::stderr().is_terminal(),
+            last: Instant::now(),
         }
+    }
 
-        // Write `versionindicator` tag
-        if meta.version_indicator.n_off != INVALID_METALIB_VALUE {
-            write!(
-                &mut out,
-                " versionindicator=\"{}\"",
-                resolve_meta_entry_name_by_net_offset(metalib, meta, meta.version_indicator.n_off)?
-            )?;
+    fn update(&mut self, message: impl FnOnce() -> String) {
+        if !self.enabled || self.last.elapsed() < PROGRESS_INTERVAL {
+            return;
         }
+        self.last = Instant::now();
+        eprintln!("{}", message());
+    }
+}
 
-        if meta.size_type.unit_size > 0 {
-            if meta.size_type.idx_size_type != INVALID_METALIB_VALUE {
-                let type_info = metalib::TDR_PRIMATIVE_TYPE_INFO
-                    .get(meta.size_type.idx_size_type as usize)
-                    .context("Failed to get type info")?;
+/// Scans `data` for metalibs, reporting how far it's got.
+fn scan_with_progress(
+    data: &[u8],
+    parse_options: &ParseOptions,
+    progress: &mut Progress,
+) -> Vec<scan::ScanHit> {
+    let total = data.len() as f64 / (1024.0 * 1024.0);
+    scan::scan_metalibs_with_progress(data, parse_options, |pos, found| {
+        progress.update(|| {
+            format!(
+                "Scanned {:.1} of {total:.1} MiB, {found} metalibs found",
+                pos as f64 / (1024.0 * 1024.0)
+            )
+        })
+    })
+}
 
-                write!(&mut out, " sizeinfo=\"{}\"", type_info.xml_name)?;
-            } else if meta.size_type.n_off != INVALID_METALIB_VALUE {
-                write!(
-                    &mut out,
-                    " sizeinfo=\"{}\"",
-                    resolve_meta_entry_name_by_net_offset(metalib, meta, meta.size_type.n_off)?
-                )?;
-            }
+/// into an error.
+#[allow(clippy::too_many_arguments)]
+fn dump_directory(
+    dir: &str,
+    glob: Option<&str>,
+    offset_spec: OffsetSpec,
+    format: OutputFormat,
+    parse_options: &ParseOptions,
+    export_options: &ExportOptions,
+    output_dir: &str,
+    progress: &mut Progress,
+) -> Result<()> {
+    let mut inputs = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read directory {dir}"))? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
         }
-
-        // None of our example metalibs have this field -- untested.
-        if meta.sort_key.sort_key_offset != INVALID_METALIB_VALUE {
-            write!(
-                &mut out,
-                " sortkey=\"{}\"",
-                resolve_meta_entry_name_by_net_offset(
-                    metalib,
-                    meta,
-                    meta.sort_key.sort_key_offset
-                )?
-            )?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if glob.is_none_or(|glob| glob_match(glob, &name)) {
+            inputs.push(entry.path());
         }
+    }
+    inputs.sort();
 
-        // Unused `primarykey` attribute.
-        if meta.primary_key_member_num > 0 && meta.ptr_primary_key_base != INVALID_METALIB_VALUE {
-            unimplemented!()
-        }
+    // Every input gets its own file, so the output always has to be a directory.
+    let output_dir = if is_directory_path(output_dir) {
+        output_dir.to_string()
+    } else {
+        format!("{output_dir}/")
+    };
 
-        // Unused `splittablefactor` attribute
-        if meta.idx_split_table_factor != INVALID_METALIB_VALUE {
-            unimplemented!()
+    let mut failures = Vec::new();
+    for (done, input) in inputs.iter().enumerate() {
+        progress.update(|| {
+            format!(
+                "{done} of {} files done, {} failed",
+                inputs.len(),
+                failures.len()
+            )
+        });
+        let input = input.to_string_lossy();
+        if let Err(err) = dump_metalib(
+            &input,
+            offset_spec,
+            format,
+            parse_options,
+            export_options,
+            Some(&output_dir),
+        ) {
+            warn!("Failed to dump {input}: {err:#}");
+            failures.push((input.to_string(), err));
         }
+    }
 
-        // Unused `splittablekey` attribute
-        if meta.split_table_key.h_off != INVALID_METALIB_VALUE {
-            unimplemented!()
-        }
+    eprintln!(
+        "Dumped {} of {} files from {dir}",
+        inputs.len() - failures.len(),
+        inputs.len()
+    );
+    if failures.is_empty() {
+        return Ok(());
+    }
+    eprintln!("Failures:");
+    for (input, err) in failures.iter() {
+        eprintln!("  {input}: {err:#}");
+    }
+    anyhow::bail!("{} of {} files failed", failures.len(), inputs.len())
+}
 
-        // Unused `splittablerule` attribute
-        // Always defaults to 0 if unused.
-        if meta.split_table_rule_id != 0 {
-            unimplemented!()
-        }
+/// Writes every metalib embedded in `input_filepath` into `output_dir`, named
+/// `<stem>_<metalib name>_<offset>`, and prints a summary of what was found.
+fn dump_all_metalibs(
+    input_filepath: &str,
+    format: OutputFormat,
+    parse_options: &ParseOptions,
+    export_options: &ExportOptions,
+    output_dir: &str,
+    progress: &mut Progress,
+) -> Result<()> {
+    let data = input::read_all(input_filepath)?;
+    let hits = scan_with_progress(&data, parse_options, progress);
+    if hits.is_empty() {
+        anyhow::bail!("No metalibs found in {input_filepath}");
+    }
 
-        // Unused `dependontable` attribute
-        if meta.ptr_dependon_struct != INVALID_METALIB_VALUE {
-            unimplemented!()
-        }
+    // Every metalib gets its own file, so the output always has to be a directory.
+    let output_dir = if is_directory_path(output_dir) {
+        output_dir.to_string()
+    } else {
+        format!("{output_dir}/")
+    };
 
-        // Unused `uniqueentryname` attribute.
-        if meta
-            .flags
-            .contains(TDRMetaFlags::NEED_PREFIX_FOR_UNIQUENAME)
-        {
-            unimplemented!()
-        }
+    let input_path_stem = input::file_stem(input_filepath);
+    let mut used = std::collections::HashSet::new();
+    eprintln!("Found {} metalibs in {input_filepath}:", hits.len());
+    for hit in hits.iter() {
+        let metalib = &hit.metalib;
+        eprintln!(
+            "  {:#010X}  {} (version {}, {} metas)",
+            hit.offset,
+            metalib.header.name,
+            metalib.header.version,
+            metalib.metas.len()
+        );
+
+        let output = format.export(metalib, export_options)?;
+        let default_name = export::unique_slug(
+            &format!("{input_path_stem}_{}_{:x}", metalib.header.name, hit.offset),
+            &mut used,
+        );
+        write_output(output, &output_dir, &default_name, format.extension())?;
     }
-    writeln!(&mut out, ">")?;
 
-    // Write meta entries....
-    for entry in meta.entries.iter() {
-        writeln!(
-            &mut out,
-            "\t\t{}",
-            dump_tdr_meta_entry_xml(metalib, meta, entry)?
-        )?;
+    Ok(())
+}
+
+/// Copies the raw bytes of the metalib at `offset_spec` (header included) to `output_path`.
+fn extract_metalib(input_filepath: &str, offset_spec: OffsetSpec, output_path: &str) -> Result<()> {
+    let mut file = Input::open(input_filepath)?;
+    let file_len = file.len()?;
+    let offset = offset_spec.resolve(file_len)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let header = metalib::read_metalib_header(&mut file)
+        .with_context(|| format!("Failed to read metalib header at {offset:#X}"))?;
+    metalib::validate_header(&header, offset, false)
+        .with_context(|| format!("No metalib at {offset:#X}"))?;
+    let available = file_len - offset;
+    if header.size as u64 > available {
+        anyhow::bail!(
+            "Metalib `{}` at {offset:#X} is {} bytes but the file ends after {available}, {} bytes are missing",
+            header.name,
+            header.size,
+            header.size as u64 - available
+        );
     }
 
-    // Something ends the tag.
-    writeln!(&mut out, "\t</{tag_name}>")?;
+    let mut data = vec![0; header.size as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut data)?;
+    info!(
+        "Extracting `{}` ({} bytes) from {input_filepath} at {offset:#X}",
+        header.name, header.size
+    );
 
-    Ok(out)
+    let default_name = format!("{}_{offset:X}", input::file_stem(input_filepath));
+    write_output(ExportOutput::Single(data), output_path, &default_name, "bin")
 }
 
-fn dump_metalib_xml(metalib: &Metalib) -> Result<String> {
-    let mut out = String::new();
-
-    let header = &metalib.header;
-    writeln!(
-        &mut out,
-        r#"<?xml version="1.0" encoding="UTF8" standalone="yes" ?>"#
-    )?;
-
-    // Open `metalib` tag.
-    let mut metaline_tag = String::new();
-    write!(&mut metaline_tag, "<metalib")?;
-    write!(
-        &mut metaline_tag,
-        " tagsetversion=\"{}\"",
-        header.xml_tag_set_ver
-    )?;
-    write!(&mut metaline_tag, " name=\"{}\"", header.name)?;
-    write!(&mut metaline_tag, " version=\"{}\"", header.version)?;
-    if header.id != metalib::INVALID_METALIB_VALUE {
-        write!(&mut metaline_tag, " id=\"{}\"", header.id)?;
-    }
-    write!(&mut metaline_tag, ">")?;
-    writeln!(&mut out, "{metaline_tag}")?;
-
-    // Write macros that are unassociated with a group.
-
-    for macro_ in metalib.macros.iter() {
-        if !metalib.is_macro_in_group(macro_)? {
-            writeln!(
-                &mut out,
-                "\t{}",
-                dump_tdr_macro_xml(macro_)?
-            )?;
-        }
-    }
-
-    // Write macro groups
-    for macrogroup in metalib.macrogroups.iter() {
-        writeln!(
-            &mut out,
-            "{}",
-            dump_tdr_macrogroup_xml(metalib, macrogroup)?
-        )?;
+/// Copies every metalib found by the scanner to `<name>.bin` in `output_dir`.
+fn extract_all_metalibs(
+    input_filepath: &str,
+    parse_options: &ParseOptions,
+    output_dir: &str,
+    progress: &mut Progress,
+) -> Result<()> {
+    let data = input::read_all(input_filepath)?;
+    let hits = scan_with_progress(&data, parse_options, progress);
+    if hits.is_empty() {
+        anyhow::bail!("No metalibs found in {input_filepath}");
     }
 
-    // Write unions/structs.
-    for meta in metalib.metas.iter() {
-        writeln!(&mut out, "{}", dump_tdr_meta_xml(metalib, meta)?)?;
+    let mut used = std::collections::HashSet::new();
+    eprintln!("Found {} metalibs in {input_filepath}:", hits.len());
+    for hit in hits.iter() {
+        let header = &hit.metalib.header;
+        let start = hit.offset as usize;
+        let name = export::unique_slug(&header.name, &mut used);
+        eprintln!(
+            "  {:#010X}  {} ({} bytes) -> {name}.bin",
+            hit.offset, header.name, header.size
+        );
+        write_file(
+            &Path::new(output_dir).join(format!("{name}.bin")),
+            &data[start..start + header.size as usize],
+        )?;
     }
 
-    // Close `metalib` tag.
-    writeln!(&mut out, "</metalib>")?;
-
-    Ok(out)
+    Ok(())
 }
 
-fn export_metalib_xml(metalib: &Metalib) -> Result<String> {
-    let mut out = String::new();
-    write!(&mut out, "{}", dump_metalib_xml(metalib)?)?;
-    Ok(out)
+fn main() {
+    let cli = cli::parse_args();
+    env_logger::Builder::new()
+        .filter_level(cli.log_level())
+        .format_timestamp(None)
+        .format_target(false)
+        .init();
+
+    let error_format = cli.errors;
+    if let Err(err) = run(cli) {
+        std::process::exit(error::report(&err, error_format));
+    }
 }
 
-fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: mldec <path to file containg compiled metalib> <hex offset>");
-        anyhow::bail!("Not enough arguments");
-    }
+fn run(cli: cli::Cli) -> Result<()> {
+    let parse_options = cli.parse.to_options();
+    let mut progress = Progress::new(cli.quiet);
+
+    match cli.command {
+        Command::Dump(args) => {
+            let export_options = args.export_options().strict(parse_options.strict);
+            if !args.metas.is_empty() && args.output.format != OutputFormat::Xml {
+                anyhow::bail!("--meta is only supported with --format xml");
+            }
+            if args.cut_version.is_some() && args.output.format != OutputFormat::Xml {
+                anyhow::bail!("--cut-version is only supported with --format xml");
+            }
+            if args.macros_only
+                && !matches!(args.output.format, OutputFormat::Xml | OutputFormat::Json)
+            {
+                anyhow::bail!("--macros-only is only supported with --format xml or json");
+            }
+            if !args.macros_only && args.output.format == OutputFormat::Json {
+                anyhow::bail!("--format json requires --macros-only");
+            }
+            let parse_options = parse_options.macros_only(args.macros_only);
+            let output_path = args.output.path();
+            let offset_spec = args.offset();
+
+            if Path::new(&args.file).is_dir() {
+                let output_dir = output_path
+                    .context("--stdout can't be used when dumping a directory")?;
+                return dump_directory(
+                    &args.file,
+                    args.glob.as_deref(),
+                    offset_spec,
+                    args.output.format,
+                    &parse_options,
+                    &export_options,
+                    output_dir,
+                    &mut progress,
+                );
+            }
 
-    let input_filepath = &args[1];
-    let offset = &args[2];
-    let offset =
-        u64::from_str_radix(offset.trim_start_matches("0x"), 16).expect("unable to parse offset");
+            if args.all {
+                let output_dir = output_path.context("--stdout can't be used with --all")?;
+                return dump_all_metalibs(
+                    &args.file,
+                    args.output.format,
+                    &parse_options,
+                    &export_options,
+                    output_dir,
+                    &mut progress,
+                );
+            }
 
-    println!("Attempting to load TDR Metalib in file:{input_filepath}, offset:{offset:X}");
+            dump_metalib(
+                &args.file,
+                offset_spec,
+                args.output.format,
+                &parse_options,
+                &export_options,
+                output_path,
+            )
+        }
+        Command::Scan(args) => {
+            let output_dir = args
+                .output
+                .path()
+                .context("--stdout can't be used when scanning")?;
+            dump_all_metalibs(
+                &args.file,
+                args.output.format,
+                &parse_options,
+                &args.output.to_options().strict(parse_options.strict),
+                output_dir,
+                &mut progress,
+            )
+        }
+        Command::Info(args) => {
+            let mut input = Input::open(&args.file)?;
+            let offset = resolve_offset(&mut input, args.offset())?;
+            let info = MetalibInfo::read(&mut input, offset, args.full)?;
+            if args.json {
+                print!("{}", info.to_json()?);
+            } else {
+                print!("{}", info.to_text()?);
+            }
+            Ok(())
+        }
+        Command::List(args) => {
+            let mut input = Input::open(&args.file)?;
+            let offset = resolve_offset(&mut input, args.offset())?;
+            let metalib = read_metalib_at(&mut input, offset, &parse_options)?;
+            print!(
+                "{}",
+                list::list_metalib(&metalib, args.sort, args.macros, args.tsv)?
+            );
+            Ok(())
+        }
+        Command::Find(args) => {
+            let mut input = Input::open(&args.file)?;
+            let offset = resolve_offset(&mut input, args.offset())?;
+            let metalib = read_metalib_at(&mut input, offset, &parse_options)?;
+            let flags = args
+                .flags
+                .iter()
+                .fold(metalib::TDRMetaEntryDBFlags::empty(), |all, &flag| all | flag);
+            let found = metalib.find_entries(|_, entry| {
+                args.type_.is_none_or(|type_| entry.type_ == type_) && entry.db_flag.contains(flags)
+            });
+            for (meta, entry) in found {
+                println!("{}.{}", meta.name, entry.name);
+            }
+            Ok(())
+        }
+        Command::Validate(args) => {
+            let mut input = Input::open(&args.file)?;
+            let offset = resolve_offset(&mut input, args.offset())?;
+            let metalib = read_metalib_at(&mut input, offset, &parse_options)?;
+            let issues = metalib.validate();
+            if issues.is_empty() {
+                println!("OK");
+                return Ok(());
+            }
+            for issue in issues.iter() {
+                println!("{issue}");
+            }
+            let errors = issues
+                .iter()
+                .filter(|issue| issue.severity == validate::Severity::Error)
+                .count();
+            if errors > 0 {
+                anyhow::bail!("Found {errors} errors in `{}`", metalib.header.name);
+            }
+            Ok(())
+        }
+        Command::Show(args) => {
+            let mut input = Input::open(&args.file)?;
+            let offset = resolve_offset(&mut input, args.offset)?;
+            let metalib = read_metalib_at(&mut input, offset, &parse_options)?;
+            let meta = metalib.get_meta_by_name(&args.meta)?;
+            print!("{}", metalib.format_meta_tree(meta, args.depth)?);
+            Ok(())
+        }
+        Command::Offsetof(args) => {
+            let mut input = Input::open(&args.file)?;
+            let offset = resolve_offset(&mut input, args.offset)?;
+            let metalib = read_metalib_at(&mut input, offset, &parse_options)?;
+            let meta = metalib.get_meta_by_name(&args.meta)?;
+            let field_offset = match args.path.strip_prefix("0x") {
+                Some(hex) => i64::from_str_radix(hex, 16).ok(),
+                None => args.path.parse().ok(),
+            };
+            let field = match field_offset {
+                Some(field_offset) => layout::field_at(&metalib, meta, field_offset)?,
+                None => layout::resolve_path(&metalib, meta, &args.path)?,
+            };
+            println!(
+                "{}: offset {} ({:#X}), size {}, {}",
+                field.path,
+                field.offset,
+                field.offset,
+                field.size,
+                metalib.entry_type_name(field.entry)?
+            );
+            Ok(())
+        }
+        Command::Decode(args) => {
+            let mut input = Input::open(&args.file)?;
+            let offset = resolve_offset(&mut input, args.offset)?;
+            let metalib = read_metalib_at(&mut input, offset, &parse_options)?;
+            let meta = metalib.get_meta_by_name(&args.meta)?;
+            let data = input::read_all(&args.data)?;
+            let value = match args.net {
+                true => codec::decode_net(&metalib, meta, &data, args.cut_version),
+                false => codec::decode_host(&metalib, meta, &data, args.cut_version),
+            }
+            .with_context(|| format!("Failed to decode {} as `{}`", args.data, meta.name))?;
+            print!("{}", value.to_json());
+            Ok(())
+        }
+        Command::Encode(args) => {
+            let mut input = Input::open(&args.file)?;
+            let offset = resolve_offset(&mut input, args.offset)?;
+            let metalib = read_metalib_at(&mut input, offset, &parse_options)?;
+            let meta = metalib.get_meta_by_name(&args.meta)?;
+            let json = String::from_utf8(input::read_all(&args.json)?)
+                .with_context(|| format!("{} isn't UTF-8", args.json))?;
+            let value = codec::Value::from_json(&json)?;
+            let data = codec::encode_host(&metalib, meta, &value, args.cut_version)
+                .with_context(|| format!("Failed to encode {} as `{}`", args.json, meta.name))?;
+            write_output(
+                ExportOutput::Single(data),
+                &args.output,
+                &input::file_stem(&args.json),
+                "bin",
+            )
+        }
+        Command::Compile(args) => {
+            let xml = compile::decode_xml(&input::read_all(&args.file)?)?;
+            let mut metalib = compile::compile_metalib_xml(&xml)
+                .with_context(|| format!("Failed to compile {}", args.file))?;
+            metalib.arch = args.arch;
+            metalib.header.platform_arch = args.arch.platform_arch();
+            let mut data = Vec::new();
+            writer::write_metalib(&metalib, &mut data)?;
+            info!(
+                "Compiled `{}` ({} metas, {} bytes)",
+                metalib.header.name,
+                metalib.metas.len(),
+                data.len()
+            );
+            write_output(
+                ExportOutput::Single(data),
+                &args.output,
+                &input::file_stem(&args.file),
+                "bin",
+            )
+        }
+        Command::Extract(args) => {
+            let output_path = args.output.as_deref().unwrap_or("./output/");
+            if args.all {
+                extract_all_metalibs(&args.file, &parse_options, output_path, &mut progress)
+            } else {
+                extract_metalib(&args.file, args.offset.spec(), output_path)
+            }
+        }
+        Command::Diff { json, old, new } => diff_metalibs(&old, &new, json, &parse_options),
+        Command::DigestCompare { names, old, new } => {
+            digest_compare(&old, &new, &names, &parse_options)
+        }
+        Command::SurveyFields { json, inputs } => survey_fields(&inputs, json, &parse_options),
+        Command::Site { manifest, output } => build_site(&manifest, &output, &parse_options),
+    }
+}
 
-    // Read metalib
-    let mut file = BufReader::new(File::open(input_filepath)?);
-    _ = file.seek(SeekFrom::Start(offset));
-    let metalib = read_metalib(&mut file)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_syntaxes() {
+        assert_eq!(OffsetSpec::parse("0").unwrap(), OffsetSpec::Start(0));
+        assert_eq!(OffsetSpec::parse("4096").unwrap(), OffsetSpec::Start(4096));
+        assert_eq!(OffsetSpec::parse("0x1000").unwrap(), OffsetSpec::Start(0x1000));
+        assert_eq!(OffsetSpec::parse("0XaBc").unwrap(), OffsetSpec::Start(0xABC));
+        assert_eq!(OffsetSpec::parse("-0x2000").unwrap(), OffsetSpec::FromEnd(0x2000));
+        assert_eq!(OffsetSpec::parse("-16").unwrap(), OffsetSpec::FromEnd(16));
+        assert_eq!(
+            OffsetSpec::parse("18446744073709551615").unwrap(),
+            OffsetSpec::Start(u64::MAX)
+        );
+    }
 
-    let _xml_data = export_metalib_xml(&metalib)?;
+    #[test]
+    fn bad_offsets_are_errors() {
+        let hint = OffsetSpec::parse("1a00").unwrap_err().to_string();
+        assert!(hint.contains("0x1a00"), "{hint}");
 
-    // Find input file name
-    let input_path_stem: String = Path::new(input_filepath).file_stem().unwrap().to_string_lossy().to_string();
+        for value in ["", "-", "0x", "zz", "0xzz", "--1", "1.5", "18446744073709551616"] {
+            let err = OffsetSpec::parse(value).unwrap_err().to_string();
+            assert!(err.starts_with("Invalid offset"), "{value}: {err}");
+        }
+    }
 
-    let mut file = File::create(format!("./output/{input_path_stem}_{offset:X}.xml"))?;
-    file.write_all(_xml_data.as_bytes())?;
+    #[test]
+    fn offsets_resolve_against_the_file_length() {
+        assert_eq!(OffsetSpec::Start(0).resolve(0x100).unwrap(), 0);
+        assert_eq!(OffsetSpec::Start(0xFF).resolve(0x100).unwrap(), 0xFF);
+        assert_eq!(OffsetSpec::FromEnd(0x10).resolve(0x100).unwrap(), 0xF0);
+        assert_eq!(OffsetSpec::FromEnd(0x100).resolve(0x100).unwrap(), 0);
 
-    Ok(())
+        assert!(OffsetSpec::Start(0x100).resolve(0x100).is_err());
+        assert!(OffsetSpec::FromEnd(0).resolve(0x100).is_err());
+        assert!(OffsetSpec::FromEnd(0x101).resolve(0x100).is_err());
+    }
 }
@@ -2,10 +2,26 @@ use anyhow::{anyhow, Context, Result};
 use bitflags::bitflags;
 use byteorder::{LittleEndian, ReadBytesExt};
 use int_enum::IntEnum;
+use log::{debug, trace, warn};
 use reader_utils::StringReadExt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::io::{prelude::*, Cursor, SeekFrom};
+use std::ops::Range;
+use std::net::Ipv4Addr;
+use std::sync::OnceLock;
 
+use crate::arch::{probe_arch, ArchProbe, MetalibArch, PLATFORM_ARCH_32, PLATFORM_ARCH_64};
+use crate::codec::{format_date, format_ip, format_time, parse_date, parse_time};
+use crate::error::{find_parse_error, is_truncation, locate, rebase, ErrorKind, ParseError};
+use crate::options::ParseOptions;
 use crate::reader_utils;
+use crate::report::{collect_warnings, report_warning, ParseReport, ParseWarning, WarningCategory};
+use crate::writer::encode_string;
+
+#[cfg(feature = "serde")]
+mod flags_serde;
 
 // None of the structs in this file have unused fields, despite the #[allow(unused)] attribute.
 // Rust gives these errors because the fields are not used directly here (e.g. only in a debug print)
@@ -70,6 +86,8 @@ bitflags! {
 
 #[repr(i32)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq, IntEnum)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[allow(clippy::upper_case_acronyms)]
 pub enum MetaPrimativeType {
     UNKNOWN = -1,
     UNION = 0,
@@ -148,12 +166,166 @@ pub const TDR_PRIMATIVE_TYPE_INFO: &[TDRTypeInfo] = &[
     TDRTypeInfo { xml_name: "ulong",     c_name: "uint32_t",       primative_type: MetaPrimativeType::ULONG,     size: 4 },
     TDRTypeInfo { xml_name: "longlong",  c_name: "int64_t",        primative_type: MetaPrimativeType::LONGLONG,  size: 8 },
     TDRTypeInfo { xml_name: "ulonglong", c_name: "uint64_t",       primative_type: MetaPrimativeType::ULONGLONG, size: 8 },
+    // Not seen in a metalib yet, so its index is a guess. It's last so it can't shift the
+    // indexes of the rows above.
+    TDRTypeInfo { xml_name: "money",     c_name: "int32_t",        primative_type: MetaPrimativeType::MONEY,     size: 4 },
 ];
 
-/// Serialized size of the MetalibHeader struct.
+/// The row `idx_type` of [`TDR_PRIMATIVE_TYPE_INFO`], if there is one.
+pub fn primitive_type_info(idx_type: i32) -> Option<&'static TDRTypeInfo<'static>> {
+    usize::try_from(idx_type)
+        .ok()
+        .and_then(|idx| TDR_PRIMATIVE_TYPE_INFO.get(idx))
+}
+
+/// [`primitive_type_info`], failing with "`{what}` has `{field}={idx_type}`, which
+/// exceeds the primitive type table".
+fn primitive_type_info_of(
+    what: &str,
+    field: &str,
+    idx_type: i32,
+) -> Result<&'static TDRTypeInfo<'static>> {
+    primitive_type_info(idx_type).with_context(|| type_index_message(what, field, idx_type))
+}
+
+fn type_index_message(what: &str, field: &str, idx_type: i32) -> String {
+    format!(
+        "{what} has {field}={idx_type}, which exceeds the primitive type table ({} entries)",
+        TDR_PRIMATIVE_TYPE_INFO.len()
+    )
+}
+
+/// Serialized size of the MetalibHeader struct, in the layout of [`METALIB_BUILD`].
 pub const METALIB_HEADER_SIZE: u32 = 0x114;
 
+/// Default limit on `MetalibHeader.size`. Real metalibs are a few MB at most, so anything
+/// near this is a corrupt header rather than a body worth reading.
+pub const MAX_METALIB_SIZE: u64 = 512 * 1024 * 1024;
+
+/// `MetalibHeader.magic` of every metalib (`D6 02` on disk).
+pub const METALIB_MAGIC: u16 = 0x02D6;
+
+/// `MetalibHeader.build` of the current header layout, the one the builder and writer use.
+pub const METALIB_BUILD: u16 = 0xB;
+
+/// `MetalibHeader.build` of the older header layout, without `field_78`..`field_90`.
+pub const METALIB_BUILD_10: u16 = 0xA;
+
+/// How the header of one `build` is laid out. Every layout shares the fields up to the
+/// macrogroup pointers at 0x74, so those are at the same offsets in all of them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct HeaderLayout {
+    pub build: u16,
+
+    /// Serialized size of the header, where the body starts.
+    pub size: u32,
+
+    /// Whether the seven words `field_78`..`field_90` come before the name. Without them
+    /// they're read as 0.
+    pub has_field_78: bool,
+}
+
+/// The header layouts we can read, newest first. Other builds may lay the header out
+/// differently, so reading them would shift every pointer.
+pub const HEADER_LAYOUTS: [HeaderLayout; 2] = [
+    HeaderLayout {
+        build: METALIB_BUILD,
+        size: METALIB_HEADER_SIZE,
+        has_field_78: true,
+    },
+    // Reconstructed rather than taken from a real file: build 11 without the words it added
+    // after the macrogroup pointers, which the older runtimes leave out.
+    HeaderLayout {
+        build: METALIB_BUILD_10,
+        size: METALIB_HEADER_SIZE - 7 * 4,
+        has_field_78: false,
+    },
+];
+
+impl HeaderLayout {
+    /// The layout of `build`, if it's one we know.
+    pub fn for_build(build: u16) -> Option<HeaderLayout> {
+        HEADER_LAYOUTS
+            .iter()
+            .find(|layout| layout.build == build)
+            .copied()
+    }
+
+    /// The layout of `build`, falling back to the current one for a build we don't know, so
+    /// that its header can still be read far enough to report it.
+    pub fn for_build_or_current(build: u16) -> HeaderLayout {
+        HeaderLayout::for_build(build).unwrap_or(HEADER_LAYOUTS[0])
+    }
+
+    /// Serialized size of the smallest header layout.
+    pub fn min_size() -> u32 {
+        HEADER_LAYOUTS.iter().map(|layout| layout.size).min().unwrap_or(METALIB_HEADER_SIZE)
+    }
+}
+
+/// Serialized size of a TDRMacro (32-bit layout).
+pub const TDR_MACRO_SIZE: u32 = 0x10;
+
+/// Serialized size of a TDRIdEntry, TDRNameEntry or TDRMapEntry.
+pub const TDR_TABLE_ENTRY_SIZE: u32 = 0x8;
+
+/// Serialized size of a TDRMeta, not counting its entries (32-bit layout).
+pub const TDR_META_SIZE: u32 = 0xB8;
+
+/// Serialized size of a TDRMetaEntry (32-bit layout).
+pub const TDR_META_ENTRY_SIZE: u32 = 0xB4;
+
+/// Serialized size of a TDRMacroGroup, not counting its two index maps.
+pub const TDR_MACRO_GROUP_SIZE: u32 = 0x94;
+
+/// Largest `TDRMeta.split_table_rule_id` TDR defines: 0 is no rule, 1 splits rows by their
+/// `splittablekey` modulo the `splittablefactor`.
+pub const MAX_SPLIT_TABLE_RULE: i16 = 1;
+
+/// Serialized sizes of the records that hold pointers, which depend on the layout.
+#[derive(Clone, Copy, Debug)]
+pub struct RecordSizes {
+    pub pointer: u32,
+    pub macro_: u32,
+    pub id_entry: u32,
+
+    /// A TDRNameEntry or TDRMapEntry.
+    pub table_entry: u32,
+    pub meta: u32,
+    pub meta_entry: u32,
+    pub macro_group: u32,
+
+    /// A TDRDBKeyInfo, one row of a meta's primary key table.
+    pub db_key_info: u32,
+}
+
+/// Record sizes of the 32-bit layout.
+pub const RECORD_SIZES_32: RecordSizes = RecordSizes {
+    pointer: 4,
+    macro_: TDR_MACRO_SIZE,
+    id_entry: TDR_TABLE_ENTRY_SIZE,
+    table_entry: TDR_TABLE_ENTRY_SIZE,
+    meta: TDR_META_SIZE,
+    meta_entry: TDR_META_ENTRY_SIZE,
+    macro_group: TDR_MACRO_GROUP_SIZE,
+    db_key_info: 8,
+};
+
+/// Record sizes of the 64-bit layout: the 32-bit records with every pointer widened to 8
+/// bytes and aligned to 8, and each record padded to a multiple of 8.
+pub const RECORD_SIZES_64: RecordSizes = RecordSizes {
+    pointer: 8,
+    macro_: 0x20,
+    id_entry: TDR_TABLE_ENTRY_SIZE,
+    table_entry: 0x10,
+    meta: 0xE8,
+    meta_entry: 0xE0,
+    macro_group: 0xA0,
+    db_key_info: 0x10,
+};
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(unused)]
 pub struct MetalibHeader {
     pub magic: u16,
@@ -238,14 +410,44 @@ pub struct MetalibHeader {
     /// (Stored on-disk as fixed size string buffer: `[u8; 128]`)
     pub name: String,
 }
-// fn read_metalib_header(rdr: &mut impl ReadBytesExt) -> Result<MetalibHeader>
-fn read_metalib_header<T>(rdr: &mut T) -> Result<MetalibHeader>
+impl MetalibHeader {
+    /// The layout this header is read and written in, picked by its build.
+    pub fn layout(&self) -> HeaderLayout {
+        HeaderLayout::for_build_or_current(self.build)
+    }
+
+    /// Serialized size of this header, where the body starts.
+    pub fn header_size(&self) -> u32 {
+        self.layout().size
+    }
+
+    /// Size of the body, as claimed by `size`.
+    pub fn body_size(&self) -> u32 {
+        self.size.saturating_sub(self.header_size())
+    }
+}
+
+/// Reads a word that's only in layouts with `field_78`..`field_90`, or 0 for the others.
+fn read_field_78_word<T: Read>(rdr: &mut T, layout: HeaderLayout) -> Result<u32> {
+    if layout.has_field_78 {
+        Ok(rdr.read_u32::<LittleEndian>()?)
+    } else {
+        Ok(0)
+    }
+}
+
+/// Reads a header in the layout its `build` selects. A build we don't know is read in the
+/// current layout, for [`validate_header`] to reject.
+pub fn read_metalib_header<T>(rdr: &mut T) -> Result<MetalibHeader>
 where
     T: Read + std::io::Seek,
 {
+    let magic = rdr.read_u16::<LittleEndian>()?;
+    let build = rdr.read_u16::<LittleEndian>()?;
+    let layout = HeaderLayout::for_build_or_current(build);
     let header = MetalibHeader {
-        magic: rdr.read_u16::<LittleEndian>()?,
-        build: rdr.read_u16::<LittleEndian>()?,
+        magic,
+        build,
         platform_arch: rdr.read_u32::<LittleEndian>()?,
         size: rdr.read_u32::<LittleEndian>()?,
         field_c: rdr.read_u32::<LittleEndian>()?,
@@ -275,21 +477,43 @@ where
         ptr_free_str_buf: rdr.read_u32::<LittleEndian>()?,
         ptr_macro_group_map: rdr.read_u32::<LittleEndian>()?,
         ptr_macros_group: rdr.read_u32::<LittleEndian>()?,
-        field_78: rdr.read_u32::<LittleEndian>()?,
-        field_7c: rdr.read_i32::<LittleEndian>()?,
-        field_80: rdr.read_i32::<LittleEndian>()?,
-        field_84: rdr.read_u32::<LittleEndian>()?,
-        field_88: rdr.read_u32::<LittleEndian>()?,
-        field_8c: rdr.read_i32::<LittleEndian>()?,
-        field_90: rdr.read_i32::<LittleEndian>()?,
+        field_78: read_field_78_word(rdr, layout)?,
+        field_7c: read_field_78_word(rdr, layout)? as i32,
+        field_80: read_field_78_word(rdr, layout)? as i32,
+        field_84: read_field_78_word(rdr, layout)?,
+        field_88: read_field_78_word(rdr, layout)?,
+        field_8c: read_field_78_word(rdr, layout)? as i32,
+        field_90: read_field_78_word(rdr, layout)? as i32,
         name: rdr.read_fixed_size_utf8_string(128)?,
     };
 
     Ok(header)
 }
 
+/// A field of a meta, referenced by its offset from another entry or meta.
 #[derive(Debug)]
 #[allow(unused)]
+pub struct EntryRef {
+    /// Dotted path of the field, relative to the meta (e.g. `header.len`).
+    pub path: String,
+
+    /// Offset the field was resolved from.
+    pub offset: i32,
+}
+
+/// Where the serialized size of an entry or meta is stored.
+#[derive(Debug)]
+pub enum SizeSource {
+    /// A length prefix of the given type written right before the data.
+    TypePrefix(&'static TDRTypeInfo<'static>),
+
+    /// Another field of the meta.
+    Field(EntryRef),
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[allow(unused)]
 pub struct TDRSizeInfo {
     pub _offset: u64,
     pub n_off: i32,
@@ -298,6 +522,30 @@ pub struct TDRSizeInfo {
     pub idx_size_type: i32,
 }
 
+impl TDRSizeInfo {
+    /// Resolves the `sizeinfo` of an entry or meta, preferring an explicit prefix type over a field.
+    pub fn resolve(&self, metalib: &Metalib, meta: &TDRMeta) -> Result<Option<SizeSource>> {
+        if self.unit_size <= 0 {
+            return Ok(None);
+        }
+
+        if self.idx_size_type != INVALID_METALIB_VALUE {
+            let what = format!("A sizeinfo in `{}`", meta.name);
+            let type_info = primitive_type_info_of(&what, "idx_size_type", self.idx_size_type)?;
+            return Ok(Some(SizeSource::TypePrefix(type_info)));
+        }
+
+        if self.n_off != INVALID_METALIB_VALUE {
+            return Ok(Some(SizeSource::Field(EntryRef {
+                path: metalib.resolve_field_path_by_net_offset(meta, self.n_off)?.path,
+                offset: self.n_off,
+            })));
+        }
+
+        Ok(None)
+    }
+}
+
 fn read_tdr_size_info<T>(rdr: &mut T) -> Result<TDRSizeInfo>
 where
     T: ReadBytesExt + std::io::Seek,
@@ -312,6 +560,7 @@ where
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(unused)]
 pub struct TDRRedirector {
     pub _offset: u64,
@@ -320,6 +569,20 @@ pub struct TDRRedirector {
     pub unit_size: i32,
 }
 
+impl TDRRedirector {
+    /// Resolves the field this redirector points at (by net offset), if it's set.
+    pub fn resolve(&self, metalib: &Metalib, meta: &TDRMeta) -> Result<Option<EntryRef>> {
+        if self.n_off == INVALID_METALIB_VALUE {
+            return Ok(None);
+        }
+
+        Ok(Some(EntryRef {
+            path: metalib.resolve_field_path_by_net_offset(meta, self.n_off)?.path,
+            offset: self.n_off,
+        }))
+    }
+}
+
 fn read_tdr_redirector<T>(rdr: &mut T) -> Result<TDRRedirector>
 where
     T: ReadBytesExt + std::io::Seek,
@@ -333,6 +596,7 @@ where
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(unused)]
 pub struct TDRSelector {
     pub _offset: u64,
@@ -341,7 +605,59 @@ pub struct TDRSelector {
     pub ptr_entry: i32,
 }
 
-fn read_tdr_selector<T>(rdr: &mut T) -> Result<TDRSelector>
+impl TDRSelector {
+    /// Resolves the field this selector points at, if it's set: by host offset, or by the
+    /// entry pointer when the offset is unset or no field starts there. Unlike a
+    /// [`TDRRedirector`] there's no net offset; the tdr compiler stores the entry instead.
+    pub fn resolve(&self, metalib: &Metalib, meta: &TDRMeta) -> Result<Option<EntryRef>> {
+        let by_offset = match self.h_off {
+            INVALID_METALIB_VALUE => None,
+            h_off => Some(metalib.resolve_field_path_by_host_offset(meta, h_off)),
+        };
+        let err = match by_offset {
+            None if self.ptr_entry == INVALID_METALIB_VALUE => return Ok(None),
+            None => None,
+            Some(Ok(field)) => {
+                return Ok(Some(EntryRef {
+                    path: field.path,
+                    offset: self.h_off,
+                }))
+            }
+            Some(Err(err)) => Some(err),
+        };
+
+        let field = match self.ptr_entry {
+            INVALID_METALIB_VALUE => None,
+            ptr_entry => metalib
+                .iter_field_paths(meta)
+                .find(|field| field.entry._offset == ptr_entry as u64),
+        };
+        match (field, err) {
+            (Some(field), err) => {
+                if let Some(err) = err {
+                    warn!(
+                        "{err}, using the entry pointer {:#X} of the selector at {:#X} (`{}`)",
+                        self.ptr_entry, self._offset, field.path
+                    );
+                }
+                Ok(Some(EntryRef {
+                    path: field.path,
+                    offset: field.abs_h_off as i32,
+                }))
+            }
+            (None, Some(err)) => Err(err),
+            (None, None) => {
+                warn!(
+                    "Selector at {:#X} points at entry {:#X}, not a field of `{}`, ignoring it",
+                    self._offset, self.ptr_entry, meta.name
+                );
+                Ok(None)
+            }
+        }
+    }
+}
+
+fn read_tdr_selector<T>(rdr: &mut T, arch: MetalibArch) -> Result<TDRSelector>
 where
     T: ReadBytesExt + std::io::Seek,
 {
@@ -349,11 +665,12 @@ where
         _offset: rdr.stream_position()?,
         unit_size: rdr.read_i32::<LittleEndian>()?,
         h_off: rdr.read_i32::<LittleEndian>()?,
-        ptr_entry: rdr.read_i32::<LittleEndian>()?,
+        ptr_entry: read_pointer(rdr, arch)?,
     })
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(unused)]
 pub struct TDRSortKeyInfo {
     pub _offset: u64,
@@ -362,7 +679,7 @@ pub struct TDRSortKeyInfo {
     pub ptr_sort_key_meta: i32,
 }
 
-fn read_tdr_sort_key_info<T>(rdr: &mut T) -> Result<TDRSortKeyInfo>
+fn read_tdr_sort_key_info<T>(rdr: &mut T, arch: MetalibArch) -> Result<TDRSortKeyInfo>
 where
     T: ReadBytesExt + std::io::Seek,
 {
@@ -370,11 +687,12 @@ where
         _offset: rdr.stream_position()?,
         idx_sort_entry: rdr.read_i32::<LittleEndian>()?,
         sort_key_offset: rdr.read_i32::<LittleEndian>()?,
-        ptr_sort_key_meta: rdr.read_i32::<LittleEndian>()?,
+        ptr_sort_key_meta: read_pointer(rdr, arch)?,
     })
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(unused)]
 pub struct TDRDBKeyInfo {
     pub _offset: u64,
@@ -382,18 +700,46 @@ pub struct TDRDBKeyInfo {
     pub ptr_entry: i32,
 }
 
-fn read_tdr_db_key_info<T>(rdr: &mut T) -> Result<TDRDBKeyInfo>
+impl TDRDBKeyInfo {
+    /// Resolves the field this key points at (by host offset), if it's set. Warns if
+    /// `ptr_entry` is set but points somewhere other than the field's entry.
+    pub fn resolve(&self, metalib: &Metalib, meta: &TDRMeta) -> Result<Option<EntryRef>> {
+        if self.h_off == INVALID_METALIB_VALUE {
+            return Ok(None);
+        }
+
+        let field = metalib.resolve_field_path_by_host_offset(meta, self.h_off)?;
+        if self.ptr_entry != INVALID_METALIB_VALUE && self.ptr_entry as u64 != field.entry._offset {
+            let message = format!(
+                "Key `{}` of meta `{}` is the entry at {:#X}, but its entry pointer is {:#X}",
+                field.path, meta.name, field.entry._offset, self.ptr_entry
+            );
+            report_warning(
+                ParseWarning::new(WarningCategory::Pointer, message)
+                    .at(self._offset)
+                    .meta(&meta.name),
+            );
+        }
+        Ok(Some(EntryRef {
+            path: field.path,
+            offset: self.h_off,
+        }))
+    }
+}
+
+fn read_tdr_db_key_info<T>(rdr: &mut T, arch: MetalibArch) -> Result<TDRDBKeyInfo>
 where
     T: ReadBytesExt + std::io::Seek,
 {
     Ok(TDRDBKeyInfo {
         _offset: rdr.stream_position()?,
         h_off: rdr.read_i32::<LittleEndian>()?,
-        ptr_entry: rdr.read_i32::<LittleEndian>()?,
+        ptr_entry: read_pointer(rdr, arch)?,
     })
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(unused)]
 pub struct TDRIdEntry {
     pub _offset: u64,
@@ -417,6 +763,7 @@ where
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(unused)]
 pub struct TDRNameEntry {
     pub _offset: u64,
@@ -424,22 +771,38 @@ pub struct TDRNameEntry {
     /// Offset to a GBK encoded string
     pub ptr: i32,
 
-    /// Offset to a TDRMeta
+    /// Index of the meta's entry in the meta map
     pub idx: i32,
+
+    /// Parsed string at `ptr`.
+    pub name: String,
 }
 
-fn read_tdr_name_entry<T>(rdr: &mut T) -> Result<TDRNameEntry>
+fn read_tdr_name_entry<T>(
+    rdr: &mut T,
+    arch: MetalibArch,
+    options: &ParseOptions,
+) -> Result<TDRNameEntry>
 where
-    T: ReadBytesExt + std::io::Seek,
+    T: ReadBytesExt + std::io::Seek + StringBuffer,
 {
-    Ok(TDRNameEntry {
-        _offset: rdr.stream_position()?,
-        ptr: rdr.read_i32::<LittleEndian>()?,
+    let _offset = rdr.stream_position()?;
+    let ptr = read_pointer(rdr, arch)?;
+    rdr.seek(SeekFrom::Start(_offset))?;
+    let name =
+        read_string_pointer(rdr, arch, "name table entry", (_offset, StringField::Name), options)?;
+    let entry = TDRNameEntry {
+        _offset,
+        ptr,
         idx: rdr.read_i32::<LittleEndian>()?,
-    })
+        name,
+    };
+    align_pointer(rdr, arch)?;
+    Ok(entry)
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(unused)]
 pub struct TDRMapEntry {
     pub _offset: u64,
@@ -451,18 +814,21 @@ pub struct TDRMapEntry {
     pub size: i32,
 }
 
-fn read_tdr_map_entry<T>(rdr: &mut T) -> Result<TDRMapEntry>
+fn read_tdr_map_entry<T>(rdr: &mut T, arch: MetalibArch) -> Result<TDRMapEntry>
 where
     T: ReadBytesExt + std::io::Seek,
 {
-    Ok(TDRMapEntry {
+    let entry = TDRMapEntry {
         _offset: rdr.stream_position()?,
-        ptr: rdr.read_i32::<LittleEndian>()?,
+        ptr: read_pointer(rdr, arch)?,
         size: rdr.read_i32::<LittleEndian>()?,
-    })
+    };
+    align_pointer(rdr, arch)?;
+    Ok(entry)
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(unused)]
 pub struct TDRMacro {
     pub _offset: u64,
@@ -472,26 +838,350 @@ pub struct TDRMacro {
     pub unk: i32,
 }
 
-fn read_tdr_macro<T>(rdr: &mut T) -> Result<TDRMacro>
+/// Placeholder written in place of a value that couldn't be read in lenient mode.
+pub fn placeholder(what: &str, offset: u64) -> String {
+    format!("??{what}@{offset:#X}??")
+}
+
+/// Skips to the next multiple of 8 in the 64-bit layout, where pointers and the records
+/// holding them are 8-byte aligned.
+fn align_pointer<T>(rdr: &mut T, arch: MetalibArch) -> Result<()>
+where
+    T: std::io::Seek,
+{
+    if arch == MetalibArch::Bits64 {
+        let pos = rdr.stream_position()?;
+        rdr.seek(SeekFrom::Start(pos.next_multiple_of(8)))?;
+    }
+    Ok(())
+}
+
+/// Reads a pointer (a body offset): an i32, or an aligned i64 in the 64-bit layout.
+/// Either width of -1 comes back as [`INVALID_METALIB_VALUE`].
+fn read_pointer<T>(rdr: &mut T, arch: MetalibArch) -> Result<i32>
 where
     T: ReadBytesExt + std::io::Seek,
 {
-    Ok(TDRMacro {
-        _offset: rdr.stream_position()?,
-        name: rdr.read_null_terminated_gbk_string_i32_offset_pointer()?,
+    if arch == MetalibArch::Bits32 {
+        return Ok(rdr.read_i32::<LittleEndian>()?);
+    }
+    align_pointer(rdr, arch)?;
+    let pos = rdr.stream_position()?;
+    match rdr.read_i64::<LittleEndian>()? {
+        -1 | 0xFFFF_FFFF => Ok(INVALID_METALIB_VALUE),
+        ptr => i32::try_from(ptr).map_err(|_| {
+            ParseError::at(
+                ErrorKind::Other,
+                pos,
+                format!("Pointer {ptr:#X} is out of range"),
+            )
+        }),
+    }
+}
+
+/// Length of the body `rdr` reads.
+fn body_len<T>(rdr: &mut T) -> Result<u64>
+where
+    T: std::io::Seek,
+{
+    let pos = rdr.stream_position()?;
+    let len = rdr.seek(SeekFrom::End(0))?;
+    rdr.seek(SeekFrom::Start(pos))?;
+    Ok(len)
+}
+
+/// Fails if `what`, `len` bytes at `start`, runs past the end of the body `rdr` reads.
+fn check_extent<T>(rdr: &mut T, start: u64, len: u64, what: impl FnOnce() -> String) -> Result<()>
+where
+    T: std::io::Seek,
+{
+    let body_len = body_len(rdr)?;
+    if start.checked_add(len).is_none_or(|end| end > body_len) {
+        return Err(ParseError::at(
+            ErrorKind::Truncated,
+            start,
+            format!("{} exceeds metalib body of {body_len:#X} bytes", what()),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns `ptr`, a meta or macrogroup pointer of `entry`, after checking it's inside the
+/// body. In lenient mode a bad pointer is cleared instead.
+fn check_entry_pointer<T>(
+    rdr: &mut T,
+    entry: &TDRMetaEntry,
+    field: &str,
+    ptr: i32,
+    options: &ParseOptions,
+) -> Result<i32>
+where
+    T: std::io::Seek,
+{
+    let body_len = body_len(rdr)?;
+    if ptr == INVALID_METALIB_VALUE || (0..body_len as i64).contains(&(ptr as i64)) {
+        return Ok(ptr);
+    }
+    let message = format!(
+        "Entry `{}` {field} {ptr:#X} is outside the metalib body of {body_len:#X} bytes",
+        entry.name
+    );
+    if options.strict {
+        return Err(ParseError::at(ErrorKind::Other, entry._offset, message));
+    }
+    report_warning(
+        ParseWarning::new(WarningCategory::Pointer, message)
+            .at(entry._offset)
+            .entry(&entry.name),
+    );
+    Ok(INVALID_METALIB_VALUE)
+}
+
+/// Reads the `field` string through an offset pointer, keeping any raw bytes under
+/// `raw_key`, the record's offset and the field. In lenient mode an unreadable string
+/// becomes a placeholder instead of an error.
+fn read_string_pointer<T>(
+    rdr: &mut T,
+    arch: MetalibArch,
+    field: &str,
+    raw_key: (u64, StringField),
+    options: &ParseOptions,
+) -> Result<String>
+where
+    T: ReadBytesExt + std::io::Seek + StringBuffer,
+{
+    let ptr = read_pointer(rdr, arch)?;
+    if ptr == INVALID_METALIB_VALUE {
+        return Ok("".to_string());
+    }
+    let pos = rdr.stream_position()?;
+    let string = read_buffer_string(rdr, ptr as u32 as u64, options);
+    _ = rdr.seek(SeekFrom::Start(pos))?;
+    let err = match string {
+        Ok(string) => {
+            keep_raw_string(rdr, ptr as u32 as u64, raw_key.0, raw_key.1);
+            return Ok(string);
+        }
+        Err(err) => err,
+    };
+
+    let pos = pos - arch.record_sizes().pointer as u64;
+    if options.strict {
+        return Err(ParseError::at(
+            ErrorKind::StringOob,
+            pos,
+            format!("Unreadable {field} pointer {ptr:#X}: {err:#}"),
+        ));
+    }
+    let message = format!("Unreadable {field} pointer {ptr:#X} at {pos:#X}: {err:#}");
+    report_warning(ParseWarning::new(WarningCategory::String, message).at(pos));
+    Ok(placeholder("bad-string", ptr as u32 as u64))
+}
+
+/// A reader over a metalib body that knows where its string buffer is, so string pointers
+/// can be checked against it rather than followed anywhere in the body.
+trait StringBuffer {
+    /// Body offsets of the strings: from `ptr_str_buf` up to `ptr_free_str_buf`.
+    fn string_buffer(&self) -> Range<u64>;
+
+    /// Strings already read from the buffer, by offset.
+    fn string_cache(&mut self) -> &mut HashMap<u64, String>;
+
+    /// Original bytes of the strings read that don't encode back to their text, by offset.
+    fn raw_bytes(&mut self) -> &mut HashMap<u64, Vec<u8>>;
+
+    /// Those bytes again for each record field that points at them, for the metalib.
+    fn raw_strings(&mut self) -> &mut Vec<RawString>;
+}
+
+/// The body of the metalib being parsed.
+struct BodyReader<'a> {
+    cursor: Cursor<&'a [u8]>,
+    string_buffer: Range<u64>,
+    /// The compiler writes each distinct string once, so names like `count` or `reserved`
+    /// are pointed at by many entries and only need decoding the first time. A reader lasts
+    /// one parse, whose options don't change, so they aren't part of the key.
+    string_cache: HashMap<u64, String>,
+    raw_bytes: HashMap<u64, Vec<u8>>,
+    raw_strings: Vec<RawString>,
+}
+
+impl<'a> BodyReader<'a> {
+    /// Takes the string buffer from `header`, or the whole body (with a warning) if the
+    /// header's doesn't fit in it.
+    fn new(header: &MetalibHeader, body: &'a [u8]) -> BodyReader<'a> {
+        let (start, end) = (header.ptr_str_buf as u64, header.ptr_free_str_buf as u64);
+        let string_buffer = if start == end {
+            // No strings at all, which is fine wherever the (unused) pointers are.
+            start..end
+        } else if start <= end && end <= body.len() as u64 {
+            start..end
+        } else {
+            let message = format!(
+                "String buffer {start:#X}..{end:#X} isn't inside the {:#X} byte body, \
+                 allowing strings anywhere in it",
+                body.len()
+            );
+            report_warning(ParseWarning::new(WarningCategory::Header, message));
+            0..body.len() as u64
+        };
+        BodyReader {
+            cursor: Cursor::new(body),
+            string_buffer,
+            string_cache: HashMap::new(),
+            raw_bytes: HashMap::new(),
+            raw_strings: Vec::new(),
+        }
+    }
+}
+
+impl Read for BodyReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.cursor.read(buf)
+    }
+}
+
+impl Seek for BodyReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.cursor.seek(pos)
+    }
+}
+
+impl StringBuffer for BodyReader<'_> {
+    fn string_buffer(&self) -> Range<u64> {
+        self.string_buffer.clone()
+    }
+
+    fn string_cache(&mut self) -> &mut HashMap<u64, String> {
+        &mut self.string_cache
+    }
+
+    fn raw_bytes(&mut self) -> &mut HashMap<u64, Vec<u8>> {
+        &mut self.raw_bytes
+    }
+
+    fn raw_strings(&mut self) -> &mut Vec<RawString> {
+        &mut self.raw_strings
+    }
+}
+
+/// Reads the null-terminated string at `ptr`, which (terminator included) must be inside
+/// the string buffer. Leaves `rdr` somewhere after it, or where it was if the string had
+/// been read before.
+fn read_buffer_string<T>(rdr: &mut T, ptr: u64, options: &ParseOptions) -> Result<String>
+where
+    T: Read + std::io::Seek + StringBuffer,
+{
+    let strings = rdr.string_buffer();
+    if !strings.contains(&ptr) {
+        return Err(anyhow!(
+            "outside the string buffer {:#X}..{:#X}",
+            strings.start,
+            strings.end
+        ));
+    }
+    if let Some(string) = rdr.string_cache().get(&ptr) {
+        return Ok(string.clone());
+    }
+    _ = rdr.seek(SeekFrom::Start(ptr))?;
+    let max_size = options.max_string_size.min((strings.end - ptr) as usize);
+    let bytes = rdr.read_until_byte(b'\0', max_size)?;
+    let string = options.string_encoding.decode(&bytes)?;
+    if options.keep_raw_strings && encode_string(&string).ok().as_ref() != Some(&bytes) {
+        rdr.raw_bytes().insert(ptr, bytes);
+    }
+    rdr.string_cache().insert(ptr, string.clone());
+    Ok(string)
+}
+
+/// Keeps the original bytes of the buffer string at `ptr`, if it had to, as `field` of the
+/// record at `record`.
+fn keep_raw_string<T>(rdr: &mut T, ptr: u64, record: u64, field: StringField)
+where
+    T: StringBuffer,
+{
+    if let Some(bytes) = rdr.raw_bytes().get(&ptr).cloned() {
+        rdr.raw_strings().push(RawString { record, field, bytes });
+    }
+}
+
+/// Keeps the original bytes of `entry`'s default, which `rdr` has just read as `text`, if
+/// it's text and they don't encode back from it.
+fn keep_raw_default<T>(rdr: &mut T, entry: &TDRMetaEntry, text: &str) -> Result<()>
+where
+    T: Read + std::io::Seek + StringBuffer,
+{
+    let Some(type_info) = primitive_type_info(entry.idx_type) else {
+        return Ok(());
+    };
+    let is_string = matches!(
+        type_info.primative_type,
+        MetaPrimativeType::STRING | MetaPrimativeType::WSTRING
+    );
+    let is_text =
+        is_string || (type_info.xml_name == "char" && entry.default_val_len > type_info.size);
+    if !is_text {
+        return Ok(());
+    }
+    let start = entry.ptr_default_val as u64;
+    let end = rdr.stream_position()?;
+    let mut bytes = vec![0; end.saturating_sub(start) as usize];
+    _ = rdr.seek(SeekFrom::Start(start))?;
+    rdr.read_exact(&mut bytes)?;
+    if encode_default_value(entry.idx_type, text).ok().as_ref() != Some(&bytes) {
+        rdr.raw_strings().push(RawString {
+            record: entry._offset,
+            field: StringField::Default,
+            bytes,
+        });
+    }
+    Ok(())
+}
+
+/// Converts the raw type read at `pos`. In lenient mode an unknown value (say, a type added
+/// by a newer TDR) becomes `UNKNOWN`, and the caller keeps the raw value.
+fn primative_type(value: i32, pos: u64, options: &ParseOptions) -> Result<MetaPrimativeType> {
+    match MetaPrimativeType::from_int(value) {
+        Ok(type_) => Ok(type_),
+        Err(_) if !options.strict => {
+            let message = format!("Unknown type {value} at {pos:#X}");
+            report_warning(ParseWarning::new(WarningCategory::Type, message).at(pos));
+            Ok(MetaPrimativeType::UNKNOWN)
+        }
+        Err(_) => Err(ParseError::at(
+            ErrorKind::UnknownType,
+            pos,
+            format!("Unknown type {value}"),
+        )),
+    }
+}
+
+fn read_tdr_macro<T>(rdr: &mut T, arch: MetalibArch, options: &ParseOptions) -> Result<TDRMacro>
+where
+    T: ReadBytesExt + std::io::Seek + StringBuffer,
+{
+    let _offset = rdr.stream_position()?;
+    let tdr_macro = TDRMacro {
+        _offset,
+        name: read_string_pointer(rdr, arch, "macro name", (_offset, StringField::Name), options)?,
         value: rdr.read_i32::<LittleEndian>()?,
-        desc: rdr.read_null_terminated_gbk_string_i32_offset_pointer()?,
+        desc: read_string_pointer(rdr, arch, "macro desc", (_offset, StringField::Desc), options)?,
         unk: rdr.read_i32::<LittleEndian>()?,
-    })
+    };
+    align_pointer(rdr, arch)?;
+    Ok(tdr_macro)
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(unused)]
 pub struct TDRMetaEntry {
     pub _offset: u64,
     pub id: i32,
     pub version: i32,
     pub type_: MetaPrimativeType,
+    /// `type_` as stored, which is all there is to go on when it's `UNKNOWN`.
+    pub raw_type: i32,
     pub name: String,
     pub h_real_size: i32,
     pub n_real_size: i32,
@@ -532,18 +1222,38 @@ pub struct TDRMetaEntry {
 
     /// Parsed string of value at `ptr_default_val`.
     pub default_value_string: String,
+
+    /// The `customattr` string at `ptr_custom_attr`.
+    pub custom_attr: String,
 }
 
-fn read_tdr_meta_entry<T>(rdr: &mut T) -> Result<TDRMetaEntry>
+impl TDRMetaEntry {
+    /// Returns true if the entry occupies no space on the wire (e.g. VOID or pointer members).
+    ///
+    /// Zero-size entries still have a position, so they can be the target of an exact offset
+    /// lookup, but they never contain any other offset and contribute nothing to sizes.
+    pub fn is_zero_size(&self) -> bool {
+        self.n_unit_size == 0
+    }
+}
+
+fn read_tdr_meta_entry<T>(
+    rdr: &mut T,
+    arch: MetalibArch,
+    meta: &str,
+    options: &ParseOptions,
+) -> Result<TDRMetaEntry>
 where
-    T: ReadBytesExt + std::io::Seek,
+    T: ReadBytesExt + std::io::Seek + StringBuffer,
 {
+    let _offset = rdr.stream_position()?;
     let mut meta_entry = TDRMetaEntry {
-        _offset: rdr.stream_position()?,
+        _offset,
         id: rdr.read_i32::<LittleEndian>()?,
         version: rdr.read_i32::<LittleEndian>()?,
-        type_: MetaPrimativeType::from_int(rdr.read_i32::<LittleEndian>()?)?,
-        name: rdr.read_null_terminated_gbk_string_i32_offset_pointer()?,
+        type_: MetaPrimativeType::UNKNOWN,
+        raw_type: rdr.read_i32::<LittleEndian>()?,
+        name: read_string_pointer(rdr, arch, "entry name", (_offset, StringField::Name), options)?,
         h_real_size: rdr.read_i32::<LittleEndian>()?,
         n_real_size: rdr.read_i32::<LittleEndian>()?,
         h_unit_size: rdr.read_i32::<LittleEndian>()?,
@@ -565,116 +1275,369 @@ where
         },
         order: rdr.read_u8()?,
         size_info: read_tdr_size_info(rdr)?,
-        referer: read_tdr_selector(rdr)?,
-        selector: read_tdr_selector(rdr)?,
+        referer: read_tdr_selector(rdr, arch)?,
+        selector: read_tdr_selector(rdr, arch)?,
         io: rdr.read_i32::<LittleEndian>()?,
         idx_io: rdr.read_i32::<LittleEndian>()?,
-        ptr_meta: rdr.read_i32::<LittleEndian>()?,
+        ptr_meta: read_pointer(rdr, arch)?,
         max_id: rdr.read_i32::<LittleEndian>()?,
         min_id: rdr.read_i32::<LittleEndian>()?,
         max_id_idx: rdr.read_i32::<LittleEndian>()?,
         min_id_idx: rdr.read_i32::<LittleEndian>()?,
         default_val_len: rdr.read_i32::<LittleEndian>()?,
-        desc: rdr.read_null_terminated_gbk_string_i32_offset_pointer()?,
-        chinese_name: rdr.read_null_terminated_gbk_string_i32_offset_pointer()?,
-        ptr_default_val: rdr.read_i32::<LittleEndian>()?,
-        ptr_macros_group: rdr.read_i32::<LittleEndian>()?,
-        ptr_custom_attr: rdr.read_i32::<LittleEndian>()?,
+        desc: read_string_pointer(rdr, arch, "entry desc", (_offset, StringField::Desc), options)?,
+        chinese_name: read_string_pointer(
+            rdr,
+            arch,
+            "entry cname",
+            (_offset, StringField::ChineseName),
+            options,
+        )?,
+        ptr_default_val: read_pointer(rdr, arch)?,
+        ptr_macros_group: read_pointer(rdr, arch)?,
+        ptr_custom_attr: read_pointer(rdr, arch)?,
         off_to_meta: rdr.read_i32::<LittleEndian>()?,
         field_a8: rdr.read_i32::<LittleEndian>()?,
         field_ac: rdr.read_i32::<LittleEndian>()?,
         field_b0: rdr.read_i32::<LittleEndian>()?,
         default_value_string: "".to_string(),
+        custom_attr: "".to_string(),
     };
+    align_pointer(rdr, arch)?;
+    meta_entry.type_ = primative_type(meta_entry.raw_type, meta_entry._offset + 8, options)?;
+    check_entry_type_index(meta, &meta_entry, options)?;
+
+    meta_entry.ptr_meta =
+        check_entry_pointer(rdr, &meta_entry, "ptr_meta", meta_entry.ptr_meta, options)?;
+    meta_entry.ptr_macros_group = check_entry_pointer(
+        rdr,
+        &meta_entry,
+        "ptr_macros_group",
+        meta_entry.ptr_macros_group,
+        options,
+    )?;
 
     if meta_entry.ptr_default_val != INVALID_METALIB_VALUE {
         let original_position = rdr.stream_position()?;
         _ = rdr.seek(SeekFrom::Start(meta_entry.ptr_default_val as u64))?;
 
-        // Get the type info to determine how many bytes to read.
-        let type_info = TDR_PRIMATIVE_TYPE_INFO
-            .get(meta_entry.idx_type as usize)
-            .context("Failed to get type info")?;
-
-        // Read it and set string
-        // let mut buf = vec![0; type_info.size.try_into()?];
-        let default_string: String = match type_info.primative_type {
-            MetaPrimativeType::UNKNOWN => unreachable!(),
-            MetaPrimativeType::UNION => unreachable!(),
-            MetaPrimativeType::STRUCT => unreachable!(),
-            MetaPrimativeType::CHAR => format!("{:?}", rdr.read_i8()?),
-            MetaPrimativeType::UCHAR => format!("{:?}", rdr.read_u8()?),
-            MetaPrimativeType::BYTE => format!("{:?}", rdr.read_u8()?),
-            MetaPrimativeType::SHORT => format!("{:?}", rdr.read_i16::<LittleEndian>()?),
-            MetaPrimativeType::USHORT => format!("{:?}", rdr.read_u16::<LittleEndian>()?),
-            MetaPrimativeType::INT => format!("{:?}", rdr.read_i32::<LittleEndian>()?),
-            MetaPrimativeType::UINT => format!("{:?}", rdr.read_u32::<LittleEndian>()?),
-            MetaPrimativeType::LONG => format!("{:?}", rdr.read_i32::<LittleEndian>()?),
-            MetaPrimativeType::ULONG => format!("{:?}", rdr.read_u32::<LittleEndian>()?),
-            MetaPrimativeType::LONGLONG => format!("{:?}", rdr.read_i64::<LittleEndian>()?),
-            MetaPrimativeType::ULONGLONG => format!("{:?}", rdr.read_u64::<LittleEndian>()?),
-            MetaPrimativeType::DATE => todo!(),
-            MetaPrimativeType::TIME => todo!(),
-            MetaPrimativeType::DATETIME => todo!(),
-            MetaPrimativeType::MONEY => todo!(),
-            MetaPrimativeType::FLOAT => format!("{:?}", rdr.read_f32::<LittleEndian>()?),
-            MetaPrimativeType::DOUBLE => format!("{:?}", rdr.read_f64::<LittleEndian>()?),
-            MetaPrimativeType::IP => todo!(),
-            MetaPrimativeType::WCHAR => todo!(),
-            MetaPrimativeType::STRING => {
-                // println!("Reading string default at {:X}", METALIB_HEADER_SIZE as u64 + rdr.stream_position()?);
-                let data = rdr.read_null_terminated_utf8_string()?;
-                // println!("Data: {}", data);
-                data
-            },
-            MetaPrimativeType::WSTRING => todo!(),
-            MetaPrimativeType::VOID => unreachable!(),
+        meta_entry.default_value_string = match read_default_value(
+            rdr,
+            meta_entry.idx_type,
+            meta_entry.default_val_len,
+            meta_entry.count,
+            options,
+        ) {
+            Ok(default_string) => {
+                if options.keep_raw_strings {
+                    keep_raw_default(rdr, &meta_entry, &default_string)?;
+                }
+                default_string
+            }
+            Err(err) if !options.strict => {
+                let message = format!(
+                    "Entry `{meta}.{}` at {:#X}: unreadable default value at {:#X}: {err:#}",
+                    meta_entry.name, meta_entry._offset, meta_entry.ptr_default_val
+                );
+                report_warning(
+                    ParseWarning::new(WarningCategory::Value, message)
+                        .at(meta_entry._offset)
+                        .meta(meta)
+                        .entry(&meta_entry.name),
+                );
+                placeholder("bad-default", meta_entry.ptr_default_val as u64)
+            }
+            Err(err) => return Err(err),
         };
-        // rdr.read_exact(&mut buf)?;
-        // meta_entry.default_value_string = format!("{buf:?}");
-        meta_entry.default_value_string = default_string;
 
         // Return back to read position.
         _ = rdr.seek(SeekFrom::Start(original_position))?;
     }
 
+    if meta_entry.ptr_custom_attr != INVALID_METALIB_VALUE {
+        let ptr = meta_entry.ptr_custom_attr as u32 as u64;
+        let original_position = rdr.stream_position()?;
+        meta_entry.custom_attr = match read_buffer_string(rdr, ptr, options) {
+            Ok(custom_attr) => {
+                keep_raw_string(rdr, ptr, meta_entry._offset, StringField::CustomAttr);
+                custom_attr
+            }
+            Err(err) if !options.strict => {
+                let message = format!(
+                    "Entry `{}` at {:#X}: unreadable custom attribute at {ptr:#X}: {err:#}",
+                    meta_entry.name, meta_entry._offset
+                );
+                report_warning(
+                    ParseWarning::new(WarningCategory::String, message)
+                        .at(meta_entry._offset)
+                        .entry(&meta_entry.name),
+                );
+                placeholder("bad-customattr", ptr)
+            }
+            Err(err) => {
+                return Err(ParseError::at(
+                    ErrorKind::StringOob,
+                    meta_entry._offset,
+                    format!("Entry `{}`: unreadable custom attribute at {ptr:#X}: {err:#}", meta_entry.name),
+                ))
+            }
+        };
+        _ = rdr.seek(SeekFrom::Start(original_position))?;
+    }
+
     Ok(meta_entry)
 }
 
-#[derive(Debug)]
-#[allow(unused)]
-pub struct TDRMeta {
-    pub _offset: u64,
-    pub flags: TDRMetaFlags,
-    pub id: i32,
-    pub base_version: i32,
-    pub cur_version: i32,
-    pub type_: MetaPrimativeType,
-    pub mem_size: i32,
-    pub n_unit_size: i32,
-    pub h_unit_size: i32,
-    pub custom_h_unit_size: i32,
-    pub idx_custom_h_unit_size: i32,
-    pub uncertain_max_sub_id: i32,
-    pub entries_num: i32,
-    pub unk_table_count: i32,
-    pub unk_table_ptr: i32,
-    pub unk_table_unk: i32,
+/// Reads a default value of the type at `idx_type` in [`TDR_PRIMATIVE_TYPE_INFO`], `len`
+/// bytes long, for an entry of `count` elements.
+///
+/// A default longer than one value belongs to an array. A `char` array's is its text, up to
+/// the first NUL. Any other array's is its elements separated by spaces, or just one of them
+/// if they're all the same (as when the compiler repeats a scalar `default`).
+pub(crate) fn read_default_value<T>(
+    rdr: &mut T,
+    idx_type: i32,
+    len: i32,
+    count: i32,
+    options: &ParseOptions,
+) -> Result<String>
+where
+    T: ReadBytesExt + std::io::Seek,
+{
+    let type_info = primitive_type_info_of("The default value", "idx_type", idx_type)?;
+    let is_string = matches!(type_info.primative_type, MetaPrimativeType::STRING | MetaPrimativeType::WSTRING);
+    if is_string || type_info.size <= 0 || len == type_info.size {
+        return read_default_scalar(rdr, type_info, options);
+    }
+    if len < type_info.size {
+        return Err(anyhow!(
+            "The {len} byte default is shorter than one {} byte {} value",
+            type_info.size,
+            type_info.xml_name
+        ));
+    }
 
-    /// The start address of this TDRMeta (file address)
-    pub ptr_meta: i32,
+    // Checked before anything is allocated for it.
+    let array_len = i64::from(count.max(1)) * i64::from(type_info.size);
+    if i64::from(len) > array_len {
+        return Err(anyhow!(
+            "The {len} byte default is longer than the {} byte {} array it's for",
+            array_len,
+            type_info.xml_name
+        ));
+    }
+    if let Some(available) = remaining_len(rdr).filter(|&available| len as u64 > available) {
+        return Err(anyhow!(
+            "The {len} byte default runs past the end of the data, {available} bytes on"
+        ));
+    }
 
-    ///  Index of this TDRMeta within the `Metalib.ptr_meta` table.
-    pub idx: i32,
+    if type_info.xml_name == "char" {
+        let mut buf = vec![0; len as usize];
+        rdr.read_exact(&mut buf)?;
+        let end = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+        return options.string_encoding.decode(&buf[..end]);
+    }
 
-    /// UNCLEAR: Often exceeds the max value in the `Metalib.ptr_id` lookup map
-    pub idx_id: i32,
-    pub idx_type: i32,
+    if len % type_info.size != 0 {
+        return Err(anyhow!(
+            "The {len} byte default isn't a whole number of {} byte {} values",
+            type_info.size,
+            type_info.xml_name
+        ));
+    }
 
-    /// Index into the macros table of an entry containing the meta version.
-    pub idx_version: i32,
+    let mut elements = Vec::new();
+    for _ in 0..len / type_info.size {
+        elements.push(read_default_scalar(rdr, type_info, options)?);
+    }
+    if elements.iter().all(|element| *element == elements[0]) {
+        elements.truncate(1);
+    }
+    Ok(elements.join(" "))
+}
 
-    pub custom_align: i32,
+/// Reads one default value of the given type.
+fn read_default_scalar<T>(rdr: &mut T, type_info: &TDRTypeInfo, options: &ParseOptions) -> Result<String>
+where
+    T: ReadBytesExt + std::io::Seek,
+{
+    // Read it and set string
+    // let mut buf = vec![0; type_info.size.try_into()?];
+    let default_string: String = match type_info.primative_type {
+        MetaPrimativeType::UNKNOWN
+        | MetaPrimativeType::UNION
+        | MetaPrimativeType::STRUCT
+        | MetaPrimativeType::VOID => {
+            return Err(anyhow!("A {} can't have a default value", type_info.xml_name))
+        }
+        MetaPrimativeType::CHAR => format!("{:?}", rdr.read_i8()?),
+        MetaPrimativeType::UCHAR => format!("{:?}", rdr.read_u8()?),
+        MetaPrimativeType::BYTE => format!("{:?}", rdr.read_u8()?),
+        MetaPrimativeType::SHORT => format!("{:?}", rdr.read_i16::<LittleEndian>()?),
+        MetaPrimativeType::USHORT => format!("{:?}", rdr.read_u16::<LittleEndian>()?),
+        MetaPrimativeType::INT => format!("{:?}", rdr.read_i32::<LittleEndian>()?),
+        MetaPrimativeType::UINT => format!("{:?}", rdr.read_u32::<LittleEndian>()?),
+        MetaPrimativeType::LONG => format!("{:?}", rdr.read_i32::<LittleEndian>()?),
+        MetaPrimativeType::ULONG => format!("{:?}", rdr.read_u32::<LittleEndian>()?),
+        MetaPrimativeType::LONGLONG => format!("{:?}", rdr.read_i64::<LittleEndian>()?),
+        MetaPrimativeType::ULONGLONG => format!("{:?}", rdr.read_u64::<LittleEndian>()?),
+        MetaPrimativeType::DATE | MetaPrimativeType::TIME | MetaPrimativeType::DATETIME => {
+            let mut buf = [0; 8];
+            let buf = &mut buf[..type_info.size as usize];
+            rdr.read_exact(buf)?;
+            match type_info.primative_type {
+                MetaPrimativeType::DATE => format_date::<LittleEndian>(buf),
+                MetaPrimativeType::TIME => format_time::<LittleEndian>(buf),
+                _ => format!(
+                    "{} {}",
+                    format_date::<LittleEndian>(&buf[..4]),
+                    format_time::<LittleEndian>(&buf[4..])
+                ),
+            }
+        }
+        MetaPrimativeType::MONEY => format!("{:?}", rdr.read_i32::<LittleEndian>()?),
+        MetaPrimativeType::FLOAT => format!("{:?}", rdr.read_f32::<LittleEndian>()?),
+        MetaPrimativeType::DOUBLE => format!("{:?}", rdr.read_f64::<LittleEndian>()?),
+        MetaPrimativeType::IP => {
+            let mut octets = [0; 4];
+            rdr.read_exact(&mut octets)?;
+            format_ip(&octets)
+        }
+        MetaPrimativeType::WCHAR => String::from_utf16_lossy(&[rdr.read_u16::<LittleEndian>()?]),
+        MetaPrimativeType::STRING => {
+            let bytes = rdr.read_until_byte(b'\0', options.max_string_size)?;
+            options.string_encoding.decode(&bytes)?
+        }
+        MetaPrimativeType::WSTRING => rdr.read_null_terminated_utf16le_string(options.max_string_size)?,
+    };
+    // rdr.read_exact(&mut buf)?;
+    // meta_entry.default_value_string = format!("{buf:?}");
+    Ok(default_string)
+}
+
+/// Encodes a default value of the type at `idx_type` the way [`read_default_value`] reads it.
+pub(crate) fn encode_default_value(idx_type: i32, value: &str) -> Result<Vec<u8>> {
+    let type_info = primitive_type_info_of("The default value", "idx_type", idx_type)?;
+    let invalid = |err: &dyn std::fmt::Display| {
+        anyhow!("Invalid {} default `{value}`: {err}", type_info.xml_name)
+    };
+
+    // Array defaults, as `read_default_value` writes them.
+    if type_info.xml_name == "char" && value.parse::<i8>().is_err() {
+        let mut bytes = encode_string(value)?;
+        bytes.push(0);
+        return Ok(bytes);
+    }
+    // A datetime is two words, so its array elements are pairs of them.
+    if type_info.primative_type == MetaPrimativeType::DATETIME {
+        let words: Vec<&str> = value.split_whitespace().collect();
+        if words.len() != 2 && words.len().is_multiple_of(2) {
+            let mut bytes = Vec::new();
+            for pair in words.chunks(2) {
+                bytes.extend(encode_default_value(idx_type, &pair.join(" "))?);
+            }
+            return Ok(bytes);
+        }
+    }
+    let is_string = matches!(type_info.primative_type, MetaPrimativeType::STRING | MetaPrimativeType::WSTRING);
+    let is_one_value = is_string || type_info.primative_type == MetaPrimativeType::DATETIME;
+    if !is_one_value && value.split_whitespace().nth(1).is_some() {
+        let mut bytes = Vec::new();
+        for element in value.split_whitespace() {
+            bytes.extend(encode_default_value(idx_type, element)?);
+        }
+        return Ok(bytes);
+    }
+
+    let bytes = match type_info.primative_type {
+        MetaPrimativeType::CHAR => value.parse::<i8>().map_err(|e| invalid(&e))?.to_le_bytes().to_vec(),
+        MetaPrimativeType::UCHAR | MetaPrimativeType::BYTE => value.parse::<u8>().map_err(|e| invalid(&e))?.to_le_bytes().to_vec(),
+        MetaPrimativeType::SHORT => value.parse::<i16>().map_err(|e| invalid(&e))?.to_le_bytes().to_vec(),
+        MetaPrimativeType::USHORT => value.parse::<u16>().map_err(|e| invalid(&e))?.to_le_bytes().to_vec(),
+        MetaPrimativeType::INT | MetaPrimativeType::LONG | MetaPrimativeType::MONEY => value.parse::<i32>().map_err(|e| invalid(&e))?.to_le_bytes().to_vec(),
+        MetaPrimativeType::UINT | MetaPrimativeType::ULONG => value.parse::<u32>().map_err(|e| invalid(&e))?.to_le_bytes().to_vec(),
+        MetaPrimativeType::LONGLONG => value.parse::<i64>().map_err(|e| invalid(&e))?.to_le_bytes().to_vec(),
+        MetaPrimativeType::ULONGLONG => value.parse::<u64>().map_err(|e| invalid(&e))?.to_le_bytes().to_vec(),
+        MetaPrimativeType::FLOAT => value.parse::<f32>().map_err(|e| invalid(&e))?.to_le_bytes().to_vec(),
+        MetaPrimativeType::DOUBLE => value.parse::<f64>().map_err(|e| invalid(&e))?.to_le_bytes().to_vec(),
+        MetaPrimativeType::DATE | MetaPrimativeType::TIME | MetaPrimativeType::DATETIME => {
+            let mut bytes = vec![0; type_info.size as usize];
+            match type_info.primative_type {
+                MetaPrimativeType::DATE => parse_date::<LittleEndian>(value, &mut bytes),
+                MetaPrimativeType::TIME => parse_time::<LittleEndian>(value, &mut bytes),
+                _ => match value.split_once(' ') {
+                    Some((date, time)) => parse_date::<LittleEndian>(date, &mut bytes[..4])
+                        .and_then(|()| parse_time::<LittleEndian>(time, &mut bytes[4..])),
+                    None => Err(anyhow!("Expected a `YYYY-MM-DD HH:MM:SS` datetime")),
+                },
+            }
+            .map_err(|e| invalid(&e))?;
+            bytes
+        }
+        // Network order, as `format_ip` reads it.
+        MetaPrimativeType::IP => value.parse::<Ipv4Addr>().map_err(|e| invalid(&e))?.octets().to_vec(),
+        MetaPrimativeType::STRING => {
+            let mut bytes = encode_string(value)?;
+            bytes.push(0);
+            bytes
+        }
+        MetaPrimativeType::WCHAR => match value.encode_utf16().collect::<Vec<_>>()[..] {
+            [unit] => unit.to_le_bytes().to_vec(),
+            _ => return Err(invalid(&"expected one UTF-16 code unit")),
+        },
+        MetaPrimativeType::WSTRING => value
+            .encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_le_bytes)
+            .collect(),
+        _ => {
+            return Err(anyhow!(
+                "Default values of type {} aren't supported",
+                type_info.xml_name
+            ))
+        }
+    };
+    Ok(bytes)
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[allow(unused)]
+pub struct TDRMeta {
+    pub _offset: u64,
+    pub flags: TDRMetaFlags,
+    pub id: i32,
+    pub base_version: i32,
+    pub cur_version: i32,
+    pub type_: MetaPrimativeType,
+    /// `type_` as stored, which is all there is to go on when it's `UNKNOWN`.
+    pub raw_type: i32,
+    pub mem_size: i32,
+    pub n_unit_size: i32,
+    pub h_unit_size: i32,
+    pub custom_h_unit_size: i32,
+    pub idx_custom_h_unit_size: i32,
+    pub uncertain_max_sub_id: i32,
+    pub entries_num: i32,
+    pub unk_table_count: i32,
+    pub unk_table_ptr: i32,
+    pub unk_table_unk: i32,
+
+    /// The start address of this TDRMeta (file address)
+    pub ptr_meta: i32,
+
+    ///  Index of this TDRMeta within the `Metalib.ptr_meta` table.
+    pub idx: i32,
+
+    /// UNCLEAR: Often exceeds the max value in the `Metalib.ptr_id` lookup map
+    pub idx_id: i32,
+    pub idx_type: i32,
+
+    /// Index into the macros table of an entry containing the meta version.
+    pub idx_version: i32,
+
+    pub custom_align: i32,
     pub valid_align: i32,
     pub uncertain_version_indicator_min_ver: i32,
     pub size_type: TDRSizeInfo,
@@ -696,21 +1659,26 @@ pub struct TDRMeta {
 
     //entries: Array(this.iEntriesNum, TDRMetaEntry),
     pub entries: Vec<TDRMetaEntry>,
+
+    /// The `primary_key_member_num` rows at `ptr_primary_key_base`, one per key member.
+    pub primary_key: Vec<TDRDBKeyInfo>,
 }
 
-fn read_tdr_meta<T>(rdr: &mut T) -> Result<TDRMeta>
+fn read_tdr_meta<T>(rdr: &mut T, arch: MetalibArch, options: &ParseOptions) -> Result<TDRMeta>
 where
-    T: ReadBytesExt + std::io::Seek,
+    T: ReadBytesExt + std::io::Seek + StringBuffer,
 {
+    let _offset = rdr.stream_position()?;
     let mut meta = TDRMeta {
-        _offset: rdr.stream_position()?,
+        _offset,
         flags: TDRMetaFlags {
             bits: rdr.read_u32::<LittleEndian>()?,
         },
         id: rdr.read_i32::<LittleEndian>()?,
         base_version: rdr.read_i32::<LittleEndian>()?,
         cur_version: rdr.read_i32::<LittleEndian>()?,
-        type_: MetaPrimativeType::from_int(rdr.read_i32::<LittleEndian>()?)?,
+        type_: MetaPrimativeType::UNKNOWN,
+        raw_type: rdr.read_i32::<LittleEndian>()?,
         mem_size: rdr.read_i32::<LittleEndian>()?,
         n_unit_size: rdr.read_i32::<LittleEndian>()?,
         h_unit_size: rdr.read_i32::<LittleEndian>()?,
@@ -719,9 +1687,9 @@ where
         uncertain_max_sub_id: rdr.read_i32::<LittleEndian>()?,
         entries_num: rdr.read_i32::<LittleEndian>()?,
         unk_table_count: rdr.read_i32::<LittleEndian>()?,
-        unk_table_ptr: rdr.read_i32::<LittleEndian>()?,
+        unk_table_ptr: read_pointer(rdr, arch)?,
         unk_table_unk: rdr.read_i32::<LittleEndian>()?,
-        ptr_meta: rdr.read_i32::<LittleEndian>()?,
+        ptr_meta: read_pointer(rdr, arch)?,
         idx: rdr.read_i32::<LittleEndian>()?,
         idx_id: rdr.read_i32::<LittleEndian>()?,
         idx_type: rdr.read_i32::<LittleEndian>()?,
@@ -731,31 +1699,169 @@ where
         uncertain_version_indicator_min_ver: rdr.read_i32::<LittleEndian>()?,
         size_type: read_tdr_size_info(rdr)?,
         version_indicator: read_tdr_redirector(rdr)?,
-        sort_key: read_tdr_sort_key_info(rdr)?,
-        name: rdr.read_null_terminated_gbk_string_i32_offset_pointer()?,
-        desc: rdr.read_null_terminated_gbk_string_i32_offset_pointer()?,
-        chinese_name: rdr.read_null_terminated_gbk_string_i32_offset_pointer()?,
+        sort_key: read_tdr_sort_key_info(rdr, arch)?,
+        name: read_string_pointer(rdr, arch, "meta name", (_offset, StringField::Name), options)?,
+        desc: read_string_pointer(rdr, arch, "meta desc", (_offset, StringField::Desc), options)?,
+        chinese_name: read_string_pointer(
+            rdr,
+            arch,
+            "meta cname",
+            (_offset, StringField::ChineseName),
+            options,
+        )?,
         split_table_factor: rdr.read_i32::<LittleEndian>()?,
         split_table_rule_id: rdr.read_i16::<LittleEndian>()?,
         primary_key_member_num: rdr.read_i16::<LittleEndian>()?,
         idx_split_table_factor: rdr.read_i32::<LittleEndian>()?,
-        split_table_key: read_tdr_db_key_info(rdr)?,
-        ptr_primary_key_base: rdr.read_i32::<LittleEndian>()?,
-        ptr_dependon_struct: rdr.read_i32::<LittleEndian>()?,
+        split_table_key: read_tdr_db_key_info(rdr, arch)?,
+        ptr_primary_key_base: read_pointer(rdr, arch)?,
+        ptr_dependon_struct: read_pointer(rdr, arch)?,
         field_ac: rdr.read_i32::<LittleEndian>()?,
         field_b0: rdr.read_i32::<LittleEndian>()?,
         field_b4: rdr.read_i32::<LittleEndian>()?,
         entries: Vec::new(),
+        primary_key: Vec::new(),
     };
+    align_pointer(rdr, arch)?;
+    meta.type_ = primative_type(meta.raw_type, meta._offset + 0x10, options)?;
+    if !(0..=MAX_SPLIT_TABLE_RULE).contains(&meta.split_table_rule_id) {
+        let message = format!(
+            "Meta `{}` has split table rule {}, expected 0 to {MAX_SPLIT_TABLE_RULE}",
+            meta.name, meta.split_table_rule_id
+        );
+        if options.strict {
+            return Err(ParseError::at(ErrorKind::Other, meta._offset, message));
+        }
+        report_warning(
+            ParseWarning::new(WarningCategory::Value, format!("{message}, ignoring it"))
+                .at(meta._offset)
+                .meta(&meta.name),
+        );
+        meta.split_table_rule_id = 0;
+    }
 
+    let sizes = arch.record_sizes();
+    let len = sizes.meta as u64 + meta.entries_num.max(0) as u64 * sizes.meta_entry as u64;
+    check_extent(rdr, meta._offset, len, || {
+        format!("Meta `{}` ({} entries)", meta.name, meta.entries_num)
+    })?;
     for _i in 0..meta.entries_num {
-        meta.entries.push(read_tdr_meta_entry(rdr)?);
+        meta.entries.push(read_tdr_meta_entry(rdr, arch, &meta.name, options)?);
     }
+    for entry in meta.entries.iter_mut() {
+        // 1-3 are noinput, nooutput and noio.
+        entry.io = check_entry_enum(&meta.name, entry, "io", entry.io, 3, false, options)?;
+        // 1 and 2 are ascending and descending. An unknown order is kept for the XML to show.
+        let order = entry.order as i32;
+        check_entry_enum(&meta.name, entry, "order", order, 2, true, options)?;
+    }
+    meta.primary_key = read_primary_key(rdr, arch, &meta, options)?;
 
     Ok(meta)
 }
 
+/// Checks the `idx_type` of `entry` in `meta` is a row of [`TDR_PRIMATIVE_TYPE_INFO`] (or
+/// unset). In lenient mode a bad one is warned about and kept, and written as the raw index.
+fn check_entry_type_index(meta: &str, entry: &TDRMetaEntry, options: &ParseOptions) -> Result<()> {
+    if entry.idx_type == INVALID_METALIB_VALUE || primitive_type_info(entry.idx_type).is_some() {
+        return Ok(());
+    }
+    let what = format!("Entry `{meta}.{}`", entry.name);
+    let message = type_index_message(&what, "idx_type", entry.idx_type);
+    if options.strict {
+        return Err(ParseError::at(ErrorKind::UnknownType, entry._offset, message));
+    }
+    report_warning(
+        ParseWarning::new(
+            WarningCategory::Type,
+            format!("{message} at {:#X}, writing the raw index", entry._offset),
+        )
+        .at(entry._offset)
+        .meta(meta)
+        .entry(&entry.name),
+    );
+    Ok(())
+}
+
+/// Returns `value`, the `field` of `entry` in `meta`, after checking it's one TDR defines (0
+/// to `max`). In lenient mode an unknown value is warned about, and kept if `keep` is set
+/// or cleared otherwise.
+fn check_entry_enum(
+    meta: &str,
+    entry: &TDRMetaEntry,
+    field: &str,
+    value: i32,
+    max: i32,
+    keep: bool,
+    options: &ParseOptions,
+) -> Result<i32> {
+    if (0..=max).contains(&value) {
+        return Ok(value);
+    }
+    let message = format!(
+        "Entry `{meta}.{}` has unknown {field} value {value}",
+        entry.name
+    );
+    if options.strict {
+        return Err(ParseError::at(ErrorKind::Other, entry._offset, message));
+    }
+    let message = match keep {
+        true => format!("{message} at {:#X}", entry._offset),
+        false => format!("{message} at {:#X}, ignoring it", entry._offset),
+    };
+    report_warning(
+        ParseWarning::new(WarningCategory::Value, message)
+            .at(entry._offset)
+            .meta(meta)
+            .entry(&entry.name),
+    );
+    Ok(if keep { value } else { 0 })
+}
+
+/// Reads the primary key table of `meta`, leaving the reader where it was. In lenient mode
+/// a table outside the body is warned about and left empty.
+fn read_primary_key<T>(
+    rdr: &mut T,
+    arch: MetalibArch,
+    meta: &TDRMeta,
+    options: &ParseOptions,
+) -> Result<Vec<TDRDBKeyInfo>>
+where
+    T: ReadBytesExt + std::io::Seek,
+{
+    let mut primary_key = Vec::new();
+    let count = meta.primary_key_member_num;
+    if count <= 0 || meta.ptr_primary_key_base == INVALID_METALIB_VALUE {
+        return Ok(primary_key);
+    }
+
+    let what = || format!("Primary key of meta `{}` ({count} members)", meta.name);
+    let start = meta.ptr_primary_key_base as u32 as u64;
+    let len = count as u64 * arch.record_sizes().db_key_info as u64;
+    if let Err(err) = check_extent(rdr, start, len, what) {
+        if options.strict {
+            return Err(err);
+        }
+        report_warning(
+            ParseWarning::new(WarningCategory::Pointer, format!("{err}, skipping it"))
+                .at(meta._offset)
+                .meta(&meta.name),
+        );
+        return Ok(primary_key);
+    }
+
+    let pos = rdr.stream_position()?;
+    _ = rdr.seek(SeekFrom::Start(start))?;
+    for _i in 0..count {
+        primary_key.push(read_tdr_db_key_info(rdr, arch)?);
+        align_pointer(rdr, arch)?;
+    }
+    _ = rdr.seek(SeekFrom::Start(pos))?;
+    Ok(primary_key)
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(unused)]
 pub struct TDRMacroGroup {
     pub _offset: u64,
@@ -771,52 +1877,110 @@ pub struct TDRMacroGroup {
     pub value_idx_map: Vec<i32>,
 }
 
-fn read_tdr_macros_group<T>(rdr: &mut T) -> Result<TDRMacroGroup>
+fn read_tdr_macros_group<T>(
+    rdr: &mut T,
+    arch: MetalibArch,
+    options: &ParseOptions,
+) -> Result<TDRMacroGroup>
 where
-    T: ReadBytesExt + std::io::Seek,
+    T: ReadBytesExt + std::io::Seek + StringBuffer,
 {
     let offset = rdr.stream_position()?;
     let mut macros_group = TDRMacroGroup {
         _offset: offset,
         cur_macro_count: rdr.read_i32::<LittleEndian>()?,
         max_macro_count: rdr.read_i32::<LittleEndian>()?,
-        desc: rdr.read_null_terminated_gbk_string_i32_offset_pointer()?,
-        _ptr_name_idx_map: rdr.read_i32::<LittleEndian>()?,
-        _ptr_value_idx_map: rdr.read_i32::<LittleEndian>()?,
+        desc: read_string_pointer(
+            rdr,
+            arch,
+            "macrogroup desc",
+            (offset, StringField::Desc),
+            options,
+        )?,
+        _ptr_name_idx_map: read_pointer(rdr, arch)?,
+        _ptr_value_idx_map: read_pointer(rdr, arch)?,
         name: rdr.read_fixed_size_utf8_string(128)?,
         name_idx_map: Vec::new(),
         value_idx_map: Vec::new(),
     };
 
-    // let original_position = rdr.stream_position()?;
-    assert_eq!(
-        macros_group._ptr_name_idx_map as u64,
-        rdr.stream_position()? - offset
-    );
-    //_ = rdr.seek(SeekFrom::Start(offset + macros_group._ptr_name_idx_map as u64))?;
-    for _i in 0..macros_group.cur_macro_count {
-        macros_group
-            .name_idx_map
-            .push(rdr.read_i32::<LittleEndian>()?);
+    // Each map has room for `max_macro_count` macros, so the next group starts after the
+    // slack rather than after the last macro in use.
+    let what = || format!("Macrogroup `{}` index map", macros_group.name);
+    let count = macros_group.cur_macro_count.max(0);
+    let capacity = count.max(macros_group.max_macro_count) as u64;
+    let record_size = arch.record_sizes().macro_group as u64;
+    let mut end = offset + record_size;
+    let mut starts = [None, None];
+    for (start, ptr) in starts
+        .iter_mut()
+        .zip([macros_group._ptr_name_idx_map, macros_group._ptr_value_idx_map])
+    {
+        let problem = if ptr < 0 {
+            Some(format!("{} pointer {ptr} is negative", what()))
+        } else if (ptr as u64) < record_size {
+            Some(format!("{} at {ptr:#X} overlaps the macrogroup", what()))
+        } else {
+            None
+        };
+        if let Some(message) = problem {
+            if options.strict {
+                return Err(ParseError::at(ErrorKind::Other, offset, message));
+            }
+            // Left empty, so the group has no members.
+            let message = format!("{message}, skipping the map");
+            report_warning(ParseWarning::new(WarningCategory::Pointer, message).at(offset));
+            continue;
+        }
+        let map_start = offset.checked_add(ptr as u64).ok_or_else(|| {
+            ParseError::at(ErrorKind::Other, offset, format!("{} overflows", what()))
+        })?;
+        check_extent(rdr, map_start, 4 * capacity, what)?;
+        end = end.max(map_start + 4 * capacity);
+        *start = Some(map_start);
     }
 
-    assert_eq!(
-        macros_group._ptr_value_idx_map as u64,
-        rdr.stream_position()? - offset
-    );
-    // _ = rdr.seek(SeekFrom::Start(offset + macros_group._ptr_value_idx_map as u64))?;
-    for _i in 0..macros_group.cur_macro_count {
-        macros_group
-            .value_idx_map
-            .push(rdr.read_i32::<LittleEndian>()?);
+    let maps = [&mut macros_group.name_idx_map, &mut macros_group.value_idx_map];
+    for (start, map) in starts.into_iter().zip(maps) {
+        let Some(start) = start else {
+            continue;
+        };
+        _ = rdr.seek(SeekFrom::Start(start))?;
+        for _i in 0..count {
+            map.push(rdr.read_i32::<LittleEndian>()?);
+        }
     }
 
-    // _ = rdr.seek(SeekFrom::Start(original_position))?;
-
+    _ = rdr.seek(SeekFrom::Start(end))?;
+    align_pointer(rdr, arch)?;
     Ok(macros_group)
 }
 
+/// A string field of a macro, name table entry, meta, entry or macrogroup.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StringField {
+    Name,
+    Desc,
+    ChineseName,
+    /// A string, wide string or `char` array default.
+    Default,
+    CustomAttr,
+}
+
+/// Original bytes of a string that didn't decode to text that encodes back to them.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RawString {
+    /// Body offset (`_offset`) of the record the string belongs to.
+    pub record: u64,
+    pub field: StringField,
+    /// The bytes as stored: a buffer string's without its terminator, all of a default's.
+    pub bytes: Vec<u8>,
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(unused)]
 pub struct Metalib {
     pub _offset: u64,
@@ -829,9 +1993,79 @@ pub struct Metalib {
     pub metas: Vec<TDRMeta>,
     // pub macrogroup_map: Vec<TDRMapEntry>,
     pub macrogroups: Vec<TDRMacroGroup>,
+
+    /// Layout the body was parsed with.
+    pub arch: MetalibArch,
+
+    /// How the header's `platform_arch` claim compared against the body tables.
+    pub arch_probe: ArchProbe,
+
+    /// Original bytes of the strings that didn't decode losslessly from GBK, which the
+    /// writer and GBK XML use instead of encoding the text. Only filled in with
+    /// [`ParseOptions::keep_raw_strings`]. Call [`Metalib::reindex`] after changing it.
+    pub raw_strings: Vec<RawString>,
+
+    /// Offset lookups, built on first use.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) index: OnceLock<MetalibIndex>,
+}
+
+/// Lookups from offsets to positions in a [`Metalib`]'s tables.
+#[derive(Debug, Default)]
+pub(crate) struct MetalibIndex {
+    /// Meta offset -> index in `metas`.
+    metas: HashMap<u64, usize>,
+    /// Macrogroup offset -> index in `macrogroups`.
+    macrogroups: HashMap<u64, usize>,
+    /// Offsets of the macros that belong to a macrogroup.
+    grouped_macros: HashSet<u64>,
+    /// Record offset and field -> index in `raw_strings`.
+    raw_strings: HashMap<(u64, StringField), usize>,
+}
+
+impl MetalibIndex {
+    fn build(metalib: &Metalib) -> MetalibIndex {
+        let mut index = MetalibIndex::default();
+        // The first of any duplicate offsets wins, as with a linear search.
+        for (idx, meta) in metalib.metas.iter().enumerate() {
+            index.metas.entry(meta._offset).or_insert(idx);
+        }
+        for (idx, group) in metalib.macrogroups.iter().enumerate() {
+            index.macrogroups.entry(group._offset).or_insert(idx);
+        }
+        index.grouped_macros = metalib
+            .macrogroups
+            .iter()
+            .flat_map(|group| group.value_idx_map.iter())
+            .filter_map(|&idx| metalib.macros.get(idx as usize))
+            .map(|tdr_macro| tdr_macro._offset)
+            .collect();
+        for (idx, raw) in metalib.raw_strings.iter().enumerate() {
+            index.raw_strings.entry((raw.record, raw.field)).or_insert(idx);
+        }
+        index
+    }
 }
 
 impl Metalib {
+    /// Parses a metalib that starts at the beginning of `data`.
+    ///
+    /// The body is parsed in place rather than copied out first.
+    pub fn from_bytes(data: &[u8]) -> Result<Metalib> {
+        let options = ParseOptions::default();
+        let header = read_checked_header(&mut Cursor::new(data), 0, &options)?;
+        let body_size = header.body_size() as usize;
+        let body = data.get(header.header_size() as usize..).unwrap_or_default();
+        check_body_size(0, &header, body.len() as u64)?;
+        parse_metalib_body(0, header, &body[..body_size], &options)
+    }
+
+    /// Seeks `rdr` to `offset` and parses the metalib there.
+    pub fn from_reader_at<R: Read + Seek>(rdr: &mut R, offset: u64) -> Result<Metalib> {
+        rdr.seek(SeekFrom::Start(offset))?;
+        read_metalib(rdr)
+    }
+
     /// Returns the first TDRMeta found with the given ID
     #[allow(unused)]
     pub fn get_meta_by_id(&self, id: i32) -> Result<&TDRMeta> {
@@ -848,6 +2082,71 @@ impl Metalib {
         Err(anyhow!("Failed to get meta by id"))
     }
 
+    /// Get a meta by its exact name, through the name table.
+    ///
+    /// Fails if no meta has the name, or if several do (which only happens in malformed
+    /// metalibs). One of those can still be picked by adding its offset, as in
+    /// `Packet@0x5C4`.
+    pub fn get_meta_by_name(&self, name: &str) -> Result<&TDRMeta> {
+        self.find_meta_by_name(name, |candidate| candidate == name)
+    }
+
+    /// Like [`Metalib::get_meta_by_name`], but ignoring case.
+    pub fn get_meta_by_name_ignore_case(&self, name: &str) -> Result<&TDRMeta> {
+        let lower = name.to_lowercase();
+        self.find_meta_by_name(name, |candidate| candidate.to_lowercase() == lower)
+    }
+
+    fn find_meta_by_name(&self, name: &str, matches: impl Fn(&str) -> bool) -> Result<&TDRMeta> {
+        if let Some((base, offset)) = name.rsplit_once('@') {
+            let offset = match offset.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None => offset.parse().ok(),
+            };
+            if let Some(offset) = offset {
+                return self
+                    .metas
+                    .iter()
+                    .find(|meta| meta._offset == offset && meta.name == base)
+                    .with_context(|| format!("No meta `{base}` at {offset:#X}"));
+            }
+        }
+
+        // The name table can miss a meta whose name was patched, so check the metas too.
+        let mut found: Vec<&TDRMeta> =
+            self.metas.iter().filter(|meta| matches(&meta.name)).collect();
+        for entry in self.names.iter().filter(|entry| matches(&entry.name)) {
+            let meta = self
+                .meta_map
+                .get(entry.idx as usize)
+                .and_then(|map_entry| self.get_meta_by_offset(map_entry.ptr).ok())
+                .with_context(|| {
+                    format!(
+                        "Name table entry for `{}` at {:#X} points at missing meta {}",
+                        entry.name, entry._offset, entry.idx
+                    )
+                })?;
+            if !found.iter().any(|other| other._offset == meta._offset) {
+                found.push(meta);
+            }
+        }
+
+        match found.as_slice() {
+            [] => Err(anyhow!("Failed to get meta by name `{name}`")),
+            [meta] => Ok(meta),
+            metas => Err(anyhow!(
+                "Meta name `{name}` is ambiguous, it matches {} (pick one with `{}@{:#X}`)",
+                metas
+                    .iter()
+                    .map(|meta| format!("`{}` at {:#X}", meta.name, meta._offset))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                metas[0].name,
+                metas[0]._offset
+            )),
+        }
+    }
+
     /// Get a meta by the given (file) offset.
     #[allow(unused)]
     pub fn get_meta_by_offset(&self, offset: i32) -> Result<&TDRMeta> {
@@ -855,13 +2154,11 @@ impl Metalib {
             return Err(anyhow!("Invalid meta offset (-1)"));
         }
 
-        for entry in self.metas.iter() {
-            if entry._offset == offset as u64 {
-                return Ok(entry);
-            }
-        }
-
-        Err(anyhow!("Failed to get meta by offset"))
+        self.index()
+            .metas
+            .get(&(offset as u64))
+            .map(|&idx| &self.metas[idx])
+            .ok_or_else(|| anyhow!("Failed to get meta by offset"))
     }
 
     /// Get a macrogroup by the given (file) offset.
@@ -871,82 +2168,593 @@ impl Metalib {
             return Err(anyhow!("Invalid meta offset (-1)"));
         }
 
-        for entry in self.macrogroups.iter() {
-            if entry._offset == offset as u64 {
-                return Ok(entry);
-            }
-        }
+        self.index()
+            .macrogroups
+            .get(&(offset as u64))
+            .map(|&idx| &self.macrogroups[idx])
+            .ok_or_else(|| anyhow!("Failed to get macrogroup by offset"))
+    }
 
-        Err(anyhow!("Failed to get macrogroup by offset"))
+    /// The macros in `group`, in the order of its value map. Unused slots (`-1`) and
+    /// indexes outside the macro table are skipped.
+    pub fn macrogroup_macros<'a>(
+        &'a self,
+        group: &'a TDRMacroGroup,
+    ) -> impl Iterator<Item = &'a TDRMacro> + 'a {
+        group
+            .value_idx_map
+            .iter()
+            .filter_map(|&idx| usize::try_from(idx).ok().and_then(|idx| self.macros.get(idx)))
     }
 
     /// Returns true if the provided macro is in ANY macrogroup.
     pub fn is_macro_in_group(&self, tdr_macro: &TDRMacro) -> Result<bool> {
-        // Doesn't need to be fast, but I probably should have done better than this:
-        for group in self.macrogroups.iter() {
-            for &tdr_macro_idx in group.value_idx_map.iter() {
-                let cur_tdr_macro = self.macros.get(tdr_macro_idx as usize).context("Failed toget macro by idx")?;
-                if cur_tdr_macro._offset == tdr_macro._offset {
-                    return Ok(true);
-                }
+        Ok(self.index().grouped_macros.contains(&tdr_macro._offset))
+    }
+
+    /// Type of an entry as written in the XML `type` attribute: the meta name or primitive
+    /// name (the raw number for an unknown type), prefixed with `*` for pointers and `@` for
+    /// references.
+    pub fn entry_type_name(&self, entry: &TDRMetaEntry) -> Result<String> {
+        let type_name = if entry.ptr_meta != INVALID_METALIB_VALUE {
+            self.get_meta_by_offset(entry.ptr_meta)?.name.clone()
+        } else if entry.type_ == MetaPrimativeType::UNKNOWN {
+            entry.raw_type.to_string()
+        } else if entry.idx_type != INVALID_METALIB_VALUE {
+            // Out of range only after lenient parsing, which already warned about it.
+            primitive_type_info(entry.idx_type)
+                .map_or_else(|| entry.idx_type.to_string(), |info| info.xml_name.to_string())
+        } else {
+            String::new()
+        };
+        let type_prefix = if entry.flag.contains(TDRMetaEntryFlags::POINT_TYPE) {
+            "*"
+        } else if entry.flag.contains(TDRMetaEntryFlags::REFER_TYPE) {
+            "@"
+        } else {
+            ""
+        };
+        Ok(format!("{type_prefix}{type_name}"))
+    }
+
+    /// The original bytes kept for `field` of the record at body offset `record`, if any.
+    pub fn raw_string(&self, record: u64, field: StringField) -> Option<&RawString> {
+        self.raw_string_index(record, field).map(|idx| &self.raw_strings[idx])
+    }
+
+    /// Index in `raw_strings` of the bytes kept for `field` of the record at `record`.
+    pub(crate) fn raw_string_index(&self, record: u64, field: StringField) -> Option<usize> {
+        self.index().raw_strings.get(&(record, field)).copied()
+    }
+
+    fn index(&self) -> &MetalibIndex {
+        self.index.get_or_init(|| MetalibIndex::build(self))
+    }
+
+    /// Drops the offset lookups so they're rebuilt on next use. Needed after changing
+    /// `metas`, `macrogroups` or `macros`.
+    pub fn reindex(&mut self) {
+        self.index = OnceLock::new();
+    }
+}
+
+/// Returns each name shared by more than one of `metas`, with the metas that have it, in
+/// table order. Only a hand-patched or corrupt metalib has any.
+pub(crate) fn duplicate_meta_names(metas: &[TDRMeta]) -> Vec<(&str, Vec<&TDRMeta>)> {
+    let mut by_name: Vec<(&str, Vec<&TDRMeta>)> = Vec::new();
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for meta in metas.iter() {
+        match index.get(meta.name.as_str()) {
+            Some(&idx) => by_name[idx].1.push(meta),
+            None => {
+                index.insert(&meta.name, by_name.len());
+                by_name.push((&meta.name, vec![meta]));
             }
         }
+    }
+    by_name.retain(|(_, same)| same.len() > 1);
+    by_name
+}
+
+/// Lists the offsets of `metas` like `0x5C4, 0x6F0`.
+pub(crate) fn format_meta_offsets(metas: &[&TDRMeta]) -> String {
+    metas
+        .iter()
+        .map(|meta| format!("{:#X}", meta._offset))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Resets macro indexes that point past the macro table, so exporters fall back to the
+/// literal value instead of failing.
+fn clear_bad_macro_indexes(metas: &mut [TDRMeta], macro_num: usize) {
+    let check = |idx: &mut i32, what: &str, offset: u64, meta: &str, entry: Option<&str>| {
+        if *idx != INVALID_METALIB_VALUE && (*idx < 0 || *idx as usize >= macro_num) {
+            let owner = match entry {
+                Some(entry) => format!("{meta}.{entry}"),
+                None => meta.to_string(),
+            };
+            let message =
+                format!("`{owner}`: {what} macro index {idx} is out of range, using the literal value");
+            let warning = ParseWarning::new(WarningCategory::MacroIndex, message)
+                .at(offset)
+                .meta(meta);
+            report_warning(match entry {
+                Some(entry) => warning.entry(entry),
+                None => warning,
+            });
+            *idx = INVALID_METALIB_VALUE;
+        }
+    };
+    for meta in metas.iter_mut() {
+        let offset = meta._offset;
+        check(&mut meta.idx_version, "version", offset, &meta.name, None);
+        check(&mut meta.idx_id, "id", offset, &meta.name, None);
+        check(&mut meta.idx_custom_h_unit_size, "size", offset, &meta.name, None);
+        for entry in meta.entries.iter_mut() {
+            let (offset, name) = (entry._offset, Some(entry.name.as_str()));
+            check(&mut entry.idx_id, "id", offset, &meta.name, name);
+            check(&mut entry.idx_version, "version", offset, &meta.name, name);
+            check(&mut entry.idx_count, "count", offset, &meta.name, name);
+            check(&mut entry.idx_custom_h_unit_size, "size", offset, &meta.name, name);
+        }
+    }
+}
 
-        Ok(false)
+/// Checks the macro indexes in each macrogroup's maps. `-1` marks an unused slot and is
+/// left alone. Any other index outside the macro table is an error, or in lenient mode is
+/// logged and turned into `-1`.
+fn check_macrogroup_indexes(
+    macrogroups: &mut [TDRMacroGroup],
+    macro_num: usize,
+    options: &ParseOptions,
+) -> Result<()> {
+    for group in macrogroups.iter_mut() {
+        let maps = [
+            ("name", group._ptr_name_idx_map, &mut group.name_idx_map),
+            ("value", group._ptr_value_idx_map, &mut group.value_idx_map),
+        ];
+        for (map, ptr, idx_map) in maps {
+            for (pos, idx) in idx_map.iter_mut().enumerate() {
+                if *idx == INVALID_METALIB_VALUE || (*idx >= 0 && (*idx as usize) < macro_num) {
+                    continue;
+                }
+                let message = format!(
+                    "Macrogroup `{}` {map} index {pos} is {idx}, outside the macro table ({macro_num} macros)",
+                    group.name
+                );
+                let offset = group._offset.saturating_add_signed(ptr as i64 + 4 * pos as i64);
+                if options.strict {
+                    return Err(ParseError::at(ErrorKind::Other, offset, message));
+                }
+                let message = format!("{message}, skipping it");
+                report_warning(ParseWarning::new(WarningCategory::MacroIndex, message).at(offset));
+                *idx = INVALID_METALIB_VALUE;
+            }
+        }
     }
+    Ok(())
 }
 
+/// Reads a metalib from the current position of `rdr` using the default [`ParseOptions`].
+#[allow(unused)]
 pub fn read_metalib<T>(rdr: &mut T) -> Result<Metalib>
+where
+    T: Read + ReadBytesExt + std::io::Seek,
+{
+    read_metalib_with(rdr, &ParseOptions::default())
+}
+
+/// Reads a metalib from the current position of `rdr`.
+pub fn read_metalib_with<T>(rdr: &mut T, options: &ParseOptions) -> Result<Metalib>
+where
+    T: Read + ReadBytesExt + std::io::Seek,
+{
+    Ok(read_metalib_with_report(rdr, options)?.0)
+}
+
+/// Like [`read_metalib_with`], also returning the warnings lenient parsing logged, at file
+/// offsets. With `fail_on_warnings` set, any warning fails the parse with the report as the
+/// error.
+///
+/// The body is read into memory first, since its strings are found by seeking around it.
+/// [`Metalib::from_bytes`] parses data that's already in memory without the copy.
+pub fn read_metalib_with_report<T>(
+    rdr: &mut T,
+    options: &ParseOptions,
+) -> Result<(Metalib, ParseReport)>
 where
     T: Read + ReadBytesExt + std::io::Seek,
 {
     let _offset = rdr.stream_position()?;
-    let header = read_metalib_header(rdr)?;
+    let (metalib, mut report) = collect_warnings(|| {
+        let header = read_checked_header(rdr, _offset, options)?;
+
+        let mut metadata_body: Vec<u8> = Vec::new();
+        rdr.take(header.body_size() as u64).read_to_end(&mut metadata_body)?;
+        check_body_size(_offset, &header, metadata_body.len() as u64)?;
+
+        parse_metalib_body(_offset, header, &metadata_body, options)
+    });
+    let metalib = metalib?;
+    report.rebase(_offset + metalib.header.header_size() as u64);
+    if options.fail_on_warnings && !report.is_empty() {
+        return Err(anyhow::Error::new(report));
+    }
+    Ok((metalib, report))
+}
+
+/// Reads the header at `_offset` and checks it's one we can parse.
+fn read_checked_header<T>(rdr: &mut T, _offset: u64, options: &ParseOptions) -> Result<MetalibHeader>
+where
+    T: Read + std::io::Seek,
+{
+    let available = remaining_len(rdr);
+    let header_size = peek_header_size(rdr);
+    let header = read_metalib_header(rdr).map_err(|err| match available {
+        Some(available) if is_truncation(&err) => ParseError::at(
+            ErrorKind::Truncated,
+            _offset + available,
+            format!(
+                "Metalib header at {_offset:#X} is {header_size:#X} bytes but the data ends \
+                 after {available:#X}; {TRUNCATED_HINT}"
+            ),
+        ),
+        _ => locate(err, _offset),
+    })?;
+    // Checked before the size, so that data that isn't a metalib at all is reported as a bad
+    // magic rather than as truncated.
+    if let Err(err) = validate_header(&header, _offset, options.force_arch.is_none()) {
+        // Without a layout for the build, nothing after it can be read, even leniently.
+        if options.strict || HeaderLayout::for_build(header.build).is_none() {
+            return Err(err);
+        }
+        let mut warning = ParseWarning::new(WarningCategory::Header, format!("{err:#}"));
+        if let Some(parse_error) = find_parse_error(&err) {
+            warning = warning.at_file(parse_error.offset);
+        }
+        report_warning(warning);
+    }
+    // The body size is worked out from this, so even lenient parsing can't go on.
+    if header.size < header.header_size() {
+        return Err(header_size_error(&header, _offset));
+    }
+    if header.size as u64 > options.max_metalib_size {
+        return Err(ParseError::header_field(
+            ErrorKind::BadHeader,
+            _offset + 8,
+            "size",
+            format!("at most {:#X} (see --max-metalib-size)", options.max_metalib_size),
+            format!("{:#X}", header.size),
+        ));
+    }
+    // Catch a size past the end of the data before reading any of the body.
+    if let Some(available) = remaining_len(rdr) {
+        check_body_size(_offset, &header, available)?;
+    }
+
+    debug!(
+        "Read header of `{}` at {:#X}: size {:#X}, platform_arch {:#X}",
+        header.name, _offset, header.size, header.platform_arch
+    );
+    Ok(header)
+}
+
+/// Size of the header `rdr` is at, from the layout of its build, without moving it. The
+/// current layout's if the build can't be read.
+fn peek_header_size<T: Read + std::io::Seek>(rdr: &mut T) -> u32 {
+    let mut magic_and_build = [0; 4];
+    let build = rdr.stream_position().ok().and_then(|pos| {
+        let read = rdr.read_exact(&mut magic_and_build);
+        rdr.seek(SeekFrom::Start(pos)).ok()?;
+        read.ok()?;
+        Some(u16::from_le_bytes([magic_and_build[2], magic_and_build[3]]))
+    });
+    build.map_or(METALIB_HEADER_SIZE, |build| HeaderLayout::for_build_or_current(build).size)
+}
+
+/// How many bytes `rdr` has left, if it can tell.
+fn remaining_len<T: std::io::Seek>(rdr: &mut T) -> Option<u64> {
+    let pos = rdr.stream_position().ok()?;
+    let end = rdr.seek(SeekFrom::End(0)).ok()?;
+    rdr.seek(SeekFrom::Start(pos)).ok()?;
+    Some(end.saturating_sub(pos))
+}
+
+fn header_size_error(header: &MetalibHeader, offset: u64) -> anyhow::Error {
+    ParseError::header_field(
+        ErrorKind::BadHeader,
+        offset + 8,
+        "size",
+        format!("at least {:#X}", header.header_size()),
+        format!("{:#X}", header.size),
+    )
+}
+
+/// Checks the header of the metalib at `offset` before its body is read: the magic, the
+/// build, a known `platform_arch` (if `check_arch`), a size that covers the header, and table
+/// counts within their capacity.
+///
+/// The error is a [`ParseError`] at the offending field, naming it with the expected and
+/// found values. A wrong magic is [`ErrorKind::BadMagic`], anything else
+/// [`ErrorKind::BadHeader`].
+pub fn validate_header(header: &MetalibHeader, offset: u64, check_arch: bool) -> Result<()> {
+    if header.magic != METALIB_MAGIC {
+        return Err(ParseError::header_field(
+            ErrorKind::BadMagic,
+            offset,
+            "magic",
+            format!("{METALIB_MAGIC:#06X}"),
+            format!("{:#06X}", header.magic),
+        ));
+    }
+    if HeaderLayout::for_build(header.build).is_none() {
+        let known: Vec<String> = HEADER_LAYOUTS
+            .iter()
+            .map(|layout| format!("{} ({:#X})", layout.build, layout.build))
+            .collect();
+        return Err(ParseError::at(
+            ErrorKind::BadHeader,
+            offset + 2,
+            format!(
+                "Unsupported metalib build {} ({:#X}), only the headers of builds {} are known",
+                header.build,
+                header.build,
+                known.join(" and ")
+            ),
+        ));
+    }
+    if check_arch && MetalibArch::from_platform_arch(header.platform_arch).is_none() {
+        return Err(ParseError::header_field(
+            ErrorKind::BadHeader,
+            offset + 4,
+            "platform_arch",
+            format!("{PLATFORM_ARCH_32:#X} or {PLATFORM_ARCH_64:#X}"),
+            format!("{:#X}", header.platform_arch),
+        ));
+    }
+    if header.size < header.header_size() {
+        return Err(header_size_error(header, offset));
+    }
+    for (field_offset, table, cur, max) in [
+        (0x2C, "meta", header.cur_meta_num, header.max_meta_num),
+        (0x34, "macro", header.cur_macro_num, header.max_macro_num),
+        (
+            0x3C,
+            "macrogroup",
+            header.cur_macros_group_num,
+            header.max_macros_group_num,
+        ),
+    ] {
+        if cur < 0 || cur > max {
+            return Err(ParseError::header_field(
+                ErrorKind::BadHeader,
+                offset + field_offset,
+                &format!("{table} count"),
+                format!("between 0 and its capacity {max}"),
+                cur,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// What to suspect when the data ends early.
+const TRUNCATED_HINT: &str = "the file may be truncated or the offset wrong";
+
+/// Fails with a truncation error if fewer body bytes than `header` claims were available for
+/// the metalib at `_offset`.
+fn check_body_size(_offset: u64, header: &MetalibHeader, available: u64) -> Result<()> {
+    let body_size = header.body_size() as u64;
+    if available < body_size {
+        return Err(ParseError::at(
+            ErrorKind::Truncated,
+            _offset + header.header_size() as u64 + available,
+            format!(
+                "Metalib at {_offset:#X} has a body of {body_size} bytes but the data ends \
+                 after {available}, {} bytes are missing; {TRUNCATED_HINT}",
+                body_size - available
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Tags an error from reading record `index` of `table` at body offset `pos`, for a body at
+/// file offset `base`. Running out of data there is a truncation error naming the record.
+fn table_error(
+    err: anyhow::Error,
+    base: u64,
+    table: &str,
+    index: i32,
+    pos: u64,
+    body_len: usize,
+) -> anyhow::Error {
+    if find_parse_error(&err).is_none() && is_truncation(&err) {
+        return ParseError::at(
+            ErrorKind::Truncated,
+            base + pos,
+            format!(
+                "{table} {index} at {pos:#X} runs past the end of the metalib body \
+                 ({body_len:#X} bytes); {TRUNCATED_HINT}"
+            ),
+        );
+    }
+    locate(rebase(err, base), base + pos)
+}
+
+/// Fails if the `count` records of `record_size` bytes at `ptr` don't fit in a body of
+/// `body_len` bytes. The error points at the header field at `field_offset`.
+fn check_table(
+    _offset: u64,
+    field_offset: u64,
+    table: &str,
+    ptr: u32,
+    count: i32,
+    record_size: u32,
+    body_len: usize,
+) -> Result<()> {
+    if count <= 0 {
+        return Ok(());
+    }
+    if ptr as u64 + count as u64 * record_size as u64 > body_len as u64 {
+        return Err(ParseError::at(
+            ErrorKind::BadHeader,
+            _offset + field_offset,
+            format!(
+                "{table} table (offset {ptr:#X}, {count} entries) exceeds metalib body of {body_len:#X} bytes"
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Parses the tables in `metadata_body`, the bytes following the header at `_offset`.
+fn parse_metalib_body(
+    _offset: u64,
+    header: MetalibHeader,
+    metadata_body: &[u8],
+    options: &ParseOptions,
+) -> Result<Metalib> {
+    // Errors from the tables are tagged with the record and its position in the file.
+    let base = _offset + header.header_size() as u64;
+    let at = |err: anyhow::Error, table: &str, index: i32, pos: u64| {
+        table_error(err, base, table, index, pos, metadata_body.len())
+    };
+
+    let arch_probe = probe_arch(&header, metadata_body);
+    let arch = arch_probe.resolve(options.force_arch);
+    let mut rdr = BodyReader::new(&header, metadata_body);
+
+    // The remaining meta tables are only needed for structs/unions.
+    let meta_num = if options.macros_only {
+        0
+    } else {
+        header.cur_meta_num
+    };
 
-    let mut metadata_body: Vec<u8> = vec![0; (header.size - METALIB_HEADER_SIZE).try_into()?];
-    rdr.read_exact(&mut metadata_body)?;
-    let mut rdr = Cursor::new(metadata_body);
+    let sizes = arch.record_sizes();
+    for (field_offset, table, ptr, count, record_size) in [
+        (0x4C, "Macro", header.ptr_macro, header.cur_macro_num, sizes.macro_),
+        (0x50, "Id", header.ptr_id, meta_num, sizes.id_entry),
+        (0x54, "Name", header.ptr_name, meta_num, sizes.table_entry),
+        (0x58, "Meta map", header.ptr_map, meta_num, sizes.table_entry),
+        (0x5C, "Meta", header.ptr_meta, meta_num, sizes.meta),
+        (
+            0x74,
+            "Macrogroup",
+            header.ptr_macros_group,
+            header.cur_macros_group_num,
+            sizes.macro_group,
+        ),
+    ] {
+        check_table(
+            _offset,
+            field_offset,
+            table,
+            ptr,
+            count,
+            record_size,
+            metadata_body.len(),
+        )?;
+    }
 
     // Macro Table
+    debug!(
+        "Reading {} macros at {:#X}",
+        header.cur_macro_num, header.ptr_macro
+    );
     _ = rdr.seek(SeekFrom::Start(header.ptr_macro as u64));
     let mut macros: Vec<TDRMacro> = Vec::new();
-    for _ in 0..header.cur_macro_num {
-        let entry = read_tdr_macro(&mut rdr)?;
+    for index in 0..header.cur_macro_num {
+        let pos = rdr.stream_position()?;
+        let entry =
+            read_tdr_macro(&mut rdr, arch, options).map_err(|err| at(err, "Macro", index, pos))?;
         macros.push(entry);
     }
 
     // ID Table
+    debug!("Reading {meta_num} ids at {:#X}", header.ptr_id);
     _ = rdr.seek(SeekFrom::Start(header.ptr_id as u64));
     let mut ids: Vec<TDRIdEntry> = Vec::new();
-    for _ in 0..header.cur_meta_num {
-        let entry = read_tdr_id_entry(&mut rdr)?;
+    for index in 0..meta_num {
+        let pos = rdr.stream_position()?;
+        let entry = read_tdr_id_entry(&mut rdr).map_err(|err| at(err, "Id", index, pos))?;
         //assert_eq!(entry.id, -1);
         ids.push(entry);
     }
 
     // Name Table
+    debug!("Reading {meta_num} names at {:#X}", header.ptr_name);
     _ = rdr.seek(SeekFrom::Start(header.ptr_name as u64));
     let mut names: Vec<TDRNameEntry> = Vec::new();
-    for _ in 0..header.cur_meta_num {
-        let entry = read_tdr_name_entry(&mut rdr)?;
+    for index in 0..meta_num {
+        let pos = rdr.stream_position()?;
+        let entry = read_tdr_name_entry(&mut rdr, arch, options)
+            .map_err(|err| at(err, "Name", index, pos))?;
         names.push(entry);
     }
 
     // Meta Map
+    debug!(
+        "Reading {meta_num} meta map entries at {:#X}",
+        header.ptr_map
+    );
     _ = rdr.seek(SeekFrom::Start(header.ptr_map as u64));
     let mut meta_map: Vec<TDRMapEntry> = Vec::new();
-    for _ in 0..header.cur_meta_num {
-        let entry = read_tdr_map_entry(&mut rdr)?;
+    for index in 0..meta_num {
+        let pos = rdr.stream_position()?;
+        let entry =
+            read_tdr_map_entry(&mut rdr, arch).map_err(|err| at(err, "Meta map", index, pos))?;
         meta_map.push(entry);
     }
 
     // Meta Table
+    debug!("Reading {meta_num} metas at {:#X}", header.ptr_meta);
     _ = rdr.seek(SeekFrom::Start(header.ptr_meta as u64));
     let mut metas: Vec<TDRMeta> = Vec::new();
-    for _ in 0..header.cur_meta_num {
-        let entry = read_tdr_meta(&mut rdr)?;
+    for index in 0..meta_num {
+        let pos = rdr.stream_position()?;
+        let entry =
+            read_tdr_meta(&mut rdr, arch, options).map_err(|err| at(err, "Meta", index, pos))?;
+        trace!(
+            "Read meta `{}` at {:#X} with {} entries",
+            entry.name,
+            entry._offset,
+            entry.entries_num
+        );
+        if !matches!(
+            entry.type_,
+            MetaPrimativeType::STRUCT | MetaPrimativeType::UNION
+        ) {
+            let message = format!(
+                "Meta `{}` at {:#X} has type {:?} ({}), expected a struct or union",
+                entry.name, entry._offset, entry.type_, entry.raw_type
+            );
+            report_warning(
+                ParseWarning::new(WarningCategory::Type, message)
+                    .at(entry._offset)
+                    .meta(&entry.name),
+            );
+        }
         metas.push(entry);
     }
+    for (name, same) in duplicate_meta_names(&metas) {
+        let message = format!(
+            "{} metas are named `{name}` (at {}), so looking them up by name will fail",
+            same.len(),
+            format_meta_offsets(&same)
+        );
+        report_warning(
+            ParseWarning::new(WarningCategory::Name, message)
+                .at(same[1]._offset)
+                .meta(name),
+        );
+    }
+
+    if !options.strict {
+        clear_bad_macro_indexes(&mut metas, macros.len());
+    }
 
     // // MacroGroup Map
     // _ = rdr.seek(SeekFrom::Start(header.ptr_macro_group_map as u64));
@@ -957,12 +2765,26 @@ where
     // }
 
     // MacroGroup table
+    debug!(
+        "Reading {} macrogroups at {:#X}",
+        header.cur_macros_group_num, header.ptr_macros_group
+    );
     _ = rdr.seek(SeekFrom::Start(header.ptr_macros_group as u64));
     let mut macrogroups: Vec<TDRMacroGroup> = Vec::new();
-    for _ in 0..header.cur_macros_group_num {
-        let entry = read_tdr_macros_group(&mut rdr)?;
+    for index in 0..header.cur_macros_group_num {
+        let pos = rdr.stream_position()?;
+        let entry = read_tdr_macros_group(&mut rdr, arch, options)
+            .map_err(|err| at(err, "Macrogroup", index, pos))?;
+        trace!(
+            "Read macrogroup `{}` at {:#X} with {} macros",
+            entry.name,
+            entry._offset,
+            entry.cur_macro_count
+        );
         macrogroups.push(entry);
     }
+    check_macrogroup_indexes(&mut macrogroups, macros.len(), options)?;
+    let raw_strings = std::mem::take(rdr.raw_strings());
 
     Ok(Metalib {
         _offset,
@@ -974,5 +2796,1417 @@ where
         metas,
         // macrogroup_map,
         macrogroups,
+        arch,
+        arch_probe,
+        raw_strings,
+        index: OnceLock::new(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::find_parse_error;
+    use crate::options::StringEncoding;
+    use crate::xml::export_metalib_xml;
+    use crate::report::WarningCategory;
+    use crate::test_support::{
+        compile, compile_bytes, parse, parse_with, DB_XML, EMPTY_XML, FULL_XML, MACROS_XML,
+        TYPES_XML,
+    };
+
+    fn entry<'a>(meta: &'a TDRMeta, name: &str) -> &'a TDRMetaEntry {
+        meta.entries.iter().find(|e| e.name == name).unwrap()
+    }
+
+    #[test]
+    fn size_info_variants() {
+        let metalib = parse(FULL_XML);
+        let packet = metalib.get_meta_by_name("Packet").unwrap();
+
+        match packet.size_type.resolve(&metalib, packet).unwrap() {
+            Some(SizeSource::Field(field)) => assert_eq!(field.path, "head.len"),
+            other => panic!("expected a field, got {other:?}"),
+        }
+        match entry(packet, "name").size_info.resolve(&metalib, packet).unwrap() {
+            Some(SizeSource::TypePrefix(info)) => assert_eq!(info.size, 4),
+            other => panic!("expected a prefix, got {other:?}"),
+        }
+        let kind = entry(packet, "kind");
+        assert!(kind.size_info.resolve(&metalib, packet).unwrap().is_none());
+
+        let sorted = metalib.get_meta_by_name("Sorted").unwrap();
+        match sorted.size_type.resolve(&metalib, sorted).unwrap() {
+            Some(SizeSource::TypePrefix(info)) => assert_eq!(info.size, 2),
+            other => panic!("expected a prefix, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn redirector_variants() {
+        let metalib = parse(FULL_XML);
+        let packet = metalib.get_meta_by_name("Packet").unwrap();
+        let indicator = packet.version_indicator.resolve(&metalib, packet).unwrap();
+        assert_eq!(indicator.unwrap().path, "head.ver");
+
+        let header = metalib.get_meta_by_name("Header").unwrap();
+        assert!(header
+            .version_indicator
+            .resolve(&metalib, header)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn selector_variants() {
+        let mut metalib = parse(FULL_XML);
+        let packet = metalib.get_meta_by_name("Packet").unwrap();
+        let select = |metalib: &Metalib| {
+            let packet = metalib.get_meta_by_name("Packet").unwrap();
+            entry(packet, "body").selector.resolve(metalib, packet)
+        };
+        assert_eq!(select(&metalib).unwrap().unwrap().path, "kind");
+        let refer = entry(packet, "items").referer.resolve(&metalib, packet);
+        assert_eq!(refer.unwrap().unwrap().path, "num");
+        let unset = entry(packet, "kind").selector.resolve(&metalib, packet);
+        assert!(unset.unwrap().is_none());
+
+        let idx = metalib.metas.iter().position(|m| m.name == "Packet").unwrap();
+        let body = metalib.metas[idx]
+            .entries
+            .iter()
+            .position(|e| e.name == "body")
+            .unwrap();
+
+        // No field at the offset, so the entry pointer is used instead.
+        metalib.metas[idx].entries[body].selector.h_off = 1;
+        assert_eq!(select(&metalib).unwrap().unwrap().path, "kind");
+
+        // Neither points anywhere.
+        metalib.metas[idx].entries[body].selector.ptr_entry = 1;
+        assert!(select(&metalib).is_err());
+
+        // Only a bad entry pointer is ignored.
+        metalib.metas[idx].entries[body].selector.h_off = INVALID_METALIB_VALUE;
+        assert!(select(&metalib).unwrap().is_none());
+    }
+
+    #[test]
+    fn db_key_variants() {
+        let metalib = parse(FULL_XML);
+        let account = metalib.get_meta_by_name("Account").unwrap();
+        let keys: Vec<String> = account
+            .primary_key
+            .iter()
+            .map(|key| key.resolve(&metalib, account).unwrap().unwrap().path)
+            .collect();
+        assert_eq!(keys, ["uin", "zone"]);
+        let split = account.split_table_key.resolve(&metalib, account).unwrap();
+        assert_eq!(split.unwrap().path, "uin");
+
+        let packet = metalib.get_meta_by_name("Packet").unwrap();
+        assert!(packet
+            .split_table_key
+            .resolve(&metalib, packet)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn composite_primary_key() {
+        let metalib = parse(DB_XML);
+        let friend = metalib.get_meta_by_name("Friend").unwrap();
+        assert_eq!(friend.primary_key_member_num, 2);
+        let keys: Vec<String> = friend
+            .primary_key
+            .iter()
+            .map(|key| key.resolve(&metalib, friend).unwrap().unwrap().path)
+            .collect();
+        assert_eq!(keys, ["owner", "friend"]);
+
+        // A key table past the end of the body.
+        let mut bytes = compile_bytes(DB_XML, MetalibArch::Bits32);
+        let body_len = metalib.header.size - METALIB_HEADER_SIZE;
+        let start = METALIB_HEADER_SIZE as usize + friend._offset as usize;
+        let pos = (start..start + 0x100)
+            .step_by(4)
+            .find(|&pos| bytes[pos..pos + 4] == friend.ptr_primary_key_base.to_le_bytes())
+            .unwrap();
+        bytes[pos..pos + 4].copy_from_slice(&(body_len - 4).to_le_bytes());
+
+        let (kind, _, message) = parse_failure(&bytes);
+        assert_eq!(kind, ErrorKind::Truncated);
+        assert!(message.starts_with("Primary key of meta `Friend` (2 members)"), "{message}");
+
+        let options = ParseOptions::default().strict(false);
+        let (metalib, report) =
+            read_metalib_with_report(&mut Cursor::new(&bytes), &options).unwrap();
+        assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+        assert_eq!(report.warnings[0].category, WarningCategory::Pointer);
+        assert!(metalib.get_meta_by_name("Friend").unwrap().primary_key.is_empty());
+    }
+
+    #[test]
+    fn split_table_key() {
+        let mut metalib = parse(DB_XML);
+        let player = metalib.get_meta_by_name("Player").unwrap();
+        let key = player.split_table_key.resolve(&metalib, player).unwrap().unwrap();
+        assert_eq!(key.path, "stats.level");
+
+        // An entry pointer that disagrees with the host offset is warned about.
+        let player = metalib.metas.iter_mut().find(|meta| meta.name == "Player").unwrap();
+        player.split_table_key.ptr_entry = player.entries[0]._offset as i32;
+        let player = metalib.get_meta_by_name("Player").unwrap();
+        let (key, report) = collect_warnings(|| player.split_table_key.resolve(&metalib, player));
+        assert_eq!(key.unwrap().unwrap().path, "stats.level");
+        assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+        assert_eq!(report.warnings[0].category, WarningCategory::Pointer);
+        assert!(report.warnings[0].message.starts_with("Key `stats.level` of meta `Player`"));
+    }
+
+    #[test]
+    fn split_table_rules() {
+        let mut bytes = compile_bytes(DB_XML, MetalibArch::Bits32);
+        let metalib = parse(DB_XML);
+        let friend = metalib.get_meta_by_name("Friend").unwrap();
+        assert_eq!(friend.split_table_rule_id, 1);
+
+        // `split_table_factor`, then the rule and `primary_key_member_num`.
+        let start = METALIB_HEADER_SIZE as usize + friend._offset as usize;
+        let pos = (start..start + 0x100)
+            .find(|&pos| bytes[pos..pos + 8] == [16, 0, 0, 0, 1, 0, 2, 0])
+            .unwrap()
+            + 4;
+        bytes[pos..pos + 2].copy_from_slice(&7i16.to_le_bytes());
+
+        let message = "Meta `Friend` has split table rule 7, expected 0 to 1".to_string();
+        assert_eq!(
+            parse_failure(&bytes),
+            (ErrorKind::Other, start as u64, message)
+        );
+
+        let options = ParseOptions::default().strict(false);
+        let (metalib, report) =
+            read_metalib_with_report(&mut Cursor::new(&bytes), &options).unwrap();
+        assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+        assert_eq!(report.warnings[0].category, WarningCategory::Value);
+        assert_eq!(metalib.get_meta_by_name("Friend").unwrap().split_table_rule_id, 0);
+    }
+
+    #[test]
+    fn corrupted_string_pointer_strict_and_lenient() {
+        let mut bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        let header = parse(FULL_XML).header;
+        let name_ptr = (METALIB_HEADER_SIZE + header.ptr_macro) as usize;
+        bytes[name_ptr..name_ptr + 4].copy_from_slice(&0x7FFF_FFF0u32.to_le_bytes());
+
+        let err = parse_with(&bytes, &ParseOptions::default()).unwrap_err();
+        let parse_error = find_parse_error(&err).unwrap();
+        assert_eq!(parse_error.kind, ErrorKind::StringOob);
+        assert_eq!(parse_error.offset, name_ptr as u64);
+
+        let options = ParseOptions::default().strict(false);
+        let (metalib, report) =
+            read_metalib_with_report(&mut Cursor::new(&bytes), &options).unwrap();
+        let placeholder = placeholder("bad-string", 0x7FFF_FFF0);
+        assert_eq!(metalib.macros[0].name, placeholder);
+        assert_eq!(metalib.macros[0].value, 8);
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].category, WarningCategory::String);
+        assert_eq!(report.warnings[0].offset, Some(name_ptr as u64));
+
+        // Everything else still reads, and the XML shows where the placeholder is.
+        assert_eq!(metalib.metas.len(), 5);
+        let xml = export_metalib_xml(&metalib).unwrap();
+        assert!(xml.contains(&format!("<macro name=\"{placeholder}\" value=\"8\"")), "{xml}");
+
+        let strict_warnings = ParseOptions::default().strict(false).fail_on_warnings(true);
+        assert!(parse_with(&bytes, &strict_warnings).is_err());
+    }
+
+    #[test]
+    fn name_pointer_outside_the_string_buffer() {
+        let header = parse(FULL_XML).header;
+        let (start, end) = (header.ptr_str_buf, header.ptr_free_str_buf);
+        let name_ptr = (METALIB_HEADER_SIZE + header.ptr_macro) as usize;
+
+        // Into the body, but before the strings (the macro table itself), and just past them.
+        for ptr in [header.ptr_macro, end] {
+            let mut bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+            bytes[name_ptr..name_ptr + 4].copy_from_slice(&ptr.to_le_bytes());
+            let message = format!(
+                "Unreadable macro name pointer {ptr:#X}: outside the string buffer \
+                 {start:#X}..{end:#X}"
+            );
+            assert_eq!(
+                parse_failure(&bytes),
+                (ErrorKind::StringOob, name_ptr as u64, message)
+            );
+        }
+
+        // The last string in the buffer, missing its NUL, stops at the end of the buffer.
+        let mut bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        let last = METALIB_HEADER_SIZE as usize + end as usize - 1;
+        assert_eq!(bytes[last], 0);
+        bytes[last] = b'x';
+        let (kind, _, message) = parse_failure(&bytes);
+        assert_eq!(kind, ErrorKind::StringOob);
+        assert!(message.contains("no terminator within"), "{message}");
+    }
+
+    const DATES_XML: &str = r#"<metalib tagsetversion="1" name="dates" version="1">
+        <struct name="When" version="1">
+            <entry name="day" type="date" default="2024-01-31"/>
+            <entry name="at" type="time" default="12:30:05"/>
+            <entry name="stamp" type="datetime" default="1999-12-31 23:59:59"/>
+            <entry name="days" type="date" count="2" default="2024-02-01 2024-03-01"/>
+            <entry name="stamps" type="datetime" count="2"
+                default="2000-01-01 00:00:00 2001-02-03 04:05:06"/>
+        </struct>
+    </metalib>"#;
+
+    fn type_idx(xml_name: &str) -> i32 {
+        TDR_PRIMATIVE_TYPE_INFO
+            .iter()
+            .position(|info| info.xml_name == xml_name)
+            .unwrap() as i32
+    }
+
+    #[test]
+    fn date_and_time_defaults() {
+        let metalib = parse(DATES_XML);
+        let defaults: Vec<&str> = metalib.metas[0]
+            .entries
+            .iter()
+            .map(|entry| entry.default_value_string.as_str())
+            .collect();
+        assert_eq!(
+            defaults,
+            [
+                "2024-01-31",
+                "12:30:05",
+                "1999-12-31 23:59:59",
+                "2024-02-01 2024-03-01",
+                "2000-01-01 00:00:00 2001-02-03 04:05:06",
+            ]
+        );
+
+        let date = type_idx("date");
+        let bytes = encode_default_value(date, "2024-01-31").unwrap();
+        assert_eq!(bytes, [0xE8, 0x07, 1, 31]);
+        let options = ParseOptions::default();
+        let read = read_default_value(&mut Cursor::new(&bytes), date, 4, 1, &options).unwrap();
+        assert_eq!(read, "2024-01-31");
+
+        let datetime = type_idx("datetime");
+        let err = encode_default_value(datetime, "1999-12-31").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid datetime default `1999-12-31`: Expected a `YYYY-MM-DD HH:MM:SS` datetime"
+        );
+        let time = type_idx("time");
+        assert!(encode_default_value(time, "12:30").is_err());
+        // A short default is an error rather than a panic.
+        let short = read_default_value(&mut Cursor::new([1, 2, 3]), datetime, 8, 1, &options);
+        assert!(short.is_err());
+    }
+
+    #[test]
+    fn wide_defaults() {
+        let xml = r#"<metalib tagsetversion="1" name="wide" version="1">
+            <struct name="Wide" version="1">
+                <entry name="mark" type="wchar" default="語"/>
+                <entry name="title" type="wstring" size="16" default="勇者 &lt;&amp;&quot;&gt;"/>
+            </struct>
+        </metalib>"#;
+        let metalib = parse(xml);
+        let entries = &metalib.metas[0].entries;
+        assert_eq!(entries[0].default_value_string, "語");
+        assert_eq!(entries[1].default_value_string, "勇者 <&\">");
+        let exported = export_metalib_xml(&metalib).unwrap();
+        assert!(exported.contains(r#"default="語""#), "{exported}");
+        assert!(exported.contains(r#"default="勇者 &lt;&amp;&quot;&gt;""#), "{exported}");
+
+        // A lone high surrogate, then `A`, decodes lossily rather than failing.
+        let options = ParseOptions::default();
+        let wstring = type_idx("wstring");
+        let bytes = [0x00, 0xD8, b'A', 0, 0, 0];
+        let read = read_default_value(&mut Cursor::new(bytes), wstring, 6, 1, &options).unwrap();
+        assert_eq!(read, "\u{FFFD}A");
+        let wchar = type_idx("wchar");
+        let read = read_default_value(&mut Cursor::new([0x00, 0xDC]), wchar, 2, 1, &options);
+        assert_eq!(read.unwrap(), "\u{FFFD}");
+    }
+
+    #[test]
+    fn string_defaults_follow_the_string_encoding() {
+        let xml = r#"<metalib tagsetversion="1" name="text" version="1">
+            <struct name="Hero" version="1">
+                <entry name="title" type="string" size="16" default="勇者"/>
+                <entry name="tag" type="char" count="8" default="勇者"/>
+            </struct>
+        </metalib>"#;
+        let bytes = compile_bytes(xml, MetalibArch::Bits32);
+        // The builder writes the defaults as GBK, which reads as other text under Big5.
+        for (encoding, text) in [(StringEncoding::Gbk, "勇者"), (StringEncoding::Big5, "蚋氪")] {
+            let options = ParseOptions::default().string_encoding(encoding);
+            let metalib = parse_with(&bytes, &options).unwrap();
+            let defaults: Vec<&str> = metalib.metas[0]
+                .entries
+                .iter()
+                .map(|entry| entry.default_value_string.as_str())
+                .collect();
+            assert_eq!(defaults, [text, text], "{encoding:?}");
+            let exported = export_metalib_xml(&metalib).unwrap();
+            assert!(exported.contains(&format!(r#"default="{text}""#)), "{exported}");
+        }
+
+        let string = type_idx("string");
+        let gbk = [0xD3, 0xC2, 0xD5, 0xDF, 0];
+        assert_eq!(encode_default_value(string, "勇者").unwrap(), gbk);
+        let options = ParseOptions::default().string_encoding(StringEncoding::Utf8);
+        let read = read_default_value(&mut Cursor::new("勇者\0"), string, 7, 1, &options);
+        assert_eq!(read.unwrap(), "勇者");
+    }
+
+    #[test]
+    fn money_entries() {
+        let xml = r#"<metalib tagsetversion="1" name="bank" version="1">
+            <struct name="Account" version="1">
+                <entry name="balance" type="money" default="-250"/>
+                <entry name="history" type="money" count="3" default="1 2 3"/>
+                <entry name="id" type="int"/>
+            </struct>
+        </metalib>"#;
+        let metalib = parse(xml);
+        let meta = &metalib.metas[0];
+        let sizes: Vec<(MetaPrimativeType, i32, &str)> = meta
+            .entries
+            .iter()
+            .map(|e| (e.type_, e.h_real_size, e.default_value_string.as_str()))
+            .collect();
+        assert_eq!(
+            sizes,
+            [
+                (MetaPrimativeType::MONEY, 4, "-250"),
+                (MetaPrimativeType::MONEY, 12, "1 2 3"),
+                (MetaPrimativeType::INT, 4, ""),
+            ]
+        );
+        assert_eq!(meta.h_unit_size, 20);
+
+        let exported = export_metalib_xml(&metalib).unwrap();
+        assert!(exported.contains(r#"<entry name="balance" type="money" default="-250"/>"#));
+        assert!(
+            exported.contains(r#"<entry name="history" type="money" count="3" default="1 2 3"/>"#),
+            "{exported}"
+        );
+    }
+
+    #[test]
+    fn array_defaults() {
+        let xml = r#"<metalib tagsetversion="1" name="arrays" version="1">
+            <struct name="Arrays" version="1">
+                <entry name="ints" type="int" count="3" default="1 -2 3"/>
+                <entry name="same" type="int16" count="4" default="7"/>
+                <entry name="tag" type="char" count="8" default="abc"/>
+                <entry name="small" type="char" count="8" default="5"/>
+            </struct>
+        </metalib>"#;
+        let metalib = parse(xml);
+        let defaults: Vec<(&str, i32)> = metalib.metas[0]
+            .entries
+            .iter()
+            .map(|entry| (entry.default_value_string.as_str(), entry.default_val_len))
+            .collect();
+        assert_eq!(defaults, [("1 -2 3", 12), ("7", 2), ("abc", 4), ("5", 1)]);
+
+        let options = ParseOptions::default();
+        let int = type_idx("int");
+        let bytes = [1, 0, 0, 0, 2, 0, 0, 0];
+        let err = read_default_value(&mut Cursor::new(bytes), int, 8, 1, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The 8 byte default is longer than the 4 byte int array it's for"
+        );
+        let err = read_default_value(&mut Cursor::new(bytes), int, 16, 4, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The 16 byte default runs past the end of the data, 8 bytes on"
+        );
+        let err = read_default_value(&mut Cursor::new(bytes), int, 6, 2, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The 6 byte default isn't a whole number of 4 byte int values"
+        );
+        let err = read_default_value(&mut Cursor::new(bytes), int, 2, 3, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The 2 byte default is shorter than one 4 byte int value"
+        );
+        // A huge length is an error rather than a huge allocation.
+        let char_ = type_idx("char");
+        let mut rdr = Cursor::new(b"abc\0");
+        assert!(read_default_value(&mut rdr, char_, i32::MAX, i32::MAX, &options).is_err());
+
+        // The text of a `char` array is in the string encoding.
+        let gbk = [0xD3, 0xC2, 0xD5, 0xDF, 0];
+        let read = read_default_value(&mut Cursor::new(gbk), char_, 5, 8, &options).unwrap();
+        assert_eq!(read, "勇者");
+        assert_eq!(encode_default_value(char_, "勇者").unwrap(), gbk);
+
+        for type_name in ["struct", "union", "void"] {
+            let idx = type_idx(type_name);
+            let err = read_default_value(&mut Cursor::new([0; 4]), idx, 1, 1, &options);
+            let err = err.unwrap_err();
+            assert_eq!(err.to_string(), format!("A {type_name} can't have a default value"));
+        }
+    }
+
+    #[test]
+    fn corrupted_custom_attr_pointer() {
+        let mut bytes = compile_bytes(DB_XML, MetalibArch::Bits32);
+        let metalib = parse(DB_XML);
+        let level = &metalib.metas[0].entries[0];
+        assert_eq!(level.custom_attr, "ui=\"lvl\" & max=<99>");
+        let body_len = metalib.header.size - METALIB_HEADER_SIZE;
+
+        let start = METALIB_HEADER_SIZE as usize + level._offset as usize;
+        let pos = (start..start + 0x100)
+            .step_by(4)
+            .find(|&pos| bytes[pos..pos + 4] == level.ptr_custom_attr.to_le_bytes())
+            .unwrap();
+        bytes[pos..pos + 4].copy_from_slice(&(body_len + 4).to_le_bytes());
+
+        let (kind, offset, message) = parse_failure(&bytes);
+        assert_eq!((kind, offset), (ErrorKind::StringOob, start as u64));
+        assert!(message.starts_with("Entry `level`: unreadable custom attribute"), "{message}");
+
+        let options = ParseOptions::default().strict(false);
+        let (metalib, report) =
+            read_metalib_with_report(&mut Cursor::new(&bytes), &options).unwrap();
+        assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+        assert_eq!(report.warnings[0].category, WarningCategory::String);
+        let level = &metalib.metas[0].entries[0];
+        assert_eq!(level.custom_attr, placeholder("bad-customattr", body_len as u64 + 4));
+    }
+
+    /// FULL_XML in the 32-bit layout, with `patch` applied to the file bytes given the
+    /// `Packet.<name>` entry.
+    fn patch_packet_entry(name: &str, patch: impl FnOnce(&mut [u8], &TDRMetaEntry)) -> Vec<u8> {
+        let mut bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        let metalib = parse(FULL_XML);
+        patch(&mut bytes, entry(metalib.get_meta_by_name("Packet").unwrap(), name));
+        bytes
+    }
+
+    #[test]
+    fn unknown_io_values() {
+        let bytes = patch_packet_entry("name", |bytes, entry| {
+            assert_eq!(entry.io, 2);
+            // Right after the selector, which is 12 bytes in this layout.
+            let pos = METALIB_HEADER_SIZE as usize + entry.selector._offset as usize + 12;
+            bytes[pos..pos + 4].copy_from_slice(&7i32.to_le_bytes());
+        });
+        let message = "Entry `Packet.name` has unknown io value 7";
+        let (kind, _, strict) = parse_failure(&bytes);
+        assert_eq!((kind, strict.as_str()), (ErrorKind::Other, message));
+
+        let options = ParseOptions::default().strict(false);
+        let (metalib, report) =
+            read_metalib_with_report(&mut Cursor::new(&bytes), &options).unwrap();
+        assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+        assert_eq!(report.warnings[0].category, WarningCategory::Value);
+        assert!(report.warnings[0].message.starts_with(message));
+        assert!(report.warnings[0].message.ends_with(", ignoring it"));
+        let name = entry(metalib.get_meta_by_name("Packet").unwrap(), "name");
+        assert_eq!(name.io, 0);
+        let xml = export_metalib_xml(&metalib).unwrap();
+        let name = r#"unique="true" notnull="true" sizeinfo="int"/>"#;
+        assert!(xml.contains(name), "{xml}");
+        assert!(!xml.contains(" io="), "{xml}");
+    }
+
+    #[test]
+    fn sort_orders() {
+        // `order` is the byte just before the size info.
+        let with_order = |order: u8| {
+            patch_packet_entry("items", |bytes, entry| {
+                assert_eq!(entry.order, 1);
+                let pos = METALIB_HEADER_SIZE as usize + entry.size_info._offset as usize - 1;
+                bytes[pos] = order;
+            })
+        };
+        let lenient = ParseOptions::default().strict(false);
+        let items_xml = |bytes: &[u8]| {
+            let (metalib, report) =
+                read_metalib_with_report(&mut Cursor::new(bytes), &lenient).unwrap();
+            let xml = export_metalib_xml(&metalib).unwrap();
+            let items = xml.lines().find(|line| line.contains(r#"name="items""#)).unwrap();
+            (items.trim().to_string(), report.warnings)
+        };
+
+        let items = r#"<entry name="items" type="uint32" count="MAX_ITEMS" refer="num""#;
+        let known = [
+            (0, "/>"),
+            (1, r#" sortMethod="asc"/>"#),
+            (2, r#" sortMethod="desc"/>"#),
+        ];
+        for (order, attr) in known {
+            let bytes = with_order(order);
+            assert!(parse_with(&bytes, &ParseOptions::default()).is_ok());
+            let (xml, warnings) = items_xml(&bytes);
+            assert_eq!(xml, format!("{items}{attr}"));
+            assert!(warnings.is_empty());
+        }
+
+        let bytes = with_order(5);
+        let message = "Entry `Packet.items` has unknown order value 5";
+        let (kind, _, strict) = parse_failure(&bytes);
+        assert_eq!((kind, strict.as_str()), (ErrorKind::Other, message));
+        let (xml, warnings) = items_xml(&bytes);
+        assert_eq!(xml, format!(r#"{items} order="5"/>"#));
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+        assert_eq!(warnings[0].category, WarningCategory::Value);
+        assert!(warnings[0].message.starts_with(message));
+    }
+
+    #[test]
+    fn duplicate_meta_names() {
+        let mut metalib = compile(FULL_XML);
+        let sorted = metalib.metas.iter_mut().find(|m| m.name == "Sorted").unwrap();
+        sorted.name = "Header".to_string();
+        let mut bytes = Vec::new();
+        crate::writer::write_metalib(&metalib, &mut bytes).unwrap();
+
+        let (metalib, report) =
+            read_metalib_with_report(&mut Cursor::new(&bytes), &ParseOptions::default()).unwrap();
+        let offsets: Vec<u64> = metalib
+            .metas
+            .iter()
+            .filter(|meta| meta.name == "Header")
+            .map(|meta| meta._offset)
+            .collect();
+        let [first, second] = offsets[..] else { panic!("{offsets:?}") };
+        assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+        assert_eq!(report.warnings[0].category, WarningCategory::Name);
+        assert_eq!(
+            report.warnings[0].message,
+            format!(
+                "2 metas are named `Header` (at {first:#X}, {second:#X}), so looking them up by \
+                 name will fail"
+            )
+        );
+
+        let err = metalib.get_meta_by_name("Header").unwrap_err();
+        assert!(err.to_string().starts_with("Meta name `Header` is ambiguous"), "{err}");
+        let picked = metalib.get_meta_by_name(&format!("Header@{second:#X}")).unwrap();
+        assert_eq!((picked._offset, picked.entries[0].name.as_str()), (second, "key"));
+        let issues = metalib.validate();
+        assert!(
+            issues.iter().any(|issue| issue.severity == crate::validate::Severity::Error
+                && issue.meta.as_deref() == Some("Header")
+                && issue.message.starts_with("2 metas have this name")),
+            "{issues:#?}"
+        );
+    }
+
+    /// The kind, offset and message of the error parsing `bytes` strictly.
+    fn parse_failure(bytes: &[u8]) -> (ErrorKind, u64, String) {
+        let err = parse_with(bytes, &ParseOptions::default()).unwrap_err();
+        let parse_error = find_parse_error(&err).unwrap_or_else(|| panic!("{err:#}"));
+        (parse_error.kind, parse_error.offset, parse_error.message.clone())
+    }
+
+    #[test]
+    fn random_bytes_are_rejected() {
+        // xorshift64, so every run sees the same bytes.
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut random_bytes = |len: usize| -> Vec<u8> {
+            (0..len)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect()
+        };
+
+        // Too short to hold a header at all.
+        let (kind, offset, _) = parse_failure(&random_bytes(0x113));
+        assert_eq!((kind, offset), (ErrorKind::Truncated, 0x113));
+
+        for len in [0x114, 0x200, 0x1000, 0x10000] {
+            let bytes = random_bytes(len);
+            let (kind, offset, message) = parse_failure(&bytes);
+            assert_eq!((kind, offset), (ErrorKind::BadMagic, 0), "{message}");
+            assert!(message.starts_with("Header magic is 0x"), "{message}");
+
+            // With the right magic, the next field is the one reported.
+            let mut bytes = random_bytes(len);
+            bytes[..2].copy_from_slice(&METALIB_MAGIC.to_le_bytes());
+            let (kind, offset, message) = parse_failure(&bytes);
+            assert_eq!((kind, offset), (ErrorKind::BadHeader, 2), "{message}");
+            assert!(message.starts_with("Unsupported metalib build"), "{message}");
+        }
+    }
+
+    #[test]
+    fn truncated_headers() {
+        let bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        for len in [0, 1, 2, 4, 8, 0x2C, 0x90, METALIB_HEADER_SIZE as usize - 1] {
+            let (kind, offset, message) = parse_failure(&bytes[..len]);
+            assert_eq!((kind, offset), (ErrorKind::Truncated, len as u64), "{message}");
+        }
+    }
+
+    #[test]
+    fn bad_header_fields() {
+        let bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        let corrupt = |field: usize, value: u32| {
+            let mut bytes = bytes.clone();
+            bytes[field..field + 4].copy_from_slice(&value.to_le_bytes());
+            parse_failure(&bytes)
+        };
+        let bad_header =
+            |offset, message: &str| (ErrorKind::BadHeader, offset, message.to_string());
+
+        assert_eq!(
+            corrupt(4, 7),
+            bad_header(4, "Header platform_arch is 0x7, expected 0x20 or 0x40")
+        );
+        assert_eq!(
+            corrupt(8, 0x113),
+            bad_header(8, "Header size is 0x113, expected at least 0x114")
+        );
+        // The header's own meta count is 5, so a capacity of 4 is too small.
+        assert_eq!(
+            corrupt(0x28, 4),
+            bad_header(0x2C, "Header meta count is 5, expected between 0 and its capacity 4")
+        );
+        assert_eq!(
+            corrupt(0x34, u32::MAX),
+            bad_header(0x34, "Header macro count is -1, expected between 0 and its capacity 4")
+        );
+    }
+
+    #[test]
+    fn truncated_tables() {
+        let bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        let metalib = parse(FULL_XML);
+        let header = &metalib.header;
+        let header_len = METALIB_HEADER_SIZE as usize;
+
+        // Cut short with the size to match, as if the file had been truncated and the
+        // header fixed up: the first table past the end is named, at its header field.
+        let cut = |body_len: u32| {
+            let mut bytes = bytes[..header_len + body_len as usize].to_vec();
+            bytes[8..12].copy_from_slice(&(METALIB_HEADER_SIZE + body_len).to_le_bytes());
+            parse_failure(&bytes)
+        };
+        let cuts = [
+            (0, 0x4C, "Macro table (offset 0x0, 4 entries)"),
+            (header.ptr_id + 4, 0x50, "Id table (offset 0x40, 5 entries)"),
+            (header.ptr_name + 4, 0x54, "Name table (offset 0x68, 5 entries)"),
+            (header.ptr_map + 4, 0x58, "Meta map table (offset 0x90, 5 entries)"),
+            (header.ptr_meta + 4, 0x5C, "Meta table (offset 0xB8, 5 entries)"),
+            (header.ptr_last_meta, 0x74, "Macrogroup table (offset 0x13C8, 1 entries)"),
+        ];
+        for (body_len, field, table) in cuts {
+            let (kind, offset, message) = cut(body_len);
+            assert_eq!((kind, offset), (ErrorKind::BadHeader, field), "{message}");
+            let expected = format!("{table} exceeds metalib body of {body_len:#X} bytes");
+            assert_eq!(message, expected);
+        }
+
+        // The tables fit but the last meta claims more entries than the body has room for:
+        // the meta is named, at its record.
+        let last = metalib.metas.last().unwrap();
+        let mut bytes = bytes.clone();
+        let entries_num = header_len + last._offset as usize + 0x2C;
+        bytes[entries_num..entries_num + 4].copy_from_slice(&1000i32.to_le_bytes());
+        let (kind, offset, message) = parse_failure(&bytes);
+        let record = header_len as u64 + last._offset;
+        assert_eq!((kind, offset), (ErrorKind::Truncated, record), "{message}");
+        assert_eq!(
+            message,
+            format!(
+                "Meta `Account` (1000 entries) exceeds metalib body of {:#X} bytes",
+                header.size - METALIB_HEADER_SIZE
+            )
+        );
+    }
+
+    #[test]
+    fn header_sizes() {
+        let bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        let with_size = |size: u32| {
+            let mut bytes = bytes.clone();
+            bytes[8..12].copy_from_slice(&size.to_le_bytes());
+            bytes
+        };
+        let lenient = ParseOptions::default().strict(false);
+
+        // Too small to cover the header, which even lenient parsing can't get past.
+        for size in [0, METALIB_HEADER_SIZE - 1] {
+            let expected = format!("Header size is {size:#X}, expected at least 0x114");
+            let (kind, offset, message) = parse_failure(&with_size(size));
+            assert_eq!((kind, offset, message.as_str()), (ErrorKind::BadHeader, 8, &*expected));
+            let err = parse_with(&with_size(size), &lenient).unwrap_err();
+            assert_eq!(find_parse_error(&err).unwrap().message, expected);
+        }
+
+        // Over the limit, reported before anything is allocated for it.
+        let expected = format!(
+            "Header size is 0xFFFFFFFF, expected at most {MAX_METALIB_SIZE:#X} \
+             (see --max-metalib-size)"
+        );
+        let (kind, offset, message) = parse_failure(&with_size(u32::MAX));
+        assert_eq!((kind, offset, message.as_str()), (ErrorKind::BadHeader, 8, &*expected));
+        let err = parse_with(&with_size(u32::MAX), &lenient).unwrap_err();
+        assert_eq!(find_parse_error(&err).unwrap().message, expected);
+
+        // Under a raised limit, the same size is past the end of the data instead.
+        let unlimited = ParseOptions::default().max_metalib_size(u64::MAX);
+        let err = parse_with(&with_size(u32::MAX), &unlimited).unwrap_err();
+        let parse_error = find_parse_error(&err).unwrap();
+        assert_eq!(parse_error.kind, ErrorKind::Truncated);
+        assert_eq!(parse_error.offset, bytes.len() as u64);
+        assert!(parse_error.message.contains("has a body of 4294967019 bytes"), "{err:#}");
+
+        // And a limit under the real size fails the fixture itself.
+        let small = ParseOptions::default().max_metalib_size(0x200);
+        let err = parse_with(&bytes, &small).unwrap_err();
+        assert!(format!("{err:#}").contains("expected at most 0x200"), "{err:#}");
+    }
+
+    #[test]
+    fn header_layout_selection() {
+        assert_eq!(HeaderLayout::for_build(METALIB_BUILD).unwrap().size, 0x114);
+        assert_eq!(HeaderLayout::for_build(METALIB_BUILD_10).unwrap().size, 0xF8);
+        assert_eq!(HeaderLayout::for_build(12), None);
+        assert_eq!(HeaderLayout::for_build_or_current(12).build, METALIB_BUILD);
+        assert_eq!(HeaderLayout::min_size(), 0xF8);
+
+        // The older build reads the same metalib, with the words it doesn't have as 0.
+        let bytes = crate::test_support::compile_build_10_bytes(FULL_XML);
+        let current = parse(FULL_XML);
+        let metalib = parse_with(&bytes, &ParseOptions::default()).unwrap();
+        assert_eq!(metalib.header.build, METALIB_BUILD_10);
+        assert_eq!(metalib.header.header_size(), 0xF8);
+        assert_eq!(metalib.header.body_size(), current.header.body_size());
+        assert_eq!(metalib.header.name, current.header.name);
+        assert_eq!(metalib.header.ptr_macros_group, current.header.ptr_macros_group);
+        assert_eq!((metalib.header.field_78, metalib.header.field_90), (0, 0));
+        assert_eq!(
+            export_metalib_xml(&metalib).unwrap(),
+            export_metalib_xml(&current).unwrap()
+        );
+
+        // Written back in its own layout.
+        let mut written = Vec::new();
+        crate::writer::write_metalib(&metalib, &mut written).unwrap();
+        assert_eq!(written, bytes);
+
+        // Errors in the body are at file offsets past the smaller header.
+        let key = entry(&metalib.metas[3], "key");
+        let mut corrupt = bytes.clone();
+        let raw_type = 0xF8 + key._offset as usize + 8;
+        corrupt[raw_type..raw_type + 4].copy_from_slice(&99i32.to_le_bytes());
+        let (kind, offset, _) = parse_failure(&corrupt);
+        assert_eq!((kind, offset), (ErrorKind::UnknownType, raw_type as u64));
+
+        // Cut inside the header, the older layout's size is the one expected.
+        let (_, _, message) = parse_failure(&bytes[..0xF0]);
+        assert!(message.starts_with("Metalib header at 0x0 is 0xF8 bytes"), "{message}");
+
+        // A build we have no layout for is still rejected by name.
+        let mut unknown = bytes;
+        unknown[2..4].copy_from_slice(&12u16.to_le_bytes());
+        let (kind, offset, message) = parse_failure(&unknown);
+        assert_eq!((kind, offset), (ErrorKind::BadHeader, 2));
+        let expected = "Unsupported metalib build 12 (0xC), only the headers of builds 11 (0xB) \
+                        and 10 (0xA) are known";
+        assert_eq!(message, expected);
+        // Even leniently, rather than read with another build's layout.
+        let lenient = ParseOptions::default().strict(false);
+        let err = parse_with(&unknown, &lenient).unwrap_err();
+        assert_eq!(find_parse_error(&err).unwrap().message, expected);
+    }
+
+    #[test]
+    fn empty_and_macros_only() {
+        for (xml, macros) in [(EMPTY_XML, 0), (MACROS_XML, 2)] {
+            let metalib = parse(xml);
+            assert!(metalib.metas.is_empty());
+            assert!(metalib.macrogroups.is_empty());
+            assert_eq!(metalib.macros.len(), macros);
+            assert_eq!(export_metalib_xml(&metalib).unwrap(), xml);
+
+            // Tables that aren't there may point anywhere, 0 and -1 included.
+            let bytes = compile_bytes(xml, MetalibArch::Bits32);
+            for ptr in [0, u32::MAX] {
+                let mut bytes = bytes.clone();
+                let mut fields = vec![0x50, 0x54, 0x58, 0x5C, 0x60, 0x70, 0x74];
+                if macros == 0 {
+                    fields.push(0x4C);
+                }
+                for field in fields {
+                    bytes[field..field + 4].copy_from_slice(&ptr.to_le_bytes());
+                }
+                let reparsed = parse_with(&bytes, &ParseOptions::default())
+                    .unwrap_or_else(|err| panic!("{ptr:#X}: {err:#}"));
+                assert_eq!(export_metalib_xml(&reparsed).unwrap(), xml);
+                assert!(reparsed.validate().is_empty(), "{:?}", reparsed.validate());
+            }
+        }
+
+        // Macros only, without even reading the metas.
+        let bytes = compile_bytes(MACROS_XML, MetalibArch::Bits32);
+        let options = ParseOptions::default().macros_only(true);
+        let metalib = parse_with(&bytes, &options).unwrap();
+        assert_eq!(metalib.macros[1].name, "VERSION");
+    }
+
+    #[test]
+    fn scan_skips_bad_headers() {
+        let bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        let mut bad_arch = bytes.clone();
+        bad_arch[4] = 7;
+        let mut data = bad_arch;
+        data.extend_from_slice(&bytes);
+
+        let hits = crate::scan::scan_metalibs(&data, &ParseOptions::default());
+        let offsets: Vec<u64> = hits.iter().map(|hit| hit.offset).collect();
+        assert_eq!(offsets, [bytes.len() as u64]);
+    }
+
+    #[test]
+    fn corrupted_table_pointers() {
+        let bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        let metalib = parse(FULL_XML);
+        let body_len = metalib.header.size - METALIB_HEADER_SIZE;
+        let counts = [
+            (0x4C, "Macro", 4),
+            (0x50, "Id", 5),
+            (0x54, "Name", 5),
+            (0x58, "Meta map", 5),
+            (0x5C, "Meta", 5),
+            (0x74, "Macrogroup", 1),
+        ];
+        for (field, table, count) in counts {
+            for ptr in [body_len, u32::MAX] {
+                let mut bytes = bytes.clone();
+                bytes[field..field + 4].copy_from_slice(&ptr.to_le_bytes());
+                let message = format!(
+                    "{table} table (offset {ptr:#X}, {count} entries) exceeds metalib body \
+                     of {body_len:#X} bytes"
+                );
+                assert_eq!(
+                    parse_failure(&bytes),
+                    (ErrorKind::BadHeader, field as u64, message)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_entry_pointers() {
+        let bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        let metalib = parse(FULL_XML);
+        let packet = metalib.get_meta_by_name("Packet").unwrap();
+        let body_len = metalib.header.size - METALIB_HEADER_SIZE;
+
+        for (name, field, ptr) in [
+            ("head", "ptr_meta", packet.entries[0].ptr_meta),
+            ("kind", "ptr_macros_group", packet.entries[1].ptr_macros_group),
+        ] {
+            let entry = entry(packet, name);
+            // The pointer's position in the entry's record, found by its value.
+            let start = METALIB_HEADER_SIZE as usize + entry._offset as usize;
+            let pos = (start..start + 0x100)
+                .step_by(4)
+                .find(|&pos| bytes[pos..pos + 4] == ptr.to_le_bytes())
+                .unwrap();
+            let mut bytes = bytes.clone();
+            bytes[pos..pos + 4].copy_from_slice(&(body_len + 4).to_le_bytes());
+
+            let message = format!(
+                "Entry `{name}` {field} {:#X} is outside the metalib body of {body_len:#X} bytes",
+                body_len + 4
+            );
+            assert_eq!(
+                parse_failure(&bytes),
+                (ErrorKind::Other, start as u64, message.clone())
+            );
+
+            let options = ParseOptions::default().strict(false);
+            let (metalib, report) =
+                read_metalib_with_report(&mut Cursor::new(&bytes), &options).unwrap();
+            assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+            assert_eq!(report.warnings[0].category, WarningCategory::Pointer);
+            assert!(report.warnings[0].message.starts_with(&message));
+            let packet = metalib.get_meta_by_name("Packet").unwrap();
+            let entry = super::tests::entry(packet, name);
+            let cleared = match field {
+                "ptr_meta" => entry.ptr_meta,
+                _ => entry.ptr_macros_group,
+            };
+            assert_eq!(cleared, INVALID_METALIB_VALUE);
+        }
+    }
+
+    /// Two macrogroups, the first with room for 6 macros but 2 in use.
+    fn slack_groups() -> Vec<u8> {
+        let mut metalib = crate::builder::MetalibBuilder::new("groups")
+            .macrogroup("A", &["A1", "A2"])
+            .macro_("A1", 1)
+            .macro_("A2", 2)
+            .macrogroup("B", &["B1"])
+            .macro_("B1", 3)
+            .build()
+            .unwrap();
+        metalib.macrogroups[0].max_macro_count = 6;
+        let mut bytes = Vec::new();
+        crate::writer::write_metalib(&metalib, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn members(metalib: &Metalib, group: usize) -> Vec<&str> {
+        let group = &metalib.macrogroups[group];
+        metalib
+            .macrogroup_macros(group)
+            .map(|m| m.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn macrogroup_with_spare_capacity() {
+        let metalib = parse_with(&slack_groups(), &ParseOptions::default()).unwrap();
+        let [a, b] = &metalib.macrogroups[..] else {
+            panic!("expected 2 macrogroups");
+        };
+        assert_eq!((a.cur_macro_count, a.max_macro_count), (2, 6));
+        assert_eq!(a._ptr_value_idx_map - a._ptr_name_idx_map, 4 * 6);
+        assert_eq!(b.name, "B");
+        assert_eq!(members(&metalib, 0), ["A1", "A2"]);
+        assert_eq!(members(&metalib, 1), ["B1"]);
+    }
+
+    #[test]
+    fn negative_macrogroup_map_pointer() {
+        let mut bytes = slack_groups();
+        let metalib = parse_with(&bytes, &ParseOptions::default()).unwrap();
+        let group = METALIB_HEADER_SIZE as usize + metalib.macrogroups[0]._offset as usize;
+        // Counts, then the description pointer, then the name map pointer.
+        let ptr = group + 12;
+        assert_eq!(
+            bytes[ptr..ptr + 4],
+            metalib.macrogroups[0]._ptr_name_idx_map.to_le_bytes()
+        );
+        bytes[ptr..ptr + 4].copy_from_slice(&(-16i32).to_le_bytes());
+
+        let message = "Macrogroup `A` index map pointer -16 is negative";
+        assert_eq!(
+            parse_failure(&bytes),
+            (ErrorKind::Other, group as u64, message.to_string())
+        );
+
+        let options = ParseOptions::default().strict(false);
+        let (metalib, report) =
+            read_metalib_with_report(&mut Cursor::new(&bytes), &options).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].category, WarningCategory::Pointer);
+        assert_eq!(report.warnings[0].offset, Some(group as u64));
+        assert!(metalib.macrogroups[0].name_idx_map.is_empty());
+        // The other map and the next group still read.
+        assert_eq!(members(&metalib, 0), ["A1", "A2"]);
+        assert_eq!(members(&metalib, 1), ["B1"]);
+    }
+
+    /// `slack_groups` with a third slot in group `A` holding `idx`.
+    fn group_with_slot(idx: i32) -> Vec<u8> {
+        let mut metalib = parse_with(&slack_groups(), &ParseOptions::default()).unwrap();
+        let group = &mut metalib.macrogroups[0];
+        group.cur_macro_count = 3;
+        group.name_idx_map.insert(1, idx);
+        group.value_idx_map.insert(1, idx);
+        let mut bytes = Vec::new();
+        crate::writer::write_metalib(&metalib, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn unused_macrogroup_slots() {
+        let metalib = parse_with(&group_with_slot(-1), &ParseOptions::default()).unwrap();
+        assert_eq!(metalib.macrogroups[0].value_idx_map, [0, -1, 1]);
+        assert_eq!(members(&metalib, 0), ["A1", "A2"]);
+        assert!(metalib.validate().is_empty());
+
+        let xml = export_metalib_xml(&metalib).unwrap();
+        let group_a = xml
+            .lines()
+            .skip_while(|line| !line.contains("<macrosgroup name=\"A\""))
+            .take_while(|line| !line.contains("</macrosgroup>"))
+            .filter(|line| line.contains("<macro "))
+            .count();
+        assert_eq!(group_a, 2, "{xml}");
+    }
+
+    #[test]
+    fn out_of_range_macrogroup_slots() {
+        let bytes = group_with_slot(99);
+        let (kind, _, message) = parse_failure(&bytes);
+        assert_eq!(kind, ErrorKind::Other);
+        assert_eq!(
+            message,
+            "Macrogroup `A` name index 1 is 99, outside the macro table (3 macros)"
+        );
+
+        let options = ParseOptions::default().strict(false);
+        let (metalib, report) =
+            read_metalib_with_report(&mut Cursor::new(&bytes), &options).unwrap();
+        assert_eq!(report.warnings.len(), 2);
+        assert!(report
+            .warnings
+            .iter()
+            .all(|w| w.category == WarningCategory::MacroIndex));
+        assert_eq!(metalib.macrogroups[0].value_idx_map, [0, -1, 1]);
+        assert_eq!(members(&metalib, 0), ["A1", "A2"]);
+    }
+
+    #[test]
+    fn unknown_entry_type() {
+        let mut bytes = compile_bytes(TYPES_XML, MetalibArch::Bits32);
+        let metalib = parse_with(&bytes, &ParseOptions::default()).unwrap();
+        let entry = &metalib.metas[0].entries[4];
+        assert_eq!(entry.name, "i");
+        let pos = entry._offset + 8;
+        let at = 0x114 + pos as usize;
+        bytes[at..at + 4].copy_from_slice(&99i32.to_le_bytes());
+
+        assert_eq!(
+            parse_failure(&bytes),
+            (ErrorKind::UnknownType, 0x114 + pos, "Unknown type 99".to_string())
+        );
+
+        let options = ParseOptions::default().strict(false);
+        let (metalib, report) =
+            read_metalib_with_report(&mut Cursor::new(&bytes), &options).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].category, WarningCategory::Type);
+        let entry = &metalib.metas[0].entries[4];
+        assert_eq!((entry.type_, entry.raw_type), (MetaPrimativeType::UNKNOWN, 99));
+
+        let xml = export_metalib_xml(&metalib).unwrap();
+        assert!(xml.contains("<entry name=\"i\" type=\"99\"/>"), "{xml}");
+        assert!(xml.contains("<entry name=\"u\" type=\"uint\"/>"), "{xml}");
+    }
+
+    #[test]
+    fn out_of_range_type_index() {
+        let mut bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        let metalib = parse(FULL_XML);
+        let table_len = TDR_PRIMATIVE_TYPE_INFO.len();
+        // `gold` has a default to read with it and `name` a size to divide by its unit.
+        let gold = entry(&metalib.metas[4], "gold");
+        let name = entry(&metalib.metas[2], "name");
+        for (entry, idx_type) in [(gold, 87i32), (name, -2)] {
+            let at = 0x114 + entry._offset as usize + 0x3C;
+            assert_eq!(bytes[at..at + 4], entry.idx_type.to_le_bytes());
+            bytes[at..at + 4].copy_from_slice(&idx_type.to_le_bytes());
+        }
+
+        let (kind, offset, message) = parse_failure(&bytes);
+        assert_eq!((kind, offset), (ErrorKind::UnknownType, 0x114 + name._offset));
+        assert_eq!(
+            message,
+            format!(
+                "Entry `Packet.name` has idx_type=-2, which exceeds the primitive type table \
+                 ({table_len} entries)"
+            )
+        );
+
+        let options = ParseOptions::default().strict(false);
+        let (lenient, report) =
+            read_metalib_with_report(&mut Cursor::new(&bytes), &options).unwrap();
+        let warnings: Vec<_> = report
+            .warnings
+            .iter()
+            .map(|w| (w.category, w.offset, w.meta.as_deref(), w.entry.as_deref()))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                (WarningCategory::Type, Some(0x114 + name._offset), Some("Packet"), Some("name")),
+                (WarningCategory::Type, Some(0x114 + gold._offset), Some("Account"), Some("gold")),
+                (WarningCategory::Value, Some(0x114 + gold._offset), Some("Account"), Some("gold")),
+            ]
+        );
+        assert_eq!(
+            report.warnings[1].message,
+            format!(
+                "Entry `Account.gold` has idx_type=87, which exceeds the primitive type table \
+                 ({table_len} entries) at {:#X}, writing the raw index",
+                gold._offset
+            )
+        );
+        assert!(
+            report.warnings[2].message.contains(&format!(
+                "Entry `Account.gold` at {:#X}: unreadable default value at {:#X}: The default \
+                 value has idx_type=87, which exceeds the primitive type table \
+                 ({table_len} entries)",
+                gold._offset, gold.ptr_default_val
+            )),
+            "{}",
+            report.warnings[2].message
+        );
+
+        // The raw indexes are written in place of the types, and a placeholder for the default.
+        let xml = export_metalib_xml(&lenient).unwrap();
+        let gold_xml = format!(
+            "<entry name=\"gold\" type=\"87\" default=\"??bad-default@{:#X}??\"/>",
+            gold.ptr_default_val
+        );
+        assert!(xml.contains(&gold_xml), "{xml}");
+        assert!(
+            xml.contains("<entry name=\"name\" type=\"-2\" version=\"VER2\" size=\"16\""),
+            "{xml}"
+        );
+    }
+
+    #[test]
+    fn entry_points_agree() {
+        let bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        let from_reader = read_metalib(&mut Cursor::new(&bytes)).unwrap();
+        let from_bytes = Metalib::from_bytes(&bytes).unwrap();
+        let from_reader_at = Metalib::from_reader_at(&mut Cursor::new(&bytes), 0).unwrap();
+        assert_eq!(format!("{from_bytes:?}"), format!("{from_reader:?}"));
+        assert_eq!(format!("{from_reader_at:?}"), format!("{from_reader:?}"));
+
+        // Somewhere other than the start, only the metalib's own offset differs.
+        let mut embedded = vec![0xCC; 0x40];
+        embedded.extend_from_slice(&bytes);
+        let at = Metalib::from_reader_at(&mut Cursor::new(&embedded), 0x40).unwrap();
+        assert_eq!(at._offset, 0x40);
+        assert_eq!(format!("{:?}", at.metas), format!("{:?}", from_reader.metas));
+        assert_eq!(format!("{:?}", at.macros), format!("{:?}", from_reader.macros));
+        assert_eq!(export_metalib_xml(&at).unwrap(), FULL_XML);
+
+        // Trailing bytes after the metalib are ignored.
+        embedded.extend_from_slice(&[0xCC; 0x10]);
+        let from_bytes = Metalib::from_bytes(&embedded[0x40..]).unwrap();
+        assert_eq!(format!("{from_bytes:?}"), format!("{from_reader:?}"));
+    }
+
+    #[test]
+    fn string_pointers_are_body_relative() {
+        let bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        let metalib = parse(FULL_XML);
+        let mut corrupt = bytes.clone();
+        let name_ptr = 0x114 + metalib.macros[0]._offset as usize;
+        corrupt[name_ptr..name_ptr + 4].copy_from_slice(&0x7FFF_0000u32.to_le_bytes());
+
+        // Wherever the metalib sits, its strings and the offsets of its errors move with it.
+        for base in [0, 1, 0x40, 0x1003] {
+            let mut embedded = vec![0xCC; base];
+            embedded.extend_from_slice(&bytes);
+            let at = Metalib::from_reader_at(&mut Cursor::new(&embedded), base as u64).unwrap();
+            assert_eq!(export_metalib_xml(&at).unwrap(), FULL_XML, "at {base:#X}");
+            let in_place = Metalib::from_bytes(&embedded[base..]).unwrap();
+            assert_eq!(format!("{:?}", in_place.metas), format!("{:?}", at.metas));
+
+            let mut embedded = vec![0xCC; base];
+            embedded.extend_from_slice(&corrupt);
+            let mut rdr = Cursor::new(&embedded);
+            rdr.set_position(base as u64);
+            let err = read_metalib(&mut rdr).unwrap_err();
+            let parse_error = find_parse_error(&err).unwrap();
+            assert_eq!(parse_error.kind, ErrorKind::StringOob);
+            assert_eq!(parse_error.offset, (base + name_ptr) as u64);
+            assert_eq!(
+                parse_error.message,
+                format!(
+                    "Unreadable macro name pointer 0x7FFF0000: outside the string buffer \
+                     {:#X}..{:#X}",
+                    metalib.header.ptr_str_buf, metalib.header.ptr_free_str_buf
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn meta_by_name() {
+        let xml = r#"<metalib tagsetversion="1" name="cjk" version="1">
+	<struct name="账号" version="1">
+		<entry name="id" type="int"/>
+	</struct>
+	<struct name="Login" version="1">
+		<entry name="account" type="账号"/>
+	</struct>
+</metalib>
+"#;
+        let metalib = parse(xml);
+
+        assert_eq!(metalib.get_meta_by_name("Login").unwrap().name, "Login");
+        assert_eq!(metalib.get_meta_by_name("账号").unwrap().entries[0].name, "id");
+        let err = metalib.get_meta_by_name("login").unwrap_err();
+        assert_eq!(err.to_string(), "Failed to get meta by name `login`");
+        assert!(metalib.get_meta_by_name("").is_err());
+
+        assert_eq!(metalib.get_meta_by_name_ignore_case("LOGIN").unwrap().name, "Login");
+        assert!(metalib.get_meta_by_name_ignore_case("missing").is_err());
+    }
+
+    #[test]
+    fn colliding_meta_names() {
+        let mut metalib = parse(FULL_XML);
+        let packet = metalib.get_meta_by_name("Packet").unwrap()._offset;
+        let account = metalib.metas.iter_mut().find(|m| m.name == "Account").unwrap();
+        account.name = "Packet".to_string();
+        let account = account._offset;
+
+        let err = metalib.get_meta_by_name("Packet").unwrap_err().to_string();
+        assert!(err.contains(&format!("`Packet` at {packet:#X}")), "{err}");
+        assert!(err.contains(&format!("`Packet` at {account:#X}")), "{err}");
+
+        let picked = metalib.get_meta_by_name(&format!("Packet@{account:#X}")).unwrap();
+        assert_eq!(picked._offset, account);
+        assert!(metalib.get_meta_by_name("Packet@0x1").is_err());
+    }
+
+    #[test]
+    fn big_synthetic_metalib() {
+        const METAS: usize = 3000;
+        let mut xml = String::from("<metalib tagsetversion=\"1\" name=\"big\" version=\"1\">\n");
+        xml.push_str("\t<macrosgroup name=\"Group\">\n");
+        for i in 0..100 {
+            xml.push_str(&format!("\t\t<macro name=\"M{i}\" value=\"{i}\"/>\n"));
+        }
+        xml.push_str("\t</macrosgroup>\n\t<macro name=\"LOOSE\" value=\"1\"/>\n");
+        for i in 0..METAS {
+            xml.push_str(&format!("\t<struct name=\"S{i}\" version=\"1\">\n"));
+            xml.push_str("\t\t<entry name=\"v\" type=\"int\" bindmacrosgroup=\"Group\"/>\n");
+            if i > 0 {
+                xml.push_str(&format!("\t\t<entry name=\"prev\" type=\"S{}\"/>\n", i - 1));
+            }
+            xml.push_str("\t</struct>\n");
+        }
+        xml.push_str("</metalib>\n");
+
+        let metalib = parse(&xml);
+        assert_eq!(metalib.metas.len(), METAS);
+
+        let start = std::time::Instant::now();
+        let dumped = export_metalib_xml(&metalib).unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed.as_secs() < 10, "dump took {elapsed:?}");
+        assert_eq!(dumped.matches("<struct ").count(), METAS);
+
+        // The indexed lookups find what a linear search would.
+        for meta in metalib.metas.iter() {
+            for entry in meta.entries.iter().filter(|e| e.ptr_meta != INVALID_METALIB_VALUE) {
+                let linear = metalib.metas.iter().find(|m| m._offset == entry.ptr_meta as u64);
+                let found = metalib.get_meta_by_offset(entry.ptr_meta).unwrap();
+                assert!(std::ptr::eq(found, linear.unwrap()));
+            }
+            let group = metalib.get_macrogroup_by_offset(meta.entries[0].ptr_macros_group);
+            assert_eq!(group.unwrap().name, "Group");
+        }
+        assert!(metalib.get_meta_by_offset(1).is_err());
+        assert!(metalib.get_meta_by_offset(INVALID_METALIB_VALUE).is_err());
+        for macro_ in metalib.macros.iter() {
+            assert_eq!(metalib.is_macro_in_group(macro_).unwrap(), macro_.name != "LOOSE");
+        }
+    }
+
+    #[test]
+    fn buffer_strings_are_cached_by_offset() {
+        // "勇者" in GBK, then "id".
+        let body = [0xD3, 0xC2, 0xD5, 0xDF, 0, b'i', b'd', 0];
+        let reader = || BodyReader {
+            cursor: Cursor::new(&body[..]),
+            string_buffer: 0..body.len() as u64,
+            string_cache: HashMap::new(),
+            raw_bytes: HashMap::new(),
+            raw_strings: Vec::new(),
+        };
+        let gbk = ParseOptions::default();
+        let tiny = ParseOptions::default().max_string_size(2);
+        let utf8 = ParseOptions::default().string_encoding(StringEncoding::Utf8);
+
+        let mut rdr = reader();
+        assert_eq!(read_buffer_string(&mut rdr, 0, &gbk).unwrap(), "勇者");
+        assert_eq!(read_buffer_string(&mut rdr, 5, &gbk).unwrap(), "id");
+        assert_eq!(rdr.string_cache.len(), 2);
+
+        // Read again, it comes from the cache without moving the reader, even with options
+        // that would have read it differently.
+        let pos = rdr.stream_position().unwrap();
+        for options in [&gbk, &tiny, &utf8] {
+            assert_eq!(read_buffer_string(&mut rdr, 0, options).unwrap(), "勇者");
+            assert_eq!(rdr.stream_position().unwrap(), pos);
+        }
+        assert_eq!(rdr.string_cache.len(), 2);
+
+        // Which they do, read the first time.
+        assert!(read_buffer_string(&mut reader(), 0, &tiny).is_err());
+        let read = read_buffer_string(&mut reader(), 0, &utf8).unwrap();
+        assert_ne!(read, "勇者");
+    }
+
+    #[test]
+    fn shared_strings_parse_quickly() {
+        // Every struct has the same entry names and descriptions, so nearly every string
+        // pointer is one already read.
+        const METAS: usize = 2000;
+        let mut xml = String::from("<metalib tagsetversion=\"1\" name=\"shared\" version=\"1\">\n");
+        for i in 0..METAS {
+            xml.push_str(&format!("\t<struct name=\"S{i}\" version=\"1\">\n"));
+            for field in 0..20 {
+                xml.push_str(&format!(
+                    r#"		<entry name="field{field}" type="int" desc="shared field {field}"/>"#
+                ));
+                xml.push('\n');
+            }
+            xml.push_str("\t</struct>\n");
+        }
+        xml.push_str("</metalib>\n");
+        let bytes = compile_bytes(&xml, MetalibArch::Bits32);
+
+        let start = std::time::Instant::now();
+        let metalib = parse_with(&bytes, &ParseOptions::default()).unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed.as_secs() < 10, "parse took {elapsed:?}");
+        assert_eq!(metalib.metas.len(), METAS);
+        let last = &metalib.metas[METAS - 1].entries[19];
+        assert_eq!((last.name.as_str(), last.desc.as_str()), ("field19", "shared field 19"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let metalib = parse(FULL_XML);
+        let json = serde_json::to_string(&metalib).unwrap();
+        let read: Metalib = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(format!("{:?}", read.header), format!("{:?}", metalib.header));
+        assert_eq!(format!("{:?}", read.macros), format!("{:?}", metalib.macros));
+        assert_eq!(format!("{:?}", read.metas), format!("{:?}", metalib.metas));
+        assert_eq!(format!("{:?}", read.macrogroups), format!("{:?}", metalib.macrogroups));
+        assert_eq!(read.arch, metalib.arch);
+        // The lookups are rebuilt rather than serialized.
+        assert_eq!(export_metalib_xml(&read).unwrap(), FULL_XML);
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let packet = &value["metas"][2];
+        assert_eq!(packet["name"], "Packet");
+        let head = &packet["entries"][0];
+        assert_eq!(head["type_"], "STRUCT");
+        assert!(head["flag"]["bits"].is_u64());
+        let name = &packet["entries"][5];
+        assert_eq!(name["type_"], "STRING");
+        assert_eq!(name["db_flag"]["names"], serde_json::json!(["UNIQUE", "NOT_NULL"]));
+    }
+}
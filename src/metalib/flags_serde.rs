@@ -0,0 +1,79 @@
+//! Serde support for the bit flag types, which are written as
+//! `{ "bits": 3, "names": ["FIXED_SIZE", "HAS_ID"] }`. Only `bits` is read back, so
+//! unknown bits survive a round trip.
+
+use serde::de::Deserializer;
+use serde::ser::{SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+
+use super::{TDRMetaEntryDBFlags, TDRMetaEntryFlags, TDRMetaFlags};
+
+macro_rules! serde_flags {
+    ($flags:ident, $bits:ty, [$($name:ident),* $(,)?]) => {
+        impl Serialize for $flags {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut names: Vec<&str> = Vec::new();
+                $(
+                    if self.contains($flags::$name) {
+                        names.push(stringify!($name));
+                    }
+                )*
+                let mut state = serializer.serialize_struct(stringify!($flags), 2)?;
+                state.serialize_field("bits", &self.bits)?;
+                state.serialize_field("names", &names)?;
+                state.end()
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $flags {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                #[derive(Deserialize)]
+                struct Raw {
+                    bits: $bits,
+                }
+                let raw = Raw::deserialize(deserializer)?;
+                Ok($flags { bits: raw.bits })
+            }
+        }
+    };
+}
+
+serde_flags!(
+    TDRMetaFlags,
+    u32,
+    [
+        FIXED_SIZE,
+        HAS_ID,
+        RESOVLED,
+        VARIABLE,
+        STRICT_INPUT,
+        HAS_AUTOINCREMENT_ENTRY,
+        NEED_PREFIX_FOR_UNIQUENAME,
+        HAS_EXTEND_META,
+        IS_EXTEND_META,
+        UNKNOWN_FLAG_512,
+    ]
+);
+
+serde_flags!(
+    TDRMetaEntryFlags,
+    u16,
+    [
+        RESOVLED,
+        POINT_TYPE,
+        REFER_TYPE,
+        HAS_ID,
+        HAS_MAXMIN_ID,
+        FIXED_SIZE,
+        REFER_COUNT,
+        UNKNOWN_FLAG_X0080,
+        UNKNOWN_FLAG_X0100,
+        UNKNOWN_FLAG_X0200,
+    ]
+);
+
+serde_flags!(
+    TDRMetaEntryDBFlags,
+    u8,
+    [UNIQUE, NOT_NULL, EXTEND_TO_TABLE, PRIMARY_KEY, AUTO_INCREMENT]
+);
@@ -0,0 +1,377 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use anyhow::{anyhow, Result};
+use encoding::all::{BIG5_2003, GB18030, GBK, UTF_8};
+use encoding::{DecoderTrap, EncodingRef};
+
+use crate::arch::MetalibArch;
+use crate::metalib::MAX_METALIB_SIZE;
+use crate::reader_utils::MAX_STRING_SIZE;
+
+/// Options controlling how a metalib is parsed.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ParseOptions {
+    /// Maximum length in bytes of any null-terminated string read from the metalib.
+    pub max_string_size: usize,
+
+    /// Largest `size` a metalib header may claim, in bytes, checked before the body is read.
+    pub max_metalib_size: u64,
+
+    /// Encoding of the names, descriptions and other strings in the string buffer.
+    pub string_encoding: StringEncoding,
+
+    /// Keep the original bytes of strings that don't encode back to them as GBK (invalid
+    /// bytes, or another `string_encoding`) in `Metalib::raw_strings`, by record and field,
+    /// for the writer and GBK XML.
+    pub keep_raw_strings: bool,
+
+    /// Parse with this layout regardless of what the header claims or the data suggests.
+    pub force_arch: Option<MetalibArch>,
+
+    /// Only read the macro and macrogroup tables, leaving `metas` (and the id, name and
+    /// map tables) empty.
+    pub macros_only: bool,
+
+    /// Fail on any malformed field. When false, recoverable problems (bad string pointers,
+    /// unknown types, out of range macro indexes) are logged and replaced with placeholders.
+    pub strict: bool,
+
+    /// Fail if anything was worked around (only possible when not `strict`), with the
+    /// warnings as the error.
+    pub fail_on_warnings: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            max_string_size: MAX_STRING_SIZE,
+            max_metalib_size: MAX_METALIB_SIZE,
+            string_encoding: StringEncoding::default(),
+            keep_raw_strings: false,
+            force_arch: None,
+            macros_only: false,
+            strict: true,
+            fail_on_warnings: false,
+        }
+    }
+}
+
+impl ParseOptions {
+    #[allow(unused)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_string_size(mut self, max_string_size: usize) -> Self {
+        self.max_string_size = max_string_size;
+        self
+    }
+
+    pub fn max_metalib_size(mut self, max_metalib_size: u64) -> Self {
+        self.max_metalib_size = max_metalib_size;
+        self
+    }
+
+    pub fn string_encoding(mut self, string_encoding: StringEncoding) -> Self {
+        self.string_encoding = string_encoding;
+        self
+    }
+
+    pub fn keep_raw_strings(mut self, keep_raw_strings: bool) -> Self {
+        self.keep_raw_strings = keep_raw_strings;
+        self
+    }
+
+    pub fn force_arch(mut self, arch: MetalibArch) -> Self {
+        self.force_arch = Some(arch);
+        self
+    }
+
+    pub fn macros_only(mut self, macros_only: bool) -> Self {
+        self.macros_only = macros_only;
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn fail_on_warnings(mut self, fail_on_warnings: bool) -> Self {
+        self.fail_on_warnings = fail_on_warnings;
+        self
+    }
+}
+
+/// Character encoding of the strings in a metalib.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StringEncoding {
+    /// What the tdr compiler writes for mainland builds.
+    #[default]
+    Gbk,
+    Gb18030,
+    /// Seen in metalibs from Taiwanese builds.
+    Big5,
+    Utf8,
+}
+
+impl StringEncoding {
+    pub fn from_name(name: &str) -> Result<StringEncoding> {
+        match name.to_ascii_lowercase().as_str() {
+            "gbk" => Ok(StringEncoding::Gbk),
+            "gb18030" => Ok(StringEncoding::Gb18030),
+            "big5" => Ok(StringEncoding::Big5),
+            "utf-8" | "utf8" => Ok(StringEncoding::Utf8),
+            _ => Err(anyhow!(
+                "Unknown encoding `{name}`, expected gbk, gb18030, big5 or utf-8"
+            )),
+        }
+    }
+
+    /// Decodes `bytes`, replacing anything invalid with U+FFFD.
+    pub fn decode(self, bytes: &[u8]) -> Result<String> {
+        let encoding: EncodingRef = match self {
+            StringEncoding::Gbk => GBK,
+            StringEncoding::Gb18030 => GB18030,
+            StringEncoding::Big5 => BIG5_2003,
+            StringEncoding::Utf8 => UTF_8,
+        };
+        encoding
+            .decode(bytes, DecoderTrap::Replace)
+            .map_err(|err| anyhow!("Error trying to decode: {err}"))
+    }
+}
+
+/// Character encoding of the written XML document.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum XmlEncoding {
+    #[default]
+    Utf8,
+    /// What the official tdr tools write.
+    Gbk,
+}
+
+impl XmlEncoding {
+    pub fn from_name(name: &str) -> Result<XmlEncoding> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(XmlEncoding::Utf8),
+            "gbk" => Ok(XmlEncoding::Gbk),
+            _ => Err(anyhow!("Unknown encoding `{name}`, expected utf-8 or gbk")),
+        }
+    }
+
+    /// Name used in the XML declaration.
+    pub fn xml_name(self) -> &'static str {
+        match self {
+            XmlEncoding::Utf8 => "UTF-8",
+            XmlEncoding::Gbk => "GBK",
+        }
+    }
+}
+
+/// Options controlling how a parsed metalib is written out as XML.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExportOptions {
+    /// String used for one level of indentation.
+    pub indent: String,
+
+    /// Put each attribute of an `<entry>` tag on its own line once the tag is longer than
+    /// this many characters.
+    pub wrap_attrs: Option<usize>,
+
+    /// Encoding of the written document.
+    pub encoding: XmlEncoding,
+
+    /// Only write the metas with these names, if any are given.
+    pub metas: Vec<String>,
+
+    /// Also write everything the selected metas depend on.
+    pub with_deps: bool,
+
+    /// Write the metalib as it was at this version: metas and entries added later are left
+    /// out, along with the entries that use them as a type or a `refer`, `select` or
+    /// `sizeinfo` field, and the metalib's `version` is lowered to it. A meta attribute
+    /// (`sizeinfo`, `versionindicator`, ...) that refers to a left out field is an error.
+    pub cut_version: Option<i32>,
+
+    /// Fail on a field reference (`refer`, `versionindicator`, ...) that no field starts at.
+    /// When false, it's logged and written as the raw offset (`@0x1C`) instead.
+    pub strict: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            indent: "\t".to_string(),
+            wrap_attrs: None,
+            encoding: XmlEncoding::Utf8,
+            metas: Vec::new(),
+            with_deps: false,
+            cut_version: None,
+            strict: true,
+        }
+    }
+}
+
+impl ExportOptions {
+    #[allow(unused)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn indent(mut self, indent: impl Into<String>) -> Self {
+        self.indent = indent.into();
+        self
+    }
+
+    pub fn wrap_attrs(mut self, width: usize) -> Self {
+        self.wrap_attrs = Some(width);
+        self
+    }
+
+    pub fn encoding(mut self, encoding: XmlEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn metas(mut self, names: Vec<String>, with_deps: bool) -> Self {
+        self.metas = names;
+        self.with_deps = with_deps;
+        self
+    }
+
+    pub fn cut_version(mut self, version: i32) -> Self {
+        self.cut_version = Some(version);
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Returns the indentation string for the given nesting depth.
+    pub fn indent_for(&self, depth: usize) -> String {
+        self.indent.repeat(depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::metalib::read_metalib;
+    use crate::test_support::{compile_bytes, parse_with, FULL_XML};
+    use crate::xml::{export_metalib_xml, export_metalib_xml_with};
+
+    #[test]
+    fn parse_defaults() {
+        let options = ParseOptions::default();
+        assert_eq!(options.max_string_size, MAX_STRING_SIZE);
+        assert_eq!(options.max_metalib_size, MAX_METALIB_SIZE);
+        assert_eq!(options.string_encoding, StringEncoding::Gbk);
+        assert!(!options.keep_raw_strings);
+        assert_eq!(options.force_arch, None);
+        assert!(!options.macros_only);
+        assert!(options.strict);
+        assert!(!options.fail_on_warnings);
+    }
+
+    #[test]
+    fn export_defaults() {
+        let options = ExportOptions::default();
+        assert_eq!(options.indent, "\t");
+        assert_eq!(options.wrap_attrs, None);
+        assert_eq!(options.encoding, XmlEncoding::Utf8);
+        assert!(options.metas.is_empty());
+        assert!(!options.with_deps);
+        assert_eq!(options.cut_version, None);
+        assert!(options.strict);
+        assert_eq!(options.indent_for(2), "\t\t");
+    }
+
+    #[test]
+    fn simple_functions_use_the_defaults() {
+        let bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        let plain = read_metalib(&mut Cursor::new(&bytes)).unwrap();
+        let with = parse_with(&bytes, &ParseOptions::default()).unwrap();
+
+        let xml = export_metalib_xml(&plain).unwrap();
+        assert_eq!(
+            xml,
+            export_metalib_xml_with(&with, &ExportOptions::default()).unwrap()
+        );
+        assert_eq!(xml, FULL_XML);
+    }
+
+    #[test]
+    fn builders_set_their_field() {
+        let options = ParseOptions::new()
+            .max_string_size(16)
+            .max_metalib_size(1024)
+            .string_encoding(StringEncoding::Big5)
+            .keep_raw_strings(true)
+            .force_arch(MetalibArch::Bits64)
+            .macros_only(true)
+            .strict(false)
+            .fail_on_warnings(true);
+        assert_eq!(options.max_string_size, 16);
+        assert_eq!(options.max_metalib_size, 1024);
+        assert_eq!(options.string_encoding, StringEncoding::Big5);
+        assert!(options.keep_raw_strings);
+        assert_eq!(options.force_arch, Some(MetalibArch::Bits64));
+        assert!(options.macros_only);
+        assert!(!options.strict);
+        assert!(options.fail_on_warnings);
+
+        let options = ExportOptions::new()
+            .indent("  ")
+            .wrap_attrs(80)
+            .encoding(XmlEncoding::Gbk)
+            .metas(vec!["Packet".to_string()], true)
+            .cut_version(2)
+            .strict(false);
+        assert_eq!(options.indent_for(2), "    ");
+        assert_eq!(options.wrap_attrs, Some(80));
+        assert_eq!(options.encoding, XmlEncoding::Gbk);
+        assert_eq!(options.metas, ["Packet"]);
+        assert!(options.with_deps);
+        assert_eq!(options.cut_version, Some(2));
+        assert!(!options.strict);
+    }
+
+    #[test]
+    fn encoding_names() {
+        assert_eq!(
+            StringEncoding::from_name("UTF8").unwrap(),
+            StringEncoding::Utf8
+        );
+        assert_eq!(
+            StringEncoding::from_name("gb18030").unwrap(),
+            StringEncoding::Gb18030
+        );
+        assert!(StringEncoding::from_name("latin1").is_err());
+        assert_eq!(XmlEncoding::from_name("GBK").unwrap().xml_name(), "GBK");
+        assert!(XmlEncoding::from_name("big5").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn options_serialize() {
+        let options = ParseOptions::default().strict(false);
+        let json = serde_json::to_string(&options).unwrap();
+        let read: ParseOptions = serde_json::from_str(&json).unwrap();
+        assert_eq!(format!("{read:?}"), format!("{options:?}"));
+
+        let options = ExportOptions::default().cut_version(2);
+        let json = serde_json::to_string(&options).unwrap();
+        let read: ExportOptions = serde_json::from_str(&json).unwrap();
+        assert_eq!(format!("{read:?}"), format!("{options:?}"));
+    }
+}
@@ -1,17 +1,56 @@
 use anyhow::{anyhow, Result};
 use byteorder::{LittleEndian, ReadBytesExt};
 // use byteorder::{ReadBytesExt, LittleEndian};
-use encoding::all::GBK;
-use encoding::{DecoderTrap, Encoding};
+use std::fmt;
+use std::io::{ErrorKind, SeekFrom};
 
-const MAX_STRING_SIZE: usize = 4 * 1024 * 1024;
+use crate::options::StringEncoding;
+
+/// Default limit on the length of null-terminated strings.
+pub const MAX_STRING_SIZE: usize = 4 * 1024 * 1024;
+
+/// How many bytes `read_until_byte` reads at a time. Most strings in a metalib are names,
+/// well under this.
+const READ_CHUNK_SIZE: usize = 256;
+
+/// Why a terminated string couldn't be read. `start` is the reader position it started at.
+#[derive(Debug)]
+pub enum StringReadError {
+    /// The data ended after `read` bytes, before the terminator.
+    Eof { start: u64, read: usize },
+    /// No terminator in the `max_size` bytes the string may take up.
+    TooLong { start: u64, max_size: usize },
+}
+
+impl fmt::Display for StringReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StringReadError::Eof { start, read } => write!(
+                f,
+                "the data ends {read} bytes into the string at {start:#X}, before its terminator"
+            ),
+            StringReadError::TooLong { start, max_size } => write!(
+                f,
+                "no terminator within {max_size} bytes of the string at {start:#X}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StringReadError {}
 
 pub trait StringReadExt {
     fn read_until_byte(&mut self, byte: u8, max_size: usize) -> Result<Vec<u8>>;
     fn read_fixed_size_utf8_string(&mut self, length: u32) -> Result<String>;
-    fn read_null_terminated_utf8_string(&mut self) -> Result<String>;
-    fn read_null_terminated_gbk_string(&mut self) -> Result<String>;
-    fn read_null_terminated_gbk_string_i32_offset_pointer(&mut self) -> Result<String>;
+    fn read_null_terminated_utf8_string(&mut self, max_size: usize) -> Result<String>;
+    fn read_null_terminated_gbk_string(&mut self, max_size: usize) -> Result<String>;
+    fn read_null_terminated_string(
+        &mut self,
+        max_size: usize,
+        encoding: StringEncoding,
+    ) -> Result<String>;
+    fn read_null_terminated_utf16le_string(&mut self, max_size: usize) -> Result<String>;
+    fn read_fixed_size_utf16le_string(&mut self, len_units: u32) -> Result<String>;
 }
 
 impl<T> StringReadExt for T
@@ -19,22 +58,35 @@ where
     T: std::io::Read,
     T: std::io::Seek,
 {
-    // This mimics the read_until method available on BufRead,
-    // but is intentionally reimplemented (poorly) here on a regular
-    // std::io::Read + std::io::Seek reader.
+    // This mimics the read_until method available on BufRead on a regular
+    // std::io::Read + std::io::Seek reader: it reads in chunks, then seeks back
+    // to just past the delimiter. At most `max_size` bytes are read, delimiter
+    // included.
     fn read_until_byte(&mut self, delimiter: u8, max_size: usize) -> Result<Vec<u8>> {
+        let start = self.stream_position()?;
         let mut data = Vec::<u8>::new();
+        let mut chunk = [0; READ_CHUNK_SIZE];
 
-        for _index in 0..max_size {
-            let mut buf = [0; 1];
-            self.read_exact(&mut buf)?;
-            if buf[0] == delimiter {
+        while data.len() < max_size {
+            let want = (max_size - data.len()).min(READ_CHUNK_SIZE);
+            let read = match self.read(&mut chunk[..want]) {
+                Ok(0) => {
+                    let read = data.len();
+                    return Err(StringReadError::Eof { start, read }.into());
+                }
+                Ok(read) => read,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if let Some(end) = chunk[..read].iter().position(|&b| b == delimiter) {
+                data.extend_from_slice(&chunk[..end]);
+                _ = self.seek(SeekFrom::Current(end as i64 + 1 - read as i64))?;
                 return Ok(data);
             }
-            data.extend(buf);
+            data.extend_from_slice(&chunk[..read]);
         }
 
-        Err(anyhow!("Read MAX_STRING_SIZE bytes!"))
+        Err(StringReadError::TooLong { start, max_size }.into())
     }
 
     fn read_fixed_size_utf8_string(&mut self, length: u32) -> Result<String> {
@@ -47,8 +99,8 @@ where
         Ok(String::from_utf8_lossy(&buf[0..null_position]).into())
     }
 
-    fn read_null_terminated_utf8_string(&mut self) -> Result<String> {
-        let buf = self.read_until_byte(b'\x00', MAX_STRING_SIZE)?;
+    fn read_null_terminated_utf8_string(&mut self, max_size: usize) -> Result<String> {
+        let buf = self.read_until_byte(b'\x00', max_size)?;
 
         // Terminate at NULL byte
         let null_position = buf.iter().position(|&c| c == b'\0').unwrap_or(buf.len());
@@ -56,25 +108,253 @@ where
         Ok(String::from_utf8_lossy(&buf[0..null_position]).into())
     }
 
-    fn read_null_terminated_gbk_string(&mut self) -> Result<String> {
-        let buf = self.read_until_byte(b'\x00', MAX_STRING_SIZE)?;
+    fn read_null_terminated_gbk_string(&mut self, max_size: usize) -> Result<String> {
+        self.read_null_terminated_string(max_size, StringEncoding::Gbk)
+    }
+
+    fn read_null_terminated_string(
+        &mut self,
+        max_size: usize,
+        encoding: StringEncoding,
+    ) -> Result<String> {
+        let buf = self.read_until_byte(b'\x00', max_size)?;
+        encoding.decode(&buf)
+    }
+
+    // `max_size` is in bytes, like the others. Unpaired surrogates become U+FFFD.
+    fn read_null_terminated_utf16le_string(&mut self, max_size: usize) -> Result<String> {
+        let start = self.stream_position()?;
+        let mut units = Vec::<u16>::new();
+
+        for _index in 0..max_size / 2 {
+            let unit = match self.read_u16::<LittleEndian>() {
+                Ok(unit) => unit,
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                    let read = units.len() * 2;
+                    return Err(StringReadError::Eof { start, read }.into());
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if unit == 0 {
+                return Ok(String::from_utf16_lossy(&units));
+            }
+            units.push(unit);
+        }
+
+        Err(StringReadError::TooLong { start, max_size }.into())
+    }
+
+    // `len_units` UTF-16 code units, up to the first NUL. Unpaired surrogates become U+FFFD.
+    fn read_fixed_size_utf16le_string(&mut self, len_units: u32) -> Result<String> {
+        if len_units as usize > MAX_STRING_SIZE {
+            return Err(anyhow!(
+                "A {len_units} unit string is longer than the {MAX_STRING_SIZE} unit limit"
+            ));
+        }
+        let mut buf = vec![0; len_units as usize * 2];
+        self.read_exact(&mut buf)?;
+
+        let units: Vec<u16> = buf
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect();
+        Ok(String::from_utf16_lossy(&units))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A reader that counts the reads it's asked for.
+    struct CountingReader {
+        inner: Cursor<Vec<u8>>,
+        reads: usize,
+    }
 
-        match GBK.decode(&buf, DecoderTrap::Replace) {
-            Ok(s) => Ok(s),
-            Err(err) => Err(anyhow::anyhow!("Error trying to decode: {err}")),
+    impl std::io::Read for CountingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.reads += 1;
+            self.inner.read(buf)
         }
     }
 
-    fn read_null_terminated_gbk_string_i32_offset_pointer(&mut self) -> Result<String> {
-        let offset = self.read_i32::<LittleEndian>()?;
-        if offset == -1 {
-            return Ok("".to_string());
+    impl std::io::Seek for CountingReader {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
         }
+    }
+
+    fn string_error(err: anyhow::Error) -> StringReadError {
+        err.downcast().expect("should be a StringReadError")
+    }
+
+    #[test]
+    fn read_until_byte_positions() {
+        let mut rdr = Cursor::new(b"xxabc\0\0def\0".to_vec());
+        rdr.set_position(2);
+        assert_eq!(rdr.read_until_byte(0, 64).unwrap(), b"abc");
+        assert_eq!(rdr.position(), 6);
+        assert_eq!(rdr.read_until_byte(0, 64).unwrap(), b"");
+        assert_eq!(rdr.position(), 7);
+        // Exactly as long as the limit, terminator included.
+        assert_eq!(rdr.read_until_byte(0, 4).unwrap(), b"def");
+        assert_eq!(rdr.position(), 11);
+
+        // Terminators on both sides of a chunk boundary.
+        for len in [
+            READ_CHUNK_SIZE - 1,
+            READ_CHUNK_SIZE,
+            READ_CHUNK_SIZE + 1,
+            1000,
+        ] {
+            let mut data = vec![b'a'; len];
+            data.extend(b"\0tail");
+            let mut rdr = Cursor::new(data);
+            assert_eq!(rdr.read_until_byte(0, MAX_STRING_SIZE).unwrap().len(), len);
+            assert_eq!(rdr.position(), len as u64 + 1);
+        }
+    }
+
+    #[test]
+    fn read_until_byte_eof_and_limit() {
+        // The data ends first: how far it got, from where.
+        let mut rdr = Cursor::new(b"\0\0abcdef".to_vec());
+        rdr.set_position(2);
+        match string_error(rdr.read_until_byte(0, 64).unwrap_err()) {
+            StringReadError::Eof { start, read } => assert_eq!((start, read), (2, 6)),
+            err => panic!("{err}"),
+        }
+        let mut rdr = Cursor::new(Vec::new());
+        let err = rdr.read_until_byte(0, 64).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the data ends 0 bytes into the string at 0x0, before its terminator"
+        );
+
+        // The limit comes first, even with the terminator just past it.
+        let mut data = vec![0xFF; 0x10];
+        data.extend(vec![b'a'; 600]);
+        data.push(0);
+        let mut rdr = Cursor::new(data);
+        rdr.set_position(0x10);
+        match string_error(rdr.read_until_byte(0, 600).unwrap_err()) {
+            StringReadError::TooLong { start, max_size } => {
+                assert_eq!((start, max_size), (0x10, 600))
+            }
+            err => panic!("{err}"),
+        }
+        rdr.set_position(0x10);
+        let err = rdr.read_until_byte(0, 600).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no terminator within 600 bytes of the string at 0x10"
+        );
+        rdr.set_position(0x10);
+        assert_eq!(rdr.read_until_byte(0, 601).unwrap().len(), 600);
+    }
+
+    #[test]
+    fn read_until_byte_reads_in_chunks() {
+        let mut data = vec![b'a'; 10_000];
+        data.push(0);
+        let mut rdr = CountingReader {
+            inner: Cursor::new(data),
+            reads: 0,
+        };
+        assert_eq!(
+            rdr.read_until_byte(0, MAX_STRING_SIZE).unwrap().len(),
+            10_000
+        );
+        assert_eq!(rdr.reads, 10_001usize.div_ceil(READ_CHUNK_SIZE));
+    }
+
+    fn utf16le(units: &[u16]) -> Vec<u8> {
+        units.iter().flat_map(|unit| unit.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn utf16le_strings() {
+        let mut data: Vec<u16> = "勇者".encode_utf16().collect();
+        data.extend([0, u16::from(b'x')]);
+        let mut rdr = Cursor::new(utf16le(&data));
+        assert_eq!(rdr.read_null_terminated_utf16le_string(64).unwrap(), "勇者");
+        assert_eq!(rdr.position(), 6);
+
+        // Unpaired surrogates, high and low.
+        let mut rdr = Cursor::new(utf16le(&[0xD800, u16::from(b'a'), 0xDC00, 0]));
+        assert_eq!(
+            rdr.read_null_terminated_utf16le_string(64).unwrap(),
+            "\u{FFFD}a\u{FFFD}"
+        );
+    }
+
+    #[test]
+    fn utf16le_fixed_strings() {
+        // ASCII filling the whole field, then CJK stopped by a NUL in the middle of it.
+        let mut data = utf16le(&"name".encode_utf16().collect::<Vec<_>>());
+        let mut cjk: Vec<u16> = "账号".encode_utf16().collect();
+        cjk.extend([0, u16::from(b'x'), 0, 0]);
+        data.extend(utf16le(&cjk));
+        let mut rdr = Cursor::new(data);
+        assert_eq!(rdr.read_fixed_size_utf16le_string(4).unwrap(), "name");
+        assert_eq!(rdr.position(), 8);
+        // The whole field is consumed, past the NUL.
+        assert_eq!(rdr.read_fixed_size_utf16le_string(6).unwrap(), "账号");
+        assert_eq!(rdr.position(), 20);
+        assert_eq!(rdr.read_fixed_size_utf16le_string(0).unwrap(), "");
+
+        let err = rdr
+            .read_fixed_size_utf16le_string(MAX_STRING_SIZE as u32 + 1)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "A {} unit string is longer than the {MAX_STRING_SIZE} unit limit",
+                MAX_STRING_SIZE + 1
+            )
+        );
+    }
+
+    #[test]
+    fn utf16le_odd_length_streams() {
+        // A stray byte after the last whole unit, with no NUL before it.
+        let mut data = utf16le(&[u16::from(b'a'), u16::from(b'b')]);
+        data.push(0);
+        let mut rdr = Cursor::new(data.clone());
+        let err = rdr.read_null_terminated_utf16le_string(64).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the data ends 4 bytes into the string at 0x0, before its terminator"
+        );
+
+        // A fixed field needs both bytes of its last unit.
+        let mut rdr = Cursor::new(data.clone());
+        assert!(rdr.read_fixed_size_utf16le_string(3).is_err());
+        let mut rdr = Cursor::new(data);
+        assert_eq!(rdr.read_fixed_size_utf16le_string(2).unwrap(), "ab");
+        assert_eq!(rdr.position(), 4);
+    }
+
+    #[test]
+    fn utf16le_string_limits() {
+        let mut rdr = Cursor::new(utf16le(&[u16::from(b'a'); 8]));
+        let err = rdr.read_null_terminated_utf16le_string(64).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the data ends 16 bytes into the string at 0x0, before its terminator"
+        );
 
-        let pos = self.stream_position()?;
-        _ = self.seek(std::io::SeekFrom::Start(offset as u64))?;
-        let s = self.read_null_terminated_gbk_string()?;
-        _ = self.seek(std::io::SeekFrom::Start(pos))?;
-        Ok(s)
+        let mut rdr = Cursor::new(utf16le(&[u16::from(b'a'), u16::from(b'b'), 0]));
+        let err = rdr.read_null_terminated_utf16le_string(4).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no terminator within 4 bytes of the string at 0x0"
+        );
+        let mut rdr = Cursor::new(utf16le(&[u16::from(b'a'), u16::from(b'b'), 0]));
+        assert_eq!(rdr.read_null_terminated_utf16le_string(6).unwrap(), "ab");
     }
 }
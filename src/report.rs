@@ -0,0 +1,255 @@
+//! Warnings from lenient parsing, collected into a [`ParseReport`] by
+//! [`crate::metalib::read_metalib_with_report`] as well as logged.
+
+use log::warn;
+use std::cell::RefCell;
+use std::fmt;
+use std::fmt::Write as _;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::export::json_string;
+
+/// What a [`ParseWarning`] is about.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum WarningCategory {
+    /// A header field that failed its check.
+    Header,
+    /// A string that couldn't be read, replaced by a placeholder.
+    String,
+    /// A type value TDR doesn't define.
+    Type,
+    /// A pointer or table outside the body.
+    Pointer,
+    /// A macro index outside the macro table.
+    MacroIndex,
+    /// Any other field holding a value TDR doesn't define.
+    Value,
+    /// Metas sharing a name.
+    Name,
+}
+
+impl WarningCategory {
+    pub fn code(self) -> &'static str {
+        match self {
+            WarningCategory::Header => "header",
+            WarningCategory::String => "string",
+            WarningCategory::Type => "type",
+            WarningCategory::Pointer => "pointer",
+            WarningCategory::MacroIndex => "macro_index",
+            WarningCategory::Value => "value",
+            WarningCategory::Name => "name",
+        }
+    }
+}
+
+/// Something wrong that lenient parsing worked around.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ParseWarning {
+    pub category: WarningCategory,
+    /// The message that was logged.
+    pub message: String,
+    /// File offset of the offending record, if there is one.
+    pub offset: Option<u64>,
+    pub meta: Option<String>,
+    pub entry: Option<String>,
+    /// Whether `offset` is still relative to the body rather than the file.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    in_body: bool,
+}
+
+impl ParseWarning {
+    pub fn new(category: WarningCategory, message: impl Into<String>) -> Self {
+        ParseWarning {
+            category,
+            message: message.into(),
+            offset: None,
+            meta: None,
+            entry: None,
+            in_body: false,
+        }
+    }
+
+    /// Places the warning at `offset` in the body, which the report turns into a file offset.
+    pub(crate) fn at(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self.in_body = true;
+        self
+    }
+
+    /// Places the warning at file offset `offset`.
+    pub(crate) fn at_file(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self.in_body = false;
+        self
+    }
+
+    pub(crate) fn meta(mut self, name: &str) -> Self {
+        self.meta = Some(name.to_string());
+        self
+    }
+
+    pub(crate) fn entry(mut self, name: &str) -> Self {
+        self.entry = Some(name.to_string());
+        self
+    }
+
+    fn to_json(&self) -> String {
+        let optional = |value: Option<&String>| value.map_or("null".to_string(), |v| json_string(v));
+        format!(
+            "{{\"category\": {}, \"offset\": {}, \"meta\": {}, \"entry\": {}, \"message\": {}}}",
+            json_string(self.category.code()),
+            self.offset.map_or("null".to_string(), |offset| offset.to_string()),
+            optional(self.meta.as_ref()),
+            optional(self.entry.as_ref()),
+            json_string(&self.message)
+        )
+    }
+}
+
+/// Everything lenient parsing worked around, in the order it was found.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ParseReport {
+    pub warnings: Vec<ParseWarning>,
+}
+
+impl ParseReport {
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    /// Turns the body offsets of the warnings into file offsets, for a body at `base`.
+    pub(crate) fn rebase(&mut self, base: u64) {
+        for warning in self.warnings.iter_mut().filter(|warning| warning.in_body) {
+            warning.offset = warning.offset.map(|offset| offset + base);
+            warning.in_body = false;
+        }
+    }
+
+    /// Writes the warnings as a JSON array.
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("[");
+        for (i, warning) in self.warnings.iter().enumerate() {
+            let separator = if i == 0 { "" } else { ", " };
+            _ = write!(&mut out, "{separator}{}", warning.to_json());
+        }
+        out.push(']');
+        out
+    }
+}
+
+// A report is also the error when warnings should fail the parse.
+impl fmt::Display for ParseReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Parsing gave {} warnings", self.warnings.len())
+    }
+}
+
+impl std::error::Error for ParseReport {}
+
+thread_local! {
+    /// Warnings of the parse running on this thread, if it's collecting them.
+    static COLLECTED: RefCell<Option<Vec<ParseWarning>>> = const { RefCell::new(None) };
+}
+
+/// Runs `parse`, collecting the warnings it reports into a [`ParseReport`].
+pub(crate) fn collect_warnings<R>(parse: impl FnOnce() -> R) -> (R, ParseReport) {
+    let outer = COLLECTED.replace(Some(Vec::new()));
+    let result = parse();
+    let warnings = COLLECTED.replace(outer).unwrap_or_default();
+    (result, ParseReport { warnings })
+}
+
+/// Logs `warning` and keeps it for the report, if one is being collected.
+pub(crate) fn report_warning(warning: ParseWarning) {
+    warn!("{}", warning.message);
+    COLLECTED.with_borrow_mut(|collected| {
+        if let Some(collected) = collected {
+            collected.push(warning);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_only_while_asked() {
+        // Outside a collection the warning is only logged.
+        report_warning(ParseWarning::new(WarningCategory::Value, "dropped"));
+
+        let ((), outer) = collect_warnings(|| {
+            report_warning(ParseWarning::new(WarningCategory::Header, "outer").at_file(2));
+            let ((), inner) = collect_warnings(|| {
+                report_warning(ParseWarning::new(WarningCategory::Type, "inner").at(0x10));
+            });
+            assert_eq!(inner.warnings.len(), 1);
+            assert_eq!(inner.warnings[0].message, "inner");
+            report_warning(ParseWarning::new(WarningCategory::Name, "after"));
+        });
+        let messages: Vec<&str> = outer.warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(messages, ["outer", "after"]);
+    }
+
+    #[test]
+    fn rebase_moves_body_offsets_only() {
+        let mut report = ParseReport {
+            warnings: vec![
+                ParseWarning::new(WarningCategory::Header, "header").at_file(2),
+                ParseWarning::new(WarningCategory::Pointer, "body").at(0x10),
+                ParseWarning::new(WarningCategory::String, "nowhere"),
+            ],
+        };
+        report.rebase(0x114);
+        report.rebase(0x114);
+        let offsets: Vec<Option<u64>> = report.warnings.iter().map(|w| w.offset).collect();
+        assert_eq!(offsets, [Some(2), Some(0x124), None]);
+    }
+
+    #[test]
+    fn json() {
+        let report = ParseReport {
+            warnings: vec![
+                ParseWarning::new(WarningCategory::MacroIndex, "Macro \"7\" isn't defined")
+                    .at_file(0x200)
+                    .meta("Packet")
+                    .entry("body"),
+                ParseWarning::new(WarningCategory::Header, "size"),
+            ],
+        };
+        assert_eq!(
+            report.to_json(),
+            "[{\"category\": \"macro_index\", \"offset\": 512, \"meta\": \"Packet\", \
+             \"entry\": \"body\", \"message\": \"Macro \\\"7\\\" isn't defined\"}, \
+             {\"category\": \"header\", \"offset\": null, \"meta\": null, \"entry\": null, \
+             \"message\": \"size\"}]"
+        );
+        assert_eq!(ParseReport::default().to_json(), "[]");
+        assert_eq!(report.to_string(), "Parsing gave 2 warnings");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_matches_json() {
+        let report = ParseReport {
+            warnings: vec![ParseWarning::new(WarningCategory::MacroIndex, "index")
+                .at_file(0x200)
+                .meta("Packet")],
+        };
+        let serialized = serde_json::to_value(&report).unwrap();
+        let handwritten: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(serialized["warnings"], handwritten);
+
+        let back: ParseReport = serde_json::from_value(serialized).unwrap();
+        assert_eq!(back.warnings[0].category, WarningCategory::MacroIndex);
+        assert_eq!(back.warnings[0].offset, Some(0x200));
+        assert_eq!(back.warnings[0].meta.as_deref(), Some("Packet"));
+        assert_eq!(back.warnings[0].entry, None);
+    }
+}
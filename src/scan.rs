@@ -0,0 +1,74 @@
+//! Finds every metalib embedded in a larger binary (e.g. a game executable).
+//!
+//! Candidates are positions where the header magic appears. A candidate only counts if its
+//! header is plausible and the whole `header.size` region parses; the scan then resumes
+//! after that region, so nothing inside an accepted metalib is reported twice.
+
+use std::io::Cursor;
+
+use crate::metalib::{
+    read_metalib_header, read_metalib_with, validate_header, HeaderLayout, Metalib, METALIB_MAGIC,
+};
+use crate::options::ParseOptions;
+
+/// A metalib found by [`scan_metalibs`].
+pub struct ScanHit {
+    /// Offset of the header within the scanned data.
+    pub offset: u64,
+    pub metalib: Metalib,
+}
+
+/// Returns true if the header at the start of `data` passes [`validate_header`] and its
+/// metalib fits.
+fn is_plausible_header(data: &[u8]) -> bool {
+    let header = match read_metalib_header(&mut Cursor::new(data)) {
+        Ok(header) => header,
+        Err(_) => return false,
+    };
+    validate_header(&header, 0, true).is_ok() && header.size as usize <= data.len()
+}
+
+/// Bytes scanned between calls to the progress callback.
+const PROGRESS_INTERVAL: usize = 1 << 20;
+
+/// Scans `data` for embedded metalibs, in file order.
+#[allow(unused)]
+pub fn scan_metalibs(data: &[u8], options: &ParseOptions) -> Vec<ScanHit> {
+    scan_metalibs_with_progress(data, options, |_, _| {})
+}
+
+/// Same as [`scan_metalibs`], calling `progress` with the bytes scanned so far and the
+/// number of metalibs found after roughly every megabyte.
+pub fn scan_metalibs_with_progress(
+    data: &[u8],
+    options: &ParseOptions,
+    mut progress: impl FnMut(usize, usize),
+) -> Vec<ScanHit> {
+    let magic = METALIB_MAGIC.to_le_bytes();
+    let mut hits = Vec::new();
+    let mut pos = 0;
+    let mut next_report = PROGRESS_INTERVAL;
+    while pos + HeaderLayout::min_size() as usize <= data.len() {
+        if pos >= next_report {
+            progress(pos, hits.len());
+            next_report = pos + PROGRESS_INTERVAL;
+        }
+        if data[pos..pos + magic.len()] != magic || !is_plausible_header(&data[pos..]) {
+            pos += 1;
+            continue;
+        }
+
+        match read_metalib_with(&mut Cursor::new(&data[pos..]), options) {
+            Ok(metalib) => {
+                let size = metalib.header.size as usize;
+                hits.push(ScanHit {
+                    offset: pos as u64,
+                    metalib,
+                });
+                pos += size;
+            }
+            Err(_) => pos += 1,
+        }
+    }
+    hits
+}
@@ -0,0 +1,243 @@
+//! Picking a subset of metas to dump (`dump --meta <name>`), and the parts of them that
+//! existed at a version (`--cut-version`).
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeSet;
+
+use crate::metalib::{Metalib, SizeSource, TDRMeta, TDRMetaEntry, INVALID_METALIB_VALUE};
+
+/// Most suggestions listed when a name isn't found.
+const MAX_SUGGESTIONS: usize = 5;
+
+/// Metas, macrogroups and ungrouped macros to include in a partial dump, by file offset.
+#[derive(Clone, Debug, Default)]
+pub struct MetaSelection {
+    pub metas: BTreeSet<u64>,
+    pub macrogroups: BTreeSet<u64>,
+    pub macros: BTreeSet<u64>,
+}
+
+impl MetaSelection {
+    /// Selects the metas named in `names`. With `with_deps`, every struct they reference
+    /// (transitively), every macrogroup bound to their entries and every macro used for
+    /// a count, id or version is selected too.
+    pub fn resolve(metalib: &Metalib, names: &[String], with_deps: bool) -> Result<MetaSelection> {
+        let mut selection = MetaSelection::default();
+        let mut pending: Vec<&TDRMeta> = Vec::new();
+        for name in names {
+            let meta = metalib.get_meta_by_name(name).map_err(|err| {
+                if metalib.metas.iter().any(|meta| meta.name == *name) {
+                    // Found, but not uniquely.
+                    return err;
+                }
+                let suggestions =
+                    close_matches(name, metalib.metas.iter().map(|m| m.name.as_str()));
+                if suggestions.is_empty() {
+                    anyhow!("No struct or union named `{name}`")
+                } else {
+                    anyhow!(
+                        "No struct or union named `{name}`, did you mean: {}?",
+                        suggestions.join(", ")
+                    )
+                }
+            })?;
+            if selection.metas.insert(meta._offset) {
+                pending.push(meta);
+            }
+        }
+
+        if !with_deps {
+            return Ok(selection);
+        }
+        while let Some(meta) = pending.pop() {
+            selection.add_macro(metalib, meta.idx_version)?;
+            for entry in meta.entries.iter() {
+                for idx in [
+                    entry.idx_count,
+                    entry.idx_id,
+                    entry.idx_version,
+                    entry.idx_custom_h_unit_size,
+                ] {
+                    selection.add_macro(metalib, idx)?;
+                }
+                if entry.ptr_meta != INVALID_METALIB_VALUE {
+                    let referenced = metalib.get_meta_by_offset(entry.ptr_meta)?;
+                    if selection.metas.insert(referenced._offset) {
+                        pending.push(referenced);
+                    }
+                }
+                if entry.ptr_macros_group != INVALID_METALIB_VALUE {
+                    let group = metalib.get_macrogroup_by_offset(entry.ptr_macros_group)?;
+                    selection.macrogroups.insert(group._offset);
+                }
+            }
+        }
+        Ok(selection)
+    }
+
+    fn add_macro(&mut self, metalib: &Metalib, idx: i32) -> Result<()> {
+        if idx != INVALID_METALIB_VALUE {
+            let macro_ = metalib
+                .macros
+                .get(idx as usize)
+                .context("Failed to get macro by idx")?;
+            self.macros.insert(macro_._offset);
+        }
+        Ok(())
+    }
+}
+
+/// The metas and entries left out of a dump cut at a version, by file offset.
+///
+/// Metas and entries newer than the cut are left out, and so is everything that depends
+/// on them: entries of a left out type, and entries whose `refer`, `select` or `sizeinfo`
+/// field is left out.
+#[derive(Clone, Debug)]
+pub struct VersionCut {
+    pub version: i32,
+    pub metas: BTreeSet<u64>,
+    pub entries: BTreeSet<u64>,
+}
+
+impl VersionCut {
+    pub fn resolve(metalib: &Metalib, version: i32) -> VersionCut {
+        let mut cut = VersionCut {
+            version,
+            metas: BTreeSet::new(),
+            entries: BTreeSet::new(),
+        };
+        // Leaving an entry out can leave out the entries that refer to it, so repeat until
+        // nothing changes.
+        loop {
+            let mut changed = false;
+            for meta in metalib.metas.iter() {
+                if cut.metas.contains(&meta._offset) {
+                    continue;
+                }
+                if meta.base_version > version {
+                    changed |= cut.metas.insert(meta._offset);
+                    continue;
+                }
+                for entry in meta.entries.iter() {
+                    if !cut.entries.contains(&entry._offset)
+                        && cut.depends_on_cut(metalib, meta, entry)
+                    {
+                        changed |= cut.entries.insert(entry._offset);
+                    }
+                }
+            }
+            if !changed {
+                return cut;
+            }
+        }
+    }
+
+    /// Whether `entry` is newer than the cut or refers to something that's left out.
+    /// References that don't resolve are left for the dump to report.
+    fn depends_on_cut(&self, metalib: &Metalib, meta: &TDRMeta, entry: &TDRMetaEntry) -> bool {
+        if entry.version > self.version {
+            return true;
+        }
+        if entry.ptr_meta != INVALID_METALIB_VALUE {
+            if let Ok(type_) = metalib.get_meta_by_offset(entry.ptr_meta) {
+                if self.metas.contains(&type_._offset) {
+                    return true;
+                }
+            }
+        }
+        let size_info = match entry.size_info.resolve(metalib, meta) {
+            Ok(Some(SizeSource::Field(field))) => Some(field.path),
+            _ => None,
+        };
+        let refer = entry.referer.resolve(metalib, meta).ok().flatten();
+        let select = entry.selector.resolve(metalib, meta).ok().flatten();
+        [size_info, refer.map(|f| f.path), select.map(|f| f.path)]
+            .into_iter()
+            .flatten()
+            .any(|path| !self.keeps_path(metalib, meta, &path))
+    }
+
+    /// Whether every entry along `path`, a dotted path of fields from `meta`, is kept.
+    pub fn keeps_path(&self, metalib: &Metalib, meta: &TDRMeta, path: &str) -> bool {
+        let mut current = meta;
+        for name in path.split('.') {
+            let Some(entry) = current.entries.iter().find(|entry| entry.name == name) else {
+                return true;
+            };
+            if self.entries.contains(&entry._offset) {
+                return false;
+            }
+            match metalib.get_meta_by_offset(entry.ptr_meta) {
+                Ok(nested) => current = nested,
+                Err(_) => return true,
+            }
+        }
+        true
+    }
+
+    /// Fails if `path`, the field the `attr` attribute of `meta` refers to, is left out,
+    /// since the meta can't be written without it.
+    pub fn check_path(
+        &self,
+        metalib: &Metalib,
+        meta: &TDRMeta,
+        attr: &str,
+        path: &str,
+    ) -> Result<()> {
+        match self.keeps_path(metalib, meta, path) {
+            true => Ok(()),
+            false => Err(anyhow!(
+                "`{attr}` of `{}` refers to `{path}`, which is newer than version {}",
+                meta.name,
+                self.version
+            )),
+        }
+    }
+}
+
+/// Levenshtein distance between two strings, by character.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            } else {
+                1 + prev.min(cur).min(row[j])
+            };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+/// Returns the candidates that look like a misspelling of `name`, closest first.
+fn close_matches<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let lower = name.to_lowercase();
+    let max_distance = (name.chars().count() / 3).max(2);
+    let mut matches: Vec<(usize, &str)> = candidates
+        .filter_map(|candidate| {
+            let candidate_lower = candidate.to_lowercase();
+            let distance = edit_distance(&lower, &candidate_lower);
+            if distance <= max_distance
+                || candidate_lower.contains(&lower)
+                || lower.contains(&candidate_lower)
+            {
+                Some((distance, candidate))
+            } else {
+                None
+            }
+        })
+        .collect();
+    matches.sort();
+    matches.dedup();
+    matches
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
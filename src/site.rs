@@ -0,0 +1,301 @@
+//! Static documentation site covering several versions of a metalib.
+//!
+//! Layout of the generated directory:
+//!
+//! ```text
+//! index.html          version list, search box and changelog between adjacent versions
+//! <version>.html      one page per metalib, with an anchor per struct/union and macrogroup
+//! search-index.json   every searchable name with the page/anchor it lives at
+//! search-index.js     the same index wrapped in a script, so search works from file://
+//! ```
+//!
+//! Everything is self-contained: no external stylesheets, scripts or fonts.
+
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::digest::{name_hash, Digest};
+use crate::export::html::{html_escape, render_metalib_page, write_page_start, PageAnchors};
+use crate::export::{json_string, unique_slug};
+use crate::metalib::{Metalib, TDRMeta};
+
+const CHANGELOG_CSS: &str = ".added{color:#080}.removed{color:#a00}.changed{color:#a60}";
+
+const SEARCH_SCRIPT: &str = "function search(q){\
+q=q.toLowerCase();var out=document.getElementById('results');out.innerHTML='';\
+if(!q)return;SEARCH_INDEX.filter(function(e){return e.name.toLowerCase().indexOf(q)>=0})\
+.slice(0,100).forEach(function(e){var li=document.createElement('li');\
+var a=document.createElement('a');a.href=e.page+'#'+e.anchor;\
+a.textContent=e.name+' ('+e.kind+', '+e.version+')';li.appendChild(a);out.appendChild(li);});}";
+
+/// One metalib version to include in the site, in manifest order.
+pub struct SiteVersion {
+    pub label: String,
+    pub metalib: Metalib,
+}
+
+/// A searchable name and where it's documented.
+struct SearchEntry {
+    name: String,
+    kind: &'static str,
+    version: String,
+    page: String,
+    anchor: String,
+}
+
+/// Writes the changelog between two adjacent versions, using the digest diff.
+fn write_changelog(
+    out: &mut String,
+    old: (&SiteVersion, &str, &PageAnchors),
+    new: (&SiteVersion, &str, &PageAnchors),
+) -> Result<()> {
+    let (old_version, old_page, old_anchors) = old;
+    let (new_version, new_page, new_anchors) = new;
+
+    // Digests only keep name hashes, so map them back to the metas.
+    let old_metas: HashMap<u64, &TDRMeta> = old_version
+        .metalib
+        .metas
+        .iter()
+        .map(|meta| (name_hash(&meta.name), meta))
+        .collect();
+    let new_metas: HashMap<u64, &TDRMeta> = new_version
+        .metalib
+        .metas
+        .iter()
+        .map(|meta| (name_hash(&meta.name), meta))
+        .collect();
+
+    let diff = Digest::from_metalib(&old_version.metalib)?
+        .diff(&Digest::from_metalib(&new_version.metalib)?);
+
+    writeln!(
+        out,
+        "<h3>{} &rarr; {}</h3>",
+        html_escape(&old_version.label),
+        html_escape(&new_version.label)
+    )?;
+    if diff.is_empty() {
+        writeln!(out, "<p>No struct changes.</p>")?;
+        return Ok(());
+    }
+
+    writeln!(out, "<ul>")?;
+    for meta in diff.added.iter() {
+        let meta = new_metas[&meta.name_hash];
+        writeln!(
+            out,
+            "<li class=\"added\">Added <a href=\"{new_page}#{}\">{}</a></li>",
+            new_anchors.metas[&meta._offset],
+            html_escape(&meta.name)
+        )?;
+    }
+    for meta in diff.removed.iter() {
+        let meta = old_metas[&meta.name_hash];
+        writeln!(
+            out,
+            "<li class=\"removed\">Removed <a href=\"{old_page}#{}\">{}</a></li>",
+            old_anchors.metas[&meta._offset],
+            html_escape(&meta.name)
+        )?;
+    }
+    for change in diff.changed.iter() {
+        let meta = new_metas[&change.new.name_hash];
+        let mut reasons = Vec::new();
+        if change.old.layout_hash != change.new.layout_hash {
+            reasons.push("layout".to_string());
+        }
+        if change.old.version != change.new.version {
+            reasons.push(format!(
+                "version {} &rarr; {}",
+                change.old.version, change.new.version
+            ));
+        }
+        writeln!(
+            out,
+            "<li class=\"changed\">Changed <a href=\"{new_page}#{}\">{}</a> ({})</li>",
+            new_anchors.metas[&meta._offset],
+            html_escape(&meta.name),
+            reasons.join(", ")
+        )?;
+    }
+    writeln!(out, "</ul>")?;
+    Ok(())
+}
+
+fn render_index(
+    versions: &[SiteVersion],
+    pages: &[String],
+    anchors: &[PageAnchors],
+) -> Result<String> {
+    let mut out = String::new();
+    write_page_start(&mut out, "Metalib versions")?;
+    writeln!(&mut out, "<style>{CHANGELOG_CSS}</style>")?;
+    writeln!(&mut out, "<main><h1>Metalib versions</h1><ul>")?;
+    for (version, page) in versions.iter().zip(pages.iter()) {
+        writeln!(
+            &mut out,
+            "<li><a href=\"{page}\">{}</a> &mdash; {} (version {}, {} structs)</li>",
+            html_escape(&version.label),
+            html_escape(&version.metalib.header.name),
+            version.metalib.header.version,
+            version.metalib.metas.len()
+        )?;
+    }
+    writeln!(&mut out, "</ul>")?;
+
+    writeln!(&mut out, "<h2>Search</h2>")?;
+    writeln!(
+        &mut out,
+        "<input type=\"search\" placeholder=\"Struct or macrogroup name\" oninput=\"search(this.value)\">"
+    )?;
+    writeln!(&mut out, "<ul id=\"results\"></ul>")?;
+
+    if versions.len() > 1 {
+        writeln!(&mut out, "<h2>Changelog</h2>")?;
+        for i in 1..versions.len() {
+            write_changelog(
+                &mut out,
+                (&versions[i - 1], &pages[i - 1], &anchors[i - 1]),
+                (&versions[i], &pages[i], &anchors[i]),
+            )?;
+        }
+    }
+
+    writeln!(&mut out, "</main>")?;
+    writeln!(&mut out, "<script src=\"search-index.js\"></script>")?;
+    writeln!(&mut out, "<script>{SEARCH_SCRIPT}</script>")?;
+    writeln!(&mut out, "</body></html>")?;
+    Ok(out)
+}
+
+fn render_search_index(entries: &[SearchEntry]) -> String {
+    let items: Vec<String> = entries
+        .iter()
+        .map(|e| {
+            format!(
+                "{{\"name\":{},\"kind\":\"{}\",\"version\":{},\"page\":{},\"anchor\":{}}}",
+                json_string(&e.name),
+                e.kind,
+                json_string(&e.version),
+                json_string(&e.page),
+                json_string(&e.anchor)
+            )
+        })
+        .collect();
+    format!("[\n{}\n]\n", items.join(",\n"))
+}
+
+/// Builds the site for the given versions (oldest first) into `out_dir`.
+pub fn build_site(versions: &[SiteVersion], out_dir: &Path) -> Result<()> {
+    fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create {}", out_dir.display()))?;
+
+    let mut used_pages = HashSet::new();
+    // Reserve the fixed file names so no version page can overwrite them.
+    used_pages.insert("index".to_string());
+    let pages: Vec<String> = versions
+        .iter()
+        .map(|version| format!("{}.html", unique_slug(&version.label, &mut used_pages)))
+        .collect();
+    let anchors: Vec<PageAnchors> = versions
+        .iter()
+        .map(|version| PageAnchors::new(&version.metalib))
+        .collect();
+
+    let mut search_entries = Vec::new();
+    for ((version, page), page_anchors) in versions.iter().zip(pages.iter()).zip(anchors.iter()) {
+        fs::write(
+            out_dir.join(page),
+            render_metalib_page(
+                &version.metalib,
+                page_anchors,
+                Some(&version.label),
+                Some(("index.html", "All versions")),
+            )?,
+        )?;
+
+        for meta in version.metalib.metas.iter() {
+            search_entries.push(SearchEntry {
+                name: meta.name.clone(),
+                kind: "struct",
+                version: version.label.clone(),
+                page: page.clone(),
+                anchor: page_anchors.metas[&meta._offset].clone(),
+            });
+        }
+        for group in version.metalib.macrogroups.iter() {
+            search_entries.push(SearchEntry {
+                name: group.name.clone(),
+                kind: "macrogroup",
+                version: version.label.clone(),
+                page: page.clone(),
+                anchor: page_anchors.macrogroups[&group._offset].clone(),
+            });
+        }
+    }
+
+    let search_index = render_search_index(&search_entries);
+    fs::write(
+        out_dir.join("search-index.js"),
+        format!("var SEARCH_INDEX = {};\n", search_index.trim_end()),
+    )?;
+    fs::write(out_dir.join("search-index.json"), search_index)?;
+    fs::write(
+        out_dir.join("index.html"),
+        render_index(versions, &pages, &anchors)?,
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{parse, temp_path, FULL_XML};
+
+    #[test]
+    fn builds_a_site_for_two_versions() {
+        let new_xml = FULL_XML.replace(r#"<struct name="Sorted""#, r#"<struct name="Ordered""#);
+        let versions = [
+            SiteVersion {
+                label: "v1".to_string(),
+                metalib: parse(FULL_XML),
+            },
+            SiteVersion {
+                label: "v2".to_string(),
+                metalib: parse(&new_xml),
+            },
+        ];
+        let dir = temp_path("site");
+        build_site(&versions, &dir).unwrap();
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        let index = read("index.html");
+        assert!(index.contains("href=\"v1.html\""), "{index}");
+        assert!(index.contains("href=\"v2.html\""), "{index}");
+        let added = index
+            .find("Ordered")
+            .expect("changelog lists the added struct");
+        assert!(index[..added].contains("added"), "{index}");
+        assert!(index.contains("Sorted"), "{index}");
+
+        let search: serde_json::Value = serde_json::from_str(&read("search-index.json")).unwrap();
+        let names: Vec<&str> = search
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["name"].as_str().unwrap())
+            .collect();
+        assert!(names.contains(&"Ordered") && names.contains(&"Sorted"));
+
+        for page in ["index.html", "v1.html", "v2.html", "search-index.js"] {
+            let page = read(page);
+            assert!(!page.contains("http://") && !page.contains("https://"));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,271 @@
+//! Frequency tables of header/meta/entry field values across many metalibs.
+//!
+//! Used to work out which raw fields map to which XML attributes: fields that are only
+//! ever 0/-1 are probably unused, and flags that never appear can be ignored.
+//! To survey another field, add a line to `SURVEY_FIELDS`.
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::Write;
+
+use crate::metalib::{Metalib, MetalibHeader, TDRMeta, TDRMetaEntry, INVALID_METALIB_VALUE};
+
+/// Distinct values tracked per histogram before the rest are lumped into "other".
+const MAX_HISTOGRAM_BUCKETS: usize = 64;
+
+/// How the values of a surveyed field are tallied.
+#[derive(Clone, Copy)]
+enum SurveyKind {
+    /// Counts how often the field is set (not 0 and not -1).
+    Set,
+    /// Counts every bit that appears.
+    Bits,
+    /// Counts every distinct value.
+    Histogram,
+}
+
+/// Where a surveyed field is read from.
+#[derive(Clone, Copy)]
+enum SurveyGetter {
+    Header(fn(&MetalibHeader) -> i64),
+    Meta(fn(&TDRMeta) -> i64),
+    Entry(fn(&TDRMetaEntry) -> i64),
+}
+
+struct SurveyField {
+    name: &'static str,
+    kind: SurveyKind,
+    getter: SurveyGetter,
+}
+
+use SurveyGetter::{Entry, Header, Meta};
+use SurveyKind::{Bits, Histogram, Set};
+
+#[rustfmt::skip]
+const SURVEY_FIELDS: &[SurveyField] = &[
+    SurveyField { name: "header.build",                    kind: Histogram, getter: Header(|h| h.build as i64) },
+    SurveyField { name: "header.platform_arch",            kind: Histogram, getter: Header(|h| h.platform_arch as i64) },
+    SurveyField { name: "header.xml_tag_set_ver",          kind: Histogram, getter: Header(|h| h.xml_tag_set_ver as i64) },
+    SurveyField { name: "header.field_c",                  kind: Histogram, getter: Header(|h| h.field_c as i64) },
+    SurveyField { name: "header.field_10",                 kind: Histogram, getter: Header(|h| h.field_10 as i64) },
+    SurveyField { name: "header.field_14",                 kind: Histogram, getter: Header(|h| h.field_14 as i64) },
+    SurveyField { name: "header.field_18",                 kind: Histogram, getter: Header(|h| h.field_18 as i64) },
+    SurveyField { name: "header.field_24",                 kind: Histogram, getter: Header(|h| h.field_24 as i64) },
+    SurveyField { name: "header.field_40",                 kind: Histogram, getter: Header(|h| h.field_40 as i64) },
+    SurveyField { name: "header.field_44",                 kind: Histogram, getter: Header(|h| h.field_44 as i64) },
+    SurveyField { name: "header.field_78",                 kind: Histogram, getter: Header(|h| h.field_78 as i64) },
+    SurveyField { name: "header.field_7c",                 kind: Histogram, getter: Header(|h| h.field_7c as i64) },
+    SurveyField { name: "header.field_80",                 kind: Histogram, getter: Header(|h| h.field_80 as i64) },
+    SurveyField { name: "header.field_84",                 kind: Histogram, getter: Header(|h| h.field_84 as i64) },
+    SurveyField { name: "header.field_88",                 kind: Histogram, getter: Header(|h| h.field_88 as i64) },
+    SurveyField { name: "header.field_8c",                 kind: Histogram, getter: Header(|h| h.field_8c as i64) },
+    SurveyField { name: "header.field_90",                 kind: Histogram, getter: Header(|h| h.field_90 as i64) },
+    SurveyField { name: "meta.flags",                      kind: Bits,      getter: Meta(|m| m.flags.bits() as i64) },
+    SurveyField { name: "meta.unk_table_count",            kind: Set,       getter: Meta(|m| m.unk_table_count as i64) },
+    SurveyField { name: "meta.unk_table_unk",              kind: Histogram, getter: Meta(|m| m.unk_table_unk as i64) },
+    SurveyField { name: "meta.uncertain_max_sub_id",       kind: Set,       getter: Meta(|m| m.uncertain_max_sub_id as i64) },
+    SurveyField { name: "meta.idx_type",                   kind: Histogram, getter: Meta(|m| m.idx_type as i64) },
+    SurveyField { name: "meta.custom_align",               kind: Histogram, getter: Meta(|m| m.custom_align as i64) },
+    SurveyField { name: "meta.valid_align",                kind: Histogram, getter: Meta(|m| m.valid_align as i64) },
+    SurveyField { name: "meta.uncertain_version_indicator_min_ver", kind: Histogram, getter: Meta(|m| m.uncertain_version_indicator_min_ver as i64) },
+    SurveyField { name: "meta.split_table_rule_id",        kind: Histogram, getter: Meta(|m| m.split_table_rule_id as i64) },
+    SurveyField { name: "meta.primary_key_member_num",     kind: Histogram, getter: Meta(|m| m.primary_key_member_num as i64) },
+    SurveyField { name: "meta.field_ac",                   kind: Histogram, getter: Meta(|m| m.field_ac as i64) },
+    SurveyField { name: "meta.field_b0",                   kind: Histogram, getter: Meta(|m| m.field_b0 as i64) },
+    SurveyField { name: "meta.field_b4",                   kind: Histogram, getter: Meta(|m| m.field_b4 as i64) },
+    SurveyField { name: "entry.flag",                      kind: Bits,      getter: Entry(|e| e.flag.bits() as i64) },
+    SurveyField { name: "entry.db_flag",                   kind: Bits,      getter: Entry(|e| e.db_flag.bits() as i64) },
+    SurveyField { name: "entry.order",                     kind: Histogram, getter: Entry(|e| e.order as i64) },
+    SurveyField { name: "entry.io",                        kind: Histogram, getter: Entry(|e| e.io as i64) },
+    SurveyField { name: "entry.idx_io",                    kind: Set,       getter: Entry(|e| e.idx_io as i64) },
+    SurveyField { name: "entry.custom_h_unit_size",        kind: Set,       getter: Entry(|e| e.custom_h_unit_size as i64) },
+    SurveyField { name: "entry.idx_custom_h_unit_size",    kind: Set,       getter: Entry(|e| e.idx_custom_h_unit_size as i64) },
+    SurveyField { name: "entry.ptr_custom_attr",           kind: Set,       getter: Entry(|e| e.ptr_custom_attr as i64) },
+    SurveyField { name: "entry.off_to_meta",               kind: Set,       getter: Entry(|e| e.off_to_meta as i64) },
+    SurveyField { name: "entry.field_a8",                  kind: Histogram, getter: Entry(|e| e.field_a8 as i64) },
+    SurveyField { name: "entry.field_ac",                  kind: Histogram, getter: Entry(|e| e.field_ac as i64) },
+    SurveyField { name: "entry.field_b0",                  kind: Histogram, getter: Entry(|e| e.field_b0 as i64) },
+];
+
+/// Tally for a single surveyed field.
+#[derive(Default)]
+struct FieldTally {
+    /// Number of headers/metas/entries the field was read from.
+    samples: u64,
+    counts: BTreeMap<i64, u64>,
+    /// Samples that didn't fit into the histogram.
+    other: u64,
+}
+
+impl FieldTally {
+    fn add(&mut self, kind: SurveyKind, value: i64) {
+        self.samples += 1;
+        match kind {
+            SurveyKind::Set => {
+                if value != 0 && value != INVALID_METALIB_VALUE as i64 {
+                    *self.counts.entry(1).or_default() += 1;
+                }
+            }
+            SurveyKind::Bits => {
+                for bit in 0..32 {
+                    let mask = 1i64 << bit;
+                    if value & mask != 0 {
+                        *self.counts.entry(mask).or_default() += 1;
+                    }
+                }
+            }
+            SurveyKind::Histogram => {
+                if self.counts.len() < MAX_HISTOGRAM_BUCKETS || self.counts.contains_key(&value) {
+                    *self.counts.entry(value).or_default() += 1;
+                } else {
+                    self.other += 1;
+                }
+            }
+        }
+    }
+
+    /// Returns `(value label, count)` rows for the report.
+    fn rows(&self, kind: SurveyKind) -> Vec<(String, u64)> {
+        let mut rows: Vec<(String, u64)> = match kind {
+            SurveyKind::Set => vec![("set".to_string(), self.counts.get(&1).copied().unwrap_or(0))],
+            SurveyKind::Bits => self
+                .counts
+                .iter()
+                .map(|(mask, count)| (format!("{mask:#06X}"), *count))
+                .collect(),
+            SurveyKind::Histogram => self
+                .counts
+                .iter()
+                .map(|(value, count)| (value.to_string(), *count))
+                .collect(),
+        };
+        if self.other > 0 {
+            rows.push(("other".to_string(), self.other));
+        }
+        rows
+    }
+}
+
+/// Field statistics aggregated over any number of metalibs.
+pub struct Survey {
+    pub metalib_count: u64,
+    tallies: Vec<FieldTally>,
+}
+
+impl Default for Survey {
+    fn default() -> Self {
+        Survey::new()
+    }
+}
+
+impl Survey {
+    pub fn new() -> Survey {
+        Survey {
+            metalib_count: 0,
+            tallies: SURVEY_FIELDS.iter().map(|_| FieldTally::default()).collect(),
+        }
+    }
+
+    pub fn add(&mut self, metalib: &Metalib) {
+        self.metalib_count += 1;
+        for (field, tally) in SURVEY_FIELDS.iter().zip(self.tallies.iter_mut()) {
+            match field.getter {
+                SurveyGetter::Header(get) => tally.add(field.kind, get(&metalib.header)),
+                SurveyGetter::Meta(get) => {
+                    for meta in metalib.metas.iter() {
+                        tally.add(field.kind, get(meta));
+                    }
+                }
+                SurveyGetter::Entry(get) => {
+                    for entry in metalib.metas.iter().flat_map(|meta| meta.entries.iter()) {
+                        tally.add(field.kind, get(entry));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes one `field,value,count,samples` row per tallied value.
+    pub fn write_csv<W: Write>(&self, w: &mut W) -> Result<()> {
+        writeln!(w, "field,value,count,samples")?;
+        for (field, tally) in SURVEY_FIELDS.iter().zip(self.tallies.iter()) {
+            for (value, count) in tally.rows(field.kind) {
+                writeln!(w, "{},{value},{count},{}", field.name, tally.samples)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        let mut out = String::new();
+        writeln!(&mut out, "{{")?;
+        writeln!(&mut out, "  \"metalibs\": {},", self.metalib_count)?;
+        writeln!(&mut out, "  \"fields\": {{")?;
+        for (i, (field, tally)) in SURVEY_FIELDS.iter().zip(self.tallies.iter()).enumerate() {
+            let values: Vec<String> = tally
+                .rows(field.kind)
+                .into_iter()
+                .map(|(value, count)| format!("\"{value}\": {count}"))
+                .collect();
+            let separator = if i + 1 < SURVEY_FIELDS.len() { "," } else { "" };
+            writeln!(
+                &mut out,
+                "    \"{}\": {{ \"samples\": {}, \"values\": {{ {} }} }}{separator}",
+                field.name,
+                tally.samples,
+                values.join(", ")
+            )?;
+        }
+        writeln!(&mut out, "  }}")?;
+        writeln!(&mut out, "}}")?;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::MetalibArch;
+    use crate::options::ParseOptions;
+    use crate::test_support::{compile_bytes, parse_with, FULL_XML, TYPES_XML};
+
+    fn survey() -> Survey {
+        let mut survey = Survey::new();
+        for (xml, arch) in [
+            (FULL_XML, MetalibArch::Bits32),
+            (TYPES_XML, MetalibArch::Bits64),
+        ] {
+            let bytes = compile_bytes(xml, arch);
+            survey.add(&parse_with(&bytes, &ParseOptions::default()).unwrap());
+        }
+        survey
+    }
+
+    #[test]
+    fn counts_over_two_metalibs() {
+        let json: serde_json::Value = serde_json::from_str(&survey().to_json().unwrap()).unwrap();
+        assert_eq!(json["metalibs"], 2);
+
+        let fields = &json["fields"];
+        assert_eq!(fields["header.build"]["values"]["11"], 2);
+        assert_eq!(fields["header.platform_arch"]["values"]["32"], 1);
+        assert_eq!(fields["header.platform_arch"]["values"]["64"], 1);
+        // Five metas in `full`, one in `types`.
+        assert_eq!(fields["meta.idx_type"]["samples"], 6);
+        // Only `Account` has a primary key, of two members.
+        assert_eq!(fields["meta.primary_key_member_num"]["values"]["2"], 1);
+        // `Packet.name` is the only `io="nooutput"` entry.
+        assert_eq!(fields["entry.io"]["values"]["2"], 1);
+    }
+
+    #[test]
+    fn csv_has_a_row_per_value() {
+        let mut csv = Vec::new();
+        survey().write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("field,value,count,samples\n"));
+        assert!(csv.contains("\nheader.build,11,2,2\n"), "{csv}");
+        assert!(csv.contains("\nheader.platform_arch,64,1,2\n"), "{csv}");
+    }
+}
@@ -0,0 +1,70 @@
+//! Fixtures shared by the unit tests: metalibs compiled from the XML under `tests/fixtures/`.
+
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::arch::MetalibArch;
+use crate::compile::compile_metalib_xml;
+use crate::metalib::{read_metalib_with, Metalib, METALIB_BUILD_10};
+use crate::options::ParseOptions;
+use crate::writer::write_metalib;
+
+/// Macros, a macrogroup, a union, refer/select/sizeinfo paths, DB keys and defaults.
+pub(crate) const FULL_XML: &str = include_str!("../tests/fixtures/full.xml");
+
+/// Database-style metas: keys, flattened struct columns and the other DB attributes.
+pub(crate) const DB_XML: &str = include_str!("../tests/fixtures/db.xml");
+
+/// A metalib with no macros, macrogroups or metas.
+pub(crate) const EMPTY_XML: &str = include_str!("../tests/fixtures/empty.xml");
+
+/// Macros and nothing else.
+pub(crate) const MACROS_XML: &str = include_str!("../tests/fixtures/macros.xml");
+
+/// One entry of every primitive type, without defaults.
+pub(crate) const TYPES_XML: &str = include_str!("../tests/fixtures/types.xml");
+
+/// Compiles `xml` into an in-memory metalib, as the builder lays it out.
+pub(crate) fn compile(xml: &str) -> Metalib {
+    compile_metalib_xml(xml).expect("fixture XML should compile")
+}
+
+/// A path in the temp directory that's unique to this test process.
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mldec-test-{}-{name}", std::process::id()))
+}
+
+/// Compiles `xml` and writes it out in the `arch` layout.
+pub(crate) fn compile_bytes(xml: &str, arch: MetalibArch) -> Vec<u8> {
+    let mut metalib = compile(xml);
+    metalib.arch = arch;
+    metalib.header.platform_arch = arch.platform_arch();
+    let mut bytes = Vec::new();
+    write_metalib(&metalib, &mut bytes).expect("fixture metalib should write");
+    bytes
+}
+
+/// Compiles `xml` in the 32-bit layout, then cuts its header down to the older build 10
+/// layout by hand: the seven words at 0x78 go and `size` shrinks to match.
+pub(crate) fn compile_build_10_bytes(xml: &str) -> Vec<u8> {
+    let mut bytes = compile_bytes(xml, MetalibArch::Bits32);
+    bytes[2..4].copy_from_slice(&METALIB_BUILD_10.to_le_bytes());
+    let size = bytes.len() as u32 - 7 * 4;
+    bytes[8..12].copy_from_slice(&size.to_le_bytes());
+    bytes.drain(0x78..0x94);
+    assert_eq!(bytes.len() as u32, size);
+    bytes
+}
+
+/// Parses compiled bytes with `options`.
+pub(crate) fn parse_with(bytes: &[u8], options: &ParseOptions) -> Result<Metalib> {
+    read_metalib_with(&mut Cursor::new(bytes), options)
+}
+
+/// Compiles `xml`, writes it out in the 32-bit layout and parses it back strictly.
+pub(crate) fn parse(xml: &str) -> Metalib {
+    let bytes = compile_bytes(xml, MetalibArch::Bits32);
+    parse_with(&bytes, &ParseOptions::default()).expect("fixture metalib should parse")
+}
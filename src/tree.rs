@@ -0,0 +1,84 @@
+//! Indented tree view of a meta, for the `show` subcommand.
+
+use anyhow::Result;
+use std::fmt::Write as _;
+
+use crate::list::write_table;
+use crate::metalib::{MetaPrimativeType, Metalib, TDRMeta, TDRMetaEntryFlags};
+
+impl Metalib {
+    /// Formats `meta` as an indented tree, one row per entry with its type, count and host
+    /// and network offsets and sizes. Struct and union entries are expanded `depth` levels
+    /// deep. Offsets are from the start of `meta`, so nested rows can be read off a packet
+    /// dump directly.
+    pub fn format_meta_tree(&self, meta: &TDRMeta, depth: usize) -> Result<String> {
+        let mut rows = Vec::new();
+        self.meta_tree_rows(meta, depth, 0, 0, 0, &mut vec![meta._offset], &mut rows)?;
+
+        let kind = match meta.type_ {
+            MetaPrimativeType::UNION => "union",
+            _ => "struct",
+        };
+        let mut out = String::new();
+        writeln!(
+            &mut out,
+            "{kind} {} (version {}, {} bytes host, {} bytes net)",
+            meta.name, meta.base_version, meta.h_unit_size, meta.n_unit_size
+        )?;
+        write_table(
+            &mut out,
+            &[
+                "entry", "type", "count", "h_off", "h_size", "n_off", "n_size",
+            ],
+            &rows,
+            false,
+        )?;
+        Ok(out)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn meta_tree_rows(
+        &self,
+        meta: &TDRMeta,
+        depth: usize,
+        level: usize,
+        h_base: i32,
+        n_base: i32,
+        walking: &mut Vec<u64>,
+        rows: &mut Vec<Vec<String>>,
+    ) -> Result<()> {
+        for entry in meta.entries.iter() {
+            let count = match entry.idx_count {
+                idx if idx >= 0 && (idx as usize) < self.macros.len() => {
+                    format!("{} ({})", entry.count, self.macros[idx as usize].name)
+                }
+                _ => entry.count.to_string(),
+            };
+            let (h_off, n_off) = (h_base + entry.h_off, n_base + entry.n_off);
+            rows.push(vec![
+                format!("{}{}", "  ".repeat(level), entry.name),
+                self.entry_type_name(entry)?,
+                count,
+                h_off.to_string(),
+                entry.h_real_size.to_string(),
+                n_off.to_string(),
+                entry.n_real_size.to_string(),
+            ]);
+
+            // Pointers don't hold the struct, and a struct that contains itself would never end.
+            if level >= depth || entry.flag.contains(TDRMetaEntryFlags::POINT_TYPE) {
+                continue;
+            }
+            let Ok(child) = self.get_meta_by_offset(entry.ptr_meta) else {
+                continue;
+            };
+            if walking.contains(&child._offset) {
+                continue;
+            }
+            walking.push(child._offset);
+            self.meta_tree_rows(child, depth, level + 1, h_off, n_off, walking, rows)?;
+            walking.pop();
+        }
+        Ok(())
+    }
+}
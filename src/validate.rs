@@ -0,0 +1,723 @@
+//! Consistency checks on a parsed metalib, for the `validate` subcommand.
+//!
+//! The reader only fails on data it can't read at all. These checks catch tables that were
+//! read fine but don't agree with each other, which would otherwise surface as confusing
+//! failures in the exporters.
+
+use std::fmt;
+
+use crate::metalib::{
+    duplicate_meta_names, format_meta_offsets, primitive_type_info, MetaPrimativeType, Metalib,
+    TDRMeta, TDRMetaEntry, TDRMetaEntryDBFlags, TDRMetaFlags, INVALID_METALIB_VALUE,
+};
+
+/// How bad a [`ValidationIssue`] is.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Severity {
+    /// Unusual, but the metalib can still be exported.
+    Warning,
+    /// The metalib is inconsistent and exporting it will likely fail or be wrong.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A single problem found by [`Metalib::validate`].
+#[derive(Clone, Debug)]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    /// Name of the meta (or macro/macrogroup) the issue is in, if any.
+    pub meta: Option<String>,
+    /// Name of the entry within `meta`, if any.
+    pub entry: Option<String>,
+    /// File offset of the offending record.
+    pub offset: u64,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.severity)?;
+        match (&self.meta, &self.entry) {
+            (Some(meta), Some(entry)) => write!(f, "{meta}.{entry}: ")?,
+            (Some(meta), None) => write!(f, "{meta}: ")?,
+            _ => {}
+        }
+        write!(f, "{} (at {:#X})", self.message, self.offset)
+    }
+}
+
+/// Collects issues for one metalib.
+struct Checker<'a> {
+    metalib: &'a Metalib,
+    issues: Vec<ValidationIssue>,
+}
+
+impl Checker<'_> {
+    /// Converts an offset within the body into a file offset.
+    fn file_offset(&self, body_offset: u64) -> u64 {
+        self.metalib._offset + self.metalib.header.header_size() as u64 + body_offset
+    }
+
+    fn report(
+        &mut self,
+        severity: Severity,
+        meta: Option<&str>,
+        entry: Option<&str>,
+        offset: u64,
+        message: String,
+    ) {
+        self.issues.push(ValidationIssue {
+            severity,
+            meta: meta.map(str::to_string),
+            entry: entry.map(str::to_string),
+            offset,
+            message,
+        });
+    }
+
+    fn check_counts(&mut self) {
+        let metalib = self.metalib;
+        let header = &metalib.header;
+        let tables = [
+            (
+                "metas",
+                metalib.metas.len(),
+                header.cur_meta_num,
+                header.max_meta_num,
+            ),
+            (
+                "ids",
+                metalib.ids.len(),
+                header.cur_meta_num,
+                header.max_meta_num,
+            ),
+            (
+                "names",
+                metalib.names.len(),
+                header.cur_meta_num,
+                header.max_meta_num,
+            ),
+            (
+                "meta map",
+                metalib.meta_map.len(),
+                header.cur_meta_num,
+                header.max_meta_num,
+            ),
+            (
+                "macros",
+                metalib.macros.len(),
+                header.cur_macro_num,
+                header.max_macro_num,
+            ),
+            (
+                "macrogroups",
+                metalib.macrogroups.len(),
+                header.cur_macros_group_num,
+                header.max_macros_group_num,
+            ),
+        ];
+        let header_offset = metalib._offset;
+        for (table, len, cur, max) in tables {
+            if len as i64 != cur as i64 {
+                self.report(
+                    Severity::Error,
+                    None,
+                    None,
+                    header_offset,
+                    format!("The {table} table has {len} rows but the header says {cur}"),
+                );
+            }
+            if cur > max {
+                self.report(
+                    Severity::Warning,
+                    None,
+                    None,
+                    header_offset,
+                    format!("The header's {table} count {cur} is above its maximum {max}"),
+                );
+            }
+        }
+    }
+
+    /// Reports `idx` if it's set but isn't a valid index into the macro table.
+    fn check_macro_idx(
+        &mut self,
+        meta: Option<&str>,
+        entry: Option<&str>,
+        body_offset: u64,
+        field: &str,
+        idx: i32,
+    ) {
+        if idx != INVALID_METALIB_VALUE && (idx < 0 || idx as usize >= self.metalib.macros.len()) {
+            let message = format!(
+                "{field} {idx} is outside the macro table ({} macros)",
+                self.metalib.macros.len()
+            );
+            let offset = self.file_offset(body_offset);
+            self.report(Severity::Error, meta, entry, offset, message);
+        }
+    }
+
+    fn check_macrogroups(&mut self) {
+        for group in self.metalib.macrogroups.iter() {
+            for &idx in group.name_idx_map.iter().chain(group.value_idx_map.iter()) {
+                self.check_macro_idx(Some(&group.name), None, group._offset, "Member", idx);
+            }
+        }
+    }
+
+    /// The meta map repeats each meta's `mem_size`. Nothing we read uses it, so a mismatch
+    /// is only a warning.
+    fn check_meta_map(&mut self) {
+        for (map_entry, meta) in self.metalib.meta_map.iter().zip(self.metalib.metas.iter()) {
+            if map_entry.size != meta.mem_size {
+                let message = format!(
+                    "The meta map gives size {} but mem_size is {}",
+                    map_entry.size, meta.mem_size
+                );
+                let offset = self.file_offset(map_entry._offset);
+                self.report(Severity::Warning, Some(&meta.name), None, offset, message);
+            }
+        }
+    }
+
+    /// Every name-based lookup (`--meta`, `decode`, ...) fails on a name several metas share.
+    fn check_duplicate_names(&mut self) {
+        for (name, same) in duplicate_meta_names(&self.metalib.metas) {
+            let message = format!(
+                "{} metas have this name (at {}), so it can't be looked up",
+                same.len(),
+                format_meta_offsets(&same)
+            );
+            let offset = self.file_offset(same[1]._offset);
+            self.report(Severity::Error, Some(name), None, offset, message);
+        }
+    }
+
+    /// A meta has at most one autoincrement entry, and says whether it has one in its flags.
+    fn check_auto_increment(&mut self, meta: &TDRMeta) {
+        let entries: Vec<&str> = meta
+            .entries
+            .iter()
+            .filter(|entry| entry.db_flag.contains(TDRMetaEntryDBFlags::AUTO_INCREMENT))
+            .map(|entry| entry.name.as_str())
+            .collect();
+        let offset = self.file_offset(meta._offset);
+        if entries.len() > 1 {
+            let message = format!(
+                "{} entries are autoincrement ({}), at most one is allowed",
+                entries.len(),
+                entries.join(", ")
+            );
+            self.report(Severity::Error, Some(&meta.name), None, offset, message);
+        }
+        let flagged = meta.flags.contains(TDRMetaFlags::HAS_AUTOINCREMENT_ENTRY);
+        if flagged == entries.is_empty() {
+            let message = match flagged {
+                true => "HAS_AUTOINCREMENT_ENTRY is set but no entry is autoincrement",
+                false => "An entry is autoincrement but HAS_AUTOINCREMENT_ENTRY isn't set",
+            };
+            let message = message.to_string();
+            self.report(Severity::Warning, Some(&meta.name), None, offset, message);
+        }
+    }
+
+    fn check_meta(&mut self, meta: &TDRMeta) {
+        let name = Some(meta.name.as_str());
+        if meta.entries_num as usize != meta.entries.len() {
+            let message = format!(
+                "entries_num is {} but {} entries were read",
+                meta.entries_num,
+                meta.entries.len()
+            );
+            let offset = self.file_offset(meta._offset);
+            self.report(Severity::Error, name, None, offset, message);
+        }
+        self.check_auto_increment(meta);
+        if meta.ptr_dependon_struct != INVALID_METALIB_VALUE {
+            let message = match self.metalib.get_meta_by_offset(meta.ptr_dependon_struct) {
+                Ok(other) if other.type_ == MetaPrimativeType::STRUCT => None,
+                Ok(other) => Some(format!(
+                    "ptr_dependon_struct points at `{}`, which isn't a struct",
+                    other.name
+                )),
+                Err(_) => Some(format!(
+                    "ptr_dependon_struct {:#X} doesn't point at a struct or union",
+                    meta.ptr_dependon_struct
+                )),
+            };
+            if let Some(message) = message {
+                let offset = self.file_offset(meta._offset);
+                self.report(Severity::Error, name, None, offset, message);
+            }
+        }
+        self.check_macro_idx(
+            name,
+            None,
+            meta._offset,
+            "idx_split_table_factor",
+            meta.idx_split_table_factor,
+        );
+        let has_split_table_factor =
+            meta.idx_split_table_factor != INVALID_METALIB_VALUE || meta.split_table_factor != 0;
+        if has_split_table_factor && meta.split_table_factor <= 0 {
+            let message = format!(
+                "split_table_factor is {}, it must be positive",
+                meta.split_table_factor
+            );
+            let offset = self.file_offset(meta._offset);
+            self.report(Severity::Error, name, None, offset, message);
+        }
+        self.check_macro_idx(name, None, meta._offset, "idx_id", meta.idx_id);
+        self.check_macro_idx(name, None, meta._offset, "idx_version", meta.idx_version);
+        self.check_macro_idx(
+            name,
+            None,
+            meta._offset,
+            "idx_custom_h_unit_size",
+            meta.idx_custom_h_unit_size,
+        );
+
+        for entry in meta.entries.iter() {
+            let entry_name = Some(entry.name.as_str());
+            let entry_offset = self.file_offset(entry._offset);
+            for (field, idx) in [
+                ("idx_count", entry.idx_count),
+                ("idx_version", entry.idx_version),
+                ("idx_id", entry.idx_id),
+                ("idx_custom_h_unit_size", entry.idx_custom_h_unit_size),
+                ("min_id_idx", entry.min_id_idx),
+                ("max_id_idx", entry.max_id_idx),
+            ] {
+                self.check_macro_idx(name, entry_name, entry._offset, field, idx);
+            }
+
+            if entry.ptr_meta != INVALID_METALIB_VALUE
+                && self.metalib.get_meta_by_offset(entry.ptr_meta).is_err()
+            {
+                let message = format!(
+                    "ptr_meta {:#X} doesn't point at a struct or union",
+                    entry.ptr_meta
+                );
+                self.report(Severity::Error, name, entry_name, entry_offset, message);
+            }
+
+            if entry.ptr_macros_group != INVALID_METALIB_VALUE
+                && self
+                    .metalib
+                    .get_macrogroup_by_offset(entry.ptr_macros_group)
+                    .is_err()
+            {
+                let message = format!(
+                    "ptr_macros_group {:#X} doesn't point at a macrogroup",
+                    entry.ptr_macros_group
+                );
+                self.report(Severity::Error, name, entry_name, entry_offset, message);
+            }
+
+            // Only a struct's columns can be pulled into the parent's table.
+            if entry.db_flag.contains(TDRMetaEntryDBFlags::EXTEND_TO_TABLE)
+                && entry.type_ != MetaPrimativeType::STRUCT
+            {
+                let message = format!("extendtotable is set but the type is {:?}", entry.type_);
+                self.report(Severity::Error, name, entry_name, entry_offset, message);
+            }
+
+            if let Some(message) = default_len_mismatch(entry) {
+                self.report(Severity::Warning, name, entry_name, entry_offset, message);
+            }
+
+            if entry.is_zero_size() {
+                continue;
+            }
+            for (side, off, size, parent_size) in [
+                ("Host", entry.h_off, entry.h_real_size, meta.h_unit_size),
+                ("Network", entry.n_off, entry.n_real_size, meta.n_unit_size),
+            ] {
+                let end = off as i64 + size as i64;
+                if off < 0 || end > parent_size as i64 {
+                    let message = format!(
+                        "{side} range {off}..{end} doesn't fit in the parent's {parent_size} bytes"
+                    );
+                    self.report(Severity::Error, name, entry_name, entry_offset, message);
+                }
+            }
+        }
+    }
+}
+
+/// Describes how `default_val_len` disagrees with the entry, if it does. A string's default
+/// (or a `char` array's text) has to fit, and any other default should be one value or one
+/// per element.
+fn default_len_mismatch(entry: &TDRMetaEntry) -> Option<String> {
+    if entry.ptr_default_val == INVALID_METALIB_VALUE {
+        return None;
+    }
+    let type_info = primitive_type_info(entry.idx_type)?;
+    let len = entry.default_val_len;
+    let is_char_array = type_info.xml_name == "char" && len > type_info.size;
+    if is_char_array
+        || matches!(
+            type_info.primative_type,
+            MetaPrimativeType::STRING | MetaPrimativeType::WSTRING
+        )
+    {
+        return (len > entry.h_real_size).then(|| {
+            format!(
+                "The default is {len} bytes but the string holds {}",
+                entry.h_real_size
+            )
+        });
+    }
+    let size = type_info.size;
+    let Some(array_size) = size.checked_mul(entry.count.max(1)) else {
+        return Some(format!(
+            "The default is {len} bytes, but {} values of {size} bytes overflow",
+            entry.count
+        ));
+    };
+    (len != size && len != array_size).then(|| {
+        format!(
+            "The default is {len} bytes, expected {size} (one value) or {array_size} ({} values)",
+            entry.count
+        )
+    })
+}
+
+impl Metalib {
+    /// Checks that the tables agree with the header and with each other: table counts,
+    /// `ptr_meta`, `ptr_macros_group` and `ptr_dependon_struct` references, macro indexes, entry offsets, default
+    /// value lengths, split table factors, the sizes in the meta map and unique meta names. Returns every problem found, worst first.
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        let mut checker = Checker {
+            metalib: self,
+            issues: Vec::new(),
+        };
+        checker.check_counts();
+        checker.check_macrogroups();
+        checker.check_meta_map();
+        checker.check_duplicate_names();
+        for meta in self.metas.iter() {
+            checker.check_meta(meta);
+        }
+        let mut issues = checker.issues;
+        issues.sort_by_key(|issue| std::cmp::Reverse(issue.severity));
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metalib::METALIB_HEADER_SIZE;
+    use crate::test_support::{parse, DB_XML, FULL_XML};
+
+    /// Validates FULL_XML after `corrupt` has been applied, returning the one issue found.
+    fn issue(corrupt: impl FnOnce(&mut Metalib)) -> ValidationIssue {
+        let mut metalib = parse(FULL_XML);
+        corrupt(&mut metalib);
+        metalib.reindex();
+        let mut issues = metalib.validate();
+        assert_eq!(issues.len(), 1, "{issues:#?}");
+        issues.remove(0)
+    }
+
+    fn meta_mut<'a>(metalib: &'a mut Metalib, name: &str) -> &'a mut TDRMeta {
+        metalib
+            .metas
+            .iter_mut()
+            .find(|meta| meta.name == name)
+            .unwrap()
+    }
+
+    fn check(
+        issue: &ValidationIssue,
+        severity: Severity,
+        meta: Option<&str>,
+        entry: Option<&str>,
+        message: &str,
+    ) {
+        assert_eq!(issue.severity, severity, "{issue}");
+        assert_eq!(issue.meta.as_deref(), meta, "{issue}");
+        assert_eq!(issue.entry.as_deref(), entry, "{issue}");
+        assert!(issue.message.contains(message), "{issue}");
+    }
+
+    #[test]
+    fn clean_fixture_has_no_issues() {
+        assert!(parse(FULL_XML).validate().is_empty());
+    }
+
+    #[test]
+    fn table_counts() {
+        let found = issue(|m| m.header.cur_macro_num = 3);
+        check(
+            &found,
+            Severity::Error,
+            None,
+            None,
+            "macros table has 4 rows but the header says 3",
+        );
+        assert_eq!(found.offset, 0);
+
+        let found = issue(|m| m.header.max_macros_group_num = 0);
+        check(
+            &found,
+            Severity::Warning,
+            None,
+            None,
+            "macrogroups count 1 is above its maximum 0",
+        );
+    }
+
+    #[test]
+    fn entry_counts() {
+        let found = issue(|m| meta_mut(m, "Sorted").entries_num = 2);
+        check(
+            &found,
+            Severity::Error,
+            Some("Sorted"),
+            None,
+            "entries_num is 2 but 1 entries",
+        );
+        let metalib = parse(FULL_XML);
+        let sorted = metalib.get_meta_by_name("Sorted").unwrap();
+        assert_eq!(found.offset, METALIB_HEADER_SIZE as u64 + sorted._offset);
+    }
+
+    #[test]
+    fn unresolved_references() {
+        let found = issue(|m| meta_mut(m, "Packet").entries[0].ptr_meta = 0x7FFF_0000);
+        check(
+            &found,
+            Severity::Error,
+            Some("Packet"),
+            Some("head"),
+            "ptr_meta 0x7FFF0000",
+        );
+
+        let found = issue(|m| meta_mut(m, "Packet").entries[1].ptr_macros_group = 4);
+        check(
+            &found,
+            Severity::Error,
+            Some("Packet"),
+            Some("kind"),
+            "ptr_macros_group 0x4",
+        );
+
+        let found = issue(|m| {
+            let header = meta_mut(m, "Header")._offset as i32;
+            meta_mut(m, "Packet").ptr_dependon_struct = header + 1;
+        });
+        check(
+            &found,
+            Severity::Error,
+            Some("Packet"),
+            None,
+            "ptr_dependon_struct",
+        );
+    }
+
+    #[test]
+    fn macro_indexes() {
+        let found = issue(|m| meta_mut(m, "Packet").entries[3].idx_count = 4);
+        check(
+            &found,
+            Severity::Error,
+            Some("Packet"),
+            Some("items"),
+            "idx_count 4 is outside the macro table (4 macros)",
+        );
+
+        let found = issue(|m| m.macrogroups[0].value_idx_map[0] = 40);
+        check(&found, Severity::Error, Some("Kind"), None, "Member 40");
+    }
+
+    #[test]
+    fn entry_ranges() {
+        let found = issue(|m| meta_mut(m, "Header").entries[1].h_off = 14);
+        check(
+            &found,
+            Severity::Error,
+            Some("Header"),
+            Some("ver"),
+            "Host range 14..18 doesn't fit in the parent's 16 bytes",
+        );
+
+        // Offsets near i32::MAX don't overflow the check.
+        let found = issue(|m| meta_mut(m, "Header").entries[1].n_off = i32::MAX);
+        check(
+            &found,
+            Severity::Error,
+            Some("Header"),
+            Some("ver"),
+            "Network range",
+        );
+    }
+
+    #[test]
+    fn meta_map_sizes() {
+        let found = issue(|m| m.meta_map[0].size += 1);
+        check(
+            &found,
+            Severity::Warning,
+            Some("Header"),
+            None,
+            "meta map gives size 17",
+        );
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let found = issue(|m| meta_mut(m, "Sorted").name = "Header".to_string());
+        check(
+            &found,
+            Severity::Error,
+            Some("Header"),
+            None,
+            "2 metas have this name",
+        );
+    }
+
+    #[test]
+    fn db_flags_and_defaults() {
+        let found = issue(|m| {
+            let uin = &mut meta_mut(m, "Account").entries[0];
+            uin.db_flag |= TDRMetaEntryDBFlags::AUTO_INCREMENT;
+        });
+        check(
+            &found,
+            Severity::Warning,
+            Some("Account"),
+            None,
+            "HAS_AUTOINCREMENT_ENTRY isn't set",
+        );
+
+        let found = issue(|m| {
+            meta_mut(m, "Packet").entries[1].db_flag |= TDRMetaEntryDBFlags::EXTEND_TO_TABLE;
+        });
+        check(
+            &found,
+            Severity::Error,
+            Some("Packet"),
+            Some("kind"),
+            "extendtotable",
+        );
+
+        let found = issue(|m| meta_mut(m, "Account").entries[1].default_val_len = 3);
+        check(
+            &found,
+            Severity::Warning,
+            Some("Account"),
+            Some("zone"),
+            "The default is 3 bytes",
+        );
+
+        let found = issue(|m| meta_mut(m, "Account").entries[1].count = i32::MAX);
+        check(
+            &found,
+            Severity::Warning,
+            Some("Account"),
+            Some("zone"),
+            "values of 2 bytes overflow",
+        );
+
+        let found = issue(|m| meta_mut(m, "Account").split_table_factor = -1);
+        check(
+            &found,
+            Severity::Error,
+            Some("Account"),
+            None,
+            "split_table_factor is -1",
+        );
+    }
+
+    #[test]
+    fn auto_increment_entries() {
+        let mut metalib = parse(DB_XML);
+        assert!(metalib.validate().is_empty());
+        let player = meta_mut(&mut metalib, "Player");
+        assert!(player.flags.contains(TDRMetaFlags::HAS_AUTOINCREMENT_ENTRY));
+
+        player.entries[1].db_flag |= TDRMetaEntryDBFlags::AUTO_INCREMENT;
+        let issues = metalib.validate();
+        let found: Vec<(Severity, &str)> = issues
+            .iter()
+            .map(|issue| (issue.severity, issue.message.as_str()))
+            .collect();
+        assert!(
+            found.contains(&(
+                Severity::Error,
+                "2 entries are autoincrement (id, stats), at most one is allowed"
+            )),
+            "{issues:#?}"
+        );
+    }
+
+    #[test]
+    fn split_table_factor_macro() {
+        let mut metalib = parse(DB_XML);
+        let friend = meta_mut(&mut metalib, "Friend");
+        assert_eq!((friend.idx_split_table_factor, friend.split_table_factor), (0, 16));
+
+        friend.idx_split_table_factor = 1;
+        let issues = metalib.validate();
+        assert_eq!(issues.len(), 1, "{issues:#?}");
+        check(
+            &issues[0],
+            Severity::Error,
+            Some("Friend"),
+            None,
+            "idx_split_table_factor 1 is outside the macro table (1 macros)",
+        );
+    }
+
+    #[test]
+    fn dependon_struct() {
+        let mut metalib = parse(DB_XML);
+        let player = metalib.get_meta_by_name("Player").unwrap()._offset as i32;
+        assert_eq!(meta_mut(&mut metalib, "Friend").ptr_dependon_struct, player);
+
+        let found = issue(|m| {
+            let body = m.get_meta_by_name("Body").unwrap()._offset as i32;
+            meta_mut(m, "Account").ptr_dependon_struct = body;
+        });
+        check(
+            &found,
+            Severity::Error,
+            Some("Account"),
+            None,
+            "ptr_dependon_struct points at `Body`, which isn't a struct",
+        );
+
+        let found = issue(|m| meta_mut(m, "Account").ptr_dependon_struct = 3);
+        check(
+            &found,
+            Severity::Error,
+            Some("Account"),
+            None,
+            "ptr_dependon_struct 0x3 doesn't point at a struct or union",
+        );
+    }
+
+    #[test]
+    fn errors_sort_first() {
+        let mut metalib = parse(FULL_XML);
+        metalib.meta_map[0].size += 1;
+        meta_mut(&mut metalib, "Sorted").entries_num = 2;
+        let issues = metalib.validate();
+        let severities: Vec<Severity> = issues.iter().map(|issue| issue.severity).collect();
+        assert_eq!(severities, [Severity::Error, Severity::Warning]);
+        assert!(issues[1]
+            .to_string()
+            .starts_with("warning: Header: The meta map"));
+    }
+}
@@ -0,0 +1,29 @@
+//! WebAssembly bindings for JavaScript (built with the `wasm` feature), e.g. with
+//! `wasm-pack build --target web -- --features wasm`.
+//!
+//! Both functions take the bytes of a compiled metalib starting at offset 0 and throw an
+//! `Error` with the parse failure.
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::metalib::Metalib;
+use crate::xml::export_metalib_xml;
+
+fn parse(bytes: &[u8]) -> Result<Metalib, JsError> {
+    Metalib::from_bytes(bytes).map_err(|e| JsError::new(&format!("{e:#}")))
+}
+
+/// Parses a metalib into the same model the `serde` feature serializes, as a plain object.
+#[wasm_bindgen(js_name = parseMetalib)]
+pub fn parse_metalib(bytes: &[u8]) -> Result<JsValue, JsError> {
+    let metalib = parse(bytes)?;
+    let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+    Ok(metalib.serialize(&serializer)?)
+}
+
+/// Parses a metalib and exports it as TDR XML.
+#[wasm_bindgen(js_name = metalibToXml)]
+pub fn metalib_to_xml(bytes: &[u8]) -> Result<String, JsError> {
+    export_metalib_xml(&parse(bytes)?).map_err(|e| JsError::new(&format!("{e:#}")))
+}
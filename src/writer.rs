@@ -0,0 +1,735 @@
+//! Writing a [`Metalib`] back out in the compiled format (in its 32 or 64-bit layout), so
+//! edited metalibs can be loaded by the game and server tooling again.
+//!
+//! The body is laid out afresh rather than at the offsets it was read from: macros, the id,
+//! name and map tables, the metas with their entries, the macrogroups, the primary key
+//! tables, and finally the deduplicated string buffer. Pointers between records are
+//! remapped to the new layout.
+
+use anyhow::{anyhow, Context, Result};
+use byteorder::{LittleEndian, WriteBytesExt};
+use encoding::all::GBK;
+use encoding::{EncoderTrap, Encoding};
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::arch::MetalibArch;
+use crate::metalib::{
+    encode_default_value, Metalib, RawString, RecordSizes, StringField, TDRMacroGroup, TDRMeta,
+    TDRMetaEntry, INVALID_METALIB_VALUE,
+};
+
+/// Size of the fixed name buffers in the header and macrogroups.
+const FIXED_NAME_SIZE: usize = 128;
+
+/// Where each table and record goes in a freshly laid out body.
+pub(crate) struct BodyLayout {
+    pub ptr_id: u32,
+    pub ptr_name: u32,
+    pub ptr_map: u32,
+    pub ptr_meta: u32,
+    pub meta_offsets: Vec<u32>,
+    pub ptr_macros_group: u32,
+    pub group_offsets: Vec<u32>,
+
+    /// Offset of each meta's primary key table, or -1 if it has none.
+    pub key_offsets: Vec<i32>,
+    pub ptr_str_buf: u32,
+    sizes: &'static RecordSizes,
+}
+
+impl BodyLayout {
+    /// Lays out `macro_num` macros, metas with the given numbers of entries, macrogroups
+    /// with room for the given numbers of members, and primary keys with the given numbers
+    /// of members (one per meta), with records of `sizes`.
+    pub fn new(
+        sizes: &'static RecordSizes,
+        macro_num: usize,
+        entry_nums: impl ExactSizeIterator<Item = usize>,
+        group_sizes: impl Iterator<Item = usize>,
+        key_nums: impl Iterator<Item = usize>,
+    ) -> BodyLayout {
+        let meta_num = entry_nums.len() as u32;
+        let ptr_id = macro_num as u32 * sizes.macro_;
+        let ptr_name = ptr_id + meta_num * sizes.id_entry;
+        let ptr_map = ptr_name + meta_num * sizes.table_entry;
+        let ptr_meta = ptr_map + meta_num * sizes.table_entry;
+
+        let mut offset = ptr_meta;
+        let mut meta_offsets = Vec::new();
+        for entry_num in entry_nums {
+            meta_offsets.push(offset);
+            offset += sizes.meta + entry_num as u32 * sizes.meta_entry;
+        }
+
+        let ptr_macros_group = offset;
+        let mut group_offsets = Vec::new();
+        for group_size in group_sizes {
+            group_offsets.push(offset);
+            // Followed by the name and value index maps.
+            offset += sizes.macro_group + 2 * 4 * group_size as u32;
+        }
+
+        let mut key_offsets = Vec::new();
+        for key_num in key_nums {
+            if key_num == 0 {
+                key_offsets.push(INVALID_METALIB_VALUE);
+                continue;
+            }
+            key_offsets.push(offset as i32);
+            offset += key_num as u32 * sizes.db_key_info;
+        }
+
+        BodyLayout {
+            ptr_id,
+            ptr_name,
+            ptr_map,
+            ptr_meta,
+            meta_offsets,
+            ptr_macros_group,
+            group_offsets,
+            key_offsets,
+            ptr_str_buf: offset,
+            sizes,
+        }
+    }
+
+    /// Offset of entry `entry` of the meta at `meta`.
+    pub fn entry_offset(&self, meta: usize, entry: usize) -> u32 {
+        self.meta_offsets[meta] + self.sizes.meta + entry as u32 * self.sizes.meta_entry
+    }
+}
+
+/// Strings and default values collected for the string buffer, deduplicated.
+pub(crate) struct StringBuffer {
+    /// Body offset of the buffer.
+    base: u32,
+    pub data: Vec<u8>,
+    strings: HashMap<Vec<u8>, u32>,
+    /// Bytes to write instead of the GBK encoding of these record fields.
+    raw_strings: HashMap<(u64, StringField), Vec<u8>>,
+}
+
+impl StringBuffer {
+    pub fn new(base: u32) -> StringBuffer {
+        StringBuffer {
+            base,
+            data: Vec::new(),
+            strings: HashMap::new(),
+            raw_strings: HashMap::new(),
+        }
+    }
+
+    /// Writes the original bytes of these strings, as kept by
+    /// [`ParseOptions::keep_raw_strings`](crate::options::ParseOptions::keep_raw_strings).
+    pub fn with_raw_strings(mut self, raw_strings: &[RawString]) -> StringBuffer {
+        for raw in raw_strings {
+            self.raw_strings
+                .entry((raw.record, raw.field))
+                .or_insert_with(|| raw.bytes.clone());
+        }
+        self
+    }
+
+    /// Adds `bytes` and returns their body offset.
+    pub fn add(&mut self, bytes: Vec<u8>) -> i32 {
+        let offset = self.base + self.data.len() as u32;
+        *self.strings.entry(bytes).or_insert_with_key(|bytes| {
+            self.data.extend_from_slice(bytes);
+            offset
+        }) as i32
+    }
+
+    /// Adds a GBK string, or returns -1 for an empty one like the compiler does.
+    pub fn add_string(&mut self, string: &str) -> Result<i32> {
+        if string.is_empty() {
+            return Ok(INVALID_METALIB_VALUE);
+        }
+        let mut bytes = encode_string(string)?;
+        bytes.push(0);
+        Ok(self.add(bytes))
+    }
+
+    /// Adds `string`, the `field` of the record at `record`, as its raw bytes if it has
+    /// them or as [`add_string`](Self::add_string) does if not.
+    pub fn add_field(&mut self, record: u64, field: StringField, string: &str) -> Result<i32> {
+        match self.raw_strings.get(&(record, field)) {
+            Some(raw) => {
+                let mut bytes = raw.clone();
+                bytes.push(0);
+                Ok(self.add(bytes))
+            }
+            None => self.add_string(string),
+        }
+    }
+
+    /// Adds the default value of `entry` (its raw bytes, if it has them), or returns -1 if
+    /// it has none.
+    pub fn add_default(&mut self, entry: &TDRMetaEntry) -> Result<i32> {
+        if entry.ptr_default_val == INVALID_METALIB_VALUE {
+            return Ok(INVALID_METALIB_VALUE);
+        }
+        if let Some(raw) = self.raw_strings.get(&(entry._offset, StringField::Default)) {
+            return Ok(self.add(raw.clone()));
+        }
+        Ok(self.add(encode_default_value(
+            entry.idx_type,
+            &entry.default_value_string,
+        )?))
+    }
+}
+
+/// Encodes `string` as GBK, without a terminator.
+pub(crate) fn encode_string(string: &str) -> Result<Vec<u8>> {
+    GBK.encode(string, EncoderTrap::Strict)
+        .map_err(|_| anyhow!("`{string}` can't be encoded as GBK"))
+}
+
+/// Old body offsets of metas, entries and macrogroups, and where they moved to.
+struct Relocations {
+    offsets: HashMap<i32, i32>,
+}
+
+impl Relocations {
+    /// Maps a pointer to its new value. -1 stays -1.
+    fn map(&self, ptr: i32, field: &str) -> Result<i32> {
+        if ptr == INVALID_METALIB_VALUE {
+            return Ok(ptr);
+        }
+        self.offsets
+            .get(&ptr)
+            .copied()
+            .ok_or_else(|| anyhow!("{field} {ptr:#X} doesn't point at a meta, entry or macrogroup"))
+    }
+}
+
+/// Pads `w` with zeros to the next multiple of 8 in the 64-bit layout, where pointers and
+/// the records holding them are 8-byte aligned.
+fn align_pointer(w: &mut Vec<u8>, arch: MetalibArch) {
+    if arch == MetalibArch::Bits64 {
+        w.resize(w.len().next_multiple_of(8), 0);
+    }
+}
+
+/// Writes a pointer (a body offset) in the width of `arch`.
+fn write_pointer(w: &mut Vec<u8>, arch: MetalibArch, ptr: i32) -> Result<()> {
+    match arch {
+        MetalibArch::Bits32 => w.write_i32::<LittleEndian>(ptr)?,
+        MetalibArch::Bits64 => {
+            align_pointer(w, arch);
+            w.write_i64::<LittleEndian>(ptr as i64)?;
+        }
+    }
+    Ok(())
+}
+
+/// Number of macros the index maps of `group` have room for.
+fn group_capacity(group: &TDRMacroGroup) -> i32 {
+    group.max_macro_count.max(group.name_idx_map.len() as i32)
+}
+
+fn write_fixed_name(w: &mut impl Write, name: &str) -> Result<()> {
+    let bytes = name.as_bytes();
+    if bytes.len() >= FIXED_NAME_SIZE {
+        return Err(anyhow!(
+            "Name `{name}` is longer than {} bytes",
+            FIXED_NAME_SIZE - 1
+        ));
+    }
+    w.write_all(bytes)?;
+    w.write_all(&[0; FIXED_NAME_SIZE][bytes.len()..])?;
+    Ok(())
+}
+
+/// Serializes `metalib` in the compiled format: header, tables, and string buffer.
+///
+/// The tables use the layout in `metalib.arch`; the header is the same in both, laid out
+/// for its `build`. Sizes, counts and table pointers in the header are recomputed.
+/// Everything else is written as it was read, so `read_metalib` gives back the same model,
+/// up to offsets.
+pub fn write_metalib(metalib: &Metalib, w: &mut impl Write) -> Result<()> {
+    let arch = metalib.arch;
+    let sizes = arch.record_sizes();
+    let layout = BodyLayout::new(
+        sizes,
+        metalib.macros.len(),
+        metalib.metas.iter().map(|meta| meta.entries.len()),
+        metalib
+            .macrogroups
+            .iter()
+            .map(|group| group_capacity(group) as usize),
+        metalib.metas.iter().map(|meta| meta.primary_key.len()),
+    );
+
+    let mut relocations = Relocations {
+        offsets: HashMap::new(),
+    };
+    for (idx, meta) in metalib.metas.iter().enumerate() {
+        relocations
+            .offsets
+            .insert(meta._offset as i32, layout.meta_offsets[idx] as i32);
+        for (entry_idx, entry) in meta.entries.iter().enumerate() {
+            relocations.offsets.insert(
+                entry._offset as i32,
+                layout.entry_offset(idx, entry_idx) as i32,
+            );
+        }
+    }
+    for (idx, group) in metalib.macrogroups.iter().enumerate() {
+        relocations
+            .offsets
+            .insert(group._offset as i32, layout.group_offsets[idx] as i32);
+    }
+
+    let mut strings = StringBuffer::new(layout.ptr_str_buf).with_raw_strings(&metalib.raw_strings);
+    let mut body: Vec<u8> = Vec::new();
+
+    for tdr_macro in metalib.macros.iter() {
+        let record = tdr_macro._offset;
+        let name = strings.add_field(record, StringField::Name, &tdr_macro.name)?;
+        write_pointer(&mut body, arch, name)?;
+        body.write_i32::<LittleEndian>(tdr_macro.value)?;
+        let desc = strings.add_field(record, StringField::Desc, &tdr_macro.desc)?;
+        write_pointer(&mut body, arch, desc)?;
+        body.write_i32::<LittleEndian>(tdr_macro.unk)?;
+        align_pointer(&mut body, arch);
+    }
+
+    let meta_table = |name: &str, len: usize| -> Result<()> {
+        if len != metalib.metas.len() {
+            return Err(anyhow!(
+                "The {name} table has {len} rows but there are {} metas",
+                metalib.metas.len()
+            ));
+        }
+        Ok(())
+    };
+    meta_table("ids", metalib.ids.len())?;
+    meta_table("names", metalib.names.len())?;
+    meta_table("meta map", metalib.meta_map.len())?;
+
+    for id in metalib.ids.iter() {
+        body.write_i32::<LittleEndian>(id.id)?;
+        body.write_i32::<LittleEndian>(id.idx)?;
+    }
+    for name in metalib.names.iter() {
+        let ptr = strings.add_field(name._offset, StringField::Name, &name.name)?;
+        write_pointer(&mut body, arch, ptr)?;
+        body.write_i32::<LittleEndian>(name.idx)?;
+        align_pointer(&mut body, arch);
+    }
+    for map_entry in metalib.meta_map.iter() {
+        let ptr = relocations.map(map_entry.ptr, "Meta map pointer")?;
+        write_pointer(&mut body, arch, ptr)?;
+        body.write_i32::<LittleEndian>(map_entry.size)?;
+        align_pointer(&mut body, arch);
+    }
+
+    for (idx, meta) in metalib.metas.iter().enumerate() {
+        let offset = layout.meta_offsets[idx] as i32;
+        debug_assert_eq!(body.len() as i32, offset);
+        let ptr_primary_key = layout.key_offsets[idx];
+        write_tdr_meta(
+            &mut body,
+            arch,
+            meta,
+            offset,
+            ptr_primary_key,
+            &relocations,
+            &mut strings,
+        )
+        .with_context(|| format!("Failed to write meta `{}`", meta.name))?;
+    }
+
+    for group in metalib.macrogroups.iter() {
+        let count = group.name_idx_map.len() as i32;
+        if group.value_idx_map.len() as i32 != count {
+            return Err(anyhow!(
+                "Macrogroup `{}` has {count} names but {} values",
+                group.name,
+                group.value_idx_map.len()
+            ));
+        }
+        let capacity = group_capacity(group);
+        body.write_i32::<LittleEndian>(count)?;
+        body.write_i32::<LittleEndian>(capacity)?;
+        let desc = strings.add_field(group._offset, StringField::Desc, &group.desc)?;
+        write_pointer(&mut body, arch, desc)?;
+        write_pointer(&mut body, arch, sizes.macro_group as i32)?;
+        write_pointer(&mut body, arch, sizes.macro_group as i32 + 4 * capacity)?;
+        write_fixed_name(&mut body, &group.name)?;
+        for map in [&group.name_idx_map, &group.value_idx_map] {
+            for &idx in map.iter() {
+                body.write_i32::<LittleEndian>(idx)?;
+            }
+            // Unused slots are -1, like the compiler leaves them.
+            for _ in count..capacity {
+                body.write_i32::<LittleEndian>(INVALID_METALIB_VALUE)?;
+            }
+        }
+    }
+
+    for (idx, meta) in metalib.metas.iter().enumerate() {
+        debug_assert!(meta.primary_key.is_empty() || body.len() as i32 == layout.key_offsets[idx]);
+        for key in meta.primary_key.iter() {
+            body.write_i32::<LittleEndian>(key.h_off)?;
+            let ptr_entry = relocations
+                .map(key.ptr_entry, "Primary key entry")
+                .with_context(|| format!("Failed to write meta `{}`", meta.name))?;
+            write_pointer(&mut body, arch, ptr_entry)?;
+            align_pointer(&mut body, arch);
+        }
+    }
+
+    debug_assert_eq!(body.len() as u32, layout.ptr_str_buf);
+    body.extend_from_slice(&strings.data);
+    let ptr_free_str_buf = body.len() as u32;
+    let free_str_buf_size = metalib.header.free_str_buf_size.max(0);
+    body.resize(body.len() + free_str_buf_size as usize, 0);
+
+    let header = &metalib.header;
+    let header_layout = header.layout();
+    let meta_num = metalib.metas.len() as i32;
+    let macro_num = metalib.macros.len() as i32;
+    let group_num = metalib.macrogroups.len() as i32;
+    let mut out: Vec<u8> = Vec::new();
+    out.write_u16::<LittleEndian>(header.magic)?;
+    out.write_u16::<LittleEndian>(header.build)?;
+    out.write_u32::<LittleEndian>(header.platform_arch)?;
+    out.write_u32::<LittleEndian>(header_layout.size + body.len() as u32)?;
+    out.write_u32::<LittleEndian>(header.field_c)?;
+    out.write_u32::<LittleEndian>(header.field_10)?;
+    out.write_u32::<LittleEndian>(header.field_14)?;
+    out.write_u32::<LittleEndian>(header.field_18)?;
+    out.write_i32::<LittleEndian>(header.id)?;
+    out.write_u32::<LittleEndian>(header.xml_tag_set_ver)?;
+    out.write_u32::<LittleEndian>(header.field_24)?;
+    out.write_i32::<LittleEndian>(header.max_meta_num.max(meta_num))?;
+    out.write_i32::<LittleEndian>(meta_num)?;
+    out.write_i32::<LittleEndian>(header.max_macro_num.max(macro_num))?;
+    out.write_i32::<LittleEndian>(macro_num)?;
+    out.write_i32::<LittleEndian>(header.max_macros_group_num.max(group_num))?;
+    out.write_i32::<LittleEndian>(group_num)?;
+    out.write_u32::<LittleEndian>(header.field_40)?;
+    out.write_u32::<LittleEndian>(header.field_44)?;
+    out.write_u32::<LittleEndian>(header.version)?;
+    out.write_u32::<LittleEndian>(0)?; // ptr_macro
+    out.write_u32::<LittleEndian>(layout.ptr_id)?;
+    out.write_u32::<LittleEndian>(layout.ptr_name)?;
+    out.write_u32::<LittleEndian>(layout.ptr_map)?;
+    out.write_u32::<LittleEndian>(layout.ptr_meta)?;
+    out.write_u32::<LittleEndian>(
+        layout
+            .meta_offsets
+            .last()
+            .copied()
+            .unwrap_or(layout.ptr_meta),
+    )?;
+    out.write_i32::<LittleEndian>(free_str_buf_size)?;
+    out.write_u32::<LittleEndian>(layout.ptr_str_buf)?;
+    out.write_u32::<LittleEndian>(ptr_free_str_buf)?;
+    out.write_u32::<LittleEndian>(layout.ptr_macros_group)?; // ptr_macro_group_map
+    out.write_u32::<LittleEndian>(layout.ptr_macros_group)?;
+    if header_layout.has_field_78 {
+        out.write_u32::<LittleEndian>(header.field_78)?;
+        out.write_i32::<LittleEndian>(header.field_7c)?;
+        out.write_i32::<LittleEndian>(header.field_80)?;
+        out.write_u32::<LittleEndian>(header.field_84)?;
+        out.write_u32::<LittleEndian>(header.field_88)?;
+        out.write_i32::<LittleEndian>(header.field_8c)?;
+        out.write_i32::<LittleEndian>(header.field_90)?;
+    }
+    write_fixed_name(&mut out, &header.name)?;
+    debug_assert_eq!(out.len() as u32, header_layout.size);
+
+    w.write_all(&out)?;
+    w.write_all(&body)?;
+    Ok(())
+}
+
+fn write_tdr_meta(
+    w: &mut Vec<u8>,
+    arch: MetalibArch,
+    meta: &TDRMeta,
+    offset: i32,
+    ptr_primary_key: i32,
+    relocations: &Relocations,
+    strings: &mut StringBuffer,
+) -> Result<()> {
+    w.write_u32::<LittleEndian>(meta.flags.bits())?;
+    w.write_i32::<LittleEndian>(meta.id)?;
+    w.write_i32::<LittleEndian>(meta.base_version)?;
+    w.write_i32::<LittleEndian>(meta.cur_version)?;
+    w.write_i32::<LittleEndian>(meta.raw_type)?;
+    w.write_i32::<LittleEndian>(meta.mem_size)?;
+    w.write_i32::<LittleEndian>(meta.n_unit_size)?;
+    w.write_i32::<LittleEndian>(meta.h_unit_size)?;
+    w.write_i32::<LittleEndian>(meta.custom_h_unit_size)?;
+    w.write_i32::<LittleEndian>(meta.idx_custom_h_unit_size)?;
+    w.write_i32::<LittleEndian>(meta.uncertain_max_sub_id)?;
+    w.write_i32::<LittleEndian>(meta.entries.len() as i32)?;
+    w.write_i32::<LittleEndian>(meta.unk_table_count)?;
+    write_pointer(w, arch, meta.unk_table_ptr)?;
+    w.write_i32::<LittleEndian>(meta.unk_table_unk)?;
+    write_pointer(w, arch, offset)?;
+    w.write_i32::<LittleEndian>(meta.idx)?;
+    w.write_i32::<LittleEndian>(meta.idx_id)?;
+    w.write_i32::<LittleEndian>(meta.idx_type)?;
+    w.write_i32::<LittleEndian>(meta.idx_version)?;
+    w.write_i32::<LittleEndian>(meta.custom_align)?;
+    w.write_i32::<LittleEndian>(meta.valid_align)?;
+    w.write_i32::<LittleEndian>(meta.uncertain_version_indicator_min_ver)?;
+
+    w.write_i32::<LittleEndian>(meta.size_type.n_off)?;
+    w.write_i32::<LittleEndian>(meta.size_type.h_off)?;
+    w.write_i32::<LittleEndian>(meta.size_type.unit_size)?;
+    w.write_i32::<LittleEndian>(meta.size_type.idx_size_type)?;
+
+    w.write_i32::<LittleEndian>(meta.version_indicator.n_off)?;
+    w.write_i32::<LittleEndian>(meta.version_indicator.h_off)?;
+    w.write_i32::<LittleEndian>(meta.version_indicator.unit_size)?;
+
+    w.write_i32::<LittleEndian>(meta.sort_key.idx_sort_entry)?;
+    w.write_i32::<LittleEndian>(meta.sort_key.sort_key_offset)?;
+    let sort_key_meta = relocations.map(meta.sort_key.ptr_sort_key_meta, "Sort key meta")?;
+    write_pointer(w, arch, sort_key_meta)?;
+
+    for (field, string) in [
+        (StringField::Name, &meta.name),
+        (StringField::Desc, &meta.desc),
+        (StringField::ChineseName, &meta.chinese_name),
+    ] {
+        write_pointer(w, arch, strings.add_field(meta._offset, field, string)?)?;
+    }
+    w.write_i32::<LittleEndian>(meta.split_table_factor)?;
+    w.write_i16::<LittleEndian>(meta.split_table_rule_id)?;
+    let primary_key_member_num = i16::try_from(meta.primary_key.len())
+        .map_err(|_| anyhow!("{} primary key members is too many", meta.primary_key.len()))?;
+    w.write_i16::<LittleEndian>(primary_key_member_num)?;
+    w.write_i32::<LittleEndian>(meta.idx_split_table_factor)?;
+    w.write_i32::<LittleEndian>(meta.split_table_key.h_off)?;
+    let split_table_key =
+        relocations.map(meta.split_table_key.ptr_entry, "Split table key entry")?;
+    write_pointer(w, arch, split_table_key)?;
+    write_pointer(w, arch, ptr_primary_key)?;
+    let dependon_struct = relocations.map(meta.ptr_dependon_struct, "ptr_dependon_struct")?;
+    write_pointer(w, arch, dependon_struct)?;
+    w.write_i32::<LittleEndian>(meta.field_ac)?;
+    w.write_i32::<LittleEndian>(meta.field_b0)?;
+    w.write_i32::<LittleEndian>(meta.field_b4)?;
+    align_pointer(w, arch);
+
+    for entry in meta.entries.iter() {
+        write_tdr_meta_entry(w, arch, entry, relocations, strings)
+            .with_context(|| format!("Failed to write entry `{}`", entry.name))?;
+    }
+    Ok(())
+}
+
+fn write_tdr_meta_entry(
+    w: &mut Vec<u8>,
+    arch: MetalibArch,
+    entry: &TDRMetaEntry,
+    relocations: &Relocations,
+    strings: &mut StringBuffer,
+) -> Result<()> {
+    w.write_i32::<LittleEndian>(entry.id)?;
+    w.write_i32::<LittleEndian>(entry.version)?;
+    w.write_i32::<LittleEndian>(entry.raw_type)?;
+    write_pointer(
+        w,
+        arch,
+        strings.add_field(entry._offset, StringField::Name, &entry.name)?,
+    )?;
+    w.write_i32::<LittleEndian>(entry.h_real_size)?;
+    w.write_i32::<LittleEndian>(entry.n_real_size)?;
+    w.write_i32::<LittleEndian>(entry.h_unit_size)?;
+    w.write_i32::<LittleEndian>(entry.n_unit_size)?;
+    w.write_i32::<LittleEndian>(entry.custom_h_unit_size)?;
+    w.write_i32::<LittleEndian>(entry.count)?;
+    w.write_i32::<LittleEndian>(entry.n_off)?;
+    w.write_i32::<LittleEndian>(entry.h_off)?;
+    w.write_i32::<LittleEndian>(entry.idx_id)?;
+    w.write_i32::<LittleEndian>(entry.idx_version)?;
+    w.write_i32::<LittleEndian>(entry.idx_count)?;
+    w.write_i32::<LittleEndian>(entry.idx_type)?;
+    w.write_i32::<LittleEndian>(entry.idx_custom_h_unit_size)?;
+    w.write_u16::<LittleEndian>(entry.flag.bits())?;
+    w.write_u8(entry.db_flag.bits())?;
+    w.write_u8(entry.order)?;
+
+    w.write_i32::<LittleEndian>(entry.size_info.n_off)?;
+    w.write_i32::<LittleEndian>(entry.size_info.h_off)?;
+    w.write_i32::<LittleEndian>(entry.size_info.unit_size)?;
+    w.write_i32::<LittleEndian>(entry.size_info.idx_size_type)?;
+
+    for selector in [&entry.referer, &entry.selector] {
+        w.write_i32::<LittleEndian>(selector.unit_size)?;
+        w.write_i32::<LittleEndian>(selector.h_off)?;
+        write_pointer(
+            w,
+            arch,
+            relocations.map(selector.ptr_entry, "Selector entry")?,
+        )?;
+    }
+
+    w.write_i32::<LittleEndian>(entry.io)?;
+    w.write_i32::<LittleEndian>(entry.idx_io)?;
+    write_pointer(w, arch, relocations.map(entry.ptr_meta, "ptr_meta")?)?;
+    w.write_i32::<LittleEndian>(entry.max_id)?;
+    w.write_i32::<LittleEndian>(entry.min_id)?;
+    w.write_i32::<LittleEndian>(entry.max_id_idx)?;
+    w.write_i32::<LittleEndian>(entry.min_id_idx)?;
+    w.write_i32::<LittleEndian>(entry.default_val_len)?;
+    write_pointer(
+        w,
+        arch,
+        strings.add_field(entry._offset, StringField::Desc, &entry.desc)?,
+    )?;
+    let chinese_name =
+        strings.add_field(entry._offset, StringField::ChineseName, &entry.chinese_name)?;
+    write_pointer(w, arch, chinese_name)?;
+    write_pointer(w, arch, strings.add_default(entry)?)?;
+    let macros_group = relocations.map(entry.ptr_macros_group, "ptr_macros_group")?;
+    write_pointer(w, arch, macros_group)?;
+    let custom_attr =
+        strings.add_field(entry._offset, StringField::CustomAttr, &entry.custom_attr)?;
+    write_pointer(w, arch, custom_attr)?;
+    w.write_i32::<LittleEndian>(entry.off_to_meta)?;
+    w.write_i32::<LittleEndian>(entry.field_a8)?;
+    w.write_i32::<LittleEndian>(entry.field_ac)?;
+    w.write_i32::<LittleEndian>(entry.field_b0)?;
+    align_pointer(w, arch);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::metalib::read_metalib;
+    use crate::options::{ExportOptions, ParseOptions, XmlEncoding};
+    use crate::test_support::{compile_bytes, parse_with, FULL_XML, TYPES_XML};
+    use crate::xml::{encode_xml, export_metalib_xml, export_metalib_xml_with};
+
+    #[test]
+    fn read_write_round_trip() {
+        for xml in [FULL_XML, TYPES_XML] {
+            for arch in [MetalibArch::Bits32, MetalibArch::Bits64] {
+                let bytes = compile_bytes(xml, arch);
+                let metalib = read_metalib(&mut Cursor::new(&bytes)).unwrap();
+                let mut written = Vec::new();
+                write_metalib(&metalib, &mut written).unwrap();
+                assert!(written == bytes, "{arch:?} bytes differ");
+
+                let read = read_metalib(&mut Cursor::new(&written)).unwrap();
+                assert_eq!(format!("{read:?}"), format!("{metalib:?}"));
+            }
+        }
+    }
+
+    #[test]
+    fn edits_survive_a_rewrite() {
+        let bytes = compile_bytes(FULL_XML, MetalibArch::Bits32);
+        let mut metalib = read_metalib(&mut Cursor::new(&bytes)).unwrap();
+        let packet = metalib
+            .metas
+            .iter_mut()
+            .find(|m| m.name == "Packet")
+            .unwrap();
+        packet.entries[2].name = "count_of_items_renamed".to_string();
+        packet.base_version = 2;
+        metalib.header.version = 4;
+
+        let mut written = Vec::new();
+        write_metalib(&metalib, &mut written).unwrap();
+        let read = read_metalib(&mut Cursor::new(&written)).unwrap();
+        let packet = read.get_meta_by_name("Packet").unwrap();
+        assert_eq!(packet.entries[2].name, "count_of_items_renamed");
+        assert_eq!(packet.base_version, 2);
+        assert_eq!(read.header.version, 4);
+        assert_eq!(read.header.size as usize, written.len());
+        // The longer name goes in the string buffer, and the `refer` path still resolves.
+        let items = &packet.entries[3];
+        let refer = items.referer.resolve(&read, packet).unwrap().unwrap();
+        assert_eq!(refer.path, "count_of_items_renamed");
+    }
+
+    #[test]
+    fn raw_strings_round_trip() {
+        let xml = r#"<metalib tagsetversion="1" name="raw" version="1">
+            <macro name="LIMIT" value="4" desc="M1"/>
+            <struct name="Item" version="1" cname="C1" desc="D1">
+                <entry name="N1" type="int" desc="E1"/>
+                <entry name="title" type="string" size="8" desc="E2" default="S1"/>
+                <entry name="tag" type="char" count="4" default="T1" customattr="A1"/>
+            </struct>
+        </metalib>"#;
+        // Each placeholder becomes its letter and a byte GBK can't decode. `E1` and `E2` end
+        // up as the same text.
+        let mut bytes = compile_bytes(xml, MetalibArch::Bits32);
+        for (placeholder, bad) in [
+            (b"M1", 0xFF),
+            (b"C1", 0xFF),
+            (b"D1", 0xFF),
+            (b"N1", 0xFF),
+            (b"E1", 0xFF),
+            (b"E2", 0xFE),
+            (b"S1", 0xFF),
+            (b"T1", 0xFF),
+            (b"A1", 0xFF),
+        ] {
+            let needle = [placeholder[0], placeholder[1], 0];
+            let at: Vec<usize> = (0..bytes.len() - 2)
+                .filter(|&at| bytes[at..at + 3] == needle)
+                .collect();
+            assert_eq!(at.len(), 1, "{:?}", std::str::from_utf8(placeholder));
+            bytes[at[0] + 1] = bad;
+        }
+
+        // Without the raw bytes the lossy text can't be written back.
+        let metalib = parse_with(&bytes, &ParseOptions::default()).unwrap();
+        assert!(metalib.raw_strings.is_empty());
+        let entries = &metalib.metas[0].entries;
+        assert_eq!(entries[0].desc, "E\u{FFFD}");
+        assert_eq!(entries[1].desc, "E\u{FFFD}");
+        assert!(write_metalib(&metalib, &mut Vec::new()).is_err());
+
+        let options = ParseOptions::default().keep_raw_strings(true);
+        let metalib = parse_with(&bytes, &options).unwrap();
+        assert_eq!(metalib.raw_strings.len(), 9);
+        let entries = &metalib.metas[0].entries;
+        let raw = |record, field| metalib.raw_string(record, field).unwrap().bytes.as_slice();
+        assert_eq!(raw(entries[0]._offset, StringField::Desc), b"E\xFF");
+        assert_eq!(raw(entries[1]._offset, StringField::Desc), b"E\xFE");
+        assert_eq!(raw(entries[1]._offset, StringField::Default), b"S\xFF\0");
+        assert_eq!(raw(entries[2]._offset, StringField::Default), b"T\xFF\0");
+        assert_eq!(raw(entries[2]._offset, StringField::CustomAttr), b"A\xFF");
+        assert_eq!(raw(metalib.macros[0]._offset, StringField::Desc), b"M\xFF");
+        let mut written = Vec::new();
+        write_metalib(&metalib, &mut written).unwrap();
+        assert!(written == bytes, "bytes differ");
+
+        // GBK XML carries the same bytes, UTF-8 XML the text.
+        let options = ExportOptions::default().encoding(XmlEncoding::Gbk);
+        let xml = export_metalib_xml_with(&metalib, &options).unwrap();
+        let gbk = encode_xml(&metalib, xml, XmlEncoding::Gbk).unwrap();
+        for attr in [
+            &b"<macro name=\"LIMIT\" value=\"4\" desc=\"M\xFF\" />"[..],
+            b" cname=\"C\xFF\" desc=\"D\xFF\"",
+            b"<entry name=\"N\xFF\" type=\"int\" desc=\"E\xFF\"/>",
+            b" desc=\"E\xFE\" default=\"S\xFF\"/>",
+            b" default=\"T\xFF\" customattr=\"A\xFF\"/>",
+        ] {
+            assert!(
+                gbk.windows(attr.len()).any(|w| w == attr),
+                "{}",
+                attr.escape_ascii()
+            );
+        }
+        let utf8 = export_metalib_xml(&metalib).unwrap();
+        assert_eq!(utf8.matches("E\u{FFFD}").count(), 2);
+        assert!(!utf8.contains('\u{FDD0}'));
+    }
+}
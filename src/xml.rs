@@ -0,0 +1,1151 @@
+//! Writing a parsed metalib back out as TDR XML, in the same form the tdr tools accept.
+
+use anyhow::{anyhow, Context, Result};
+use encoding::all::GBK;
+use encoding::{EncoderTrap, Encoding};
+use log::warn;
+
+// Needed to prevent namespace clash.
+use std::fmt::Write as _;
+
+use crate::metalib::{
+    self, MetaPrimativeType, Metalib, SizeSource, StringField, TDRMetaEntryDBFlags,
+    TDRMetaEntryFlags, TDRMetaFlags, INVALID_METALIB_VALUE,
+};
+use crate::options::{ExportOptions, XmlEncoding};
+use crate::select::{MetaSelection, VersionCut};
+
+/// Start and end of the marker [`kept_string`] writes, with an index into
+/// `Metalib::raw_strings` between them. Noncharacters, which don't turn up in metalib strings.
+const RAW_STRING_START: char = '\u{FDD0}';
+const RAW_STRING_END: char = '\u{FDD1}';
+
+/// Returns `text`, the `field` of the record at `record`. Written as GBK, a string the
+/// metalib kept the original bytes of comes back as a marker instead, which [`encode_xml`]
+/// puts those bytes in place of.
+fn kept_string(
+    metalib: &Metalib,
+    options: &ExportOptions,
+    record: u64,
+    field: StringField,
+    text: &str,
+) -> String {
+    match metalib.raw_string_index(record, field) {
+        Some(idx) if options.encoding == XmlEncoding::Gbk => {
+            format!("{RAW_STRING_START}{idx}{RAW_STRING_END}")
+        }
+        _ => text.to_string(),
+    }
+}
+
+fn dump_tdr_macro_xml(
+    metalib: &Metalib,
+    tdr_macro: &metalib::TDRMacro,
+    options: &ExportOptions,
+) -> Result<String> {
+    let kept = |field, text: &str| kept_string(metalib, options, tdr_macro._offset, field, text);
+    let mut out = String::new();
+    write!(&mut out, "<macro")?;
+    let name = kept(StringField::Name, &tdr_macro.name);
+    write!(&mut out, " name=\"{}\"", xml_escape_attr(&name))?;
+    write!(&mut out, " value=\"{}\"", tdr_macro.value)?;
+    if !tdr_macro.desc.is_empty() {
+        let desc = kept(StringField::Desc, &tdr_macro.desc);
+        write!(&mut out, " desc=\"{}\"", xml_escape_attr(&desc))?;
+    }
+    write!(&mut out, " />")?;
+    Ok(out)
+}
+
+fn dump_tdr_macrogroup_xml(
+    metalib: &Metalib,
+    macrogroup: &metalib::TDRMacroGroup,
+    options: &ExportOptions,
+) -> Result<String> {
+    let mut out = String::new();
+    let indent = options.indent_for(1);
+    let entry_indent = options.indent_for(2);
+
+    // Open `macrosgroup` tag.
+    let mut macrogroup_tag = String::new();
+    write!(&mut macrogroup_tag, "{indent}<macrosgroup")?;
+    write!(&mut macrogroup_tag, " name=\"{}\"", xml_escape_attr(&macrogroup.name))?;
+    if !macrogroup.desc.is_empty() {
+        let desc =
+            kept_string(metalib, options, macrogroup._offset, StringField::Desc, &macrogroup.desc);
+        write!(&mut macrogroup_tag, " desc=\"{}\"", xml_escape_attr(&desc))?;
+    }
+    write!(&mut macrogroup_tag, ">")?;
+    writeln!(&mut out, "{macrogroup_tag}")?;
+
+    // Write macro entries
+    for tdr_macro in metalib.macrogroup_macros(macrogroup) {
+        let macro_tag = dump_tdr_macro_xml(metalib, tdr_macro, options)?;
+        writeln!(&mut out, "{entry_indent}{macro_tag}")?;
+    }
+
+    // Close `macrosgroup` tag.
+    write!(&mut out, "{indent}</macrosgroup>")?;
+
+    Ok(out)
+}
+
+fn dump_tdr_meta_entry_xml(
+    metalib: &Metalib,
+    meta: &metalib::TDRMeta,
+    meta_entry: &metalib::TDRMetaEntry,
+    options: &ExportOptions,
+) -> Result<String> {
+    let kept = |record, field, text: &str| kept_string(metalib, options, record, field, text);
+    let macro_name = |tdr_macro: &metalib::TDRMacro| {
+        kept(tdr_macro._offset, StringField::Name, &tdr_macro.name)
+    };
+    let mut attrs: Vec<(&str, String)> = Vec::new();
+    attrs.push(("name", kept(meta_entry._offset, StringField::Name, &meta_entry.name)));
+    let owner = format!("{}.{}", meta.name, meta_entry.name);
+
+    // Write "type" attribute
+    let type_string: String = {
+        if meta_entry.ptr_meta != INVALID_METALIB_VALUE {
+            let type_meta = metalib
+                .get_meta_by_offset(meta_entry.ptr_meta)
+                .context("Failed to get meta by ptr_meta")?;
+            kept(type_meta._offset, StringField::Name, &type_meta.name)
+        } else if meta_entry.type_ == MetaPrimativeType::UNKNOWN {
+            // No tdr tool will accept this, but it keeps the rest of the file usable.
+            warn!(
+                "Entry `{}.{}` has unknown type {}, writing it as a number",
+                meta.name, meta_entry.name, meta_entry.raw_type
+            );
+            meta_entry.raw_type.to_string()
+        } else if meta_entry.idx_type != INVALID_METALIB_VALUE {
+            // Out of range only after lenient parsing, which already warned about it.
+            metalib::primitive_type_info(meta_entry.idx_type).map_or_else(
+                || meta_entry.idx_type.to_string(),
+                |type_info| type_info.xml_name.to_string(),
+            )
+        } else {
+            String::new()
+        }
+    };
+    let type_prefix = {
+        if meta_entry.flag.contains(TDRMetaEntryFlags::POINT_TYPE) {
+            "*"
+        } else if meta_entry.flag.contains(TDRMetaEntryFlags::REFER_TYPE) {
+            "@"
+        } else {
+            ""
+        }
+    };
+    attrs.push(("type", format!("{type_prefix}{type_string}")));
+
+    // Write `count` attribute
+    if meta_entry.count > 1 {
+        if meta_entry.idx_count != INVALID_METALIB_VALUE {
+            let count_macro = metalib
+                .macros
+                .get(meta_entry.idx_count as usize)
+                .context("Failed to get macro by meta_entry.idx_count")?;
+            attrs.push(("count", macro_name(count_macro)));
+        } else {
+            attrs.push(("count", meta_entry.count.to_string()));
+        }
+    }
+
+    // Write `version` attribute
+    if meta_entry.version != meta.base_version{
+        if meta_entry.idx_version != INVALID_METALIB_VALUE {
+            let version_macro = metalib
+                .macros
+                .get(meta_entry.idx_version as usize)
+                .context("Failed to get macro by meta_entry.idx_version")?;
+            attrs.push(("version", macro_name(version_macro)));
+        } else {
+            attrs.push(("version", meta_entry.version.to_string()));
+        }
+    }
+
+    // Write `id` attribute
+    if meta_entry.idx_id != INVALID_METALIB_VALUE {
+        let id_macro = metalib
+            .macros
+            .get(meta_entry.idx_id as usize)
+            .context("Failed to get macro by meta_entry.idx_id")?;
+        attrs.push(("id", macro_name(id_macro)));
+    } else if meta_entry.id != INVALID_METALIB_VALUE {
+        attrs.push(("id", meta_entry.id.to_string()));
+    }
+
+    // Write `size` attribute
+    if meta_entry.idx_custom_h_unit_size != INVALID_METALIB_VALUE {
+        let id_macro = metalib
+            .macros
+            .get(meta_entry.idx_custom_h_unit_size as usize)
+            .context("Failed to get macro by meta_entry.idx_custom_h_unit_size")?;
+        attrs.push(("size", macro_name(id_macro)));
+    } else if meta_entry.custom_h_unit_size > 0 {
+        // Zero-size types (struct/union rows) and unknown types have no unit to divide by.
+        let unit_size = metalib::primitive_type_info(meta_entry.idx_type)
+            .map_or(0, |type_info| type_info.size);
+        let size = if unit_size > 0 {
+            meta_entry.custom_h_unit_size / unit_size
+        } else {
+            meta_entry.custom_h_unit_size
+        };
+        attrs.push(("size", size.to_string()));
+    }
+
+    if !meta_entry.chinese_name.is_empty() {
+        let cname = kept(meta_entry._offset, StringField::ChineseName, &meta_entry.chinese_name);
+        attrs.push(("cname", cname));
+    }
+
+    if !meta_entry.desc.is_empty() {
+        attrs.push(("desc", kept(meta_entry._offset, StringField::Desc, &meta_entry.desc)));
+    }
+
+    if meta_entry.db_flag.contains(TDRMetaEntryDBFlags::UNIQUE) {
+        attrs.push(("unique", "true".to_string()));
+    }
+
+    if meta_entry.db_flag.contains(TDRMetaEntryDBFlags::NOT_NULL) {
+        attrs.push(("notnull", "true".to_string()));
+    }
+
+    // Write `refer` attribute
+    let refer = meta_entry.referer.resolve(metalib, meta).map(|f| f.map(|f| f.path));
+    let h_off = meta_entry.referer.h_off;
+    if let Some(refer) = field_attr(refer, "refer", &owner, h_off, options)? {
+        attrs.push(("refer", refer));
+    }
+
+    // Write `default` attribute
+    // TODO: Update default value reader to parse value instead of bytes if needed.
+    if meta_entry.ptr_default_val != INVALID_METALIB_VALUE {
+        let default = &meta_entry.default_value_string;
+        attrs.push(("default", kept(meta_entry._offset, StringField::Default, default)));
+    }
+
+    // Write `sizeinfo` attribute
+    let size_info = meta_entry.size_info.resolve(metalib, meta).map(|source| match source {
+        Some(SizeSource::TypePrefix(type_info))
+            if (type_info.primative_type != MetaPrimativeType::STRING
+                && type_info.primative_type != MetaPrimativeType::WSTRING)
+                || type_info.xml_name == "int" =>
+        {
+            Some(type_info.xml_name.to_string())
+        }
+        Some(SizeSource::Field(field)) => Some(field.path),
+        Some(SizeSource::TypePrefix(_)) | None => None,
+    });
+    let n_off = meta_entry.size_info.n_off;
+    if let Some(size_info) = field_attr(size_info, "sizeinfo", &owner, n_off, options)? {
+        attrs.push(("sizeinfo", size_info));
+    }
+
+    // Write `sortMethod` attribute
+    // Only arrays are sorted. An unknown order (only read in lenient mode) goes in an `order`
+    // attribute instead, which the tdr tools won't accept until it's fixed by hand.
+    match meta_entry.order {
+        0 => {}
+        1 | 2 if meta_entry.count <= 1 => {}
+        1 => attrs.push(("sortMethod", "asc".to_string())),
+        2 => attrs.push(("sortMethod", "desc".to_string())),
+        order => {
+            warn!(
+                "Entry `{}.{}` has unknown order value {order}, writing it as `order`",
+                meta.name, meta_entry.name
+            );
+            attrs.push(("order", order.to_string()));
+        }
+    }
+
+    // Write `io` attribute
+    if meta_entry.io != 0 {
+        let io_type = {
+            match meta_entry.io {
+                1 => "noinput",
+                2 => "nooutput",
+                3 => "noio",
+                io => {
+                    return Err(anyhow!(
+                        "Entry `{}.{}` has unknown io value {io}",
+                        meta.name,
+                        meta_entry.name
+                    ))
+                }
+            }
+        };
+
+        attrs.push(("io", io_type.to_string()));
+    }
+
+    // Write `select` attribute
+    if meta_entry.type_ == MetaPrimativeType::UNION {
+        let select = meta_entry.selector.resolve(metalib, meta).map(|f| f.map(|f| f.path));
+        let h_off = meta_entry.selector.h_off;
+        if let Some(select) = field_attr(select, "select", &owner, h_off, options)? {
+            attrs.push(("select", select));
+        }
+    }
+
+    if meta_entry.flag.contains(TDRMetaEntryFlags::HAS_MAXMIN_ID) {
+        // Write `minid` attribute
+        if meta_entry.min_id_idx != INVALID_METALIB_VALUE {
+            let min_id_macro = metalib
+                .macros
+                .get(meta_entry.min_id_idx as usize)
+                .context("Failed to get macro by meta_entry.min_id_idx")?;
+            attrs.push(("minid", macro_name(min_id_macro)));
+        } else {
+            attrs.push(("minid", meta_entry.min_id.to_string()));
+        }
+
+        // Write `maxid` attribute
+        if meta_entry.max_id_idx != INVALID_METALIB_VALUE {
+            let max_id_macro = metalib
+                .macros
+                .get(meta_entry.max_id_idx as usize)
+                .context("Failed to get macro by meta_entry.max_id_idx")?;
+            attrs.push(("maxid", macro_name(max_id_macro)));
+        } else {
+            attrs.push(("maxid", meta_entry.max_id.to_string()));
+        }
+    }
+
+    // Write `extendtotable` attribute
+    if meta_entry
+        .db_flag
+        .contains(TDRMetaEntryDBFlags::EXTEND_TO_TABLE)
+    {
+        attrs.push(("extendtotable", "true".to_string()));
+    }
+
+    // Write `bindmacrosgroup` attribute
+    if meta_entry.ptr_macros_group != INVALID_METALIB_VALUE {
+        let macro_group = metalib.get_macrogroup_by_offset(meta_entry.ptr_macros_group)?;
+        attrs.push(("bindmacrosgroup", macro_group.name.clone()));
+    }
+
+    // Write `autoincrement` attribute
+    if meta_entry
+        .db_flag
+        .contains(TDRMetaEntryDBFlags::AUTO_INCREMENT)
+    {
+        attrs.push(("autoincrement", "true".to_string()));
+    }
+
+    // Write `customattr` attribute
+    if meta_entry.ptr_custom_attr != INVALID_METALIB_VALUE {
+        let custom_attr = &meta_entry.custom_attr;
+        attrs.push(("customattr", kept(meta_entry._offset, StringField::CustomAttr, custom_attr)));
+    }
+
+    // Entries sit at depth 2, so wrapped attributes go one level deeper.
+    write_empty_tag("entry", &attrs, &options.indent_for(3), options.wrap_attrs)
+}
+
+/// Returns the value of the `attr` attribute of `owner` (a meta or `meta.entry`), which refers
+/// to the field at `offset`. If no field starts there that's an error, or with `--lenient` a
+/// warning, and the offset is written as `@0x1C` instead, which the tdr tools won't accept
+/// until it's fixed by hand.
+fn field_attr(
+    resolved: Result<Option<String>>,
+    attr: &str,
+    owner: &str,
+    offset: i32,
+    options: &ExportOptions,
+) -> Result<Option<String>> {
+    match resolved {
+        Ok(value) => Ok(value),
+        Err(err) if options.strict => {
+            Err(err.context(format!("Failed to resolve `{attr}` of `{owner}`")))
+        }
+        Err(err) => {
+            warn!(
+                "Failed to resolve `{attr}` of `{owner}` ({err}), writing its offset {offset:#X}"
+            );
+            Ok(Some(format!("@{offset:#X}")))
+        }
+    }
+}
+
+/// Escapes `value` for a double-quoted attribute: the five XML entities, and tabs and line
+/// breaks as character references so parsers don't normalize them to spaces. Other control
+/// characters can't appear in XML 1.0 at all, even escaped, so they're dropped.
+fn xml_escape_attr(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' => out.push_str("&#9;"),
+            '\n' => out.push_str("&#10;"),
+            '\r' => out.push_str("&#13;"),
+            '\u{0}'..='\u{1F}' | '\u{FFFE}' | '\u{FFFF}' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Writes `<name attr="value" .../>`, escaping the values. If the tag would be longer than `wrap_width`
+/// characters, every attribute after the first goes on its own line, prefixed by
+/// `attr_indent`.
+fn write_empty_tag(
+    name: &str,
+    attrs: &[(&str, String)],
+    attr_indent: &str,
+    wrap_width: Option<usize>,
+) -> Result<String> {
+    let mut out = String::new();
+    write!(&mut out, "<{name}")?;
+    let attrs: Vec<(&str, String)> = attrs
+        .iter()
+        .map(|(attr, value)| (*attr, xml_escape_attr(value)))
+        .collect();
+    for (attr, value) in attrs.iter() {
+        write!(&mut out, " {attr}=\"{value}\"")?;
+    }
+    write!(&mut out, "/>")?;
+    if wrap_width.is_none_or(|width| out.chars().count() <= width) {
+        return Ok(out);
+    }
+
+    out.clear();
+    write!(&mut out, "<{name}")?;
+    for (i, (attr, value)) in attrs.iter().enumerate() {
+        if i == 0 {
+            write!(&mut out, " {attr}=\"{value}\"")?;
+        } else {
+            write!(&mut out, "\n{attr_indent}{attr}=\"{value}\"")?;
+        }
+    }
+    write!(&mut out, "/>")?;
+    Ok(out)
+}
+
+fn dump_tdr_meta_xml(
+    metalib: &Metalib,
+    meta: &metalib::TDRMeta,
+    cut: Option<&VersionCut>,
+    options: &ExportOptions,
+) -> Result<String> {
+    // A field the meta itself refers to can't be left out by the cut.
+    let check_cut = |attr: &str, path: &str| match cut {
+        Some(cut) => cut.check_path(metalib, meta, attr, path),
+        None => Ok(()),
+    };
+    let mut out = String::new();
+    let indent = options.indent_for(1);
+    let entry_indent = options.indent_for(2);
+
+    let tag_name = match meta.type_ {
+        metalib::MetaPrimativeType::UNION => "union",
+        metalib::MetaPrimativeType::STRUCT => "struct",
+        _ => {
+            warn!(
+                "Meta `{}` has type {} rather than struct or union, writing it as a struct",
+                meta.name, meta.raw_type
+            );
+            "struct"
+        }
+    };
+    let kept = |record, field, text: &str| kept_string(metalib, options, record, field, text);
+    let macro_name = |tdr_macro: &metalib::TDRMacro| {
+        xml_escape_attr(&kept(tdr_macro._offset, StringField::Name, &tdr_macro.name))
+    };
+    write!(&mut out, "{indent}<{tag_name}")?;
+    let name = kept(meta._offset, StringField::Name, &meta.name);
+    write!(&mut out, " name=\"{}\"", xml_escape_attr(&name))?;
+
+    if meta.idx_version != INVALID_METALIB_VALUE {
+        let version_macro = metalib
+            .macros
+            .get(meta.idx_version as usize)
+            .context("Error getting macro by idx_version")?;
+        write!(&mut out, " version=\"{}\"", macro_name(version_macro))?;
+    } else {
+        let version = cut.map_or(meta.base_version, |cut| meta.base_version.min(cut.version));
+        write!(&mut out, " version=\"{}\"", version)?;
+    }
+
+    if meta.flags.contains(TDRMetaFlags::HAS_ID) {
+        if meta.idx_id != INVALID_METALIB_VALUE {
+            let id_macro = metalib
+                .macros
+                .get(meta.idx_id as usize)
+                .context("Error getting macro by idx_id")?;
+            write!(&mut out, " id=\"{}\"", macro_name(id_macro))?;
+        } else {
+            write!(&mut out, " id=\"{}\"", meta.id)?;
+        }
+    }
+
+    if !meta.chinese_name.is_empty() {
+        let cname = kept(meta._offset, StringField::ChineseName, &meta.chinese_name);
+        write!(&mut out, " cname=\"{}\"", xml_escape_attr(&cname))?;
+    }
+
+    if !meta.desc.is_empty() {
+        let desc = kept(meta._offset, StringField::Desc, &meta.desc);
+        write!(&mut out, " desc=\"{}\"", xml_escape_attr(&desc))?;
+    }
+
+    // Fields diverge here depending on if this is a union or a struct tag.
+    if meta.type_ == metalib::MetaPrimativeType::STRUCT {
+        // Write `size` tag
+        if meta.idx_custom_h_unit_size != INVALID_METALIB_VALUE {
+            let custom_host_size_macro =
+                metalib
+                    .macros
+                    .get(meta.idx_custom_h_unit_size as usize)
+                    .context("Error getting macro by idx_custom_h_unit_size")?;
+            write!(&mut out, " size=\"{}\"", macro_name(custom_host_size_macro))?;
+        } else if meta.custom_h_unit_size > 0 {
+            write!(&mut out, " size=\"{}\"", meta.custom_h_unit_size)?;
+        }
+
+        // Write custom align tag
+        // (Always defaults to 1, tag skipped if default.)
+        if meta.custom_align != 1 {
+            write!(&mut out, " align=\"{}\"", meta.custom_align)?;
+        }
+
+        // Write `versionindicator` tag
+        let version_indicator = meta
+            .version_indicator
+            .resolve(metalib, meta)
+            .map(|f| f.map(|f| f.path));
+        let n_off = meta.version_indicator.n_off;
+        if let Some(version_indicator) =
+            field_attr(version_indicator, "versionindicator", &meta.name, n_off, options)?
+        {
+            check_cut("versionindicator", &version_indicator)?;
+            write!(
+                &mut out,
+                " versionindicator=\"{}\"",
+                xml_escape_attr(&version_indicator)
+            )?;
+        }
+
+        let size_info = meta.size_type.resolve(metalib, meta).map(|source| match source {
+            Some(SizeSource::TypePrefix(type_info)) => Some(type_info.xml_name.to_string()),
+            Some(SizeSource::Field(field)) => Some(field.path),
+            None => None,
+        });
+        let n_off = meta.size_type.n_off;
+        if let Some(size_info) = field_attr(size_info, "sizeinfo", &meta.name, n_off, options)? {
+            check_cut("sizeinfo", &size_info)?;
+            write!(&mut out, " sizeinfo=\"{}\"", xml_escape_attr(&size_info))?;
+        }
+
+        // None of our example metalibs have this field -- untested.
+        if meta.sort_key.sort_key_offset != INVALID_METALIB_VALUE {
+            let n_off = meta.sort_key.sort_key_offset;
+            let sort_key = metalib
+                .resolve_field_path_by_net_offset(meta, n_off)
+                .map(|f| Some(f.path));
+            if let Some(sort_key) = field_attr(sort_key, "sortkey", &meta.name, n_off, options)? {
+                check_cut("sortkey", &sort_key)?;
+                write!(&mut out, " sortkey=\"{}\"", xml_escape_attr(&sort_key))?;
+            }
+        }
+
+        if !meta.primary_key.is_empty() {
+            let mut paths = Vec::new();
+            for key in meta.primary_key.iter() {
+                let path = key.resolve(metalib, meta).map(|f| f.map(|f| f.path));
+                let h_off = key.h_off;
+                if let Some(path) = field_attr(path, "primarykey", &meta.name, h_off, options)? {
+                    check_cut("primarykey", &path)?;
+                    paths.push(path);
+                }
+            }
+            write!(&mut out, " primarykey=\"{}\"", xml_escape_attr(&paths.join(",")))?;
+        }
+
+        if meta.idx_split_table_factor != INVALID_METALIB_VALUE {
+            let split_table_factor_macro = metalib
+                .macros
+                .get(meta.idx_split_table_factor as usize)
+                .context("Error getting macro by idx_split_table_factor")?;
+            write!(
+                &mut out,
+                " splittablefactor=\"{}\"",
+                macro_name(split_table_factor_macro)
+            )?;
+        } else if meta.split_table_factor != 0 {
+            write!(&mut out, " splittablefactor=\"{}\"", meta.split_table_factor)?;
+        }
+
+        let split_table_key = meta
+            .split_table_key
+            .resolve(metalib, meta)
+            .map(|f| f.map(|f| f.path));
+        let h_off = meta.split_table_key.h_off;
+        if let Some(split_table_key) =
+            field_attr(split_table_key, "splittablekey", &meta.name, h_off, options)?
+        {
+            check_cut("splittablekey", &split_table_key)?;
+            write!(&mut out, " splittablekey=\"{}\"", xml_escape_attr(&split_table_key))?;
+        }
+
+        // Always defaults to 0 if unused.
+        if meta.split_table_rule_id != 0 {
+            write!(
+                &mut out,
+                " splittablerule=\"{}\"",
+                meta.split_table_rule_id
+            )?;
+        }
+
+        if meta.ptr_dependon_struct != INVALID_METALIB_VALUE {
+            let dependon_struct = metalib
+                .get_meta_by_offset(meta.ptr_dependon_struct)
+                .context("Error getting meta by ptr_dependon_struct")?;
+            if let Some(cut) = cut.filter(|cut| cut.metas.contains(&dependon_struct._offset)) {
+                return Err(anyhow!(
+                    "`dependontable` of `{}` is `{}`, which is newer than version {}",
+                    meta.name,
+                    dependon_struct.name,
+                    cut.version
+                ));
+            }
+            let name = kept(dependon_struct._offset, StringField::Name, &dependon_struct.name);
+            write!(&mut out, " dependontable=\"{}\"", xml_escape_attr(&name))?;
+        }
+    }
+
+    if meta
+        .flags
+        .contains(TDRMetaFlags::NEED_PREFIX_FOR_UNIQUENAME)
+    {
+        write!(&mut out, " uniqueentryname=\"true\"")?;
+    }
+    writeln!(&mut out, ">")?;
+
+    // Write meta entries....
+    for entry in meta.entries.iter() {
+        if cut.is_some_and(|cut| cut.entries.contains(&entry._offset)) {
+            continue;
+        }
+        writeln!(
+            &mut out,
+            "{entry_indent}{}",
+            dump_tdr_meta_entry_xml(metalib, meta, entry, options)?
+        )?;
+    }
+
+    // Something ends the tag.
+    writeln!(&mut out, "{indent}</{tag_name}>")?;
+
+    Ok(out)
+}
+
+fn dump_metalib_xml(metalib: &Metalib, options: &ExportOptions) -> Result<String> {
+    let mut out = String::new();
+    let indent = options.indent_for(1);
+
+    let header = &metalib.header;
+    writeln!(
+        &mut out,
+        r#"<?xml version="1.0" encoding="{}" standalone="yes" ?>"#,
+        options.encoding.xml_name()
+    )?;
+
+    // Open `metalib` tag.
+    let mut metaline_tag = String::new();
+    write!(&mut metaline_tag, "<metalib")?;
+    write!(
+        &mut metaline_tag,
+        " tagsetversion=\"{}\"",
+        header.xml_tag_set_ver
+    )?;
+    write!(&mut metaline_tag, " name=\"{}\"", xml_escape_attr(&header.name))?;
+    let version = match options.cut_version {
+        Some(cut) if cut >= 0 => header.version.min(cut as u32),
+        _ => header.version,
+    };
+    write!(&mut metaline_tag, " version=\"{}\"", version)?;
+    if header.id != metalib::INVALID_METALIB_VALUE {
+        write!(&mut metaline_tag, " id=\"{}\"", header.id)?;
+    }
+    write!(&mut metaline_tag, ">")?;
+    writeln!(&mut out, "{metaline_tag}")?;
+
+    // Write macros that are unassociated with a group.
+
+    // A partial dump only includes the macros and macrogroups its metas use.
+    let selection = match options.metas.is_empty() {
+        true => None,
+        false => Some(MetaSelection::resolve(
+            metalib,
+            &options.metas,
+            options.with_deps,
+        )?),
+    };
+    let selection = selection.as_ref();
+    let cut = options.cut_version.map(|version| VersionCut::resolve(metalib, version));
+    let cut = cut.as_ref();
+    for macro_ in metalib.macros.iter() {
+        let selected = selection.is_none_or(|s| s.macros.contains(&macro_._offset));
+        if selected && !metalib.is_macro_in_group(macro_)? {
+            writeln!(
+                &mut out,
+                "{indent}{}",
+                dump_tdr_macro_xml(metalib, macro_, options)?
+            )?;
+        }
+    }
+
+    // Write macro groups
+    for macrogroup in metalib.macrogroups.iter() {
+        if selection.is_some_and(|s| !s.macrogroups.contains(&macrogroup._offset)) {
+            continue;
+        }
+        writeln!(
+            &mut out,
+            "{}",
+            dump_tdr_macrogroup_xml(metalib, macrogroup, options)?
+        )?;
+    }
+
+    // Write unions/structs.
+    for meta in metalib.metas.iter() {
+        if selection.is_some_and(|s| !s.metas.contains(&meta._offset)) {
+            continue;
+        }
+        if cut.is_some_and(|cut| cut.metas.contains(&meta._offset)) {
+            continue;
+        }
+        writeln!(&mut out, "{}", dump_tdr_meta_xml(metalib, meta, cut, options)?)?;
+    }
+
+    // Close `metalib` tag.
+    writeln!(&mut out, "</metalib>")?;
+
+    Ok(out)
+}
+
+/// Writes the metalib as XML with the default options.
+pub fn export_metalib_xml(metalib: &Metalib) -> Result<String> {
+    export_metalib_xml_with(metalib, &ExportOptions::default())
+}
+
+/// Writes the metalib as XML, formatted and filtered by `options`.
+pub fn export_metalib_xml_with(metalib: &Metalib, options: &ExportOptions) -> Result<String> {
+    let mut out = String::new();
+    write!(&mut out, "{}", dump_metalib_xml(metalib, options)?)?;
+    Ok(out)
+}
+
+/// Converts a written XML document to `encoding`. As GBK, the strings the metalib kept the
+/// original bytes of are written as those bytes.
+pub fn encode_xml(metalib: &Metalib, xml: String, encoding: XmlEncoding) -> Result<Vec<u8>> {
+    let encode_gbk = |text: &str| {
+        GBK.encode(text, EncoderTrap::Strict).map_err(|_| {
+            match find_non_gbk_string(metalib) {
+                Some(location) => anyhow!("{location} can't be encoded as GBK"),
+                None => anyhow!("The XML document can't be encoded as GBK"),
+            }
+        })
+    };
+    match encoding {
+        XmlEncoding::Utf8 => Ok(xml.into_bytes()),
+        XmlEncoding::Gbk => {
+            let mut out = Vec::with_capacity(xml.len());
+            let mut rest = xml.as_str();
+            while let Some((text, marker)) = rest.split_once(RAW_STRING_START) {
+                let raw = marker.split_once(RAW_STRING_END).and_then(|(idx, after)| {
+                    let raw = metalib.raw_strings.get(idx.parse::<usize>().ok()?)?;
+                    Some((raw, after))
+                });
+                let Some((raw, after)) = raw else {
+                    // Not one of ours, so it fails to encode like any other noncharacter.
+                    break;
+                };
+                out.extend(encode_gbk(text)?);
+                out.extend(xml_escape_attr_bytes(&raw.bytes));
+                rest = after;
+            }
+            out.extend(encode_gbk(rest)?);
+            Ok(out)
+        }
+    }
+}
+
+/// [`xml_escape_attr`] for the raw bytes of a string, up to its first NUL. Multibyte GBK and
+/// Big5 characters never have a byte below 0x40, so only ASCII bytes need escaping.
+fn xml_escape_attr_bytes(bytes: &[u8]) -> Vec<u8> {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    let mut out = Vec::with_capacity(end);
+    for &b in &bytes[..end] {
+        match b {
+            b'&' => out.extend_from_slice(b"&amp;"),
+            b'<' => out.extend_from_slice(b"&lt;"),
+            b'>' => out.extend_from_slice(b"&gt;"),
+            b'"' => out.extend_from_slice(b"&quot;"),
+            b'\'' => out.extend_from_slice(b"&apos;"),
+            b'\t' => out.extend_from_slice(b"&#9;"),
+            b'\n' => out.extend_from_slice(b"&#10;"),
+            b'\r' => out.extend_from_slice(b"&#13;"),
+            0..=0x1F => {}
+            b => out.push(b),
+        }
+    }
+    out
+}
+
+/// Describes the first string written to the XML (a name, description, default or custom
+/// attribute) with a character GBK can't represent. Strings written as their raw bytes don't
+/// count.
+fn find_non_gbk_string(metalib: &Metalib) -> Option<String> {
+    let bad_char = |s: &str| {
+        s.chars()
+            .find(|c| GBK.encode(&c.to_string(), EncoderTrap::Strict).is_err())
+    };
+    let bad_field = |record: u64, field: StringField, s: &str| match metalib
+        .raw_string(record, field)
+    {
+        Some(_) => None,
+        None => bad_char(s),
+    };
+    if let Some(c) = bad_char(&metalib.header.name) {
+        return Some(format!("Metalib name ({c:?})"));
+    }
+    for macro_ in metalib.macros.iter() {
+        for (what, field, s) in [
+            ("name", StringField::Name, &macro_.name),
+            ("desc", StringField::Desc, &macro_.desc),
+        ] {
+            if let Some(c) = bad_field(macro_._offset, field, s) {
+                return Some(format!("Macro `{}` {what} ({c:?})", macro_.name));
+            }
+        }
+    }
+    for group in metalib.macrogroups.iter() {
+        let desc = bad_field(group._offset, StringField::Desc, &group.desc);
+        for (what, c) in [("name", bad_char(&group.name)), ("desc", desc)] {
+            if let Some(c) = c {
+                return Some(format!("Macrogroup `{}` {what} ({c:?})", group.name));
+            }
+        }
+    }
+    for meta in metalib.metas.iter() {
+        for (what, field, s) in [
+            ("name", StringField::Name, &meta.name),
+            ("cname", StringField::ChineseName, &meta.chinese_name),
+            ("desc", StringField::Desc, &meta.desc),
+        ] {
+            if let Some(c) = bad_field(meta._offset, field, s) {
+                return Some(format!("Meta `{}` {what} ({c:?})", meta.name));
+            }
+        }
+        for entry in meta.entries.iter() {
+            for (what, field, s) in [
+                ("name", StringField::Name, &entry.name),
+                ("cname", StringField::ChineseName, &entry.chinese_name),
+                ("desc", StringField::Desc, &entry.desc),
+                ("default", StringField::Default, &entry.default_value_string),
+                ("customattr", StringField::CustomAttr, &entry.custom_attr),
+            ] {
+                if let Some(c) = bad_field(entry._offset, field, s) {
+                    return Some(format!(
+                        "Entry `{}.{}` {what} ({c:?})",
+                        meta.name, entry.name
+                    ));
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{compile, parse, DB_XML, FULL_XML};
+
+    #[test]
+    fn default_formatting_is_unchanged() {
+        let metalib = parse(FULL_XML);
+        assert_eq!(export_metalib_xml(&metalib).unwrap(), FULL_XML);
+    }
+
+    #[test]
+    fn db_attributes_round_trip() {
+        let metalib = parse(DB_XML);
+        assert_eq!(export_metalib_xml(&metalib).unwrap(), DB_XML);
+
+        let player = metalib.get_meta_by_name("Player").unwrap();
+        let flags: Vec<TDRMetaEntryDBFlags> = player.entries.iter().map(|e| e.db_flag).collect();
+        assert_eq!(
+            flags,
+            [
+                TDRMetaEntryDBFlags::NOT_NULL | TDRMetaEntryDBFlags::AUTO_INCREMENT,
+                TDRMetaEntryDBFlags::EXTEND_TO_TABLE
+            ]
+        );
+    }
+
+    #[test]
+    fn indent_with_spaces() {
+        let metalib = parse(FULL_XML);
+        let options = ExportOptions::default().indent("  ");
+        let xml = export_metalib_xml_with(&metalib, &options).unwrap();
+
+        let expected: String = FULL_XML
+            .lines()
+            .map(|line| {
+                let tabs = line.len() - line.trim_start_matches('\t').len();
+                format!("{}{}\n", "  ".repeat(tabs), &line[tabs..])
+            })
+            .collect();
+        assert_eq!(xml, expected);
+    }
+
+    #[test]
+    fn wrapped_attributes() {
+        let metalib = parse(FULL_XML);
+        let options = ExportOptions::default().indent("    ").wrap_attrs(40);
+        let xml = export_metalib_xml_with(&metalib, &options).unwrap();
+
+        let kind = "        <entry name=\"kind\"\n            type=\"int\"\n            \
+                    default=\"1\"\n            bindmacrosgroup=\"Kind\"/>\n";
+        assert!(xml.contains(kind), "{xml}");
+        // Tags up to the width (not counting the indent) stay on one line.
+        assert!(xml.contains("        <entry name=\"num\" type=\"uint8\"/>\n"), "{xml}");
+        for line in xml.lines().map(str::trim_start) {
+            if line.starts_with("<entry") && line.ends_with("/>") {
+                assert!(line.chars().count() <= 40, "{line}");
+            }
+        }
+
+        // Still the same metalib.
+        roxmltree::Document::parse(&xml).unwrap();
+        assert_eq!(export_metalib_xml(&compile(&xml)).unwrap(), FULL_XML);
+    }
+
+    /// Every character XML attributes need escaped, plus ones XML 1.0 can't hold at all.
+    const ADVERSARIAL: &str = "a \"b\" <c> & 'd'\ttab\nline\r\u{1}\u{FFFE}end";
+
+    /// Sets a name, a description and a default of FULL_XML's model to [`ADVERSARIAL`].
+    fn adversarial_metalib() -> Metalib {
+        let mut metalib = parse(FULL_XML);
+        metalib.macros[0].desc = ADVERSARIAL.to_string();
+        metalib.macrogroups[0].desc = ADVERSARIAL.to_string();
+        let sorted = metalib.metas.iter_mut().find(|m| m.name == "Sorted").unwrap();
+        sorted.name = format!("Sorted {ADVERSARIAL}");
+        let account = metalib.metas.iter_mut().find(|m| m.name == "Account").unwrap();
+        account.chinese_name = ADVERSARIAL.to_string();
+        // A string long enough for the default to compile back.
+        let body = metalib.metas.iter_mut().find(|m| m.name == "Body").unwrap();
+        let b = body.entries.iter_mut().find(|e| e.name == "b").unwrap();
+        b.ptr_default_val = 0;
+        b.default_value_string = ADVERSARIAL.to_string();
+        b.desc = ADVERSARIAL.to_string();
+        metalib
+    }
+
+    #[test]
+    fn adversarial_strings_are_escaped() {
+        let xml = export_metalib_xml(&adversarial_metalib()).unwrap();
+        let document =
+            roxmltree::Document::parse(&xml).unwrap_or_else(|err| panic!("{err}\n{xml}"));
+        // Only the characters XML can't hold are lost.
+        let expected = ADVERSARIAL.replace(['\u{1}', '\u{FFFE}'], "");
+
+        let attr = |tag: &str, name: &str, attr: &str| -> String {
+            let node = document
+                .descendants()
+                .find(|n| n.has_tag_name(tag) && n.attribute("name") == Some(name))
+                .unwrap_or_else(|| panic!("no <{tag} name={name:?}>\n{xml}"));
+            node.attribute(attr).unwrap().to_string()
+        };
+        assert_eq!(attr("macro", "MAX_ITEMS", "desc"), expected);
+        assert_eq!(attr("macrosgroup", "Kind", "desc"), expected);
+        assert_eq!(attr("struct", &format!("Sorted {expected}"), "sortkey"), "key");
+        assert_eq!(attr("struct", "Account", "cname"), expected);
+        assert_eq!(attr("entry", "b", "default"), expected);
+        assert_eq!(attr("entry", "b", "desc"), expected);
+    }
+
+    #[test]
+    fn adversarial_strings_survive_both_encodings() {
+        let metalib = adversarial_metalib();
+        let expected = ADVERSARIAL.replace(['\u{1}', '\u{FFFE}'], "");
+        for encoding in [XmlEncoding::Utf8, XmlEncoding::Gbk] {
+            let options = ExportOptions::default().encoding(encoding);
+            let xml = export_metalib_xml_with(&metalib, &options).unwrap();
+            let bytes = encode_xml(&metalib, xml, encoding).unwrap();
+            let (xml, name) = match encoding {
+                XmlEncoding::Utf8 => (String::from_utf8(bytes).unwrap(), "UTF-8"),
+                XmlEncoding::Gbk => {
+                    let xml = GBK.decode(&bytes, encoding::DecoderTrap::Strict).unwrap();
+                    (xml, "GBK")
+                }
+            };
+            assert!(xml.starts_with(&format!(r#"<?xml version="1.0" encoding="{name}""#)));
+            let document = roxmltree::Document::parse(&xml).unwrap();
+            assert_eq!(document.root_element().attribute("name"), Some("full"));
+
+            // The compiler reads it back with the same parser.
+            let compiled = compile(&xml);
+            assert_eq!(compiled.macros[0].desc, expected);
+            assert_eq!(compiled.macrogroups[0].desc, expected);
+            let account = compiled.get_meta_by_name("Account").unwrap();
+            assert_eq!(account.chinese_name, expected);
+            let body = compiled.get_meta_by_name("Body").unwrap();
+            assert_eq!(body.entries[1].desc, expected);
+            assert_eq!(body.entries[1].default_value_string, expected);
+            assert!(compiled.get_meta_by_name(&format!("Sorted {expected}")).is_ok());
+        }
+    }
+
+    #[test]
+    fn encoded_output_decodes() {
+        let metalib = parse(FULL_XML);
+
+        let options = ExportOptions::default().encoding(XmlEncoding::Utf8);
+        let xml = export_metalib_xml_with(&metalib, &options).unwrap();
+        let bytes = encode_xml(&metalib, xml, XmlEncoding::Utf8).unwrap();
+        let utf8 = String::from_utf8(bytes).unwrap();
+        assert!(utf8.starts_with(r#"<?xml version="1.0" encoding="UTF-8""#));
+        assert!(utf8.contains(r#"cname="包""#));
+
+        let options = ExportOptions::default().encoding(XmlEncoding::Gbk);
+        let xml = export_metalib_xml_with(&metalib, &options).unwrap();
+        let bytes = encode_xml(&metalib, xml, XmlEncoding::Gbk).unwrap();
+        assert!(String::from_utf8(bytes.clone()).is_err());
+        assert!(bytes.windows(2).any(|pair| pair == [0xB0, 0xFC]), "包 in GBK");
+        let gbk = GBK.decode(&bytes, encoding::DecoderTrap::Strict).unwrap();
+        assert!(gbk.starts_with(r#"<?xml version="1.0" encoding="GBK""#));
+        assert_eq!(gbk.replacen("GBK", "UTF-8", 1), utf8);
+    }
+
+    #[test]
+    fn non_gbk_character_names_the_entry() {
+        let mut metalib = parse(FULL_XML);
+        let account = metalib.metas.iter_mut().find(|m| m.name == "Account").unwrap();
+        account.entries[0].desc = "emoji 😀".to_string();
+
+        let options = ExportOptions::default().encoding(XmlEncoding::Gbk);
+        let xml = export_metalib_xml_with(&metalib, &options).unwrap();
+        let err = encode_xml(&metalib, xml, XmlEncoding::Gbk).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Entry `Account.uin` desc ('😀') can't be encoded as GBK"
+        );
+    }
+
+    #[test]
+    fn unresolvable_version_indicator() {
+        let mut metalib = parse(FULL_XML);
+        let packet = metalib.metas.iter_mut().find(|m| m.name == "Packet").unwrap();
+        // Inside `head.len`, not at the start of a field.
+        packet.version_indicator.n_off = 1;
+
+        let err = export_metalib_xml(&metalib).unwrap_err();
+        assert!(
+            format!("{err:#}").starts_with("Failed to resolve `versionindicator` of `Packet`: "),
+            "{err:#}"
+        );
+
+        let options = ExportOptions::default().strict(false);
+        let xml = export_metalib_xml_with(&metalib, &options).unwrap();
+        assert!(xml.contains(r#" versionindicator="@0x1" sizeinfo="head.len">"#), "{xml}");
+        // Everything else is written as before.
+        let expected = FULL_XML.replace(r#"="head.ver""#, r#"="@0x1""#);
+        assert_eq!(xml, expected);
+    }
+
+    #[test]
+    fn refer_by_entry_pointer_only() {
+        for h_off in [INVALID_METALIB_VALUE, 1] {
+            let mut metalib = parse(FULL_XML);
+            let packet = metalib.metas.iter_mut().find(|m| m.name == "Packet").unwrap();
+            let items = packet.entries.iter_mut().find(|e| e.name == "items").unwrap();
+            assert_ne!(items.referer.ptr_entry, INVALID_METALIB_VALUE);
+            // Unset, or inside `head.len` where no field starts.
+            items.referer.h_off = h_off;
+            assert_eq!(export_metalib_xml(&metalib).unwrap(), FULL_XML);
+        }
+    }
+
+    const VERSIONS_XML: &str = r#"<metalib tagsetversion="1" name="versions" version="3">
+        <struct name="Old" version="1">
+            <entry name="a" type="int"/>
+        </struct>
+        <struct name="New" version="3">
+            <entry name="b" type="int"/>
+        </struct>
+        <union name="Body" version="1">
+            <entry name="x" type="int" id="1"/>
+            <entry name="y" type="int16" id="2"/>
+        </union>
+        <struct name="Msg" version="1" versionindicator="ver">
+            <entry name="ver" type="int"/>
+            <entry name="num" type="uint8" version="2"/>
+            <entry name="items" type="int" count="4" refer="num"/>
+            <entry name="tag" type="int" version="2"/>
+            <entry name="body" type="Body" select="tag"/>
+            <entry name="old" type="Old"/>
+            <entry name="new" type="New" version="3"/>
+            <entry name="also_new" type="New"/>
+        </struct>
+    </metalib>"#;
+
+    /// Names of the metas, and of Msg's entries, written at `cut`. The output must compile.
+    fn cut_at(metalib: &Metalib, cut: i32) -> (Vec<String>, Vec<String>) {
+        let options = ExportOptions::default().cut_version(cut);
+        let xml = export_metalib_xml_with(metalib, &options).unwrap();
+        let cut_metalib = compile(&xml);
+        assert_eq!(cut_metalib.header.version, cut as u32);
+        let metas = cut_metalib.metas.iter().map(|m| m.name.clone()).collect();
+        let msg = cut_metalib.get_meta_by_name("Msg").unwrap();
+        let entries = msg.entries.iter().map(|e| e.name.clone()).collect();
+        (metas, entries)
+    }
+
+    #[test]
+    fn cut_version_leaves_out_dependents() {
+        let metalib = parse(VERSIONS_XML);
+
+        // `items` refers to, and `body` is selected by, fields added in version 2, and
+        // `also_new`'s type was added in version 3.
+        let (metas, entries) = cut_at(&metalib, 1);
+        assert_eq!(metas, ["Old", "Body", "Msg"]);
+        assert_eq!(entries, ["ver", "old"]);
+
+        let (metas, entries) = cut_at(&metalib, 2);
+        assert_eq!(metas, ["Old", "Body", "Msg"]);
+        assert_eq!(entries, ["ver", "num", "items", "tag", "body", "old"]);
+
+        let (metas, entries) = cut_at(&metalib, 3);
+        assert_eq!(metas, ["Old", "New", "Body", "Msg"]);
+        assert_eq!(entries.len(), 8);
+    }
+
+    #[test]
+    fn cut_version_of_a_meta_attribute_is_an_error() {
+        let metalib = parse(
+            r#"<metalib tagsetversion="1" name="sized" version="2">
+                <struct name="Sized" version="1" sizeinfo="len">
+                    <entry name="a" type="int"/>
+                    <entry name="len" type="int" version="2"/>
+                </struct>
+            </metalib>"#,
+        );
+        let options = ExportOptions::default().cut_version(1);
+        let err = export_metalib_xml_with(&metalib, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`sizeinfo` of `Sized` refers to `len`, which is newer than version 1"
+        );
+        assert!(export_metalib_xml_with(&metalib, &options.cut_version(2)).is_ok());
+    }
+}
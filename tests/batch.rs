@@ -0,0 +1,61 @@
+//! Dumping a directory: every matching file is tried, failures are summarised at the end and
+//! make the run exit non-zero.
+
+mod common;
+
+use std::fs;
+
+use common::{fixture_bin, fixture_xml, mldec, mldec_ok, stderr, TempDir};
+
+#[test]
+fn directory_with_valid_and_invalid_files() {
+    let dir = TempDir::new("batch");
+    let inputs = TempDir::new("batch-inputs");
+    fixture_bin(&inputs, "full");
+    fixture_bin(&inputs, "types");
+    fs::write(inputs.join("junk.bin"), b"not a metalib at all").unwrap();
+    let full = fs::read(inputs.join("full.bin")).unwrap();
+    fs::write(inputs.join("truncated.bin"), &full[..full.len() / 2]).unwrap();
+    fs::write(inputs.join("notes.txt"), b"skipped by the glob").unwrap();
+
+    let out = dir.join("out");
+    let output = mldec([
+        inputs.path().as_os_str(),
+        "--glob".as_ref(),
+        "*.bin".as_ref(),
+        "-o".as_ref(),
+        out.as_os_str(),
+    ]);
+    assert!(!output.status.success());
+
+    let stderr = stderr(&output);
+    assert!(stderr.contains("Dumped 2 of 4 files"), "{stderr}");
+    assert!(stderr.contains("2 of 4 files failed"), "{stderr}");
+    let failures = stderr.split("Failures:").nth(1).expect(&stderr);
+    assert!(failures.contains("junk.bin: "), "{stderr}");
+    assert!(failures.contains("truncated.bin: "), "{stderr}");
+    assert!(!failures.contains("full.bin"), "{stderr}");
+    assert!(!stderr.contains("notes.txt"), "{stderr}");
+
+    // The valid inputs were still dumped.
+    assert_eq!(
+        fs::read_to_string(out.join("full_0.xml")).unwrap(),
+        fixture_xml("full.xml")
+    );
+    assert!(out.join("types_0.xml").is_file());
+    assert_eq!(fs::read_dir(&out).unwrap().count(), 2);
+}
+
+#[test]
+fn directory_of_valid_files_succeeds() {
+    let dir = TempDir::new("batch-ok");
+    let inputs = TempDir::new("batch-ok-inputs");
+    fixture_bin(&inputs, "full");
+    fixture_bin(&inputs, "types");
+
+    let out = dir.join("out/");
+    let output = mldec_ok([inputs.path().as_os_str(), "-o".as_ref(), out.as_os_str()]);
+    assert!(stderr(&output).contains("Dumped 2 of 2 files"));
+    assert!(out.join("full_0.xml").is_file());
+    assert!(out.join("types_0.xml").is_file());
+}
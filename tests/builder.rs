@@ -0,0 +1,82 @@
+//! Metalibs built in memory work with everything a parsed one does.
+
+mod common;
+
+use std::io::Cursor;
+
+use mldec_rs::compile::compile_metalib_xml;
+use mldec_rs::metalib::{read_metalib, Metalib, INVALID_METALIB_VALUE};
+use mldec_rs::writer::write_metalib;
+use mldec_rs::xml::export_metalib_xml;
+
+use common::builders;
+
+/// Checks the lookups, validation, writing and XML round trip of a built metalib, and
+/// returns its XML.
+fn check(metalib: &Metalib) -> String {
+    for meta in metalib.metas.iter() {
+        for entry in meta.entries.iter() {
+            if entry.ptr_meta != INVALID_METALIB_VALUE {
+                metalib.get_meta_by_offset(entry.ptr_meta).unwrap();
+            }
+            if entry.ptr_macros_group != INVALID_METALIB_VALUE {
+                metalib
+                    .get_macrogroup_by_offset(entry.ptr_macros_group)
+                    .unwrap();
+            }
+        }
+    }
+    assert!(metalib.validate().is_empty(), "{:?}", metalib.validate());
+
+    let xml = export_metalib_xml(metalib).unwrap();
+    let compiled = compile_metalib_xml(&xml).unwrap();
+    assert_eq!(export_metalib_xml(&compiled).unwrap(), xml);
+
+    let mut bytes = Vec::new();
+    write_metalib(metalib, &mut bytes).unwrap();
+    let read = read_metalib(&mut Cursor::new(&bytes)).unwrap();
+    assert_eq!(export_metalib_xml(&read).unwrap(), xml);
+    xml
+}
+
+#[test]
+fn macros_fixture() {
+    let metalib = builders::macros();
+    let xml = check(&metalib);
+    assert!(xml.contains(r#"<macro name="MAX_NAME" value="32" desc="name length" />"#));
+    assert!(xml.contains(r#"<macrosgroup name="Color" desc="colors">"#));
+    assert!(xml.contains(r#"size="MAX_NAME""#), "{xml}");
+    assert!(xml.contains(r#"version="VER_NEW""#), "{xml}");
+    let player = metalib.get_meta_by_name("Player").unwrap();
+    assert_eq!(player.h_unit_size, 32 + 4 + 1);
+    assert!(metalib.is_macro_in_group(&metalib.macros[2]).unwrap());
+    assert!(!metalib.is_macro_in_group(&metalib.macros[0]).unwrap());
+}
+
+#[test]
+fn union_fixture() {
+    let metalib = builders::union();
+    let xml = check(&metalib);
+    assert!(xml.contains("<union name=\"Value\""), "{xml}");
+    assert!(
+        xml.contains(r#"<entry name="value" type="Value" select="kind"/>"#),
+        "{xml}"
+    );
+    let value = metalib.get_meta_by_name("Value").unwrap();
+    assert_eq!(value.h_unit_size, 12);
+    assert!(value.entries.iter().all(|entry| entry.h_off == 0));
+}
+
+#[test]
+fn arrays_fixture() {
+    let metalib = builders::arrays();
+    let xml = check(&metalib);
+    assert!(
+        xml.contains(r#"<entry name="points" type="Point" count="MAX_POINTS" refer="num"/>"#),
+        "{xml}"
+    );
+    let path = metalib.get_meta_by_name("Path").unwrap();
+    assert_eq!(path.h_unit_size, 1 + 4 * 4 + 3 * 4);
+    let tags = &path.entries[2];
+    assert_eq!((tags.count, tags.h_off, tags.h_unit_size), (3, 17, 4));
+}
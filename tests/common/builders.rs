@@ -0,0 +1,68 @@
+//! Small metalibs assembled with [`MetalibBuilder`], each built around one feature.
+
+use mldec_rs::builder::{EntryBuilder, MetaBuilder, MetalibBuilder};
+use mldec_rs::metalib::Metalib;
+
+/// Loose macros, a macrogroup, and entries using them for sizes, versions and bindings.
+pub fn macros() -> Metalib {
+    MetalibBuilder::new("macros")
+        .version(2)
+        .macro_with_desc("MAX_NAME", 32, "name length")
+        .macro_("VER_NEW", 2)
+        .macrogroup_with_desc("Color", &["RED", "GREEN"], "colors")
+        .macro_("RED", 1)
+        .macro_("GREEN", 2)
+        .meta(
+            MetaBuilder::struct_("Player")
+                .entry(EntryBuilder::new("name", "string").size("MAX_NAME"))
+                .entry(
+                    EntryBuilder::new("color", "int")
+                        .macrogroup("Color")
+                        .default("2"),
+                )
+                .entry(EntryBuilder::new("extra", "uint8").version("VER_NEW")),
+        )
+        .build()
+        .unwrap()
+}
+
+/// A union selected by a field of the struct holding it.
+pub fn union() -> Metalib {
+    MetalibBuilder::new("unions")
+        .meta(
+            MetaBuilder::union("Value")
+                .entry(EntryBuilder::new("i", "int").id(1))
+                .entry(EntryBuilder::new("d", "double").id(2))
+                .entry(EntryBuilder::new("s", "string").size(12).id(3)),
+        )
+        .meta(
+            MetaBuilder::struct_("Tagged")
+                .entry(EntryBuilder::new("kind", "int"))
+                .entry(EntryBuilder::new("value", "Value").select("kind")),
+        )
+        .build()
+        .unwrap()
+}
+
+/// Arrays of primitives and of structs, one of them with a count field.
+pub fn arrays() -> Metalib {
+    MetalibBuilder::new("arrays")
+        .macro_("MAX_POINTS", 4)
+        .meta(
+            MetaBuilder::struct_("Point")
+                .entry(EntryBuilder::new("x", "int16"))
+                .entry(EntryBuilder::new("y", "int16")),
+        )
+        .meta(
+            MetaBuilder::struct_("Path")
+                .entry(EntryBuilder::new("num", "uint8"))
+                .entry(
+                    EntryBuilder::new("points", "Point")
+                        .count("MAX_POINTS")
+                        .refer("num"),
+                )
+                .entry(EntryBuilder::new("tags", "uint32").count(3)),
+        )
+        .build()
+        .unwrap()
+}
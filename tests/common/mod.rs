@@ -0,0 +1,101 @@
+//! Helpers shared by the integration tests, which drive the `mldec-rs` binary against
+//! metalibs compiled from `tests/fixtures/*.xml`.
+
+#![allow(dead_code)]
+
+pub mod builders;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use mldec_rs::arch::MetalibArch;
+use mldec_rs::compile::compile_metalib_xml;
+use mldec_rs::writer::write_metalib;
+
+/// A scratch directory, removed again when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("mldec-it-{}-{name}", std::process::id()));
+        _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+pub fn fixture_xml(name: &str) -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    fs::read_to_string(&path).unwrap_or_else(|err| panic!("{}: {err}", path.display()))
+}
+
+/// Compiles the XML `xml` in the `arch` layout.
+pub fn compile_xml(xml: &str, arch: MetalibArch) -> Vec<u8> {
+    let mut metalib = compile_metalib_xml(xml).unwrap();
+    metalib.arch = arch;
+    metalib.header.platform_arch = arch.platform_arch();
+    let mut bytes = Vec::new();
+    write_metalib(&metalib, &mut bytes).unwrap();
+    bytes
+}
+
+/// Compiles `tests/fixtures/<name>.xml` into `<dir>/<name>.bin` (32-bit) and returns its path.
+pub fn fixture_bin(dir: &TempDir, name: &str) -> PathBuf {
+    let path = dir.join(&format!("{name}.bin"));
+    let xml = fixture_xml(&format!("{name}.xml"));
+    fs::write(&path, compile_xml(&xml, MetalibArch::Bits32)).unwrap();
+    path
+}
+
+/// Runs the binary with `args`.
+pub fn mldec<I, S>(args: I) -> Output
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    Command::new(env!("CARGO_BIN_EXE_mldec-rs"))
+        .args(args)
+        .output()
+        .expect("failed to run mldec-rs")
+}
+
+/// Runs the binary with `args`, failing the test (with its stderr) if it fails.
+pub fn mldec_ok<I, S>(args: I) -> Output
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    let output = mldec(args);
+    assert!(
+        output.status.success(),
+        "mldec-rs failed ({}):\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).expect("stdout should be UTF-8")
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
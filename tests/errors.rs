@@ -0,0 +1,119 @@
+//! `--errors json`: the error code, offset and exit code for files that fail to parse.
+
+mod common;
+
+use std::ffi::OsStr;
+use std::fs;
+
+use common::{fixture_bin, mldec, stderr, TempDir};
+
+/// Runs `dump --errors json` on `bytes`, returning the exit code and the JSON error.
+fn json_error(dir: &TempDir, name: &str, bytes: &[u8]) -> (i32, serde_json::Value) {
+    let path = dir.join(name);
+    fs::write(&path, bytes).unwrap();
+    let output = mldec([
+        "dump".as_ref(),
+        path.as_os_str(),
+        "--stdout".as_ref(),
+        "--errors".as_ref(),
+        "json".as_ref(),
+    ]);
+    assert!(output.stdout.is_empty());
+    let stderr = stderr(&output);
+    let line = stderr.lines().last().unwrap();
+    let value = serde_json::from_str(line).unwrap_or_else(|err| panic!("{err}: {stderr}"));
+    (output.status.code().unwrap(), value)
+}
+
+#[test]
+fn truncated_fixture() {
+    let dir = TempDir::new("errors-truncated");
+    let full = fs::read(fixture_bin(&dir, "full")).unwrap();
+
+    let (code, error) = json_error(&dir, "body.bin", &full[..5000]);
+    assert_eq!(code, 4);
+    assert_eq!(error["code"], "truncated");
+    assert_eq!(error["offset"], 5000);
+    assert!(error["message"]
+        .as_str()
+        .unwrap()
+        .contains("bytes are missing"));
+
+    let (code, error) = json_error(&dir, "header.bin", &full[..0x80]);
+    assert_eq!(code, 4);
+    assert_eq!(error["code"], "truncated");
+    assert_eq!(error["offset"], 0x80);
+}
+
+#[test]
+fn bad_magic_fixture() {
+    let dir = TempDir::new("errors-magic");
+    let mut bytes = b"XX".to_vec();
+    bytes.extend(fs::read(fixture_bin(&dir, "full")).unwrap());
+
+    let (code, error) = json_error(&dir, "shifted.bin", &bytes);
+    assert_eq!(code, 3);
+    assert_eq!(error["code"], "bad_magic");
+    assert_eq!(error["offset"], 0);
+}
+
+/// Runs `dump --lenient` on `path` with `extra` arguments.
+fn lenient_dump(path: &std::path::Path, extra: &[&str]) -> std::process::Output {
+    let mut args: Vec<&OsStr> = vec![
+        "dump".as_ref(),
+        path.as_os_str(),
+        "--stdout".as_ref(),
+        "--lenient".as_ref(),
+    ];
+    args.extend(extra.iter().map(OsStr::new));
+    mldec(args)
+}
+
+#[test]
+fn warnings_fixture() {
+    let dir = TempDir::new("errors-warnings");
+    let mut bytes = fs::read(fixture_bin(&dir, "full")).unwrap();
+    bytes[4..8].copy_from_slice(&7u32.to_le_bytes());
+    let path = dir.join("arch.bin");
+    fs::write(&path, &bytes).unwrap();
+
+    // Lenient parsing goes on, printing the warning after the dump.
+    let output = lenient_dump(&path, &[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(common::stdout(&output), common::fixture_xml("full.xml"));
+    let log = stderr(&output);
+    assert!(log.contains("Header platform_arch is 0x7"), "{log}");
+    assert!(log.contains("with 1 warnings"), "{log}");
+
+    // Strictly, the same header is an error.
+    let (code, error) = json_error(&dir, "arch.bin", &bytes);
+    assert_eq!(code, 7);
+    assert_eq!(error["code"], "bad_header");
+    assert_eq!(error["offset"], 4);
+
+    let output = lenient_dump(&path, &["--fail-on-warnings", "--errors", "json"]);
+    assert_eq!(output.status.code(), Some(8));
+    assert!(output.stdout.is_empty());
+    let log = stderr(&output);
+    let error: serde_json::Value = serde_json::from_str(log.lines().last().unwrap()).unwrap();
+    assert_eq!(error["code"], "warnings");
+    let warnings = error["warnings"].as_array().unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0]["category"], "header");
+    assert_eq!(warnings[0]["offset"], 4);
+    assert_eq!(warnings[0]["meta"], serde_json::Value::Null);
+    assert!(warnings[0]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Header platform_arch is 0x7"));
+
+    // A build without a known header layout can't be read even leniently.
+    bytes[2..4].copy_from_slice(&0xFFFFu16.to_le_bytes());
+    let path = dir.join("build.bin");
+    fs::write(&path, &bytes).unwrap();
+    let output = lenient_dump(&path, &[]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let log = stderr(&output);
+    assert!(log.contains("Unsupported metalib build 65535 (0xFFFF)"), "{log}");
+}
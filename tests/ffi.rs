@@ -0,0 +1,92 @@
+//! Loads the cdylib the way a C/C++ host would and parses a metalib through the C ABI in
+//! `include/mldec.h`. Run with `cargo test --features ffi`.
+#![cfg(feature = "ffi")]
+
+mod common;
+
+use std::ffi::{c_char, c_int, CStr};
+use std::path::PathBuf;
+use std::ptr;
+
+use common::{fixture_bin, TempDir};
+use libloading::{Library, Symbol};
+
+/// Opaque handle, as in the header.
+#[repr(C)]
+struct MldecMetalib {
+    _private: [u8; 0],
+}
+
+type Parse = unsafe extern "C" fn(*const u8, usize, *mut *mut MldecMetalib) -> c_int;
+type ExportXml = unsafe extern "C" fn(*const MldecMetalib) -> *mut c_char;
+type MetaCount = unsafe extern "C" fn(*const MldecMetalib) -> usize;
+type GetMetaName = unsafe extern "C" fn(*const MldecMetalib, usize) -> *mut c_char;
+type Free = unsafe extern "C" fn(*mut MldecMetalib);
+type FreeString = unsafe extern "C" fn(*mut c_char);
+type LastError = unsafe extern "C" fn() -> *const c_char;
+
+/// The cdylib cargo built next to this test, in `target/<profile>/deps` or its parent.
+fn library_path() -> PathBuf {
+    let name = format!(
+        "{}mldec_rs{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    );
+    let exe = std::env::current_exe().unwrap();
+    exe.ancestors()
+        .skip(1)
+        .take(2)
+        .map(|dir| dir.join(&name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| panic!("{name} wasn't built next to {}", exe.display()))
+}
+
+/// Takes ownership of a string returned by the library.
+unsafe fn take_string(free_string: &Symbol<FreeString>, s: *mut c_char) -> String {
+    assert!(!s.is_null());
+    let text = CStr::from_ptr(s).to_str().unwrap().to_string();
+    free_string(s);
+    text
+}
+
+#[test]
+fn parse_through_the_c_abi() {
+    let dir = TempDir::new("ffi");
+    let bytes = std::fs::read(fixture_bin(&dir, "full")).unwrap();
+    let expected_xml = common::fixture_xml("full.xml");
+
+    unsafe {
+        let library = Library::new(library_path()).unwrap();
+        let parse: Symbol<Parse> = library.get(b"mldec_parse").unwrap();
+        let export_xml: Symbol<ExportXml> = library.get(b"mldec_export_xml").unwrap();
+        let meta_count: Symbol<MetaCount> = library.get(b"mldec_meta_count").unwrap();
+        let get_meta_name: Symbol<GetMetaName> = library.get(b"mldec_get_meta_name").unwrap();
+        let free: Symbol<Free> = library.get(b"mldec_free").unwrap();
+        let free_string: Symbol<FreeString> = library.get(b"mldec_free_string").unwrap();
+        let last_error: Symbol<LastError> = library.get(b"mldec_last_error").unwrap();
+
+        let mut handle = ptr::null_mut();
+        assert_eq!(parse(bytes.as_ptr(), bytes.len(), &mut handle), 0);
+        assert!(!handle.is_null());
+
+        assert_eq!(meta_count(handle), 5);
+        let names: Vec<String> = (0..meta_count(handle))
+            .map(|idx| take_string(&free_string, get_meta_name(handle, idx)))
+            .collect();
+        assert_eq!(names, ["Header", "Body", "Packet", "Sorted", "Account"]);
+        assert_eq!(take_string(&free_string, export_xml(handle)), expected_xml);
+
+        // Failures return NULL or -1 and leave a message.
+        assert!(get_meta_name(handle, 5).is_null());
+        let error = CStr::from_ptr(last_error()).to_str().unwrap();
+        assert_eq!(error, "Meta index 5 is out of range (5 metas)");
+        free(handle);
+
+        let mut handle = ptr::null_mut();
+        assert_eq!(parse(bytes.as_ptr(), 0x80, &mut handle), -1);
+        assert!(handle.is_null());
+        assert!(!last_error().is_null());
+        assert_eq!(meta_count(ptr::null()), 0);
+        free(ptr::null_mut());
+    }
+}
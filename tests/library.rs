@@ -0,0 +1,30 @@
+//! The binary is a thin wrapper: what it writes is what the library produces.
+
+mod common;
+
+use std::fs::{self, File};
+use std::io::BufReader;
+
+use mldec_rs::metalib::read_metalib;
+use mldec_rs::xml::export_metalib_xml;
+
+use common::{fixture_bin, fixture_xml, mldec_ok, stdout, TempDir};
+
+#[test]
+fn binary_output_matches_the_library() {
+    let dir = TempDir::new("library");
+    for name in ["full", "types"] {
+        let input = fixture_bin(&dir, name);
+        let metalib = read_metalib(&mut BufReader::new(File::open(&input).unwrap())).unwrap();
+        let expected = export_metalib_xml(&metalib).unwrap();
+        assert_eq!(expected, fixture_xml(&format!("{name}.xml")));
+
+        let output = mldec_ok([input.as_os_str(), "--stdout".as_ref()]);
+        assert_eq!(stdout(&output), expected, "{name} on stdout");
+
+        let out = dir.join(&format!("{name}-out/"));
+        mldec_ok([input.as_os_str(), "-o".as_ref(), out.as_os_str()]);
+        let written = fs::read_to_string(out.join(format!("{name}_0.xml"))).unwrap();
+        assert_eq!(written, expected, "{name} in a file");
+    }
+}
@@ -0,0 +1,50 @@
+//! Where `dump` writes its output: `-o` as a directory, as a file, and the errors for paths
+//! that can't be written.
+
+mod common;
+
+use std::fs;
+
+use common::{fixture_bin, fixture_xml, mldec, mldec_ok, stderr, TempDir};
+
+#[test]
+fn output_directory_uses_the_input_stem() {
+    let dir = TempDir::new("output-dir");
+    let input = fixture_bin(&dir, "full");
+
+    // Missing directories are created, with or without a trailing separator.
+    let nested = dir.join("a/b/");
+    mldec_ok([input.as_os_str(), "-o".as_ref(), nested.as_os_str()]);
+    let written = fs::read_to_string(nested.join("full_0.xml")).unwrap();
+    assert_eq!(written, fixture_xml("full.xml"));
+
+    // An existing directory without one.
+    mldec_ok([input.as_os_str(), "-o".as_ref(), dir.path().as_os_str()]);
+    assert!(dir.join("full_0.xml").is_file());
+}
+
+#[test]
+fn output_file_is_written_exactly_there() {
+    let dir = TempDir::new("output-file");
+    let input = fixture_bin(&dir, "full");
+
+    let path = dir.join("out/renamed.txt");
+    mldec_ok([input.as_os_str(), "-o".as_ref(), path.as_os_str()]);
+    assert_eq!(fs::read_to_string(&path).unwrap(), fixture_xml("full.xml"));
+    assert!(!dir.join("out/full_0.xml").exists());
+}
+
+#[test]
+fn unwritable_output_names_the_path() {
+    let dir = TempDir::new("output-error");
+    let input = fixture_bin(&dir, "full");
+
+    // A file where a parent directory should be.
+    let blocker = dir.join("blocker");
+    fs::write(&blocker, b"").unwrap();
+    let path = blocker.join("out.xml");
+    let output = mldec([input.as_os_str(), "-o".as_ref(), path.as_os_str()]);
+    assert!(!output.status.success());
+    let stderr = stderr(&output);
+    assert!(stderr.contains(&blocker.display().to_string()), "{stderr}");
+}
@@ -0,0 +1,67 @@
+//! `-` reads the metalib from stdin.
+
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use common::{fixture_bin, fixture_xml, TempDir};
+
+fn pipe(args: &[&str], input: &[u8]) -> std::process::Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mldec-rs"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+#[test]
+fn dump_from_stdin() {
+    let dir = TempDir::new("stdin");
+    let full = fs::read(fixture_bin(&dir, "full")).unwrap();
+
+    let output = pipe(&["dump", "-", "--offset", "0", "--stdout"], &full);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        fixture_xml("full.xml")
+    );
+
+    // Offsets count within what was piped in, including from the end.
+    let mut embedded = vec![0; 0x20];
+    embedded.extend_from_slice(&full);
+    let from_end = format!("-{:#x}", full.len());
+    let output = pipe(&["dump", "-", &from_end, "--stdout"], &embedded);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        fixture_xml("full.xml")
+    );
+}
+
+#[test]
+fn stdin_output_is_named_stdin() {
+    let dir = TempDir::new("stdin-name");
+    let full = fs::read(fixture_bin(&dir, "full")).unwrap();
+
+    let out = dir.join("out/");
+    pipe(&["dump", "-", "-o", out.to_str().unwrap()], &full);
+    let names: Vec<_> = fs::read_dir(&out)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(names, ["stdin.xml"]);
+    assert_eq!(
+        fs::read_to_string(out.join("stdin.xml")).unwrap(),
+        fixture_xml("full.xml")
+    );
+}
@@ -0,0 +1,33 @@
+//! `--stdout` writes nothing but the document to stdout; diagnostics go to stderr.
+
+mod common;
+
+use common::{fixture_bin, fixture_xml, mldec_ok, stderr, stdout, TempDir};
+
+#[test]
+fn stdout_is_only_well_formed_xml() {
+    let dir = TempDir::new("stdout");
+    let input = fixture_bin(&dir, "full");
+
+    for verbosity in [None, Some("-vv")] {
+        let mut args = vec![input.display().to_string(), "--stdout".to_string()];
+        args.extend(verbosity.map(String::from));
+        let output = mldec_ok(args);
+
+        let xml = stdout(&output);
+        let document = roxmltree::Document::parse(&xml).expect("stdout should be well-formed XML");
+        assert_eq!(document.root_element().tag_name().name(), "metalib");
+        assert_eq!(xml, fixture_xml("full.xml"));
+
+        assert!(stderr(&output).contains("Attempting to load TDR Metalib"));
+    }
+}
+
+#[test]
+fn dash_output_is_stdout() {
+    let dir = TempDir::new("stdout-dash");
+    let input = fixture_bin(&dir, "full");
+
+    let output = mldec_ok([input.as_os_str(), "-o".as_ref(), "-".as_ref()]);
+    assert_eq!(stdout(&output), fixture_xml("full.xml"));
+}
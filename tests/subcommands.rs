@@ -0,0 +1,427 @@
+//! One test per subcommand, each driving the binary against metalibs compiled from the
+//! fixtures.
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use common::{fixture_bin, fixture_xml, mldec, mldec_ok, stderr, stdout, TempDir};
+
+/// Writes `full.bin` twice into `embedded.bin`, at 0x64 and 0x171A, around some junk.
+fn embedded_bin(dir: &TempDir) -> (PathBuf, Vec<u8>) {
+    let full = fs::read(fixture_bin(dir, "full")).unwrap();
+    let mut bytes = vec![0xAA; 0x64];
+    bytes.extend_from_slice(&full);
+    bytes.extend_from_slice(&[0x55; 50]);
+    bytes.extend_from_slice(&full);
+    bytes.extend_from_slice(&[0; 7]);
+    assert_eq!(0x64 + full.len() + 50, 0x171A);
+    let path = dir.join("embedded.bin");
+    fs::write(&path, bytes).unwrap();
+    (path, full)
+}
+
+/// `full.xml` without the `Sorted` struct, compiled into `<dir>/v2.bin`.
+fn without_sorted(dir: &TempDir) -> PathBuf {
+    let xml = fixture_xml("full.xml");
+    let start = xml.find("\t<struct name=\"Sorted\"").unwrap();
+    let end = start + xml[start..].find("</struct>\n\n").unwrap() + "</struct>\n\n".len();
+    let xml_path = dir.join("v2.xml");
+    fs::write(&xml_path, format!("{}{}", &xml[..start], &xml[end..])).unwrap();
+    let path = dir.join("v2.bin");
+    mldec_ok([
+        "compile".as_ref(),
+        xml_path.as_os_str(),
+        "-o".as_ref(),
+        path.as_os_str(),
+    ]);
+    path
+}
+
+/// `full.bin` with its header cut down to the build 10 layout, as `<dir>/build10.bin`.
+fn build_10_bin(dir: &TempDir) -> PathBuf {
+    let mut bytes = fs::read(fixture_bin(dir, "full")).unwrap();
+    bytes[2..4].copy_from_slice(&10u16.to_le_bytes());
+    let size = bytes.len() as u32 - 0x1C;
+    bytes[8..12].copy_from_slice(&size.to_le_bytes());
+    bytes.drain(0x78..0x94);
+    let path = dir.join("build10.bin");
+    fs::write(&path, bytes).unwrap();
+    path
+}
+
+fn s(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+#[test]
+fn dump() {
+    let dir = TempDir::new("sub-dump");
+    let input = fixture_bin(&dir, "full");
+    let (embedded, _) = embedded_bin(&dir);
+
+    let output = mldec_ok(["dump", s(&input), "--stdout"]);
+    assert_eq!(stdout(&output), fixture_xml("full.xml"));
+
+    let output = mldec_ok(["dump", s(&embedded), "--offset", "0x171A", "--stdout"]);
+    assert_eq!(stdout(&output), fixture_xml("full.xml"));
+
+    let output = mldec_ok([
+        "dump",
+        s(&input),
+        "--format",
+        "json",
+        "--macros-only",
+        "--stdout",
+    ]);
+    assert!(stdout(&output).contains("MAX_ITEMS"));
+
+    // The old `<file> <offset>` form is still a dump.
+    let out = dir.join("legacy/");
+    mldec_ok([s(&embedded), "100", "-o", s(&out)]);
+    assert_eq!(
+        fs::read_to_string(out.join("embedded_64.xml")).unwrap(),
+        fixture_xml("full.xml")
+    );
+}
+
+#[test]
+fn scan() {
+    let dir = TempDir::new("sub-scan");
+    let (embedded, _) = embedded_bin(&dir);
+
+    let out = dir.join("scan/");
+    let output = mldec_ok(["scan", s(&embedded), "-o", s(&out)]);
+    let stderr = stderr(&output);
+    assert!(stderr.contains("Found 2 metalibs"), "{stderr}");
+    assert!(stderr.contains("0x00000064  full"), "{stderr}");
+    assert!(stderr.contains("0x0000171A  full"), "{stderr}");
+    for name in ["embedded_full_64.xml", "embedded_full_171a.xml"] {
+        assert_eq!(
+            fs::read_to_string(out.join(name)).unwrap(),
+            fixture_xml("full.xml")
+        );
+    }
+}
+
+#[test]
+fn extract() {
+    let dir = TempDir::new("sub-extract");
+    let (embedded, full) = embedded_bin(&dir);
+
+    let out = dir.join("one.bin");
+    mldec_ok(["extract", s(&embedded), "0x64", "-o", s(&out)]);
+    assert_eq!(fs::read(&out).unwrap(), full);
+
+    let out = dir.join("all/");
+    mldec_ok(["extract", s(&embedded), "--all", "-o", s(&out)]);
+    assert_eq!(fs::read(out.join("full.bin")).unwrap(), full);
+    assert_eq!(fs::read(out.join("full-2.bin")).unwrap(), full);
+}
+
+#[test]
+fn info() {
+    let dir = TempDir::new("sub-info");
+    let input = fixture_bin(&dir, "full");
+
+    let text = stdout(&mldec_ok(["info", s(&input)]));
+    assert!(text.contains("Name:              full\n"), "{text}");
+    assert!(
+        text.contains("Platform arch:     0x20 (32-bit)\n"),
+        "{text}"
+    );
+    assert!(text.contains("Metas:             5 of 5\n"), "{text}");
+    assert!(!text.contains("Layout probe"), "{text}");
+
+    let text = stdout(&mldec_ok(["info", "--full", s(&input)]));
+    assert!(
+        text.contains("Layout probe: header claims 32-bit, data suggests 32-bit"),
+        "{text}"
+    );
+    assert!(text.contains("first meta name: `Header`"), "{text}");
+}
+
+#[test]
+fn older_build() {
+    let dir = TempDir::new("sub-build10");
+    let input = build_10_bin(&dir);
+
+    let xml = stdout(&mldec_ok(["dump", s(&input), "--stdout"]));
+    assert_eq!(xml, fixture_xml("full.xml"));
+    let text = stdout(&mldec_ok(["info", "--full", s(&input)]));
+    assert!(text.contains("Build:             10 (0xF8 byte header)\n"), "{text}");
+    assert!(text.contains("first meta name: `Header`"), "{text}");
+
+    let text = stdout(&mldec_ok(["info", s(&fixture_bin(&dir, "full"))]));
+    assert!(text.contains("Build:             11 (0x114 byte header)\n"), "{text}");
+}
+
+#[test]
+fn list() {
+    let dir = TempDir::new("sub-list");
+    let input = fixture_bin(&dir, "full");
+
+    let text = stdout(&mldec_ok(["list", s(&input)]));
+    let rows: Vec<Vec<&str>> = text
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().take(2).collect())
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            ["struct", "Header"],
+            ["union", "Body"],
+            ["struct", "Packet"],
+            ["struct", "Sorted"],
+            ["struct", "Account"],
+        ]
+    );
+}
+
+#[test]
+fn validate() {
+    let dir = TempDir::new("sub-validate");
+    let input = fixture_bin(&dir, "full");
+    assert_eq!(stdout(&mldec_ok(["validate", s(&input)])), "OK\n");
+
+    let mut bytes = fs::read(&input).unwrap();
+    bytes.truncate(bytes.len() - 100);
+    let truncated = dir.join("truncated.bin");
+    fs::write(&truncated, bytes).unwrap();
+    assert!(!mldec(["validate", s(&truncated)]).status.success());
+}
+
+#[test]
+fn find() {
+    let dir = TempDir::new("sub-find");
+    let input = fixture_bin(&dir, "full");
+
+    let text = stdout(&mldec_ok(["find", s(&input), "--type", "string"]));
+    assert_eq!(text, "Body.b\nPacket.name\n");
+    let text = stdout(&mldec_ok(["find", s(&input), "--flag", "notnull"]));
+    assert_eq!(text, "Packet.name\nAccount.uin\n");
+    let text = stdout(&mldec_ok([
+        "find", s(&input), "--type", "string", "--flag", "notnull", "--flag", "unique",
+    ]));
+    assert_eq!(text, "Packet.name\n");
+    let text = stdout(&mldec_ok(["find", s(&input), "--type", "double"]));
+    assert_eq!(text, "Account.gold\n");
+
+    let output = mldec(["find", s(&input), "--type", "Header"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Unknown type `Header`"));
+}
+
+#[test]
+fn show() {
+    let dir = TempDir::new("sub-show");
+    let input = fixture_bin(&dir, "full");
+
+    let text = stdout(&mldec_ok(["show", s(&input), "0", "Packet"]));
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(
+        lines[0],
+        "struct Packet (version 1, 112 bytes host, 95 bytes net)"
+    );
+    let items = lines
+        .iter()
+        .find(|line| line.starts_with("items "))
+        .unwrap();
+    assert_eq!(
+        items.split_whitespace().collect::<Vec<_>>(),
+        [
+            "items",
+            "uint32",
+            "8",
+            "(MAX_ITEMS)",
+            "24",
+            "32",
+            "11",
+            "32"
+        ]
+    );
+    assert!(lines.iter().any(|line| line.starts_with("  ver ")));
+
+    assert!(!mldec(["show", s(&input), "0", "Missing"]).status.success());
+}
+
+#[test]
+fn offsetof() {
+    let dir = TempDir::new("sub-offsetof");
+    let input = fixture_bin(&dir, "full");
+
+    let text = stdout(&mldec_ok([
+        "offsetof",
+        s(&input),
+        "0",
+        "Packet",
+        "items[3]",
+    ]));
+    assert_eq!(text, "items[3]: offset 36 (0x24), size 4, uint32\n");
+    let text = stdout(&mldec_ok(["offsetof", s(&input), "0", "Packet", "0x18"]));
+    assert_eq!(text, "items[0]: offset 24 (0x18), size 4, uint32\n");
+}
+
+#[test]
+fn decode() {
+    let dir = TempDir::new("sub-decode");
+    let input = fixture_bin(&dir, "full");
+
+    let mut data = vec![0; 16];
+    data[..2].copy_from_slice(&7u16.to_le_bytes());
+    data[4..8].copy_from_slice(&3i32.to_le_bytes());
+    let data_path = dir.join("header.bin");
+    fs::write(&data_path, data).unwrap();
+
+    let text = stdout(&mldec_ok([
+        "decode",
+        s(&input),
+        "0",
+        "Header",
+        s(&data_path),
+    ]));
+    let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(value, serde_json::json!({ "len": 7, "ver": 3 }));
+}
+
+#[test]
+fn encode() {
+    let dir = TempDir::new("sub-encode");
+    let input = fixture_bin(&dir, "full");
+
+    let json_path = dir.join("header.json");
+    fs::write(&json_path, r#"{"len":7,"ver":3}"#).unwrap();
+    let out = dir.join("header.bin");
+    mldec_ok([
+        "encode",
+        s(&input),
+        "0",
+        "Header",
+        s(&json_path),
+        "-o",
+        s(&out),
+    ]);
+
+    let mut expected = vec![0; 16];
+    expected[..2].copy_from_slice(&7u16.to_le_bytes());
+    expected[4..8].copy_from_slice(&3i32.to_le_bytes());
+    assert_eq!(fs::read(&out).unwrap(), expected);
+}
+
+#[test]
+fn compile() {
+    let dir = TempDir::new("sub-compile");
+    let xml_path = dir.join("full.xml");
+    fs::write(&xml_path, fixture_xml("full.xml")).unwrap();
+
+    for arch in ["32", "64"] {
+        let out = dir.join(&format!("full{arch}.bin"));
+        mldec_ok(["compile", s(&xml_path), "--arch", arch, "-o", s(&out)]);
+        let output = mldec_ok(["dump", s(&out), "--stdout"]);
+        assert_eq!(stdout(&output), fixture_xml("full.xml"), "{arch}-bit");
+    }
+}
+
+#[test]
+fn diff() {
+    let dir = TempDir::new("sub-diff");
+    let (embedded, full) = embedded_bin(&dir);
+    let v2 = without_sorted(&dir);
+
+    let old = format!("{}@0x64", s(&embedded));
+    let text = stdout(&mldec_ok(["diff", &old, s(&v2)]));
+    assert_eq!(text, "- meta Sorted\n");
+
+    // A bare number after `@` is decimal, and `-N` counts back from the end.
+    let from_end = format!("{}@-{}", s(&embedded), full.len() + 7);
+    for old in [format!("{}@100", s(&embedded)), from_end] {
+        assert_eq!(stdout(&mldec_ok(["diff", &old, s(&v2)])), "- meta Sorted\n");
+    }
+    let output = mldec(["diff", &format!("{}@64", s(&embedded)), s(&v2)]);
+    assert!(!output.status.success());
+    let output = mldec(["diff", &format!("{}@1a", s(&embedded)), s(&v2)]);
+    assert!(stderr(&output).contains("hex offsets need a 0x prefix"));
+
+    let text = stdout(&mldec_ok(["diff", "--json", &old, s(&v2)]));
+    let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(value["removed_metas"], serde_json::json!(["Sorted"]));
+    assert_eq!(value["added_metas"], serde_json::json!([]));
+}
+
+#[test]
+fn digest_compare() {
+    let dir = TempDir::new("sub-digest");
+    let input = fixture_bin(&dir, "full");
+    let v2 = without_sorted(&dir);
+
+    let old = dir.join("old.digest");
+    let new = dir.join("new.digest");
+    mldec_ok(["dump", s(&input), "--format", "digest", "-o", s(&old)]);
+    mldec_ok(["dump", s(&v2), "--format", "digest", "-o", s(&new)]);
+
+    mldec_ok(["digest-compare", s(&old), s(&old)]);
+    let output = mldec(["digest-compare", s(&old), s(&new)]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("0 added, 1 removed, 0 changed"));
+    let text = stdout(&output);
+    assert!(text.starts_with("Digests don't hold meta names"), "{text}");
+    assert!(!text.contains("Sorted"), "{text}");
+
+    let output = mldec(["digest-compare", "--names", s(&input), s(&old), s(&new)]);
+    let text = stdout(&output);
+    let hash = mldec_rs::digest::name_hash("Sorted");
+    assert_eq!(text, format!("- removed  {hash:016X} Sorted\n"));
+}
+
+#[test]
+fn survey_fields() {
+    let dir = TempDir::new("sub-survey");
+    let input = fixture_bin(&dir, "full");
+    let v2 = without_sorted(&dir);
+
+    let text = stdout(&mldec_ok(["survey-fields", s(&input), s(&v2)]));
+    let mut lines = text.lines();
+    assert_eq!(lines.next(), Some("field,value,count,samples"));
+    assert!(text.contains("\nheader.build,11,2,2\n"), "{text}");
+
+    let text = stdout(&mldec_ok(["survey-fields", "--json", s(&input)]));
+    serde_json::from_str::<serde_json::Value>(&text).unwrap();
+}
+
+#[test]
+fn site() {
+    let dir = TempDir::new("sub-site");
+    let input = fixture_bin(&dir, "full");
+    let v2 = without_sorted(&dir);
+
+    let manifest = dir.join("manifest.txt");
+    fs::write(&manifest, format!("{} v1\n{} v2\n", s(&input), s(&v2))).unwrap();
+    let out = dir.join("site");
+    mldec_ok(["site", s(&manifest), "-o", s(&out)]);
+
+    for name in ["index.html", "v1.html", "v2.html", "search-index.json"] {
+        assert!(out.join(name).is_file(), "{name}");
+    }
+    let index = fs::read_to_string(out.join("index.html")).unwrap();
+    assert!(index.contains("v1.html") && index.contains("v2.html"));
+}
+
+#[test]
+fn empty_metalibs() {
+    let dir = TempDir::new("sub-empty");
+    for (name, macros) in [("empty", 0), ("macros", 2)] {
+        let input = fixture_bin(&dir, name);
+
+        let xml = stdout(&mldec_ok(["dump", s(&input), "--stdout"]));
+        assert_eq!(xml, fixture_xml(&format!("{name}.xml")));
+        let text = stdout(&mldec_ok(["info", "--full", s(&input)]));
+        assert!(text.contains("Metas:             0 of 0\n"), "{text}");
+        assert!(text.contains(&format!("Macros:            {macros} of {macros}\n")), "{text}");
+        let text = stdout(&mldec_ok(["list", s(&input)]));
+        assert_eq!(text.lines().count(), 1, "{text}");
+        assert!(text.starts_with("KIND"), "{text}");
+        assert_eq!(stdout(&mldec_ok(["validate", s(&input)])), "OK\n");
+    }
+}
@@ -0,0 +1,54 @@
+//! The WebAssembly bindings, on an embedded fixture. The `wasm32` tests run under
+//! `wasm-pack test --node -- --features wasm`; the native one under
+//! `cargo test --features wasm`.
+#![cfg(feature = "wasm")]
+
+use mldec_rs::arch::MetalibArch;
+use mldec_rs::compile::compile_metalib_xml;
+use mldec_rs::wasm::metalib_to_xml;
+use mldec_rs::writer::write_metalib;
+
+const FULL_XML: &str = include_str!("fixtures/full.xml");
+
+/// The fixture compiled in the 32-bit layout, without touching the file system.
+fn full_bin() -> Vec<u8> {
+    let mut metalib = compile_metalib_xml(FULL_XML).unwrap();
+    metalib.arch = MetalibArch::Bits32;
+    metalib.header.platform_arch = MetalibArch::Bits32.platform_arch();
+    let mut bytes = Vec::new();
+    write_metalib(&metalib, &mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn xml_natively() {
+    let xml = metalib_to_xml(&full_bin()).unwrap_or_else(|_| panic!("fixture should export"));
+    assert_eq!(xml, FULL_XML);
+}
+
+#[cfg(target_arch = "wasm32")]
+mod browser {
+    use super::*;
+    use mldec_rs::wasm::parse_metalib;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn xml() {
+        let xml = metalib_to_xml(&full_bin()).unwrap_or_else(|_| panic!("fixture should export"));
+        assert_eq!(xml, FULL_XML);
+    }
+
+    #[wasm_bindgen_test]
+    fn model() {
+        let model = parse_metalib(&full_bin()).unwrap_or_else(|_| panic!("fixture should parse"));
+        let model: serde_json::Value = serde_wasm_bindgen::from_value(model).unwrap();
+        assert_eq!(model["metas"].as_array().unwrap().len(), 5);
+        assert_eq!(model["metas"][2]["name"], "Packet");
+    }
+
+    #[wasm_bindgen_test]
+    fn errors_throw() {
+        assert!(metalib_to_xml(&full_bin()[..0x80]).is_err());
+        assert!(parse_metalib(b"not a metalib").is_err());
+    }
+}